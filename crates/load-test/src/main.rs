@@ -1,22 +1,32 @@
 //! Load test for pathfinder JSON-RPC endpoints.
 //!
-//! This program expects a mainnet pathfinder node synced until block 1800,
-//! since it contains references to transactions and contract addresses on mainnet.
+//! Before the load test starts, a preflight phase samples random blocks from the node under
+//! test to discover the block hashes, transactions, contracts and call targets the scenarios
+//! use, see [preflight]. It therefore runs against any network and sync state.
 //!
 //! Running the load test:
 //! ```
 //! cargo run --release --bin load-test -- -H http://127.0.0.1:9545 --report-file /tmp/report.html -u 30 -r 5 -t 60 --no-gzip
 //! ```
+//!
+//! In addition to goose's own options, the following are supported:
+//! - `--sample-size <N>`: number of blocks sampled by the preflight phase, 20 by default.
+//! - `--assert-p95-ms <MS>`: exit with a nonzero status if the 95th percentile of any scenario's
+//!   run time exceeds this many milliseconds, e.g. to gate CI performance runs.
+mod preflight;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 use goose::prelude::*;
-use rand::{Rng, SeedableRng};
+use gumdrop::Options;
 use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::json;
-use stark_hash::StarkHash;
 
 use pathfinder_lib::{
     core::{
-        ContractAddress, StarknetBlockHash, StarknetBlockNumber, StarknetTransactionHash,
-        StarknetTransactionIndex,
+        CallParam, ContractAddress, EntryPoint, StarknetBlockHash, StarknetBlockNumber,
+        StarknetTransactionHash, StarknetTransactionIndex,
     },
     rpc::types::{
         reply::{
@@ -28,16 +38,17 @@ use pathfinder_lib::{
     },
 };
 
+use preflight::Corpus;
+
 //
 // Tasks
 //
 
 /// Fetch a random block, then fetch all individual transactions and receipts in the block.
-async fn block_explorer(user: &mut GooseUser) -> TransactionResult {
-    let mut rng = rand::rngs::StdRng::from_entropy();
-    let block_number: u64 = rng.gen_range(1..1800);
+async fn block_explorer(user: &mut GooseUser, corpus: &Corpus) -> TransactionResult {
+    let block_number = corpus.random_block().number;
 
-    let block = get_block_by_number(user, StarknetBlockNumber(block_number)).await?;
+    let block = get_block_by_number(user, block_number).await?;
     let block_by_hash = get_block_by_hash(user, block.block_hash.unwrap()).await?;
     assert_eq!(block, block_by_hash);
 
@@ -68,168 +79,123 @@ async fn block_explorer(user: &mut GooseUser) -> TransactionResult {
     Ok(())
 }
 
-async fn task_block_by_number(user: &mut GooseUser) -> TransactionResult {
-    get_block_by_number(user, StarknetBlockNumber(1000)).await?;
+async fn task_block_by_number(user: &mut GooseUser, corpus: &Corpus) -> TransactionResult {
+    get_block_by_number(user, corpus.random_block().number).await?;
     Ok(())
 }
 
-async fn task_block_by_hash(user: &mut GooseUser) -> TransactionResult {
-    get_block_by_hash(
-        user,
-        StarknetBlockHash(
-            StarkHash::from_hex_str(
-                "0x58d8604f22510af5b120d1204ebf25292a79bfb09c4882c2e456abc2763d4a",
-            )
-            .unwrap(),
-        ),
-    )
-    .await?;
+async fn task_block_by_hash(user: &mut GooseUser, corpus: &Corpus) -> TransactionResult {
+    get_block_by_hash(user, corpus.random_block().hash).await?;
     Ok(())
 }
 
-async fn task_block_transaction_count_by_hash(user: &mut GooseUser) -> TransactionResult {
-    get_block_transaction_count_by_hash(
-        user,
-        BlockHashOrTag::Hash(StarknetBlockHash(
-            StarkHash::from_hex_str(
-                "0x58d8604f22510af5b120d1204ebf25292a79bfb09c4882c2e456abc2763d4a",
-            )
-            .unwrap(),
-        )),
-    )
-    .await?;
+async fn task_block_transaction_count_by_hash(
+    user: &mut GooseUser,
+    corpus: &Corpus,
+) -> TransactionResult {
+    let block = corpus.random_block();
+    let count = get_block_transaction_count_by_hash(user, BlockHashOrTag::Hash(block.hash)).await?;
+    assert_eq!(count, block.transaction_count as u64);
     Ok(())
 }
 
-async fn task_block_transaction_count_by_number(user: &mut GooseUser) -> TransactionResult {
-    get_block_transaction_count_by_number(
-        user,
-        BlockNumberOrTag::Number(StarknetBlockNumber(1000)),
-    )
-    .await?;
+async fn task_block_transaction_count_by_number(
+    user: &mut GooseUser,
+    corpus: &Corpus,
+) -> TransactionResult {
+    let block = corpus.random_block();
+    let count =
+        get_block_transaction_count_by_number(user, BlockNumberOrTag::Number(block.number)).await?;
+    assert_eq!(count, block.transaction_count as u64);
     Ok(())
 }
 
-async fn task_transaction_by_hash(user: &mut GooseUser) -> TransactionResult {
-    get_transaction_by_hash(
-        user,
-        StarknetTransactionHash(
-            StarkHash::from_hex_str(
-                "0x39ee26a0251338f1ef96b66c0ffacbc7a41f36bd465055e39621673ff10fb60",
-            )
-            .unwrap(),
-        ),
-    )
-    .await?;
+async fn task_transaction_by_hash(user: &mut GooseUser, corpus: &Corpus) -> TransactionResult {
+    let transaction = corpus.random_transaction().unwrap();
+    get_transaction_by_hash(user, transaction.hash).await?;
     Ok(())
 }
 
-async fn task_transaction_by_block_number_and_index(user: &mut GooseUser) -> TransactionResult {
-    get_transaction_by_block_number_and_index(
-        user,
-        StarknetBlockNumber(1000),
-        StarknetTransactionIndex(3),
-    )
-    .await?;
+async fn task_transaction_by_block_number_and_index(
+    user: &mut GooseUser,
+    corpus: &Corpus,
+) -> TransactionResult {
+    let transaction = corpus.random_transaction().unwrap();
+    get_transaction_by_block_number_and_index(user, transaction.block_number, transaction.index)
+        .await?;
     Ok(())
 }
 
-async fn task_transaction_by_block_hash_and_index(user: &mut GooseUser) -> TransactionResult {
-    get_transaction_by_block_hash_and_index(
-        user,
-        StarknetBlockHash(
-            StarkHash::from_hex_str(
-                "0x58d8604f22510af5b120d1204ebf25292a79bfb09c4882c2e456abc2763d4a",
-            )
-            .unwrap(),
-        ),
-        StarknetTransactionIndex(3),
-    )
-    .await?;
+async fn task_transaction_by_block_hash_and_index(
+    user: &mut GooseUser,
+    corpus: &Corpus,
+) -> TransactionResult {
+    let transaction = corpus.random_transaction().unwrap();
+    get_transaction_by_block_hash_and_index(user, transaction.block_hash, transaction.index)
+        .await?;
     Ok(())
 }
 
-async fn task_transaction_receipt_by_hash(user: &mut GooseUser) -> TransactionResult {
-    get_transaction_receipt_by_hash(
-        user,
-        StarknetTransactionHash(
-            StarkHash::from_hex_str(
-                "0x39ee26a0251338f1ef96b66c0ffacbc7a41f36bd465055e39621673ff10fb60",
-            )
-            .unwrap(),
-        ),
-    )
-    .await?;
+async fn task_transaction_receipt_by_hash(
+    user: &mut GooseUser,
+    corpus: &Corpus,
+) -> TransactionResult {
+    let transaction = corpus.random_transaction().unwrap();
+    get_transaction_receipt_by_hash(user, transaction.hash).await?;
     Ok(())
 }
 
-async fn task_block_number(user: &mut GooseUser) -> TransactionResult {
+async fn task_block_number(user: &mut GooseUser, _: &Corpus) -> TransactionResult {
     block_number(user).await?;
     Ok(())
 }
 
-async fn task_syncing(user: &mut GooseUser) -> TransactionResult {
+async fn task_syncing(user: &mut GooseUser, _: &Corpus) -> TransactionResult {
     syncing(user).await?;
     Ok(())
 }
 
-async fn task_call(user: &mut GooseUser) -> TransactionResult {
-    // call a test contract deployed in block 0
-    // https://voyager.online/contract/0x06ee3440b08a9c805305449ec7f7003f27e9f7e287b83610952ec36bdc5a6bae
+async fn task_call(user: &mut GooseUser, corpus: &Corpus) -> TransactionResult {
+    let target = corpus.random_call_target().unwrap();
     call(
         user,
-        ContractAddress(
-            StarkHash::from_hex_str(
-                "0x06ee3440b08a9c805305449ec7f7003f27e9f7e287b83610952ec36bdc5a6bae",
-            )
-            .unwrap(),
-        ),
-        &[
-            // address
-            "0x01e2cd4b3588e8f6f9c4e89fb0e293bf92018c96d7a93ee367d29a284223b6ff",
-            // value
-            "0x071d1e9d188c784a0bde95c1d508877a0d93e9102b37213d1e13f3ebc54a7751",
-        ],
-        // "set_value" entry point
-        "0x3d7905601c217734671143d457f0db37f7f8883112abd34b92c4abfeafde0c3",
-        // hash of mainnet block 0
-        BlockHashOrTag::Hash(StarknetBlockHash(
-            StarkHash::from_hex_str(
-                "0x47c3637b57c2b079b93c61539950c17e868a28f46cdef28f88521067f21e943",
-            )
-            .unwrap(),
-        )),
+        target.contract_address,
+        &target.calldata,
+        target.entry_point_selector,
+        BlockHashOrTag::Hash(target.block_hash),
     )
     .await?;
     Ok(())
 }
 
-async fn task_chain_id(user: &mut GooseUser) -> TransactionResult {
+async fn task_chain_id(user: &mut GooseUser, _: &Corpus) -> TransactionResult {
     chain_id(user).await?;
     Ok(())
 }
 
-async fn task_get_events(user: &mut GooseUser) -> TransactionResult {
-    // This returns a single event.
+async fn task_pending_transactions(user: &mut GooseUser, _: &Corpus) -> TransactionResult {
+    pending_transactions(user).await?;
+    Ok(())
+}
+
+async fn task_get_events(user: &mut GooseUser, corpus: &Corpus) -> TransactionResult {
+    let source = corpus.random_event_source().unwrap();
     let events = get_events(
         user,
         EventFilter {
-            from_block: Some(StarknetBlockNumber(1000)),
-            to_block: Some(StarknetBlockNumber(1100)),
-            address: Some(ContractAddress(
-                StarkHash::from_hex_str(
-                    "0x103114c4c5ac233a360d39a9217b9067be6979f3d08e1cf971fd22baf8f8713",
-                )
-                .unwrap(),
-            )),
+            from_block: Some(source.block),
+            to_block: Some(source.block),
+            address: Some(source.address),
             keys: vec![],
             page_size: 1024,
             page_number: 0,
+            chain_head: None,
         },
     )
     .await?;
 
-    assert_eq!(events.events.len(), 1);
+    // The event this source was discovered from.
+    assert!(!events.events.is_empty());
 
     Ok(())
 }
@@ -350,6 +316,10 @@ async fn chain_id(user: &mut GooseUser) -> MethodResult<String> {
     post_jsonrpc_request(user, "starknet_chainId", json!({})).await
 }
 
+async fn pending_transactions(user: &mut GooseUser) -> MethodResult<Vec<StarknetTransaction>> {
+    post_jsonrpc_request(user, "starknet_pendingTransactions", json!({})).await
+}
+
 async fn get_events(user: &mut GooseUser, filter: EventFilter) -> MethodResult<GetEventsResult> {
     post_jsonrpc_request(user, "starknet_getEvents", json!({ "filter": filter })).await
 }
@@ -357,8 +327,8 @@ async fn get_events(user: &mut GooseUser, filter: EventFilter) -> MethodResult<G
 async fn call(
     user: &mut GooseUser,
     contract_address: ContractAddress,
-    call_data: &[&str],
-    entry_point_selector: &str,
+    call_data: &[CallParam],
+    entry_point_selector: EntryPoint,
     at_block: BlockHashOrTag,
 ) -> MethodResult<Vec<String>> {
     post_jsonrpc_request(
@@ -401,60 +371,214 @@ fn jsonrpc_request(method: &str, params: serde_json::Value) -> serde_json::Value
     })
 }
 
+/// Number of blocks sampled by the preflight phase, unless `--sample-size` is given.
+const DEFAULT_SAMPLE_SIZE: usize = 20;
+
+/// The options of this binary, as opposed to goose's own.
+struct LoadTestOptions {
+    sample_size: usize,
+    assert_p95_ms: Option<usize>,
+}
+
+/// Splits this binary's own options off `args`, returning them along with the remaining
+/// arguments, which are meant for goose.
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(LoadTestOptions, Vec<String>), GooseError> {
+    fn value<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, GooseError> {
+        let value = value.unwrap_or_default();
+        value.parse().map_err(|_| GooseError::InvalidOption {
+            option: option.to_owned(),
+            value,
+            detail: format!("{} requires a positive integer", option),
+        })
+    }
+
+    let mut options = LoadTestOptions {
+        sample_size: DEFAULT_SAMPLE_SIZE,
+        assert_p95_ms: None,
+    };
+    let mut goose_args = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sample-size" => options.sample_size = value(&arg, args.next())?,
+            "--assert-p95-ms" => options.assert_p95_ms = Some(value(&arg, args.next())?),
+            _ => goose_args.push(arg),
+        }
+    }
+
+    Ok((options, goose_args))
+}
+
+/// Creates a transaction which runs `$task` on the shared [Corpus].
+macro_rules! corpus_transaction {
+    ($corpus:expr, $task:ident) => {{
+        let corpus: Arc<Corpus> = Arc::clone(&$corpus);
+        Transaction::new(Arc::new(move |user| {
+            let corpus = Arc::clone(&corpus);
+            Box::pin(async move { $task(user, &corpus).await })
+        }))
+    }};
+}
+
+/// Returns the smallest time (in milliseconds) such that at least `percentile` of all
+/// samples took no longer, or [None] if there are no samples.
+///
+/// `times` maps each (rounded) time to the number of samples which took that long, as
+/// collected by goose.
+fn percentile(times: &BTreeMap<usize, usize>, percentile: f64) -> Option<usize> {
+    let total: usize = times.values().sum();
+    let rank = (total as f64 * percentile).ceil() as usize;
+
+    let mut seen = 0;
+    for (time, count) in times {
+        seen += count;
+        if seen >= rank.max(1) {
+            return Some(*time);
+        }
+    }
+    None
+}
+
 #[tokio::main]
 async fn main() -> Result<(), GooseError> {
-    use pathfinder_lib::core::EntryPoint;
-    dbg!(EntryPoint::hashed("set_value".as_bytes()));
-
-    GooseAttack::initialize()?
+    let (options, goose_args) = parse_args(std::env::args().skip(1))?;
+    let config = GooseConfiguration::parse_args_default(&goose_args).map_err(|e| {
+        GooseError::InvalidOption {
+            option: String::new(),
+            value: goose_args.join(" "),
+            detail: e.to_string(),
+        }
+    })?;
+
+    if config.host.is_empty() {
+        return Err(GooseError::InvalidOption {
+            option: "--host".to_owned(),
+            value: String::new(),
+            detail: "The preflight phase requires the host to be set".to_owned(),
+        });
+    }
+    let corpus = preflight::discover(&config.host, options.sample_size)
+        .await
+        .map_err(|detail| GooseError::InvalidOption {
+            option: "--host".to_owned(),
+            value: config.host.clone(),
+            detail: format!("Preflight failed: {}", detail),
+        })?;
+    println!(
+        "Preflight discovered {} blocks, {} transactions, {} event sources and {} call targets",
+        corpus.blocks.len(),
+        corpus.transactions.len(),
+        corpus.event_sources.len(),
+        corpus.call_targets.len()
+    );
+    let corpus = Arc::new(corpus);
+
+    let mut attack = GooseAttack::initialize_with_config(config)?
         // primitive operations using the database
         .register_scenario(
-            scenario!("block_by_number").register_transaction(transaction!(task_block_by_number)),
+            scenario!("block_by_number")
+                .register_transaction(corpus_transaction!(corpus, task_block_by_number)),
         )
         .register_scenario(
-            scenario!("block_by_hash").register_transaction(transaction!(task_block_by_hash)),
+            scenario!("block_by_hash")
+                .register_transaction(corpus_transaction!(corpus, task_block_by_hash)),
         )
         .register_scenario(
-            scenario!("block_transaction_count_by_hash")
-                .register_transaction(transaction!(task_block_transaction_count_by_hash)),
-        )
-        .register_scenario(
-            scenario!("block_transaction_count_by_number")
-                .register_transaction(transaction!(task_block_transaction_count_by_number)),
-        )
-        .register_scenario(
-            scenario!("transaction_by_hash")
-                .register_transaction(transaction!(task_transaction_by_hash)),
+            scenario!("block_transaction_count_by_hash").register_transaction(corpus_transaction!(
+                corpus,
+                task_block_transaction_count_by_hash
+            )),
         )
         .register_scenario(
-            scenario!("transaction_by_block_number_and_index")
-                .register_transaction(transaction!(task_transaction_by_block_number_and_index)),
+            scenario!("block_transaction_count_by_number").register_transaction(
+                corpus_transaction!(corpus, task_block_transaction_count_by_number),
+            ),
         )
         .register_scenario(
-            scenario!("transaction_by_block_hash_and_index")
-                .register_transaction(transaction!(task_transaction_by_block_hash_and_index)),
+            scenario!("block_number")
+                .register_transaction(corpus_transaction!(corpus, task_block_number)),
         )
+        // primitive operations that don't use the database
         .register_scenario(
-            scenario!("transaction_receipt_by_hash")
-                .register_transaction(transaction!(task_transaction_receipt_by_hash)),
+            scenario!("syncing").register_transaction(corpus_transaction!(corpus, task_syncing)),
         )
         .register_scenario(
-            scenario!("block_number").register_transaction(transaction!(task_block_number)),
+            scenario!("chain_id").register_transaction(corpus_transaction!(corpus, task_chain_id)),
         )
         .register_scenario(
-            scenario!("get_events").register_transaction(transaction!(task_get_events)),
+            scenario!("pending_transactions")
+                .register_transaction(corpus_transaction!(corpus, task_pending_transactions)),
         )
-        // primitive operations that don't use the database
-        .register_scenario(scenario!("syncing").register_transaction(transaction!(task_syncing)))
-        .register_scenario(scenario!("chain_id").register_transaction(transaction!(task_chain_id)))
-        // primitive operation utilizing the Cairo Python subprocesses
-        .register_scenario(scenario!("call").register_transaction(transaction!(task_call)))
         // composite scenario
         .register_scenario(
-            scenario!("block_explorer").register_transaction(transaction!(block_explorer)),
-        )
-        .execute()
-        .await?;
+            scenario!("block_explorer")
+                .register_transaction(corpus_transaction!(corpus, block_explorer)),
+        );
+
+    // Scenarios which require data which the sampled blocks may not contain.
+    if corpus.transactions.is_empty() {
+        println!("No transactions discovered, skipping the transaction scenarios");
+    } else {
+        attack = attack
+            .register_scenario(
+                scenario!("transaction_by_hash")
+                    .register_transaction(corpus_transaction!(corpus, task_transaction_by_hash)),
+            )
+            .register_scenario(
+                scenario!("transaction_by_block_number_and_index").register_transaction(
+                    corpus_transaction!(corpus, task_transaction_by_block_number_and_index),
+                ),
+            )
+            .register_scenario(
+                scenario!("transaction_by_block_hash_and_index").register_transaction(
+                    corpus_transaction!(corpus, task_transaction_by_block_hash_and_index),
+                ),
+            )
+            .register_scenario(
+                scenario!("transaction_receipt_by_hash").register_transaction(corpus_transaction!(
+                    corpus,
+                    task_transaction_receipt_by_hash
+                )),
+            );
+    }
+    if corpus.event_sources.is_empty() {
+        println!("No events discovered, skipping the get_events scenario");
+    } else {
+        attack = attack.register_scenario(
+            scenario!("get_events")
+                .register_transaction(corpus_transaction!(corpus, task_get_events)),
+        );
+    }
+    if corpus.call_targets.is_empty() {
+        println!("No invoke transactions discovered, skipping the call scenario");
+    } else {
+        // primitive operation utilizing the Cairo Python subprocesses
+        attack = attack.register_scenario(
+            scenario!("call").register_transaction(corpus_transaction!(corpus, task_call)),
+        );
+    }
+
+    let metrics = attack.execute().await?;
+
+    if let Some(limit) = options.assert_p95_ms {
+        let mut exceeded = false;
+        for scenario in &metrics.scenarios {
+            if let Some(p95) = percentile(&scenario.times, 0.95) {
+                if p95 > limit {
+                    eprintln!(
+                        "Scenario {} exceeds the p95 limit of {} ms: {} ms",
+                        scenario.name, limit, p95
+                    );
+                    exceeded = true;
+                }
+            }
+        }
+
+        if exceeded {
+            std::process::exit(1);
+        }
+    }
 
     Ok(())
 }
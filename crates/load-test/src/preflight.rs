@@ -0,0 +1,193 @@
+//! Discovery of the data the load test scenarios operate on.
+//!
+//! Before the load test starts, a random sample of blocks is fetched from the node under
+//! test. The transactions, event emitting contracts and call targets found in those blocks
+//! make up the [Corpus] which the scenarios draw their requests from. This way the load
+//! test runs against any network, and against nodes which are not fully synced.
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+use pathfinder_lib::{
+    core::{
+        CallParam, ContractAddress, EntryPoint, StarknetBlockHash, StarknetBlockNumber,
+        StarknetTransactionHash, StarknetTransactionIndex,
+    },
+    rpc::types::{
+        reply::{Block, Transactions},
+        request::BlockResponseScope,
+    },
+};
+
+/// A block which is known to exist on the node under test.
+#[derive(Clone, Debug)]
+pub struct BlockRef {
+    pub number: StarknetBlockNumber,
+    pub hash: StarknetBlockHash,
+    pub transaction_count: usize,
+}
+
+/// A transaction which is known to exist on the node under test.
+#[derive(Clone, Debug)]
+pub struct TransactionRef {
+    pub hash: StarknetTransactionHash,
+    pub block_number: StarknetBlockNumber,
+    pub block_hash: StarknetBlockHash,
+    pub index: StarknetTransactionIndex,
+}
+
+/// A contract which emitted events in `block`.
+#[derive(Clone, Debug)]
+pub struct EventSource {
+    pub address: ContractAddress,
+    pub block: StarknetBlockNumber,
+}
+
+/// An invoke transaction, replayed as a call on the state it was originally executed on.
+#[derive(Clone, Debug)]
+pub struct CallTarget {
+    pub contract_address: ContractAddress,
+    pub entry_point_selector: EntryPoint,
+    pub calldata: Vec<CallParam>,
+    /// The parent of the block which contains the transaction.
+    pub block_hash: StarknetBlockHash,
+}
+
+/// The data discovered by [discover], shared by all scenarios.
+#[derive(Clone, Debug, Default)]
+pub struct Corpus {
+    pub blocks: Vec<BlockRef>,
+    pub transactions: Vec<TransactionRef>,
+    pub event_sources: Vec<EventSource>,
+    pub call_targets: Vec<CallTarget>,
+}
+
+impl Corpus {
+    pub fn random_block(&self) -> &BlockRef {
+        self.blocks
+            .choose(&mut rand::rngs::StdRng::from_entropy())
+            .expect("Corpus contains at least one block")
+    }
+
+    pub fn random_transaction(&self) -> Option<&TransactionRef> {
+        self.transactions
+            .choose(&mut rand::rngs::StdRng::from_entropy())
+    }
+
+    pub fn random_event_source(&self) -> Option<&EventSource> {
+        self.event_sources
+            .choose(&mut rand::rngs::StdRng::from_entropy())
+    }
+
+    pub fn random_call_target(&self) -> Option<&CallTarget> {
+        self.call_targets
+            .choose(&mut rand::rngs::StdRng::from_entropy())
+    }
+}
+
+/// Builds a [Corpus] from `sample_size` randomly chosen blocks of the node at `host`.
+pub async fn discover(host: &str, sample_size: usize) -> Result<Corpus, String> {
+    let client = reqwest::Client::new();
+
+    let head: u64 = request(&client, host, "starknet_blockNumber", json!({})).await?;
+
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let mut numbers = (0..sample_size)
+        .map(|_| rng.gen_range(0..=head))
+        .collect::<Vec<_>>();
+    numbers.sort_unstable();
+    numbers.dedup();
+
+    let mut corpus = Corpus::default();
+    for number in numbers {
+        let block: Block = request(
+            &client,
+            host,
+            "starknet_getBlockByNumber",
+            json!({
+                "block_number": number,
+                "requested_scope": BlockResponseScope::FullTransactionsAndReceipts,
+            }),
+        )
+        .await?;
+
+        let (number, hash) = match (block.block_number, block.block_hash) {
+            (Some(number), Some(hash)) => (number, hash),
+            _ => return Err(format!("Block {} is missing its number or hash", number)),
+        };
+        let transactions = match block.transactions {
+            Transactions::FullWithReceipts(transactions) => transactions,
+            _ => return Err(format!("Block {} is missing its receipts", number.0)),
+        };
+
+        corpus.blocks.push(BlockRef {
+            number,
+            hash,
+            transaction_count: transactions.len(),
+        });
+
+        for (index, transaction) in transactions.into_iter().enumerate() {
+            corpus.transactions.push(TransactionRef {
+                hash: transaction.txn_hash,
+                block_number: number,
+                block_hash: hash,
+                index: StarknetTransactionIndex(index as u64),
+            });
+
+            corpus
+                .event_sources
+                .extend(transaction.events.iter().map(|event| EventSource {
+                    address: event.from_address,
+                    block: number,
+                }));
+
+            // Deploy transactions have neither.
+            if let (Some(entry_point_selector), Some(calldata)) =
+                (transaction.entry_point_selector, transaction.calldata)
+            {
+                if number != StarknetBlockNumber::GENESIS {
+                    corpus.call_targets.push(CallTarget {
+                        contract_address: transaction.contract_address,
+                        entry_point_selector,
+                        calldata,
+                        block_hash: block.parent_hash,
+                    });
+                }
+            }
+        }
+    }
+
+    if corpus.blocks.is_empty() {
+        return Err("No blocks could be sampled".to_owned());
+    }
+
+    Ok(corpus)
+}
+
+async fn request<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    host: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<T, String> {
+    #[derive(Deserialize)]
+    struct Response<T> {
+        result: Option<T>,
+        error: Option<serde_json::Value>,
+    }
+
+    let response = client
+        .post(host)
+        .json(&crate::jsonrpc_request(method, params))
+        .send()
+        .await
+        .map_err(|e| format!("{} request failed: {}", method, e))?
+        .json::<Response<T>>()
+        .await
+        .map_err(|e| format!("Invalid {} response: {}", method, e))?;
+
+    match (response.result, response.error) {
+        (Some(result), _) => Ok(result),
+        (None, error) => Err(format!("{} failed: {:?}", method, error)),
+    }
+}
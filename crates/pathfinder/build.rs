@@ -1,6 +1,7 @@
 //! Pathfinder build script.
 //!
-//! Just sets up `vergen` to query our git information for the build.
+//! Sets up `vergen` to query our git information for the build, and records
+//! the build timestamp and enabled features for [pathfinder_lib::build_info].
 
 fn main() {
     // our Dockerfile is set up to a dependency only run, to cache a layer with all of the
@@ -20,4 +21,21 @@ fn main() {
         vergen::vergen(config)
             .expect("vergen failed; this is probably due to missing .git directory");
     }
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("system time is before the unix epoch")
+        .as_secs();
+    println!("cargo:rustc-env=PATHFINDER_BUILD_TIMESTAMP={}", timestamp);
+
+    // cargo exposes enabled features as CARGO_FEATURE_<NAME> with the name uppercased
+    // and dashes replaced by underscores.
+    let mut features = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=PATHFINDER_FEATURES={}", features.join(","));
 }
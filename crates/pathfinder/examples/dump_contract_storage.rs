@@ -0,0 +1,101 @@
+//! Dumps the storage of a single contract as `address value` lines to stdout.
+//!
+//! Large contract tries can be extracted in resumable chunks by restricting the dump to a
+//! key range and limiting the number of entries. Once the limit is hit, the key to resume
+//! from is printed to stderr.
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use pathfinder_lib::{
+    core::{ContractAddress, StorageAddress},
+    state::state_tree::{ContractsStateTree, GlobalStateTree},
+    storage::{ContractsStateTable, StarknetBlocksBlockId, StarknetBlocksTable},
+};
+use stark_hash::StarkHash;
+
+const USAGE: &str = "USAGE: dump_contract_storage db_file contract_address [--block <number|hash|latest>] [--start-key <hex>] [--end-key <hex>] [--limit <count>]";
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (path, contract) = match (args.next(), args.next()) {
+        (Some(path), Some(contract)) => (path, contract),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let contract = ContractAddress(StarkHash::from_hex_str(&contract)?);
+    let mut block = StarknetBlocksBlockId::Latest;
+    let mut start = StorageAddress(StarkHash::ZERO);
+    let mut end = None;
+    let mut limit = None;
+
+    while let Some(flag) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => anyhow::bail!("Missing value for {}\n{}", flag, USAGE),
+        };
+        match flag.as_str() {
+            "--block" => block = value.parse()?,
+            "--start-key" => start = StorageAddress(StarkHash::from_hex_str(&value)?),
+            "--end-key" => end = Some(StorageAddress(StarkHash::from_hex_str(&value)?)),
+            "--limit" => limit = Some(value.parse::<usize>()?),
+            other => anyhow::bail!("Unknown argument {}\n{}", other, USAGE),
+        }
+    }
+
+    let end = match end {
+        Some(end) => end,
+        None => {
+            let max = bitvec::bitvec![bitvec::order::Msb0, u8; 1; 251];
+            StorageAddress(StarkHash::from_bits(&max)?)
+        }
+    };
+
+    // Opened directly to avoid migrating the database as a side-effect of dumping it.
+    let mut connection = rusqlite::Connection::open(path)?;
+    let transaction = connection.transaction()?;
+
+    let block = StarknetBlocksTable::get(&transaction, block)?
+        .ok_or_else(|| anyhow::anyhow!("Block not found"))?;
+    let global_tree = GlobalStateTree::load(&transaction, block.root)?;
+    let state_hash = global_tree.get(contract)?;
+    let contract_root = ContractsStateTable::get_root(&transaction, state_hash)?
+        .ok_or_else(|| anyhow::anyhow!("Contract not found at block {}", block.number.0))?;
+    let contract_tree = ContractsStateTree::load(&transaction, contract_root)?;
+
+    // Writing to a locked stdout blocks while a slow consumer catches up, which throttles
+    // the trie walk instead of buffering the entire dump in memory.
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut count = 0;
+    let mut resume_from = None;
+    let mut write_result = Ok(());
+
+    contract_tree.visit_storage_in_range(start, end, |address, value| {
+        if Some(count) == limit {
+            resume_from = Some(address);
+            return ControlFlow::Break(());
+        }
+        count += 1;
+
+        write_result = writeln!(stdout, "{} {}", address.0, value.0);
+        match write_result.is_ok() {
+            true => ControlFlow::Continue(()),
+            false => ControlFlow::Break(()),
+        }
+    })?;
+
+    write_result?;
+    stdout.flush()?;
+
+    if let Some(resume_from) = resume_from {
+        eprintln!(
+            "Limit of {} entries reached, resume with --start-key {}",
+            count, resume_from.0
+        );
+    }
+
+    Ok(())
+}
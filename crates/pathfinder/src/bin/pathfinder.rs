@@ -1,12 +1,12 @@
 use anyhow::Context;
 use pathfinder_lib::{
-    cairo, config,
+    build_info, cairo, config,
     ethereum::{
         self,
         transport::{EthereumTransport, HttpTransport},
     },
     rpc, sequencer, state,
-    storage::Storage,
+    storage::{self, Storage},
 };
 use std::sync::Arc;
 use tracing::info;
@@ -17,16 +17,26 @@ async fn main() -> anyhow::Result<()> {
         std::env::set_var("RUST_LOG", "info");
     }
 
-    setup_tracing();
+    let log_filter = setup_tracing();
 
     let config =
         config::Configuration::parse_cmd_line_and_cfg_file().context("Parsing configuration")?;
+    let command = config::Command::parse_cmd_line();
 
     info!(
         // this is expected to be $(last_git_tag)-$(commits_since)-$(commit_hash)
-        version = env!("VERGEN_GIT_SEMVER_LIGHTWEIGHT"),
+        version = build_info::GIT_DESCRIBE,
+        commit = build_info::GIT_COMMIT_HASH,
+        dirty = build_info::git_dirty(),
+        build_timestamp = build_info::BUILD_TIMESTAMP,
+        features = ?build_info::features(),
         "🏁 Starting node."
     );
+    let configuration = config.dump();
+    info!(
+        configuration = %serde_json::to_string(&configuration).context("Serializing configuration")?,
+        "Resolved configuration."
+    );
     let eth_transport =
         HttpTransport::from_config(config.ethereum).context("Creating Ethereum transport")?;
 
@@ -39,21 +49,154 @@ async fn main() -> anyhow::Result<()> {
         ethereum::Chain::Mainnet => "mainnet.sqlite",
         ethereum::Chain::Goerli => "goerli.sqlite",
     });
-    let storage = Storage::migrate(database_path.clone()).unwrap();
-    info!(location=?database_path, "Database migrated.");
+    let limits = storage::StorageLimits {
+        max_blob_size: config.max_blob_size,
+        max_block_transactions: config.max_block_transactions,
+        event_query_cost_budget: config.event_query_cost_budget,
+    };
+    let storage = match config.serve_only {
+        true => {
+            let storage = Storage::open_read_only(
+                database_path.clone(),
+                config.statement_cache_capacity,
+                limits,
+            )
+            .context("Opening database read-only")?;
+            storage
+                .verify_chain(network_chain, config.force)
+                .context("Verifying the database's chain")?;
+            info!(location=?database_path, "Database opened read-only, serving without sync.");
+            storage
+        }
+        false => {
+            let storage = Storage::migrate_verified(
+                database_path.clone(),
+                config.statement_cache_capacity,
+                limits,
+                network_chain,
+                config.force,
+            )
+            .context("Migrating database")?;
+            info!(location=?database_path, "Database migrated.");
+            storage
+        }
+    };
+
+    if let Some(command) = command {
+        anyhow::ensure!(
+            !config.serve_only,
+            "Commands cannot be run on a read-only database"
+        );
+        return tokio::task::block_in_place(|| run_command(&storage, command, &config));
+    }
 
     let sequencer = sequencer::Client::new(network_chain).unwrap();
     let sync_state = Arc::new(state::SyncState::default());
+    let sync_options = state::SyncOptions {
+        validate_constructor_calldata: config.validate_constructor_calldata,
+        max_reorg_depth: config.max_reorg_depth,
+        stall_watchdog: config.sync_stall_timeout.map(state::StallWatchdog::new),
+        reject_non_monotonic_timestamps: config.reject_non_monotonic_timestamps,
+        timestamp_tolerance: config.timestamp_tolerance,
+        state_update_parallelism: config.state_update_parallelism,
+        raw_block_window: config.raw_block_window,
+        l1_confirmation_depth: config.l1_confirmation_depth,
+        pending_poll_interval: config.pending_poll_interval,
+    };
 
-    let sync_handle = tokio::spawn(state::sync(
-        storage.clone(),
-        eth_transport,
-        network_chain,
-        sequencer.clone(),
-        sync_state.clone(),
-        state::l1::sync,
-        state::l2::sync,
-    ));
+    let shadow_storage = match config.shadow_gateway_url {
+        Some(url) if !config.serve_only => {
+            let shadow_path = storage::ChainNamespace::Shadow.database_path(&database_path);
+            let shadow_storage = Storage::migrate_verified(
+                shadow_path.clone(),
+                config.statement_cache_capacity,
+                limits,
+                network_chain,
+                config.force,
+            )
+            .context("Migrating shadow database")?;
+
+            let shadow_sequencer = sequencer::Client::with_url(url.clone())
+                .context("Creating shadow gateway client")?;
+            state::shadow::spawn(
+                shadow_storage.clone(),
+                eth_transport.clone(),
+                network_chain,
+                shadow_sequencer,
+                sync_options,
+                state::shadow::DEFAULT_REQUEST_INTERVAL,
+            )
+            .context("Starting shadow sync")?;
+            info!(location=?shadow_path, gateway=%url, "Shadow sync started.");
+            Some(shadow_storage)
+        }
+        Some(_) => {
+            tracing::warn!("Not running the shadow sync, as the node only serves.");
+            None
+        }
+        None => None,
+    };
+
+    // Serve-only nodes follow the head which another process syncs into the database.
+    let sync_handle = match config.serve_only {
+        true => tokio::spawn(state::follow::follow(
+            storage.clone(),
+            sync_state.clone(),
+            std::time::Duration::from_secs(5),
+        )),
+        false => tokio::spawn(state::sync(
+            storage.clone(),
+            eth_transport,
+            network_chain,
+            sequencer.clone(),
+            sync_state.clone(),
+            sync_options,
+            state::l1::sync,
+            state::l2::sync,
+        )),
+    };
+
+    if config.class_backfill && !config.serve_only {
+        info!("Class definition backfill enabled.");
+        let backfill = state::class_backfill::backfill(
+            storage.clone(),
+            sequencer.clone(),
+            sync_state.clone(),
+            std::time::Duration::from_millis(500),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = backfill.await {
+                tracing::error!(reason=?e, "Class definition backfill failed");
+            }
+        });
+    }
+
+    if config.block_backfill && !config.serve_only {
+        info!("Block backfill enabled.");
+        let backfill = state::block_backfill::backfill_with_retry(
+            storage.clone(),
+            sequencer.clone(),
+            std::time::Duration::from_secs(5),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = backfill.await {
+                tracing::error!(reason=?e, "Block backfill failed");
+            }
+        });
+    }
+
+    if !config.serve_only {
+        let retry = state::sync_retry::drain(
+            storage.clone(),
+            sequencer.clone(),
+            std::time::Duration::from_secs(10),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = retry.await {
+                tracing::error!(reason=?e, "Retrying deferred downloads failed");
+            }
+        });
+    }
 
     // TODO: the error could be recovered, but currently it's required for startup. There should
     // not be other reason for the start to fail than python script not firing up.
@@ -67,14 +210,42 @@ async fn main() -> anyhow::Result<()> {
         "Creating python process for call handling. Have you setup our Python dependencies?",
     )?;
 
-    let api = rpc::api::RpcApi::new(storage, sequencer, network_chain, sync_state)
-        .with_call_handling(call_handle);
+    let admin_storage = storage.clone();
+    let api = rpc::api::RpcApi::new(storage, sequencer, network_chain, sync_state.clone())
+        .with_call_handling(call_handle)
+        .with_configuration(configuration);
+    let api = match config.enable_sql_rpc {
+        true => api.with_sql_rpc(),
+        false => api,
+    };
+    let api = match config.forward_transactions {
+        true => api,
+        false => api.without_transaction_forwarding(),
+    };
+    let api = match shadow_storage {
+        Some(shadow_storage) => api.with_shadow_storage(shadow_storage),
+        None => api,
+    };
 
+    let admin_api = rpc::admin::AdminApi::new(admin_storage, api.rate_limiter(), sync_state)
+        .with_log_filter(log_filter);
     let (rpc_handle, local_addr) = rpc::run_server(config.http_rpc_addr, api)
         .await
         .context("Starting the RPC server")?;
     info!("📡 HTTP-RPC server started on: {}", local_addr);
 
+    // The handle must be kept alive, dropping it stops the server.
+    let _admin_handle = match config.admin_rpc_addr {
+        Some(addr) => {
+            let (handle, local_addr) = rpc::admin::run_admin_server(addr, admin_api)
+                .await
+                .context("Starting the admin RPC server")?;
+            info!("🔧 Admin RPC server started on: {}", local_addr);
+            Some(handle)
+        }
+        None => None,
+    };
+
     let update_handle = tokio::spawn(pathfinder_lib::update::poll_github_for_releases());
 
     // Monitor our spawned process tasks.
@@ -106,31 +277,96 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Runs a one-off [command](config::Command) in a single database transaction.
+fn run_command(
+    storage: &Storage,
+    command: config::Command,
+    config: &config::Configuration,
+) -> anyhow::Result<()> {
+    let mut connection = storage
+        .connection()
+        .context("Opening database connection")?;
+    let transaction = connection
+        .transaction()
+        .context("Create database transaction")?;
+
+    match command {
+        config::Command::ReprocessBlocks { from, to } => {
+            let summary =
+                state::reprocess::reprocess_blocks(&transaction, from, to, storage.limits())
+                    .with_context(|| format!("Reprocessing blocks {} to {}", from.0, to.0))?;
+            info!(
+                repaired = summary.repaired,
+                unchanged = summary.unchanged,
+                missing = summary.missing,
+                "Blocks reprocessed."
+            );
+        }
+        config::Command::GcTrees { keep_blocks } => {
+            let summary =
+                state::tree_gc::gc_trees(&transaction, keep_blocks, config.max_reorg_depth)
+                    .context("Garbage collecting state trees")?;
+            info!(
+                pruned_blocks = summary.pruned_blocks,
+                released_contract_roots = summary.released_contract_roots,
+                "State trees garbage collected."
+            );
+        }
+        config::Command::RepackTransactions => {
+            let repacked =
+                storage::StarknetTransactionsTable::repack(&transaction, storage.limits())
+                    .context("Repacking transactions")?;
+            info!(%repacked, "Transactions repacked.");
+        }
+    }
+
+    transaction.commit().context("Commit database transaction")
+}
+
+/// The directives of the log filter installed by [setup_tracing].
+fn log_filter_directives() -> String {
+    std::env::var("RUST_LOG").unwrap_or_default()
+}
+
 #[cfg(feature = "tokio-console")]
-fn setup_tracing() {
+fn setup_tracing() -> rpc::admin::LogFilter {
+    use std::sync::RwLock;
     use tracing_subscriber::prelude::*;
 
     // EnvFilter isn't really a Filter, so this we need this ugly workaround for filtering with it.
     // See https://github.com/tokio-rs/tracing/issues/1868 for more details.
-    let env_filter = Arc::new(tracing_subscriber::EnvFilter::from_default_env());
+    let env_filter = Arc::new(RwLock::new(
+        tracing_subscriber::EnvFilter::from_default_env(),
+    ));
+    let reloaded = env_filter.clone();
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_target(false)
         .compact()
         .with_filter(tracing_subscriber::filter::dynamic_filter_fn(
-            move |m, c| env_filter.enabled(m, c.clone()),
+            move |m, c| env_filter.read().unwrap().enabled(m, c.clone()),
         ));
     let console_layer = console_subscriber::spawn();
     tracing_subscriber::registry()
         .with(fmt_layer)
         .with(console_layer)
         .init();
+
+    rpc::admin::LogFilter::new(log_filter_directives(), move |filter| {
+        *reloaded.write().unwrap() = filter;
+        tracing::callsite::rebuild_interest_cache();
+        Ok(())
+    })
 }
 
 #[cfg(not(feature = "tokio-console"))]
-fn setup_tracing() {
-    tracing_subscriber::fmt()
+fn setup_tracing() -> rpc::admin::LogFilter {
+    let builder = tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .with_target(false)
         .compact()
-        .init();
+        .with_filter_reloading();
+    let handle = builder.reload_handle();
+    builder.init();
+
+    rpc::admin::LogFilter::from_reload_handle(log_filter_directives(), handle)
 }
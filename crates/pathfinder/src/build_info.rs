@@ -0,0 +1,29 @@
+//! Build metadata embedded at compile time.
+//!
+//! Git information is provided by `vergen`, the remainder is set by our build script.
+
+/// The crate version as specified in `Cargo.toml`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Output of `git describe`, expected to be `$(last_git_tag)-$(commits_since)-$(commit_hash)`,
+/// with a `-dirty` suffix if the working tree had uncommitted changes.
+pub const GIT_DESCRIBE: &str = env!("VERGEN_GIT_SEMVER_LIGHTWEIGHT");
+
+/// The full hash of the git commit this binary was built from.
+pub const GIT_COMMIT_HASH: &str = env!("VERGEN_GIT_SHA");
+
+/// Unix timestamp (in seconds) of when the build script ran.
+pub const BUILD_TIMESTAMP: &str = env!("PATHFINDER_BUILD_TIMESTAMP");
+
+/// Comma separated list of the enabled cargo features.
+const FEATURES: &str = env!("PATHFINDER_FEATURES");
+
+/// Returns `true` if the binary was built from a working tree with uncommitted changes.
+pub fn git_dirty() -> bool {
+    GIT_DESCRIBE.ends_with("-dirty")
+}
+
+/// Returns the cargo features this binary was built with.
+pub fn features() -> Vec<&'static str> {
+    FEATURES.split(',').filter(|f| !f.is_empty()).collect()
+}
@@ -1,13 +1,15 @@
 //! External python process pool for execute calls.
 //!
 //! The python processes are executing `$REPO_ROOT/py/src/call.py` and communicate over by sending
-//! and receiving json + `'\n'`. Main entry point is the [`service::start`] which manages running
+//! and receiving length prefixed msgpack frames, after negotiating the protocol version as
+//! described in [`protocol`]. Main entry point is the [`service::start`] which manages running
 //! given number of N processes. The python script uses sqlite to read pathfinder's database, which
 //! should not cause issues in WAL mode.
 //!
 //! Use of the call functionality happens through [`Handle::call`], which hands out futures in
 //! exchange for [`Call`] and [`BlockHashOrTag`], former selects the contract and method to call,
-//! latter selectes "when" to call it on the history. None of the block or tags are resolved over
+//! latter selectes "when" to call it on the history. [`Handle::estimate_message_fee`] works the
+//! same, but invokes an L1 handler and returns the fee of doing so instead. None of the block or tags are resolved over
 //! at rust side, because transactions cannot carry over between processes.
 //!
 //! While the python script does attempt to resolve "latest", it probably needs fixing. To make it
@@ -16,13 +18,14 @@
 //! to add an alternative way to use a hash directly rather as a root than assume it's a block hash.
 
 use crate::core::CallResultValue;
-use crate::rpc::types::{request::Call, BlockHashOrTag};
+use crate::rpc::types::{reply::FeeEstimate, request::Call, BlockHashOrTag};
 use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot, Mutex};
 
 mod de;
 use de::{ErrorKind, Status, Timings};
 
+mod protocol;
 mod ser;
 mod sub_process;
 
@@ -48,7 +51,7 @@ impl Handle {
         let continued_span = tracing::info_span!("ext_py_call", pid = Empty);
 
         self.command_tx
-            .send(((call, at_block, tx), continued_span))
+            .send(((call, at_block, Responder::Call(tx)), continued_span))
             .await
             .map_err(|_| CallFailure::Shutdown)?;
 
@@ -57,6 +60,39 @@ impl Handle {
             Err(_closed) => Err(CallFailure::Shutdown),
         }
     }
+
+    /// Estimate the fee of invoking the L1 handler given by `call`, as an L1 to L2 message would.
+    ///
+    /// The calldata must already start with the L1 sender of the message.
+    pub async fn estimate_message_fee(
+        &self,
+        call: Call,
+        at_block: BlockHashOrTag,
+    ) -> Result<FeeEstimate, CallFailure> {
+        use tracing::field::Empty;
+        let (tx, rx) = oneshot::channel();
+
+        let continued_span = tracing::info_span!("ext_py_estimate_message_fee", pid = Empty);
+
+        self.command_tx
+            .send(((call, at_block, Responder::EstimateFee(tx)), continued_span))
+            .await
+            .map_err(|_| CallFailure::Shutdown)?;
+
+        match rx.await {
+            Ok(x) => x,
+            Err(_closed) => Err(CallFailure::Shutdown),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Handle {
+    /// Creates a handle whose commands are received by the caller instead of python executors.
+    pub(crate) fn for_test() -> (Self, mpsc::Receiver<(Command, tracing::Span)>) {
+        let (command_tx, command_rx) = mpsc::channel(1);
+        (Self { command_tx }, command_rx)
+    }
 }
 
 /// Reasons for a call to fail.
@@ -66,6 +102,9 @@ pub enum CallFailure {
     NoSuchBlock,
     /// The called top-level contract could not be found.
     NoSuchContract,
+    /// The called contract has no such entry point. Only reported when estimating the fee of a
+    /// message, calls report a missing entry point as a failed execution.
+    NoSuchEntryPoint,
     /// `cairo-lang` failed the call, string has the exception name.
     ExecutionFailed(String),
     /// Internal, opaque-ish failure reason, none of them signal an issue with the call.
@@ -80,6 +119,7 @@ impl From<ErrorKind> for CallFailure {
         match e {
             NoSuchBlock => CallFailure::NoSuchBlock,
             NoSuchContract => CallFailure::NoSuchContract,
+            NoSuchEntryPoint => CallFailure::NoSuchEntryPoint,
             InvalidSchemaVersion => CallFailure::Internal("Wrong database version"),
             InvalidCommand => CallFailure::Internal("Invalid json sent"),
         }
@@ -91,11 +131,54 @@ impl From<ErrorKind> for CallFailure {
 type SharedReceiver<T> = Arc<Mutex<mpsc::Receiver<T>>>;
 
 /// Alias for the type used to transfer commands over to executors.
-type Command = (
-    Call,
-    BlockHashOrTag,
-    oneshot::Sender<Result<Vec<CallResultValue>, CallFailure>>,
-);
+pub(crate) type Command = (Call, BlockHashOrTag, Responder);
+
+/// The output of an executed command: the retdata of the call, and its fee if it was requested.
+type Output = (Vec<CallResultValue>, Option<FeeEstimate>);
+
+/// Where the result of a [`Command`] is sent, which also determines what is asked of python.
+#[derive(Debug)]
+pub(crate) enum Responder {
+    /// Returns the retdata of a call.
+    Call(oneshot::Sender<Result<Vec<CallResultValue>, CallFailure>>),
+    /// Returns the fee of invoking an L1 handler.
+    EstimateFee(oneshot::Sender<Result<FeeEstimate, CallFailure>>),
+}
+
+impl Responder {
+    pub(crate) fn estimates_fee(&self) -> bool {
+        matches!(self, Responder::EstimateFee(_))
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        match self {
+            Responder::Call(tx) => tx.is_closed(),
+            Responder::EstimateFee(tx) => tx.is_closed(),
+        }
+    }
+
+    /// Completes once the caller has stopped waiting for the result.
+    pub(crate) async fn closed(&mut self) {
+        match self {
+            Responder::Call(tx) => tx.closed().await,
+            Responder::EstimateFee(tx) => tx.closed().await,
+        }
+    }
+
+    /// Sends the part of `result` which the caller asked for.
+    pub(crate) fn send(self, result: Result<Output, CallFailure>) {
+        match self {
+            Responder::Call(tx) => {
+                let _ = tx.send(result.map(|(retdata, _)| retdata));
+            }
+            Responder::EstimateFee(tx) => {
+                let _ = tx.send(result.and_then(|(_, fee)| {
+                    fee.ok_or(CallFailure::Internal("Fee estimate missing from response"))
+                }));
+            }
+        }
+    }
+}
 
 /// Informational events from python process executors.
 #[derive(Debug)]
@@ -112,6 +195,7 @@ enum SubprocessExitReason {
     Shutdown,
     Death,
     Cancellation,
+    MalformedFrame,
 }
 
 /// Errors which can happen during an RPC alike round with the subprocess.
@@ -120,6 +204,10 @@ enum SubprocessError {
     IO,
     /// Python sent us invalid response
     InvalidJson(serde_json::Error),
+    /// Python sent us a frame with an impossible length
+    InvalidFrame,
+    /// Python sent us a frame which could not be decoded
+    InvalidMsgpack(rmp_serde::decode::Error),
     /// Python sent us a response we couldn't understand
     InvalidResponse,
 }
@@ -1,7 +1,8 @@
-//! The json deserializable types
+//! The deserializable types, received as json or msgpack depending on the negotiated protocol
 
-use super::{CallFailure, SubprocessError};
+use super::{CallFailure, Output, SubprocessError};
 use crate::core::CallResultValue;
+use crate::rpc::types::reply::FeeEstimate;
 
 /// The python loop currently responds with these four possibilities. An enum would be more
 /// appropriate.
@@ -24,13 +25,16 @@ pub(crate) struct ChildResponse<'a> {
     /// The real output from the contract when `status` is [`Status::Ok`].
     #[serde(default)]
     output: Vec<CallResultValue>,
+    /// The fee of the call when `status` is [`Status::Ok`] and it was requested.
+    #[serde(default)]
+    fee: Option<FeeEstimate>,
 }
 
 impl<'a> ChildResponse<'a> {
     pub(super) fn refine(mut self) -> Result<RefinedChildResponse<'a>, SubprocessError> {
         match (&self.status, &mut self.kind, &mut self.exception) {
             (Status::Ok, None, None) => Ok(RefinedChildResponse {
-                status: RefinedStatus::Ok(self.output),
+                status: RefinedStatus::Ok(self.output, self.fee),
                 timings: self.timings,
             }),
             (Status::Error, x @ Some(_), None) => Ok(RefinedChildResponse {
@@ -48,18 +52,12 @@ impl<'a> ChildResponse<'a> {
 }
 
 impl RefinedChildResponse<'_> {
-    pub fn into_messages(
-        self,
-    ) -> (
-        Option<Timings>,
-        Status,
-        Result<Vec<CallResultValue>, CallFailure>,
-    ) {
+    pub fn into_messages(self) -> (Option<Timings>, Status, Result<Output, CallFailure>) {
         match self {
             RefinedChildResponse {
                 timings,
-                status: RefinedStatus::Ok(x),
-            } => (Some(timings), Status::Ok, Ok(x)),
+                status: RefinedStatus::Ok(x, fee),
+            } => (Some(timings), Status::Ok, Ok((x, fee))),
             RefinedChildResponse {
                 timings,
                 status: RefinedStatus::Error(e),
@@ -83,6 +81,8 @@ pub enum ErrorKind {
     NoSuchBlock,
     #[serde(rename = "NO_SUCH_CONTRACT")]
     NoSuchContract,
+    #[serde(rename = "NO_SUCH_ENTRY_POINT")]
+    NoSuchEntryPoint,
     #[serde(rename = "INVALID_SCHEMA_VERSION")]
     InvalidSchemaVersion,
     #[serde(rename = "INVALID_INPUT")]
@@ -121,7 +121,7 @@ pub struct RefinedChildResponse<'a> {
 
 /// More sensible alternative to [`Status`].
 pub enum RefinedStatus<'a> {
-    Ok(Vec<CallResultValue>),
+    Ok(Vec<CallResultValue>, Option<FeeEstimate>),
     Error(ErrorKind),
     Failed(std::borrow::Cow<'a, str>),
 }
@@ -0,0 +1,187 @@
+//! Version negotiation and framing of the messages exchanged with the python process.
+//!
+//! Once the python process has started up it announces the highest protocol version and the
+//! features it supports as a single json line:
+//!
+//! ```text
+//! {"protocol": 2, "features": ["max_steps"]}
+//! ```
+//!
+//! to which we reply with the version both sides support, again as a json line
+//! (`{"protocol": 2}`). From that point on, all messages are framed as a 4-byte big-endian length
+//! prefix followed by a msgpack payload.
+//!
+//! Older python processes only write `ready`, in which case no reply is sent and both sides keep
+//! exchanging json + `'\n'` ([`Protocol::JsonLines`]). This fallback is only kept for a single
+//! release.
+
+use super::SubprocessError;
+use anyhow::Context;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStdin, ChildStdout};
+
+/// The highest protocol version we support.
+pub(super) const PROTOCOL_VERSION: u32 = 2;
+
+/// Upper bound for a single frame, anything longer is assumed to be the result of a corrupted
+/// length prefix.
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// The wire format agreed upon with a python process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Protocol {
+    /// Json documents separated by `'\n'`, spoken by python processes before versioning.
+    JsonLines,
+    /// Length prefixed msgpack payloads.
+    Framed,
+}
+
+/// Optional capabilities of the python process.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct Features {
+    pub estimate_fee: bool,
+    pub max_steps: bool,
+    pub traces: bool,
+}
+
+impl<'a> FromIterator<&'a str> for Features {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        let mut features = Features::default();
+        for feature in iter {
+            match feature {
+                "estimate_fee" => features.estimate_fee = true,
+                "max_steps" => features.max_steps = true,
+                "traces" => features.traces = true,
+                // newer python processes may know of features we don't
+                _ => {}
+            }
+        }
+        features
+    }
+}
+
+/// Outcome of the handshake with a python process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Negotiated {
+    pub protocol: Protocol,
+    pub features: Features,
+}
+
+/// The first line written by a versioned python process.
+#[derive(serde::Deserialize, Debug)]
+struct Hello {
+    protocol: u32,
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+/// Our reply to [`Hello`].
+#[derive(serde::Serialize, Debug)]
+struct HelloReply {
+    protocol: u32,
+}
+
+/// Reads the python process' announcement and replies with the agreed upon protocol version.
+pub(super) async fn handshake(
+    stdin: &mut ChildStdin,
+    stdout: &mut BufReader<ChildStdout>,
+) -> anyhow::Result<Negotiated> {
+    let mut line = String::new();
+
+    // reasons for this part to error out:
+    // - invalid schema version
+    // - some other pythonic thing happens, for example, no call.py found
+    let read = stdout
+        .read_line(&mut line)
+        .await
+        .context("Failed to read handshake from python process")?;
+    anyhow::ensure!(read != 0, "Python process exited before the handshake");
+
+    let line = line.trim();
+
+    if line == "ready" {
+        return Ok(Negotiated {
+            protocol: Protocol::JsonLines,
+            features: Features::default(),
+        });
+    }
+
+    let hello = serde_json::from_str::<Hello>(line).map_err(|e| {
+        anyhow::anyhow!("Failed to read handshake from python process, read: {line:?}: {e}")
+    })?;
+
+    let version = hello.protocol.min(PROTOCOL_VERSION);
+    anyhow::ensure!(
+        version >= 1,
+        "Python process announced an invalid protocol version: {}",
+        hello.protocol
+    );
+
+    let mut reply = serde_json::to_vec(&HelloReply { protocol: version })?;
+    reply.push(b'\n');
+    stdin.write_all(&reply).await?;
+    stdin.flush().await?;
+
+    Ok(Negotiated {
+        protocol: if version >= 2 {
+            Protocol::Framed
+        } else {
+            Protocol::JsonLines
+        },
+        features: hello.features.iter().map(String::as_str).collect(),
+    })
+}
+
+/// Writes `payload` as a single frame.
+///
+/// Not cancellation safe, similar to [`AsyncWriteExt::write_all`].
+pub(super) async fn write_frame(stdin: &mut ChildStdin, payload: &[u8]) -> std::io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Frame too long"))?;
+
+    stdin.write_all(&len.to_be_bytes()).await?;
+    stdin.write_all(payload).await?;
+    stdin.flush().await
+}
+
+/// Reads a single frame into `buffer`, replacing its previous contents.
+pub(super) async fn read_frame(
+    stdout: &mut BufReader<ChildStdout>,
+    buffer: &mut Vec<u8>,
+) -> Result<(), SubprocessError> {
+    buffer.clear();
+
+    let mut len = [0u8; 4];
+    stdout.read_exact(&mut len).await?;
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(SubprocessError::InvalidFrame);
+    }
+
+    buffer.resize(len, 0);
+    stdout.read_exact(buffer).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Features;
+
+    #[test]
+    fn features_from_names() {
+        let features = ["traces", "estimate_fee", "something_new"]
+            .into_iter()
+            .collect::<Features>();
+
+        assert_eq!(
+            features,
+            Features {
+                estimate_fee: true,
+                max_steps: false,
+                traces: true,
+            }
+        );
+    }
+}
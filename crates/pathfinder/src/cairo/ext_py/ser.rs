@@ -1,7 +1,8 @@
-//! The json serializable types
+//! The serializable types, sent as json or msgpack depending on the negotiated protocol
 
 use crate::core::{CallParam, ContractAddress, EntryPoint};
 use crate::rpc::types::BlockHashOrTag;
+use crate::sequencer::reply::transaction::EntryPointType;
 
 /// The command we send to the python loop.
 #[derive(serde::Serialize, Debug)]
@@ -10,4 +11,10 @@ pub struct ChildCommand<'a> {
     pub calldata: &'a [CallParam],
     pub entry_point_selector: &'a EntryPoint,
     pub at_block: &'a BlockHashOrTag,
+    /// Only sent for L1 handlers, python defaults to external entry points.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry_point_type: Option<EntryPointType>,
+    /// Only sent if set, requires the `estimate_fee` feature.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub estimate_fee: bool,
 }
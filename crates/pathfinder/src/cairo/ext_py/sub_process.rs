@@ -2,9 +2,11 @@
 
 use super::{
     de::{ChildResponse, RefinedChildResponse, Status, Timings},
+    protocol::{self, Negotiated, Protocol},
     ser::ChildCommand,
     CallFailure, Command, SharedReceiver, SubProcessEvent, SubprocessError, SubprocessExitReason,
 };
+use crate::sequencer::reply::transaction::EntryPointType;
 use anyhow::Context;
 use std::{io::Write, path::PathBuf};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -18,21 +20,40 @@ use tracing::{debug, error, info, trace, warn, Instrument};
 /// similarly to spawning this as a task usually handled.
 ///
 /// Launching happens in two stages, similar to the python process. Initially we only launch, then
-/// read `"ready\n"` from the subprocess and after that enter the loop where we contend for the
-/// commands.
-#[tracing::instrument(name = "subproc", skip_all, fields(pid))]
+/// complete the [`protocol::handshake`] with the subprocess and after that enter the loop where we
+/// contend for the commands.
 pub(super) async fn launch_python(
+    database_path: PathBuf,
+    commands: SharedReceiver<(Command, tracing::Span)>,
+    status_updates: mpsc::Sender<SubProcessEvent>,
+    shutdown_rx: broadcast::Receiver<()>,
+) -> anyhow::Result<(u32, Option<std::process::ExitStatus>, SubprocessExitReason)> {
+    launch(
+        PYTHON_SCRIPT_SOURCE,
+        database_path,
+        commands,
+        status_updates,
+        shutdown_rx,
+    )
+    .await
+}
+
+/// [`launch_python`] with the given script instead of `call.py`.
+#[tracing::instrument(name = "subproc", skip_all, fields(pid))]
+async fn launch(
+    script_source: &str,
     database_path: PathBuf,
     commands: SharedReceiver<(Command, tracing::Span)>,
     status_updates: mpsc::Sender<SubProcessEvent>,
     mut shutdown_rx: broadcast::Receiver<()>,
 ) -> anyhow::Result<(u32, Option<std::process::ExitStatus>, SubprocessExitReason)> {
-    let (mut child, pid, mut stdin, mut stdout, mut buffer) = match spawn(database_path).await {
-        Ok(tuple) => tuple,
-        Err(e) => {
-            return Err(e.context("Failed to start python subprocess"));
-        }
-    };
+    let (mut child, pid, mut stdin, mut stdout, negotiated) =
+        match spawn(script_source, database_path).await {
+            Ok(tuple) => tuple,
+            Err(e) => {
+                return Err(e.context("Failed to start python subprocess"));
+            }
+        };
 
     if status_updates
         .send(SubProcessEvent::ProcessLaunched(pid))
@@ -43,9 +64,14 @@ pub(super) async fn launch_python(
         return Err(anyhow::anyhow!("Failed to notify of start"));
     }
 
-    info!("Subprocess launched");
+    info!(protocol=?negotiated.protocol, features=?negotiated.features, "Subprocess launched");
+
+    if negotiated.protocol == Protocol::JsonLines {
+        warn!("Python subprocess does not support the framed protocol, falling back to json");
+    }
 
     let mut command_buffer = Vec::new();
+    let mut buffer = Vec::new();
 
     // TODO: Why not have an outer loop to respawn a process fast? The idea occured during review.
     // Currently the "policy" over respawning is controlled by the "service" in `super::start`.
@@ -85,6 +111,7 @@ pub(super) async fn launch_python(
         let (timings, status) = {
             let op = process(
                 command,
+                negotiated,
                 &mut command_buffer,
                 &mut stdin,
                 &mut stdout,
@@ -163,13 +190,14 @@ pub(super) async fn launch_python(
 const PYTHON_SCRIPT_SOURCE: &str = include_str!("../../../../../py/src/call.py");
 
 async fn spawn(
+    script_source: &str,
     database_path: PathBuf,
-) -> anyhow::Result<(Child, u32, ChildStdin, BufReader<ChildStdout>, String)> {
+) -> anyhow::Result<(Child, u32, ChildStdin, BufReader<ChildStdout>, Negotiated)> {
     let script_file = tempfile::NamedTempFile::new()
         .context("Failed to create temporary file for Python script")?;
     script_file
         .as_file()
-        .write_all(PYTHON_SCRIPT_SOURCE.as_bytes())
+        .write_all(script_source.as_bytes())
         .context("Failed to write temporary file for Python script")?;
 
     // FIXME: use choom, add something over /proc/self/oom_score_adj ?
@@ -195,7 +223,7 @@ async fn spawn(
         span.record("pid", &pid);
     }
 
-    let stdin = child.stdin.take().expect("stdin was piped");
+    let mut stdin = child.stdin.take().expect("stdin was piped");
     let stdout = child.stdout.take().expect("stdout was piped");
 
     // spawn the stderr out, just forget it it will die down once the process has been torn down
@@ -226,24 +254,10 @@ async fn spawn(
     // default buffer is fine for us ... but this is double buffering, for no good reason
     // it could actually be destroyed even between runs, because the buffer should be empty
     let mut stdout = BufReader::new(stdout);
-    let mut buffer = String::new();
-
-    // reasons for this part to error out:
-    // - invalid schema version
-    // - some other pythonic thing happens, for example, no call.py found
-    let _read = stdout
-        .read_line(&mut buffer)
-        .await
-        .context("Failed to read 'ready' from python process")?;
 
-    anyhow::ensure!(
-        // buffer will contain the newline, which doesn't bother serde_json
-        buffer.trim() == "ready",
-        "Failed to read 'ready' from python process, read: {buffer:?}"
-    );
-    buffer.clear();
+    let negotiated = protocol::handshake(&mut stdin, &mut stdout).await?;
 
-    Ok((child, pid, stdin, stdout, buffer))
+    Ok((child, pid, stdin, stdout, negotiated))
 }
 
 /// Process a single command with the external process.
@@ -254,28 +268,46 @@ async fn spawn(
 /// - Err(Some(_)) if the process can no longer be reused
 async fn process(
     command: Command,
+    negotiated: Negotiated,
     command_buffer: &mut Vec<u8>,
     stdin: &mut ChildStdin,
     stdout: &mut BufReader<ChildStdout>,
-    buffer: &mut String,
+    buffer: &mut Vec<u8>,
 ) -> Result<(Option<Timings>, Status), Option<SubprocessExitReason>> {
     let (call, at_block, mut response) = command;
+    let protocol = negotiated.protocol;
     command_buffer.clear();
 
+    let estimate_fee = response.estimates_fee();
+    if estimate_fee && !negotiated.features.estimate_fee {
+        response.send(Err(CallFailure::Internal(
+            "Fee estimation is not supported by the python process",
+        )));
+        return Err(None);
+    }
+
     let cmd = ChildCommand {
         contract_address: &call.contract_address,
         calldata: &call.calldata,
         entry_point_selector: &call.entry_point_selector,
         at_block: &at_block,
+        // fees are only estimated for L1 to L2 messages
+        entry_point_type: estimate_fee.then(|| EntryPointType::L1Handler),
+        estimate_fee,
     };
 
     let mut cursor = std::io::Cursor::new(command_buffer);
 
-    if let Err(e) = serde_json::to_writer(&mut cursor, &cmd) {
-        error!(command=?cmd, error=%e, "Failed to render command as json");
-        let _ = response.send(Err(CallFailure::Internal(
-            "Failed to render command as json",
-        )));
+    let rendered = match protocol {
+        Protocol::JsonLines => serde_json::to_writer(&mut cursor, &cmd).map_err(|e| e.to_string()),
+        Protocol::Framed => {
+            rmp_serde::encode::write_named(&mut cursor, &cmd).map_err(|e| e.to_string())
+        }
+    };
+
+    if let Err(e) = rendered {
+        error!(command=?cmd, error=%e, "Failed to render command");
+        response.send(Err(CallFailure::Internal("Failed to render command")));
         return Err(None);
     }
 
@@ -286,7 +318,7 @@ async fn process(
     let res = {
         // AsyncWriteExt::write_all used in the rpc_round is not cancellation safe, but
         // similar to above, if we lose the race, will kill the subprocess and get out.
-        let rpc_op = rpc_round(command_buffer, stdin, stdout, buffer);
+        let rpc_op = rpc_round(protocol, command_buffer, stdin, stdout, buffer);
         tokio::pin!(rpc_op);
 
         tokio::select! {
@@ -312,6 +344,7 @@ async fn process(
         Err(SubprocessError::InvalidJson(error)) => {
             // buffer still holds the response... might be good for debugging
             // this doesn't however mess up our line at once, so no worries.
+            let buffer = String::from_utf8_lossy(buffer);
             error!(%error, ?buffer, "Failed to parse json from subprocess");
             (
                 None,
@@ -320,16 +353,33 @@ async fn process(
             )
         }
         Err(SubprocessError::InvalidResponse) => {
-            error!(?buffer, "Failed to understand parsed json from subprocess");
+            let buffer = String::from_utf8_lossy(buffer);
+            error!(
+                ?buffer,
+                "Failed to understand parsed response from subprocess"
+            );
             (
                 None,
                 Status::Failed,
                 Err(CallFailure::Internal("Invalid json received")),
             )
         }
+        Err(SubprocessError::InvalidFrame) => {
+            error!("Subprocess sent a frame exceeding the maximum length");
+            response.send(Err(CallFailure::Internal("Malformed frame received")));
+
+            // the framing can no longer be trusted, so the process must be recycled
+            return Err(Some(SubprocessExitReason::MalformedFrame));
+        }
+        Err(SubprocessError::InvalidMsgpack(error)) => {
+            error!(%error, "Failed to decode frame from subprocess");
+            response.send(Err(CallFailure::Internal("Malformed frame received")));
+
+            return Err(Some(SubprocessExitReason::MalformedFrame));
+        }
         Err(SubprocessError::IO) => {
             let error = CallFailure::Internal("Input/output");
-            let _ = response.send(Err(error));
+            response.send(Err(error));
 
             // TODO: consider if we'd just retry; put this back into the queue?
             return Err(Some(SubprocessExitReason::UnrecoverableIO));
@@ -340,39 +390,208 @@ async fn process(
     // let result = response.send(sent_response).map_err(|_| ());
     // trace!(?result, "Call result sent");
 
-    let _ = response.send(sent_response);
+    response.send(sent_response);
 
     Ok((timings, status))
 }
 
 /// Run a round of writing out the request, and reading a sane response type.
 async fn rpc_round<'a>(
+    protocol: Protocol,
     cmd: &[u8],
     stdin: &mut tokio::process::ChildStdin,
     stdout: &mut tokio::io::BufReader<tokio::process::ChildStdout>,
-    buffer: &'a mut String,
+    buffer: &'a mut Vec<u8>,
 ) -> Result<RefinedChildResponse<'a>, SubprocessError> {
-    // TODO: using a vectored write here would make most sense, but alas, advancing [IoSlice]'s is
-    // still unstable. it could be copied, but we'd still lack `write_vectored_all`.
-    //
-    // note: write_all are not cancellation safe, and we call this from tokio::select! see callsite
-    // for more discussion.
-    stdin.write_all(cmd).await?;
-    stdin.write_all(&b"\n"[..]).await?;
-    stdin.flush().await?;
-
-    // the read buffer is cleared very late to allow logging the output in case of an error.
-    buffer.clear();
-
-    let read = stdout.read_line(buffer).await?;
-
-    if read == 0 {
-        // EOF
-        return Err(SubprocessError::IO);
-    }
+    let resp = match protocol {
+        Protocol::JsonLines => {
+            // TODO: using a vectored write here would make most sense, but alas, advancing
+            // [IoSlice]'s is still unstable. it could be copied, but we'd still lack
+            // `write_vectored_all`.
+            //
+            // note: write_all are not cancellation safe, and we call this from tokio::select!
+            // see callsite for more discussion.
+            stdin.write_all(cmd).await?;
+            stdin.write_all(&b"\n"[..]).await?;
+            stdin.flush().await?;
+
+            // the read buffer is cleared very late to allow logging the output in case of an
+            // error.
+            buffer.clear();
+
+            let read = stdout.read_until(b'\n', buffer).await?;
+
+            if read == 0 {
+                // EOF
+                return Err(SubprocessError::IO);
+            }
+
+            serde_json::from_slice::<ChildResponse>(buffer).map_err(SubprocessError::InvalidJson)?
+        }
+        Protocol::Framed => {
+            protocol::write_frame(stdin, cmd).await?;
+            protocol::read_frame(stdout, buffer).await?;
 
-    let resp =
-        serde_json::from_str::<ChildResponse>(buffer).map_err(SubprocessError::InvalidJson)?;
+            rmp_serde::from_slice::<ChildResponse>(buffer)
+                .map_err(SubprocessError::InvalidMsgpack)?
+        }
+    };
 
     resp.refine()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::launch;
+    use crate::cairo::ext_py::{CallFailure, Responder, SubprocessExitReason};
+    use crate::core::{CallParam, CallResultValue, ContractAddress, EntryPoint};
+    use crate::rpc::types::{request::Call, BlockHashOrTag, Tag};
+    use stark_hash::StarkHash;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+
+    /// Fake python worker which only speaks the json lines protocol, like before versioning.
+    const LEGACY_WORKER: &str = r#"
+import json, sys
+print("ready", flush=True)
+command = json.loads(sys.stdin.buffer.readline())
+assert command["calldata"] == ["0x84"], command
+print(json.dumps(dict(status="ok", output=["0x1"], timings=dict())), flush=True)
+sys.stdin.buffer.read()
+"#;
+
+    /// Fake python worker which negotiates the framed protocol and then replies to the first
+    /// command with the given hex encoded bytes.
+    fn framed_worker(response_hex: &str) -> String {
+        format!(
+            r#"
+import json, sys
+stdin = sys.stdin.buffer
+print(json.dumps(dict(protocol=2, features=["max_steps"])), flush=True)
+reply = json.loads(stdin.readline())
+assert reply["protocol"] == 2, reply
+length = int.from_bytes(stdin.read(4), "big")
+assert len(stdin.read(length)) == length
+sys.stdout.buffer.write(bytes.fromhex("{response_hex}"))
+sys.stdout.buffer.flush()
+stdin.read()
+"#
+        )
+    }
+
+    /// Runs a single call against a worker executing `script`, returning the call result and the
+    /// reason the worker was stopped.
+    async fn call_once(
+        script: &str,
+    ) -> (
+        Result<Vec<CallResultValue>, CallFailure>,
+        SubprocessExitReason,
+    ) {
+        let (command_tx, command_rx) = mpsc::channel(1);
+        let (status_tx, _status_rx) = mpsc::channel(10);
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let (response_tx, response_rx) = oneshot::channel();
+
+        let call = Call {
+            contract_address: ContractAddress(StarkHash::from_hex_str("0x57dde").unwrap()),
+            calldata: vec![CallParam(StarkHash::from_hex_str("0x84").unwrap())],
+            entry_point_selector: EntryPoint::hashed(&b"get_value"[..]),
+        };
+
+        command_tx
+            .send((
+                (
+                    call,
+                    BlockHashOrTag::Tag(Tag::Latest),
+                    Responder::Call(response_tx),
+                ),
+                tracing::Span::none(),
+            ))
+            .await
+            .unwrap();
+        // no more commands, so the worker is shut down after the first one
+        drop(command_tx);
+
+        let (_pid, _exit_status, exit_reason) = launch(
+            script,
+            PathBuf::from("not-used.sqlite"),
+            Arc::new(Mutex::new(command_rx)),
+            status_tx,
+            shutdown_rx,
+        )
+        .await
+        .unwrap();
+
+        (response_rx.await.unwrap(), exit_reason)
+    }
+
+    fn frame(payload: &[u8]) -> String {
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        hex::encode(frame)
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn handshake_success() {
+        #[derive(serde::Serialize)]
+        struct Response {
+            status: &'static str,
+            output: Vec<&'static str>,
+        }
+
+        let payload = rmp_serde::to_vec_named(&Response {
+            status: "ok",
+            output: vec!["0x1"],
+        })
+        .unwrap();
+
+        let (result, exit_reason) = call_once(&framed_worker(&frame(&payload))).await;
+
+        assert_eq!(
+            result.unwrap(),
+            vec![CallResultValue(StarkHash::from_hex_str("0x1").unwrap())]
+        );
+        assert!(matches!(exit_reason, SubprocessExitReason::Shutdown));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn version_mismatch_falls_back_to_json() {
+        let (result, exit_reason) = call_once(LEGACY_WORKER).await;
+
+        assert_eq!(
+            result.unwrap(),
+            vec![CallResultValue(StarkHash::from_hex_str("0x1").unwrap())]
+        );
+        assert!(matches!(exit_reason, SubprocessExitReason::Shutdown));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn corrupt_frame_recycles_worker() {
+        let (result, exit_reason) = call_once(&framed_worker("ffffffff0badf00d")).await;
+
+        assert!(matches!(result, Err(CallFailure::Internal(_))));
+        assert!(matches!(exit_reason, SubprocessExitReason::MalformedFrame));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn undecodable_frame_recycles_worker() {
+        let (result, exit_reason) = call_once(&framed_worker(&frame(&[0xc1]))).await;
+
+        assert!(matches!(result, Err(CallFailure::Internal(_))));
+        assert!(matches!(exit_reason, SubprocessExitReason::MalformedFrame));
+    }
+
+    #[test]
+    fn python_expects_current_schema_revision() {
+        let expected = super::PYTHON_SCRIPT_SOURCE
+            .lines()
+            .find_map(|line| line.strip_prefix("EXPECTED_SCHEMA_REVISION = "))
+            .expect("call.py defines EXPECTED_SCHEMA_REVISION")
+            .trim()
+            .parse::<u32>()
+            .unwrap();
+
+        assert_eq!(expected, crate::storage::DB_VERSION_CURRENT);
+    }
+}
@@ -1,7 +1,7 @@
 //! Provides [ConfigBuilder] which is a convenient and safe way of collecting
 //! configuration parameters from various sources and combining them into one.
 
-use crate::config::{ConfigOption, Configuration, EthereumConfig};
+use crate::config::{ConfigOption, ConfigSource, Configuration, EthereumConfig};
 use reqwest::Url;
 use std::{collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr};
 
@@ -9,26 +9,73 @@ use std::{collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr};
 ///
 /// Once finalised, can be converted to [Configuration] using `try_build`.
 #[derive(Default, PartialEq, Debug)]
-pub struct ConfigBuilder(HashMap<ConfigOption, String>);
+pub struct ConfigBuilder {
+    values: HashMap<ConfigOption, String>,
+    /// Where the values came from, for the options whose source was recorded.
+    sources: HashMap<ConfigOption, ConfigSource>,
+}
 
 impl ConfigBuilder {
     /// Sets the [ConfigOption] to value; if the value is [None] it gets removed.
     pub fn with(mut self, option: ConfigOption, value: Option<String>) -> Self {
         match value {
-            Some(v) => self.0.insert(option, v),
-            None => self.0.remove(&option),
+            Some(v) => {
+                self.values.insert(option, v);
+            }
+            None => {
+                self.values.remove(&option);
+                self.sources.remove(&option);
+            }
         };
         self
     }
 
+    /// Records `source` as the origin of all options which are currently set.
+    pub fn with_source(mut self, source: ConfigSource) -> Self {
+        for option in self.values.keys() {
+            self.sources.insert(*option, source);
+        }
+        self
+    }
+
+    /// Records `source` as the origin of `option`, if it is set.
+    pub fn with_option_source(mut self, option: ConfigOption, source: ConfigSource) -> Self {
+        if self.values.contains_key(&option) {
+            self.sources.insert(option, source);
+        }
+        self
+    }
+
+    /// Returns where the value of `option` came from, [ConfigSource::Default] if it is not set.
+    ///
+    /// Values set without recording their source are attributed to the command-line.
+    pub fn source(&self, option: ConfigOption) -> ConfigSource {
+        match self.values.contains_key(&option) {
+            true => self
+                .sources
+                .get(&option)
+                .copied()
+                .unwrap_or(ConfigSource::CommandLine),
+            false => ConfigSource::Default,
+        }
+    }
+
     /// Merges two [ConfigBuilder] options together, preferring the values
     /// from [self] if they're not [None].
     pub fn merge(mut self, other: Self) -> Self {
         // `extend` has the opposite effect that we want, so we swop
         // self and other's maps.
-        let mut merged = other.0;
-        merged.extend(self.0.into_iter());
-        self.0 = merged;
+        let mut merged = other.values;
+        let mut sources = other.sources;
+        for (option, value) in self.values {
+            match self.sources.remove(&option) {
+                Some(source) => sources.insert(option, source),
+                None => sources.remove(&option),
+            };
+            merged.insert(option, value);
+        }
+        self.values = merged;
+        self.sources = sources;
         self
     }
 
@@ -37,6 +84,11 @@ impl ConfigBuilder {
     /// required options are set.
     pub fn try_build(mut self) -> std::io::Result<Configuration> {
         use super::DEFAULT_HTTP_RPC_ADDR;
+        use enum_iterator::IntoEnumIterator;
+
+        let sources = ConfigOption::into_enum_iter()
+            .map(|option| (option, self.source(option)))
+            .collect();
 
         // Required parameters.
         let eth_url = self.take_required(ConfigOption::EthereumHttpUrl)?;
@@ -72,13 +124,463 @@ impl ConfigBuilder {
             )
         })?;
 
+        let admin_rpc_addr = self
+            .take(ConfigOption::AdminRpcAddress)
+            .map(|s| {
+                s.parse::<SocketAddr>()
+                    .map_err(|err| err.to_string())
+                    .and_then(|addr| match addr.ip().is_loopback() {
+                        true => Ok(addr),
+                        false => Err("must be a loopback address".to_owned()),
+                    })
+                    .map_err(|err| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!(
+                                "Invalid {} value ({}): {}",
+                                ConfigOption::AdminRpcAddress,
+                                s,
+                                err
+                            ),
+                        )
+                    })
+            })
+            .transpose()?;
+
+        let validate_constructor_calldata = self
+            .take(ConfigOption::ValidateConstructorCalldata)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::ValidateConstructorCalldata,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+
+        let max_blob_size = match self.take(ConfigOption::MaxBlobSize) {
+            Some(s) => match s.parse::<usize>() {
+                Ok(mib) if mib > 0 => mib.checked_mul(1024 * 1024).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): too large",
+                            ConfigOption::MaxBlobSize,
+                            s
+                        ),
+                    )
+                })?,
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): must be positive",
+                            ConfigOption::MaxBlobSize,
+                            s
+                        ),
+                    ))
+                }
+                Err(err) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::MaxBlobSize,
+                            s,
+                            err
+                        ),
+                    ))
+                }
+            },
+            None => crate::storage::DEFAULT_MAX_BLOB_SIZE,
+        };
+
+        // Zero disables the limit.
+        let max_reorg_depth = self
+            .take(ConfigOption::MaxReorgDepth)
+            .map(|s| {
+                s.parse::<u64>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::MaxReorgDepth,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(super::DEFAULT_MAX_REORG_DEPTH);
+        let max_reorg_depth = Some(max_reorg_depth).filter(|depth| *depth > 0);
+
+        let enable_sql_rpc = self
+            .take(ConfigOption::EnableSqlRpc)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::EnableSqlRpc,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+
+        let block_backfill = self
+            .take(ConfigOption::BlockBackfill)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::BlockBackfill,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+
+        let class_backfill = self
+            .take(ConfigOption::ClassBackfill)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::ClassBackfill,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+
+        let statement_cache_capacity = self
+            .take(ConfigOption::StatementCacheCapacity)
+            .map(|s| {
+                s.parse::<usize>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::StatementCacheCapacity,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(crate::storage::DEFAULT_STATEMENT_CACHE_CAPACITY);
+
+        // Zero disables the watchdog.
+        let sync_stall_timeout = self
+            .take(ConfigOption::SyncStallTimeout)
+            .map(|s| {
+                s.parse::<u64>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::SyncStallTimeout,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(super::DEFAULT_STALL_TIMEOUT_SECS);
+        let sync_stall_timeout = Some(sync_stall_timeout)
+            .filter(|secs| *secs > 0)
+            .map(std::time::Duration::from_secs);
+
+        let reject_non_monotonic_timestamps = self
+            .take(ConfigOption::RejectNonMonotonicTimestamps)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::RejectNonMonotonicTimestamps,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+
+        let timestamp_tolerance = self
+            .take(ConfigOption::TimestampTolerance)
+            .map(|s| {
+                s.parse::<u64>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::TimestampTolerance,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .map(std::time::Duration::from_secs)
+            .unwrap_or_default();
+
+        let state_update_parallelism = self
+            .take(ConfigOption::StateUpdateParallelism)
+            .map(|s| {
+                s.parse::<usize>()
+                    .map_err(|err| err.to_string())
+                    .and_then(|threads| match threads {
+                        0 => Err("must be at least 1".to_owned()),
+                        threads => Ok(threads),
+                    })
+                    .map_err(|err| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!(
+                                "Invalid {} value ({}): {}",
+                                ConfigOption::StateUpdateParallelism,
+                                s,
+                                err
+                            ),
+                        )
+                    })
+            })
+            .transpose()?
+            .unwrap_or(1);
+
+        let raw_block_window = self
+            .take(ConfigOption::RawBlockWindow)
+            .map(|s| {
+                s.parse::<u64>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::RawBlockWindow,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(super::DEFAULT_RAW_BLOCK_WINDOW);
+
+        let l1_confirmation_depth = self
+            .take(ConfigOption::L1ConfirmationDepth)
+            .map(|s| {
+                s.parse::<u64>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::L1ConfirmationDepth,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(super::DEFAULT_L1_CONFIRMATION_DEPTH);
+
+        // Zero disables polling.
+        let pending_poll_interval = self
+            .take(ConfigOption::PendingPollInterval)
+            .map(|s| {
+                s.parse::<u64>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::PendingPollInterval,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(super::DEFAULT_PENDING_POLL_INTERVAL_SECS);
+        let pending_poll_interval = Some(pending_poll_interval)
+            .filter(|secs| *secs > 0)
+            .map(std::time::Duration::from_secs);
+
+        let max_block_transactions = match self.take(ConfigOption::MaxBlockTransactions) {
+            Some(s) => match s.parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): must be positive",
+                            ConfigOption::MaxBlockTransactions,
+                            s
+                        ),
+                    ))
+                }
+                Err(err) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::MaxBlockTransactions,
+                            s,
+                            err
+                        ),
+                    ))
+                }
+            },
+            None => crate::storage::DEFAULT_MAX_BLOCK_TRANSACTIONS,
+        };
+
+        let event_query_cost_budget = match self.take(ConfigOption::EventQueryCostBudget) {
+            Some(s) => match s.parse::<u64>() {
+                Ok(budget) if budget > 0 => Some(budget),
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): must be positive",
+                            ConfigOption::EventQueryCostBudget,
+                            s
+                        ),
+                    ))
+                }
+                Err(err) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::EventQueryCostBudget,
+                            s,
+                            err
+                        ),
+                    ))
+                }
+            },
+            None => None,
+        };
+
+        let shadow_gateway_url = self
+            .take(ConfigOption::ShadowGatewayUrl)
+            .map(|s| {
+                s.parse::<Url>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::ShadowGatewayUrl,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?;
+
+        let serve_only = self
+            .take(ConfigOption::ServeOnly)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid {} value ({}): {}", ConfigOption::ServeOnly, s, err),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+
+        let forward_transactions = self
+            .take(ConfigOption::ForwardTransactions)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid {} value ({}): {}",
+                            ConfigOption::ForwardTransactions,
+                            s,
+                            err
+                        ),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(true);
+
+        let force = self
+            .take(ConfigOption::Force)
+            .map(|s| {
+                s.parse::<bool>().map_err(|err| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid {} value ({}): {}", ConfigOption::Force, s, err),
+                    )
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+
         Ok(Configuration {
             ethereum: EthereumConfig {
                 url: eth_url,
                 password: eth_password,
             },
             http_rpc_addr,
+            admin_rpc_addr,
             data_directory,
+            validate_constructor_calldata,
+            max_blob_size,
+            max_reorg_depth,
+            enable_sql_rpc,
+            class_backfill,
+            block_backfill,
+            statement_cache_capacity,
+            sync_stall_timeout,
+            reject_non_monotonic_timestamps,
+            timestamp_tolerance,
+            state_update_parallelism,
+            raw_block_window,
+            l1_confirmation_depth,
+            pending_poll_interval,
+            max_block_transactions,
+            shadow_gateway_url,
+            event_query_cost_budget,
+            serve_only,
+            forward_transactions,
+            force,
+            sources,
         })
     }
 
@@ -94,7 +596,7 @@ impl ConfigBuilder {
 
     /// Returns the [ConfigOption], leaving it set to [None].
     pub fn take(&mut self, option: ConfigOption) -> Option<String> {
-        self.0.remove(&option)
+        self.values.remove(&option)
     }
 }
 
@@ -195,6 +697,38 @@ mod tests {
 
             assert_eq!(merged, ConfigBuilder::default());
         }
+
+        #[test]
+        fn sources_follow_values() {
+            let cli = ConfigBuilder::default()
+                .with(ConfigOption::EthereumHttpUrl, Some("cli".to_owned()))
+                .with_source(ConfigSource::Environment)
+                .with(ConfigOption::HttpRpcAddress, Some("cli".to_owned()))
+                .with_option_source(ConfigOption::HttpRpcAddress, ConfigSource::CommandLine);
+            let file = ConfigBuilder::default()
+                .with(ConfigOption::EthereumHttpUrl, Some("file".to_owned()))
+                .with(ConfigOption::DataDirectory, Some("file".to_owned()))
+                .with_source(ConfigSource::File);
+
+            let merged = cli.merge(file);
+
+            assert_eq!(
+                merged.source(ConfigOption::EthereumHttpUrl),
+                ConfigSource::Environment
+            );
+            assert_eq!(
+                merged.source(ConfigOption::HttpRpcAddress),
+                ConfigSource::CommandLine
+            );
+            assert_eq!(
+                merged.source(ConfigOption::DataDirectory),
+                ConfigSource::File
+            );
+            assert_eq!(
+                merged.source(ConfigOption::EthereumPassword),
+                ConfigSource::Default
+            );
+        }
     }
 
     mod try_build {
@@ -207,6 +741,27 @@ mod tests {
         fn get_valid_value(option: ConfigOption) -> String {
             match option {
                 ConfigOption::EthereumHttpUrl => "http://localhost",
+                ConfigOption::ValidateConstructorCalldata => "true",
+                ConfigOption::MaxBlobSize => "16",
+                ConfigOption::MaxReorgDepth => "16",
+                ConfigOption::EnableSqlRpc => "true",
+                ConfigOption::ServeOnly => "true",
+                ConfigOption::ForwardTransactions => "false",
+                ConfigOption::Force => "true",
+                ConfigOption::ClassBackfill => "true",
+                ConfigOption::BlockBackfill => "true",
+                ConfigOption::StatementCacheCapacity => "128",
+                ConfigOption::SyncStallTimeout => "600",
+                ConfigOption::RejectNonMonotonicTimestamps => "true",
+                ConfigOption::TimestampTolerance => "60",
+                ConfigOption::StateUpdateParallelism => "8",
+                ConfigOption::RawBlockWindow => "10",
+                ConfigOption::L1ConfirmationDepth => "12",
+                ConfigOption::PendingPollInterval => "10",
+                ConfigOption::MaxBlockTransactions => "5000",
+                ConfigOption::AdminRpcAddress => "127.0.0.1:9546",
+                ConfigOption::ShadowGatewayUrl => "http://localhost",
+                ConfigOption::EventQueryCostBudget => "5000",
                 _ => "value",
             }
             .to_owned()
@@ -242,6 +797,21 @@ mod tests {
 
             use super::builder_with_all_required;
 
+            #[test]
+            fn sources() {
+                use crate::config::{ConfigOption, ConfigSource};
+                use enum_iterator::IntoEnumIterator;
+
+                let config = builder_with_all_required().try_build().unwrap();
+                for option in ConfigOption::into_enum_iter() {
+                    let expected = match option {
+                        ConfigOption::EthereumHttpUrl => ConfigSource::CommandLine,
+                        _ => ConfigSource::Default,
+                    };
+                    assert_eq!(config.sources[&option], expected, "{}", option);
+                }
+            }
+
             #[test]
             fn data_directory() {
                 let expected = std::env::current_dir().unwrap();
@@ -259,6 +829,365 @@ mod tests {
                 let config = builder_with_all_required().try_build().unwrap();
                 assert_eq!(config.http_rpc_addr, expected);
             }
+
+            #[test]
+            fn validate_constructor_calldata() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(!config.validate_constructor_calldata);
+            }
+
+            #[test]
+            fn max_blob_size() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.max_blob_size, crate::storage::DEFAULT_MAX_BLOB_SIZE);
+            }
+
+            #[test]
+            fn max_reorg_depth() {
+                use crate::config::DEFAULT_MAX_REORG_DEPTH;
+
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.max_reorg_depth, Some(DEFAULT_MAX_REORG_DEPTH));
+            }
+
+            #[test]
+            fn enable_sql_rpc() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(!config.enable_sql_rpc);
+            }
+
+            #[test]
+            fn serve_only() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(!config.serve_only);
+            }
+
+            #[test]
+            fn forward_transactions() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(config.forward_transactions);
+            }
+
+            #[test]
+            fn force() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(!config.force);
+            }
+
+            #[test]
+            fn class_backfill() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(!config.class_backfill);
+            }
+
+            #[test]
+            fn block_backfill() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(!config.block_backfill);
+            }
+
+            #[test]
+            fn statement_cache_capacity() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(
+                    config.statement_cache_capacity,
+                    crate::storage::DEFAULT_STATEMENT_CACHE_CAPACITY
+                );
+            }
+
+            #[test]
+            fn sync_stall_timeout() {
+                use crate::config::DEFAULT_STALL_TIMEOUT_SECS;
+
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(
+                    config.sync_stall_timeout,
+                    Some(std::time::Duration::from_secs(DEFAULT_STALL_TIMEOUT_SECS))
+                );
+            }
+
+            #[test]
+            fn reject_non_monotonic_timestamps() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert!(!config.reject_non_monotonic_timestamps);
+            }
+
+            #[test]
+            fn timestamp_tolerance() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.timestamp_tolerance, std::time::Duration::ZERO);
+            }
+
+            #[test]
+            fn state_update_parallelism() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.state_update_parallelism, 1);
+            }
+
+            #[test]
+            fn raw_block_window() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.raw_block_window, 1000);
+            }
+
+            #[test]
+            fn l1_confirmation_depth() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.l1_confirmation_depth, 6);
+            }
+
+            #[test]
+            fn pending_poll_interval() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(
+                    config.pending_poll_interval,
+                    Some(std::time::Duration::from_secs(5))
+                );
+            }
+
+            #[test]
+            fn max_block_transactions() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(
+                    config.max_block_transactions,
+                    crate::storage::DEFAULT_MAX_BLOCK_TRANSACTIONS
+                );
+            }
+
+            #[test]
+            fn event_query_cost_budget() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.event_query_cost_budget, None);
+            }
+
+            #[test]
+            fn admin_rpc_addr() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.admin_rpc_addr, None);
+            }
+
+            #[test]
+            fn shadow_gateway_url() {
+                let config = builder_with_all_required().try_build().unwrap();
+                assert_eq!(config.shadow_gateway_url, None);
+            }
+        }
+
+        #[test]
+        fn max_blob_size_is_in_mib() {
+            let config = builder_with_all_required()
+                .with(ConfigOption::MaxBlobSize, Some("16".to_owned()))
+                .try_build()
+                .unwrap();
+            assert_eq!(config.max_blob_size, 16 * 1024 * 1024);
+        }
+
+        #[test]
+        fn invalid_max_blob_size_should_error() {
+            for invalid in ["0", "-1", "lots"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::MaxBlobSize, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn zero_max_reorg_depth_disables_limit() {
+            let config = builder_with_all_required()
+                .with(ConfigOption::MaxReorgDepth, Some("0".to_owned()))
+                .try_build()
+                .unwrap();
+            assert_eq!(config.max_reorg_depth, None);
+        }
+
+        #[test]
+        fn invalid_max_reorg_depth_should_error() {
+            for invalid in ["-1", "deep"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::MaxReorgDepth, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn zero_sync_stall_timeout_disables_watchdog() {
+            let config = builder_with_all_required()
+                .with(ConfigOption::SyncStallTimeout, Some("0".to_owned()))
+                .try_build()
+                .unwrap();
+            assert_eq!(config.sync_stall_timeout, None);
+        }
+
+        #[test]
+        fn invalid_sync_stall_timeout_should_error() {
+            for invalid in ["-1", "1h"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::SyncStallTimeout, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_enable_sql_rpc_should_error() {
+            let builder = builder_with_all_required()
+                .with(ConfigOption::EnableSqlRpc, Some("yes".to_owned()));
+            assert!(builder.try_build().is_err());
+        }
+
+        #[test]
+        fn invalid_serve_only_should_error() {
+            let builder =
+                builder_with_all_required().with(ConfigOption::ServeOnly, Some("yes".to_owned()));
+            assert!(builder.try_build().is_err());
+        }
+
+        #[test]
+        fn invalid_force_should_error() {
+            let builder =
+                builder_with_all_required().with(ConfigOption::Force, Some("yes".to_owned()));
+            assert!(builder.try_build().is_err());
+        }
+
+        #[test]
+        fn invalid_forward_transactions_should_error() {
+            let builder = builder_with_all_required()
+                .with(ConfigOption::ForwardTransactions, Some("no".to_owned()));
+            assert!(builder.try_build().is_err());
+        }
+
+        #[test]
+        fn invalid_statement_cache_capacity_should_error() {
+            for invalid in ["-1", "many"] {
+                let builder = builder_with_all_required().with(
+                    ConfigOption::StatementCacheCapacity,
+                    Some(invalid.to_owned()),
+                );
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_reject_non_monotonic_timestamps_should_error() {
+            let builder = builder_with_all_required().with(
+                ConfigOption::RejectNonMonotonicTimestamps,
+                Some("strict".to_owned()),
+            );
+            assert!(builder.try_build().is_err());
+        }
+
+        #[test]
+        fn invalid_timestamp_tolerance_should_error() {
+            for invalid in ["-1", "1m"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::TimestampTolerance, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_state_update_parallelism_should_error() {
+            for invalid in ["0", "-1", "all"] {
+                let builder = builder_with_all_required().with(
+                    ConfigOption::StateUpdateParallelism,
+                    Some(invalid.to_owned()),
+                );
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_admin_rpc_addr_should_error() {
+            for invalid in ["0.0.0.0:9546", "192.168.0.1:9546", "localhost"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::AdminRpcAddress, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_shadow_gateway_url_should_error() {
+            for invalid in ["", "alpha4-2.starknet.io"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::ShadowGatewayUrl, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_raw_block_window_should_error() {
+            for invalid in ["-1", "all"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::RawBlockWindow, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_l1_confirmation_depth_should_error() {
+            for invalid in ["-1", "deep"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::L1ConfirmationDepth, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn zero_pending_poll_interval_disables_polling() {
+            let config = builder_with_all_required()
+                .with(ConfigOption::PendingPollInterval, Some("0".to_owned()))
+                .try_build()
+                .unwrap();
+            assert_eq!(config.pending_poll_interval, None);
+        }
+
+        #[test]
+        fn invalid_pending_poll_interval_should_error() {
+            for invalid in ["-1", "5s"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::PendingPollInterval, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_max_block_transactions_should_error() {
+            for invalid in ["0", "-1", "lots"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::MaxBlockTransactions, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_event_query_cost_budget_should_error() {
+            for invalid in ["0", "-1", "lots"] {
+                let builder = builder_with_all_required()
+                    .with(ConfigOption::EventQueryCostBudget, Some(invalid.to_owned()));
+                assert!(builder.try_build().is_err(), "{invalid} was accepted");
+            }
+        }
+
+        #[test]
+        fn invalid_block_backfill_should_error() {
+            let builder = builder_with_all_required()
+                .with(ConfigOption::BlockBackfill, Some("sometimes".to_owned()));
+            assert!(builder.try_build().is_err());
+        }
+
+        #[test]
+        fn invalid_class_backfill_should_error() {
+            let builder = builder_with_all_required()
+                .with(ConfigOption::ClassBackfill, Some("sometimes".to_owned()));
+            assert!(builder.try_build().is_err());
+        }
+
+        #[test]
+        fn invalid_validate_constructor_calldata_should_error() {
+            let builder = builder_with_all_required().with(
+                ConfigOption::ValidateConstructorCalldata,
+                Some("maybe".to_owned()),
+            );
+            assert!(builder.try_build().is_err());
         }
     }
 }
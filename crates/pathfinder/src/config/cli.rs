@@ -1,16 +1,77 @@
 //! Command-line argument parsing
 use clap::Arg;
+use enum_iterator::IntoEnumIterator;
 use std::ffi::OsString;
 
 use crate::config::builder::ConfigBuilder;
+use crate::core::StarknetBlockNumber;
 
-use super::ConfigOption;
+use super::{Command, ConfigOption, ConfigSource};
 
 const CONFIG_KEY: &str = "config";
 const DATA_DIR_KEY: &str = "data-directory";
 const ETH_URL_KEY: &str = "ethereum.url";
 const ETH_PASS_KEY: &str = "ethereum.password";
 const HTTP_RPC_ADDR_KEY: &str = "http-rpc";
+const ADMIN_RPC_ADDR_KEY: &str = "admin-rpc";
+const VALIDATE_CONSTRUCTOR_CALLDATA_KEY: &str = "sync.validate-constructor-calldata";
+const MAX_BLOB_SIZE_KEY: &str = "storage.max-blob-size";
+const MAX_REORG_DEPTH_KEY: &str = "sync.max-reorg-depth";
+const ENABLE_SQL_RPC_KEY: &str = "enable-sql-rpc";
+const CLASS_BACKFILL_KEY: &str = "sync.class-backfill";
+const BLOCK_BACKFILL_KEY: &str = "sync.block-backfill";
+const STATEMENT_CACHE_CAPACITY_KEY: &str = "storage.statement-cache-capacity";
+const SYNC_STALL_TIMEOUT_KEY: &str = "sync.stall-timeout";
+const REJECT_NON_MONOTONIC_TIMESTAMPS_KEY: &str = "sync.reject-non-monotonic-timestamps";
+const TIMESTAMP_TOLERANCE_KEY: &str = "sync.timestamp-tolerance";
+const STATE_UPDATE_PARALLELISM_KEY: &str = "sync.state-update-parallelism";
+const RAW_BLOCK_WINDOW_KEY: &str = "sync.raw-block-window";
+const L1_CONFIRMATION_DEPTH_KEY: &str = "sync.l1-confirmation-depth";
+const PENDING_POLL_INTERVAL_KEY: &str = "sync.pending-poll-interval";
+const MAX_BLOCK_TRANSACTIONS_KEY: &str = "sync.max-block-transactions";
+const SHADOW_GATEWAY_URL_KEY: &str = "sync.shadow-gateway-url";
+const EVENT_QUERY_COST_BUDGET_KEY: &str = "storage.event-query-cost-budget";
+const SERVE_ONLY_KEY: &str = "serve-only";
+const FORWARD_TRANSACTIONS_KEY: &str = "forward-transactions";
+const FORCE_KEY: &str = "force";
+
+const REPROCESS_BLOCKS_COMMAND: &str = "reprocess-blocks";
+const FROM_KEY: &str = "from";
+const TO_KEY: &str = "to";
+const GC_TREES_COMMAND: &str = "gc-trees";
+const KEEP_BLOCKS_KEY: &str = "keep-blocks";
+const REPACK_TRANSACTIONS_COMMAND: &str = "repack-transactions";
+
+/// The command-line argument name of `option`.
+pub(super) fn key(option: ConfigOption) -> &'static str {
+    match option {
+        ConfigOption::EthereumHttpUrl => ETH_URL_KEY,
+        ConfigOption::EthereumPassword => ETH_PASS_KEY,
+        ConfigOption::HttpRpcAddress => HTTP_RPC_ADDR_KEY,
+        ConfigOption::AdminRpcAddress => ADMIN_RPC_ADDR_KEY,
+        ConfigOption::DataDirectory => DATA_DIR_KEY,
+        ConfigOption::ValidateConstructorCalldata => VALIDATE_CONSTRUCTOR_CALLDATA_KEY,
+        ConfigOption::MaxBlobSize => MAX_BLOB_SIZE_KEY,
+        ConfigOption::MaxReorgDepth => MAX_REORG_DEPTH_KEY,
+        ConfigOption::EnableSqlRpc => ENABLE_SQL_RPC_KEY,
+        ConfigOption::ClassBackfill => CLASS_BACKFILL_KEY,
+        ConfigOption::BlockBackfill => BLOCK_BACKFILL_KEY,
+        ConfigOption::StatementCacheCapacity => STATEMENT_CACHE_CAPACITY_KEY,
+        ConfigOption::SyncStallTimeout => SYNC_STALL_TIMEOUT_KEY,
+        ConfigOption::RejectNonMonotonicTimestamps => REJECT_NON_MONOTONIC_TIMESTAMPS_KEY,
+        ConfigOption::TimestampTolerance => TIMESTAMP_TOLERANCE_KEY,
+        ConfigOption::StateUpdateParallelism => STATE_UPDATE_PARALLELISM_KEY,
+        ConfigOption::RawBlockWindow => RAW_BLOCK_WINDOW_KEY,
+        ConfigOption::L1ConfirmationDepth => L1_CONFIRMATION_DEPTH_KEY,
+        ConfigOption::PendingPollInterval => PENDING_POLL_INTERVAL_KEY,
+        ConfigOption::MaxBlockTransactions => MAX_BLOCK_TRANSACTIONS_KEY,
+        ConfigOption::ShadowGatewayUrl => SHADOW_GATEWAY_URL_KEY,
+        ConfigOption::EventQueryCostBudget => EVENT_QUERY_COST_BUDGET_KEY,
+        ConfigOption::ServeOnly => SERVE_ONLY_KEY,
+        ConfigOption::ForwardTransactions => FORWARD_TRANSACTIONS_KEY,
+        ConfigOption::Force => FORCE_KEY,
+    }
+}
 
 /// Parses the cmd line arguments and returns the optional
 /// configuration file's path and the specified configuration options.
@@ -40,16 +101,152 @@ where
     let ethereum_url = args.value_of(ETH_URL_KEY).map(|s| s.to_owned());
     let ethereum_password = args.value_of(ETH_PASS_KEY).map(|s| s.to_owned());
     let http_rpc_addr = args.value_of(HTTP_RPC_ADDR_KEY).map(|s| s.to_owned());
+    let admin_rpc_addr = args.value_of(ADMIN_RPC_ADDR_KEY).map(|s| s.to_owned());
+    let validate_constructor_calldata = args
+        .value_of(VALIDATE_CONSTRUCTOR_CALLDATA_KEY)
+        .map(|s| s.to_owned());
+    let max_blob_size = args.value_of(MAX_BLOB_SIZE_KEY).map(|s| s.to_owned());
+    let max_reorg_depth = args.value_of(MAX_REORG_DEPTH_KEY).map(|s| s.to_owned());
+    let enable_sql_rpc = args.value_of(ENABLE_SQL_RPC_KEY).map(|s| s.to_owned());
+    let class_backfill = args.value_of(CLASS_BACKFILL_KEY).map(|s| s.to_owned());
+    let block_backfill = args.value_of(BLOCK_BACKFILL_KEY).map(|s| s.to_owned());
+    let statement_cache_capacity = args
+        .value_of(STATEMENT_CACHE_CAPACITY_KEY)
+        .map(|s| s.to_owned());
+    let sync_stall_timeout = args.value_of(SYNC_STALL_TIMEOUT_KEY).map(|s| s.to_owned());
+    let reject_non_monotonic_timestamps = args
+        .value_of(REJECT_NON_MONOTONIC_TIMESTAMPS_KEY)
+        .map(|s| s.to_owned());
+    let timestamp_tolerance = args.value_of(TIMESTAMP_TOLERANCE_KEY).map(|s| s.to_owned());
+    let state_update_parallelism = args
+        .value_of(STATE_UPDATE_PARALLELISM_KEY)
+        .map(|s| s.to_owned());
+    let raw_block_window = args.value_of(RAW_BLOCK_WINDOW_KEY).map(|s| s.to_owned());
+    let l1_confirmation_depth = args
+        .value_of(L1_CONFIRMATION_DEPTH_KEY)
+        .map(|s| s.to_owned());
+    let pending_poll_interval = args
+        .value_of(PENDING_POLL_INTERVAL_KEY)
+        .map(|s| s.to_owned());
+    let max_block_transactions = args
+        .value_of(MAX_BLOCK_TRANSACTIONS_KEY)
+        .map(|s| s.to_owned());
+    let shadow_gateway_url = args.value_of(SHADOW_GATEWAY_URL_KEY).map(|s| s.to_owned());
+    let event_query_cost_budget = args
+        .value_of(EVENT_QUERY_COST_BUDGET_KEY)
+        .map(|s| s.to_owned());
+    let serve_only = args.value_of(SERVE_ONLY_KEY).map(|s| s.to_owned());
+    let forward_transactions = args
+        .value_of(FORWARD_TRANSACTIONS_KEY)
+        .map(|s| s.to_owned());
+    let force = args.value_of(FORCE_KEY).map(|s| s.to_owned());
 
     let cfg = ConfigBuilder::default()
         .with(ConfigOption::EthereumHttpUrl, ethereum_url)
         .with(ConfigOption::EthereumPassword, ethereum_password)
         .with(ConfigOption::HttpRpcAddress, http_rpc_addr)
-        .with(ConfigOption::DataDirectory, data_directory);
+        .with(ConfigOption::AdminRpcAddress, admin_rpc_addr)
+        .with(ConfigOption::DataDirectory, data_directory)
+        .with(
+            ConfigOption::ValidateConstructorCalldata,
+            validate_constructor_calldata,
+        )
+        .with(ConfigOption::MaxBlobSize, max_blob_size)
+        .with(ConfigOption::MaxReorgDepth, max_reorg_depth)
+        .with(ConfigOption::EnableSqlRpc, enable_sql_rpc)
+        .with(ConfigOption::ClassBackfill, class_backfill)
+        .with(ConfigOption::BlockBackfill, block_backfill)
+        .with(
+            ConfigOption::StatementCacheCapacity,
+            statement_cache_capacity,
+        )
+        .with(ConfigOption::SyncStallTimeout, sync_stall_timeout)
+        .with(
+            ConfigOption::RejectNonMonotonicTimestamps,
+            reject_non_monotonic_timestamps,
+        )
+        .with(ConfigOption::TimestampTolerance, timestamp_tolerance)
+        .with(
+            ConfigOption::StateUpdateParallelism,
+            state_update_parallelism,
+        )
+        .with(ConfigOption::RawBlockWindow, raw_block_window)
+        .with(ConfigOption::L1ConfirmationDepth, l1_confirmation_depth)
+        .with(ConfigOption::PendingPollInterval, pending_poll_interval)
+        .with(ConfigOption::MaxBlockTransactions, max_block_transactions)
+        .with(ConfigOption::ShadowGatewayUrl, shadow_gateway_url)
+        .with(ConfigOption::EventQueryCostBudget, event_query_cost_budget)
+        .with(ConfigOption::ServeOnly, serve_only)
+        .with(ConfigOption::ForwardTransactions, forward_transactions)
+        .with(ConfigOption::Force, force);
+
+    // Values which were not passed as arguments were read from the environment.
+    let cfg = ConfigOption::into_enum_iter().fold(
+        cfg.with_source(ConfigSource::Environment),
+        |cfg, option| match args.occurrences_of(key(option)) {
+            0 => cfg,
+            _ => cfg.with_option_source(option, ConfigSource::CommandLine),
+        },
+    );
 
     Ok((config_filepath, cfg))
 }
 
+/// Parses the cmd line arguments and returns the [Command] to run instead of the node, if any.
+///
+/// Note: This will terminate the program if invalid arguments are supplied.
+///       This is intended, as [clap] will show the program usage / help.
+pub fn parse_cmd_line_command() -> Option<Command> {
+    match parse_command(&mut std::env::args_os()) {
+        Ok(command) => command,
+        Err(err) => err.exit(),
+    }
+}
+
+/// A wrapper around [clap::Command]'s `get_matches_from_safe()` which returns the
+/// [Command], if one was given.
+fn parse_command<I, T>(args: I) -> clap::Result<Option<Command>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let args = clap_app().try_get_matches_from(args)?;
+
+    match args.subcommand() {
+        Some((REPROCESS_BLOCKS_COMMAND, args)) => {
+            let from = args.value_of_t::<u64>(FROM_KEY)?;
+            let to = args.value_of_t::<u64>(TO_KEY)?;
+            if from > to {
+                return Err(clap::Error::raw(
+                    clap::ErrorKind::ValueValidation,
+                    format!(
+                        "--{} ({}) must not exceed --{} ({})",
+                        FROM_KEY, from, TO_KEY, to
+                    ),
+                ));
+            }
+
+            Ok(Some(Command::ReprocessBlocks {
+                from: StarknetBlockNumber(from),
+                to: StarknetBlockNumber(to),
+            }))
+        }
+        Some((GC_TREES_COMMAND, args)) => {
+            let keep_blocks = args.value_of_t::<u64>(KEEP_BLOCKS_KEY)?;
+            if keep_blocks == 0 {
+                return Err(clap::Error::raw(
+                    clap::ErrorKind::ValueValidation,
+                    format!("--{} must be at least 1", KEEP_BLOCKS_KEY),
+                ));
+            }
+
+            Ok(Some(Command::GcTrees { keep_blocks }))
+        }
+        Some((REPACK_TRANSACTIONS_COMMAND, _)) => Ok(Some(Command::RepackTransactions)),
+        _ => Ok(None),
+    }
+}
+
 /// Defines our command-line interface using [clap::Command].
 ///
 /// Sets the argument names, help strings etc.
@@ -99,6 +296,15 @@ Examples:
                 .value_name("IP:PORT")
                 .env("PATHFINDER_HTTP_RPC_ADDRESS")
         )
+        .arg(
+            Arg::new(ADMIN_RPC_ADDR_KEY)
+                .long(ADMIN_RPC_ADDR_KEY)
+                .help("Admin RPC listening address, which must be a loopback address")
+                .long_help("Serves the pathfinder_admin_* methods, which change the log filter and other settings of the running node, on this address. It must be a loopback address, as the methods are not authenticated. Not served by default.")
+                .takes_value(true)
+                .value_name("IP:PORT")
+                .env("PATHFINDER_ADMIN_RPC_ADDRESS")
+        )
         .arg(
             Arg::new(DATA_DIR_KEY)
                 .long(DATA_DIR_KEY)
@@ -107,6 +313,245 @@ Examples:
                 .value_name("PATH")
                 .env("PATHFINDER_DATA_DIRECTORY")
         )
+        .arg(
+            Arg::new(VALIDATE_CONSTRUCTOR_CALLDATA_KEY)
+                .long(VALIDATE_CONSTRUCTOR_CALLDATA_KEY)
+                .help("Validate the constructor calldata of deploy transactions during sync")
+                .takes_value(true)
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_SYNC_VALIDATE_CONSTRUCTOR_CALLDATA")
+                .long_help("Checks the constructor calldata length of deploy transactions against the constructor ABI of the deployed class, and logs any discrepancies. Defaults to false.")
+        )
+        .arg(
+            Arg::new(MAX_BLOB_SIZE_KEY)
+                .long(MAX_BLOB_SIZE_KEY)
+                .help("Maximum decompressed size of a stored transaction, receipt or state update")
+                .takes_value(true)
+                .value_name("MiB")
+                .env("PATHFINDER_STORAGE_MAX_BLOB_SIZE")
+                .long_help("Reading a stored transaction, receipt or state update which decompresses to more than this many MiB fails instead of exhausting the node's memory. Defaults to 8.")
+        )
+        .arg(
+            Arg::new(STATEMENT_CACHE_CAPACITY_KEY)
+                .long(STATEMENT_CACHE_CAPACITY_KEY)
+                .help("Number of prepared statements cached per database connection")
+                .takes_value(true)
+                .value_name("STATEMENTS")
+                .env("PATHFINDER_STORAGE_STATEMENT_CACHE_CAPACITY")
+                .long_help("Each database connection keeps up to this many prepared statements, so that frequently used queries are not re-prepared. Defaults to 64.")
+        )
+        .arg(
+            Arg::new(MAX_REORG_DEPTH_KEY)
+                .long(MAX_REORG_DEPTH_KEY)
+                .help("Maximum number of L2 blocks a reorg may invalidate without operator approval")
+                .takes_value(true)
+                .value_name("BLOCKS")
+                .env("PATHFINDER_SYNC_MAX_REORG_DEPTH")
+                .long_help("Sync halts on L2 reorgs which invalidate more blocks than this, until they are approved using the pathfinder_admin_approveReorg method of the admin RPC server or the node is restarted with a larger limit. Zero disables the limit. Defaults to 64.")
+        )
+        .arg(
+            Arg::new(SYNC_STALL_TIMEOUT_KEY)
+                .long(SYNC_STALL_TIMEOUT_KEY)
+                .help("Seconds without progress after which a sync process is restarted")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .env("PATHFINDER_SYNC_STALL_TIMEOUT")
+                .long_help("The L1 and L2 sync processes are cancelled and restarted if they make no progress for this long, with a growing delay between consecutive restarts. The node is marked unhealthy if a process remains stalled after several restarts. Must exceed the head poll interval of the network, which is 15 minutes on mainnet. Zero disables restarts. Defaults to 3600.")
+        )
+        .arg(
+            Arg::new(ENABLE_SQL_RPC_KEY)
+                .long(ENABLE_SQL_RPC_KEY)
+                .help("Serve read-only SQL queries via the pathfinder_sql RPC method")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("true")
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_ENABLE_SQL_RPC")
+                .long_help("Serves the pathfinder_sql RPC method, which runs a single read-only SELECT statement against the node's database. Only available if the HTTP-RPC server listens on a loopback address. Defaults to false.")
+        )
+        .arg(
+            Arg::new(SERVE_ONLY_KEY)
+                .long(SERVE_ONLY_KEY)
+                .help("Serve RPC from a read-only database without syncing it")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("true")
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_SERVE_ONLY")
+                .long_help("Opens an existing database read-only and serves RPC from it, without running the sync and Ethereum tasks. Intended for replicas of a database which a single other node syncs, e.g. on a shared volume. The database must have been migrated to this version by the syncing node. Defaults to false.")
+        )
+        .arg(
+            Arg::new(FORWARD_TRANSACTIONS_KEY)
+                .long(FORWARD_TRANSACTIONS_KEY)
+                .help("Forward transactions to the gateway via the starknet_add*Transaction methods")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("true")
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_FORWARD_TRANSACTIONS")
+                .long_help("Serves the starknet_addInvokeTransaction, starknet_addDeclareTransaction and starknet_addDeployTransaction methods, which forward transactions to the gateway. Defaults to true.")
+        )
+        .arg(
+            Arg::new(FORCE_KEY)
+                .long(FORCE_KEY)
+                .help("Use the database even if it belongs to another chain")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("true")
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_FORCE")
+                .long_help("At startup, the node refuses to use a database which was synced from another chain than the configured one. This skips that check, e.g. to inspect such a database. It does not affect the check of the database schema revision. Defaults to false.")
+        )
+        .arg(
+            Arg::new(CLASS_BACKFILL_KEY)
+                .long(CLASS_BACKFILL_KEY)
+                .help("Download the definitions of referenced classes which are missing from storage")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("true")
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_SYNC_CLASS_BACKFILL")
+                .long_help("Runs a background task which downloads the definitions of classes referenced by contracts but missing from storage, verifies their class hashes and stores them. Definitions which fail verification are recorded and not retried. The task pauses while sync is catching up. Defaults to false.")
+        )
+        .arg(
+            Arg::new(BLOCK_BACKFILL_KEY)
+                .long(BLOCK_BACKFILL_KEY)
+                .help("Store recent blocks backwards from the chain head ahead of the forward sync")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("true")
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_SYNC_BLOCK_BACKFILL")
+                .long_help("Runs a background task which stores blocks backwards from the chain head, along with their transactions, receipts and events, so that these can be served long before the forward sync reaches them. It also follows new blocks at the head. State trees can only be built forwards from genesis, so methods which read the state of such blocks fail with NODE_NOT_READY until the forward sync has applied them. Defaults to false.")
+        )
+        .arg(
+            Arg::new(REJECT_NON_MONOTONIC_TIMESTAMPS_KEY)
+                .long(REJECT_NON_MONOTONIC_TIMESTAMPS_KEY)
+                .help("Halt sync on blocks whose timestamp precedes their parent's")
+                .takes_value(true)
+                .min_values(0)
+                .default_missing_value("true")
+                .possible_values(["true", "false"])
+                .value_name("BOOL")
+                .env("PATHFINDER_SYNC_REJECT_NON_MONOTONIC_TIMESTAMPS")
+                .long_help("Blocks whose timestamp precedes their parent's timestamp by more than the timestamp tolerance are always logged. If enabled, sync also halts on such blocks instead of storing them. Defaults to false.")
+        )
+        .arg(
+            Arg::new(TIMESTAMP_TOLERANCE_KEY)
+                .long(TIMESTAMP_TOLERANCE_KEY)
+                .help("Seconds by which a block's timestamp may precede its parent's")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .env("PATHFINDER_SYNC_TIMESTAMP_TOLERANCE")
+                .long_help("Blocks whose timestamp precedes their parent's timestamp by at most this many seconds are stored without a warning, and are not rejected by --sync.reject-non-monotonic-timestamps. Defaults to 0.")
+        )
+        .arg(
+            Arg::new(STATE_UPDATE_PARALLELISM_KEY)
+                .long(STATE_UPDATE_PARALLELISM_KEY)
+                .help("Number of threads which update contract storage trees concurrently")
+                .takes_value(true)
+                .value_name("THREADS")
+                .env("PATHFINDER_SYNC_STATE_UPDATE_PARALLELISM")
+                .long_help("The storage trees of the contracts updated by a block are hashed on this many threads. Database access remains serial, so this mostly speeds up blocks which touch many contracts. Defaults to 1, which updates them one after the other.")
+        )
+        .arg(
+            Arg::new(RAW_BLOCK_WINDOW_KEY)
+                .long(RAW_BLOCK_WINDOW_KEY)
+                .help("Number of most recent blocks whose gateway JSON is retained")
+                .takes_value(true)
+                .value_name("BLOCKS")
+                .env("PATHFINDER_SYNC_RAW_BLOCK_WINDOW")
+                .long_help("The verbatim gateway JSON of this many of the most recent blocks is stored alongside the parsed data, so that the blocks can be re-parsed with `reprocess-blocks` after a parsing bug is fixed. Defaults to 1000, 0 disables retention.")
+        )
+        .arg(
+            Arg::new(L1_CONFIRMATION_DEPTH_KEY)
+                .long(L1_CONFIRMATION_DEPTH_KEY)
+                .help("Number of Ethereum blocks which must confirm a StarkNet state update")
+                .takes_value(true)
+                .value_name("BLOCKS")
+                .env("PATHFINDER_SYNC_L1_CONFIRMATION_DEPTH")
+                .long_help("StarkNet state updates found on Ethereum are only stored once their Ethereum block is at least this many blocks below the Ethereum head. Ethereum reorgs of more recent blocks are then handled without touching the database. Defaults to 6, 0 follows the Ethereum head.")
+        )
+        .arg(
+            Arg::new(PENDING_POLL_INTERVAL_KEY)
+                .long(PENDING_POLL_INTERVAL_KEY)
+                .help("Seconds between polls of the pending block")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .env("PATHFINDER_SYNC_PENDING_POLL_INTERVAL")
+                .long_help("The gateway's pending block is polled this often and served by the RPC methods which read pending data, as long as it builds on the latest stored block. Otherwise these methods query the gateway themselves. Defaults to 5, 0 disables polling.")
+        )
+        .arg(
+            Arg::new(MAX_BLOCK_TRANSACTIONS_KEY)
+                .long(MAX_BLOCK_TRANSACTIONS_KEY)
+                .help("Maximum number of transactions in a single block")
+                .takes_value(true)
+                .value_name("COUNT")
+                .env("PATHFINDER_SYNC_MAX_BLOCK_TRANSACTIONS")
+                .long_help("Blocks with more transactions than this are rejected instead of being stored, which guards against absurd block data exhausting memory. Defaults to 100000, far above any block seen so far.")
+        )
+        .arg(
+            Arg::new(SHADOW_GATEWAY_URL_KEY)
+                .long(SHADOW_GATEWAY_URL_KEY)
+                .help("Gateway to shadow sync for comparison with the primary gateway")
+                .takes_value(true)
+                .value_name("HTTP(s) URL")
+                .env("PATHFINDER_SYNC_SHADOW_GATEWAY_URL")
+                .long_help("Syncs the chain a second time from this gateway, into a separate database next to the primary one, so that the two can be compared with pathfinder_compareShadow. The shadow sync is throttled and runs on a single thread, so that it never competes with the primary sync. Disabled by default.")
+        )
+        .arg(
+            Arg::new(EVENT_QUERY_COST_BUDGET_KEY)
+                .long(EVENT_QUERY_COST_BUDGET_KEY)
+                .help("Maximum estimated number of events scanned by a starknet_getEvents query")
+                .takes_value(true)
+                .value_name("EVENTS")
+                .env("PATHFINDER_STORAGE_EVENT_QUERY_COST_BUDGET")
+                .long_help("Event queries are estimated to scan the events in their block range which match their filters, up to the requested page. Queries estimated to scan more than this many events are rejected with an error suggesting a narrower filter, instead of running for minutes. By default no queries are rejected, and queries estimated to scan more than 1000000 events are logged.")
+        )
+        .subcommand(
+            clap::Command::new(REPROCESS_BLOCKS_COMMAND)
+                .about("Re-parses the retained gateway JSON of blocks and repairs their stored data, instead of running the node")
+                .arg(
+                    Arg::new(FROM_KEY)
+                        .long(FROM_KEY)
+                        .help("First block to reprocess")
+                        .takes_value(true)
+                        .value_name("BLOCK")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new(TO_KEY)
+                        .long(TO_KEY)
+                        .help("Last block to reprocess")
+                        .takes_value(true)
+                        .value_name("BLOCK")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(GC_TREES_COMMAND)
+                .about("Deletes the state tree nodes which are only referenced by old blocks, instead of running the node")
+                .arg(
+                    Arg::new(KEEP_BLOCKS_KEY)
+                        .long(KEEP_BLOCKS_KEY)
+                        .help("Number of most recent blocks whose state is kept")
+                        .long_help("Number of most recent blocks whose state is kept. The state of older blocks can no longer be queried, and the chain can no longer be reorged to below them, so this should exceed the deepest expected reorg.")
+                        .takes_value(true)
+                        .value_name("N")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(REPACK_TRANSACTIONS_COMMAND)
+                .about("Rewrites the transactions and receipts stored by older versions in the compact packed format, instead of running the node"),
+        )
 }
 
 #[cfg(test)]
@@ -125,7 +570,28 @@ mod tests {
         env::remove_var("PATHFINDER_ETHEREUM_API_PASSWORD");
         env::remove_var("PATHFINDER_ETHEREUM_API_URL");
         env::remove_var("PATHFINDER_HTTP_RPC_ADDRESS");
+        env::remove_var("PATHFINDER_ADMIN_RPC_ADDRESS");
         env::remove_var("PATHFINDER_DATA_DIRECTORY");
+        env::remove_var("PATHFINDER_SYNC_VALIDATE_CONSTRUCTOR_CALLDATA");
+        env::remove_var("PATHFINDER_STORAGE_MAX_BLOB_SIZE");
+        env::remove_var("PATHFINDER_SYNC_MAX_REORG_DEPTH");
+        env::remove_var("PATHFINDER_ENABLE_SQL_RPC");
+        env::remove_var("PATHFINDER_SYNC_CLASS_BACKFILL");
+        env::remove_var("PATHFINDER_SYNC_BLOCK_BACKFILL");
+        env::remove_var("PATHFINDER_STORAGE_STATEMENT_CACHE_CAPACITY");
+        env::remove_var("PATHFINDER_SYNC_STALL_TIMEOUT");
+        env::remove_var("PATHFINDER_SYNC_REJECT_NON_MONOTONIC_TIMESTAMPS");
+        env::remove_var("PATHFINDER_SYNC_TIMESTAMP_TOLERANCE");
+        env::remove_var("PATHFINDER_SYNC_STATE_UPDATE_PARALLELISM");
+        env::remove_var("PATHFINDER_SYNC_RAW_BLOCK_WINDOW");
+        env::remove_var("PATHFINDER_SYNC_L1_CONFIRMATION_DEPTH");
+        env::remove_var("PATHFINDER_SYNC_PENDING_POLL_INTERVAL");
+        env::remove_var("PATHFINDER_SYNC_MAX_BLOCK_TRANSACTIONS");
+        env::remove_var("PATHFINDER_SYNC_SHADOW_GATEWAY_URL");
+        env::remove_var("PATHFINDER_STORAGE_EVENT_QUERY_COST_BUDGET");
+        env::remove_var("PATHFINDER_SERVE_ONLY");
+        env::remove_var("PATHFINDER_FORWARD_TRANSACTIONS");
+        env::remove_var("PATHFINDER_FORCE");
     }
 
     #[test]
@@ -232,6 +698,579 @@ mod tests {
         assert_eq!(cfg.take(ConfigOption::DataDirectory), Some(value));
     }
 
+    #[test]
+    fn validate_constructor_calldata_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "true".to_owned();
+        let (_, mut cfg) = parse_args(vec![
+            "bin name",
+            "--sync.validate-constructor-calldata",
+            &value,
+        ])
+        .unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ValidateConstructorCalldata),
+            Some(value)
+        );
+    }
+
+    #[test]
+    fn validate_constructor_calldata_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "false".to_owned();
+        env::set_var("PATHFINDER_SYNC_VALIDATE_CONSTRUCTOR_CALLDATA", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ValidateConstructorCalldata),
+            Some(value)
+        );
+    }
+
+    #[test]
+    fn max_blob_size_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "16".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--storage.max-blob-size", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::MaxBlobSize), Some(value));
+    }
+
+    #[test]
+    fn max_blob_size_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "16".to_owned();
+        env::set_var("PATHFINDER_STORAGE_MAX_BLOB_SIZE", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::MaxBlobSize), Some(value));
+    }
+
+    #[test]
+    fn statement_cache_capacity_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "128".to_owned();
+        let (_, mut cfg) = parse_args(vec![
+            "bin name",
+            "--storage.statement-cache-capacity",
+            &value,
+        ])
+        .unwrap();
+        assert_eq!(cfg.take(ConfigOption::StatementCacheCapacity), Some(value));
+    }
+
+    #[test]
+    fn statement_cache_capacity_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "128".to_owned();
+        env::set_var("PATHFINDER_STORAGE_STATEMENT_CACHE_CAPACITY", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::StatementCacheCapacity), Some(value));
+    }
+
+    #[test]
+    fn sync_stall_timeout_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "600".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--sync.stall-timeout", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::SyncStallTimeout), Some(value));
+    }
+
+    #[test]
+    fn sync_stall_timeout_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "600".to_owned();
+        env::set_var("PATHFINDER_SYNC_STALL_TIMEOUT", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::SyncStallTimeout), Some(value));
+    }
+
+    #[test]
+    fn max_reorg_depth_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "128".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--sync.max-reorg-depth", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::MaxReorgDepth), Some(value));
+    }
+
+    #[test]
+    fn max_reorg_depth_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "128".to_owned();
+        env::set_var("PATHFINDER_SYNC_MAX_REORG_DEPTH", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::MaxReorgDepth), Some(value));
+    }
+
+    #[test]
+    fn enable_sql_rpc_flag() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--enable-sql-rpc"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::EnableSqlRpc),
+            Some("true".to_owned())
+        );
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--enable-sql-rpc", "false"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::EnableSqlRpc),
+            Some("false".to_owned())
+        );
+
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::EnableSqlRpc), None);
+    }
+
+    #[test]
+    fn enable_sql_rpc_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "true".to_owned();
+        env::set_var("PATHFINDER_ENABLE_SQL_RPC", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::EnableSqlRpc), Some(value));
+    }
+
+    #[test]
+    fn serve_only_flag() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--serve-only"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ServeOnly), Some("true".to_owned()));
+
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ServeOnly), None);
+    }
+
+    #[test]
+    fn serve_only_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "true".to_owned();
+        env::set_var("PATHFINDER_SERVE_ONLY", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ServeOnly), Some(value));
+    }
+
+    #[test]
+    fn forward_transactions_flag() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--forward-transactions", "false"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ForwardTransactions),
+            Some("false".to_owned())
+        );
+    }
+
+    #[test]
+    fn forward_transactions_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "false".to_owned();
+        env::set_var("PATHFINDER_FORWARD_TRANSACTIONS", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ForwardTransactions), Some(value));
+    }
+
+    #[test]
+    fn force_flag() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--force"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::Force), Some("true".to_owned()));
+
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::Force), None);
+    }
+
+    #[test]
+    fn class_backfill_flag() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--sync.class-backfill"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ClassBackfill),
+            Some("true".to_owned())
+        );
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--sync.class-backfill", "false"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ClassBackfill),
+            Some("false".to_owned())
+        );
+    }
+
+    #[test]
+    fn block_backfill_flag() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let (_, mut cfg) = parse_args(vec!["bin name", "--sync.block-backfill"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::BlockBackfill),
+            Some("true".to_owned())
+        );
+
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::BlockBackfill), None);
+    }
+
+    #[test]
+    fn class_backfill_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "true".to_owned();
+        env::set_var("PATHFINDER_SYNC_CLASS_BACKFILL", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ClassBackfill), Some(value));
+    }
+
+    #[test]
+    fn reject_non_monotonic_timestamps_flag() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--sync.reject-non-monotonic-timestamps"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::RejectNonMonotonicTimestamps),
+            Some("true".to_owned())
+        );
+    }
+
+    #[test]
+    fn reject_non_monotonic_timestamps_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "true".to_owned();
+        env::set_var("PATHFINDER_SYNC_REJECT_NON_MONOTONIC_TIMESTAMPS", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::RejectNonMonotonicTimestamps),
+            Some(value)
+        );
+    }
+
+    #[test]
+    fn timestamp_tolerance_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "60".to_owned();
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--sync.timestamp-tolerance", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::TimestampTolerance), Some(value));
+    }
+
+    #[test]
+    fn timestamp_tolerance_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "60".to_owned();
+        env::set_var("PATHFINDER_SYNC_TIMESTAMP_TOLERANCE", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::TimestampTolerance), Some(value));
+    }
+
+    #[test]
+    fn state_update_parallelism_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "8".to_owned();
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--sync.state-update-parallelism", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::StateUpdateParallelism), Some(value));
+    }
+
+    #[test]
+    fn state_update_parallelism_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "8".to_owned();
+        env::set_var("PATHFINDER_SYNC_STATE_UPDATE_PARALLELISM", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::StateUpdateParallelism), Some(value));
+    }
+
+    #[test]
+    fn admin_rpc_address_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "127.0.0.1:9546".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--admin-rpc", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::AdminRpcAddress), Some(value));
+    }
+
+    #[test]
+    fn admin_rpc_address_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "127.0.0.1:9546".to_owned();
+        env::set_var("PATHFINDER_ADMIN_RPC_ADDRESS", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::AdminRpcAddress), Some(value));
+    }
+
+    #[test]
+    fn raw_block_window_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "10".to_owned();
+        let (_, mut cfg) = parse_args(vec!["bin name", "--sync.raw-block-window", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RawBlockWindow), Some(value));
+    }
+
+    #[test]
+    fn raw_block_window_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "10".to_owned();
+        env::set_var("PATHFINDER_SYNC_RAW_BLOCK_WINDOW", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::RawBlockWindow), Some(value));
+    }
+
+    #[test]
+    fn l1_confirmation_depth_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "12".to_owned();
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--sync.l1-confirmation-depth", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::L1ConfirmationDepth), Some(value));
+    }
+
+    #[test]
+    fn l1_confirmation_depth_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "12".to_owned();
+        env::set_var("PATHFINDER_SYNC_L1_CONFIRMATION_DEPTH", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::L1ConfirmationDepth), Some(value));
+    }
+
+    #[test]
+    fn pending_poll_interval_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "10".to_owned();
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--sync.pending-poll-interval", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::PendingPollInterval), Some(value));
+    }
+
+    #[test]
+    fn pending_poll_interval_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "10".to_owned();
+        env::set_var("PATHFINDER_SYNC_PENDING_POLL_INTERVAL", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::PendingPollInterval), Some(value));
+    }
+
+    #[test]
+    fn max_block_transactions_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "5000".to_owned();
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--sync.max-block-transactions", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::MaxBlockTransactions), Some(value));
+    }
+
+    #[test]
+    fn max_block_transactions_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "5000".to_owned();
+        env::set_var("PATHFINDER_SYNC_MAX_BLOCK_TRANSACTIONS", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::MaxBlockTransactions), Some(value));
+    }
+
+    #[test]
+    fn shadow_gateway_url_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "https://alpha4-2.starknet.io/".to_owned();
+        let (_, mut cfg) =
+            parse_args(vec!["bin name", "--sync.shadow-gateway-url", &value]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ShadowGatewayUrl), Some(value));
+    }
+
+    #[test]
+    fn shadow_gateway_url_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "https://alpha4-2.starknet.io/".to_owned();
+        env::set_var("PATHFINDER_SYNC_SHADOW_GATEWAY_URL", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ShadowGatewayUrl), Some(value));
+    }
+
+    #[test]
+    fn event_query_cost_budget_long() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "5000".to_owned();
+        let (_, mut cfg) = parse_args(vec![
+            "bin name",
+            "--storage.event-query-cost-budget",
+            &value,
+        ])
+        .unwrap();
+        assert_eq!(cfg.take(ConfigOption::EventQueryCostBudget), Some(value));
+    }
+
+    #[test]
+    fn event_query_cost_budget_environment_variable() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        let value = "5000".to_owned();
+        env::set_var("PATHFINDER_STORAGE_EVENT_QUERY_COST_BUDGET", &value);
+        let (_, mut cfg) = parse_args(vec!["bin name"]).unwrap();
+        assert_eq!(cfg.take(ConfigOption::EventQueryCostBudget), Some(value));
+    }
+
+    #[test]
+    fn reprocess_blocks_command() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        assert_eq!(parse_command(vec!["bin name"]).unwrap(), None);
+
+        let args = vec![
+            "bin name",
+            "--ethereum.url",
+            "http://localhost",
+            "reprocess-blocks",
+            "--from",
+            "10",
+            "--to",
+            "20",
+        ];
+        assert_eq!(
+            parse_command(args.clone()).unwrap(),
+            Some(Command::ReprocessBlocks {
+                from: StarknetBlockNumber(10),
+                to: StarknetBlockNumber(20),
+            })
+        );
+        // The node's options still apply.
+        let (_, mut cfg) = parse_args(args).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::EthereumHttpUrl),
+            Some("http://localhost".to_owned())
+        );
+
+        parse_command(vec![
+            "bin name",
+            "reprocess-blocks",
+            "--from",
+            "20",
+            "--to",
+            "10",
+        ])
+        .unwrap_err();
+        parse_command(vec!["bin name", "reprocess-blocks", "--from", "10"]).unwrap_err();
+    }
+
+    #[test]
+    fn gc_trees_command() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        assert_eq!(
+            parse_command(vec!["bin name", "gc-trees", "--keep-blocks", "100"]).unwrap(),
+            Some(Command::GcTrees { keep_blocks: 100 })
+        );
+
+        parse_command(vec!["bin name", "gc-trees", "--keep-blocks", "0"]).unwrap_err();
+        parse_command(vec!["bin name", "gc-trees"]).unwrap_err();
+    }
+
+    #[test]
+    fn repack_transactions_command() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        assert_eq!(
+            parse_command(vec!["bin name", "repack-transactions"]).unwrap(),
+            Some(Command::RepackTransactions)
+        );
+        assert_eq!(parse_command(vec!["bin name"]).unwrap(), None);
+    }
+
+    #[test]
+    fn sources() {
+        let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+        clear_environment();
+
+        env::set_var("PATHFINDER_ETHEREUM_API_URL", "value");
+        env::set_var("PATHFINDER_HTTP_RPC_ADDRESS", "value");
+        let (_, cfg) = parse_args(vec!["bin name", "--http-rpc", "value"]).unwrap();
+        assert_eq!(
+            cfg.source(ConfigOption::EthereumHttpUrl),
+            ConfigSource::Environment
+        );
+        assert_eq!(
+            cfg.source(ConfigOption::HttpRpcAddress),
+            ConfigSource::CommandLine
+        );
+        assert_eq!(
+            cfg.source(ConfigOption::DataDirectory),
+            ConfigSource::Default
+        );
+    }
+
     #[test]
     fn empty_config() {
         let _env_guard = ENV_VAR_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
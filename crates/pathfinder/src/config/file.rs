@@ -1,7 +1,7 @@
 //! TOML configuration file parsing
 use serde::Deserialize;
 
-use crate::config::builder::ConfigBuilder;
+use crate::config::{builder::ConfigBuilder, ConfigSource};
 
 #[derive(Deserialize, Debug, PartialEq)]
 struct EthereumConfig {
@@ -9,13 +9,63 @@ struct EthereumConfig {
     password: Option<String>,
 }
 
+#[derive(Deserialize, Debug, PartialEq)]
+struct SyncConfig {
+    #[serde(rename = "validate-constructor-calldata")]
+    validate_constructor_calldata: Option<bool>,
+    #[serde(rename = "max-reorg-depth")]
+    max_reorg_depth: Option<u64>,
+    #[serde(rename = "class-backfill")]
+    class_backfill: Option<bool>,
+    #[serde(rename = "block-backfill")]
+    block_backfill: Option<bool>,
+    #[serde(rename = "stall-timeout")]
+    stall_timeout: Option<u64>,
+    #[serde(rename = "reject-non-monotonic-timestamps")]
+    reject_non_monotonic_timestamps: Option<bool>,
+    #[serde(rename = "timestamp-tolerance")]
+    timestamp_tolerance: Option<u64>,
+    #[serde(rename = "state-update-parallelism")]
+    state_update_parallelism: Option<usize>,
+    #[serde(rename = "raw-block-window")]
+    raw_block_window: Option<u64>,
+    #[serde(rename = "l1-confirmation-depth")]
+    l1_confirmation_depth: Option<u64>,
+    #[serde(rename = "pending-poll-interval")]
+    pending_poll_interval: Option<u64>,
+    #[serde(rename = "max-block-transactions")]
+    max_block_transactions: Option<usize>,
+    #[serde(rename = "shadow-gateway-url")]
+    shadow_gateway_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct StorageConfig {
+    #[serde(rename = "max-blob-size")]
+    max_blob_size: Option<usize>,
+    #[serde(rename = "statement-cache-capacity")]
+    statement_cache_capacity: Option<usize>,
+    #[serde(rename = "event-query-cost-budget")]
+    event_query_cost_budget: Option<u64>,
+}
+
 #[derive(Deserialize, Debug, PartialEq)]
 struct FileConfig {
     ethereum: Option<EthereumConfig>,
+    sync: Option<SyncConfig>,
+    storage: Option<StorageConfig>,
     #[serde(rename = "http-rpc")]
     http_rpc: Option<String>,
+    #[serde(rename = "admin-rpc")]
+    admin_rpc: Option<String>,
     #[serde(rename = "data-directory")]
     data_directory: Option<String>,
+    #[serde(rename = "enable-sql-rpc")]
+    enable_sql_rpc: Option<bool>,
+    #[serde(rename = "serve-only")]
+    serve_only: Option<bool>,
+    #[serde(rename = "forward-transactions")]
+    forward_transactions: Option<bool>,
 }
 
 impl FileConfig {
@@ -29,6 +79,128 @@ impl FileConfig {
         }
         .with(ConfigOption::DataDirectory, self.data_directory)
         .with(ConfigOption::HttpRpcAddress, self.http_rpc)
+        .with(ConfigOption::AdminRpcAddress, self.admin_rpc)
+        .with(
+            ConfigOption::EnableSqlRpc,
+            self.enable_sql_rpc.map(|b| b.to_string()),
+        )
+        .with(
+            ConfigOption::ServeOnly,
+            self.serve_only.map(|b| b.to_string()),
+        )
+        .with(
+            ConfigOption::ForwardTransactions,
+            self.forward_transactions.map(|b| b.to_string()),
+        )
+        .with(
+            ConfigOption::ValidateConstructorCalldata,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.validate_constructor_calldata)
+                .map(|b| b.to_string()),
+        )
+        .with(
+            ConfigOption::ClassBackfill,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.class_backfill)
+                .map(|b| b.to_string()),
+        )
+        .with(
+            ConfigOption::BlockBackfill,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.block_backfill)
+                .map(|b| b.to_string()),
+        )
+        .with(
+            ConfigOption::SyncStallTimeout,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.stall_timeout)
+                .map(|secs| secs.to_string()),
+        )
+        .with(
+            ConfigOption::RejectNonMonotonicTimestamps,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.reject_non_monotonic_timestamps)
+                .map(|b| b.to_string()),
+        )
+        .with(
+            ConfigOption::TimestampTolerance,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.timestamp_tolerance)
+                .map(|secs| secs.to_string()),
+        )
+        .with(
+            ConfigOption::StateUpdateParallelism,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.state_update_parallelism)
+                .map(|threads| threads.to_string()),
+        )
+        .with(
+            ConfigOption::RawBlockWindow,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.raw_block_window)
+                .map(|blocks| blocks.to_string()),
+        )
+        .with(
+            ConfigOption::L1ConfirmationDepth,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.l1_confirmation_depth)
+                .map(|blocks| blocks.to_string()),
+        )
+        .with(
+            ConfigOption::PendingPollInterval,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.pending_poll_interval)
+                .map(|secs| secs.to_string()),
+        )
+        .with(
+            ConfigOption::MaxBlockTransactions,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.max_block_transactions)
+                .map(|count| count.to_string()),
+        )
+        .with(
+            ConfigOption::ShadowGatewayUrl,
+            self.sync
+                .as_ref()
+                .and_then(|sync| sync.shadow_gateway_url.clone()),
+        )
+        .with(
+            ConfigOption::MaxReorgDepth,
+            self.sync
+                .and_then(|sync| sync.max_reorg_depth)
+                .map(|depth| depth.to_string()),
+        )
+        .with(
+            ConfigOption::EventQueryCostBudget,
+            self.storage
+                .as_ref()
+                .and_then(|storage| storage.event_query_cost_budget)
+                .map(|budget| budget.to_string()),
+        )
+        .with(
+            ConfigOption::MaxBlobSize,
+            self.storage
+                .as_ref()
+                .and_then(|storage| storage.max_blob_size)
+                .map(|size| size.to_string()),
+        )
+        .with(
+            ConfigOption::StatementCacheCapacity,
+            self.storage
+                .and_then(|storage| storage.statement_cache_capacity)
+                .map(|capacity| capacity.to_string()),
+        )
     }
 }
 
@@ -41,7 +213,7 @@ pub fn config_from_filepath(filepath: &std::path::Path) -> std::io::Result<Confi
 fn config_from_str(s: &str) -> std::io::Result<ConfigBuilder> {
     toml::from_str::<FileConfig>(s)
         .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))
-        .map(|cfg| cfg.into_config_options())
+        .map(|cfg| cfg.into_config_options().with_source(ConfigSource::File))
 }
 
 #[cfg(test)]
@@ -98,6 +270,210 @@ password = "{}""#,
         assert_eq!(cfg.take(ConfigOption::DataDirectory), Some(value));
     }
 
+    #[test]
+    fn validate_constructor_calldata() {
+        let toml = r#"sync.validate-constructor-calldata = true"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ValidateConstructorCalldata),
+            Some("true".to_owned())
+        );
+    }
+
+    #[test]
+    fn class_backfill() {
+        let toml = r#"sync.class-backfill = true"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ClassBackfill),
+            Some("true".to_owned())
+        );
+    }
+
+    #[test]
+    fn block_backfill() {
+        let toml = r#"sync.block-backfill = true"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::BlockBackfill),
+            Some("true".to_owned())
+        );
+    }
+
+    #[test]
+    fn enable_sql_rpc() {
+        let toml = r#"enable-sql-rpc = true"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::EnableSqlRpc),
+            Some("true".to_owned())
+        );
+    }
+
+    #[test]
+    fn serve_only() {
+        let toml = r#"serve-only = true"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::ServeOnly), Some("true".to_owned()));
+    }
+
+    #[test]
+    fn forward_transactions() {
+        let toml = r#"forward-transactions = false"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ForwardTransactions),
+            Some("false".to_owned())
+        );
+    }
+
+    #[test]
+    fn max_reorg_depth() {
+        let toml = r#"sync.max-reorg-depth = 128"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::MaxReorgDepth),
+            Some("128".to_owned())
+        );
+    }
+
+    #[test]
+    fn max_blob_size() {
+        let toml = r#"storage.max-blob-size = 16"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(cfg.take(ConfigOption::MaxBlobSize), Some("16".to_owned()));
+    }
+
+    #[test]
+    fn sync_stall_timeout() {
+        let toml = r#"sync.stall-timeout = 600"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::SyncStallTimeout),
+            Some("600".to_owned())
+        );
+    }
+
+    #[test]
+    fn reject_non_monotonic_timestamps() {
+        let toml = r#"sync.reject-non-monotonic-timestamps = true"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::RejectNonMonotonicTimestamps),
+            Some("true".to_owned())
+        );
+    }
+
+    #[test]
+    fn timestamp_tolerance() {
+        let toml = r#"sync.timestamp-tolerance = 60"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::TimestampTolerance),
+            Some("60".to_owned())
+        );
+    }
+
+    #[test]
+    fn state_update_parallelism() {
+        let toml = r#"sync.state-update-parallelism = 8"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::StateUpdateParallelism),
+            Some("8".to_owned())
+        );
+    }
+
+    #[test]
+    fn raw_block_window() {
+        let toml = r#"sync.raw-block-window = 10"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::RawBlockWindow),
+            Some("10".to_owned())
+        );
+    }
+
+    #[test]
+    fn l1_confirmation_depth() {
+        let toml = r#"sync.l1-confirmation-depth = 12"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::L1ConfirmationDepth),
+            Some("12".to_owned())
+        );
+    }
+
+    #[test]
+    fn pending_poll_interval() {
+        let toml = r#"sync.pending-poll-interval = 10"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::PendingPollInterval),
+            Some("10".to_owned())
+        );
+    }
+
+    #[test]
+    fn max_block_transactions() {
+        let toml = r#"sync.max-block-transactions = 5000"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::MaxBlockTransactions),
+            Some("5000".to_owned())
+        );
+    }
+
+    #[test]
+    fn shadow_gateway_url() {
+        let toml = r#"sync.shadow-gateway-url = "https://alpha4-2.starknet.io/""#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::ShadowGatewayUrl),
+            Some("https://alpha4-2.starknet.io/".to_owned())
+        );
+    }
+
+    #[test]
+    fn event_query_cost_budget() {
+        let toml = r#"storage.event-query-cost-budget = 5000"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::EventQueryCostBudget),
+            Some("5000".to_owned())
+        );
+    }
+
+    #[test]
+    fn admin_rpc_address() {
+        let toml = r#"admin-rpc = "127.0.0.1:9546""#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::AdminRpcAddress),
+            Some("127.0.0.1:9546".to_owned())
+        );
+    }
+
+    #[test]
+    fn statement_cache_capacity() {
+        let toml = r#"storage.statement-cache-capacity = 128"#;
+        let mut cfg = config_from_str(toml).unwrap();
+        assert_eq!(
+            cfg.take(ConfigOption::StatementCacheCapacity),
+            Some("128".to_owned())
+        );
+    }
+
+    #[test]
+    fn sources() {
+        let cfg = config_from_str(r#"http-rpc = "value""#).unwrap();
+        assert_eq!(cfg.source(ConfigOption::HttpRpcAddress), ConfigSource::File);
+        assert_eq!(
+            cfg.source(ConfigOption::DataDirectory),
+            ConfigSource::Default
+        );
+    }
+
     #[test]
     fn empty_config() {
         let cfg = config_from_str("").unwrap();
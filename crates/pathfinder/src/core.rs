@@ -98,7 +98,10 @@ pub struct GlobalRoot(pub StarkHash);
 pub struct StarknetBlockHash(pub StarkHash);
 
 /// A StarkNet block number.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+///
+/// Serializes as an integer, and deserializes from either an integer or a `0x`-prefixed hex
+/// string.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
 pub struct StarknetBlockNumber(pub u64);
 
 /// The timestamp of a Starknet block.
@@ -189,6 +192,51 @@ impl StarknetBlockNumber {
     pub const GENESIS: StarknetBlockNumber = StarknetBlockNumber(0);
 }
 
+impl<'de> Deserialize<'de> for StarknetBlockNumber {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = StarknetBlockNumber;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("a block number as an integer or a 0x-prefixed hex string")
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(StarknetBlockNumber(v))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                u64::try_from(v)
+                    .map(StarknetBlockNumber)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                v.strip_prefix("0x")
+                    .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+                    .map(StarknetBlockNumber)
+                    .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
 impl std::cmp::PartialOrd for StarknetBlockNumber {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.0.partial_cmp(&other.0)
@@ -223,6 +271,21 @@ impl std::ops::SubAssign<u64> for StarknetBlockNumber {
     }
 }
 
+impl StarknetBlockTimestamp {
+    /// Formats the timestamp as an RFC 3339 UTC date-time, e.g. `2021-11-16T13:12:28Z`.
+    ///
+    /// Returns [None] if the timestamp is too far in the future to be represented.
+    pub fn to_rfc3339(self) -> Option<String> {
+        use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+
+        let seconds = i64::try_from(self.0).ok()?;
+        let time = NaiveDateTime::from_timestamp_opt(seconds, 0)?;
+        let time = DateTime::<Utc>::from_utc(time, Utc);
+
+        Some(time.to_rfc3339_opts(SecondsFormat::Secs, true))
+    }
+}
+
 impl From<EthereumBlockNumber> for web3::types::BlockId {
     fn from(number: EthereumBlockNumber) -> Self {
         web3::types::BlockId::Number(web3::types::BlockNumber::Number(number.0.into()))
@@ -269,6 +332,28 @@ impl GasPrice {
 
         Ok(Self::from_be_bytes(buf))
     }
+
+    /// Returns the [Fee] for `gas_consumed` units of gas, or [None] on overflow.
+    pub fn checked_mul(self, gas_consumed: u128) -> Option<Fee> {
+        self.0
+            .checked_mul(gas_consumed)
+            .map(|fee| Fee::from(Self(fee)))
+    }
+
+    /// Returns the [Fee] for `gas_consumed` units of gas, capped at `u128::MAX`.
+    pub fn saturating_mul(self, gas_consumed: u128) -> Fee {
+        Fee::from(Self(self.0.saturating_mul(gas_consumed)))
+    }
+
+    /// Returns the sum of both prices, or [None] on overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    /// Returns the sum of both prices, capped at `u128::MAX`.
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
 }
 
 impl From<u64> for GasPrice {
@@ -276,3 +361,129 @@ impl From<u64> for GasPrice {
         Self(u128::from(src))
     }
 }
+
+impl From<GasPrice> for Fee {
+    fn from(price: GasPrice) -> Self {
+        Fee(H128::from(price.to_be_bytes()))
+    }
+}
+
+/// Formats the price in decimal, i.e. in wei.
+impl std::fmt::Display for GasPrice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::fmt::LowerHex for GasPrice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod starknet_block_number {
+        use crate::core::StarknetBlockNumber;
+
+        #[test]
+        fn deserialize_integer_and_hex() {
+            let from_integer = serde_json::from_str::<StarknetBlockNumber>("1000").unwrap();
+            let from_hex = serde_json::from_str::<StarknetBlockNumber>(r#""0x3e8""#).unwrap();
+
+            assert_eq!(from_integer, StarknetBlockNumber(1000));
+            assert_eq!(from_hex, StarknetBlockNumber(1000));
+        }
+
+        #[test]
+        fn serialize_as_integer() {
+            let json = serde_json::to_string(&StarknetBlockNumber(1000)).unwrap();
+            assert_eq!(json, "1000");
+        }
+
+        #[test]
+        fn invalid() {
+            for invalid in [
+                r#""3e8""#,
+                r#""0x""#,
+                r#""0xzz""#,
+                "-1",
+                "1.5",
+                r#""latest""#,
+            ] {
+                serde_json::from_str::<StarknetBlockNumber>(invalid).unwrap_err();
+            }
+        }
+    }
+
+    mod gas_price {
+        use crate::core::{Fee, GasPrice};
+        use web3::types::H128;
+
+        #[test]
+        fn mul_overflow() {
+            let price = GasPrice(u128::MAX / 2);
+            assert_eq!(
+                price.checked_mul(2),
+                Some(Fee(H128::from((u128::MAX - 1).to_be_bytes())))
+            );
+            assert_eq!(price.checked_mul(3), None);
+            assert_eq!(
+                price.saturating_mul(3),
+                Fee(H128::from(u128::MAX.to_be_bytes()))
+            );
+            assert_eq!(
+                GasPrice(100).checked_mul(21_000),
+                Some(Fee(H128::from_low_u64_be(2_100_000)))
+            );
+            assert_eq!(
+                GasPrice::ZERO.checked_mul(u128::MAX),
+                Some(Fee(H128::zero()))
+            );
+        }
+
+        #[test]
+        fn add_overflow() {
+            let price = GasPrice(u128::MAX - 1);
+            assert_eq!(price.checked_add(GasPrice(1)), Some(GasPrice(u128::MAX)));
+            assert_eq!(price.checked_add(GasPrice(2)), None);
+            assert_eq!(price.saturating_add(GasPrice(2)), GasPrice(u128::MAX));
+        }
+
+        #[test]
+        fn decimal_round_trip() {
+            for value in [0, 1, 1_500_000_007, u128::MAX] {
+                let price = GasPrice(value);
+                let decimal = price.to_string();
+                assert_eq!(decimal, value.to_string());
+                assert_eq!(GasPrice(decimal.parse().unwrap()), price);
+            }
+        }
+
+        #[test]
+        fn hex_round_trip() {
+            let price = GasPrice(1_500_000_007);
+            let hex = format!("{:#x}", price);
+            assert_eq!(hex, "0x59682f07");
+            assert_eq!(
+                GasPrice(u128::from_str_radix(&hex[2..], 16).unwrap()),
+                price
+            );
+
+            // The hex formatting matches the RPC serialization.
+            #[serde_with::serde_as]
+            #[derive(serde::Serialize)]
+            struct Wrapper(#[serde_as(as = "crate::serde::GasPriceAsHexStr")] GasPrice);
+            assert_eq!(
+                serde_json::to_string(&Wrapper(price)).unwrap(),
+                format!("\"{}\"", hex)
+            );
+        }
+
+        #[test]
+        fn into_fee() {
+            let fee = Fee::from(GasPrice(0x1234));
+            assert_eq!(fee, Fee(H128::from_low_u64_be(0x1234)));
+        }
+    }
+}
@@ -16,7 +16,7 @@ use crate::{
 };
 
 /// Describes the deployment of a new StarkNet contract.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct DeployedContract {
     pub address: ContractAddress,
     pub hash: ClassHash,
@@ -24,14 +24,14 @@ pub struct DeployedContract {
 }
 
 /// A StarkNet contract's storage updates.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 pub struct ContractUpdate {
     pub address: ContractAddress,
     pub storage_updates: Vec<StorageUpdate>,
 }
 
 /// A StarkNet contract's storage update.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 pub struct StorageUpdate {
     pub address: StorageAddress,
     pub value: StorageValue,
@@ -40,12 +40,47 @@ pub struct StorageUpdate {
 /// The set of state updates of a StarkNet [StateUpdate].
 ///
 /// Contains new [DeployedContracts](DeployedContract) as well as [ContractUpdates](ContractUpdate).
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct StateUpdate {
     pub deployed_contracts: Vec<DeployedContract>,
     pub contract_updates: Vec<ContractUpdate>,
 }
 
+impl From<crate::sequencer::reply::state_update::StateDiff> for StateUpdate {
+    fn from(diff: crate::sequencer::reply::state_update::StateDiff) -> Self {
+        let deployed_contracts = diff
+            .deployed_contracts
+            .into_iter()
+            .map(|contract| DeployedContract {
+                address: contract.address,
+                hash: contract.contract_hash,
+                // The sequencer's state diff does not include the constructor calldata.
+                call_data: vec![],
+            })
+            .collect();
+
+        let contract_updates = diff
+            .storage_diffs
+            .into_iter()
+            .map(|(address, diffs)| ContractUpdate {
+                address,
+                storage_updates: diffs
+                    .into_iter()
+                    .map(|diff| StorageUpdate {
+                        address: diff.key,
+                        value: diff.value,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            deployed_contracts,
+            contract_updates,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum RetrieveStateUpdateError {
     #[error("not found: State transition fact")]
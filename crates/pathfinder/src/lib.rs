@@ -1,3 +1,4 @@
+pub mod build_info;
 pub mod cairo;
 pub mod config;
 pub(crate) mod consts;
@@ -6,6 +7,7 @@ pub mod ethereum;
 pub mod retry;
 pub mod rpc;
 pub mod sequencer;
+pub mod serde;
 pub mod state;
 pub mod storage;
 pub mod update;
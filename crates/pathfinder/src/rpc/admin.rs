@@ -0,0 +1,551 @@
+//! The `pathfinder_admin_*` methods, which let operators adjust a running node without
+//! restarting it.
+//!
+//! They are served by [run_admin_server] on a separate listener, which only accepts loopback
+//! addresses. Every change is logged with its old and new value.
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use jsonrpsee::{
+    core::Error,
+    http_server::{HttpServerBuilder, HttpServerHandle, RpcModule},
+    types::error::CallError,
+};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+use crate::rpc::api::internal_server_error;
+use crate::rpc::rate_limit::RateLimiter;
+use crate::rpc::types::reply::HaltedReorg;
+use crate::state::SyncState;
+use crate::storage::Storage;
+
+type Reload = dyn Fn(EnvFilter) -> anyhow::Result<()> + Send + Sync;
+
+/// Replaces the node's log [EnvFilter] at runtime.
+#[derive(Clone)]
+pub struct LogFilter {
+    /// The directives of the currently installed filter.
+    directives: Arc<Mutex<String>>,
+    reload: Arc<Reload>,
+}
+
+impl LogFilter {
+    /// `directives` are those of the filter which `reload` replaces.
+    pub fn new<F>(directives: String, reload: F) -> Self
+    where
+        F: Fn(EnvFilter) -> anyhow::Result<()> + Send + Sync + 'static,
+    {
+        Self {
+            directives: Arc::new(Mutex::new(directives)),
+            reload: Arc::new(reload),
+        }
+    }
+
+    /// Replaces the filter held by `handle`, whose directives are `directives`.
+    pub fn from_reload_handle<S: 'static>(
+        directives: String,
+        handle: tracing_subscriber::reload::Handle<EnvFilter, S>,
+    ) -> Self {
+        Self::new(directives, move |filter| {
+            handle.reload(filter).context("Reloading log filter")
+        })
+    }
+
+    pub fn directives(&self) -> String {
+        self.directives.lock().unwrap().clone()
+    }
+
+    /// Installs a filter with `directives`, returning the previous directives.
+    ///
+    /// Invalid directives are rejected and leave the current filter in place.
+    pub fn set(&self, directives: &str) -> anyhow::Result<String> {
+        let filter = EnvFilter::try_new(directives).context("Parsing log filter directives")?;
+
+        let mut current = self.directives.lock().unwrap();
+        (self.reload)(filter)?;
+        Ok(std::mem::replace(&mut *current, directives.to_owned()))
+    }
+}
+
+/// The context of the admin methods.
+pub struct AdminApi {
+    storage: Storage,
+    rate_limiter: Arc<RateLimiter>,
+    sync_state: Arc<SyncState>,
+    log_filter: Option<LogFilter>,
+}
+
+/// The old and new value of a setting changed by an admin method.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+/// The current values of the settings which can be changed via the admin methods.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// `None` if the node was started without a [LogFilter].
+    pub log_filter: Option<String>,
+    /// Maximum number of RPC calls per second, `None` if unlimited.
+    pub rpc_rate_limit: Option<NonZeroU32>,
+    pub statement_cache_capacity: usize,
+}
+
+/// The outcome of `PRAGMA wal_checkpoint(TRUNCATE)`.
+///
+/// Without write-ahead logging, or on a read-only database, nothing is checkpointed and both
+/// frame counts are `-1`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WalCheckpoint {
+    /// Whether the checkpoint could not complete because of concurrent readers or writers.
+    pub busy: bool,
+    pub log_frames: i64,
+    pub checkpointed_frames: i64,
+}
+
+impl AdminApi {
+    /// `rate_limiter` is the one of the served [RpcApi](super::api::RpcApi), see
+    /// [RpcApi::rate_limiter](super::api::RpcApi::rate_limiter), and `sync_state` the one of the
+    /// sync.
+    pub fn new(
+        storage: Storage,
+        rate_limiter: Arc<RateLimiter>,
+        sync_state: Arc<SyncState>,
+    ) -> Self {
+        Self {
+            storage,
+            rate_limiter,
+            sync_state,
+            log_filter: None,
+        }
+    }
+
+    /// Enables changing the node's log filter.
+    pub fn with_log_filter(self, log_filter: LogFilter) -> Self {
+        Self {
+            log_filter: Some(log_filter),
+            ..self
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            log_filter: self.log_filter.as_ref().map(LogFilter::directives),
+            rpc_rate_limit: self.rate_limiter.limit(),
+            statement_cache_capacity: self.storage.statement_cache_capacity(),
+        }
+    }
+
+    pub fn set_log_filter(&self, directives: String) -> Result<Change<String>, Error> {
+        let log_filter = self
+            .log_filter
+            .as_ref()
+            .ok_or_else(|| internal_server_error("The log filter cannot be changed"))?;
+        let old = log_filter
+            .set(&directives)
+            .map_err(|e| Error::Call(CallError::InvalidParams(e)))?;
+
+        tracing::info!(%old, new=%directives, "Log filter changed");
+        Ok(Change {
+            old,
+            new: directives,
+        })
+    }
+
+    pub fn set_rpc_rate_limit(&self, limit: Option<NonZeroU32>) -> Change<Option<NonZeroU32>> {
+        let old = self.rate_limiter.set_limit(limit);
+
+        tracing::info!(?old, new=?limit, "RPC rate limit changed");
+        Change { old, new: limit }
+    }
+
+    /// Connections opened before the change keep their capacity.
+    pub fn set_statement_cache_capacity(&self, capacity: usize) -> Change<usize> {
+        let old = self.storage.set_statement_cache_capacity(capacity);
+
+        tracing::info!(old, new = capacity, "Statement cache capacity changed");
+        Change { old, new: capacity }
+    }
+
+    /// Lets sync proceed with the L2 reorg it is halted on, returning that reorg.
+    ///
+    /// Returns `None` if sync is not halted.
+    pub fn approve_reorg(&self) -> Option<HaltedReorg> {
+        let approved = self.sync_state.approve_reorg();
+        if let Some(reorg) = approved {
+            tracing::warn!(depth=%reorg.depth(), "Deep L2 reorg approved via the admin RPC");
+        }
+        approved.map(Into::into)
+    }
+
+    pub async fn checkpoint_wal(&self) -> Result<WalCheckpoint, Error> {
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let connection = storage
+                .connection()
+                .context("Opening database connection")?;
+            connection
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+                    Ok(WalCheckpoint {
+                        busy: row.get::<_, i64>(0)? != 0,
+                        log_frames: row.get(1)?,
+                        checkpointed_frames: row.get(2)?,
+                    })
+                })
+                .context("Checkpointing the write-ahead log")
+        });
+
+        let checkpoint = jh
+            .await
+            .context("Database checkpoint panic or shutting down")
+            .and_then(|x| x)
+            .map_err(internal_server_error)?;
+
+        tracing::info!(
+            busy = checkpoint.busy,
+            log_frames = checkpoint.log_frames,
+            checkpointed_frames = checkpoint.checkpointed_frames,
+            "Write-ahead log checkpointed"
+        );
+        Ok(checkpoint)
+    }
+}
+
+/// Starts the admin HTTP-RPC server, which serves the `pathfinder_admin_*` methods.
+///
+/// Fails if `addr` is not a loopback address, as the methods are not authenticated.
+pub async fn run_admin_server(
+    addr: SocketAddr,
+    api: AdminApi,
+) -> Result<(HttpServerHandle, SocketAddr), Error> {
+    if !addr.ip().is_loopback() {
+        return Err(Error::Custom(format!(
+            "The admin RPC server may only listen on a loopback address, not {}",
+            addr
+        )));
+    }
+
+    let server = HttpServerBuilder::default().build(addr).await?;
+    let local_addr = server.local_addr()?;
+
+    let mut module = RpcModule::new(api);
+    module.register_method("pathfinder_admin_getSettings", |_, context| {
+        Ok(context.settings())
+    })?;
+    module.register_method("pathfinder_admin_setLogFilter", |params, context| {
+        #[derive(Debug, Deserialize)]
+        pub struct NamedArgs {
+            pub filter: String,
+        }
+        let params = params.parse::<NamedArgs>()?;
+        context.set_log_filter(params.filter)
+    })?;
+    module.register_method("pathfinder_admin_setRpcRateLimit", |params, context| {
+        #[derive(Debug, Deserialize)]
+        pub struct NamedArgs {
+            pub limit: Option<NonZeroU32>,
+        }
+        let params = params.parse::<NamedArgs>()?;
+        Ok(context.set_rpc_rate_limit(params.limit))
+    })?;
+    module.register_method(
+        "pathfinder_admin_setStatementCacheCapacity",
+        |params, context| {
+            #[derive(Debug, Deserialize)]
+            pub struct NamedArgs {
+                pub capacity: usize,
+            }
+            let params = params.parse::<NamedArgs>()?;
+            Ok(context.set_statement_cache_capacity(params.capacity))
+        },
+    )?;
+    module.register_method("pathfinder_admin_approveReorg", |_, context| {
+        Ok(context.approve_reorg())
+    })?;
+    module.register_async_method("pathfinder_admin_checkpointWal", |_, context| async move {
+        context.checkpoint_wal().await
+    })?;
+
+    server.start(module).map(|handle| (handle, local_addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethereum::Chain;
+    use crate::rpc::api::RpcApi;
+    use crate::rpc::types::reply::ErrorCode;
+    use crate::sequencer::Client as SeqClient;
+    use jsonrpsee::{
+        core::client::ClientT,
+        http_client::{HttpClient, HttpClientBuilder},
+        rpc_params,
+        types::ParamsSer,
+    };
+    use std::collections::BTreeMap;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use tracing_subscriber::prelude::*;
+
+    fn client(addr: SocketAddr) -> HttpClient {
+        HttpClientBuilder::default()
+            .build(format!("http://{}", addr))
+            .expect("Failed to create HTTP-RPC client")
+    }
+
+    fn by_name<const N: usize>(params: [(&'_ str, serde_json::Value); N]) -> Option<ParamsSer<'_>> {
+        Some(BTreeMap::from(params).into())
+    }
+
+    fn localhost() -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))
+    }
+
+    /// Collects the messages of all events.
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Messages {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Visitor<'a>(&'a Mutex<Vec<String>>);
+
+            impl tracing::field::Visit for Visitor<'_> {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    if field.name() == "message" {
+                        self.0.lock().unwrap().push(format!("{:?}", value));
+                    }
+                }
+            }
+
+            event.record(&mut Visitor(&self.0));
+        }
+    }
+
+    #[tokio::test]
+    async fn log_filter_change_takes_effect() {
+        let messages = Messages::default();
+        let (filter, handle) = tracing_subscriber::reload::Layer::new(EnvFilter::new("info"));
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(messages.clone()),
+        );
+
+        let storage = Storage::in_memory().unwrap();
+        let api = AdminApi::new(storage, Arc::default(), Arc::default())
+            .with_log_filter(LogFilter::from_reload_handle("info".to_owned(), handle));
+        let (_handle, addr) = run_admin_server(localhost(), api).await.unwrap();
+
+        tracing::debug!("suppressed");
+        let change = client(addr)
+            .request::<Change<String>>(
+                "pathfinder_admin_setLogFilter",
+                by_name([("filter", serde_json::json!("debug"))]),
+            )
+            .await
+            .unwrap();
+        tracing::debug!("shown");
+
+        assert_eq!(
+            change,
+            Change {
+                old: "info".to_owned(),
+                new: "debug".to_owned()
+            }
+        );
+        let messages = messages.0.lock().unwrap();
+        assert!(!messages.iter().any(|m| m == "suppressed"));
+        assert!(messages.iter().any(|m| m == "shown"));
+        assert!(messages.iter().any(|m| m == "Log filter changed"));
+    }
+
+    #[tokio::test]
+    async fn invalid_log_filter_is_rejected() {
+        let log_filter = LogFilter::new("info".to_owned(), |_| Ok(()));
+        let api = AdminApi::new(
+            Storage::in_memory().unwrap(),
+            Arc::default(),
+            Arc::default(),
+        )
+        .with_log_filter(log_filter.clone());
+        let (_handle, addr) = run_admin_server(localhost(), api).await.unwrap();
+
+        client(addr)
+            .request::<Change<String>>(
+                "pathfinder_admin_setLogFilter",
+                by_name([("filter", serde_json::json!("pathfinder=loud"))]),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(log_filter.directives(), "info");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_change_takes_effect() {
+        let storage = Storage::in_memory().unwrap();
+        let sequencer = SeqClient::new(Chain::Goerli).unwrap();
+        let sync_state = Arc::new(SyncState::default());
+        let api = RpcApi::new(storage.clone(), sequencer, Chain::Goerli, sync_state);
+        let admin = AdminApi::new(storage, api.rate_limiter(), Arc::default());
+
+        let (_handle, addr) = crate::rpc::run_server(localhost(), api).await.unwrap();
+        let (_admin_handle, admin_addr) = run_admin_server(localhost(), admin).await.unwrap();
+        let chain_id = || async move {
+            crate::rpc::test_utils::client(addr)
+                .request::<String>("starknet_chainId", rpc_params!())
+                .await
+        };
+        let set_limit = |limit: serde_json::Value| async move {
+            client(admin_addr)
+                .request::<Change<Option<NonZeroU32>>>(
+                    "pathfinder_admin_setRpcRateLimit",
+                    by_name([("limit", limit)]),
+                )
+                .await
+                .unwrap()
+        };
+
+        for _ in 0..3 {
+            chain_id().await.unwrap();
+        }
+
+        let change = set_limit(serde_json::json!(1)).await;
+        assert_eq!(change.old, None);
+        assert_eq!(change.new, NonZeroU32::new(1));
+        // The calls above may already have used up the current window.
+        let _ = chain_id().await;
+        let error = chain_id().await.unwrap_err();
+        assert_eq!(ErrorCode::RateLimited, error);
+
+        set_limit(serde_json::Value::Null).await;
+        chain_id().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn statement_cache_capacity_change() {
+        let storage = Storage::in_memory_with_statement_cache_capacity(16).unwrap();
+        let api = AdminApi::new(storage.clone(), Arc::default(), Arc::default());
+        let (_handle, addr) = run_admin_server(localhost(), api).await.unwrap();
+
+        let change = client(addr)
+            .request::<Change<usize>>(
+                "pathfinder_admin_setStatementCacheCapacity",
+                by_name([("capacity", serde_json::json!(64))]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(change, Change { old: 16, new: 64 });
+        assert_eq!(storage.statement_cache_capacity(), 64);
+
+        let settings = client(addr)
+            .request::<Settings>("pathfinder_admin_getSettings", rpc_params!())
+            .await
+            .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                log_filter: None,
+                rpc_rate_limit: None,
+                statement_cache_capacity: 64,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn checkpoint_wal() {
+        let api = AdminApi::new(
+            Storage::in_memory().unwrap(),
+            Arc::default(),
+            Arc::default(),
+        );
+        let (_handle, addr) = run_admin_server(localhost(), api).await.unwrap();
+
+        let checkpoint = client(addr)
+            .request::<WalCheckpoint>("pathfinder_admin_checkpointWal", rpc_params!())
+            .await
+            .unwrap();
+        assert!(!checkpoint.busy);
+    }
+
+    mod approve_reorg {
+        use super::*;
+        use crate::core::StarknetBlockNumber;
+        use crate::state::DeepReorg;
+
+        #[tokio::test]
+        async fn not_halted() {
+            let api = AdminApi::new(
+                Storage::in_memory().unwrap(),
+                Arc::default(),
+                Arc::default(),
+            );
+            let (_handle, addr) = run_admin_server(localhost(), api).await.unwrap();
+
+            let approved = client(addr)
+                .request::<Option<HaltedReorg>>("pathfinder_admin_approveReorg", rpc_params!())
+                .await
+                .unwrap();
+            assert_eq!(approved, None);
+        }
+
+        #[tokio::test]
+        async fn halted() {
+            let sync_state = Arc::new(SyncState::default());
+            let approval = sync_state.halt_on_reorg(DeepReorg {
+                head: StarknetBlockNumber(100),
+                reorg_tail: StarknetBlockNumber(10),
+            });
+            let api = AdminApi::new(
+                Storage::in_memory().unwrap(),
+                Arc::default(),
+                sync_state.clone(),
+            );
+            let (_handle, addr) = run_admin_server(localhost(), api).await.unwrap();
+
+            let approved = client(addr)
+                .request::<Option<HaltedReorg>>("pathfinder_admin_approveReorg", rpc_params!())
+                .await
+                .unwrap();
+            assert_eq!(
+                approved,
+                Some(HaltedReorg {
+                    head: StarknetBlockNumber(100),
+                    reorg_tail: StarknetBlockNumber(10),
+                    depth: 91,
+                })
+            );
+            approval.await.unwrap();
+            assert_eq!(sync_state.halted_reorg(), None);
+
+            // There is nothing left to approve.
+            let approved = client(addr)
+                .request::<Option<HaltedReorg>>("pathfinder_admin_approveReorg", rpc_params!())
+                .await
+                .unwrap();
+            assert_eq!(approved, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_non_loopback_address() {
+        let api = AdminApi::new(
+            Storage::in_memory().unwrap(),
+            Arc::default(),
+            Arc::default(),
+        );
+        let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+        run_admin_server(addr, api).await.unwrap_err();
+    }
+}
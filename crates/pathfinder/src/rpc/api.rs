@@ -1,6 +1,7 @@
 //! Implementation of JSON-RPC endpoints.
 use crate::{
     cairo::ext_py,
+    config::ConfigEntry,
     core::{
         CallResultValue, CallSignatureElem, ConstructorParam, ContractAddress, ContractAddressSalt,
         ContractCode, Fee, GasPrice, GlobalRoot, SequencerAddress, StarknetBlockHash,
@@ -10,17 +11,29 @@ use crate::{
     ethereum::Chain,
     rpc::types::{
         reply::{
-            Block, BlockStatus, ErrorCode, GetEventsResult, Syncing, Transaction,
+            Block, BlockStatus, ErrorCode, EventsChainHead, GetEventsResult, Syncing, Transaction,
             TransactionReceipt,
         },
-        request::{BlockResponseScope, Call, EventFilter, OverflowingStorageAddress},
-        BlockHashOrTag, BlockNumberOrTag, Tag,
+        request::{
+            BlockResponseScope, Call, DiffStateOptions, EventFilter, MessageFromL1,
+            OverflowingStorageAddress,
+        },
+        BlockHashOrTag, BlockNumberOrTag, RelativeBlockNumber, Tag,
+    },
+    rpc::{
+        extensions::RpcExtensions,
+        health::SequencerProbe,
+        metrics::RpcMetrics,
+        rate_limit::RateLimiter,
+        spec::{v0_2::BlockId, SpecVersion},
+        token,
     },
     sequencer::{self, request::add_transaction::ContractDefinition, ClientApi},
-    state::SyncState,
+    state::{PendingBlock, SyncState},
     storage::{
-        EventFilterError, RefsTable, StarknetBlocksBlockId, StarknetBlocksTable,
-        StarknetEventsTable, StarknetTransactionsTable, Storage,
+        ChainStatsTable, ContractCodeTable, ContractsTable, DeclaredClassesTable, EventFilterError,
+        RefsTable, StarknetBlocksBlockId, StarknetBlocksTable, StarknetEventsTable,
+        StarknetTransactionsTable, Storage, SyncRetryQueueTable, TimestampDirection,
     },
 };
 use anyhow::Context;
@@ -33,16 +46,44 @@ use std::convert::TryInto;
 use std::sync::Arc;
 
 use super::types::reply::{
-    DeclareTransactionResult, DeployTransactionResult, InvokeTransactionResult,
+    BlockHeader, ChainStats, DeclareTransactionResult, DeclaredClass, DecodedFunction,
+    DecodedTransaction, DeployTransactionResult, FeeEstimate, InvokeTransactionResult, NodeHealth,
+    NodeVersion, ShadowComparison, SqlResult, StateDiffResult, SyncProgress, SyncRetryEntry,
+    TokenBalance, TransactionInclusionProof,
 };
 
+/// Upper bound on the number of entries returned by [RpcApi::diff_state].
+pub const DIFF_STATE_RESULT_LIMIT: usize = 10_000;
+
+/// Upper bound on the number of blocks compared by a single [RpcApi::compare_shadow] call.
+pub const COMPARE_SHADOW_BLOCK_LIMIT: u64 = 10_000;
+
+/// Upper bound on the number of rows returned by [RpcApi::sql].
+pub const SQL_ROW_LIMIT: usize = 1_000;
+
+/// Queries run by [RpcApi::sql] are interrupted after this long.
+pub const SQL_TIME_LIMIT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Default number of blocks by which the L1-L2 head may trail the latest block for
+/// [RpcApi::health] to consider the node synced.
+pub const HEALTH_MAX_L1_LAG: u64 = 100;
+
 /// Implements JSON-RPC endpoints.
 pub struct RpcApi {
     storage: Storage,
     sequencer: sequencer::Client,
+    chain: Chain,
     chain_id: &'static str,
     call_handle: Option<ext_py::Handle>,
     sync_state: Arc<SyncState>,
+    extensions: RpcExtensions,
+    sql_rpc: bool,
+    forward_transactions: bool,
+    metrics: Arc<RpcMetrics>,
+    rate_limiter: Arc<RateLimiter>,
+    sequencer_probe: Arc<SequencerProbe>,
+    configuration: Arc<Vec<ConfigEntry>>,
+    shadow_storage: Option<Storage>,
 }
 
 #[derive(Debug)]
@@ -58,6 +99,18 @@ pub struct RawBlock {
     pub gas_price: GasPrice,
 }
 
+impl AsRef<RpcMetrics> for RpcApi {
+    fn as_ref(&self) -> &RpcMetrics {
+        &self.metrics
+    }
+}
+
+impl AsRef<RateLimiter> for RpcApi {
+    fn as_ref(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+}
+
 /// Based on [the Starknet operator API spec](https://github.com/starkware-libs/starknet-specs/blob/master/api/starknet_api_openrpc.json).
 impl RpcApi {
     pub fn new(
@@ -69,6 +122,7 @@ impl RpcApi {
         Self {
             storage,
             sequencer,
+            chain,
             chain_id: match chain {
                 // Hex str for b"SN_GOERLI"
                 Chain::Goerli => "0x534e5f474f45524c49",
@@ -77,6 +131,14 @@ impl RpcApi {
             },
             call_handle: None,
             sync_state,
+            extensions: RpcExtensions::default(),
+            sql_rpc: false,
+            forward_transactions: true,
+            metrics: Arc::default(),
+            rate_limiter: Arc::default(),
+            sequencer_probe: Arc::default(),
+            configuration: Arc::default(),
+            shadow_storage: None,
         }
     }
 
@@ -87,6 +149,131 @@ impl RpcApi {
         }
     }
 
+    /// Serves `pathfinder_sql`, provided the server listens on a loopback address.
+    pub fn with_sql_rpc(self) -> Self {
+        Self {
+            sql_rpc: true,
+            ..self
+        }
+    }
+
+    /// Serves the [configuration dump](crate::config::Configuration::dump) via
+    /// `pathfinder_getConfiguration`, which is only served on loopback addresses.
+    pub fn with_configuration(self, configuration: Vec<ConfigEntry>) -> Self {
+        Self {
+            configuration: Arc::new(configuration),
+            ..self
+        }
+    }
+
+    pub(crate) fn sql_rpc_enabled(&self) -> bool {
+        self.sql_rpc
+    }
+
+    /// Stops serving the `starknet_add*Transaction` methods, which forward transactions to the
+    /// gateway.
+    pub fn without_transaction_forwarding(self) -> Self {
+        Self {
+            forward_transactions: false,
+            ..self
+        }
+    }
+
+    pub(crate) fn transaction_forwarding_enabled(&self) -> bool {
+        self.forward_transactions
+    }
+
+    /// Serves `pathfinder_compareShadow`, which compares the chain in `storage` with the primary
+    /// one, see [shadow](crate::state::shadow).
+    pub fn with_shadow_storage(self, storage: Storage) -> Self {
+        Self {
+            shadow_storage: Some(storage),
+            ..self
+        }
+    }
+
+    pub(crate) fn shadow_comparison_enabled(&self) -> bool {
+        self.shadow_storage.is_some()
+    }
+
+    /// The metrics recorded for the RPC methods served with this API, e.g. for serving them
+    /// on a separate endpoint.
+    pub fn metrics(&self) -> Arc<RpcMetrics> {
+        self.metrics.clone()
+    }
+
+    /// The limit on the rate of calls to the methods served with this API, e.g. for adjusting
+    /// it via the [admin API](super::admin::AdminApi).
+    pub fn rate_limiter(&self) -> Arc<RateLimiter> {
+        self.rate_limiter.clone()
+    }
+
+    /// Serves the methods in `extensions` alongside the built-in ones.
+    pub fn with_extensions(self, extensions: RpcExtensions) -> Self {
+        Self { extensions, ..self }
+    }
+
+    /// The names of the methods added via [RpcApi::with_extensions].
+    pub(crate) fn extension_names(&self) -> Vec<&'static str> {
+        self.extensions.names().collect()
+    }
+
+    /// Invokes the extension method `name`.
+    pub(crate) async fn call_extension(
+        &self,
+        name: &str,
+        params: jsonrpsee::types::Params<'static>,
+    ) -> RpcResult<serde_json::Value> {
+        match self.extensions.call(name, params, self.storage.clone()) {
+            Some(call) => call.await,
+            None => Err(internal_server_error(format!(
+                "Unknown extension method {}",
+                name
+            ))),
+        }
+    }
+
+    /// Fails if the state of the block with the given hash is not available, see
+    /// [ensure_state_available].
+    ///
+    /// Unknown blocks are left to the caller to report.
+    async fn ensure_block_state_available(&self, hash: StarknetBlockHash) -> RpcResult<()> {
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let db = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+
+            let block = StarknetBlocksTable::get(&db, hash.into())
+                .context("Reading block from database")
+                .map_err(internal_server_error)?;
+            match block {
+                Some(block) => ensure_state_available(&db, block.number),
+                None => Ok(()),
+            }
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Returns the pending block held by the sync process, failing with `block_not_found` if
+    /// there is none, e.g. while sync is catching up or if polling is disabled.
+    ///
+    /// This never falls back to the sequencer, which would otherwise be queried on behalf of
+    /// every pending request.
+    async fn pending_block(&self, block_not_found: ErrorCode) -> RpcResult<Arc<PendingBlock>> {
+        self.sync_state
+            .pending
+            .get()
+            .await
+            .ok_or_else(|| Error::from(block_not_found))
+    }
+
     /// Get block information given the block hash.
     /// `block_hash` is the [Hash](crate::rpc::types::BlockHashOrTag::Hash) or [Tag](crate::rpc::types::BlockHashOrTag::Tag)
     /// of the requested block.
@@ -97,15 +284,10 @@ impl RpcApi {
     ) -> RpcResult<Block> {
         let block_id = match block_hash {
             BlockHashOrTag::Tag(Tag::Pending) => {
-                let block = self
-                    .sequencer
-                    .block_by_hash(block_hash)
-                    .await
-                    .map_err(internal_server_error)?;
-
+                let pending = self.pending_block(ErrorCode::InvalidBlockHash).await?;
                 let scope = requested_scope.unwrap_or_default();
 
-                return Ok(Block::from_sequencer_scoped(block, scope));
+                return Ok(Block::from_sequencer_scoped(pending.block.clone(), scope));
             }
             BlockHashOrTag::Hash(hash) => hash.into(),
             BlockHashOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
@@ -120,6 +302,32 @@ impl RpcApi {
         Ok(Block::from_raw(block, transactions))
     }
 
+    /// Get block information given the spec v0.2 [BlockId], which is either a hash, a number or
+    /// a [Tag].
+    ///
+    /// Backs both the v0.1 `getBlockByHash` / `getBlockByNumber` and the v0.2
+    /// `getBlockWithTxHashes` / `getBlockWithTxs` methods.
+    pub async fn get_block_by_id(
+        &self,
+        block_id: BlockId,
+        requested_scope: Option<BlockResponseScope>,
+    ) -> RpcResult<Block> {
+        match block_id {
+            BlockId::Hash { block_hash } => {
+                self.get_block_by_hash(BlockHashOrTag::Hash(block_hash), requested_scope)
+                    .await
+            }
+            BlockId::Number { block_number } => {
+                self.get_block_by_number(BlockNumberOrTag::Number(block_number), requested_scope)
+                    .await
+            }
+            BlockId::Tag(tag) => {
+                self.get_block_by_hash(BlockHashOrTag::Tag(tag), requested_scope)
+                    .await
+            }
+        }
+    }
+
     /// This function assumes that the block ID is valid i.e. it won't check if the block hash or number exist.
     pub async fn get_block_transactions(
         &self,
@@ -141,6 +349,7 @@ impl RpcApi {
             let transactions_receipts = StarknetTransactionsTable::get_transaction_data_for_block(
                 &db_tx,
                 block_number.into(),
+                storage.limits(),
             )
             .context("Reading transactions from database")
             .map_err(internal_server_error)?;
@@ -189,11 +398,16 @@ impl RpcApi {
                                 contract_address: t.contract_address,
                                 entry_point_selector: t.entry_point_selector,
                                 calldata: t.calldata,
+                                class_hash: t.class_hash,
+                                contract_address_salt: t.contract_address_salt,
+                                constructor_calldata: t.constructor_calldata,
                                 status: r.status,
                                 status_data: r.status_data,
+                                execution_status: r.execution_status,
                                 messages_sent: r.messages_sent,
                                 l1_origin_message: r.l1_origin_message,
                                 events: r.events,
+                                placeholder: r.placeholder,
                             }
                         })
                         .collect(),
@@ -215,17 +429,12 @@ impl RpcApi {
         let block_id = match block_number {
             BlockNumberOrTag::Number(number) => number.into(),
             BlockNumberOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
+            BlockNumberOrTag::Relative(relative) => relative_block_id(relative)?,
             BlockNumberOrTag::Tag(Tag::Pending) => {
-                let block = self
-                    .sequencer
-                    .block_by_number(block_number)
-                    .await
-                    .context("Fetch block from sequencer")
-                    .map_err(internal_server_error)?;
-
+                let pending = self.pending_block(ErrorCode::InvalidBlockNumber).await?;
                 let scope = requested_scope.unwrap_or_default();
 
-                return Ok(Block::from_sequencer_scoped(block, scope));
+                return Ok(Block::from_sequencer_scoped(pending.block.clone(), scope));
             }
         };
 
@@ -366,7 +575,10 @@ impl RpcApi {
     ) -> RpcResult<StorageValue> {
         use crate::{
             core::StorageAddress,
-            state::state_tree::{ContractsStateTree, GlobalStateTree},
+            state::{
+                merkle_tree::MerkleTreeError,
+                state_tree::{ContractsStateTree, GlobalStateTree},
+            },
             storage::ContractsStateTable,
         };
         use stark_hash::OverflowError;
@@ -377,15 +589,14 @@ impl RpcApi {
             |_e: OverflowError| Error::from(ErrorCode::InvalidStorageKey),
         )?);
 
-        if key.0.has_more_than_251_bits() {
-            // Report that the value is more than 251 bits
-            return Err(Error::from(ErrorCode::InvalidStorageKey));
-        }
-
         let block_id = match block_hash {
             BlockHashOrTag::Hash(hash) => hash.into(),
             BlockHashOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
             BlockHashOrTag::Tag(Tag::Pending) => {
+                // Stored blocks reject keys of more than 251 bits in the state tree, the sequencer does not
+                if key.0.has_more_than_251_bits() {
+                    return Err(Error::from(ErrorCode::InvalidStorageKey));
+                }
                 return Ok(self
                     .sequencer
                     .storage(contract_address, key, block_hash)
@@ -408,11 +619,13 @@ impl RpcApi {
 
             // Use internal_server_error to indicate that the process of querying for a particular block failed,
             // which is not the same as being sure that the block is not in the db.
-            let global_root = StarknetBlocksTable::get_root(&tx, block_id)
+            let block = StarknetBlocksTable::get(&tx, block_id)
                 .map_err(internal_server_error)?
                 // Since the db query succeeded in execution, we can now report if the block hash was indeed not found
                 // by using a dedicated error code from the RPC API spec
                 .ok_or_else(|| Error::from(ErrorCode::InvalidBlockHash))?;
+            ensure_state_available(&tx, block.number)?;
+            let global_root = block.root;
 
             let global_state_tree = GlobalStateTree::load(&tx, global_root)
                 .context("Global state tree")
@@ -444,10 +657,13 @@ impl RpcApi {
 
             // ContractsStateTree::get() will return zero if the value is still not found (and we know the key is valid),
             // which is consistent with the specification.
-            let storage_val = contract_state_tree
-                .get(key)
-                .context("Get value from contract state tree")
-                .map_err(internal_server_error)?;
+            let storage_val = contract_state_tree.get(key).map_err(|e| match e {
+                // Report that the value is more than 251 bits
+                MerkleTreeError::Overflow => Error::from(ErrorCode::InvalidStorageKey),
+                e => internal_server_error(
+                    anyhow::Error::new(e).context("Get value from contract state tree"),
+                ),
+            })?;
 
             Ok(storage_val)
         });
@@ -479,7 +695,7 @@ impl RpcApi {
                 .map_err(internal_server_error)?;
 
             // Get the transaction from storage.
-            StarknetTransactionsTable::get_transaction(&db_tx, transaction_hash)
+            StarknetTransactionsTable::get_transaction(&db_tx, transaction_hash, storage.limits())
                 .context("Reading transaction from database")?
                 .ok_or_else(|| ErrorCode::InvalidTransactionHash.into())
                 .map(|tx| tx.into())
@@ -491,39 +707,63 @@ impl RpcApi {
             .and_then(|x| x)
     }
 
-    /// Get the details of a transaction by a given block hash and index.
-    /// `block_hash` is the [Hash](crate::rpc::types::BlockHashOrTag::Hash) or [Tag](crate::rpc::types::BlockHashOrTag::Tag)
-    /// of the requested block.
-    pub async fn get_transaction_by_block_hash_and_index(
+    /// Get the details of a transaction by a given transaction hash, along with the invoked
+    /// function decoded using the ABI of the called contract.
+    ///
+    /// The transaction is returned even if it cannot be decoded, with the reason instead.
+    pub async fn decode_transaction(
         &self,
-        block_hash: BlockHashOrTag,
-        index: StarknetTransactionIndex,
-    ) -> RpcResult<Transaction> {
-        let index: usize = index
-            .0
-            .try_into()
-            .map_err(|e| Error::Call(CallError::InvalidParams(anyhow::Error::new(e))))?;
+        transaction_hash: StarknetTransactionHash,
+    ) -> RpcResult<DecodedTransaction> {
+        let storage = self.storage.clone();
 
-        let block_id = match block_hash {
-            BlockHashOrTag::Hash(hash) => StarknetBlocksBlockId::Hash(hash),
-            BlockHashOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
-            BlockHashOrTag::Tag(Tag::Pending) => {
-                let block = self
-                    .sequencer
-                    .block_by_hash(block_hash)
-                    .await
-                    .context("Fetch block from sequencer")
-                    .map_err(internal_server_error)?;
+        let jh = tokio::task::spawn_blocking(move || {
+            let mut db = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
 
-                return block
-                    .transactions
-                    .into_iter()
-                    .nth(index)
-                    .map_or(Err(ErrorCode::InvalidTransactionIndex.into()), |txn| {
-                        Ok(txn.into())
-                    });
-            }
-        };
+            let db_tx = db
+                .transaction()
+                .context("Creating database transaction")
+                .map_err(internal_server_error)?;
+
+            let transaction = StarknetTransactionsTable::get_transaction(
+                &db_tx,
+                transaction_hash,
+                storage.limits(),
+            )
+            .context("Reading transaction from database")?
+            .ok_or(ErrorCode::InvalidTransactionHash)?;
+
+            let (function, reason) = match decode_invoke(&db_tx, &transaction)
+                .context("Reading contract ABI from database")?
+            {
+                Ok(function) => (Some(function), None),
+                Err(reason) => (None, Some(reason)),
+            };
+
+            Ok(DecodedTransaction {
+                transaction: transaction.into(),
+                function,
+                reason,
+            })
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Get a proof that the transaction is included in the transaction commitment of its block.
+    ///
+    /// The commitment is recomputed from the block's stored transactions.
+    pub async fn get_transaction_inclusion_proof(
+        &self,
+        transaction_hash: StarknetTransactionHash,
+    ) -> RpcResult<TransactionInclusionProof> {
+        use crate::state::transaction_commitment::{leaf, TransactionCommitmentTree};
 
         let storage = self.storage.clone();
 
@@ -538,24 +778,44 @@ impl RpcApi {
                 .context("Creating database transaction")
                 .map_err(internal_server_error)?;
 
-            // Get the transaction from storage.
-            match StarknetTransactionsTable::get_transaction_at_block(&db_tx, block_id, index)
-                .context("Reading transaction from database")?
-            {
-                Some(transaction) => Ok(transaction.into()),
-                None => {
-                    // We now need to check whether it was the block hash or transaction index which were invalid. We do this by checking if the block exists
-                    // at all. If no, then the block hash is invalid. If yes, then the index is invalid.
-                    //
-                    // get_root is cheaper than querying the full block.
-                    match StarknetBlocksTable::get_root(&db_tx, block_id)
-                        .context("Reading block from database")?
-                    {
-                        Some(_) => Err(ErrorCode::InvalidTransactionIndex.into()),
-                        None => Err(ErrorCode::InvalidBlockHash.into()),
-                    }
-                }
-            }
+            let (_, block_hash) =
+                StarknetTransactionsTable::get_receipt(&db_tx, transaction_hash, storage.limits())
+                    .context("Reading transaction receipt from database")?
+                    .ok_or(ErrorCode::InvalidTransactionHash)?;
+
+            let block = StarknetBlocksTable::get(&db_tx, StarknetBlocksBlockId::Hash(block_hash))
+                .context("Reading block from database")?
+                .context("Block of the transaction is missing")?;
+
+            let transactions = StarknetTransactionsTable::get_transaction_data_for_block(
+                &db_tx,
+                StarknetBlocksBlockId::Hash(block_hash),
+                storage.limits(),
+            )
+            .context("Reading transactions from database")?
+            .into_iter()
+            .map(|(transaction, _)| transaction)
+            .collect::<Vec<_>>();
+
+            let index = transactions
+                .iter()
+                .position(|tx| tx.transaction_hash == transaction_hash)
+                .context("Transaction is missing from its block")?;
+            let transaction = &transactions[index];
+            let index = index as u64;
+
+            let tree = TransactionCommitmentTree::new(&transactions)?;
+            let transaction_commitment = tree.commitment()?;
+            let proof = tree.get_proof(index)?;
+
+            Ok(TransactionInclusionProof {
+                block_hash,
+                block_number: block.number,
+                transaction_commitment,
+                transaction_index: index,
+                leaf: leaf(transaction),
+                proof: proof.into_iter().map(Into::into).collect(),
+            })
         });
 
         jh.await
@@ -564,6 +824,28 @@ impl RpcApi {
             .and_then(|x| x)
     }
 
+    /// Get the details of a transaction by a given block hash and index.
+    /// `block_hash` is the [Hash](crate::rpc::types::BlockHashOrTag::Hash) or [Tag](crate::rpc::types::BlockHashOrTag::Tag)
+    /// of the requested block.
+    pub async fn get_transaction_by_block_hash_and_index(
+        &self,
+        block_hash: BlockHashOrTag,
+        index: StarknetTransactionIndex,
+    ) -> RpcResult<Transaction> {
+        let block_id = match block_hash {
+            BlockHashOrTag::Hash(hash) => StarknetBlocksBlockId::Hash(hash),
+            BlockHashOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
+            BlockHashOrTag::Tag(Tag::Pending) => {
+                return self
+                    .get_pending_transaction_by_index(index, ErrorCode::InvalidBlockHash)
+                    .await;
+            }
+        };
+
+        self.get_stored_transaction_by_index(block_id, index, ErrorCode::InvalidBlockHash)
+            .await
+    }
+
     /// Get the details of a transaction by a given block number and index.
     /// `block_number` is the [Number](crate::rpc::types::BlockNumberOrTag::Number) (height) or [Tag](crate::rpc::types::BlockHashOrTag::Tag)
     /// of the requested block.
@@ -572,32 +854,76 @@ impl RpcApi {
         block_number: BlockNumberOrTag,
         index: StarknetTransactionIndex,
     ) -> RpcResult<Transaction> {
-        let index: usize = index
-            .0
-            .try_into()
-            .map_err(|e| Error::Call(CallError::InvalidParams(anyhow::Error::new(e))))?;
-
         let block_id = match block_number {
             BlockNumberOrTag::Number(number) => StarknetBlocksBlockId::Number(number),
             BlockNumberOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
+            BlockNumberOrTag::Relative(relative) => relative_block_id(relative)?,
             BlockNumberOrTag::Tag(Tag::Pending) => {
-                let block = self
-                    .sequencer
-                    .block_by_number(block_number)
-                    .await
-                    .context("Fetch block from sequencer")
-                    .map_err(internal_server_error)?;
+                return self
+                    .get_pending_transaction_by_index(index, ErrorCode::InvalidBlockNumber)
+                    .await;
+            }
+        };
 
-                return block
-                    .transactions
-                    .into_iter()
-                    .nth(index)
-                    .map_or(Err(ErrorCode::InvalidTransactionIndex.into()), |txn| {
-                        Ok(txn.into())
-                    });
+        self.get_stored_transaction_by_index(block_id, index, ErrorCode::InvalidBlockNumber)
+            .await
+    }
+
+    /// Get the details of a transaction given the spec v0.2 [BlockId] of its block and its index.
+    ///
+    /// Backs the v0.2 `getTransactionByBlockIdAndIndex` method, which replaces the v0.1
+    /// `getTransactionByBlockHashAndIndex` and `getTransactionByBlockNumberAndIndex` methods.
+    pub async fn get_transaction_by_block_id_and_index(
+        &self,
+        block_id: BlockId,
+        index: StarknetTransactionIndex,
+    ) -> RpcResult<Transaction> {
+        let block_id = match block_id {
+            BlockId::Hash { block_hash } => StarknetBlocksBlockId::Hash(block_hash),
+            BlockId::Number { block_number } => StarknetBlocksBlockId::Number(block_number),
+            BlockId::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
+            BlockId::Tag(Tag::Pending) => {
+                return self
+                    .get_pending_transaction_by_index(index, ErrorCode::InvalidBlockHash)
+                    .await;
             }
         };
 
+        // Spec v0.2 reports any missing block as BLOCK_NOT_FOUND, which has the code of
+        // INVALID_BLOCK_HASH.
+        self.get_stored_transaction_by_index(block_id, index, ErrorCode::InvalidBlockHash)
+            .await
+    }
+
+    /// The transaction at `index` of the pending block, failing with `block_not_found` if there
+    /// is none.
+    async fn get_pending_transaction_by_index(
+        &self,
+        index: StarknetTransactionIndex,
+        block_not_found: ErrorCode,
+    ) -> RpcResult<Transaction> {
+        let pending = self.pending_block(block_not_found).await?;
+
+        usize::try_from(index.0)
+            .ok()
+            .and_then(|index| pending.block.transactions.get(index))
+            .cloned()
+            .map_or(Err(ErrorCode::InvalidTransactionIndex.into()), |txn| {
+                Ok(txn.into())
+            })
+    }
+
+    /// The transaction at `index` of a stored block.
+    ///
+    /// The index is checked against the block's transaction count, so that an index out of
+    /// range fails with [ErrorCode::InvalidTransactionIndex] and a missing block with
+    /// `block_not_found`.
+    async fn get_stored_transaction_by_index(
+        &self,
+        block_id: StarknetBlocksBlockId,
+        index: StarknetTransactionIndex,
+        block_not_found: ErrorCode,
+    ) -> RpcResult<Transaction> {
         let storage = self.storage.clone();
 
         let jh = tokio::task::spawn_blocking(move || {
@@ -611,23 +937,26 @@ impl RpcApi {
                 .context("Creating database transaction")
                 .map_err(internal_server_error)?;
 
-            // Get the transaction from storage.
-            match StarknetTransactionsTable::get_transaction_at_block(&db_tx, block_id, index)
-                .context("Reading transaction from database")?
+            let count = StarknetTransactionsTable::get_transaction_count(&db_tx, block_id)
+                .context("Counting transactions")?;
+            match count {
+                None => return Err(block_not_found.into()),
+                Some(count) if index.0 >= count as u64 => {
+                    return Err(ErrorCode::InvalidTransactionIndex.into())
+                }
+                Some(_) => {}
+            }
+
+            match StarknetTransactionsTable::get_transaction_at_block(
+                &db_tx,
+                block_id,
+                index.0,
+                storage.limits(),
+            )
+            .context("Reading transaction from database")?
             {
                 Some(transaction) => Ok(transaction.into()),
-                None => {
-                    // We now need to check whether it was the block number or transaction index which were invalid. We do this by checking if the block exists
-                    // at all. If no, then the block number is invalid. If yes, then the index is invalid.
-                    //
-                    // get_root is cheaper than querying the full block.
-                    match StarknetBlocksTable::get_root(&db_tx, block_id)
-                        .context("Reading block from database")?
-                    {
-                        Some(_) => Err(ErrorCode::InvalidTransactionIndex.into()),
-                        None => Err(ErrorCode::InvalidBlockNumber.into()),
-                    }
-                }
+                None => Err(ErrorCode::InvalidTransactionIndex.into()),
             }
         });
 
@@ -656,7 +985,7 @@ impl RpcApi {
                 .context("Creating database transaction")
                 .map_err(internal_server_error)?;
 
-            match StarknetTransactionsTable::get_receipt(&db_tx, transaction_hash)
+            match StarknetTransactionsTable::get_receipt(&db_tx, transaction_hash, storage.limits())
                 .context("Reading transaction receipt from database")
                 .map_err(internal_server_error)?
             {
@@ -692,7 +1021,7 @@ impl RpcApi {
     /// Get the code of a specific contract.
     /// `contract_address` is the address of the contract to read from.
     pub async fn get_code(&self, contract_address: ContractAddress) -> RpcResult<ContractCode> {
-        use crate::storage::ContractCodeTable;
+        use crate::storage::{ContractCodeTable, DefinitionNotAvailable};
 
         let storage = self.storage.clone();
 
@@ -706,9 +1035,13 @@ impl RpcApi {
                 .context("Creating database transaction")
                 .map_err(internal_server_error)?;
 
-            let code = ContractCodeTable::get_code(&tx, contract_address)
-                .context("Fetching code from database")
-                .map_err(internal_server_error)?;
+            let code = ContractCodeTable::get_code(&tx, contract_address).map_err(|e| {
+                if e.downcast_ref::<DefinitionNotAvailable>().is_some() {
+                    Error::from(ErrorCode::ClassNotAvailable)
+                } else {
+                    internal_server_error(e.context("Fetching code from database"))
+                }
+            })?;
 
             match code {
                 Some(code) => Ok(code),
@@ -733,15 +1066,10 @@ impl RpcApi {
             BlockHashOrTag::Hash(hash) => hash.into(),
             BlockHashOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
             BlockHashOrTag::Tag(Tag::Pending) => {
-                let block = self
-                    .sequencer
-                    .block_by_hash(block_hash)
-                    .await
-                    .context("Fetch block from sequencer")
-                    .map_err(internal_server_error)?;
+                let pending = self.pending_block(ErrorCode::InvalidBlockHash).await?;
 
                 let len: u64 =
-                    block.transactions.len().try_into().map_err(|e| {
+                    pending.block.transactions.len().try_into().map_err(|e| {
                         Error::Call(CallError::InvalidParams(anyhow::Error::new(e)))
                     })?;
 
@@ -761,23 +1089,13 @@ impl RpcApi {
                 .context("Creating database transaction")
                 .map_err(internal_server_error)?;
 
+            // A known block without transactions has a count of zero, an unknown block none.
             match StarknetTransactionsTable::get_transaction_count(&tx, block_id)
                 .context("Reading transaction count from database")
                 .map_err(internal_server_error)?
             {
-                0 => {
-                    // We need to check if the value was 0 because there were no transactions, or because the block hash
-                    // is invalid.
-                    //
-                    // get_root is cheaper than querying the full block.
-                    match StarknetBlocksTable::get_root(&tx, block_id)
-                        .context("Reading block from database")?
-                    {
-                        Some(_) => Ok(0),
-                        None => Err(ErrorCode::InvalidBlockHash.into()),
-                    }
-                }
-                other => Ok(other as u64),
+                Some(count) => Ok(count as u64),
+                None => Err(ErrorCode::InvalidBlockHash.into()),
             }
         });
 
@@ -797,16 +1115,12 @@ impl RpcApi {
         let block_id = match block_number {
             BlockNumberOrTag::Number(number) => number.into(),
             BlockNumberOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
+            BlockNumberOrTag::Relative(relative) => relative_block_id(relative)?,
             BlockNumberOrTag::Tag(Tag::Pending) => {
-                let block = self
-                    .sequencer
-                    .block_by_number(block_number)
-                    .await
-                    .context("Fetch block from sequencer")
-                    .map_err(internal_server_error)?;
+                let pending = self.pending_block(ErrorCode::InvalidBlockNumber).await?;
 
                 let len: u64 =
-                    block.transactions.len().try_into().map_err(|e| {
+                    pending.block.transactions.len().try_into().map_err(|e| {
                         Error::Call(CallError::InvalidParams(anyhow::Error::new(e)))
                     })?;
 
@@ -826,23 +1140,13 @@ impl RpcApi {
                 .context("Creating database transaction")
                 .map_err(internal_server_error)?;
 
+            // A known block without transactions has a count of zero, an unknown block none.
             match StarknetTransactionsTable::get_transaction_count(&tx, block_id)
                 .context("Reading transaction count from database")
                 .map_err(internal_server_error)?
             {
-                0 => {
-                    // We need to check if the value was 0 because there were no transactions, or because the block number
-                    // is invalid.
-                    //
-                    // get_root is cheaper than querying the full block.
-                    match StarknetBlocksTable::get_root(&tx, block_id)
-                        .context("Reading block from database")?
-                    {
-                        Some(_) => Ok(0),
-                        None => Err(ErrorCode::InvalidBlockNumber.into()),
-                    }
-                }
-                other => Ok(other as u64),
+                Some(count) => Ok(count as u64),
+                None => Err(ErrorCode::InvalidBlockNumber.into()),
             }
         });
 
@@ -863,11 +1167,12 @@ impl RpcApi {
         use futures::future::TryFutureExt;
 
         match (self.call_handle.as_ref(), &block_hash) {
-            (Some(h), &BlockHashOrTag::Hash(_)) => {
+            (Some(h), &BlockHashOrTag::Hash(hash)) => {
                 // only forward calls to specific blocks to our local impl, because we currently
                 // don't do an on-demand poll and flush for the pending block.
                 //
                 // unsure about the expected Tag::Latest semantics either.
+                self.ensure_block_state_available(hash).await?;
                 h.call(request, block_hash).map_err(Error::from).await
             }
             (Some(_), _) | (None, _) => {
@@ -881,6 +1186,54 @@ impl RpcApi {
         }
     }
 
+    /// Estimates the fee of consuming `message` on L2, by invoking the L1 handler it targets at
+    /// `block`.
+    ///
+    /// Requires the python call handler, as the sequencer has no such estimate.
+    pub async fn estimate_message_fee(
+        &self,
+        message: MessageFromL1,
+        block: BlockHashOrTag,
+    ) -> RpcResult<FeeEstimate> {
+        use futures::future::TryFutureExt;
+
+        let handle = self
+            .call_handle
+            .as_ref()
+            .ok_or_else(|| internal_server_error("Fee estimation requires the call handler"))?;
+
+        match block {
+            BlockHashOrTag::Hash(hash) => self.ensure_block_state_available(hash).await?,
+            BlockHashOrTag::Tag(Tag::Latest) => {}
+            BlockHashOrTag::Tag(Tag::Pending) => {
+                return Err(Error::from(ErrorCode::InvalidBlockHash))
+            }
+        }
+
+        handle
+            .estimate_message_fee(message.into_call(), block)
+            .map_err(Error::from)
+            .await
+    }
+
+    /// Returns the balance of `account_address` in the ERC-20 token `token_address` at
+    /// `block_id`, along with the token's metadata.
+    pub async fn get_token_balance(
+        &self,
+        token_address: ContractAddress,
+        account_address: ContractAddress,
+        block_id: BlockHashOrTag,
+    ) -> RpcResult<TokenBalance> {
+        token::balance(
+            self,
+            &self.storage,
+            token_address,
+            account_address,
+            block_id,
+        )
+        .await
+    }
+
     /// Get the most recent accepted block number.
     pub async fn block_number(&self) -> RpcResult<u64> {
         let storage = self.storage.clone();
@@ -914,21 +1267,352 @@ impl RpcApi {
         Ok(self.chain_id)
     }
 
-    // /// Returns the transactions in the transaction pool, recognized by this sequencer.
-    // pub async fn pending_transactions(&self) -> RpcResult<Vec<Transaction>> {
-    //     todo!("Figure out where to take them from.")
-    // }
-
-    // /// Returns the current starknet protocol version identifier, as supported by this node.
-    // pub async fn protocol_version(&self) -> RpcResult<StarknetProtocolVersion> {
-    //     todo!("Figure out where to take it from.")
-    // }
-
-    /// Returns an object about the sync status, or false if the node is not synching.
-    pub async fn syncing(&self) -> RpcResult<Syncing> {
-        // Scoped so I don't have to think too hard about mutex guard drop semantics.
-        let value = { self.sync_state.status.read().await.clone() };
-        Ok(value)
+    /// Returns the version of the JSON-RPC specification which this node implements.
+    pub async fn spec_version(&self) -> RpcResult<&'static str> {
+        Ok(SpecVersion::CURRENT.as_str())
+    }
+
+    /// Returns the version and build information of this node, along with
+    /// the database schema revision and the configured chain.
+    pub async fn version(&self) -> RpcResult<NodeVersion> {
+        use crate::build_info;
+
+        let storage = self.storage.clone();
+        let chain = match self.chain {
+            Chain::Mainnet => "mainnet",
+            Chain::Goerli => "goerli",
+        };
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let connection = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+
+            let schema_revision = crate::storage::schema_version(&connection)
+                .context("Reading database schema version")
+                .map_err(internal_server_error)?;
+
+            Ok(NodeVersion {
+                version: build_info::VERSION.to_owned(),
+                git_describe: build_info::GIT_DESCRIBE.to_owned(),
+                git_commit_hash: build_info::GIT_COMMIT_HASH.to_owned(),
+                git_dirty: build_info::git_dirty(),
+                build_timestamp: build_info::BUILD_TIMESTAMP.to_owned(),
+                features: build_info::features()
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+                schema_revision,
+                chain: chain.to_owned(),
+            })
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Returns the transactions of the pending block held by the sync process.
+    ///
+    /// The sync process polls the pending block every `--sync.pending-poll-interval` while it is
+    /// at the chain head. This is expected to be polled aggressively, so it only reads the shared
+    /// pending state and never falls back to the sequencer. Returns an empty list if there is no
+    /// pending block, e.g. while sync is catching up or if polling is disabled.
+    pub async fn pending_transactions(&self) -> RpcResult<Vec<Transaction>> {
+        let transactions = match self.sync_state.pending.get().await {
+            Some(pending) => pending
+                .block
+                .transactions
+                .iter()
+                .cloned()
+                .map(Transaction::from)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(transactions)
+    }
+
+    // /// Returns the current starknet protocol version identifier, as supported by this node.
+    // pub async fn protocol_version(&self) -> RpcResult<StarknetProtocolVersion> {
+    //     todo!("Figure out where to take it from.")
+    // }
+
+    /// Returns an object about the sync status, or false if the node is not synching.
+    pub async fn syncing(&self) -> RpcResult<Syncing> {
+        // Scoped so I don't have to think too hard about mutex guard drop semantics.
+        let value = { self.sync_state.status.read().await.clone() };
+        Ok(value)
+    }
+
+    /// Returns the rate at which blocks were stored over the `since` window preceding this
+    /// call, along with the resulting estimate of the time remaining until the highest known
+    /// block is reached.
+    pub async fn sync_progress(&self, since: std::time::Duration) -> RpcResult<SyncProgress> {
+        let blocks_per_second = self
+            .sync_state
+            .throughput
+            .read()
+            .await
+            .blocks_per_second(std::time::Instant::now(), since);
+
+        let remaining = match &*self.sync_state.status.read().await {
+            Syncing::False(_) => None,
+            Syncing::Status(status) => Some(
+                status
+                    .highest
+                    .number
+                    .0
+                    .saturating_sub(status.current.number.0),
+            ),
+        };
+
+        let seconds_to_head = match (remaining, blocks_per_second) {
+            (Some(0), _) => Some(0),
+            (Some(remaining), Some(rate)) if rate > 0.0 => {
+                Some((remaining as f64 / rate).ceil() as u64)
+            }
+            _ => None,
+        };
+
+        Ok(SyncProgress {
+            blocks_per_second,
+            seconds_to_head,
+            halted_reorg: self.sync_state.halted_reorg().map(Into::into),
+            root_divergence: self.sync_state.root_divergence().map(Into::into),
+        })
+    }
+
+    /// Returns the number of deployed contracts, distinct classes, transactions and events
+    /// as of the latest block.
+    pub async fn chain_stats(&self) -> RpcResult<ChainStats> {
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let connection = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+
+            ChainStatsTable::get(&connection)
+                .context("Reading chain statistics from database")
+                .map(Into::into)
+                .map_err(internal_server_error)
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Returns the headers of all blocks which resulted in the given global root, ordered by
+    /// block number.
+    ///
+    /// A root is shared by multiple blocks if the state did not change between them.
+    pub async fn get_block_by_root(&self, root: GlobalRoot) -> RpcResult<Vec<BlockHeader>> {
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let connection = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+
+            let blocks = StarknetBlocksTable::get_all_by_root(&connection, root)
+                .context("Reading blocks from database")
+                .map_err(internal_server_error)?;
+
+            Ok(blocks.into_iter().map(Into::into).collect())
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Returns the header of the block closest to `timestamp` in the given direction.
+    ///
+    /// Returns [`ErrorCode::InvalidBlockNumber`] if there is no stored block before (or
+    /// after) `timestamp`.
+    pub async fn get_block_by_timestamp(
+        &self,
+        timestamp: StarknetBlockTimestamp,
+        direction: TimestampDirection,
+    ) -> RpcResult<BlockHeader> {
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let connection = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+
+            let block = StarknetBlocksTable::get_by_timestamp(&connection, timestamp, direction)
+                .context("Reading block from database")
+                .map_err(internal_server_error)?;
+
+            block
+                .map(Into::into)
+                .ok_or_else(|| Error::from(ErrorCode::InvalidBlockNumber))
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Returns the downloads which failed during sync and are retried in the background, in
+    /// order of their next attempt.
+    pub async fn get_sync_retry_queue(&self) -> RpcResult<Vec<SyncRetryEntry>> {
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let connection = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+
+            let entries = SyncRetryQueueTable::all(&connection)
+                .context("Reading sync retry queue from database")
+                .map_err(internal_server_error)?;
+
+            Ok(entries.into_iter().map(Into::into).collect())
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Returns whether the node is synced, its database writable and the sequencer reachable.
+    ///
+    /// The node is considered synced if the L1-L2 head trails the latest block by at most
+    /// `max_l1_lag` blocks. Whether the sequencer is reachable is only probed every few seconds,
+    /// see [SequencerProbe].
+    pub async fn health(&self, max_l1_lag: u64) -> RpcResult<NodeHealth> {
+        let sequencer_reachable = match self.sequencer_probe.reachable() {
+            Some(reachable) => reachable,
+            None => {
+                let reachable = match self.sequencer.ping().await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        tracing::debug!(reason=%e, "Sequencer health check failed");
+                        false
+                    }
+                };
+                self.sequencer_probe.record(reachable);
+                reachable
+            }
+        };
+
+        let storage = self.storage.clone();
+        let jh = tokio::task::spawn_blocking(move || {
+            let connection = storage
+                .connection()
+                .context("Opening database connection")?;
+
+            let latest_block = StarknetBlocksTable::get_latest_number(&connection)
+                .context("Reading latest block number from database")?;
+            let l1_l2_head =
+                RefsTable::get_l1_l2_head(&connection).context("Reading L1-L2 head")?;
+            let query_only: bool = connection
+                .pragma_query_value(None, "query_only", |row| row.get(0))
+                .context("Reading query_only pragma")?;
+
+            Ok::<_, anyhow::Error>((
+                latest_block,
+                l1_l2_head,
+                !storage.is_read_only() && !query_only,
+            ))
+        });
+
+        let (latest_block, l1_l2_head, database_writable) = jh
+            .await
+            .context("Database read panic or shutting down")
+            .and_then(|x| x)
+            .map_err(internal_server_error)?;
+
+        let synced = match (latest_block, l1_l2_head) {
+            (Some(latest), Some(head)) => latest.0.saturating_sub(head.0) <= max_l1_lag,
+            _ => false,
+        };
+
+        Ok(NodeHealth {
+            healthy: synced
+                && sequencer_reachable
+                && (database_writable || self.storage.is_read_only())
+                && self.sync_state.is_healthy(),
+            synced,
+            latest_block,
+            l1_l2_head,
+            database_writable,
+            sequencer_reachable,
+        })
+    }
+
+    /// Returns the node's resolved configuration and where each value came from, with secrets
+    /// redacted.
+    pub async fn get_configuration(&self) -> RpcResult<Vec<ConfigEntry>> {
+        Ok(self.configuration.as_ref().clone())
+    }
+
+    /// Returns the classes declared in the given block, in transaction order.
+    pub async fn get_declared_classes(
+        &self,
+        block: BlockNumberOrTag,
+    ) -> RpcResult<Vec<DeclaredClass>> {
+        let block = match block {
+            BlockNumberOrTag::Number(number) => StarknetBlocksBlockId::Number(number),
+            BlockNumberOrTag::Tag(Tag::Latest) => StarknetBlocksBlockId::Latest,
+            BlockNumberOrTag::Relative(relative) => relative_block_id(relative)?,
+            // Only declarations in stored blocks are indexed.
+            BlockNumberOrTag::Tag(Tag::Pending) => {
+                return Err(Error::from(ErrorCode::InvalidBlockNumber))
+            }
+        };
+
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let mut connection = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+            let tx = connection
+                .transaction()
+                .context("Creating database transaction")
+                .map_err(internal_server_error)?;
+
+            let block = StarknetBlocksTable::get(&tx, block)
+                .context("Reading block from database")
+                .map_err(internal_server_error)?
+                .ok_or_else(|| Error::from(ErrorCode::InvalidBlockNumber))?;
+
+            let classes = DeclaredClassesTable::get_for_block(&tx, block.number)
+                .context("Reading declared classes from database")
+                .map_err(internal_server_error)?;
+
+            Ok(classes.into_iter().map(Into::into).collect())
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Returns the call counts and latencies of all RPC methods, as well as the storage's
+    /// prepared statement cache hits and misses, in the Prometheus text format.
+    pub async fn rpc_metrics(&self) -> RpcResult<String> {
+        let mut rendered = self.metrics.render();
+        rendered.push_str(&crate::storage::render_statement_cache_metrics());
+        Ok(rendered)
     }
 
     /// Returns events matching the specified filter
@@ -936,27 +1620,53 @@ impl RpcApi {
         let storage = self.storage.clone();
 
         let jh = tokio::task::spawn_blocking(move || {
-            let connection = storage
+            let mut connection = storage
                 .connection()
                 .context("Opening database connection")
                 .map_err(internal_server_error)?;
+            // Read the head and the events from a single snapshot, so that a concurrent
+            // reorg cannot delete blocks half-way through assembling the page.
+            let tx = connection
+                .transaction()
+                .context("Creating database transaction")
+                .map_err(internal_server_error)?;
+
+            let head = StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Latest)
+                .context("Reading latest block from database")
+                .map_err(internal_server_error)?;
+
+            if let Some(expected) = request.chain_head {
+                let canonical =
+                    StarknetBlocksTable::get(&tx, StarknetBlocksBlockId::Hash(expected))
+                        .context("Reading block from database")
+                        .map_err(internal_server_error)?
+                        .is_some();
+                if !canonical {
+                    return Err(Error::from(ErrorCode::Reconsider));
+                }
+            }
 
             let filter = request.into();
             // We don't add context here, because [StarknetEventsTable::get_events] adds its
             // own context to the errors. This way we get meaningful error information
             // for errors related to query parameters.
-            let page = StarknetEventsTable::get_events(&connection, &filter).map_err(|e| {
-                if let Some(e) = e.downcast_ref::<EventFilterError>() {
-                    Error::from(*e)
-                } else {
-                    internal_server_error(e)
-                }
-            })?;
+            let page =
+                StarknetEventsTable::get_events(&tx, &filter, storage.limits()).map_err(|e| {
+                    if let Some(e) = e.downcast_ref::<EventFilterError>() {
+                        Error::from(*e)
+                    } else {
+                        internal_server_error(e)
+                    }
+                })?;
 
             Ok(GetEventsResult {
                 events: page.events.into_iter().map(|e| e.into()).collect(),
                 page_number: filter.page_number,
                 is_last_page: page.is_last_page,
+                chain_head: head.map(|head| EventsChainHead {
+                    block_hash: head.hash,
+                    block_number: head.number,
+                }),
             })
         });
 
@@ -967,6 +1677,151 @@ impl RpcApi {
             .and_then(|x| x)
     }
 
+    /// Returns the contracts deployed and the storage entries changed between two blocks.
+    ///
+    /// The number of returned entries is capped at [DIFF_STATE_RESULT_LIMIT], or at
+    /// `options.max_results` if that is lower.
+    pub async fn diff_state(
+        &self,
+        from_block: BlockNumberOrTag,
+        to_block: BlockNumberOrTag,
+        options: DiffStateOptions,
+    ) -> RpcResult<StateDiffResult> {
+        let block_id = |block| match block {
+            BlockNumberOrTag::Number(number) => Ok(StarknetBlocksBlockId::Number(number)),
+            BlockNumberOrTag::Tag(Tag::Latest) => Ok(StarknetBlocksBlockId::Latest),
+            BlockNumberOrTag::Relative(relative) => relative_block_id(relative),
+            // The pending block has no state tree to compare against.
+            BlockNumberOrTag::Tag(Tag::Pending) => Err(Error::from(ErrorCode::InvalidBlockNumber)),
+        };
+        let from_block = block_id(from_block)?;
+        let to_block = block_id(to_block)?;
+
+        let limit = options.max_results.map_or(DIFF_STATE_RESULT_LIMIT, |max| {
+            max.min(DIFF_STATE_RESULT_LIMIT)
+        });
+
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let mut db = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+            let tx = db
+                .transaction()
+                .context("Creating database transaction")
+                .map_err(internal_server_error)?;
+
+            let from = StarknetBlocksTable::get(&tx, from_block)
+                .context("Reading block from database")
+                .map_err(internal_server_error)?
+                .ok_or_else(|| Error::from(ErrorCode::InvalidBlockNumber))?;
+            let to = StarknetBlocksTable::get(&tx, to_block)
+                .context("Reading block from database")
+                .map_err(internal_server_error)?
+                .ok_or_else(|| Error::from(ErrorCode::InvalidBlockNumber))?;
+
+            ensure_state_available(&tx, from.number)?;
+            ensure_state_available(&tx, to.number)?;
+
+            if from.number > to.number {
+                return Err(Error::Call(CallError::InvalidParams(anyhow::anyhow!(
+                    "from_block {} is newer than to_block {}",
+                    from.number.0,
+                    to.number.0
+                ))));
+            }
+
+            let diff = crate::state::diff::diff_state(&tx, &from, &to, limit, storage.limits())
+                .context("Computing state diff")
+                .map_err(internal_server_error)?;
+
+            Ok(diff.into())
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Compares the hashes and state roots of the blocks `from_block..=to_block` of the primary
+    /// and the shadow chain, see [compare](crate::state::shadow::compare).
+    ///
+    /// At most [COMPARE_SHADOW_BLOCK_LIMIT] blocks are compared at once.
+    pub async fn compare_shadow(
+        &self,
+        from_block: StarknetBlockNumber,
+        to_block: StarknetBlockNumber,
+    ) -> RpcResult<ShadowComparison> {
+        let shadow = self
+            .shadow_storage
+            .clone()
+            .ok_or_else(|| internal_server_error(anyhow::anyhow!("Shadow sync is not enabled")))?;
+
+        if from_block > to_block {
+            return Err(Error::Call(CallError::InvalidParams(anyhow::anyhow!(
+                "from_block {} is newer than to_block {}",
+                from_block.0,
+                to_block.0
+            ))));
+        }
+        if to_block.0 - from_block.0 >= COMPARE_SHADOW_BLOCK_LIMIT {
+            return Err(Error::Call(CallError::InvalidParams(anyhow::anyhow!(
+                "At most {} blocks can be compared at once",
+                COMPARE_SHADOW_BLOCK_LIMIT
+            ))));
+        }
+
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            let primary = storage
+                .connection()
+                .context("Opening database connection")
+                .map_err(internal_server_error)?;
+            let shadow = shadow
+                .connection()
+                .context("Opening shadow database connection")
+                .map_err(internal_server_error)?;
+
+            crate::state::shadow::compare(&primary, &shadow, from_block, to_block)
+                .map(ShadowComparison::from)
+                .context("Comparing primary and shadow chain")
+                .map_err(internal_server_error)
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
+    /// Runs a single SELECT statement against the database, see
+    /// [read_only_query](crate::storage::read_only_query).
+    pub async fn sql(&self, query: String) -> RpcResult<SqlResult> {
+        use crate::storage::QueryError;
+
+        let storage = self.storage.clone();
+
+        let jh = tokio::task::spawn_blocking(move || {
+            crate::storage::read_only_query(&storage, &query, SQL_ROW_LIMIT, SQL_TIME_LIMIT)
+                .map(SqlResult::from)
+                .map_err(|e| match e {
+                    QueryError::Internal(e) => internal_server_error(e),
+                    user_error => {
+                        Error::Call(CallError::InvalidParams(anyhow::Error::new(user_error)))
+                    }
+                })
+        });
+
+        jh.await
+            .context("Database read panic or shutting down")
+            .map_err(internal_server_error)
+            .and_then(|x| x)
+    }
+
     /// Submit a new transaction to be added to the chain.
     ///
     /// This method just forwards the request received over the JSON-RPC
@@ -1055,6 +1910,7 @@ impl From<ext_py::CallFailure> for jsonrpsee::core::Error {
         match e {
             ext_py::CallFailure::NoSuchBlock => Error::from(ErrorCode::InvalidBlockHash),
             ext_py::CallFailure::NoSuchContract => Error::from(ErrorCode::ContractNotFound),
+            ext_py::CallFailure::NoSuchEntryPoint => Error::from(ErrorCode::InvalidMessageSelector),
             ext_py::CallFailure::ExecutionFailed(e) => internal_server_error(e),
             // Intentionally hide the message under Internal
             ext_py::CallFailure::Internal(_) | ext_py::CallFailure::Shutdown => {
@@ -1064,6 +1920,49 @@ impl From<ext_py::CallFailure> for jsonrpsee::core::Error {
     }
 }
 
+#[async_trait::async_trait]
+impl token::CallExecutor for RpcApi {
+    /// Executes `request` like [RpcApi::call], but distinguishes reverted calls.
+    async fn execute(
+        &self,
+        request: Call,
+        block: BlockHashOrTag,
+    ) -> Result<Vec<CallResultValue>, token::ExecutionError> {
+        use sequencer::error::{SequencerError, StarknetErrorCode};
+
+        match (self.call_handle.as_ref(), &block) {
+            (Some(h), &BlockHashOrTag::Hash(hash)) => {
+                self.ensure_block_state_available(hash)
+                    .await
+                    .map_err(token::ExecutionError::Other)?;
+                h.call(request, block).await.map_err(|e| match e {
+                    ext_py::CallFailure::ExecutionFailed(reason) => {
+                        token::ExecutionError::Reverted(reason)
+                    }
+                    other => token::ExecutionError::Other(other.into()),
+                })
+            }
+            (Some(_), _) | (None, _) => self
+                .sequencer
+                .call(request.into(), block)
+                .await
+                .map(|x| x.result)
+                .map_err(|e| match e {
+                    SequencerError::StarknetError(e)
+                        if matches!(
+                            e.code,
+                            StarknetErrorCode::TransactionFailed
+                                | StarknetErrorCode::EntryPointNotFound
+                        ) =>
+                    {
+                        token::ExecutionError::Reverted(e.message)
+                    }
+                    other => token::ExecutionError::Other(other.into()),
+                }),
+        }
+    }
+}
+
 impl From<EventFilterError> for jsonrpsee::core::Error {
     fn from(e: EventFilterError) -> Self {
         match e {
@@ -1075,10 +1974,76 @@ impl From<EventFilterError> for jsonrpsee::core::Error {
                     Some(serde_json::json!({ "max_page_size": max_size })),
                 )))
             }
+            EventFilterError::TooExpensive { .. } => {
+                Error::Call(CallError::InvalidParams(anyhow::Error::new(e)))
+            }
         }
     }
 }
 
+/// Decodes the function invoked by `transaction`, or returns the reason it can't be decoded.
+fn decode_invoke(
+    db_tx: &rusqlite::Transaction<'_>,
+    transaction: &sequencer::reply::transaction::Transaction,
+) -> anyhow::Result<Result<DecodedFunction, String>> {
+    use sequencer::reply::transaction::Type;
+
+    if transaction.r#type != Type::InvokeFunction {
+        return Ok(Err("Only invoke transactions can be decoded".to_owned()));
+    }
+    let selector = match transaction.entry_point_selector {
+        Some(selector) => selector,
+        None => return Ok(Err("Transaction has no entry point selector".to_owned())),
+    };
+
+    let class_hash = match ContractsTable::get_hash(db_tx, transaction.contract_address)? {
+        Some(class_hash) => class_hash,
+        None => return Ok(Err("Contract is not deployed".to_owned())),
+    };
+    let abi = match ContractCodeTable::get_abi(db_tx, class_hash)? {
+        Some(abi) => abi,
+        None => return Ok(Err("Contract ABI is not available".to_owned())),
+    };
+
+    let calldata = transaction.calldata.as_deref().unwrap_or_default();
+    Ok(super::decode::decode_call(&abi, selector, calldata).map_err(|e| e.to_string()))
+}
+
+/// Fails with [NodeNotReady](ErrorCode::NodeNotReady) if `block` was
+/// [backfilled](crate::state::block_backfill) ahead of the forward sync, as its state has not been
+/// built yet, and with [StatePruned](ErrorCode::StatePruned) if its state was already released by
+/// [gc_trees](crate::state::tree_gc::gc_trees).
+fn ensure_state_available(
+    connection: &rusqlite::Connection,
+    block: StarknetBlockNumber,
+) -> RpcResult<()> {
+    let backfilled = RefsTable::get_backfill_range(connection)
+        .context("Reading backfill range from database")
+        .map_err(internal_server_error)?;
+    if matches!(backfilled, Some(range) if range.contains(&block)) {
+        return Err(Error::from(ErrorCode::NodeNotReady));
+    }
+
+    let pruned_before = RefsTable::get_trees_pruned_before(connection)
+        .context("Reading pruned blocks from database")
+        .map_err(internal_server_error)?;
+    match pruned_before {
+        Some(pruned_before) if block < pruned_before => Err(Error::from(ErrorCode::StatePruned)),
+        _ => Ok(()),
+    }
+}
+
+/// Resolves a [RelativeBlockNumber] to the storage block id, which is read relative to the latest
+/// block by the same statement that reads the block.
+///
+/// Positive offsets are beyond the latest block, and are rejected as an invalid block number.
+fn relative_block_id(relative: RelativeBlockNumber) -> RpcResult<StarknetBlocksBlockId> {
+    match relative.relative {
+        offset if offset <= 0 => Ok(StarknetBlocksBlockId::LatestMinus(offset.unsigned_abs())),
+        _ => Err(Error::from(ErrorCode::InvalidBlockNumber)),
+    }
+}
+
 // We cannot just return Error::Internal (-32003) in cases which are not covered by starknet RPC API spec
 // as jsonrpsee reserved it for internal subscription related errors only, so we resort to
 // CallError::Custom with the same code value and message as Error::Internal. This way we can still provide
@@ -1086,7 +2051,7 @@ impl From<EventFilterError> for jsonrpsee::core::Error {
 //
 // This error is used for all instances of operations that are not explicitly specified in the StarkNet spec.
 // See <https://github.com/starkware-libs/starknet-specs/blob/master/api/starknet_api_openrpc.json>
-fn internal_server_error(e: impl std::fmt::Display) -> jsonrpsee::core::Error {
+pub(crate) fn internal_server_error(e: impl std::fmt::Display) -> jsonrpsee::core::Error {
     Error::Call(CallError::Custom(ErrorObject::owned(
         jsonrpsee::types::error::ErrorCode::InternalError.code(),
         format!("{}: {}", jsonrpsee::types::error::INTERNAL_ERROR_MSG, e),
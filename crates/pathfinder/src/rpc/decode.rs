@@ -0,0 +1,390 @@
+//! Best-effort decoding of invoke transaction calldata using the ABI of the called contract.
+//!
+//! Arguments are typed as `felt`, `Uint256`, arrays (`T*`, whose length is given by the
+//! preceding `<name>_len` argument) and structs declared in the ABI. Tuples and nested pointers
+//! are not supported.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use stark_hash::StarkHash;
+use web3::types::U256;
+
+use crate::{
+    core::{CallParam, EntryPoint},
+    rpc::types::reply::{DecodedArgument, DecodedFunction, DecodedValue},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("invalid ABI: {0}")]
+    InvalidAbi(#[from] serde_json::Error),
+    #[error("entry point selector {0} is not a function of the ABI")]
+    UnknownSelector(StarkHash),
+    #[error("unsupported type {ty:?} of argument {name:?}")]
+    UnsupportedType { name: String, ty: String },
+    #[error("array {0:?} is not preceded by its length")]
+    MissingLength(String),
+    #[error("value of Uint256 argument {0:?} exceeds 128 bits per member")]
+    InvalidUint256(String),
+    #[error("calldata has {actual} elements but the function takes {expected}")]
+    CalldataLength { expected: usize, actual: usize },
+    #[error("elements of array {0:?} take no calldata")]
+    ZeroSizeElement(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AbiEntry {
+    Function {
+        name: String,
+        inputs: Vec<AbiMember>,
+    },
+    Struct {
+        name: String,
+        size: usize,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct AbiMember {
+    name: String,
+    r#type: String,
+}
+
+/// Decodes a call of the function with the given `selector` from the contract with the given
+/// json `abi`.
+pub fn decode_call(
+    abi: &[u8],
+    selector: EntryPoint,
+    calldata: &[CallParam],
+) -> Result<DecodedFunction, DecodeError> {
+    let abi = serde_json::from_slice::<Vec<AbiEntry>>(abi)?;
+
+    let mut struct_sizes = HashMap::new();
+    let mut function = None;
+    for entry in abi {
+        match entry {
+            AbiEntry::Struct { name, size } => {
+                struct_sizes.insert(name, size);
+            }
+            AbiEntry::Function { name, inputs }
+                if EntryPoint::hashed(name.as_bytes()) == selector =>
+            {
+                function = Some((name, inputs));
+            }
+            _ => {}
+        }
+    }
+    let (name, inputs) = function.ok_or(DecodeError::UnknownSelector(selector.0))?;
+
+    let mut decoder = Decoder {
+        struct_sizes,
+        calldata: calldata.iter().map(|param| param.0).collect(),
+        position: 0,
+    };
+
+    let mut arguments: Vec<DecodedArgument> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let value = match input.r#type.strip_suffix('*') {
+            Some(element) => {
+                let length = arguments
+                    .last()
+                    .filter(|previous| previous.name == format!("{}_len", input.name))
+                    .and_then(|previous| match previous.value {
+                        DecodedValue::Felt(length) => Some(length),
+                        _ => None,
+                    })
+                    .ok_or_else(|| DecodeError::MissingLength(input.name.clone()))?;
+
+                decoder.array(&input.name, element, length)?
+            }
+            None => decoder.value(&input.name, &input.r#type)?,
+        };
+
+        arguments.push(DecodedArgument {
+            name: input.name,
+            r#type: input.r#type,
+            value,
+        });
+    }
+
+    decoder.finish()?;
+
+    Ok(DecodedFunction { name, arguments })
+}
+
+struct Decoder {
+    struct_sizes: HashMap<String, usize>,
+    calldata: Vec<StarkHash>,
+    position: usize,
+}
+
+impl Decoder {
+    /// The number of calldata elements taken by a value of type `ty`.
+    fn size(&self, name: &str, ty: &str) -> Result<usize, DecodeError> {
+        match ty {
+            "felt" => Ok(1),
+            "Uint256" => Ok(2),
+            other => {
+                self.struct_sizes
+                    .get(other)
+                    .copied()
+                    .ok_or_else(|| DecodeError::UnsupportedType {
+                        name: name.to_owned(),
+                        ty: ty.to_owned(),
+                    })
+            }
+        }
+    }
+
+    fn value(&mut self, name: &str, ty: &str) -> Result<DecodedValue, DecodeError> {
+        match ty {
+            "felt" => Ok(DecodedValue::Felt(self.take(1)?[0])),
+            "Uint256" => {
+                let members = self.take(2)?;
+                let (low, high) = (members[0].to_be_bytes(), members[1].to_be_bytes());
+                if low[..16].iter().chain(&high[..16]).any(|b| *b != 0) {
+                    return Err(DecodeError::InvalidUint256(name.to_owned()));
+                }
+
+                let mut bytes = [0u8; 32];
+                bytes[..16].copy_from_slice(&high[16..]);
+                bytes[16..].copy_from_slice(&low[16..]);
+
+                Ok(DecodedValue::Uint256(
+                    U256::from_big_endian(&bytes).to_string(),
+                ))
+            }
+            other => {
+                let size = self.size(name, other)?;
+                Ok(DecodedValue::Array(
+                    self.take(size)?
+                        .iter()
+                        .map(|member| DecodedValue::Felt(*member))
+                        .collect(),
+                ))
+            }
+        }
+    }
+
+    fn array(
+        &mut self,
+        name: &str,
+        element: &str,
+        length: StarkHash,
+    ) -> Result<DecodedValue, DecodeError> {
+        if element.ends_with('*') {
+            return Err(DecodeError::UnsupportedType {
+                name: name.to_owned(),
+                ty: format!("{}*", element),
+            });
+        }
+
+        let bytes = length.to_be_bytes();
+        let length = match bytes[..24].iter().all(|b| *b == 0) {
+            true => u64::from_be_bytes(bytes[24..].try_into().unwrap()),
+            false => u64::MAX,
+        };
+        let length = usize::try_from(length).unwrap_or(usize::MAX);
+
+        // The length is checked against the calldata up front, as it is chosen by the sender.
+        // Elements which take no calldata could be repeated any number of times.
+        let size = self.size(name, element)?;
+        if size == 0 {
+            return Err(DecodeError::ZeroSizeElement(name.to_owned()));
+        }
+        let remaining = self.calldata.len() - self.position;
+        if length > remaining / size {
+            return Err(DecodeError::CalldataLength {
+                expected: self.position.saturating_add(length.saturating_mul(size)),
+                actual: self.calldata.len(),
+            });
+        }
+
+        let mut elements = Vec::with_capacity(length);
+        for _ in 0..length {
+            elements.push(self.value(name, element)?);
+        }
+
+        Ok(DecodedValue::Array(elements))
+    }
+
+    fn take(&mut self, count: usize) -> Result<&[StarkHash], DecodeError> {
+        let end = self.position + count;
+        if end > self.calldata.len() {
+            return Err(DecodeError::CalldataLength {
+                expected: end,
+                actual: self.calldata.len(),
+            });
+        }
+
+        let values = &self.calldata[self.position..end];
+        self.position = end;
+        Ok(values)
+    }
+
+    fn finish(self) -> Result<(), DecodeError> {
+        match self.position == self.calldata.len() {
+            true => Ok(()),
+            false => Err(DecodeError::CalldataLength {
+                expected: self.position,
+                actual: self.calldata.len(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const ERC20_ABI: &[u8] = include_bytes!("../../fixtures/abi/erc20.json");
+
+    fn felt(hex: &str) -> StarkHash {
+        StarkHash::from_hex_str(hex).unwrap()
+    }
+
+    fn calldata(hex: &[&str]) -> Vec<CallParam> {
+        hex.iter().map(|h| CallParam(felt(h))).collect()
+    }
+
+    fn argument(name: &str, ty: &str, value: DecodedValue) -> DecodedArgument {
+        DecodedArgument {
+            name: name.to_owned(),
+            r#type: ty.to_owned(),
+            value,
+        }
+    }
+
+    #[test]
+    fn transfer() {
+        let selector = EntryPoint::hashed(b"transfer");
+        // 2**128 + 5
+        let decoded = decode_call(ERC20_ABI, selector, &calldata(&["abc", "5", "1"])).unwrap();
+
+        assert_eq!(
+            decoded,
+            DecodedFunction {
+                name: "transfer".to_owned(),
+                arguments: vec![
+                    argument("recipient", "felt", DecodedValue::Felt(felt("abc"))),
+                    argument(
+                        "amount",
+                        "Uint256",
+                        DecodedValue::Uint256("340282366920938463463374607431768211461".to_owned())
+                    ),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn arrays() {
+        let selector = EntryPoint::hashed(b"batchTransfer");
+        let data = calldata(&["2", "a", "b", "1", "64", "0"]);
+        let decoded = decode_call(ERC20_ABI, selector, &data).unwrap();
+
+        assert_eq!(
+            decoded.arguments,
+            vec![
+                argument("recipients_len", "felt", DecodedValue::Felt(felt("2"))),
+                argument(
+                    "recipients",
+                    "felt*",
+                    DecodedValue::Array(vec![
+                        DecodedValue::Felt(felt("a")),
+                        DecodedValue::Felt(felt("b")),
+                    ])
+                ),
+                argument("amounts_len", "felt", DecodedValue::Felt(felt("1"))),
+                argument(
+                    "amounts",
+                    "Uint256*",
+                    DecodedValue::Array(vec![DecodedValue::Uint256("100".to_owned())])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_selector() {
+        let selector = EntryPoint::hashed(b"mint");
+        let error = decode_call(ERC20_ABI, selector, &[]).unwrap_err();
+        assert!(matches!(error, DecodeError::UnknownSelector(s) if s == selector.0));
+
+        // Only functions are matched.
+        let selector = EntryPoint::hashed(b"constructor");
+        let error = decode_call(ERC20_ABI, selector, &[]).unwrap_err();
+        assert!(matches!(error, DecodeError::UnknownSelector(_)));
+    }
+
+    #[test]
+    fn calldata_length_mismatch() {
+        let selector = EntryPoint::hashed(b"transfer");
+
+        let error = decode_call(ERC20_ABI, selector, &calldata(&["abc", "5"])).unwrap_err();
+        assert!(matches!(
+            error,
+            DecodeError::CalldataLength {
+                expected: 3,
+                actual: 2
+            }
+        ));
+
+        let error =
+            decode_call(ERC20_ABI, selector, &calldata(&["abc", "5", "0", "1"])).unwrap_err();
+        assert!(matches!(
+            error,
+            DecodeError::CalldataLength {
+                expected: 3,
+                actual: 4
+            }
+        ));
+
+        // An array length beyond the calldata.
+        let selector = EntryPoint::hashed(b"batchTransfer");
+        let error =
+            decode_call(ERC20_ABI, selector, &calldata(&["ffffffffffffffffff", "a"])).unwrap_err();
+        assert!(matches!(error, DecodeError::CalldataLength { .. }));
+
+        // Rejected before decoding any element, as the remaining calldata can't hold them.
+        let error = decode_call(ERC20_ABI, selector, &calldata(&["3", "a", "b"])).unwrap_err();
+        assert!(matches!(
+            error,
+            DecodeError::CalldataLength {
+                expected: 4,
+                actual: 3
+            }
+        ));
+    }
+
+    #[test]
+    fn zero_size_elements() {
+        let abi = br#"[
+            {"type": "struct", "name": "Empty", "size": 0, "members": []},
+            {"type": "function", "name": "f", "inputs": [{"name": "e_len", "type": "felt"}, {"name": "e", "type": "Empty*"}], "outputs": []}
+        ]"#;
+        let data = calldata(&["ffffffffffffffff"]);
+        let error = decode_call(abi, EntryPoint::hashed(b"f"), &data).unwrap_err();
+        assert!(matches!(error, DecodeError::ZeroSizeElement(name) if name == "e"));
+    }
+
+    #[test]
+    fn invalid_uint256() {
+        let selector = EntryPoint::hashed(b"transfer");
+        let data = calldata(&["abc", "100000000000000000000000000000000", "0"]);
+        let error = decode_call(ERC20_ABI, selector, &data).unwrap_err();
+        assert!(matches!(error, DecodeError::InvalidUint256(name) if name == "amount"));
+    }
+
+    #[test]
+    fn unsupported_type() {
+        let abi = br#"[{"type": "function", "name": "f", "inputs": [{"name": "t", "type": "(felt, felt)"}], "outputs": []}]"#;
+        let error = decode_call(abi, EntryPoint::hashed(b"f"), &[]).unwrap_err();
+        assert!(matches!(error, DecodeError::UnsupportedType { .. }));
+    }
+}
@@ -0,0 +1,68 @@
+//! Additional RPC methods registered by applications which embed pathfinder.
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use jsonrpsee::{
+    core::{error::Error, RpcResult},
+    types::Params,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::storage::Storage;
+
+type Handler = Arc<
+    dyn Fn(Params<'static>, Storage) -> BoxFuture<'static, RpcResult<serde_json::Value>>
+        + Send
+        + Sync,
+>;
+
+/// Additional RPC methods which are served alongside the built-in ones, see
+/// [RpcApi::with_extensions](super::api::RpcApi::with_extensions).
+///
+/// Method names must be unique and must not collide with any built-in method, otherwise
+/// [run_server](super::run_server) fails.
+#[derive(Clone, Default)]
+pub struct RpcExtensions(Vec<(&'static str, Handler)>);
+
+impl RpcExtensions {
+    /// Adds the method `name`, which is served by `handler`.
+    ///
+    /// The request's params are deserialized into `P` before `handler` is invoked with them
+    /// and a handle to the node's storage.
+    pub fn register<P, R, F, Fut>(mut self, name: &'static str, handler: F) -> Self
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(P, Storage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = RpcResult<R>> + Send + 'static,
+    {
+        let handler = move |params: Params<'static>, storage| -> BoxFuture<'static, _> {
+            let call = params.parse::<P>().map(|params| handler(params, storage));
+            Box::pin(async move {
+                let result = call?.await?;
+                serde_json::to_value(result).map_err(|e| Error::Custom(e.to_string()))
+            })
+        };
+        self.0.push((name, Arc::new(handler)));
+        self
+    }
+
+    /// The names of all registered methods, in registration order.
+    pub(crate) fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.0.iter().map(|(name, _)| *name)
+    }
+
+    /// Invokes the method `name`, returning `None` if no such method is registered.
+    pub(crate) fn call(
+        &self,
+        name: &str,
+        params: Params<'static>,
+        storage: Storage,
+    ) -> Option<BoxFuture<'static, RpcResult<serde_json::Value>>> {
+        self.0
+            .iter()
+            .find(|(registered, _)| *registered == name)
+            .map(|(_, handler)| handler(params, storage))
+    }
+}
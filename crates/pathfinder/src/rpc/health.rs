@@ -0,0 +1,62 @@
+//! Caches the sequencer probe of [RpcApi::health](super::api::RpcApi::health), so that frequent
+//! health checks do not each send a request to the sequencer.
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the outcome of a sequencer probe is re-used.
+const TTL: Duration = Duration::from_secs(5);
+
+/// The outcome of the latest sequencer probe, and when it was made.
+#[derive(Debug, Default)]
+pub struct SequencerProbe(Mutex<Option<(Instant, bool)>>);
+
+impl SequencerProbe {
+    /// Whether the sequencer was reachable, `None` if it has not been probed within the last few
+    /// seconds and has to be probed again.
+    pub fn reachable(&self) -> Option<bool> {
+        self.reachable_at(Instant::now())
+    }
+
+    fn reachable_at(&self, now: Instant) -> Option<bool> {
+        match *self.0.lock().unwrap() {
+            Some((probed_at, reachable)) if now.saturating_duration_since(probed_at) < TTL => {
+                Some(reachable)
+            }
+            _ => None,
+        }
+    }
+
+    /// Records the outcome of probing the sequencer just now.
+    pub fn record(&self, reachable: bool) {
+        self.record_at(Instant::now(), reachable)
+    }
+
+    fn record_at(&self, now: Instant, reachable: bool) {
+        *self.0.lock().unwrap() = Some((now, reachable));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_probed_yet() {
+        let probe = SequencerProbe::default();
+        assert_eq!(probe.reachable(), None);
+    }
+
+    #[test]
+    fn outcome_expires() {
+        let probe = SequencerProbe::default();
+        let start = Instant::now();
+
+        probe.record_at(start, true);
+        assert_eq!(probe.reachable_at(start), Some(true));
+        assert_eq!(probe.reachable_at(start + TTL / 2), Some(true));
+        assert_eq!(probe.reachable_at(start + TTL), None);
+
+        probe.record_at(start + TTL, false);
+        assert_eq!(probe.reachable_at(start + TTL), Some(false));
+    }
+}
@@ -0,0 +1,147 @@
+//! Invocation counts and latencies of the RPC methods.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds (in seconds) of the latency histogram buckets.
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 2.5, 10.0];
+
+#[derive(Clone, Debug, Default)]
+struct MethodMetrics {
+    calls: u64,
+    /// Number of calls which took at most the corresponding [BUCKETS] bound.
+    buckets: [u64; BUCKETS.len()],
+    total_seconds: f64,
+}
+
+/// Per method call counters and latency histograms, which are recorded for every RPC method
+/// served by [run_server](super::run_server).
+#[derive(Debug, Default)]
+pub struct RpcMetrics(Mutex<BTreeMap<&'static str, MethodMetrics>>);
+
+impl RpcMetrics {
+    /// Records a call of `method` which took `elapsed`.
+    pub fn record(&self, method: &'static str, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f64();
+
+        let mut methods = self.0.lock().unwrap();
+        let metrics = methods.entry(method).or_default();
+        metrics.calls += 1;
+        metrics.total_seconds += elapsed;
+        BUCKETS
+            .iter()
+            .zip(metrics.buckets.iter_mut())
+            .filter(|(bound, _)| elapsed <= **bound)
+            .for_each(|(_, count)| *count += 1);
+    }
+
+    /// The number of calls of `method` recorded so far.
+    pub fn calls(&self, method: &str) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(method)
+            .map(|metrics| metrics.calls)
+            .unwrap_or_default()
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let methods = self.0.lock().unwrap().clone();
+
+        let mut out = String::new();
+        // Writing to a String cannot fail.
+        let _ = writeln!(
+            out,
+            "# HELP rpc_method_calls_total Number of RPC method calls."
+        );
+        let _ = writeln!(out, "# TYPE rpc_method_calls_total counter");
+        for (method, metrics) in &methods {
+            let _ = writeln!(
+                out,
+                "rpc_method_calls_total{{method=\"{}\"}} {}",
+                method, metrics.calls
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP rpc_method_duration_seconds Time taken to serve RPC method calls."
+        );
+        let _ = writeln!(out, "# TYPE rpc_method_duration_seconds histogram");
+        for (method, metrics) in &methods {
+            for (bound, count) in BUCKETS.iter().zip(metrics.buckets.iter()) {
+                let _ = writeln!(
+                    out,
+                    "rpc_method_duration_seconds_bucket{{method=\"{}\",le=\"{}\"}} {}",
+                    method, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "rpc_method_duration_seconds_bucket{{method=\"{}\",le=\"+Inf\"}} {}",
+                method, metrics.calls
+            );
+            let _ = writeln!(
+                out,
+                "rpc_method_duration_seconds_sum{{method=\"{}\"}} {}",
+                method, metrics.total_seconds
+            );
+            let _ = writeln!(
+                out,
+                "rpc_method_duration_seconds_count{{method=\"{}\"}} {}",
+                method, metrics.calls
+            );
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RpcMetrics;
+    use std::time::Duration;
+
+    #[test]
+    fn render() {
+        let metrics = RpcMetrics::default();
+        metrics.record("b_method", Duration::from_millis(250));
+        metrics.record("a_method", Duration::from_millis(2));
+        metrics.record("b_method", Duration::from_secs(20));
+
+        assert_eq!(metrics.calls("a_method"), 1);
+        assert_eq!(metrics.calls("b_method"), 2);
+        assert_eq!(metrics.calls("c_method"), 0);
+
+        let rendered = metrics.render();
+        let lines = rendered.lines().collect::<Vec<_>>();
+
+        // Methods are rendered in order of their names.
+        assert_eq!(
+            &lines[..4],
+            &[
+                "# HELP rpc_method_calls_total Number of RPC method calls.",
+                "# TYPE rpc_method_calls_total counter",
+                r#"rpc_method_calls_total{method="a_method"} 1"#,
+                r#"rpc_method_calls_total{method="b_method"} 2"#,
+            ]
+        );
+
+        // Buckets are cumulative.
+        for expected in [
+            r#"rpc_method_duration_seconds_bucket{method="a_method",le="0.001"} 0"#,
+            r#"rpc_method_duration_seconds_bucket{method="a_method",le="0.005"} 1"#,
+            r#"rpc_method_duration_seconds_bucket{method="a_method",le="10"} 1"#,
+            r#"rpc_method_duration_seconds_bucket{method="b_method",le="0.1"} 0"#,
+            r#"rpc_method_duration_seconds_bucket{method="b_method",le="0.25"} 1"#,
+            r#"rpc_method_duration_seconds_bucket{method="b_method",le="10"} 1"#,
+            r#"rpc_method_duration_seconds_bucket{method="b_method",le="+Inf"} 2"#,
+            r#"rpc_method_duration_seconds_sum{method="b_method"} 20.25"#,
+            r#"rpc_method_duration_seconds_count{method="b_method"} 2"#,
+        ] {
+            assert!(lines.contains(&expected), "{expected}");
+        }
+    }
+}
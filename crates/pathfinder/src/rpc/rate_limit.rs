@@ -0,0 +1,112 @@
+//! A node wide limit on the rate of RPC calls, which can be adjusted at runtime via the
+//! [admin API](super::admin).
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Methods which are served regardless of the limit, so that health checks keep working while
+/// the node is busy.
+pub const EXEMPT_METHODS: &[&str] = &["pathfinder_health"];
+
+/// Length of the windows over which calls are counted.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Limits the number of RPC calls served per second, counted over fixed one second windows.
+///
+/// There is no limit by default.
+#[derive(Debug)]
+pub struct RateLimiter(Mutex<State>);
+
+#[derive(Debug)]
+struct State {
+    limit: Option<NonZeroU32>,
+    window_start: Instant,
+    calls: u32,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self(Mutex::new(State {
+            limit: None,
+            window_start: Instant::now(),
+            calls: 0,
+        }))
+    }
+}
+
+impl RateLimiter {
+    /// The maximum number of calls per second, `None` if unlimited.
+    pub fn limit(&self) -> Option<NonZeroU32> {
+        self.0.lock().unwrap().limit
+    }
+
+    /// Replaces the limit, returning the previous one.
+    ///
+    /// Takes effect immediately, calls already counted in the current window count towards the
+    /// new limit.
+    pub fn set_limit(&self, limit: Option<NonZeroU32>) -> Option<NonZeroU32> {
+        std::mem::replace(&mut self.0.lock().unwrap().limit, limit)
+    }
+
+    /// Counts a call, returning false if it exceeds the limit and must be rejected.
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if now.duration_since(state.window_start) >= WINDOW {
+            state.window_start = now;
+            state.calls = 0;
+        }
+
+        match state.limit {
+            Some(limit) if state.calls >= limit.get() => false,
+            _ => {
+                state.calls = state.calls.saturating_add(1);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_by_default() {
+        let limiter = RateLimiter::default();
+        assert_eq!(limiter.limit(), None);
+        assert!((0..1000).all(|_| limiter.try_acquire()));
+    }
+
+    #[test]
+    fn limit_resets_with_each_window() {
+        let limiter = RateLimiter::default();
+        limiter.set_limit(NonZeroU32::new(2));
+        let start = limiter.0.lock().unwrap().window_start;
+
+        assert!(limiter.try_acquire_at(start));
+        assert!(limiter.try_acquire_at(start + WINDOW / 2));
+        assert!(!limiter.try_acquire_at(start + WINDOW / 2));
+
+        assert!(limiter.try_acquire_at(start + WINDOW));
+        assert!(limiter.try_acquire_at(start + WINDOW));
+        assert!(!limiter.try_acquire_at(start + WINDOW));
+    }
+
+    #[test]
+    fn new_limit_takes_effect_immediately() {
+        let limiter = RateLimiter::default();
+        let start = limiter.0.lock().unwrap().window_start;
+        assert!(limiter.try_acquire_at(start));
+        assert!(limiter.try_acquire_at(start));
+
+        assert_eq!(limiter.set_limit(NonZeroU32::new(2)), None);
+        assert!(!limiter.try_acquire_at(start));
+
+        assert_eq!(limiter.set_limit(None), NonZeroU32::new(2));
+        assert!(limiter.try_acquire_at(start));
+    }
+}
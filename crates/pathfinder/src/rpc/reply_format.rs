@@ -0,0 +1,208 @@
+//! Pathfinder extension parameters which control how a reply is serialized.
+//!
+//! Both field projection and pretty-printing are opt-in per request; without them the reply is
+//! serialized exactly as before.
+use std::collections::BTreeMap;
+
+use jsonrpsee::core::Error;
+use serde::Serialize;
+use serde_json::{value::RawValue, Value};
+
+/// How the reply of a request should be serialized.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplyFormat {
+    /// Dot-separated paths of the fields to keep, e.g. `transactions.txn_hash`.
+    ///
+    /// Arrays are traversed transparently, so a path applies to every element. Paths which do
+    /// not exist in the reply are ignored, which includes paths continuing into a scalar such
+    /// as `block_hash.x`: the scalar is not selected by them. `None` keeps every field.
+    pub fields: Option<Vec<String>>,
+    /// Indents the serialized reply.
+    pub pretty: bool,
+}
+
+impl ReplyFormat {
+    /// Serializes `reply` according to this format.
+    ///
+    /// The result is embedded verbatim in the JSON-RPC response, which is how the indentation
+    /// survives.
+    pub fn apply<R: Serialize>(&self, reply: &R) -> Result<Box<RawValue>, Error> {
+        let json = match &self.fields {
+            Some(fields) => {
+                let value = serde_json::to_value(reply).map_err(internal)?;
+                let value = Projection::new(fields).apply(value).unwrap_or(Value::Null);
+                self.to_string(&value)
+            }
+            None => self.to_string(reply),
+        }?;
+
+        RawValue::from_string(json).map_err(internal)
+    }
+
+    fn to_string<R: Serialize + ?Sized>(&self, reply: &R) -> Result<String, Error> {
+        match self.pretty {
+            true => serde_json::to_string_pretty(reply),
+            false => serde_json::to_string(reply),
+        }
+        .map_err(internal)
+    }
+}
+
+fn internal(e: serde_json::Error) -> Error {
+    Error::Custom(format!("Serializing reply: {}", e))
+}
+
+/// The requested field paths, as a tree keyed by path segment.
+#[derive(Debug, Default, PartialEq)]
+struct Projection {
+    /// A path ends here, so the whole value is selected.
+    all: bool,
+    children: BTreeMap<String, Projection>,
+}
+
+impl Projection {
+    fn new(paths: &[String]) -> Self {
+        let mut root = Projection::default();
+
+        for path in paths {
+            let node = path
+                .split('.')
+                .filter(|segment| !segment.is_empty())
+                .fold(&mut root, |node, segment| {
+                    node.children.entry(segment.to_owned()).or_default()
+                });
+            node.all = true;
+        }
+
+        root
+    }
+
+    /// Returns the selected part of `value`, or [None] if nothing in it is selected.
+    fn apply(&self, value: Value) -> Option<Value> {
+        if self.all {
+            return Some(value);
+        }
+
+        match value {
+            Value::Object(object) => Some(Value::Object(
+                object
+                    .into_iter()
+                    .filter_map(|(key, value)| {
+                        let child = self.children.get(&key)?;
+                        child.apply(value).map(|value| (key, value))
+                    })
+                    .collect(),
+            )),
+            Value::Array(array) => Some(Value::Array(
+                array
+                    .into_iter()
+                    .filter_map(|value| self.apply(value))
+                    .collect(),
+            )),
+            // There is nothing to project on a scalar, so a path which continues into one does
+            // not exist and does not select it.
+            _scalar => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn project(value: Value, fields: &[&str]) -> Value {
+        let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+        let format = ReplyFormat {
+            fields: Some(fields),
+            pretty: false,
+        };
+        serde_json::from_str(format.apply(&value).unwrap().get()).unwrap()
+    }
+
+    #[test]
+    fn nested_array_fields() {
+        let block = json!({
+            "block_hash": "0x1",
+            "block_number": 1,
+            "transactions": [
+                {"txn_hash": "0x10", "contract_address": "0x20", "calldata": ["0x1"]},
+                {"txn_hash": "0x11", "contract_address": "0x21", "calldata": []},
+            ],
+        });
+
+        assert_eq!(
+            project(block, &["block_hash", "transactions.txn_hash"]),
+            json!({
+                "block_hash": "0x1",
+                "transactions": [{"txn_hash": "0x10"}, {"txn_hash": "0x11"}],
+            })
+        );
+    }
+
+    #[test]
+    fn shorter_path_selects_everything_below() {
+        let value = json!({"a": {"b": 1, "c": 2}, "d": 3});
+
+        assert_eq!(
+            project(value.clone(), &["a.b", "a"]),
+            json!({"a": {"b": 1, "c": 2}})
+        );
+        assert_eq!(
+            project(value, &["a", "a.b"]),
+            json!({"a": {"b": 1, "c": 2}})
+        );
+    }
+
+    #[test]
+    fn unknown_paths_are_ignored() {
+        let value = json!({"a": {"b": 1}, "c": "0x1"});
+
+        assert_eq!(
+            project(value, &["a.b", "missing", "a.missing"]),
+            json!({"a": {"b": 1}})
+        );
+    }
+
+    #[test]
+    fn scalar_paths_are_dropped() {
+        let value = json!({
+            "block_hash": "0x1",
+            "block_number": 1,
+            "transactions": [{"txn_hash": "0x10", "calldata": ["0x1", "0x2"]}],
+        });
+
+        assert_eq!(
+            project(
+                value,
+                &[
+                    "block_hash.missing",
+                    "block_number",
+                    "transactions.calldata.missing"
+                ]
+            ),
+            json!({"block_number": 1, "transactions": [{"calldata": []}]})
+        );
+    }
+
+    #[test]
+    fn pretty_round_trips() {
+        let value = json!({
+            "block_hash": "0x1",
+            "transactions": [{"txn_hash": "0x10", "calldata": ["0x1", "0x2"]}],
+        });
+
+        let plain = ReplyFormat::default().apply(&value).unwrap();
+        let pretty = ReplyFormat {
+            fields: None,
+            pretty: true,
+        }
+        .apply(&value)
+        .unwrap();
+
+        assert!(!plain.get().contains('\n'));
+        assert!(pretty.get().contains("\n  "));
+        assert_eq!(serde_json::from_str::<Value>(pretty.get()).unwrap(), value);
+        assert_eq!(serde_json::from_str::<Value>(plain.get()).unwrap(), value);
+    }
+}
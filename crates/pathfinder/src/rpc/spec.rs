@@ -0,0 +1,130 @@
+//! Versions of the StarkNet JSON-RPC specification, and the methods which conform to them.
+//!
+//! Every method is registered along with the spec versions it conforms to. Methods which were
+//! renamed or reshaped between versions are mounted under each version's name, backed by the
+//! same [RpcApi](crate::rpc::api::RpcApi) handler and shaped per version, e.g.
+//! `starknet_getBlockByHash` (v0.1) and `starknet_getBlockWithTxHashes` (v0.2).
+use serde::{Deserialize, Serialize};
+
+/// A version of the StarkNet JSON-RPC specification.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum SpecVersion {
+    #[serde(rename = "0.1.0")]
+    V0_1,
+    #[serde(rename = "0.2.0")]
+    V0_2,
+}
+
+impl SpecVersion {
+    /// The version which the node implements in full, as reported by `starknet_specVersion`.
+    ///
+    /// Methods of newer versions are mounted as they are implemented.
+    pub const CURRENT: SpecVersion = SpecVersion::V0_1;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpecVersion::V0_1 => "0.1.0",
+            SpecVersion::V0_2 => "0.2.0",
+        }
+    }
+}
+
+/// Methods which conform to v0.1 only.
+pub(crate) const V0_1: &[SpecVersion] = &[SpecVersion::V0_1];
+/// Methods which conform to v0.2 only.
+pub(crate) const V0_2: &[SpecVersion] = &[SpecVersion::V0_2];
+/// Methods which are unchanged between v0.1 and v0.2.
+pub(crate) const V0_1_AND_V0_2: &[SpecVersion] = &[SpecVersion::V0_1, SpecVersion::V0_2];
+/// Pathfinder extensions, which are not part of any spec version.
+pub(crate) const EXTENSION: &[SpecVersion] = &[];
+
+/// A mounted RPC method, as listed by `pathfinder_rpcMethods`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct MethodInfo {
+    pub name: String,
+    /// Empty for Pathfinder extensions.
+    pub spec_versions: Vec<SpecVersion>,
+}
+
+/// Request and reply types which are specific to spec v0.2.
+pub mod v0_2 {
+    use serde::{Deserialize, Serialize};
+
+    use crate::core::{
+        GlobalRoot, SequencerAddress, StarknetBlockHash, StarknetBlockNumber,
+        StarknetBlockTimestamp,
+    };
+    use crate::rpc::types::{
+        reply::{self, BlockStatus, Transactions},
+        Tag,
+    };
+
+    /// Identifies a block by its hash, its number or a [Tag].
+    ///
+    /// Represented as `{"block_hash": "0x..."}`, `{"block_number": 1}`, `"latest"` or
+    /// `"pending"`.
+    #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(untagged)]
+    pub enum BlockId {
+        Hash { block_hash: StarknetBlockHash },
+        Number { block_number: StarknetBlockNumber },
+        Tag(Tag),
+    }
+
+    /// L2 Block in the shape of spec v0.2.
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct Block {
+        pub status: BlockStatus,
+        pub block_hash: Option<StarknetBlockHash>,
+        pub parent_hash: StarknetBlockHash,
+        pub block_number: Option<StarknetBlockNumber>,
+        pub new_root: Option<GlobalRoot>,
+        pub timestamp: StarknetBlockTimestamp,
+        pub sequencer_address: SequencerAddress,
+        pub transactions: Transactions,
+    }
+
+    impl From<reply::Block> for Block {
+        fn from(block: reply::Block) -> Self {
+            Self {
+                status: block.status,
+                block_hash: block.block_hash,
+                parent_hash: block.parent_hash,
+                block_number: block.block_number,
+                new_root: block.new_root,
+                timestamp: block.accepted_time,
+                sequencer_address: block.sequencer,
+                transactions: block.transactions,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use stark_hash::StarkHash;
+
+        #[test]
+        fn block_id() {
+            let hash = StarknetBlockHash(StarkHash::from_hex_str("0xabc").unwrap());
+
+            for (json, expected) in [
+                (
+                    r#"{"block_hash": "0xabc"}"#,
+                    BlockId::Hash { block_hash: hash },
+                ),
+                (
+                    r#"{"block_number": 5}"#,
+                    BlockId::Number {
+                        block_number: StarknetBlockNumber(5),
+                    },
+                ),
+                (r#""latest""#, BlockId::Tag(Tag::Latest)),
+                (r#""pending""#, BlockId::Tag(Tag::Pending)),
+            ] {
+                assert_eq!(serde_json::from_str::<BlockId>(json).unwrap(), expected);
+            }
+        }
+    }
+}
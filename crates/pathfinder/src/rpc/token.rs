@@ -0,0 +1,526 @@
+//! ERC-20 token balances, queried by calling the token contract's `balanceOf`.
+//!
+//! The token's decimals, symbol and name are queried once and cached in the
+//! [TokenMetadataTable], keyed by the class hash of the token contract so that the entry is
+//! refreshed if the contract is upgraded.
+
+use anyhow::Context;
+use jsonrpsee::{
+    core::{error::Error, RpcResult},
+    types::{error::CallError, ErrorObject},
+};
+use stark_hash::StarkHash;
+use web3::types::U256;
+
+use crate::{
+    core::{CallParam, CallResultValue, ContractAddress, EntryPoint},
+    rpc::{
+        api::internal_server_error,
+        types::{reply::ErrorCode, reply::TokenBalance, request::Call, BlockHashOrTag},
+    },
+    storage::{ContractsTable, Storage, TokenMetadata, TokenMetadataTable},
+};
+
+/// Reasons for which a contract call made by [balance] failed.
+#[derive(Debug)]
+pub enum ExecutionError {
+    /// The contract reverted, e.g. because it does not implement the called function.
+    Reverted(String),
+    Other(Error),
+}
+
+/// Executes the contract calls made by [balance].
+#[async_trait::async_trait]
+pub trait CallExecutor: Send + Sync {
+    async fn execute(
+        &self,
+        request: Call,
+        block: BlockHashOrTag,
+    ) -> Result<Vec<CallResultValue>, ExecutionError>;
+}
+
+/// Returns the balance of `account` in `token` at `block`.
+pub async fn balance<E: CallExecutor>(
+    executor: &E,
+    storage: &Storage,
+    token: ContractAddress,
+    account: ContractAddress,
+    block: BlockHashOrTag,
+) -> RpcResult<TokenBalance> {
+    let result = call(
+        executor,
+        token,
+        "balanceOf",
+        vec![CallParam(account.0)],
+        block,
+    )
+    .await?;
+    let balance = match result.as_slice() {
+        [low, high] => uint256(*low, *high).ok_or_else(|| {
+            contract_error("balanceOf returned a Uint256 member exceeding 128 bits")
+        })?,
+        _ => return Err(unexpected_result("balanceOf", &result)),
+    };
+
+    let (decimals, symbol, name) = metadata(executor, storage, token, block).await?;
+
+    Ok(TokenBalance {
+        balance: balance.to_string(),
+        formatted: format_amount(balance, decimals),
+        decimals,
+        symbol,
+        name,
+    })
+}
+
+/// Returns the decimals, symbol and name of `token` from the cache, querying the token contract
+/// on a cache miss or if the contract's class hash has changed since the metadata was cached.
+async fn metadata<E: CallExecutor>(
+    executor: &E,
+    storage: &Storage,
+    token: ContractAddress,
+    block: BlockHashOrTag,
+) -> RpcResult<(u8, String, String)> {
+    let cached = {
+        let storage = storage.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut db = storage
+                .connection()
+                .context("Opening database connection")?;
+            let tx = db.transaction().context("Creating database transaction")?;
+
+            let class_hash =
+                ContractsTable::get_hash(&tx, token).context("Reading contract class hash")?;
+            let metadata =
+                TokenMetadataTable::get(&tx, token).context("Reading cached token metadata")?;
+
+            Ok::<_, anyhow::Error>((class_hash, metadata))
+        })
+    };
+    let (class_hash, cached) = cached
+        .await
+        .context("Database read panic or shutting down")
+        .and_then(|x| x)
+        .map_err(internal_server_error)?;
+
+    if let Some(cached) = cached.filter(|cached| Some(cached.class_hash) == class_hash) {
+        return Ok((cached.decimals, cached.symbol, cached.name));
+    }
+
+    let decimals = single_felt(executor, token, "decimals", block).await?;
+    let decimals = decimals.to_be_bytes();
+    let decimals = match decimals[..31].iter().all(|b| *b == 0) {
+        true => decimals[31],
+        false => return Err(contract_error("decimals does not fit into a u8")),
+    };
+    let symbol = short_string(single_felt(executor, token, "symbol", block).await?);
+    let name = short_string(single_felt(executor, token, "name", block).await?);
+
+    // Contracts which aren't synced yet, e.g. deployed in the pending block, can't be cached as
+    // their class hash is unknown. Read-only nodes can't write the cache at all.
+    if let Some(class_hash) = class_hash.filter(|_| !storage.is_read_only()) {
+        let metadata = TokenMetadata {
+            class_hash,
+            decimals,
+            symbol: symbol.clone(),
+            name: name.clone(),
+        };
+
+        let storage = storage.clone();
+        tokio::task::spawn_blocking(move || {
+            let db = storage
+                .connection()
+                .context("Opening database connection")?;
+            TokenMetadataTable::upsert(&db, token, &metadata).context("Caching token metadata")
+        })
+        .await
+        .context("Database write panic or shutting down")
+        .and_then(|x| x)
+        .map_err(internal_server_error)?;
+    }
+
+    Ok((decimals, symbol, name))
+}
+
+async fn call<E: CallExecutor>(
+    executor: &E,
+    token: ContractAddress,
+    function: &str,
+    calldata: Vec<CallParam>,
+    block: BlockHashOrTag,
+) -> RpcResult<Vec<CallResultValue>> {
+    let request = Call {
+        contract_address: token,
+        calldata,
+        entry_point_selector: EntryPoint::hashed(function.as_bytes()),
+    };
+
+    executor.execute(request, block).await.map_err(|e| match e {
+        ExecutionError::Reverted(reason) => contract_error(&reason),
+        ExecutionError::Other(e) => e,
+    })
+}
+
+async fn single_felt<E: CallExecutor>(
+    executor: &E,
+    token: ContractAddress,
+    function: &str,
+    block: BlockHashOrTag,
+) -> RpcResult<StarkHash> {
+    let result = call(executor, token, function, vec![], block).await?;
+    match result.as_slice() {
+        [value] => Ok(value.0),
+        _ => Err(unexpected_result(function, &result)),
+    }
+}
+
+/// Combines the members of a Uint256, or returns `None` if either exceeds 128 bits.
+fn uint256(low: CallResultValue, high: CallResultValue) -> Option<U256> {
+    let (low, high) = (low.0.to_be_bytes(), high.0.to_be_bytes());
+    if low[..16].iter().chain(&high[..16]).any(|b| *b != 0) {
+        return None;
+    }
+
+    let mut bytes = [0u8; 32];
+    bytes[..16].copy_from_slice(&high[16..]);
+    bytes[16..].copy_from_slice(&low[16..]);
+
+    Some(U256::from_big_endian(&bytes))
+}
+
+/// Decodes a Cairo short string, which is ASCII encoded as a big-endian field element.
+fn short_string(felt: StarkHash) -> String {
+    let bytes = felt.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[start..]).into_owned()
+}
+
+/// Formats `amount` of the token's smallest unit as a decimal number of whole tokens, without
+/// trailing zeros.
+fn format_amount(amount: U256, decimals: u8) -> String {
+    let digits = amount.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+
+    match fraction.is_empty() {
+        true => whole.to_owned(),
+        false => format!("{}.{}", whole, fraction),
+    }
+}
+
+fn unexpected_result(function: &str, result: &[CallResultValue]) -> Error {
+    contract_error(&format!(
+        "{} returned {} values, which is not ERC-20 compliant",
+        function,
+        result.len()
+    ))
+}
+
+fn contract_error(reason: &str) -> Error {
+    Error::Call(CallError::Custom(ErrorObject::owned(
+        ErrorCode::ContractError as i32,
+        ErrorCode::ContractError.to_string(),
+        Some(serde_json::json!({ "revert_error": reason })),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ClassHash;
+    use crate::rpc::types::Tag;
+    use crate::storage::ContractCodeTable;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    fn felt(hex: &str) -> StarkHash {
+        StarkHash::from_hex_str(hex).unwrap()
+    }
+
+    const LATEST: BlockHashOrTag = BlockHashOrTag::Tag(Tag::Latest);
+
+    /// Returns fixed results per function and counts the calls made.
+    #[derive(Default)]
+    struct StubExecutor {
+        results: HashMap<StarkHash, Result<Vec<StarkHash>, String>>,
+        calls: Mutex<HashMap<StarkHash, usize>>,
+    }
+
+    impl StubExecutor {
+        fn erc20(balance_low: StarkHash, balance_high: StarkHash) -> Self {
+            Self::default()
+                .with("balanceOf", Ok(vec![balance_low, balance_high]))
+                .with("decimals", Ok(vec![felt("0x12")]))
+                .with(
+                    "symbol",
+                    Ok(vec![StarkHash::from_be_slice(b"ETH").unwrap()]),
+                )
+                .with(
+                    "name",
+                    Ok(vec![StarkHash::from_be_slice(b"Ether").unwrap()]),
+                )
+        }
+
+        fn with(mut self, function: &str, result: Result<Vec<StarkHash>, String>) -> Self {
+            self.results
+                .insert(EntryPoint::hashed(function.as_bytes()).0, result);
+            self
+        }
+
+        fn calls(&self, function: &str) -> usize {
+            let calls = self.calls.lock().unwrap();
+            calls
+                .get(&EntryPoint::hashed(function.as_bytes()).0)
+                .copied()
+                .unwrap_or_default()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CallExecutor for StubExecutor {
+        async fn execute(
+            &self,
+            request: Call,
+            _: BlockHashOrTag,
+        ) -> Result<Vec<CallResultValue>, ExecutionError> {
+            *self
+                .calls
+                .lock()
+                .unwrap()
+                .entry(request.entry_point_selector.0)
+                .or_default() += 1;
+
+            match self.results.get(&request.entry_point_selector.0) {
+                Some(Ok(result)) => Ok(result.iter().copied().map(CallResultValue).collect()),
+                Some(Err(reason)) => Err(ExecutionError::Reverted(reason.clone())),
+                None => Err(ExecutionError::Reverted("Entry point not found".to_owned())),
+            }
+        }
+    }
+
+    /// Storage in which `token` is deployed with `class_hash`.
+    fn storage_with_token(token: ContractAddress, class_hash: ClassHash) -> Storage {
+        let storage = Storage::in_memory().unwrap();
+        deploy(&storage, token, class_hash);
+        storage
+    }
+
+    fn deploy(storage: &Storage, token: ContractAddress, class_hash: ClassHash) {
+        let mut connection = storage.connection().unwrap();
+        let tx = connection.transaction().unwrap();
+
+        // The zstd magic bytes, as compression is asserted for internally.
+        let zstd_magic = vec![0x28, 0xb5, 0x2f, 0xfd];
+        let code = crate::state::CompressedContract {
+            abi: zstd_magic.clone(),
+            bytecode: zstd_magic.clone(),
+            definition: zstd_magic,
+            hash: class_hash,
+        };
+        ContractCodeTable::insert_compressed(&tx, &code).unwrap();
+        ContractsTable::upsert(&tx, token, class_hash).unwrap();
+
+        tx.commit().unwrap();
+    }
+
+    #[tokio::test]
+    async fn uint256_members_are_recombined() {
+        let token = ContractAddress(felt("0x70ce"));
+        let storage = storage_with_token(token, ClassHash(felt("0xc1a55")));
+        // 2^128 + 1
+        let executor = StubExecutor::erc20(felt("0x1"), felt("0x1"));
+
+        let result = balance(
+            &executor,
+            &storage,
+            token,
+            ContractAddress(felt("0xacc")),
+            LATEST,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            result,
+            TokenBalance {
+                balance: "340282366920938463463374607431768211457".to_owned(),
+                formatted: "340282366920938463463.374607431768211457".to_owned(),
+                decimals: 18,
+                symbol: "ETH".to_owned(),
+                name: "Ether".to_owned(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn uint256_member_exceeding_128_bits() {
+        let token = ContractAddress(felt("0x70ce"));
+        let storage = storage_with_token(token, ClassHash(felt("0xc1a55")));
+        let executor =
+            StubExecutor::erc20(felt("0x100000000000000000000000000000000"), StarkHash::ZERO);
+
+        let error = balance(
+            &executor,
+            &storage,
+            token,
+            ContractAddress(felt("0xacc")),
+            LATEST,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(ErrorCode::ContractError, error);
+    }
+
+    #[test]
+    fn amount_formatting() {
+        let cases = [
+            (0u64, 18u8, "0"),
+            (1, 18, "0.000000000000000001"),
+            (1_500_000_000_000_000_000, 18, "1.5"),
+            (2_000_000, 6, "2"),
+            (1_234_567, 6, "1.234567"),
+            (42, 0, "42"),
+        ];
+
+        for (amount, decimals, expected) in cases {
+            assert_eq!(
+                format_amount(U256::from(amount), decimals),
+                expected,
+                "{} with {} decimals",
+                amount,
+                decimals
+            );
+        }
+    }
+
+    #[test]
+    fn short_strings() {
+        assert_eq!(
+            short_string(StarkHash::from_be_slice(b"ETH").unwrap()),
+            "ETH"
+        );
+        assert_eq!(short_string(StarkHash::ZERO), "");
+    }
+
+    #[tokio::test]
+    async fn metadata_is_queried_once() {
+        let token = ContractAddress(felt("0x70ce"));
+        let storage = storage_with_token(token, ClassHash(felt("0xc1a55")));
+        let executor = StubExecutor::erc20(felt("0x2a"), StarkHash::ZERO);
+
+        for account in ["0xacc1", "0xacc2", "0xacc1"] {
+            balance(
+                &executor,
+                &storage,
+                token,
+                ContractAddress(felt(account)),
+                LATEST,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(executor.calls("balanceOf"), 3);
+        assert_eq!(executor.calls("decimals"), 1);
+        assert_eq!(executor.calls("symbol"), 1);
+        assert_eq!(executor.calls("name"), 1);
+    }
+
+    #[tokio::test]
+    async fn metadata_is_requeried_after_upgrade() {
+        let token = ContractAddress(felt("0x70ce"));
+        let storage = storage_with_token(token, ClassHash(felt("0xc1a55")));
+        let executor = StubExecutor::erc20(felt("0x2a"), StarkHash::ZERO);
+        let account = ContractAddress(felt("0xacc"));
+
+        balance(&executor, &storage, token, account, LATEST)
+            .await
+            .unwrap();
+        deploy(&storage, token, ClassHash(felt("0xc1a56")));
+        balance(&executor, &storage, token, account, LATEST)
+            .await
+            .unwrap();
+
+        assert_eq!(executor.calls("decimals"), 2);
+
+        let connection = storage.connection().unwrap();
+        let cached = TokenMetadataTable::get(&connection, token)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.class_hash, ClassHash(felt("0xc1a56")));
+    }
+
+    #[tokio::test]
+    async fn unknown_contract_is_not_cached() {
+        let token = ContractAddress(felt("0x70ce"));
+        let storage = Storage::in_memory().unwrap();
+        let executor = StubExecutor::erc20(felt("0x2a"), StarkHash::ZERO);
+        let account = ContractAddress(felt("0xacc"));
+
+        for _ in 0..2 {
+            balance(&executor, &storage, token, account, LATEST)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(executor.calls("decimals"), 2);
+    }
+
+    #[tokio::test]
+    async fn read_only_storage_is_not_cached() {
+        let token = ContractAddress(felt("0x70ce"));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.sqlite");
+        let writer = Storage::migrate(path.clone()).unwrap();
+        deploy(&writer, token, ClassHash(felt("0xc1a55")));
+
+        let storage = Storage::open_read_only(
+            path,
+            crate::storage::DEFAULT_STATEMENT_CACHE_CAPACITY,
+            crate::storage::StorageLimits::default(),
+        )
+        .unwrap();
+        let executor = StubExecutor::erc20(felt("0x2a"), StarkHash::ZERO);
+        let account = ContractAddress(felt("0xacc"));
+
+        for _ in 0..2 {
+            balance(&executor, &storage, token, account, LATEST)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(executor.calls("decimals"), 2);
+    }
+
+    #[tokio::test]
+    async fn non_erc20_contract() {
+        let token = ContractAddress(felt("0x70ce"));
+        let storage = storage_with_token(token, ClassHash(felt("0xc1a55")));
+        let executor = StubExecutor::default();
+
+        let error = balance(
+            &executor,
+            &storage,
+            token,
+            ContractAddress(felt("0xacc")),
+            LATEST,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(ErrorCode::ContractError, error);
+        match error {
+            Error::Call(CallError::Custom(error)) => {
+                let data = error.data().unwrap().get();
+                assert!(data.contains("Entry point not found"), "{}", data);
+            }
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 //! Data structures used by the JSON-RPC API methods.
 use crate::core::{StarknetBlockHash, StarknetBlockNumber};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 /// Special tag used when specifying the `latest` or `pending` block.
 #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -22,15 +23,23 @@ pub enum Tag {
     Pending,
 }
 
-impl std::fmt::Display for Tag {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Tag {
+    /// The JSON string representing this tag, which is also what the feeder gateway expects
+    /// as the `blockNumber` query parameter.
+    pub const fn as_str(&self) -> &'static str {
         match self {
-            Tag::Latest => f.write_str("latest"),
-            Tag::Pending => f.write_str("pending"),
+            Tag::Latest => "latest",
+            Tag::Pending => "pending",
         }
     }
 }
 
+impl std::fmt::Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// A wrapper that contains either a [Hash](self::BlockHashOrTag::Hash) or a [Tag](self::BlockHashOrTag::Tag).
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
@@ -46,6 +55,20 @@ pub enum BlockHashOrTag {
     Tag(Tag),
 }
 
+impl BlockHashOrTag {
+    /// The feeder gateway query parameter selecting this block.
+    ///
+    /// The gateway only accepts tags as a `blockNumber`, so tags are always sent as
+    /// `blockNumber=<tag>`. Omitting the parameter (or sending `blockNumber=null`) would also
+    /// select the latest block, but we always spell out the tag to keep requests unambiguous.
+    pub fn to_gateway_query(self) -> (&'static str, Cow<'static, str>) {
+        match self {
+            BlockHashOrTag::Hash(StarknetBlockHash(h)) => ("blockHash", h.to_hex_str()),
+            BlockHashOrTag::Tag(t) => ("blockNumber", Cow::Borrowed(t.as_str())),
+        }
+    }
+}
+
 impl std::fmt::Display for BlockHashOrTag {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -64,6 +87,34 @@ pub enum BlockNumberOrTag {
     Number(StarknetBlockNumber),
     /// Special [Tag](crate::rpc::types::Tag) describing a block
     Tag(Tag),
+    /// Pathfinder extension, see [RelativeBlockNumber].
+    Relative(RelativeBlockNumber),
+}
+
+/// A block identified by its offset from the latest block, as in `{"relative": -10}`.
+///
+/// This is a pathfinder extension which the feeder gateway does not support. Offsets
+/// are resolved against storage, and positive ones never identify a block.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RelativeBlockNumber {
+    pub relative: i64,
+}
+
+impl BlockNumberOrTag {
+    /// The feeder gateway query parameter selecting this block, `None` for a
+    /// [Relative](BlockNumberOrTag::Relative) block which the gateway cannot resolve.
+    ///
+    /// See [BlockHashOrTag::to_gateway_query] for how tags are encoded.
+    pub fn to_gateway_query(self) -> Option<(&'static str, Cow<'static, str>)> {
+        match self {
+            BlockNumberOrTag::Number(StarknetBlockNumber(n)) => {
+                Some(("blockNumber", Cow::Owned(n.to_string())))
+            }
+            BlockNumberOrTag::Tag(t) => Some(("blockNumber", Cow::Borrowed(t.as_str()))),
+            BlockNumberOrTag::Relative(_) => None,
+        }
+    }
 }
 
 impl std::fmt::Display for BlockNumberOrTag {
@@ -71,6 +122,9 @@ impl std::fmt::Display for BlockNumberOrTag {
         match self {
             BlockNumberOrTag::Number(StarknetBlockNumber(n)) => std::fmt::Display::fmt(n, f),
             BlockNumberOrTag::Tag(t) => std::fmt::Display::fmt(t, f),
+            BlockNumberOrTag::Relative(RelativeBlockNumber { relative }) => {
+                write!(f, "latest{:+}", relative)
+            }
         }
     }
 }
@@ -78,11 +132,15 @@ impl std::fmt::Display for BlockNumberOrTag {
 /// Groups all strictly input types of the RPC API.
 pub mod request {
     use crate::{
-        core::{CallParam, ContractAddress, EntryPoint, EventKey, StarknetBlockNumber},
-        rpc::serde::H256AsNoLeadingZerosHexStr,
+        core::{
+            CallParam, ContractAddress, EntryPoint, EthereumAddress, EventKey, StarknetBlockHash,
+            StarknetBlockNumber,
+        },
+        serde::{EthereumAddressAsHexStr, H256AsNoLeadingZerosHexStr},
     };
     use serde::{Deserialize, Serialize};
     use serde_with::serde_as;
+    use stark_hash::StarkHash;
     use web3::types::H256;
 
     /// The address of a storage element for a StarkNet contract.
@@ -102,6 +160,36 @@ pub mod request {
         pub entry_point_selector: EntryPoint,
     }
 
+    /// Contains the L1 to L2 message passed to `pathfinder_estimateMessageFee`.
+    #[serde_as]
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct MessageFromL1 {
+        #[serde_as(as = "EthereumAddressAsHexStr")]
+        pub from_address: EthereumAddress,
+        pub to_address: ContractAddress,
+        pub entry_point_selector: EntryPoint,
+        pub payload: Vec<CallParam>,
+    }
+
+    impl MessageFromL1 {
+        /// The invocation of the L1 handler consuming this message, the calldata of which is the
+        /// L1 sender followed by the payload.
+        pub fn into_call(self) -> Call {
+            let from_address = StarkHash::from_be_slice(self.from_address.0.as_bytes())
+                .expect("Ethereum address fits into a field element");
+            let calldata = std::iter::once(CallParam(from_address))
+                .chain(self.payload)
+                .collect();
+
+            Call {
+                contract_address: self.to_address,
+                calldata,
+                entry_point_selector: self.entry_point_selector,
+            }
+        }
+    }
+
     /// Determines the type of response to block related queries.
     #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
     #[serde(deny_unknown_fields)]
@@ -137,6 +225,24 @@ pub mod request {
         // don't work together.
         pub page_size: usize,
         pub page_number: usize,
+        /// The [chain head](super::reply::EventsChainHead) returned with a previous page.
+        ///
+        /// The query is rejected with [Reconsider](super::reply::ErrorCode::Reconsider) if
+        /// this block has since been reorged away.
+        #[serde(default)]
+        pub chain_head: Option<StarknetBlockHash>,
+        /// Whether events of reverted transactions are returned, which they are by default.
+        #[serde(default)]
+        pub include_reverted: Option<bool>,
+    }
+
+    /// Contains optional parameters passed to `pathfinder_diffState`.
+    #[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DiffStateOptions {
+        /// The maximum number of deployed contracts and storage changes to return.
+        #[serde(default)]
+        pub max_results: Option<usize>,
     }
 }
 
@@ -146,13 +252,16 @@ pub mod reply {
     use super::request::BlockResponseScope;
     use crate::{
         core::{
-            CallParam, ClassHash, ContractAddress, EntryPoint, EventData, EventKey, GasPrice,
-            GlobalRoot, SequencerAddress, StarknetBlockHash, StarknetBlockNumber,
-            StarknetBlockTimestamp, StarknetTransactionHash,
+            CallParam, ClassHash, ConstructorParam, ContractAddress, ContractAddressSalt,
+            EntryPoint, EventData, EventKey, GasPrice, GlobalRoot, SequencerAddress,
+            StarknetBlockHash, StarknetBlockNumber, StarknetBlockTimestamp,
+            StarknetTransactionHash, StorageAddress, StorageValue,
         },
-        rpc::{api::RawBlock, serde::GasPriceAsHexStr},
+        rpc::api::RawBlock,
         sequencer::reply as seq,
+        sequencer::reply::transaction::ExecutionStatus,
         sequencer::reply::Status as SeqStatus,
+        serde::GasPriceAsHexStr,
     };
     use serde::{Deserialize, Serialize};
     use serde_with::serde_as;
@@ -290,11 +399,16 @@ pub mod reply {
                                         contract_address: t.contract_address,
                                         entry_point_selector: t.entry_point_selector,
                                         calldata: t.calldata,
+                                        class_hash: t.class_hash,
+                                        contract_address_salt: t.contract_address_salt,
+                                        constructor_calldata: t.constructor_calldata,
                                         status: r.status,
                                         status_data: r.status_data,
+                                        execution_status: r.execution_status,
                                         messages_sent: r.messages_sent,
                                         l1_origin_message: r.l1_origin_message,
                                         events: r.events,
+                                        placeholder: r.placeholder,
                                     }
                                 })
                                 .collect(),
@@ -322,6 +436,20 @@ pub mod reply {
         InvalidTransactionIndex = 27,
         InvalidContractClassHash = 28,
         PageSizeTooBig = 31,
+        /// The chain head a paginated query started from has been reorged away, so pagination
+        /// has to be restarted.
+        Reconsider = 32,
+        /// The state of the requested block has not been built yet, as the block was
+        /// [backfilled](crate::state::block_backfill) ahead of the forward sync.
+        NodeNotReady = 33,
+        /// The node's [rate limit](crate::rpc::rate_limit::RateLimiter) was exceeded.
+        RateLimited = 34,
+        /// The contract's class definition has not been downloaded yet, and is retried in the
+        /// background.
+        ClassNotAvailable = 35,
+        /// The state of the requested block was released by
+        /// [gc_trees](crate::state::tree_gc::gc_trees).
+        StatePruned = 36,
         ContractError = 40,
         InvalidContractDefinition = 50,
     }
@@ -330,6 +458,9 @@ pub mod reply {
     /// be used when expecting that the rpc result is an error. The rpc result should first be
     /// accessed with [`Result::unwrap_err`], then compared to the expected [`ErrorCode`] with
     /// [`assert_eq!`].
+    ///
+    /// Errors returned by the server carry a request id, so they have to be received with
+    /// [test_utils::client](crate::rpc::test_utils::client), which checks and removes it.
     #[cfg(test)]
     impl PartialEq<jsonrpsee::core::error::Error> for ErrorCode {
         fn eq(&self, other: &jsonrpsee::core::error::Error) -> bool {
@@ -378,6 +509,11 @@ pub mod reply {
                 27 => InvalidTransactionIndex,
                 28 => InvalidContractClassHash,
                 31 => PageSizeTooBig,
+                32 => Reconsider,
+                33 => NodeNotReady,
+                34 => RateLimited,
+                35 => ClassNotAvailable,
+                36 => StatePruned,
                 40 => ContractError,
                 50 => InvalidContractDefinition,
                 x => return Err(x),
@@ -402,6 +538,17 @@ pub mod reply {
                     "The supplied contract class hash is invalid or unknown"
                 }
                 ErrorCode::PageSizeTooBig => "Requested page size is too big",
+                ErrorCode::Reconsider => {
+                    "The chain was reorganized since the first page, restart pagination"
+                }
+                ErrorCode::NodeNotReady => {
+                    "The state of the requested block is not available yet, try again later"
+                }
+                ErrorCode::RateLimited => "Too many requests, try again later",
+                ErrorCode::ClassNotAvailable => {
+                    "The contract's class is not available yet, try again later"
+                }
+                ErrorCode::StatePruned => "The state of the requested block has been pruned",
                 ErrorCode::ContractError => "Contract error",
                 ErrorCode::InvalidContractDefinition => "Invalid contract definition",
             }
@@ -485,6 +632,15 @@ pub mod reply {
         pub entry_point_selector: Option<EntryPoint>,
         /// Absent for "deploy" transactions
         pub calldata: Option<Vec<CallParam>>,
+        /// Only present for "deploy" and "declare" transactions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub class_hash: Option<ClassHash>,
+        /// Only present for "deploy" transactions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub contract_address_salt: Option<ContractAddressSalt>,
+        /// Only present for "deploy" transactions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub constructor_calldata: Option<Vec<ConstructorParam>>,
     }
 
     impl TryFrom<seq::Transaction> for Transaction {
@@ -494,12 +650,7 @@ pub mod reply {
             let txn = txn
                 .transaction
                 .ok_or_else(|| anyhow::anyhow!("Transaction not found."))?;
-            Ok(Self {
-                txn_hash: txn.transaction_hash,
-                contract_address: txn.contract_address,
-                entry_point_selector: txn.entry_point_selector,
-                calldata: txn.calldata,
-            })
+            Ok(txn.into())
         }
     }
 
@@ -510,6 +661,9 @@ pub mod reply {
                 contract_address: txn.contract_address,
                 entry_point_selector: txn.entry_point_selector,
                 calldata: txn.calldata,
+                class_hash: txn.class_hash,
+                contract_address_salt: txn.contract_address_salt,
+                constructor_calldata: txn.constructor_calldata,
             }
         }
     }
@@ -520,9 +674,17 @@ pub mod reply {
         pub txn_hash: StarknetTransactionHash,
         pub status: TransactionStatus,
         pub status_data: String,
+        /// Whether the transaction succeeded or was reverted, if reported by the sequencer.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub execution_status: Option<ExecutionStatus>,
         pub messages_sent: Vec<transaction_receipt::MessageToL1>,
         pub l1_origin_message: Option<transaction_receipt::MessageToL2>,
         pub events: Vec<transaction_receipt::Event>,
+        /// Set if the gateway did not report the receipt of the transaction, see
+        /// [Receipt::placeholder](seq::transaction::Receipt::placeholder). Its events and
+        /// messages are then unknown rather than empty.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub placeholder: bool,
     }
 
     impl TransactionReceipt {
@@ -532,6 +694,7 @@ pub mod reply {
                 status: status.into(),
                 // TODO at the moment not available in sequencer replies
                 status_data: String::new(),
+                execution_status: receipt.execution_status,
                 messages_sent: receipt
                     .l2_to_l1_messages
                     .into_iter()
@@ -545,6 +708,7 @@ pub mod reply {
                     .into_iter()
                     .map(transaction_receipt::Event::from)
                     .collect(),
+                placeholder: receipt.placeholder,
             }
         }
     }
@@ -556,8 +720,8 @@ pub mod reply {
                 ContractAddress, EthereumAddress, EventData, EventKey, L1ToL2MessagePayloadElem,
                 L2ToL1MessagePayloadElem,
             },
-            rpc::serde::EthereumAddressAsHexStr,
             sequencer::reply::transaction::{L1ToL2Message, L2ToL1Message},
+            serde::EthereumAddressAsHexStr,
         };
         use serde::{Deserialize, Serialize};
         use serde_with::serde_as;
@@ -568,14 +732,16 @@ pub mod reply {
         #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
         #[serde(deny_unknown_fields)]
         pub struct MessageToL1 {
+            pub from_address: ContractAddress,
             #[serde_as(as = "EthereumAddressAsHexStr")]
-            to_address: EthereumAddress,
-            payload: Vec<L2ToL1MessagePayloadElem>,
+            pub to_address: EthereumAddress,
+            pub payload: Vec<L2ToL1MessagePayloadElem>,
         }
 
         impl From<L2ToL1Message> for MessageToL1 {
             fn from(msg: L2ToL1Message) -> Self {
                 Self {
+                    from_address: msg.from_address,
                     to_address: msg.to_address,
                     payload: msg.payload,
                 }
@@ -588,8 +754,8 @@ pub mod reply {
         #[serde(deny_unknown_fields)]
         pub struct MessageToL2 {
             #[serde_as(as = "EthereumAddressAsHexStr")]
-            from_address: EthereumAddress,
-            payload: Vec<L1ToL2MessagePayloadElem>,
+            pub from_address: EthereumAddress,
+            pub payload: Vec<L1ToL2MessagePayloadElem>,
         }
 
         impl From<L1ToL2Message> for MessageToL2 {
@@ -632,11 +798,26 @@ pub mod reply {
         pub entry_point_selector: Option<EntryPoint>,
         /// Absent in "deploy" transaction
         pub calldata: Option<Vec<CallParam>>,
+        /// Only present in "deploy" and "declare" transactions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub class_hash: Option<ClassHash>,
+        /// Only present in "deploy" transaction
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub contract_address_salt: Option<ContractAddressSalt>,
+        /// Only present in "deploy" transaction
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub constructor_calldata: Option<Vec<ConstructorParam>>,
         pub status: TransactionStatus,
         pub status_data: String,
+        /// Whether the transaction succeeded or was reverted, if reported by the sequencer.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub execution_status: Option<ExecutionStatus>,
         pub messages_sent: Vec<transaction_receipt::MessageToL1>,
         pub l1_origin_message: Option<transaction_receipt::MessageToL2>,
         pub events: Vec<transaction_receipt::Event>,
+        /// See [TransactionReceipt::placeholder].
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub placeholder: bool,
     }
 
     /// Represents transaction status.
@@ -709,7 +890,7 @@ pub mod reply {
     pub mod syncing {
         use crate::{
             core::{StarknetBlockHash, StarknetBlockNumber},
-            rpc::serde::StarknetBlockNumberAsHexStr,
+            serde::StarknetBlockNumberAsHexStr,
         };
         use serde::Serialize;
         use serde_with::serde_as;
@@ -724,6 +905,14 @@ pub mod reply {
             pub current: NumberedBlock,
             #[serde(flatten, with = "prefix_highest")]
             pub highest: NumberedBlock,
+            /// The number of L1 state update logs which are not yet confirmed by enough L1 blocks
+            /// to be stored. Omitted while there are none.
+            #[serde(default, skip_serializing_if = "is_zero")]
+            pub l1_pending_confirmations: u64,
+        }
+
+        fn is_zero(value: &u64) -> bool {
+            *value == 0
         }
 
         serde_with::with_prefix!(prefix_starting "starting_");
@@ -734,8 +923,8 @@ pub mod reply {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 write!(
                     f,
-                    "starting: {:?}, current: {:?}, highest: {:?}",
-                    self.starting, self.current, self.highest,
+                    "starting: {:?}, current: {:?}, highest: {:?}, l1 pending confirmations: {}",
+                    self.starting, self.current, self.highest, self.l1_pending_confirmations,
                 )
             }
         }
@@ -791,6 +980,17 @@ pub mod reply {
                     starting: NumberedBlock::from(("a", 1)),
                     current: NumberedBlock::from(("b", 2)),
                     highest: NumberedBlock::from(("c", 3)),
+                    l1_pending_confirmations: 0,
+                }),
+            ),
+            (
+                line!(),
+                r#"{"starting_block_hash":"0xa","starting_block_num":"0x1","current_block_hash":"0xb","current_block_num":"0x2","highest_block_hash":"0xc","highest_block_num":"0x3","l1_pending_confirmations":4}"#,
+                Syncing::Status(syncing::Status {
+                    starting: NumberedBlock::from(("a", 1)),
+                    current: NumberedBlock::from(("b", 2)),
+                    highest: NumberedBlock::from(("c", 3)),
+                    l1_pending_confirmations: 4,
                 }),
             ),
         ];
@@ -836,6 +1036,16 @@ pub mod reply {
         pub events: Vec<EmittedEvent>,
         pub page_number: usize,
         pub is_last_page: bool,
+        /// The latest block at the time the page was read, [None] if there are no blocks yet.
+        pub chain_head: Option<EventsChainHead>,
+    }
+
+    /// The chain head a page of `starknet_getEvents` is consistent with.
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct EventsChainHead {
+        pub block_hash: StarknetBlockHash,
+        pub block_number: StarknetBlockNumber,
     }
 
     // Result type for starknet_addInvokeTransaction
@@ -853,6 +1063,457 @@ pub mod reply {
         pub class_hash: ClassHash,
     }
 
+    // Result type for pathfinder_getVersion
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct NodeVersion {
+        pub version: String,
+        pub git_describe: String,
+        pub git_commit_hash: String,
+        pub git_dirty: bool,
+        pub build_timestamp: String,
+        pub features: Vec<String>,
+        pub schema_revision: u32,
+        pub chain: String,
+    }
+
+    // Result type for pathfinder_syncProgress
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct SyncProgress {
+        /// Blocks stored per second over the requested window.
+        pub blocks_per_second: Option<f64>,
+        /// Estimated number of seconds until the highest known block is reached.
+        pub seconds_to_head: Option<u64>,
+        /// Set if sync is halted on an L2 reorg which awaits operator approval.
+        pub halted_reorg: Option<HaltedReorg>,
+        /// Set if sync is halted because a computed global root diverged from the block's.
+        pub root_divergence: Option<RootDivergence>,
+    }
+
+    // Result type for pathfinder_health
+    #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct NodeHealth {
+        /// Set if all of the checks below passed and the sync process is healthy.
+        pub healthy: bool,
+        /// Whether the L1-L2 head is within the allowed number of blocks of the latest block.
+        pub synced: bool,
+        pub latest_block: Option<StarknetBlockNumber>,
+        pub l1_l2_head: Option<StarknetBlockNumber>,
+        /// Always `false` when serving from a read-only database, which does not affect `healthy`.
+        pub database_writable: bool,
+        pub sequencer_reachable: bool,
+    }
+
+    // Result type for pathfinder_getTokenBalance
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct TokenBalance {
+        /// The balance in the token's smallest unit, as a decimal string.
+        pub balance: String,
+        /// The balance in whole tokens, i.e. `balance` scaled down by `decimals`.
+        pub formatted: String,
+        pub decimals: u8,
+        pub symbol: String,
+        pub name: String,
+    }
+
+    // Result type for pathfinder_getChainStats
+    #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct ChainStats {
+        /// The block up to which the counters are valid, `None` if there are no blocks.
+        pub block_number: Option<StarknetBlockNumber>,
+        pub contracts: u64,
+        pub classes: u64,
+        pub transactions: u64,
+        pub events: u64,
+    }
+
+    impl From<crate::storage::ChainStats> for ChainStats {
+        fn from(stats: crate::storage::ChainStats) -> Self {
+            Self {
+                block_number: stats.block_number,
+                contracts: stats.contracts,
+                classes: stats.classes,
+                transactions: stats.transactions,
+                events: stats.events,
+            }
+        }
+    }
+
+    // Result type for pathfinder_getBlockByRoot and pathfinder_getBlockByTimestamp
+    #[serde_as]
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct BlockHeader {
+        pub block_hash: StarknetBlockHash,
+        pub block_number: StarknetBlockNumber,
+        pub new_root: GlobalRoot,
+        pub accepted_time: StarknetBlockTimestamp,
+        /// `accepted_time` as an RFC 3339 UTC date-time.
+        pub timestamp_iso: Option<String>,
+        #[serde_as(as = "GasPriceAsHexStr")]
+        pub gas_price: GasPrice,
+        pub sequencer: SequencerAddress,
+    }
+
+    impl From<crate::storage::StarknetBlock> for BlockHeader {
+        fn from(block: crate::storage::StarknetBlock) -> Self {
+            Self {
+                block_hash: block.hash,
+                block_number: block.number,
+                new_root: block.root,
+                accepted_time: block.timestamp,
+                timestamp_iso: block.timestamp.to_rfc3339(),
+                gas_price: block.gas_price,
+                sequencer: block.sequencer_address,
+            }
+        }
+    }
+
+    /// The kind of item in a [SyncRetryEntry].
+    #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+    pub enum SyncRetryItemType {
+        /// A class definition, identified by its class hash.
+        #[serde(rename = "CLASS")]
+        Class,
+    }
+
+    // Result type for pathfinder_getSyncRetryQueue
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct SyncRetryEntry {
+        pub item_type: SyncRetryItemType,
+        pub identifier: StarkHash,
+        pub attempts: u32,
+        /// Unix timestamp in seconds of the next attempt.
+        pub next_attempt_at: u64,
+        pub last_error: String,
+    }
+
+    impl From<crate::storage::RetryEntry> for SyncRetryEntry {
+        fn from(entry: crate::storage::RetryEntry) -> Self {
+            let (item_type, identifier) = match entry.item {
+                crate::storage::RetryItem::Class(hash) => (SyncRetryItemType::Class, hash.0),
+            };
+
+            Self {
+                item_type,
+                identifier,
+                attempts: entry.attempts,
+                next_attempt_at: entry.next_attempt_at,
+                last_error: entry.last_error,
+            }
+        }
+    }
+
+    // Result type for pathfinder_getDeclaredClasses
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DeclaredClass {
+        pub class_hash: ClassHash,
+        pub transaction_hash: StarknetTransactionHash,
+    }
+
+    impl From<crate::storage::DeclaredClass> for DeclaredClass {
+        fn from(class: crate::storage::DeclaredClass) -> Self {
+            Self {
+                class_hash: class.class_hash,
+                transaction_hash: class.transaction_hash,
+            }
+        }
+    }
+
+    // Result type for pathfinder_admin_approveReorg
+    #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct HaltedReorg {
+        /// The L2 head at the time the reorg was detected.
+        pub head: StarknetBlockNumber,
+        /// The oldest block which the reorg invalidates.
+        pub reorg_tail: StarknetBlockNumber,
+        /// The number of blocks which the reorg invalidates.
+        pub depth: u64,
+    }
+
+    impl From<crate::state::DeepReorg> for HaltedReorg {
+        fn from(reorg: crate::state::DeepReorg) -> Self {
+            Self {
+                head: reorg.head,
+                reorg_tail: reorg.reorg_tail,
+                depth: reorg.depth(),
+            }
+        }
+    }
+
+    // Part of the result type for pathfinder_syncProgress
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct RootDivergence {
+        pub block_number: StarknetBlockNumber,
+        pub block_hash: StarknetBlockHash,
+        pub computed_root: GlobalRoot,
+        pub expected_root: GlobalRoot,
+        pub l1_root: Option<GlobalRoot>,
+        /// The contracts touched by the diverging block.
+        pub contracts: Vec<crate::storage::ContractDivergence>,
+    }
+
+    impl From<crate::storage::RootDivergence> for RootDivergence {
+        fn from(divergence: crate::storage::RootDivergence) -> Self {
+            Self {
+                block_number: divergence.block_number,
+                block_hash: divergence.block_hash,
+                computed_root: divergence.computed_root,
+                expected_root: divergence.expected_root,
+                l1_root: divergence.l1_root,
+                contracts: divergence.contracts,
+            }
+        }
+    }
+
+    // Result type for pathfinder_compareShadow
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct ShadowComparison {
+        /// The number of blocks in the range which both chains have stored.
+        pub compared: u64,
+        pub mismatches: Vec<ShadowMismatch>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct ShadowMismatch {
+        pub block_number: StarknetBlockNumber,
+        pub primary_block_hash: StarknetBlockHash,
+        pub shadow_block_hash: StarknetBlockHash,
+        pub primary_state_root: GlobalRoot,
+        pub shadow_state_root: GlobalRoot,
+    }
+
+    impl From<crate::state::shadow::Comparison> for ShadowComparison {
+        fn from(comparison: crate::state::shadow::Comparison) -> Self {
+            Self {
+                compared: comparison.compared,
+                mismatches: comparison
+                    .mismatches
+                    .into_iter()
+                    .map(|mismatch| ShadowMismatch {
+                        block_number: mismatch.block_number,
+                        primary_block_hash: mismatch.primary_hash,
+                        shadow_block_hash: mismatch.shadow_hash,
+                        primary_state_root: mismatch.primary_root,
+                        shadow_state_root: mismatch.shadow_root,
+                    })
+                    .collect(),
+            }
+        }
+    }
+
+    // Result type for pathfinder_diffState
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct StateDiffResult {
+        pub deployed_contracts: Vec<DiffDeployedContract>,
+        pub storage_diffs: Vec<DiffStorageEntries>,
+        /// Set if the diff contained more entries than were returned.
+        pub truncated: bool,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DiffDeployedContract {
+        pub address: ContractAddress,
+        pub class_hash: ClassHash,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DiffStorageEntries {
+        pub address: ContractAddress,
+        pub storage_entries: Vec<DiffStorageEntry>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DiffStorageEntry {
+        pub key: StorageAddress,
+        pub old_value: StorageValue,
+        pub new_value: StorageValue,
+    }
+
+    impl From<crate::state::diff::StateDiff> for StateDiffResult {
+        fn from(diff: crate::state::diff::StateDiff) -> Self {
+            Self {
+                deployed_contracts: diff
+                    .deployed_contracts
+                    .into_iter()
+                    .map(|(address, class_hash)| DiffDeployedContract {
+                        address,
+                        class_hash,
+                    })
+                    .collect(),
+                storage_diffs: diff
+                    .storage_diffs
+                    .into_iter()
+                    .map(|(address, entries)| DiffStorageEntries {
+                        address,
+                        storage_entries: entries
+                            .into_iter()
+                            .map(|(key, change)| DiffStorageEntry {
+                                key,
+                                old_value: change.old,
+                                new_value: change.new,
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+                truncated: diff.truncated,
+            }
+        }
+    }
+
+    // Result type for pathfinder_sql
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct SqlResult {
+        pub columns: Vec<String>,
+        /// Blobs are hex encoded, 32-byte blobs as felts.
+        pub rows: Vec<Vec<serde_json::Value>>,
+        /// Set if the query returned more rows than were included.
+        pub truncated: bool,
+    }
+
+    impl From<crate::storage::QueryResult> for SqlResult {
+        fn from(result: crate::storage::QueryResult) -> Self {
+            use rusqlite::types::Value;
+            use serde_json::Value as Json;
+
+            let to_json = |value| match value {
+                Value::Null => Json::Null,
+                Value::Integer(i) => Json::from(i),
+                Value::Real(f) => Json::from(f),
+                Value::Text(s) => Json::String(s),
+                Value::Blob(b) => {
+                    let hex = match StarkHash::from_be_slice(&b) {
+                        Ok(felt) if b.len() == 32 => felt.to_hex_str().into_owned(),
+                        _ => format!("0x{}", hex::encode(&b)),
+                    };
+                    Json::String(hex)
+                }
+            };
+
+            Self {
+                columns: result.columns,
+                rows: result
+                    .rows
+                    .into_iter()
+                    .map(|row| row.into_iter().map(to_json).collect())
+                    .collect(),
+                truncated: result.truncated,
+            }
+        }
+    }
+
+    // Result type for pathfinder_decodeTransaction
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DecodedTransaction {
+        pub transaction: Transaction,
+        /// The invoked function, if it could be decoded using the contract's ABI.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub function: Option<DecodedFunction>,
+        /// Why the invoked function could not be decoded.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reason: Option<String>,
+    }
+
+    /// A function call decoded using the ABI of the called contract.
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DecodedFunction {
+        pub name: String,
+        pub arguments: Vec<DecodedArgument>,
+    }
+
+    /// A named argument of a [DecodedFunction].
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct DecodedArgument {
+        pub name: String,
+        /// The Cairo type as given in the ABI.
+        pub r#type: String,
+        pub value: DecodedValue,
+    }
+
+    /// The value of a [DecodedArgument].
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum DecodedValue {
+        Felt(StarkHash),
+        /// Decimal representation of the 256-bit integer.
+        Uint256(String),
+        /// Arrays, and structs as their members in order.
+        Array(Vec<DecodedValue>),
+    }
+
+    // Result type for pathfinder_getTransactionInclusionProof
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct TransactionInclusionProof {
+        pub block_hash: StarknetBlockHash,
+        pub block_number: StarknetBlockNumber,
+        /// Root of the block's transaction commitment tree, which the block hash commits to.
+        pub transaction_commitment: StarkHash,
+        pub transaction_index: u64,
+        /// `h(transaction_hash, h(signature))`, the leaf of the transaction in the tree.
+        pub leaf: StarkHash,
+        /// The nodes from the root of the tree to the leaf.
+        pub proof: Vec<ProofNode>,
+    }
+
+    /// A node of a [TransactionInclusionProof], given by the hashes of its children.
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ProofNode {
+        Binary {
+            left: StarkHash,
+            right: StarkHash,
+        },
+        /// The path is given as a field element along with its length in bits, as it may have
+        /// leading zeros.
+        Edge {
+            child: StarkHash,
+            path: StarkHash,
+            length: usize,
+        },
+    }
+
+    impl From<crate::state::merkle_tree::ProofNode> for ProofNode {
+        fn from(node: crate::state::merkle_tree::ProofNode) -> Self {
+            use crate::state::merkle_tree::ProofNode::*;
+            match node {
+                Binary { left, right } => Self::Binary { left, right },
+                Edge { child, path } => Self::Edge {
+                    child,
+                    path: StarkHash::from_bits(&path).expect("Path fits into a field element"),
+                    length: path.len(),
+                },
+            }
+        }
+    }
+
+    // Result type for pathfinder_estimateMessageFee
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    pub struct FeeEstimate {
+        pub gas_consumed: StarkHash,
+        pub gas_price: StarkHash,
+        pub overall_fee: StarkHash,
+    }
+
     // Result type for starknet_addDeployTransaction
     #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
     #[serde(deny_unknown_fields)]
@@ -861,3 +1522,380 @@ pub mod reply {
         pub contract_address: ContractAddress,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    mod block_id {
+        use crate::core::{StarknetBlockHash, StarknetBlockNumber};
+        use crate::rpc::types::{BlockHashOrTag, BlockNumberOrTag, RelativeBlockNumber, Tag};
+        use pretty_assertions::assert_eq;
+        use serde_json::json;
+        use stark_hash::StarkHash;
+
+        fn hash() -> BlockHashOrTag {
+            BlockHashOrTag::Hash(StarknetBlockHash(
+                StarkHash::from_hex_str("0x0123abc").unwrap(),
+            ))
+        }
+
+        /// Every variant along with its exact JSON and feeder gateway query representation.
+        fn hash_or_tag_vectors() -> [(
+            BlockHashOrTag,
+            serde_json::Value,
+            (&'static str, &'static str),
+        ); 3] {
+            [
+                (hash(), json!("0x123abc"), ("blockHash", "0x123abc")),
+                (
+                    BlockHashOrTag::Tag(Tag::Latest),
+                    json!("latest"),
+                    ("blockNumber", "latest"),
+                ),
+                (
+                    BlockHashOrTag::Tag(Tag::Pending),
+                    json!("pending"),
+                    ("blockNumber", "pending"),
+                ),
+            ]
+        }
+
+        fn number_or_tag_vectors() -> [(
+            BlockNumberOrTag,
+            serde_json::Value,
+            (&'static str, &'static str),
+        ); 3] {
+            [
+                (
+                    BlockNumberOrTag::Number(StarknetBlockNumber(1716)),
+                    json!(1716),
+                    ("blockNumber", "1716"),
+                ),
+                (
+                    BlockNumberOrTag::Tag(Tag::Latest),
+                    json!("latest"),
+                    ("blockNumber", "latest"),
+                ),
+                (
+                    BlockNumberOrTag::Tag(Tag::Pending),
+                    json!("pending"),
+                    ("blockNumber", "pending"),
+                ),
+            ]
+        }
+
+        #[test]
+        fn hash_or_tag_serde() {
+            for (value, golden, _) in hash_or_tag_vectors() {
+                assert_eq!(serde_json::to_value(&value).unwrap(), golden);
+                assert_eq!(
+                    serde_json::from_value::<BlockHashOrTag>(golden).unwrap(),
+                    value
+                );
+            }
+        }
+
+        #[test]
+        fn number_or_tag_serde() {
+            for (value, golden, _) in number_or_tag_vectors() {
+                assert_eq!(serde_json::to_value(&value).unwrap(), golden);
+                assert_eq!(
+                    serde_json::from_value::<BlockNumberOrTag>(golden).unwrap(),
+                    value
+                );
+            }
+        }
+
+        #[test]
+        fn gateway_query() {
+            for (value, _, (name, query)) in hash_or_tag_vectors() {
+                let (actual_name, actual_query) = value.to_gateway_query();
+                assert_eq!((actual_name, actual_query.as_ref()), (name, query));
+            }
+            for (value, _, (name, query)) in number_or_tag_vectors() {
+                let (actual_name, actual_query) = value.to_gateway_query().unwrap();
+                assert_eq!((actual_name, actual_query.as_ref()), (name, query));
+            }
+        }
+
+        #[test]
+        fn relative_number() {
+            let value = BlockNumberOrTag::Relative(RelativeBlockNumber { relative: -10 });
+            let golden = json!({"relative": -10});
+
+            assert_eq!(serde_json::to_value(&value).unwrap(), golden);
+            assert_eq!(
+                serde_json::from_value::<BlockNumberOrTag>(golden).unwrap(),
+                value
+            );
+            assert_eq!(value.to_string(), "latest-10");
+            assert_eq!(value.to_gateway_query(), None);
+
+            serde_json::from_value::<BlockNumberOrTag>(json!({"relative": -10, "extra": 1}))
+                .unwrap_err();
+        }
+
+        #[test]
+        fn rejects_malformed() {
+            for invalid in [json!("Latest"), json!(null), json!("0xzz"), json!(1)] {
+                serde_json::from_value::<BlockHashOrTag>(invalid).unwrap_err();
+            }
+            for invalid in [json!("Latest"), json!(null), json!("0x1"), json!(-1)] {
+                serde_json::from_value::<BlockNumberOrTag>(invalid).unwrap_err();
+            }
+        }
+    }
+
+    mod transaction {
+        use crate::core::{
+            ClassHash, ConstructorParam, ContractAddress, ContractAddressSalt,
+            StarknetTransactionHash,
+        };
+        use crate::rpc::types::reply::Transaction;
+        use crate::sequencer::reply::transaction as seq;
+        use pretty_assertions::assert_eq;
+        use stark_hash::StarkHash;
+
+        fn deploy(
+            salt: Option<ContractAddressSalt>,
+            constructor_calldata: Option<Vec<ConstructorParam>>,
+        ) -> seq::Transaction {
+            seq::Transaction {
+                calldata: None,
+                class_hash: Some(ClassHash(StarkHash::from_hex_str("0xc1a55").unwrap())),
+                constructor_calldata,
+                contract_address: ContractAddress(StarkHash::from_hex_str("0xadd").unwrap()),
+                contract_address_salt: salt,
+                entry_point_type: None,
+                entry_point_selector: None,
+                max_fee: None,
+                signature: None,
+                transaction_hash: StarknetTransactionHash(
+                    StarkHash::from_hex_str("0x7a5").unwrap(),
+                ),
+                sender_address: None,
+                nonce: None,
+                r#type: seq::Type::Deploy,
+            }
+        }
+
+        #[test]
+        fn deploy_with_salt_and_calldata() {
+            let salt = ContractAddressSalt(StarkHash::from_hex_str("0x5a17").unwrap());
+            let calldata = vec![
+                ConstructorParam(StarkHash::from_hex_str("0x1").unwrap()),
+                ConstructorParam(StarkHash::from_hex_str("0x2").unwrap()),
+            ];
+            let txn = Transaction::from(deploy(Some(salt), Some(calldata.clone())));
+
+            assert_eq!(txn.calldata, None);
+            assert_eq!(txn.entry_point_selector, None);
+            assert_eq!(
+                txn.class_hash,
+                Some(ClassHash(StarkHash::from_hex_str("0xc1a55").unwrap()))
+            );
+            assert_eq!(txn.contract_address_salt, Some(salt));
+            assert_eq!(txn.constructor_calldata, Some(calldata));
+
+            let json = serde_json::to_value(&txn).unwrap();
+            assert_eq!(json["contract_address_salt"], serde_json::json!("0x5a17"));
+            assert_eq!(
+                json["constructor_calldata"],
+                serde_json::json!(["0x1", "0x2"])
+            );
+            assert_eq!(json["class_hash"], serde_json::json!("0xc1a55"));
+
+            let deserialized = serde_json::from_value::<Transaction>(json).unwrap();
+            assert_eq!(deserialized, txn);
+        }
+
+        #[test]
+        fn deploy_without_salt_and_calldata() {
+            let txn = Transaction::from(deploy(None, None));
+
+            assert_eq!(txn.contract_address_salt, None);
+            assert_eq!(txn.constructor_calldata, None);
+
+            let json = serde_json::to_value(&txn).unwrap();
+            let fields = json.as_object().unwrap();
+            assert!(!fields.contains_key("contract_address_salt"));
+            assert!(!fields.contains_key("constructor_calldata"));
+
+            let deserialized = serde_json::from_value::<Transaction>(json).unwrap();
+            assert_eq!(deserialized, txn);
+        }
+
+        #[test]
+        fn from_stored_blob() {
+            // Stored transactions are sequencer shaped, with decimal encoded calldata.
+            let stored = r#"{
+                "class_hash": "0xc1a55",
+                "constructor_calldata": ["1", "2"],
+                "contract_address": "0xadd",
+                "contract_address_salt": "0x5a17",
+                "transaction_hash": "0x7a5",
+                "type": "DEPLOY"
+            }"#;
+            let stored = serde_json::from_str::<seq::Transaction>(stored).unwrap();
+            let txn = Transaction::from(stored);
+
+            assert_eq!(
+                txn.constructor_calldata,
+                Some(vec![
+                    ConstructorParam(StarkHash::from_hex_str("0x1").unwrap()),
+                    ConstructorParam(StarkHash::from_hex_str("0x2").unwrap()),
+                ])
+            );
+            assert_eq!(
+                txn.contract_address_salt,
+                Some(ContractAddressSalt(
+                    StarkHash::from_hex_str("0x5a17").unwrap()
+                ))
+            );
+        }
+    }
+
+    mod block_header {
+        use crate::core::StarknetBlockTimestamp;
+        use crate::rpc::types::reply::BlockHeader;
+        use crate::storage::test_utils::create_blocks;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn timestamp_iso() {
+            let [mut block] = create_blocks::<1>();
+            block.timestamp = StarknetBlockTimestamp(1637068348);
+
+            let json = serde_json::to_value(BlockHeader::from(block)).unwrap();
+            assert_eq!(json["accepted_time"], 1637068348);
+            assert_eq!(json["timestamp_iso"], "2021-11-16T13:12:28Z");
+        }
+
+        #[test]
+        fn unrepresentable_timestamp_iso() {
+            let [mut block] = create_blocks::<1>();
+            block.timestamp = StarknetBlockTimestamp(u64::MAX);
+
+            let header = BlockHeader::from(block);
+            assert_eq!(header.timestamp_iso, None);
+        }
+    }
+
+    mod transaction_receipt {
+        use crate::core::{
+            ContractAddress, EthereumAddress, L1ToL2MessagePayloadElem, L2ToL1MessagePayloadElem,
+        };
+        use crate::rpc::types::reply::transaction_receipt::{MessageToL1, MessageToL2};
+        use crate::rpc::types::reply::{BlockStatus, TransactionReceipt};
+        use crate::sequencer::reply::transaction::Receipt;
+        use pretty_assertions::assert_eq;
+        use serde_json::json;
+        use stark_hash::StarkHash;
+        use web3::types::H160;
+
+        fn eth_address() -> EthereumAddress {
+            EthereumAddress(H160::from_low_u64_be(0xe7))
+        }
+
+        #[test]
+        fn message_to_l1_serde() {
+            let message = MessageToL1 {
+                from_address: ContractAddress(StarkHash::from_hex_str("0xadd").unwrap()),
+                to_address: eth_address(),
+                payload: vec![
+                    L2ToL1MessagePayloadElem(StarkHash::from_hex_str("0x1").unwrap()),
+                    L2ToL1MessagePayloadElem(StarkHash::from_hex_str("0xab").unwrap()),
+                ],
+            };
+            let golden = json!({
+                "from_address": "0xadd",
+                "to_address": "0xe7",
+                "payload": ["0x1", "0xab"]
+            });
+
+            assert_eq!(serde_json::to_value(&message).unwrap(), golden);
+            assert_eq!(
+                serde_json::from_value::<MessageToL1>(golden).unwrap(),
+                message
+            );
+        }
+
+        #[test]
+        fn message_to_l2_serde() {
+            let message = MessageToL2 {
+                from_address: eth_address(),
+                payload: vec![L1ToL2MessagePayloadElem(
+                    StarkHash::from_hex_str("0x10").unwrap(),
+                )],
+            };
+            let golden = json!({
+                "from_address": "0xe7",
+                "payload": ["0x10"]
+            });
+
+            assert_eq!(serde_json::to_value(&message).unwrap(), golden);
+            assert_eq!(
+                serde_json::from_value::<MessageToL2>(golden).unwrap(),
+                message
+            );
+        }
+
+        #[test]
+        fn from_stored_receipt() {
+            // Stored receipts are sequencer shaped, with decimal encoded payloads.
+            let stored = r#"{
+                "events": [],
+                "execution_resources": {
+                    "builtin_instance_counter": {},
+                    "n_steps": 1,
+                    "n_memory_holes": 0
+                },
+                "l1_to_l2_consumed_message": {
+                    "from_address": "0x00000000000000000000000000000000000000e7",
+                    "payload": ["16"],
+                    "selector": "0x5e1ec7",
+                    "to_address": "0xadd"
+                },
+                "l2_to_l1_messages": [{
+                    "from_address": "0xadd",
+                    "payload": ["1", "171"],
+                    "to_address": "0x00000000000000000000000000000000000000e7"
+                }],
+                "transaction_hash": "0x7a5",
+                "transaction_index": 0
+            }"#;
+            let stored = serde_json::from_str::<Receipt>(stored).unwrap();
+            let receipt = TransactionReceipt::with_status(stored, BlockStatus::AcceptedOnL2);
+
+            let json = serde_json::to_value(&receipt).unwrap();
+            assert_eq!(
+                json["messages_sent"],
+                json!([{
+                    "from_address": "0xadd",
+                    "to_address": "0xe7",
+                    "payload": ["0x1", "0xab"]
+                }])
+            );
+            assert_eq!(
+                json["l1_origin_message"],
+                json!({
+                    "from_address": "0xe7",
+                    "payload": ["0x10"]
+                })
+            );
+            assert!(json.get("placeholder").is_none());
+        }
+
+        #[test]
+        fn placeholder_is_marked() {
+            let placeholder = Receipt::placeholder(
+                crate::core::StarknetTransactionHash(StarkHash::from_hex_str("0x7a5").unwrap()),
+                crate::core::StarknetTransactionIndex(0),
+            );
+            let receipt = TransactionReceipt::with_status(placeholder, BlockStatus::AcceptedOnL2);
+
+            let json = serde_json::to_value(&receipt).unwrap();
+            assert_eq!(json["placeholder"], json!(true));
+            assert_eq!(json["events"], json!([]));
+        }
+    }
+}
@@ -2,23 +2,26 @@
 pub mod error;
 pub mod reply;
 pub mod request;
+mod throttle;
 
 use self::{
-    error::StarknetError,
+    error::{StarknetError, StarknetErrorCode},
     request::{add_transaction::ContractDefinition, Call},
 };
 use crate::{
     core::{
-        CallSignatureElem, ConstructorParam, ContractAddress, ContractAddressSalt, Fee,
+        CallSignatureElem, ClassHash, ConstructorParam, ContractAddress, ContractAddressSalt, Fee,
         StarknetTransactionHash, StorageAddress, StorageValue, TransactionNonce,
         TransactionVersion,
     },
     ethereum::Chain,
-    rpc::types::{BlockHashOrTag, BlockNumberOrTag, Tag},
+    rpc::types::{BlockHashOrTag, BlockNumberOrTag},
     sequencer::error::SequencerError,
 };
 use reqwest::Url;
-use std::{borrow::Cow, fmt::Debug, future::Future, result::Result, time::Duration};
+use std::{fmt::Debug, future::Future, result::Result, time::Duration};
+
+pub use throttle::Throttled;
 
 #[cfg_attr(test, mockall::automock)]
 #[async_trait::async_trait]
@@ -28,6 +31,12 @@ pub trait ClientApi {
         block_number: BlockNumberOrTag,
     ) -> Result<reply::Block, SequencerError>;
 
+    /// Returns the gateway's JSON for the block verbatim, without parsing it.
+    async fn raw_block_by_number(
+        &self,
+        block_number: BlockNumberOrTag,
+    ) -> Result<bytes::Bytes, SequencerError>;
+
     async fn block_by_hash(
         &self,
         block_hash: BlockHashOrTag,
@@ -44,6 +53,8 @@ pub trait ClientApi {
         contract_addr: ContractAddress,
     ) -> Result<bytes::Bytes, SequencerError>;
 
+    async fn class_by_hash(&self, class_hash: ClassHash) -> Result<bytes::Bytes, SequencerError>;
+
     async fn storage(
         &self,
         contract_addr: ContractAddress,
@@ -119,24 +130,6 @@ pub struct Client {
     sequencer_url: Url,
 }
 
-/// Helper function which simplifies the handling of optional block hashes in queries.
-fn block_hash_str(hash: BlockHashOrTag) -> (&'static str, Cow<'static, str>) {
-    match hash {
-        BlockHashOrTag::Hash(h) => ("blockHash", h.0.to_hex_str()),
-        BlockHashOrTag::Tag(Tag::Latest) => ("blockNumber", Cow::from("latest")),
-        BlockHashOrTag::Tag(Tag::Pending) => ("blockNumber", Cow::from("pending")),
-    }
-}
-
-/// Helper function which simplifies the handling of optional block numbers in queries.
-fn block_number_str(number: BlockNumberOrTag) -> Cow<'static, str> {
-    match number {
-        BlockNumberOrTag::Number(n) => Cow::from(n.0.to_string()),
-        BlockNumberOrTag::Tag(Tag::Latest) => Cow::from("latest"),
-        BlockNumberOrTag::Tag(Tag::Pending) => Cow::from("pending"),
-    }
-}
-
 /// __Mandatory__ function to parse every sequencer query response and deserialize
 /// to expected output type.
 async fn parse<T>(resp: reqwest::Response) -> Result<T, SequencerError>
@@ -197,6 +190,20 @@ where
         .await
 }
 
+/// The query parameter selecting `block_number`, failing with a [StarknetErrorCode::BlockNotFound]
+/// for block numbers which the gateway does not support.
+fn gateway_query(
+    block_number: BlockNumberOrTag,
+) -> Result<(&'static str, std::borrow::Cow<'static, str>), SequencerError> {
+    block_number.to_gateway_query().ok_or_else(|| {
+        StarknetError {
+            code: StarknetErrorCode::BlockNotFound,
+            message: format!("Block {} cannot be resolved by the gateway", block_number),
+        }
+        .into()
+    })
+}
+
 /// Determines if an error is retryable or not.
 fn retry_condition(e: &SequencerError) -> bool {
     use reqwest::StatusCode;
@@ -204,7 +211,7 @@ fn retry_condition(e: &SequencerError) -> bool {
 
     match e {
         SequencerError::ReqwestError(e) => {
-            if e.is_body() || e.is_connect() || e.is_timeout() {
+            if e.is_body() || e.is_connect() {
                 info!(reason=%e, "Request failed, retrying");
             } else if e.is_status() {
                 match e.status() {
@@ -231,19 +238,34 @@ fn retry_condition(e: &SequencerError) -> bool {
 
             true
         }
+        SequencerError::Timeout(e) => {
+            info!(reason=%e, "Request timed out, retrying");
+            true
+        }
         SequencerError::StarknetError(_) => false,
     }
 }
 
 impl Client {
-    /// Creates a new Sequencer client for the given chain.
+    /// Default timeout of each request, see [Client::with_timeout].
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+    /// Creates a new Sequencer client for the given chain, using [Client::DEFAULT_TIMEOUT].
     pub fn new(chain: Chain) -> reqwest::Result<Self> {
+        Self::with_timeout(chain, Self::DEFAULT_TIMEOUT)
+    }
+
+    /// Creates a new Sequencer client for the given chain.
+    ///
+    /// Each request fails with [SequencerError::Timeout] if it does not complete, from connecting
+    /// until the whole response has been read, within `timeout`.
+    pub fn with_timeout(chain: Chain, timeout: Duration) -> reqwest::Result<Self> {
         let url = match chain {
             Chain::Mainnet => Url::parse("https://alpha-mainnet.starknet.io/").unwrap(),
             Chain::Goerli => Url::parse("https://alpha4.starknet.io/").unwrap(),
         };
 
-        Self::with_url(url)
+        Self::with_url_and_timeout(url, timeout)
     }
 
     #[cfg(test)]
@@ -254,16 +276,38 @@ impl Client {
     }
 
     /// Create a Sequencer client for the given [Url].
-    fn with_url(url: Url) -> reqwest::Result<Self> {
+    pub fn with_url(url: Url) -> reqwest::Result<Self> {
+        Self::with_url_and_timeout(url, Self::DEFAULT_TIMEOUT)
+    }
+
+    /// Create a Sequencer client for the given [Url], see [Client::with_timeout].
+    pub(crate) fn with_url_and_timeout(url: Url, timeout: Duration) -> reqwest::Result<Self> {
         Ok(Self {
             inner: reqwest::Client::builder()
-                .timeout(Duration::from_secs(120))
+                .connect_timeout(timeout)
+                .timeout(timeout)
                 .user_agent(crate::consts::USER_AGENT)
                 .build()?,
             sequencer_url: url,
         })
     }
 
+    /// Checks that the sequencer responds to a cheap query. Unlike the [ClientApi] queries
+    /// this is not retried, and gives up after [Client::PING_TIMEOUT].
+    pub async fn ping(&self) -> Result<(), SequencerError> {
+        let resp = self
+            .inner
+            .get(self.build_query(&["feeder_gateway", "get_contract_addresses"], &[]))
+            .timeout(Self::PING_TIMEOUT)
+            .send()
+            .await?;
+        parse::<reply::EthContractAddresses>(resp).await?;
+        Ok(())
+    }
+
+    /// Timeout of [Client::ping].
+    pub const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
     /// Helper function that constructs a URL for particular query.
     fn build_query(&self, path_segments: &[&str], params: &[(&str, &str)]) -> Url {
         let mut query_url = self.sequencer_url.clone();
@@ -285,14 +329,11 @@ impl ClientApi for Client {
         &self,
         block_number: BlockNumberOrTag,
     ) -> Result<reply::Block, SequencerError> {
-        let number = block_number_str(block_number);
+        let (tag, number) = gateway_query(block_number)?;
         retry(|| async {
             let resp = self
                 .inner
-                .get(self.build_query(
-                    &["feeder_gateway", "get_block"],
-                    &[("blockNumber", &number)],
-                ))
+                .get(self.build_query(&["feeder_gateway", "get_block"], &[(tag, &number)]))
                 .send()
                 .await?;
             parse::<reply::Block>(resp).await
@@ -300,13 +341,33 @@ impl ClientApi for Client {
         .await
     }
 
+    /// Gets block by number, as the verbatim JSON sent by the gateway.
+    #[tracing::instrument(skip(self))]
+    async fn raw_block_by_number(
+        &self,
+        block_number: BlockNumberOrTag,
+    ) -> Result<bytes::Bytes, SequencerError> {
+        let (tag, number) = gateway_query(block_number)?;
+        retry(|| async {
+            let resp = self
+                .inner
+                .get(self.build_query(&["feeder_gateway", "get_block"], &[(tag, &number)]))
+                .send()
+                .await?;
+            let resp = parse_raw(resp).await?;
+            let resp = resp.bytes().await?;
+            Ok(resp)
+        })
+        .await
+    }
+
     /// Get block by hash.
     #[tracing::instrument(skip(self))]
     async fn block_by_hash(
         &self,
         block_hash: BlockHashOrTag,
     ) -> Result<reply::Block, SequencerError> {
-        let (tag, hash) = block_hash_str(block_hash);
+        let (tag, hash) = block_hash.to_gateway_query();
         retry(|| async {
             let resp = self
                 .inner
@@ -325,7 +386,7 @@ impl ClientApi for Client {
         payload: request::Call,
         block_hash: BlockHashOrTag,
     ) -> Result<reply::Call, SequencerError> {
-        let (tag, hash) = block_hash_str(block_hash);
+        let (tag, hash) = block_hash.to_gateway_query();
         retry(|| async {
             let resp = self
                 .inner
@@ -360,6 +421,25 @@ impl ClientApi for Client {
         .await
     }
 
+    /// Gets the definition of the class with the given hash.
+    #[tracing::instrument(skip(self))]
+    async fn class_by_hash(&self, class_hash: ClassHash) -> Result<bytes::Bytes, SequencerError> {
+        retry(|| async {
+            let resp = self
+                .inner
+                .get(self.build_query(
+                    &["feeder_gateway", "get_class_by_hash"],
+                    &[("classHash", &class_hash.0.to_hex_str())],
+                ))
+                .send()
+                .await?;
+            let resp = parse_raw(resp).await?;
+            let resp = resp.bytes().await?;
+            Ok(resp)
+        })
+        .await
+    }
+
     /// Gets storage value associated with a `key` for a prticular contract.
     #[tracing::instrument(skip(self))]
     async fn storage(
@@ -368,9 +448,9 @@ impl ClientApi for Client {
         key: StorageAddress,
         block_hash: BlockHashOrTag,
     ) -> Result<StorageValue, SequencerError> {
-        use crate::rpc::serde::starkhash_to_dec_str;
+        use crate::serde::starkhash_to_dec_str;
 
-        let (tag, hash) = block_hash_str(block_hash);
+        let (tag, hash) = block_hash.to_gateway_query();
         retry(|| async {
             let resp = self
                 .inner
@@ -435,7 +515,7 @@ impl ClientApi for Client {
         &self,
         block_hash: BlockHashOrTag,
     ) -> Result<reply::StateUpdate, SequencerError> {
-        let (tag, hash) = block_hash_str(block_hash);
+        let (tag, hash) = block_hash.to_gateway_query();
         retry(|| async {
             let resp = self
                 .inner
@@ -453,13 +533,11 @@ impl ClientApi for Client {
         &self,
         block_number: BlockNumberOrTag,
     ) -> Result<reply::StateUpdate, SequencerError> {
+        let (tag, number) = gateway_query(block_number)?;
         retry(|| async {
             let resp = self
                 .inner
-                .get(self.build_query(
-                    &["feeder_gateway", "get_state_update"],
-                    &[("blockNumber", &block_number_str(block_number))],
-                ))
+                .get(self.build_query(&["feeder_gateway", "get_state_update"], &[(tag, &number)]))
                 .send()
                 .await?;
             parse(resp).await
@@ -620,7 +698,7 @@ pub mod test_utils {
         pub static ref INVALID_ENTRY_POINT: EntryPoint = EntryPoint(StarkHash::ZERO);
         pub static ref INVALID_TX_INDEX: StarknetTransactionIndex = StarknetTransactionIndex(u64::MAX);
         pub static ref VALID_KEY: StorageAddress = StorageAddress::from_hex_str("0x0206F38F7E4F15E87567361213C28F235CCCDAA1D7FD34C9DB1DFE9489C6A091").unwrap();
-        pub static ref VALID_KEY_DEC: String = crate::rpc::serde::starkhash_to_dec_str(&VALID_KEY.0);
+        pub static ref VALID_KEY_DEC: String = crate::serde::starkhash_to_dec_str(&VALID_KEY.0);
         pub static ref VALID_CALL_DATA: Vec<CallParam> = vec![CallParam::from_hex_str("0x4d2").unwrap()];
     }
 }
@@ -629,6 +707,7 @@ pub mod test_utils {
 mod tests {
     use super::{error::StarknetErrorCode, test_utils::*, *};
     use crate::core::{StarknetBlockHash, StarknetBlockNumber};
+    use crate::rpc::types::Tag;
     use assert_matches::assert_matches;
     use stark_hash::StarkHash;
 
@@ -845,6 +924,61 @@ mod tests {
         }
     }
 
+    mod block_id_query {
+        use super::*;
+
+        /// The exact `get_block` query string the gateway must receive for every
+        /// block id variant, independently of how the query is assembled.
+        #[tokio::test]
+        async fn every_variant() {
+            let hash_cases = [
+                (
+                    *GENESIS_BLOCK_HASH,
+                    "blockHash=0x7d328a71faf48c5c3857e99f20a77b18522480956d1cd5bff1ff2df3c8b427b",
+                    response!("genesis_block.json"),
+                ),
+                (
+                    BlockHashOrTag::Tag(Tag::Latest),
+                    "blockNumber=latest",
+                    response!("block_200k.json"),
+                ),
+                (
+                    BlockHashOrTag::Tag(Tag::Pending),
+                    "blockNumber=pending",
+                    response!("pending_block.json"),
+                ),
+            ];
+            for (block, query, fixture) in hash_cases {
+                let (_jh, client) =
+                    setup([(format!("/feeder_gateway/get_block?{}", query), fixture)]);
+                client.block_by_hash(block).await.unwrap();
+            }
+
+            let number_cases = [
+                (
+                    *GENESIS_BLOCK_NUMBER,
+                    "blockNumber=0",
+                    response!("genesis_block.json"),
+                ),
+                (
+                    BlockNumberOrTag::Tag(Tag::Latest),
+                    "blockNumber=latest",
+                    response!("block_200k.json"),
+                ),
+                (
+                    BlockNumberOrTag::Tag(Tag::Pending),
+                    "blockNumber=pending",
+                    response!("pending_block.json"),
+                ),
+            ];
+            for (block, query, fixture) in number_cases {
+                let (_jh, client) =
+                    setup([(format!("/feeder_gateway/get_block?{}", query), fixture)]);
+                client.block_by_number(block).await.unwrap();
+            }
+        }
+    }
+
     mod block_by_hash {
         use super::*;
         use pretty_assertions::assert_eq;
@@ -1965,7 +2099,7 @@ mod tests {
     }
 
     mod retry {
-        use super::{SequencerError, StarknetErrorCode};
+        use super::{BlockNumberOrTag, Client, ClientApi, SequencerError, StarknetErrorCode, Tag};
         use assert_matches::assert_matches;
         use http::{response::Builder, StatusCode};
         use pretty_assertions::assert_eq;
@@ -2105,5 +2239,19 @@ mod tests {
             // 4th try should have timedout if this is really exponential backoff
             assert_eq!(CNT.load(Ordering::Relaxed), 4);
         }
+
+        #[tokio::test]
+        async fn client_timeout() {
+            let (_jh, addr) = slow_server();
+            let mut url = reqwest::Url::parse("http://localhost/").unwrap();
+            url.set_port(Some(addr.port())).unwrap();
+            let client = Client::with_url_and_timeout(url, Duration::from_millis(100)).unwrap();
+
+            let error = client
+                .block_by_number(BlockNumberOrTag::Tag(Tag::Latest))
+                .await
+                .unwrap_err();
+            assert_matches!(error, SequencerError::Timeout(_));
+        }
     }
 }
@@ -9,14 +9,28 @@ pub enum SequencerError {
     /// Starknet specific errors.
     #[error(transparent)]
     StarknetError(#[from] StarknetError),
+    /// The request did not complete within the client's timeout.
+    #[error("Sequencer request timed out")]
+    Timeout(#[source] reqwest::Error),
     /// All other kinds of errors
     #[error(transparent)]
-    ReqwestError(#[from] reqwest::Error),
+    ReqwestError(reqwest::Error),
+}
+
+impl From<reqwest::Error> for SequencerError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            SequencerError::Timeout(e)
+        } else {
+            SequencerError::ReqwestError(e)
+        }
+    }
 }
 
 impl From<SequencerError> for Error {
     fn from(e: SequencerError) -> Self {
         match e {
+            SequencerError::Timeout(_) => Error::Call(CallError::Failed(e.into())),
             SequencerError::ReqwestError(e) => Error::Call(CallError::Failed(e.into())),
             SequencerError::StarknetError(e) => match e.code {
                 StarknetErrorCode::OutOfRangeBlockHash | StarknetErrorCode::BlockNotFound
@@ -4,16 +4,17 @@ use crate::{
         CallResultValue, EthereumAddress, GasPrice, GlobalRoot, SequencerAddress,
         StarknetBlockHash, StarknetBlockNumber, StarknetBlockTimestamp,
     },
-    rpc::serde::{EthereumAddressAsHexStr, GasPriceAsHexStr},
+    serde::{EthereumAddressAsHexStr, GasPriceAsHexStr},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
 /// Used to deserialize replies to [ClientApi::block_by_hash](crate::sequencer::ClientApi::block_by_hash) and
 /// [ClientApi::block_by_number](crate::sequencer::ClientApi::block_by_number).
+///
+/// Serializes to the sequencer's format, so that a block can be passed on verbatim.
 #[serde_as]
-#[derive(Clone, Debug, Deserialize, PartialEq)]
-#[cfg_attr(test, derive(serde::Serialize))]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Block {
     #[serde(default)]
@@ -34,9 +35,18 @@ pub struct Block {
     pub transactions: Vec<transaction::Transaction>,
 }
 
+impl Block {
+    /// Returns `true` if the block has been accepted, either on L2 or on L1.
+    ///
+    /// Blocks in any other state (e.g. `PENDING` or `RECEIVED`) may still change,
+    /// and should therefore not be persisted.
+    pub fn is_final(&self) -> bool {
+        matches!(self.status, Status::AcceptedOnL1 | Status::AcceptedOnL2)
+    }
+}
+
 /// Block and transaction status values.
-#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
-#[cfg_attr(test, derive(serde::Serialize))]
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub enum Status {
     #[serde(rename = "NOT_RECEIVED")]
@@ -47,7 +57,8 @@ pub enum Status {
     Pending,
     #[serde(rename = "REJECTED")]
     Rejected,
-    #[serde(rename = "ACCEPTED_ON_L1")]
+    /// The earliest blocks and transactions report `ACCEPTED_ONCHAIN` instead.
+    #[serde(rename = "ACCEPTED_ON_L1", alias = "ACCEPTED_ONCHAIN")]
     AcceptedOnL1,
     #[serde(rename = "ACCEPTED_ON_L2")]
     AcceptedOnL2,
@@ -115,7 +126,7 @@ pub mod transaction {
             L1ToL2MessagePayloadElem, L2ToL1MessagePayloadElem, StarknetTransactionHash,
             StarknetTransactionIndex, TransactionNonce, TransactionSignatureElem,
         },
-        rpc::serde::{
+        serde::{
             CallParamAsDecimalStr, ConstructorParamAsDecimalStr, EthereumAddressAsHexStr,
             EventDataAsDecimalStr, EventKeyAsDecimalStr, FeeAsHexStr,
             L1ToL2MessagePayloadElemAsDecimalStr, L2ToL1MessagePayloadElemAsDecimalStr,
@@ -146,17 +157,37 @@ pub mod transaction {
 
     /// Types used when deserializing L2 execution resources related data.
     pub mod execution_resources {
-        use serde::{Deserialize, Serialize};
+        use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
         /// Sometimes `builtin_instance_counter` JSON object is returned empty.
-        #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+        ///
+        /// Any other object must be a complete [NormalBuiltinInstanceCounter]. An object with
+        /// missing or unknown builtins is rejected, instead of being mistaken for an empty
+        /// counter as an untagged enum would, so that changes to the gateway's set of builtins
+        /// are noticed. Stored receipts only ever contain either variant in full.
+        #[derive(Copy, Clone, Debug, Serialize, PartialEq)]
         #[serde(untagged)]
-        #[serde(deny_unknown_fields)]
         pub enum BuiltinInstanceCounter {
             Normal(NormalBuiltinInstanceCounter),
             Empty(EmptyBuiltinInstanceCounter),
         }
 
+        impl<'de> Deserialize<'de> for BuiltinInstanceCounter {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let counter = serde_json::Map::deserialize(deserializer)?;
+                if counter.is_empty() {
+                    return Ok(Self::Empty(EmptyBuiltinInstanceCounter {}));
+                }
+
+                NormalBuiltinInstanceCounter::deserialize(serde_json::Value::Object(counter))
+                    .map(Self::Normal)
+                    .map_err(|e| D::Error::custom(format!("Invalid builtin instance counter: {e}")))
+            }
+        }
+
         #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
         #[serde(deny_unknown_fields)]
         pub struct NormalBuiltinInstanceCounter {
@@ -170,6 +201,68 @@ pub mod transaction {
 
         #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
         pub struct EmptyBuiltinInstanceCounter {}
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+            use serde_json::json;
+
+            fn full() -> serde_json::Value {
+                json!({
+                    "bitwise_builtin": 1,
+                    "ecdsa_builtin": 2,
+                    "ec_op_builtin": 3,
+                    "output_builtin": 4,
+                    "pedersen_builtin": 5,
+                    "range_check_builtin": 6,
+                })
+            }
+
+            #[test]
+            fn full_counter() {
+                let counter = serde_json::from_value::<BuiltinInstanceCounter>(full()).unwrap();
+                assert_eq!(
+                    counter,
+                    BuiltinInstanceCounter::Normal(NormalBuiltinInstanceCounter {
+                        bitwise_builtin: 1,
+                        ecdsa_builtin: 2,
+                        ec_op_builtin: 3,
+                        output_builtin: 4,
+                        pedersen_builtin: 5,
+                        range_check_builtin: 6,
+                    })
+                );
+                assert_eq!(serde_json::to_value(&counter).unwrap(), full());
+            }
+
+            #[test]
+            fn empty_counter() {
+                let counter = serde_json::from_value::<BuiltinInstanceCounter>(json!({})).unwrap();
+                assert_eq!(
+                    counter,
+                    BuiltinInstanceCounter::Empty(EmptyBuiltinInstanceCounter {})
+                );
+                assert_eq!(serde_json::to_value(&counter).unwrap(), json!({}));
+            }
+
+            #[test]
+            fn unexpected_builtin_is_rejected() {
+                let mut counter = full();
+                counter["keccak_builtin"] = json!(7);
+
+                let error = serde_json::from_value::<BuiltinInstanceCounter>(counter).unwrap_err();
+                assert!(error.to_string().contains("keccak_builtin"), "{error}");
+            }
+
+            #[test]
+            fn missing_builtin_is_rejected() {
+                let mut counter = full();
+                counter.as_object_mut().unwrap().remove("ec_op_builtin");
+
+                let error = serde_json::from_value::<BuiltinInstanceCounter>(counter).unwrap_err();
+                assert!(error.to_string().contains("ec_op_builtin"), "{error}");
+            }
+        }
     }
 
     /// Represents deserialized L1 to L2 message.
@@ -208,11 +301,103 @@ pub mod transaction {
         #[serde(default)]
         pub actual_fee: Option<Fee>,
         pub events: Vec<Event>,
-        pub execution_resources: ExecutionResources,
+        /// Absent for some of the earliest and for rejected transactions.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub execution_resources: Option<ExecutionResources>,
+        /// Only reported by newer Starknet versions, absent for older blocks.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub execution_status: Option<ExecutionStatus>,
         pub l1_to_l2_consumed_message: Option<L1ToL2Message>,
         pub l2_to_l1_messages: Vec<L2ToL1Message>,
         pub transaction_hash: StarknetTransactionHash,
         pub transaction_index: StarknetTransactionIndex,
+        /// Set on the [placeholders](Receipt::placeholder) of receipts which the gateway did not
+        /// report. Never sent by the gateway itself.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub placeholder: bool,
+    }
+
+    impl Receipt {
+        /// An empty receipt for a transaction whose receipt is missing from its block, which is
+        /// the case for some of the earliest blocks. Its execution resources are unknown, and
+        /// therefore left out.
+        pub fn placeholder(
+            transaction_hash: StarknetTransactionHash,
+            transaction_index: StarknetTransactionIndex,
+        ) -> Self {
+            Self {
+                actual_fee: None,
+                events: Vec::new(),
+                execution_resources: None,
+                execution_status: None,
+                l1_to_l2_consumed_message: None,
+                l2_to_l1_messages: Vec::new(),
+                transaction_hash,
+                transaction_index,
+                placeholder: true,
+            }
+        }
+    }
+
+    /// Pairs each of a block's transactions with its receipt, in the order of the transactions.
+    ///
+    /// Some of the earliest blocks lack the receipts of some or all of their transactions. If
+    /// there are fewer receipts than transactions, receipts are paired by transaction hash
+    /// instead of by position, and transactions without one get a
+    /// [placeholder](Receipt::placeholder).
+    ///
+    /// Fails if there are more receipts than transactions, or a receipt does not belong to any
+    /// of the transactions.
+    pub fn pair_with_receipts(
+        transactions: Vec<Transaction>,
+        mut receipts: Vec<Receipt>,
+    ) -> anyhow::Result<Vec<(Transaction, Receipt)>> {
+        if transactions.len() == receipts.len() {
+            return Ok(transactions.into_iter().zip(receipts).collect());
+        }
+
+        anyhow::ensure!(
+            receipts.len() < transactions.len(),
+            "Transactions and receipts mismatch. There were {} transactions and {} receipts.",
+            transactions.len(),
+            receipts.len()
+        );
+
+        let paired = transactions
+            .into_iter()
+            .enumerate()
+            .map(|(index, transaction)| {
+                let receipt = receipts
+                    .iter()
+                    .position(|receipt| receipt.transaction_hash == transaction.transaction_hash)
+                    .map(|position| receipts.swap_remove(position))
+                    .unwrap_or_else(|| {
+                        Receipt::placeholder(
+                            transaction.transaction_hash,
+                            StarknetTransactionIndex(index as u64),
+                        )
+                    });
+                (transaction, receipt)
+            })
+            .collect();
+
+        if let Some(receipt) = receipts.first() {
+            anyhow::bail!(
+                "Transactions and receipts mismatch. Receipt of transaction {} does not belong to the block.",
+                receipt.transaction_hash.0
+            );
+        }
+
+        Ok(paired)
+    }
+
+    /// Outcome of a transaction's execution.
+    #[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+    pub enum ExecutionStatus {
+        #[serde(rename = "SUCCEEDED")]
+        Succeeded,
+        #[serde(rename = "REVERTED")]
+        Reverted,
     }
 
     /// Represents deserialized L2 transaction event data.
@@ -297,7 +482,7 @@ pub mod transaction {
 
 /// Used to deserialize a reply from
 /// [ClientApi::state_update_by_hash](crate::sequencer::ClientApi::state_update_by_hash).
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct StateUpdate {
     // At the moment when querying by block hash there is an additional `block_hash` field available.
     // Which btw is not available when querying by block number, so let's just ignore it.
@@ -309,22 +494,38 @@ pub struct StateUpdate {
 /// Types used when deserializing state update related data.
 pub mod state_update {
     use crate::core::{ClassHash, ContractAddress, StorageAddress, StorageValue};
-    use serde::Deserialize;
-    use serde_with::serde_as;
-    use std::collections::HashMap;
+    use serde::{Deserialize, Serialize, Serializer};
+    use std::collections::{BTreeMap, HashMap};
 
     /// L2 state diff.
-    #[serde_as]
-    #[derive(Clone, Debug, Deserialize, PartialEq)]
+    ///
+    /// Serializes the storage diffs sorted by contract address and then by storage diff, so that
+    /// the output does not depend on the iteration order of the [HashMap].
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
     #[serde(deny_unknown_fields)]
     pub struct StateDiff {
-        #[serde_as(as = "HashMap<_, Vec<_>>")]
+        #[serde(serialize_with = "serialize_sorted_storage_diffs")]
         pub storage_diffs: HashMap<ContractAddress, Vec<StorageDiff>>,
         pub deployed_contracts: Vec<Contract>,
     }
 
+    fn serialize_sorted_storage_diffs<S: Serializer>(
+        storage_diffs: &HashMap<ContractAddress, Vec<StorageDiff>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        storage_diffs
+            .iter()
+            .map(|(address, diffs)| {
+                let mut diffs = diffs.iter().collect::<Vec<_>>();
+                diffs.sort();
+                (address, diffs)
+            })
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
     /// L2 storage diff.
-    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
     #[serde(deny_unknown_fields)]
     pub struct StorageDiff {
         pub key: StorageAddress,
@@ -332,7 +533,7 @@ pub mod state_update {
     }
 
     /// L2 contract data within state diff.
-    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+    #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
     #[serde(deny_unknown_fields)]
     pub struct Contract {
         pub address: ContractAddress,
@@ -367,6 +568,47 @@ pub mod state_update {
                 expected
             );
         }
+
+        #[test]
+        fn storage_diffs_serialize_sorted() {
+            use super::{ContractAddress, StateDiff, StorageAddress, StorageDiff, StorageValue};
+            use stark_hash::StarkHash;
+
+            let felt = |i: u8| StarkHash::from_be_slice(&[i]).unwrap();
+            let diffs = |contract: u8| {
+                (0..5u8)
+                    .map(|i| StorageDiff {
+                        key: StorageAddress(felt(i)),
+                        value: StorageValue(felt(contract + i)),
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let forward = StateDiff {
+                storage_diffs: (0..20u8)
+                    .map(|contract| (ContractAddress(felt(contract)), diffs(contract)))
+                    .collect(),
+                deployed_contracts: vec![],
+            };
+            let reversed = StateDiff {
+                storage_diffs: (0..20u8)
+                    .rev()
+                    .map(|contract| {
+                        let mut diffs = diffs(contract);
+                        diffs.reverse();
+                        (ContractAddress(felt(contract)), diffs)
+                    })
+                    .collect(),
+                deployed_contracts: vec![],
+            };
+
+            let forward = serde_json::to_vec(&forward).unwrap();
+            let reversed = serde_json::to_vec(&reversed).unwrap();
+            assert_eq!(forward, reversed);
+
+            let parsed = serde_json::from_slice::<StateDiff>(&forward).unwrap();
+            assert_eq!(parsed.storage_diffs[&ContractAddress(felt(3))], diffs(3));
+        }
     }
 }
 
@@ -455,3 +697,225 @@ pub mod add_transaction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod block {
+        use super::*;
+
+        /// Serializing a block and parsing it again must yield the same block.
+        fn round_trip(fixture: &str) {
+            let block = serde_json::from_str::<Block>(fixture).unwrap();
+
+            let serialized = serde_json::to_string(&block).unwrap();
+            let parsed = serde_json::from_str::<Block>(&serialized).unwrap();
+
+            assert_eq!(parsed, block);
+        }
+
+        #[test]
+        fn genesis() {
+            round_trip(include_str!("../../fixtures/sequencer/genesis_block.json"));
+        }
+
+        #[test]
+        fn block_1716() {
+            round_trip(include_str!("../../fixtures/sequencer/block_1716.json"));
+        }
+
+        #[test]
+        fn block_200k() {
+            round_trip(include_str!("../../fixtures/sequencer/block_200k.json"));
+        }
+
+        #[test]
+        fn pending() {
+            round_trip(include_str!("../../fixtures/sequencer/pending_block.json"));
+        }
+
+        #[test]
+        fn earliest_shape() {
+            // The earliest blocks lack the gas price and sequencer address, and report the
+            // status of L1 acceptance differently.
+            let mut json: serde_json::Value =
+                serde_json::from_str(include_str!("../../fixtures/sequencer/genesis_block.json"))
+                    .unwrap();
+            let object = json.as_object_mut().unwrap();
+            object.remove("gas_price");
+            object.remove("sequencer_address");
+            object.insert("status".to_owned(), serde_json::json!("ACCEPTED_ONCHAIN"));
+
+            let block = serde_json::from_value::<Block>(json).unwrap();
+            assert_eq!(block.gas_price, None);
+            assert_eq!(block.sequencer_address, None);
+            assert_eq!(block.status, Status::AcceptedOnL1);
+            assert!(block.is_final());
+        }
+    }
+
+    mod pair_with_receipts {
+        use super::transaction::{pair_with_receipts, Receipt};
+        use super::*;
+
+        /// No block lacking receipts has been captured as a fixture, so the tests remove receipts
+        /// from the genesis block instead.
+        fn genesis() -> Block {
+            serde_json::from_str(include_str!("../../fixtures/sequencer/genesis_block.json"))
+                .unwrap()
+        }
+
+        #[test]
+        fn by_position() {
+            let block = genesis();
+            let expected = block
+                .transactions
+                .iter()
+                .cloned()
+                .zip(block.transaction_receipts.iter().cloned())
+                .collect::<Vec<_>>();
+
+            let paired =
+                pair_with_receipts(block.transactions, block.transaction_receipts).unwrap();
+            assert_eq!(paired, expected);
+        }
+
+        #[test]
+        fn missing_receipts_are_replaced_by_placeholders() {
+            let block = genesis();
+            let mut receipts = block.transaction_receipts.clone();
+            // Drop the first receipt and shuffle the others.
+            receipts.remove(0);
+            receipts.reverse();
+
+            let paired = pair_with_receipts(block.transactions.clone(), receipts).unwrap();
+
+            assert_eq!(paired.len(), block.transactions.len());
+            let (transaction, placeholder) = &paired[0];
+            assert_eq!(transaction, &block.transactions[0]);
+            assert_eq!(
+                placeholder,
+                &Receipt::placeholder(
+                    transaction.transaction_hash,
+                    crate::core::StarknetTransactionIndex(0)
+                )
+            );
+            for ((transaction, receipt), expected) in
+                paired.iter().zip(&block.transaction_receipts).skip(1)
+            {
+                assert_eq!(receipt.transaction_hash, transaction.transaction_hash);
+                assert_eq!(receipt, expected);
+            }
+        }
+
+        #[test]
+        fn without_any_receipts() {
+            let block = genesis();
+            let paired = pair_with_receipts(block.transactions, Vec::new()).unwrap();
+            assert!(paired.iter().all(|(_, receipt)| receipt.placeholder));
+        }
+
+        #[test]
+        fn foreign_receipt_is_rejected() {
+            let block = genesis();
+            let mut receipts = block.transaction_receipts.clone();
+            receipts.truncate(2);
+            receipts[1].transaction_hash = crate::core::StarknetTransactionHash(
+                stark_hash::StarkHash::from_hex_str("0x1234").unwrap(),
+            );
+
+            let error = pair_with_receipts(block.transactions, receipts).unwrap_err();
+            assert!(error.to_string().contains("does not belong"), "{error}");
+        }
+
+        #[test]
+        fn excess_receipts_are_rejected() {
+            let mut block = genesis();
+            block.transactions.pop();
+
+            let error =
+                pair_with_receipts(block.transactions, block.transaction_receipts).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains("Transactions and receipts mismatch"),
+                "{error}"
+            );
+        }
+    }
+
+    mod receipt {
+        use super::transaction::{ExecutionStatus, Receipt};
+
+        fn receipt_json() -> serde_json::Value {
+            let (_, receipt) =
+                crate::storage::test_utils::create_transactions_and_receipts::<1>()[0].clone();
+            serde_json::to_value(&receipt).unwrap()
+        }
+
+        #[test]
+        fn without_execution_status() {
+            let json = receipt_json();
+            assert!(json.get("execution_status").is_none());
+
+            let receipt = serde_json::from_value::<Receipt>(json.clone()).unwrap();
+            assert_eq!(receipt.execution_status, None);
+            // Older receipts serialize as before.
+            assert_eq!(serde_json::to_value(&receipt).unwrap(), json);
+        }
+
+        #[test]
+        fn without_execution_resources() {
+            let mut json = receipt_json();
+            json.as_object_mut().unwrap().remove("execution_resources");
+
+            let receipt = serde_json::from_value::<Receipt>(json.clone()).unwrap();
+            assert_eq!(receipt.execution_resources, None);
+            assert_eq!(serde_json::to_value(&receipt).unwrap(), json);
+
+            // Receipts which report them are unaffected.
+            let json = receipt_json();
+            let receipt = serde_json::from_value::<Receipt>(json.clone()).unwrap();
+            assert!(receipt.execution_resources.is_some());
+            assert_eq!(serde_json::to_value(&receipt).unwrap(), json);
+        }
+
+        #[test]
+        fn with_execution_status() {
+            for (value, expected) in [
+                ("SUCCEEDED", ExecutionStatus::Succeeded),
+                ("REVERTED", ExecutionStatus::Reverted),
+            ] {
+                let mut json = receipt_json();
+                json["execution_status"] = serde_json::json!(value);
+
+                let receipt = serde_json::from_value::<Receipt>(json.clone()).unwrap();
+                assert_eq!(receipt.execution_status, Some(expected));
+                assert_eq!(serde_json::to_value(&receipt).unwrap(), json);
+            }
+
+            let mut json = receipt_json();
+            json["execution_status"] = serde_json::json!("UNKNOWN");
+            serde_json::from_value::<Receipt>(json).unwrap_err();
+        }
+
+        #[test]
+        fn placeholder() {
+            let (_, receipt) =
+                crate::storage::test_utils::create_transactions_and_receipts::<1>()[0].clone();
+            let placeholder =
+                Receipt::placeholder(receipt.transaction_hash, receipt.transaction_index);
+
+            assert_eq!(placeholder.execution_resources, None);
+
+            let json = serde_json::to_value(&placeholder).unwrap();
+            assert_eq!(json["placeholder"], serde_json::json!(true));
+            assert_eq!(
+                serde_json::from_value::<Receipt>(json).unwrap(),
+                placeholder
+            );
+            assert!(receipt_json().get("placeholder").is_none());
+        }
+    }
+}
@@ -1,10 +1,8 @@
 //! Structures used for serializing requests to Starkware's sequencer REST API.
 use crate::{
     core::{CallParam, CallSignatureElem, ContractAddress, EntryPoint, Fee},
-    rpc::{
-        serde::{CallParamAsDecimalStr, CallSignatureElemAsDecimalStr},
-        types::request as rpc,
-    },
+    rpc::types::request as rpc,
+    serde::{CallParamAsDecimalStr, CallSignatureElemAsDecimalStr},
 };
 use serde::Serialize;
 use std::convert::From;
@@ -75,7 +73,7 @@ pub mod add_transaction {
     use crate::core::{
         ConstructorParam, ContractAddressSalt, TransactionNonce, TransactionVersion,
     };
-    use crate::rpc::serde::{
+    use crate::serde::{
         CallParamAsDecimalStr, CallSignatureElemAsDecimalStr, FeeAsHexStr,
         TransactionVersionAsHexStr,
     };
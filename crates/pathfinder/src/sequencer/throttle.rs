@@ -0,0 +1,239 @@
+//! Spaces out the requests made to a gateway.
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use super::{error::SequencerError, reply, request, ClientApi};
+use crate::{
+    core::{
+        CallSignatureElem, ClassHash, ConstructorParam, ContractAddress, ContractAddressSalt, Fee,
+        StarknetTransactionHash, StorageAddress, StorageValue, TransactionNonce,
+        TransactionVersion,
+    },
+    rpc::types::{BlockHashOrTag, BlockNumberOrTag},
+};
+
+/// A [ClientApi] which starts at most one query every `interval`, shared by all of its clones.
+///
+/// Queries wait for their turn in the order they were made. Only the first attempt of a query is
+/// spaced out, retries are up to the wrapped client.
+#[derive(Debug)]
+pub struct Throttled<C> {
+    inner: Arc<C>,
+    interval: Duration,
+    /// The earliest time at which the next query may start.
+    next: Arc<Mutex<Instant>>,
+}
+
+impl<C> Clone for Throttled<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            interval: self.interval,
+            next: self.next.clone(),
+        }
+    }
+}
+
+impl<C> Throttled<C> {
+    pub fn new(inner: C, interval: Duration) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            interval,
+            next: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Waits until the next query may start.
+    async fn turn(&self) {
+        // The lock is held while waiting, which queues up the other queries.
+        let mut next = self.next.lock().await;
+        tokio::time::sleep_until(*next).await;
+        *next = Instant::now() + self.interval;
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: ClientApi + Send + Sync> ClientApi for Throttled<C> {
+    async fn block_by_number(
+        &self,
+        block_number: BlockNumberOrTag,
+    ) -> Result<reply::Block, SequencerError> {
+        self.turn().await;
+        self.inner.block_by_number(block_number).await
+    }
+
+    async fn raw_block_by_number(
+        &self,
+        block_number: BlockNumberOrTag,
+    ) -> Result<bytes::Bytes, SequencerError> {
+        self.turn().await;
+        self.inner.raw_block_by_number(block_number).await
+    }
+
+    async fn block_by_hash(
+        &self,
+        block_hash: BlockHashOrTag,
+    ) -> Result<reply::Block, SequencerError> {
+        self.turn().await;
+        self.inner.block_by_hash(block_hash).await
+    }
+
+    async fn call(
+        &self,
+        payload: request::Call,
+        block_hash: BlockHashOrTag,
+    ) -> Result<reply::Call, SequencerError> {
+        self.turn().await;
+        self.inner.call(payload, block_hash).await
+    }
+
+    async fn full_contract(
+        &self,
+        contract_addr: ContractAddress,
+    ) -> Result<bytes::Bytes, SequencerError> {
+        self.turn().await;
+        self.inner.full_contract(contract_addr).await
+    }
+
+    async fn class_by_hash(&self, class_hash: ClassHash) -> Result<bytes::Bytes, SequencerError> {
+        self.turn().await;
+        self.inner.class_by_hash(class_hash).await
+    }
+
+    async fn storage(
+        &self,
+        contract_addr: ContractAddress,
+        key: StorageAddress,
+        block_hash: BlockHashOrTag,
+    ) -> Result<StorageValue, SequencerError> {
+        self.turn().await;
+        self.inner.storage(contract_addr, key, block_hash).await
+    }
+
+    async fn transaction(
+        &self,
+        transaction_hash: StarknetTransactionHash,
+    ) -> Result<reply::Transaction, SequencerError> {
+        self.turn().await;
+        self.inner.transaction(transaction_hash).await
+    }
+
+    async fn transaction_status(
+        &self,
+        transaction_hash: StarknetTransactionHash,
+    ) -> Result<reply::TransactionStatus, SequencerError> {
+        self.turn().await;
+        self.inner.transaction_status(transaction_hash).await
+    }
+
+    async fn state_update_by_hash(
+        &self,
+        block_hash: BlockHashOrTag,
+    ) -> Result<reply::StateUpdate, SequencerError> {
+        self.turn().await;
+        self.inner.state_update_by_hash(block_hash).await
+    }
+
+    async fn state_update_by_number(
+        &self,
+        block_number: BlockNumberOrTag,
+    ) -> Result<reply::StateUpdate, SequencerError> {
+        self.turn().await;
+        self.inner.state_update_by_number(block_number).await
+    }
+
+    async fn eth_contract_addresses(&self) -> Result<reply::EthContractAddresses, SequencerError> {
+        self.turn().await;
+        self.inner.eth_contract_addresses().await
+    }
+
+    async fn add_invoke_transaction(
+        &self,
+        function_invocation: request::Call,
+        max_fee: Fee,
+        version: TransactionVersion,
+    ) -> Result<reply::add_transaction::InvokeResponse, SequencerError> {
+        self.turn().await;
+        self.inner
+            .add_invoke_transaction(function_invocation, max_fee, version)
+            .await
+    }
+
+    async fn add_declare_transaction(
+        &self,
+        contract_definition: request::add_transaction::ContractDefinition,
+        sender_address: ContractAddress,
+        max_fee: Fee,
+        signature: Vec<CallSignatureElem>,
+        nonce: TransactionNonce,
+        version: TransactionVersion,
+        token: Option<String>,
+    ) -> Result<reply::add_transaction::DeclareResponse, SequencerError> {
+        self.turn().await;
+        self.inner
+            .add_declare_transaction(
+                contract_definition,
+                sender_address,
+                max_fee,
+                signature,
+                nonce,
+                version,
+                token,
+            )
+            .await
+    }
+
+    async fn add_deploy_transaction(
+        &self,
+        contract_address_salt: ContractAddressSalt,
+        constructor_calldata: Vec<ConstructorParam>,
+        contract_definition: request::add_transaction::ContractDefinition,
+        token: Option<String>,
+    ) -> Result<reply::add_transaction::DeployResponse, SequencerError> {
+        self.turn().await;
+        self.inner
+            .add_deploy_transaction(
+                contract_address_salt,
+                constructor_calldata,
+                contract_definition,
+                token,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::types::Tag;
+    use crate::sequencer::error::{StarknetError, StarknetErrorCode};
+    use crate::sequencer::MockClientApi;
+
+    #[tokio::test(start_paused = true)]
+    async fn queries_are_spaced_out() {
+        let mut mock = MockClientApi::new();
+        mock.expect_block_by_number().times(3).returning(|_| {
+            Err(StarknetError {
+                code: StarknetErrorCode::BlockNotFound,
+                message: String::new(),
+            }
+            .into())
+        });
+        let interval = Duration::from_secs(1);
+        let client = Throttled::new(mock, interval);
+        let clone = client.clone();
+        let latest = BlockNumberOrTag::Tag(Tag::Latest);
+
+        let start = Instant::now();
+        client.block_by_number(latest).await.unwrap_err();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // Clones share the interval.
+        clone.block_by_number(latest).await.unwrap_err();
+        client.block_by_number(latest).await.unwrap_err();
+        assert_eq!(start.elapsed(), 2 * interval);
+    }
+}
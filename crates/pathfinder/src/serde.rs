@@ -1,4 +1,11 @@
-//! Utilities used for serializing/deserializing sequencer REST API related data.
+//! Utilities used for serializing/deserializing sequencer REST API and JSON-RPC API related data.
+//!
+//! These adapters are shared by the [sequencer](crate::sequencer) and [rpc](crate::rpc) types,
+//! and therefore must not depend on either of them.
+//!
+//! All hex adapters serialize to a lower case, "0x" prefixed string without leading zeros, and
+//! accept upper or lower case digits with an optional "0x" prefix and any number of leading zeros.
+//! An empty string (or a lone "0x") is rejected.
 
 use crate::core::{
     CallParam, CallSignatureElem, ConstructorParam, EthereumAddress, EventData, EventKey, Fee,
@@ -271,10 +278,9 @@ impl<'de> DeserializeAs<'de, StarknetBlockNumber> for StarknetBlockNumberAsHexSt
             where
                 E: serde::de::Error,
             {
-                let stripped = v.strip_prefix("0x").unwrap_or(v);
-                u64::from_str_radix(stripped, 16)
+                bytes_from_hex_str::<8>(v)
                     .map_err(serde::de::Error::custom)
-                    .map(StarknetBlockNumber)
+                    .map(|b| StarknetBlockNumber(u64::from_be_bytes(b)))
             }
         }
 
@@ -302,24 +308,27 @@ pub(crate) fn starkhash_to_dec_str(h: &StarkHash) -> String {
 }
 
 /// A helper conversion function. Only use with __sequencer API related types__.
+///
+/// Only plain decimal digits are accepted, i.e. no sign and no `_` separators.
 fn starkhash_from_dec_str(s: &str) -> Result<StarkHash, anyhow::Error> {
-    // TODO remove fallback to hex string representation once mainnet moves to cairo-0.8.0
-    match BigUint::from_str(s) {
-        Ok(b) => {
-            let h = starkhash_from_biguint(b)?;
-            Ok(h)
-        }
-        Err(_) => {
-            let h = StarkHash::from_hex_str(s)?;
-            Ok(h)
-        }
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        // Cannot fail as the string consists of decimal digits only.
+        let b = BigUint::from_str(s)?;
+        let h = starkhash_from_biguint(b)?;
+        return Ok(h);
     }
+
+    // TODO remove fallback to hex string representation once mainnet moves to cairo-0.8.0
+    let b = bytes_from_hex_str::<32>(s)?;
+    let h = StarkHash::from_be_bytes(b)?;
+    Ok(h)
 }
 
 /// A convenience function which parses a hex string into a byte array.
 ///
 /// Supports both upper and lower case hex strings, as well as an
-/// optional "0x" prefix.
+/// optional "0x" prefix. Leading zeros are ignored, so the string may have more
+/// than `2 * N` digits as long as the value fits. An empty string is rejected.
 fn bytes_from_hex_str<const N: usize>(hex_str: &str) -> Result<[u8; N], HexParseError> {
     fn parse_hex_digit(digit: u8) -> Result<u8, HexParseError> {
         match digit {
@@ -331,9 +340,15 @@ fn bytes_from_hex_str<const N: usize>(hex_str: &str) -> Result<[u8; N], HexParse
     }
 
     let hex_str = hex_str.strip_prefix("0x").unwrap_or(hex_str);
-    if hex_str.len() > N * 2 {
+    if hex_str.is_empty() {
+        return Err(HexParseError::InvalidLength(0));
+    }
+
+    let digits = hex_str.trim_start_matches('0');
+    if digits.len() > N * 2 {
         return Err(HexParseError::InvalidLength(hex_str.len()));
     }
+    let hex_str = digits;
 
     let mut buf = [0u8; N];
 
@@ -629,5 +644,394 @@ mod tests {
                 );
             });
         }
+
+        #[test]
+        fn rejects_signs_and_empty() {
+            for s in [
+                "\"\"",
+                "\"0x\"",
+                "\"+1\"",
+                "\"-1\"",
+                "\"0x10000000000000000\"",
+            ] {
+                serde_json::from_str::<BlockNum>(s).unwrap_err();
+            }
+        }
+    }
+
+    mod edge_cases {
+        use super::*;
+
+        #[test]
+        fn empty_hex_str_is_rejected() {
+            assert_eq!(
+                bytes_from_hex_str::<32>(""),
+                Err(HexParseError::InvalidLength(0))
+            );
+            assert_eq!(
+                bytes_from_hex_str::<32>("0x"),
+                Err(HexParseError::InvalidLength(0))
+            );
+        }
+
+        #[test]
+        fn hex_str_leading_zeros_are_ignored() {
+            let padded = format!("0x{}1234", "0".repeat(100));
+            let expected = {
+                let mut b = [0u8; 8];
+                b[6..].copy_from_slice(&[0x12, 0x34]);
+                b
+            };
+            assert_eq!(bytes_from_hex_str::<8>(&padded).unwrap(), expected);
+            assert_eq!(
+                bytes_from_hex_str::<8>("0x0000000000000000000").unwrap(),
+                [0u8; 8]
+            );
+
+            // Non-zero digits beyond the width are still rejected.
+            assert_eq!(
+                bytes_from_hex_str::<8>("0x10000000000000000"),
+                Err(HexParseError::InvalidLength(17))
+            );
+        }
+
+        #[test]
+        fn dec_str_rejects_empty_sign_and_separators() {
+            assert_eq!(
+                starkhash_from_dec_str("")
+                    .unwrap_err()
+                    .downcast::<HexParseError>()
+                    .unwrap(),
+                HexParseError::InvalidLength(0)
+            );
+            assert_eq!(
+                starkhash_from_dec_str("-1")
+                    .unwrap_err()
+                    .downcast::<HexParseError>()
+                    .unwrap(),
+                HexParseError::InvalidNibble(b'-')
+            );
+            assert_eq!(
+                starkhash_from_dec_str("+1")
+                    .unwrap_err()
+                    .downcast::<HexParseError>()
+                    .unwrap(),
+                HexParseError::InvalidNibble(b'+')
+            );
+            assert_eq!(
+                starkhash_from_dec_str("1_000")
+                    .unwrap_err()
+                    .downcast::<HexParseError>()
+                    .unwrap(),
+                HexParseError::InvalidNibble(b'_')
+            );
+        }
+
+        #[test]
+        fn dec_str_leading_zeros() {
+            let padded = format!("{}123", "0".repeat(100));
+            assert_eq!(
+                starkhash_from_dec_str(&padded).unwrap(),
+                StarkHash::from_hex_str("0x7b").unwrap()
+            );
+        }
+
+        #[test]
+        fn hex_fallback_overflow() {
+            assert_eq!(
+                starkhash_from_dec_str(
+                    "0x800000000000011000000000000000000000000000000000000000000000001"
+                )
+                .unwrap_err()
+                .downcast::<OverflowError>()
+                .unwrap(),
+                OverflowError
+            );
+        }
+    }
+
+    /// Wraps a value so that it is (de)serialized using the given adapter.
+    macro_rules! adapted {
+        ($name:ident, $adapter:tt, $inner:ty) => {
+            #[serde_with::serde_as]
+            #[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+            struct $name(#[serde_as(as = $adapter)] $inner);
+        };
+    }
+
+    adapted!(AdaptedCallParam, "CallParamAsDecimalStr", CallParam);
+    adapted!(
+        AdaptedCallSignatureElem,
+        "CallSignatureElemAsDecimalStr",
+        CallSignatureElem
+    );
+    adapted!(
+        AdaptedConstructorParam,
+        "ConstructorParamAsDecimalStr",
+        ConstructorParam
+    );
+    adapted!(AdaptedEventData, "EventDataAsDecimalStr", EventData);
+    adapted!(AdaptedEventKey, "EventKeyAsDecimalStr", EventKey);
+    adapted!(
+        AdaptedL1ToL2MessagePayloadElem,
+        "L1ToL2MessagePayloadElemAsDecimalStr",
+        L1ToL2MessagePayloadElem
+    );
+    adapted!(
+        AdaptedL2ToL1MessagePayloadElem,
+        "L2ToL1MessagePayloadElemAsDecimalStr",
+        L2ToL1MessagePayloadElem
+    );
+    adapted!(
+        AdaptedTransactionSignatureElem,
+        "TransactionSignatureElemAsDecimalStr",
+        TransactionSignatureElem
+    );
+    adapted!(
+        AdaptedEthereumAddress,
+        "EthereumAddressAsHexStr",
+        EthereumAddress
+    );
+    adapted!(AdaptedH256, "H256AsNoLeadingZerosHexStr", H256);
+    adapted!(AdaptedFee, "FeeAsHexStr", Fee);
+    adapted!(AdaptedGasPrice, "GasPriceAsHexStr", GasPrice);
+    adapted!(
+        AdaptedStarknetBlockNumber,
+        "StarknetBlockNumberAsHexStr",
+        StarknetBlockNumber
+    );
+    adapted!(
+        AdaptedTransactionVersion,
+        "TransactionVersionAsHexStr",
+        TransactionVersion
+    );
+
+    /// The canonical hex representation of big-endian `bytes`.
+    fn canonical_hex(bytes: &[u8]) -> String {
+        let digits = hex::encode(bytes);
+        match digits.trim_start_matches('0') {
+            "" => "0x0".to_owned(),
+            digits => format!("0x{}", digits),
+        }
+    }
+
+    mod roundtrip {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Any valid [StarkHash], i.e. one below the field modulus.
+        fn stark_hash() -> impl Strategy<Value = StarkHash> {
+            any::<[u8; 32]>().prop_map(|mut bytes| {
+                // Values below 2^251 are always valid.
+                bytes[0] &= 0x07;
+                StarkHash::from_be_bytes(bytes).unwrap()
+            })
+        }
+
+        /// Serializes `value`, checks the output against `expected` and deserializes it again,
+        /// both as is and with upper case digits and additional leading zeros.
+        fn check<T>(value: T, expected: &str)
+        where
+            T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+        {
+            let json = serde_json::to_string(&value).unwrap();
+            assert_eq!(json, format!("\"{}\"", expected));
+            assert_eq!(serde_json::from_str::<T>(&json).unwrap(), value);
+
+            let variant = match expected.strip_prefix("0x") {
+                Some(digits) => format!("\"0x000{}\"", digits.to_uppercase()),
+                None => format!("\"000{}\"", expected),
+            };
+            assert_eq!(serde_json::from_str::<T>(&variant).unwrap(), value);
+        }
+
+        macro_rules! dec_str_roundtrip {
+            ($test:ident, $adapted:ident, $inner:ident) => {
+                proptest! {
+                    #[test]
+                    fn $test(hash in stark_hash()) {
+                        let expected = starkhash_to_dec_str(&hash);
+                        check($adapted($inner(hash)), &expected);
+                    }
+                }
+            };
+        }
+
+        dec_str_roundtrip!(call_param, AdaptedCallParam, CallParam);
+        dec_str_roundtrip!(
+            call_signature_elem,
+            AdaptedCallSignatureElem,
+            CallSignatureElem
+        );
+        dec_str_roundtrip!(constructor_param, AdaptedConstructorParam, ConstructorParam);
+        dec_str_roundtrip!(event_data, AdaptedEventData, EventData);
+        dec_str_roundtrip!(event_key, AdaptedEventKey, EventKey);
+        dec_str_roundtrip!(
+            l1_to_l2_message_payload_elem,
+            AdaptedL1ToL2MessagePayloadElem,
+            L1ToL2MessagePayloadElem
+        );
+        dec_str_roundtrip!(
+            l2_to_l1_message_payload_elem,
+            AdaptedL2ToL1MessagePayloadElem,
+            L2ToL1MessagePayloadElem
+        );
+        dec_str_roundtrip!(
+            transaction_signature_elem,
+            AdaptedTransactionSignatureElem,
+            TransactionSignatureElem
+        );
+
+        proptest! {
+            #[test]
+            fn dec_str_matches_biguint(hash in stark_hash()) {
+                let expected = BigUint::from_bytes_be(&hash.to_be_bytes()).to_string();
+                prop_assert_eq!(starkhash_to_dec_str(&hash), expected);
+            }
+
+            #[test]
+            fn ethereum_address(bytes in any::<[u8; 20]>()) {
+                let address = EthereumAddress(H160::from(bytes));
+                check(AdaptedEthereumAddress(address), &canonical_hex(&bytes));
+            }
+
+            #[test]
+            fn h256(bytes in any::<[u8; 32]>()) {
+                check(AdaptedH256(H256::from(bytes)), &canonical_hex(&bytes));
+            }
+
+            #[test]
+            fn fee(bytes in any::<[u8; 16]>()) {
+                check(AdaptedFee(Fee(H128::from(bytes))), &canonical_hex(&bytes));
+            }
+
+            #[test]
+            fn gas_price(price in any::<u128>()) {
+                check(AdaptedGasPrice(GasPrice(price)), &format!("{:#x}", price));
+            }
+
+            #[test]
+            fn starknet_block_number(number in any::<u64>()) {
+                check(
+                    AdaptedStarknetBlockNumber(StarknetBlockNumber(number)),
+                    &format!("{:#x}", number),
+                );
+            }
+
+            #[test]
+            fn transaction_version(bytes in any::<[u8; 32]>()) {
+                check(
+                    AdaptedTransactionVersion(TransactionVersion(H256::from(bytes))),
+                    &canonical_hex(&bytes),
+                );
+            }
+
+            #[test]
+            fn hex_str_within_width(bytes in any::<[u8; 16]>(), zeros in 0usize..80) {
+                let digits = canonical_hex(&bytes);
+                let padded = format!("0x{}{}", "0".repeat(zeros), &digits[2..]);
+                prop_assert_eq!(bytes_from_hex_str::<16>(&padded).unwrap(), bytes);
+                // The prefix is optional.
+                prop_assert_eq!(bytes_from_hex_str::<16>(&padded[2..]).unwrap(), bytes);
+            }
+        }
+    }
+
+    /// Values taken from actual gateway replies.
+    mod golden {
+        use super::*;
+
+        fn check<T>(input: &str, expected: T, output: &str)
+        where
+            T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+        {
+            let value = serde_json::from_str::<T>(input).unwrap();
+            assert_eq!(value, expected);
+            assert_eq!(serde_json::to_string(&value).unwrap(), output);
+        }
+
+        fn hash(hex: &str) -> StarkHash {
+            StarkHash::from_hex_str(hex).unwrap()
+        }
+
+        #[test]
+        fn gas_price() {
+            check(
+                r#""0x59682f07""#,
+                AdaptedGasPrice(GasPrice(1_500_000_007)),
+                r#""0x59682f07""#,
+            );
+            check(
+                r#""0x174876e800""#,
+                AdaptedGasPrice(GasPrice(100_000_000_000)),
+                r#""0x174876e800""#,
+            );
+        }
+
+        #[test]
+        fn actual_fee() {
+            check(r#""0x0""#, AdaptedFee(Fee(H128::zero())), r#""0x0""#);
+        }
+
+        #[test]
+        fn l1_sender_address() {
+            // Mixed case checksum addresses are normalized to lower case.
+            let bytes: [u8; 20] = hex::decode("15b01475bb3070912216dc393c3a782cc90fa1f7")
+                .unwrap()
+                .try_into()
+                .unwrap();
+            check(
+                r#""0x15B01475bb3070912216DC393C3a782cC90Fa1F7""#,
+                AdaptedEthereumAddress(EthereumAddress(H160::from(bytes))),
+                r#""0x15b01475bb3070912216dc393c3a782cc90fa1f7""#,
+            );
+        }
+
+        #[test]
+        fn calldata() {
+            // Pre cairo-0.8.0 calldata is hex encoded, it is always serialized as decimal.
+            check(
+                r#""0xae27010336608000""#,
+                AdaptedCallParam(CallParam(hash("0xae27010336608000"))),
+                r#""12549000000000000000""#,
+            );
+            check(
+                r#""12549000000000000000""#,
+                AdaptedCallParam(CallParam(hash("0xae27010336608000"))),
+                r#""12549000000000000000""#,
+            );
+            check(
+                r#""0x10478d93173c61e0599792695ac8dfc849b65659689627a5c4216c45271f2bf""#,
+                AdaptedCallParam(CallParam(hash(
+                    "0x10478d93173c61e0599792695ac8dfc849b65659689627a5c4216c45271f2bf",
+                ))),
+                &format!(
+                    "\"{}\"",
+                    starkhash_to_dec_str(&hash(
+                        "0x10478d93173c61e0599792695ac8dfc849b65659689627a5c4216c45271f2bf"
+                    ))
+                ),
+            );
+        }
+
+        #[test]
+        fn l1_to_l2_payload() {
+            check(
+                r#""0x16345785d8a0000""#,
+                AdaptedL1ToL2MessagePayloadElem(L1ToL2MessagePayloadElem(hash(
+                    "0x16345785d8a0000",
+                ))),
+                r#""100000000000000000""#,
+            );
+        }
+
+        #[test]
+        fn block_number() {
+            check(
+                r#""0x30d40""#,
+                AdaptedStarknetBlockNumber(StarknetBlockNumber(200_000)),
+                r#""0x30d40""#,
+            );
+        }
     }
 }
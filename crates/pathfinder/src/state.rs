@@ -3,20 +3,38 @@ use rusqlite::Transaction;
 use stark_hash::{stark_hash, StarkHash};
 
 use crate::{
-    core::{ClassHash, ContractRoot, ContractStateHash},
-    ethereum::state_update::ContractUpdate,
+    core::{ClassHash, ContractAddress, ContractRoot, ContractStateHash, GlobalRoot},
+    ethereum::state_update::{ContractUpdate, StateUpdate},
+    sequencer::reply,
     state::state_tree::{ContractsStateTree, GlobalStateTree},
     storage::{ContractsStateTable, ContractsTable},
 };
 
+pub mod block_backfill;
+pub mod class_backfill;
 pub(crate) mod class_hash;
+pub(crate) mod diff;
+pub mod follow;
 mod merkle_node;
 pub(crate) mod merkle_tree;
-pub(crate) mod state_tree;
+mod parallel_update;
+mod pending;
+pub mod reprocess;
+pub mod shadow;
+pub mod state_tree;
 mod sync;
+pub mod sync_retry;
+pub(crate) mod transaction_commitment;
+pub(crate) mod transaction_hash;
+pub mod tree_gc;
 
 pub use class_hash::compute_class_hash;
-pub use sync::{l1, l2, sync, State as SyncState};
+pub use pending::{PendingBlock, PendingData};
+pub use sync::{
+    l1, l2, sync, DeepReorg, StallWatchdog, State as SyncState, SyncEvent, SyncOptions,
+    Throughput as SyncThroughput,
+};
+pub use transaction_hash::{compute_legacy_transaction_hash, compute_transaction_hash};
 
 #[derive(Clone, PartialEq)]
 pub struct CompressedContract {
@@ -37,6 +55,60 @@ impl std::fmt::Debug for CompressedContract {
     }
 }
 
+/// Applies the sequencer's `state_update` on top of its `old_root`, and returns the new global
+/// root.
+///
+/// This deploys the new contracts, updates the storage tree and [ContractStateHash] of each
+/// updated contract and then the global tree. It fails if the resulting root is not the
+/// `new_root` of `state_update`, in which case `transaction` should be dropped.
+pub fn apply_state_update(
+    transaction: &Transaction<'_>,
+    state_update: &reply::StateUpdate,
+) -> anyhow::Result<GlobalRoot> {
+    let diff = StateUpdate::from(state_update.state_diff.clone());
+    let new_root = sync::apply_diff(transaction, state_update.old_root, &diff, None)
+        .context("Apply state diff")?;
+
+    anyhow::ensure!(
+        new_root == state_update.new_root,
+        "Global root mismatch: computed {}, but the state update has {}",
+        new_root.0,
+        state_update.new_root.0
+    );
+
+    Ok(new_root)
+}
+
+/// Returns the root of the storage tree of the contract at `address`, in the state with the
+/// global `block_root`.
+///
+/// Returns `None` if the contract is not deployed in that state, or if the [ContractsStateTable]
+/// has no root for its [ContractStateHash].
+pub fn contract_storage_root(
+    transaction: &Transaction<'_>,
+    block_root: GlobalRoot,
+    address: ContractAddress,
+) -> anyhow::Result<Option<ContractRoot>> {
+    let global_tree =
+        GlobalStateTree::load(transaction, block_root).context("Load global state tree")?;
+    let contract_state_hash = global_tree
+        .get(address)
+        .context("Get contract state hash from global state tree")?;
+
+    if contract_state_hash.0 == StarkHash::ZERO {
+        return Ok(None);
+    }
+
+    ContractsStateTable::get_root(transaction, contract_state_hash)
+        .context("Read contract root from contracts state table")
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The number of contract storage trees loaded by [update_contract_state] on this thread.
+    pub(crate) static CONTRACT_TREE_LOADS: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
 /// Updates a contract's state with the given [storage updates](ContractUpdate). It returns the
 /// [ContractStateHash] of the new state.
 ///
@@ -55,6 +127,8 @@ pub(crate) fn update_contract_state(
         .unwrap_or(ContractRoot(StarkHash::ZERO));
 
     // Load the contract tree and insert the updates.
+    #[cfg(test)]
+    CONTRACT_TREE_LOADS.with(|loads| loads.set(loads.get() + 1));
     let mut contract_tree =
         ContractsStateTree::load(db, contract_root).context("Load contract state tree")?;
     for storage_update in &update.storage_updates {
@@ -99,6 +173,215 @@ mod tests {
     use crate::core::{ClassHash, ContractRoot, ContractStateHash};
     use stark_hash::StarkHash;
 
+    mod contract_storage_root {
+        use super::super::{calculate_contract_state_hash, contract_storage_root};
+        use crate::core::{
+            ClassHash, ContractAddress, ContractRoot, GlobalRoot, StorageAddress, StorageValue,
+        };
+        use crate::state::state_tree::{ContractsStateTree, GlobalStateTree};
+        use crate::storage::{ContractsStateTable, Storage};
+        use rusqlite::Transaction;
+        use stark_hash::StarkHash;
+
+        fn felt(hex: &str) -> StarkHash {
+            StarkHash::from_hex_str(hex).unwrap()
+        }
+
+        /// Writes `value` to a storage slot of the contract `a` in the state with `root`, and
+        /// returns the new global root along with the contract's new storage root.
+        fn write(
+            transaction: &Transaction<'_>,
+            root: GlobalRoot,
+            value: &str,
+        ) -> (GlobalRoot, ContractRoot) {
+            let class = ClassHash(felt("c1a55"));
+            let mut contract_tree =
+                ContractsStateTree::load(transaction, ContractRoot(StarkHash::ZERO)).unwrap();
+            contract_tree
+                .set(StorageAddress(felt("1")), StorageValue(felt(value)))
+                .unwrap();
+            let contract_root = contract_tree.apply().unwrap();
+
+            let state_hash = calculate_contract_state_hash(class, contract_root);
+            ContractsStateTable::upsert(transaction, state_hash, class, contract_root).unwrap();
+
+            let mut global_tree = GlobalStateTree::load(transaction, root).unwrap();
+            global_tree
+                .set(ContractAddress(felt("a")), state_hash)
+                .unwrap();
+            (global_tree.apply().unwrap(), contract_root)
+        }
+
+        #[test]
+        fn as_of_block() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let (first_root, first_contract_root) =
+                write(&transaction, GlobalRoot(StarkHash::ZERO), "11");
+            let (second_root, second_contract_root) = write(&transaction, first_root, "12");
+            assert_ne!(first_contract_root, second_contract_root);
+
+            let address = ContractAddress(felt("a"));
+            assert_eq!(
+                contract_storage_root(&transaction, first_root, address).unwrap(),
+                Some(first_contract_root)
+            );
+            assert_eq!(
+                contract_storage_root(&transaction, second_root, address).unwrap(),
+                Some(second_contract_root)
+            );
+        }
+
+        #[test]
+        fn not_deployed() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let (root, _) = write(&transaction, GlobalRoot(StarkHash::ZERO), "11");
+
+            let unknown = ContractAddress(felt("b"));
+            assert_eq!(
+                contract_storage_root(&transaction, root, unknown).unwrap(),
+                None
+            );
+            assert_eq!(
+                contract_storage_root(&transaction, GlobalRoot(StarkHash::ZERO), unknown).unwrap(),
+                None
+            );
+        }
+    }
+
+    mod apply_state_update {
+        use super::super::{apply_state_update, calculate_contract_state_hash};
+        use crate::core::{
+            ClassHash, ContractAddress, ContractRoot, GlobalRoot, StorageAddress, StorageValue,
+        };
+        use crate::sequencer::reply::{
+            state_update::{Contract, StateDiff, StorageDiff},
+            StateUpdate,
+        };
+        use crate::state::state_tree::{ContractsStateTree, GlobalStateTree};
+        use crate::storage::{ContractCodeTable, Storage};
+        use stark_hash::StarkHash;
+
+        fn felt(hex: &str) -> StarkHash {
+            StarkHash::from_hex_str(hex).unwrap()
+        }
+
+        /// Deploys contracts `a` and `b`, and writes to two of `a`'s storage slots.
+        fn diff() -> StateDiff {
+            let class = ClassHash(felt("c1a55"));
+            StateDiff {
+                storage_diffs: [(
+                    ContractAddress(felt("a")),
+                    vec![
+                        StorageDiff {
+                            key: StorageAddress(felt("1")),
+                            value: StorageValue(felt("11")),
+                        },
+                        StorageDiff {
+                            key: StorageAddress(felt("2")),
+                            value: StorageValue(felt("12")),
+                        },
+                    ],
+                )]
+                .into_iter()
+                .collect(),
+                deployed_contracts: ["a", "b"]
+                    .iter()
+                    .map(|address| Contract {
+                        address: ContractAddress(felt(address)),
+                        contract_hash: class,
+                    })
+                    .collect(),
+            }
+        }
+
+        /// The root after [diff], computed tree by tree.
+        fn expected_root() -> GlobalRoot {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+            let class = ClassHash(felt("c1a55"));
+
+            let mut contract_tree =
+                ContractsStateTree::load(&transaction, ContractRoot(StarkHash::ZERO)).unwrap();
+            contract_tree
+                .set(StorageAddress(felt("1")), StorageValue(felt("11")))
+                .unwrap();
+            contract_tree
+                .set(StorageAddress(felt("2")), StorageValue(felt("12")))
+                .unwrap();
+            let contract_root = contract_tree.apply().unwrap();
+
+            let mut global_tree =
+                GlobalStateTree::load(&transaction, GlobalRoot(StarkHash::ZERO)).unwrap();
+            global_tree
+                .set(
+                    ContractAddress(felt("a")),
+                    calculate_contract_state_hash(class, contract_root),
+                )
+                .unwrap();
+            global_tree
+                .set(
+                    ContractAddress(felt("b")),
+                    calculate_contract_state_hash(class, ContractRoot(StarkHash::ZERO)),
+                )
+                .unwrap();
+            global_tree.apply().unwrap()
+        }
+
+        fn storage_with_class() -> Storage {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            ContractCodeTable::insert_without_definition(&connection, ClassHash(felt("c1a55")))
+                .unwrap();
+            storage
+        }
+
+        #[test]
+        fn returns_new_root() {
+            let storage = storage_with_class();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let expected = expected_root();
+            let state_update = StateUpdate {
+                new_root: expected,
+                old_root: GlobalRoot(StarkHash::ZERO),
+                state_diff: diff(),
+            };
+
+            let root = apply_state_update(&transaction, &state_update).unwrap();
+            assert_eq!(root, expected);
+
+            // The new state is readable from the returned root.
+            let global_tree = GlobalStateTree::load(&transaction, root).unwrap();
+            assert_ne!(
+                global_tree.get(ContractAddress(felt("a"))).unwrap().0,
+                StarkHash::ZERO
+            );
+        }
+
+        #[test]
+        fn root_mismatch_errors() {
+            let storage = storage_with_class();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let state_update = StateUpdate {
+                new_root: GlobalRoot(felt("bad")),
+                old_root: GlobalRoot(StarkHash::ZERO),
+                state_diff: diff(),
+            };
+
+            apply_state_update(&transaction, &state_update).unwrap_err();
+        }
+    }
+
     #[test]
     fn hash() {
         let root = StarkHash::from_hex_str(
@@ -473,6 +756,7 @@ mod tests {
             chain,
             sequencer,
             state,
+            sync::SyncOptions::default(),
             sync::l1::sync,
             sync::l2::sync,
         )
@@ -0,0 +1,423 @@
+//! Backfills recent blocks backwards from the chain head, so that the blocks, transactions and
+//! events of recent blocks can be served long before the forward [sync](super::sync) reaches
+//! them.
+//!
+//! Backfilled blocks are stored without applying their state updates, as these can only be
+//! applied on top of all preceding states. The range of such blocks is tracked in [RefsTable]:
+//! the backfill extends it downwards from the head and upwards as new blocks are produced, while
+//! the forward sync shrinks it from below as it applies their state updates. Progress is
+//! therefore implicit in the database, and the backfill resumes where it left off after a
+//! restart.
+
+use std::num::NonZeroU64;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use anyhow::Context;
+use rusqlite::{Connection, TransactionBehavior};
+use stark_hash::StarkHash;
+
+use crate::{
+    core::{GasPrice, SequencerAddress, StarknetBlockNumber},
+    retry::Retry,
+    rpc::types::{BlockNumberOrTag, Tag},
+    sequencer::{self, reply},
+    storage::{
+        L1StateTable, RefsTable, StarknetBlock, StarknetBlocksTable, StarknetTransactionsTable,
+        Storage, StorageLimits,
+    },
+};
+
+/// The backfill is complete once the forward sync is within this many blocks of the head.
+const MAX_FORWARD_LAG: u64 = 10;
+/// The number of blocks stored before the head is polled again.
+const BATCH_SIZE: usize = 100;
+
+/// Stores blocks backwards from the chain head until the forward sync catches up with the head,
+/// following new blocks at the head in the meantime.
+///
+/// The head is polled every `poll_interval` once the backfill has met the forward sync.
+pub async fn backfill(
+    storage: Storage,
+    sequencer: impl sequencer::ClientApi,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    loop {
+        let latest = sequencer
+            .block_by_number(BlockNumberOrTag::Tag(Tag::Latest))
+            .await
+            .context("Download latest block")?;
+
+        for _ in 0..BATCH_SIZE {
+            match step(&storage, &sequencer, &latest).await? {
+                Step::Stored(number) => {
+                    tracing::trace!(block=%number.0, "Backfilled block");
+                }
+                Step::Discarded(number) => {
+                    tracing::info!(block=%number.0, "Discarded backfilled block which was reorged away");
+                }
+                Step::Idle => {
+                    tokio::time::sleep(poll_interval).await;
+                    break;
+                }
+                Step::Complete => {
+                    tracing::info!(
+                        "Forward sync caught up with the chain head, block backfill complete"
+                    );
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Runs [backfill], restarting it with an exponential backoff whenever it fails, e.g. because
+/// the gateway is unavailable.
+///
+/// Progress is kept in the database, so a restart resumes where the failed run left off.
+pub async fn backfill_with_retry(
+    storage: Storage,
+    sequencer: impl sequencer::ClientApi + Clone,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    Retry::exponential(
+        || backfill(storage.clone(), sequencer.clone(), poll_interval),
+        NonZeroU64::new(2).unwrap(),
+    )
+    .factor(NonZeroU64::new(15).unwrap())
+    .max_delay(Duration::from_secs(10 * 60))
+    .when(|e| {
+        tracing::warn!(reason=?e, "Block backfill failed, restarting");
+        true
+    })
+    .await
+}
+
+/// The outcome of a single [step].
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// The block was stored and added to the backfill range.
+    Stored(StarknetBlockNumber),
+    /// The backfilled block at the head was discarded as the chain was reorganized.
+    Discarded(StarknetBlockNumber),
+    /// The backfill range meets the forward sync and is up to date with the head.
+    Idle,
+    /// The forward sync has caught up with the head, so there is nothing left to backfill.
+    Complete,
+}
+
+/// Extends the backfill range by a single block, preferring new blocks at the head over older
+/// blocks at the tail.
+async fn step(
+    storage: &Storage,
+    sequencer: &impl sequencer::ClientApi,
+    latest: &reply::Block,
+) -> anyhow::Result<Step> {
+    let latest_number = latest.block_number.context("Latest block has no number")?;
+
+    let s = storage.clone();
+    let (range, state_head) = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        let connection = s.connection().context("Opening database connection")?;
+        let range = RefsTable::get_backfill_range(&connection).context("Query backfill range")?;
+        let state_head = StarknetBlocksTable::get_state_head(&connection)
+            .context("Query state head")?
+            .map(|block| block.number);
+        Ok((range, state_head))
+    })
+    .await
+    .context("Database read panic or shutting down")??;
+
+    let block = match &range {
+        None => {
+            let lag = state_head.map(|head| latest_number.0.saturating_sub(head.0));
+            if matches!(lag, Some(lag) if lag <= MAX_FORWARD_LAG) {
+                return Ok(Step::Complete);
+            }
+            latest.clone()
+        }
+        Some(range) if latest_number > *range.end() => {
+            let next = *range.end() + 1;
+            match next == latest_number {
+                true => latest.clone(),
+                false => download(sequencer, next).await?,
+            }
+        }
+        Some(range)
+            if *range.start() > StarknetBlockNumber::GENESIS
+                && state_head.map_or(true, |head| head + 1 < *range.start()) =>
+        {
+            download(sequencer, *range.start() - 1).await?
+        }
+        Some(_) => return Ok(Step::Idle),
+    };
+
+    let s = storage.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut connection = s.connection().context("Opening database connection")?;
+        store(&mut connection, block, range, s.limits())
+    })
+    .await
+    .context("Database write panic or shutting down")?
+}
+
+async fn download(
+    sequencer: &impl sequencer::ClientApi,
+    number: StarknetBlockNumber,
+) -> anyhow::Result<reply::Block> {
+    sequencer
+        .block_by_number(BlockNumberOrTag::Number(number))
+        .await
+        .with_context(|| format!("Download block {}", number.0))
+}
+
+/// Stores `block` and adds it to the backfill range, which is expected to still be `expected`.
+///
+/// The block has to be adjacent to the range, or start a new range if there is none. A block
+/// whose parent is not the head of the range indicates a reorg, in which case the head is
+/// discarded instead.
+fn store(
+    connection: &mut Connection,
+    block: reply::Block,
+    expected: Option<RangeInclusive<StarknetBlockNumber>>,
+    limits: &StorageLimits,
+) -> anyhow::Result<Step> {
+    // The forward sync writes to the same tables, so the write lock is taken right away.
+    let transaction = connection
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .context("Create database transaction")?;
+
+    // The forward sync may have consumed part of the range in the meantime, in which case the
+    // block is downloaded again in a later step.
+    let range = RefsTable::get_backfill_range(&transaction).context("Query backfill range")?;
+    if range != expected {
+        return Ok(Step::Idle);
+    }
+
+    let starknet_block = starknet_block(&block)?;
+    let number = starknet_block.number;
+
+    let range = match range {
+        None => number..=number,
+        Some(range) if number == *range.end() + 1 => {
+            let head = StarknetBlocksTable::get(&transaction, (*range.end()).into())
+                .context("Query backfilled head")?
+                .context("Backfilled head is missing")?;
+            if head.hash != starknet_block.parent_hash {
+                StarknetBlocksTable::reorg(&transaction, head.number)
+                    .context("Delete backfilled head")?;
+                RefsTable::reorg(&transaction, head.number).context("Revert backfill range")?;
+                transaction
+                    .commit()
+                    .context("Commit database transaction")?;
+                return Ok(Step::Discarded(head.number));
+            }
+            *range.start()..=number
+        }
+        Some(range) if number + 1 == *range.start() => {
+            let tail = StarknetBlocksTable::get(&transaction, (*range.start()).into())
+                .context("Query backfilled tail")?
+                .context("Backfilled tail is missing")?;
+            anyhow::ensure!(
+                tail.parent_hash == starknet_block.hash,
+                "Hash {} of block {} does not match the parent hash {} of backfilled block {}",
+                starknet_block.hash.0,
+                number.0,
+                tail.parent_hash.0,
+                tail.number.0
+            );
+            number..=*range.end()
+        }
+        Some(range) => anyhow::bail!(
+            "Block {} is not adjacent to the backfill range {}..={}",
+            number.0,
+            range.start().0,
+            range.end().0
+        ),
+    };
+
+    // The state roots confirmed on L1 are final, a block contradicting one is not backfilled.
+    let l1_root = L1StateTable::get_root(&transaction, number.into()).context("Query L1 root")?;
+    if let Some(l1_root) = l1_root {
+        anyhow::ensure!(
+            l1_root == starknet_block.root,
+            "State root {} of block {} does not match the root {} confirmed on L1",
+            starknet_block.root.0,
+            number.0,
+            l1_root.0
+        );
+    }
+
+    let transaction_data =
+        reply::transaction::pair_with_receipts(block.transactions, block.transaction_receipts)?;
+
+    StarknetBlocksTable::insert(&transaction, &starknet_block)
+        .context("Insert block into database")?;
+    StarknetTransactionsTable::upsert(
+        &transaction,
+        starknet_block.hash,
+        number,
+        &transaction_data,
+        limits,
+    )
+    .context("Insert transaction data into database")?;
+    RefsTable::set_backfill_range(&transaction, Some(range)).context("Update backfill range")?;
+
+    transaction
+        .commit()
+        .context("Commit database transaction")?;
+
+    Ok(Step::Stored(number))
+}
+
+fn starknet_block(block: &reply::Block) -> anyhow::Result<StarknetBlock> {
+    Ok(StarknetBlock {
+        number: block.block_number.context("Block has no number")?,
+        hash: block.block_hash.context("Block has no hash")?,
+        root: block.state_root.context("Block has no state root")?,
+        timestamp: block.timestamp,
+        // Default value for cairo <0.8.2 is 0
+        gas_price: block.gas_price.unwrap_or(GasPrice::ZERO),
+        sequencer_address: block
+            .sequencer_address
+            .unwrap_or(SequencerAddress(StarkHash::ZERO)),
+        parent_hash: block.parent_block_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use stark_hash::StarkHash;
+
+    use super::{step, Step};
+    use crate::{
+        core::{GlobalRoot, StarknetBlockHash, StarknetBlockNumber, StarknetBlockTimestamp},
+        rpc::types::BlockNumberOrTag,
+        sequencer::{reply, MockClientApi},
+        storage::{RefsTable, StarknetBlocksTable, Storage},
+    };
+
+    fn felt(value: u64) -> StarkHash {
+        StarkHash::from_hex_str(&format!("{:x}", value)).unwrap()
+    }
+
+    fn hash(number: u64, fork: u64) -> StarknetBlockHash {
+        StarknetBlockHash(felt(number * 100 + fork + 1))
+    }
+
+    /// Block `number` of the given fork, whose blocks up to `fork` are shared with fork 0.
+    fn block(number: u64, fork: u64) -> reply::Block {
+        let own_fork = |number: u64| if number <= fork { 0 } else { fork };
+        let parent_hash = match number {
+            0 => StarknetBlockHash(StarkHash::ZERO),
+            n => hash(n - 1, own_fork(n - 1)),
+        };
+
+        reply::Block {
+            block_hash: Some(hash(number, own_fork(number))),
+            block_number: Some(StarknetBlockNumber(number)),
+            gas_price: None,
+            parent_block_hash: parent_hash,
+            sequencer_address: None,
+            state_root: Some(GlobalRoot(felt(number))),
+            status: reply::Status::AcceptedOnL2,
+            timestamp: StarknetBlockTimestamp(number),
+            transaction_receipts: vec![],
+            transactions: vec![],
+        }
+    }
+
+    fn sequencer() -> MockClientApi {
+        let mut sequencer = MockClientApi::new();
+        sequencer
+            .expect_block_by_number()
+            .returning(|number| match number {
+                BlockNumberOrTag::Number(number) => Ok(block(number.0, 0)),
+                other => unreachable!("Unexpected block request {:?}", other),
+            });
+        sequencer
+    }
+
+    /// Stores blocks `0..=head` as if the forward sync had applied them.
+    fn storage_with_state_head(head: u64) -> Storage {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+        for number in 0..=head {
+            let block = super::starknet_block(&block(number, 0)).unwrap();
+            StarknetBlocksTable::insert(&connection, &block).unwrap();
+        }
+        storage
+    }
+
+    #[tokio::test]
+    async fn backfills_down_to_state_head() {
+        let storage = storage_with_state_head(1);
+        let sequencer = sequencer();
+        let latest = block(20, 0);
+
+        for number in (2..=20).rev() {
+            let result = step(&storage, &sequencer, &latest).await.unwrap();
+            assert_eq!(result, Step::Stored(StarknetBlockNumber(number)));
+        }
+        let result = step(&storage, &sequencer, &latest).await.unwrap();
+        assert_eq!(result, Step::Idle);
+
+        let connection = storage.connection().unwrap();
+        assert_eq!(
+            RefsTable::get_backfill_range(&connection).unwrap(),
+            Some(StarknetBlockNumber(2)..=StarknetBlockNumber(20))
+        );
+        let state_head = StarknetBlocksTable::get_state_head(&connection).unwrap();
+        assert_eq!(state_head.unwrap().number, StarknetBlockNumber(1));
+        let latest = StarknetBlocksTable::get_latest_number(&connection).unwrap();
+        assert_eq!(latest, Some(StarknetBlockNumber(20)));
+    }
+
+    #[tokio::test]
+    async fn follows_head_before_backfilling() {
+        let storage = storage_with_state_head(1);
+        let sequencer = sequencer();
+
+        let result = step(&storage, &sequencer, &block(20, 0)).await.unwrap();
+        assert_eq!(result, Step::Stored(StarknetBlockNumber(20)));
+
+        let result = step(&storage, &sequencer, &block(22, 0)).await.unwrap();
+        assert_eq!(result, Step::Stored(StarknetBlockNumber(21)));
+        let result = step(&storage, &sequencer, &block(22, 0)).await.unwrap();
+        assert_eq!(result, Step::Stored(StarknetBlockNumber(22)));
+        let result = step(&storage, &sequencer, &block(22, 0)).await.unwrap();
+        assert_eq!(result, Step::Stored(StarknetBlockNumber(19)));
+    }
+
+    #[tokio::test]
+    async fn reorged_head_is_discarded() {
+        let storage = storage_with_state_head(1);
+        let sequencer = sequencer();
+
+        step(&storage, &sequencer, &block(20, 0)).await.unwrap();
+        step(&storage, &sequencer, &block(20, 0)).await.unwrap();
+
+        // Fork 1 diverges after block 19.
+        let result = step(&storage, &sequencer, &block(21, 19)).await.unwrap();
+        assert_eq!(result, Step::Discarded(StarknetBlockNumber(20)));
+
+        let connection = storage.connection().unwrap();
+        assert_eq!(
+            RefsTable::get_backfill_range(&connection).unwrap(),
+            Some(StarknetBlockNumber(19)..=StarknetBlockNumber(19))
+        );
+        let stored = StarknetBlocksTable::get(&connection, StarknetBlockNumber(20).into()).unwrap();
+        assert_eq!(stored, None);
+    }
+
+    #[tokio::test]
+    async fn complete_once_forward_sync_is_near_head() {
+        let storage = storage_with_state_head(15);
+        let sequencer = sequencer();
+
+        let result = step(&storage, &sequencer, &block(20, 0)).await.unwrap();
+        assert_eq!(result, Step::Complete);
+
+        let connection = storage.connection().unwrap();
+        assert_eq!(RefsTable::get_backfill_range(&connection).unwrap(), None);
+    }
+}
@@ -0,0 +1,340 @@
+//! Backfills class definitions which are referenced by contracts or contract states, but which
+//! are missing from storage.
+//!
+//! Progress is implicit in the database: stored classes are no longer missing, classes which
+//! could not be backfilled are recorded as failures and classes whose download failed
+//! transiently are queued in [SyncRetryQueueTable], so the backfill resumes where it left off
+//! after a restart.
+//!
+//! [backfill_class] is shared with the [retry](crate::state::sync_retry) of the class
+//! downloads which were deferred during sync.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::{
+    core::ClassHash,
+    rpc::types::reply::Syncing,
+    sequencer::{self, error::SequencerError},
+    state::{
+        class_hash::extract_abi_code_hash, sync_retry::unix_now, CompressedContract, SyncState,
+    },
+    storage::{
+        ClassBackfillTable, ContractCodeTable, RetryEntry, RetryItem, Storage, SyncRetryQueueTable,
+    },
+};
+
+/// The backfill pauses while sync lags behind the network by more than this many blocks.
+const MAX_SYNC_LAG: u64 = 10;
+/// The number of missing classes read from storage at a time.
+const BATCH_SIZE: usize = 100;
+/// The number of failed downloads after which a class is given up on and recorded as a failure.
+pub(crate) const MAX_ATTEMPTS: u32 = 10;
+
+/// Downloads and stores the definitions of all referenced classes which are missing from storage.
+///
+/// Requests to the gateway are spaced out by `request_interval`. See [backfill_class] for how
+/// failures are handled.
+///
+/// The missing classes are read in passes over their hashes, a batch at a time. Returns once a
+/// pass finds no missing classes, apart from those waiting for a retry.
+pub async fn backfill(
+    storage: Storage,
+    sequencer: impl sequencer::ClientApi,
+    state: Arc<SyncState>,
+    request_interval: Duration,
+) -> anyhow::Result<()> {
+    // The last class hash of the previous batch in this pass.
+    let mut cursor = None;
+    loop {
+        let s = storage.clone();
+        let missing = tokio::task::spawn_blocking(move || {
+            let connection = s.connection()?;
+            ClassBackfillTable::missing(&connection, cursor, unix_now(), BATCH_SIZE)
+        })
+        .await
+        .context("Query missing classes")??;
+
+        match (missing.last(), cursor) {
+            (Some(last), _) => cursor = Some(*last),
+            // Start another pass for the classes which failed or were added during this one.
+            (None, Some(_)) => {
+                cursor = None;
+                continue;
+            }
+            (None, None) => {
+                tracing::info!("Class definition backfill complete");
+                return Ok(());
+            }
+        }
+
+        for hash in missing {
+            wait_for_sync(&state, request_interval).await;
+
+            match backfill_class(&storage, &sequencer, hash, unix_now()).await? {
+                Outcome::Stored => {
+                    tracing::debug!(class_hash=%hash.0, "Backfilled class definition");
+                }
+                Outcome::Rejected(reason) => {
+                    tracing::warn!(class_hash=%hash.0, %reason, "Rejected class definition");
+                }
+                Outcome::Deferred(entry) => {
+                    tracing::warn!(class_hash=%hash.0, attempts=%entry.attempts, reason=%entry.last_error, "Downloading class definition failed");
+                }
+            }
+
+            tokio::time::sleep(request_interval).await;
+        }
+    }
+}
+
+/// The outcome of [backfill_class].
+#[derive(Debug, PartialEq)]
+pub(crate) enum Outcome {
+    /// The definition was stored.
+    Stored,
+    /// The class was recorded as a [failure](ClassBackfillTable::record_failure), either
+    /// because the gateway rejected it, its definition does not hash to the class hash, or it
+    /// failed to download [MAX_ATTEMPTS] times. Contains the reason.
+    Rejected(String),
+    /// The download failed transiently and the class is queued for a retry.
+    Deferred(RetryEntry),
+}
+
+/// Downloads and stores the definition of the class, at unix time `now`.
+///
+/// A transient failure queues the class in [SyncRetryQueueTable], which backs off exponentially
+/// until [MAX_ATTEMPTS] is reached. Any other failure is recorded in [ClassBackfillTable] and is
+/// not retried.
+pub(crate) async fn backfill_class(
+    storage: &Storage,
+    sequencer: &impl sequencer::ClientApi,
+    hash: ClassHash,
+    now: u64,
+) -> anyhow::Result<Outcome> {
+    let result = download_and_compress_class(hash, sequencer)
+        .await
+        .map_err(|e| format!("{:#}", e));
+
+    let s = storage.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        let mut connection = s.connection().context("Opening database connection")?;
+        let transaction = connection
+            .transaction()
+            .context("Create database transaction")?;
+
+        let item = RetryItem::Class(hash);
+        let outcome = match result {
+            Ok(Ok(class)) => {
+                ContractCodeTable::backfill(&transaction, &class)
+                    .context("Store class definition")?;
+                Outcome::Stored
+            }
+            Ok(Err(reason)) => Outcome::Rejected(reason),
+            Err(error) => {
+                let entry = SyncRetryQueueTable::record_failure(&transaction, item, &error, now)
+                    .context("Queue class for retry")?;
+                match entry.attempts < MAX_ATTEMPTS {
+                    true => Outcome::Deferred(entry),
+                    false => Outcome::Rejected(format!(
+                        "Download failed {} times, last error: {}",
+                        entry.attempts, error
+                    )),
+                }
+            }
+        };
+
+        if let Outcome::Rejected(reason) = &outcome {
+            ClassBackfillTable::record_failure(&transaction, hash, reason)
+                .context("Record class backfill failure")?;
+        }
+        if !matches!(outcome, Outcome::Deferred(_)) {
+            SyncRetryQueueTable::remove(&transaction, item).context("Remove class from queue")?;
+        }
+
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
+        Ok(outcome)
+    })
+    .await
+    .context("Database write panic or shutting down")?
+}
+
+/// Waits until sync is no more than [MAX_SYNC_LAG] blocks behind the network.
+async fn wait_for_sync(state: &SyncState, poll_interval: Duration) {
+    loop {
+        let lag = match &*state.status.read().await {
+            Syncing::Status(status) => status
+                .highest
+                .number
+                .0
+                .saturating_sub(status.current.number.0),
+            Syncing::False(_) => 0,
+        };
+
+        if lag <= MAX_SYNC_LAG {
+            return;
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Downloads the class and verifies its hash.
+///
+/// The inner error is the reason the class was rejected, while the outer error indicates a
+/// transient failure.
+async fn download_and_compress_class(
+    hash: ClassHash,
+    sequencer: &impl sequencer::ClientApi,
+) -> anyhow::Result<Result<CompressedContract, String>> {
+    let definition = match sequencer.class_by_hash(hash).await {
+        Ok(definition) => definition,
+        Err(SequencerError::StarknetError(e)) => return Ok(Err(e.to_string())),
+        Err(e) => return Err(e).context("Download class from sequencer"),
+    };
+
+    // Parsing and hashing the definition can be expensive, so perform in a blocking task.
+    let extract = tokio::task::spawn_blocking(move || -> Result<_, String> {
+        let (abi, bytecode, computed) = extract_abi_code_hash(&definition)
+            .map_err(|e| format!("Invalid class definition: {:#}", e))?;
+        if !computed.0.ct_eq(&hash.0) {
+            return Err(format!("Class hash mismatch, computed {}", computed.0));
+        }
+        Ok((definition, abi, bytecode))
+    });
+    let (definition, abi, bytecode) = match extract
+        .await
+        .context("Parse class definition and compute hash")?
+    {
+        Ok(extracted) => extracted,
+        Err(reason) => return Ok(Err(reason)),
+    };
+
+    let compress = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+        let mut compressor = zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
+
+        let abi = compressor.compress(&abi).context("Compress ABI")?;
+        let bytecode = compressor
+            .compress(&bytecode)
+            .context("Compress bytecode")?;
+        let definition = compressor
+            .compress(&*definition)
+            .context("Compress definition")?;
+
+        Ok((abi, bytecode, definition))
+    });
+    let (abi, bytecode, definition) = compress.await.context("Compress class")??;
+
+    Ok(Ok(CompressedContract {
+        abi,
+        bytecode,
+        definition,
+        hash,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use stark_hash::StarkHash;
+
+    use crate::{
+        core::{ClassHash, ContractAddress, ContractRoot, ContractStateHash},
+        sequencer::MockClientApi,
+        state::SyncState,
+        storage::{ClassBackfillTable, ContractsStateTable, ContractsTable, Storage},
+    };
+
+    #[tokio::test]
+    async fn backfill() {
+        let definition = bytes::Bytes::from(
+            zstd::decode_all(&include_bytes!("../../fixtures/contract_definition.json.zst")[..])
+                .unwrap(),
+        );
+        let valid = ClassHash(
+            StarkHash::from_hex_str(
+                "050b2148c0d782914e0b12a1a32abe5e398930b7e914f82c65cb7afce0a0ab9b",
+            )
+            .unwrap(),
+        );
+        let mismatch = ClassHash(StarkHash::from_hex_str("123").unwrap());
+
+        let storage = Storage::in_memory().unwrap();
+        {
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            // Referenced only by a contract state.
+            ContractsStateTable::upsert(
+                &transaction,
+                ContractStateHash(StarkHash::from_hex_str("abc").unwrap()),
+                valid,
+                ContractRoot(StarkHash::ZERO),
+            )
+            .unwrap();
+
+            // Referenced by a contract, but stored without a definition.
+            transaction
+                .execute(
+                    "INSERT INTO contract_code (hash) VALUES (?)",
+                    [&mismatch.0.to_be_bytes()[..]],
+                )
+                .unwrap();
+            ContractsTable::upsert(
+                &transaction,
+                ContractAddress(StarkHash::from_hex_str("def").unwrap()),
+                mismatch,
+            )
+            .unwrap();
+
+            transaction.commit().unwrap();
+        }
+
+        let mut sequencer = MockClientApi::new();
+        let returned = definition.clone();
+        sequencer
+            .expect_class_by_hash()
+            .withf(move |x| x == &valid)
+            .times(1)
+            .return_once(move |_| Ok(returned));
+        let returned = definition.clone();
+        sequencer
+            .expect_class_by_hash()
+            .withf(move |x| x == &mismatch)
+            .times(1)
+            .return_once(move |_| Ok(returned));
+
+        super::backfill(
+            storage.clone(),
+            sequencer,
+            Arc::new(SyncState::default()),
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+
+        let connection = storage.connection().unwrap();
+        let stored: Vec<u8> = connection
+            .query_row(
+                "SELECT definition FROM contract_code WHERE hash = ?",
+                [&valid.0.to_be_bytes()[..]],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(zstd::decode_all(&stored[..]).unwrap(), definition);
+
+        let failures = ClassBackfillTable::failures(&connection).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, mismatch);
+
+        assert!(ClassBackfillTable::missing(&connection, None, 0, 10)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -188,7 +188,7 @@ fn compute_class_hash0(mut contract_definition: json::ContractDefinition<'_>) ->
 /// second value is the number of values hashed together in this chain. For other values, the
 /// accumulator is on each update replaced with the `H(hash, value)` and the number of count
 /// incremented by one.
-struct HashChain {
+pub(crate) struct HashChain {
     hash: StarkHash,
     count: usize,
 }
@@ -203,7 +203,7 @@ impl Default for HashChain {
 }
 
 impl HashChain {
-    fn update(&mut self, value: StarkHash) {
+    pub(crate) fn update(&mut self, value: StarkHash) {
         self.hash = stark_hash(self.hash, value);
         self.count = self
             .count
@@ -211,7 +211,7 @@ impl HashChain {
             .expect("could not have deserialized larger than usize Vecs");
     }
 
-    fn finalize(self) -> StarkHash {
+    pub(crate) fn finalize(self) -> StarkHash {
         let count = StarkHash::from_be_slice(&self.count.to_be_bytes())
             .expect("usize is smaller than 251-bits");
         stark_hash(self.hash, count)
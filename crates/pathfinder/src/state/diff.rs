@@ -0,0 +1,492 @@
+//! Computes the changes in StarkNet state between two blocks.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::ControlFlow;
+
+use anyhow::Context;
+use rusqlite::Transaction;
+use stark_hash::StarkHash;
+
+use crate::{
+    core::{
+        ClassHash, ContractAddress, ContractRoot, ContractStateHash, GlobalRoot, StorageAddress,
+        StorageValue,
+    },
+    ethereum::state_update::StateUpdate,
+    state::state_tree::{ContractsStateTree, GlobalStateTree},
+    storage::{
+        ContractsStateTable, ContractsTable, StarknetBlock, StarknetStateUpdatesTable,
+        StorageLimits, STATE_UPDATE_RETENTION,
+    },
+};
+
+/// The changes in state between two blocks.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StateDiff {
+    /// Contracts deployed after the older block, with their class hash.
+    pub deployed_contracts: BTreeMap<ContractAddress, ClassHash>,
+    /// Storage entries whose value differs between the two blocks, per contract.
+    pub storage_diffs: BTreeMap<ContractAddress, BTreeMap<StorageAddress, StorageChange>>,
+    /// Set if the diff contains more entries than were requested.
+    pub truncated: bool,
+}
+
+/// The value of a storage entry in the older and the newer block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StorageChange {
+    pub old: StorageValue,
+    pub new: StorageValue,
+}
+
+/// Computes the changes in state from block `from` to block `to`.
+///
+/// If the state updates of all blocks after `from` up to and including `to` are stored, they are
+/// folded into a single diff. This is only attempted for ranges of at most
+/// [STATE_UPDATE_RETENTION] blocks, as older state updates are not kept. Otherwise the global
+/// state trees of both blocks are compared, skipping any subtrees which are identical.
+///
+/// At most `limit` entries are returned, where each deployed contract and each storage change
+/// counts as a single entry. Entries are ordered by contract address, with the deployment of a
+/// contract preceding its storage changes.
+pub fn diff_state(
+    transaction: &Transaction,
+    from: &StarknetBlock,
+    to: &StarknetBlock,
+    limit: usize,
+    limits: &StorageLimits,
+) -> anyhow::Result<StateDiff> {
+    anyhow::ensure!(
+        from.number <= to.number,
+        "Block {} is newer than block {}",
+        from.number.0,
+        to.number.0
+    );
+
+    if to.number.0 - from.number.0 > STATE_UPDATE_RETENTION {
+        return tree_diff(transaction, from.root, to.root, limit);
+    }
+
+    let updates =
+        StarknetStateUpdatesTable::get_range(transaction, from.number + 1, to.number, limits)
+            .context("Read state updates")?;
+
+    match updates {
+        Some(updates) => fold(transaction, from.root, &updates, limit),
+        None => tree_diff(transaction, from.root, to.root, limit),
+    }
+}
+
+/// Folds consecutive state updates into a single diff, reading the original values from the
+/// state at `from_root`.
+fn fold(
+    transaction: &Transaction,
+    from_root: GlobalRoot,
+    updates: &[StateUpdate],
+    limit: usize,
+) -> anyhow::Result<StateDiff> {
+    let mut deployed = BTreeMap::new();
+    let mut storage = BTreeMap::<ContractAddress, BTreeMap<StorageAddress, StorageValue>>::new();
+
+    for update in updates {
+        for contract in &update.deployed_contracts {
+            deployed.insert(contract.address, contract.hash);
+        }
+
+        for contract in &update.contract_updates {
+            let entries = storage.entry(contract.address).or_default();
+            for update in &contract.storage_updates {
+                entries.insert(update.address, update.value);
+            }
+        }
+    }
+
+    let global_tree =
+        GlobalStateTree::load(transaction, from_root).context("Loading global state tree")?;
+
+    let addresses = deployed
+        .keys()
+        .chain(storage.keys())
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    let mut collector = Collector::new(limit);
+
+    for address in addresses {
+        if let Some(class_hash) = deployed.get(&address) {
+            if let ControlFlow::Break(()) = collector.deployed(address, *class_hash) {
+                break;
+            }
+        }
+
+        let entries = match storage.get(&address) {
+            Some(entries) => entries,
+            None => continue,
+        };
+
+        let state_hash = global_tree
+            .get(address)
+            .context("Read contract state hash")?;
+        let contract_tree =
+            ContractsStateTree::load(transaction, contract_root(transaction, state_hash)?)
+                .context("Loading contract state tree")?;
+
+        let mut flow = ControlFlow::Continue(());
+        for (key, new) in entries {
+            let old = contract_tree.get(*key).context("Read storage value")?;
+            if old != *new {
+                flow = collector.storage(address, *key, old, *new);
+                if let ControlFlow::Break(()) = flow {
+                    break;
+                }
+            }
+        }
+
+        if let ControlFlow::Break(()) = flow {
+            break;
+        }
+    }
+
+    Ok(collector.finish())
+}
+
+/// Compares the state at `from_root` to the state at `to_root`.
+///
+/// Every changed contract contributes at least one entry to the diff, so the comparison of the
+/// global state trees stops once more than `limit` contracts have changed.
+fn tree_diff(
+    transaction: &Transaction,
+    from_root: GlobalRoot,
+    to_root: GlobalRoot,
+    limit: usize,
+) -> anyhow::Result<StateDiff> {
+    let old_tree =
+        GlobalStateTree::load(transaction, from_root).context("Loading global state tree")?;
+    let new_tree =
+        GlobalStateTree::load(transaction, to_root).context("Loading global state tree")?;
+
+    let mut changed = Vec::new();
+    old_tree
+        .diff(&new_tree, |address, old, new| {
+            changed.push((address, old, new));
+            match changed.len() > limit {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            }
+        })
+        .context("Diffing global state trees")?;
+
+    let mut collector = Collector::new(limit);
+
+    for (address, old, new) in changed {
+        if old.0 == StarkHash::ZERO {
+            let class_hash = ContractsTable::get_hash(transaction, address)
+                .context("Read class hash")?
+                .with_context(|| format!("Class hash missing for contract {}", address.0))?;
+
+            if let ControlFlow::Break(()) = collector.deployed(address, class_hash) {
+                break;
+            }
+        }
+
+        let old_storage = ContractsStateTree::load(transaction, contract_root(transaction, old)?)
+            .context("Loading contract state tree")?;
+        let new_storage = ContractsStateTree::load(transaction, contract_root(transaction, new)?)
+            .context("Loading contract state tree")?;
+
+        let mut flow = ControlFlow::Continue(());
+        old_storage
+            .diff(&new_storage, |key, old, new| {
+                flow = collector.storage(address, key, old, new);
+                flow
+            })
+            .context("Diffing contract state trees")?;
+
+        if let ControlFlow::Break(()) = flow {
+            break;
+        }
+    }
+
+    Ok(collector.finish())
+}
+
+/// Returns the storage root of a contract state, where a zero state hash denotes a contract
+/// which does not exist (yet).
+pub(super) fn contract_root(
+    transaction: &Transaction,
+    state_hash: ContractStateHash,
+) -> anyhow::Result<ContractRoot> {
+    if state_hash.0 == StarkHash::ZERO {
+        return Ok(ContractRoot(StarkHash::ZERO));
+    }
+
+    ContractsStateTable::get_root(transaction, state_hash)
+        .context("Read contract root")?
+        .with_context(|| format!("Contract state missing for hash {}", state_hash.0))
+}
+
+/// Accumulates diff entries up to a limit.
+struct Collector {
+    diff: StateDiff,
+    remaining: usize,
+}
+
+impl Collector {
+    fn new(limit: usize) -> Self {
+        Self {
+            diff: StateDiff::default(),
+            remaining: limit,
+        }
+    }
+
+    /// Takes up one entry of the limit, marking the diff as truncated if there are none left.
+    fn reserve(&mut self) -> ControlFlow<()> {
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                ControlFlow::Continue(())
+            }
+            None => {
+                self.diff.truncated = true;
+                ControlFlow::Break(())
+            }
+        }
+    }
+
+    fn deployed(&mut self, address: ContractAddress, class_hash: ClassHash) -> ControlFlow<()> {
+        if let ControlFlow::Break(()) = self.reserve() {
+            return ControlFlow::Break(());
+        }
+
+        self.diff.deployed_contracts.insert(address, class_hash);
+        ControlFlow::Continue(())
+    }
+
+    fn storage(
+        &mut self,
+        address: ContractAddress,
+        key: StorageAddress,
+        old: StorageValue,
+        new: StorageValue,
+    ) -> ControlFlow<()> {
+        if let ControlFlow::Break(()) = self.reserve() {
+            return ControlFlow::Break(());
+        }
+
+        self.diff
+            .storage_diffs
+            .entry(address)
+            .or_default()
+            .insert(key, StorageChange { old, new });
+        ControlFlow::Continue(())
+    }
+
+    fn finish(self) -> StateDiff {
+        self.diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_state, fold, tree_diff, StateDiff, StorageChange};
+    use crate::{
+        core::{ClassHash, ContractAddress, StorageAddress, StorageValue},
+        ethereum::state_update::{ContractUpdate, DeployedContract, StateUpdate, StorageUpdate},
+        storage::{
+            test_utils, ContractCodeTable, StarknetBlock, StarknetBlocksTable,
+            StarknetStateUpdatesTable, Storage, StorageLimits,
+        },
+    };
+    use rusqlite::Transaction;
+    use stark_hash::StarkHash;
+
+    fn hash(value: u64) -> StarkHash {
+        StarkHash::from_be_slice(&value.to_be_bytes()).unwrap()
+    }
+
+    fn address(value: u64) -> ContractAddress {
+        ContractAddress(hash(value))
+    }
+
+    fn class(value: u64) -> ClassHash {
+        ClassHash(hash(value))
+    }
+
+    fn deploy(contract: u64, class_hash: u64) -> DeployedContract {
+        DeployedContract {
+            address: address(contract),
+            hash: class(class_hash),
+            call_data: Vec::new(),
+        }
+    }
+
+    fn write(contract: u64, entries: &[(u64, u64)]) -> ContractUpdate {
+        ContractUpdate {
+            address: address(contract),
+            storage_updates: entries
+                .iter()
+                .map(|(key, value)| StorageUpdate {
+                    address: StorageAddress(hash(*key)),
+                    value: StorageValue(hash(*value)),
+                })
+                .collect(),
+        }
+    }
+
+    fn change(key: u64, old: u64, new: u64) -> (StorageAddress, StorageChange) {
+        (
+            StorageAddress(hash(key)),
+            StorageChange {
+                old: StorageValue(hash(old)),
+                new: StorageValue(hash(new)),
+            },
+        )
+    }
+
+    /// Applies three blocks of state updates:
+    ///
+    /// 0. deploys `0xa` with `{1: 10, 2: 20}` and deploys `0xb`
+    /// 1. writes `{1: 11}` to `0xa`, deploys `0xc` and writes `{5: 50}` to it
+    /// 2. writes `{2: 20, 3: 30}` to `0xa` and `{7: 70}` to `0xb`
+    fn setup(transaction: &Transaction) -> [StarknetBlock; 3] {
+        for class_hash in [class(0x100), class(0x200)] {
+            ContractCodeTable::insert(transaction, class_hash, &[], &[], &[]).unwrap();
+        }
+
+        let updates = [
+            StateUpdate {
+                deployed_contracts: vec![deploy(0xa, 0x100), deploy(0xb, 0x100)],
+                contract_updates: vec![write(0xa, &[(1, 10), (2, 20)])],
+            },
+            StateUpdate {
+                deployed_contracts: vec![deploy(0xc, 0x200)],
+                contract_updates: vec![write(0xa, &[(1, 11)]), write(0xc, &[(5, 50)])],
+            },
+            StateUpdate {
+                deployed_contracts: Vec::new(),
+                contract_updates: vec![write(0xa, &[(2, 20), (3, 30)]), write(0xb, &[(7, 70)])],
+            },
+        ];
+
+        let mut blocks = test_utils::create_blocks::<3>();
+        for (block, update) in blocks.iter_mut().zip(updates.iter()) {
+            block.root =
+                super::super::sync::update_starknet_state(transaction, update, None).unwrap();
+            StarknetBlocksTable::insert(transaction, block).unwrap();
+            StarknetStateUpdatesTable::insert(
+                transaction,
+                block.number,
+                update,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+        }
+
+        blocks
+    }
+
+    fn expected() -> StateDiff {
+        StateDiff {
+            deployed_contracts: [(address(0xc), class(0x200))].into_iter().collect(),
+            storage_diffs: [
+                (
+                    address(0xa),
+                    [change(1, 10, 11), change(3, 0, 30)].into_iter().collect(),
+                ),
+                (address(0xb), [change(7, 0, 70)].into_iter().collect()),
+                (address(0xc), [change(5, 0, 50)].into_iter().collect()),
+            ]
+            .into_iter()
+            .collect(),
+            truncated: false,
+        }
+    }
+
+    fn both_paths(
+        transaction: &Transaction,
+        blocks: &[StarknetBlock; 3],
+        limit: usize,
+    ) -> StateDiff {
+        let updates = StarknetStateUpdatesTable::get_range(
+            transaction,
+            blocks[1].number,
+            blocks[2].number,
+            &StorageLimits::default(),
+        )
+        .unwrap()
+        .unwrap();
+
+        let folded = fold(transaction, blocks[0].root, &updates, limit).unwrap();
+        let compared = tree_diff(transaction, blocks[0].root, blocks[2].root, limit).unwrap();
+        assert_eq!(folded, compared);
+
+        folded
+    }
+
+    #[test]
+    fn fold_and_tree_agree() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+        let blocks = setup(&transaction);
+
+        assert_eq!(both_paths(&transaction, &blocks, 100), expected());
+    }
+
+    #[test]
+    fn truncated() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+        let blocks = setup(&transaction);
+
+        let diff = both_paths(&transaction, &blocks, 3);
+
+        let mut expected = expected();
+        expected.deployed_contracts.clear();
+        expected.storage_diffs.remove(&address(0xc));
+        expected.truncated = true;
+        assert_eq!(diff, expected);
+
+        // Exactly enough room for every entry is not a truncation.
+        assert_eq!(both_paths(&transaction, &blocks, 5), self::expected());
+    }
+
+    #[test]
+    fn same_block() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+        let blocks = setup(&transaction);
+
+        let diff = diff_state(&transaction, &blocks[1], &blocks[1], 100, storage.limits()).unwrap();
+        assert_eq!(diff, StateDiff::default());
+    }
+
+    #[test]
+    fn falls_back_to_trees_without_state_updates() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+        let blocks = setup(&transaction);
+
+        transaction
+            .execute(
+                "DELETE FROM starknet_state_updates WHERE block_number = ?",
+                [blocks[1].number.0],
+            )
+            .unwrap();
+
+        let diff = diff_state(&transaction, &blocks[0], &blocks[2], 100, storage.limits()).unwrap();
+        assert_eq!(diff, expected());
+    }
+
+    #[test]
+    fn reversed_range() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+        let blocks = setup(&transaction);
+
+        diff_state(&transaction, &blocks[2], &blocks[0], 100, storage.limits()).unwrap_err();
+    }
+}
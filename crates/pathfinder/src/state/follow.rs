@@ -0,0 +1,127 @@
+//! Keeps the sync status of a serve-only node, which does not sync itself, up-to-date with a
+//! database which another process syncs.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+use crate::{
+    rpc::types::reply::{syncing, syncing::NumberedBlock, Syncing as SyncStatus},
+    state::SyncState,
+    storage::{StarknetBlocksBlockId, StarknetBlocksTable, Storage},
+};
+
+/// Re-reads the latest block from `storage` every `poll_interval`, and reports it as both the
+/// current and highest block of the sync status.
+///
+/// Never returns unless the database fails.
+pub async fn follow(
+    storage: Storage,
+    state: Arc<SyncState>,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    loop {
+        refresh(&storage, &state).await?;
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+async fn refresh(storage: &Storage, state: &SyncState) -> anyhow::Result<()> {
+    let s = storage.clone();
+    let latest = tokio::task::spawn_blocking(move || {
+        let connection = s.connection()?;
+        StarknetBlocksTable::get(&connection, StarknetBlocksBlockId::Latest)
+    })
+    .await
+    .context("Query latest block")??;
+
+    let latest = match latest {
+        Some(block) => NumberedBlock::from((block.hash, block.number)),
+        None => return Ok(()),
+    };
+
+    let advanced = match &mut *state.status.write().await {
+        status @ SyncStatus::False(_) => {
+            *status = SyncStatus::Status(syncing::Status {
+                starting: latest,
+                current: latest,
+                highest: latest,
+                l1_pending_confirmations: 0,
+            });
+            true
+        }
+        SyncStatus::Status(status) => {
+            let advanced = status.current.hash != latest.hash;
+            status.current = latest;
+            status.highest = latest;
+            advanced
+        }
+    };
+
+    if advanced {
+        state
+            .throughput
+            .write()
+            .await
+            .record(Instant::now(), latest.number);
+        tracing::debug!(number=%latest.number.0, "Followed latest block");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn status_follows_the_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("follow.sqlite");
+        let writer = Storage::migrate(path.clone()).unwrap();
+        let reader = Storage::open_read_only(
+            path,
+            crate::storage::DEFAULT_STATEMENT_CACHE_CAPACITY,
+            crate::storage::StorageLimits::default(),
+        )
+        .unwrap();
+        let state = SyncState::default();
+
+        // Nothing to report before the first block.
+        refresh(&reader, &state).await.unwrap();
+        assert_eq!(*state.status.read().await, SyncStatus::False(false));
+
+        let blocks = crate::storage::test_utils::create_blocks::<3>();
+        let connection = writer.connection().unwrap();
+        StarknetBlocksTable::insert(&connection, &blocks[0]).unwrap();
+        refresh(&reader, &state).await.unwrap();
+
+        let first = NumberedBlock::from((blocks[0].hash, blocks[0].number));
+        assert_eq!(
+            *state.status.read().await,
+            SyncStatus::Status(syncing::Status {
+                starting: first,
+                current: first,
+                highest: first,
+                l1_pending_confirmations: 0,
+            })
+        );
+
+        for block in &blocks[1..] {
+            StarknetBlocksTable::insert(&connection, block).unwrap();
+        }
+        refresh(&reader, &state).await.unwrap();
+
+        let last = NumberedBlock::from((blocks[2].hash, blocks[2].number));
+        assert_eq!(
+            *state.status.read().await,
+            SyncStatus::Status(syncing::Status {
+                starting: first,
+                current: last,
+                highest: last,
+                l1_pending_confirmations: 0,
+            })
+        );
+    }
+}
@@ -227,17 +227,23 @@ impl EdgeNode {
             None => return,
         };
 
-        let path = StarkHash::from_bits(&self.path).unwrap();
-        let mut length = [0; 32];
-        // Safe as len() is guaranteed to be <= 251
-        length[31] = self.path.len() as u8;
-
-        let length = StarkHash::from_be_bytes(length).unwrap();
-        let hash = stark_hash(child, path) + length;
-        self.hash = Some(hash);
+        self.hash = Some(edge_hash(child, &self.path));
     }
 }
 
+/// Calculates the hash of an edge node with the given `child` hash and `path`.
+///
+/// Panics if `path` is longer than 251 bits.
+pub fn edge_hash(child: StarkHash, path: &BitSlice<Msb0, u8>) -> StarkHash {
+    let path_hash = StarkHash::from_bits(path).unwrap();
+    let mut length = [0; 32];
+    // Safe as len() is guaranteed to be <= 251
+    length[31] = path.len() as u8;
+
+    let length = StarkHash::from_be_bytes(length).unwrap();
+    stark_hash(child, path_hash) + length
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,17 +45,112 @@
 //! The in-memory tree is built using a graph of `Rc<RefCell<Node>>` which is a bit painful.
 
 use anyhow::Context;
-use bitvec::prelude::BitVec;
+use bitvec::prelude::{BitSlice, BitVec, Msb0};
 use rusqlite::Transaction;
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, ops::ControlFlow, rc::Rc};
 
-use crate::state::merkle_node::{BinaryNode, Direction, EdgeNode, Node};
+use crate::state::merkle_node::{edge_hash, BinaryNode, Direction, EdgeNode, Node};
 
 use crate::storage::merkle_tree::{
-    PersistedBinaryNode, PersistedEdgeNode, PersistedNode, RcNodeStorage,
+    CorruptNodeError, PersistedBinaryNode, PersistedEdgeNode, PersistedNode, RcNodeStorage,
 };
 
-use stark_hash::StarkHash;
+use stark_hash::{stark_hash, StarkHash};
+
+/// Errors returned by [MerkleTree] operations.
+#[derive(Debug, thiserror::Error)]
+pub enum MerkleTreeError {
+    /// A node referenced by the tree, for example its root, does not exist in storage.
+    #[error("Node {0} does not exist")]
+    NotFound(StarkHash),
+    /// The [NodeStorage] failed.
+    #[error(transparent)]
+    Storage(#[from] anyhow::Error),
+    /// A node exists in storage, but could not be deserialized.
+    #[error("Node {0} is corrupt")]
+    Corrupt(StarkHash, #[source] CorruptNodeError),
+    /// A key or leaf path does not fit into the 251 bits of a path through the tree.
+    #[error("Key exceeds 251 bits")]
+    Overflow,
+    /// A proof was requested from a tree with uncommitted changes, whose node hashes are unknown.
+    #[error("Tree has uncommitted changes")]
+    Uncommitted,
+}
+
+/// A node on the path from the root of a [MerkleTree] to a leaf, as returned by
+/// [MerkleTree::get_proof].
+///
+/// Only the hashes of a node's children are included, which is enough to calculate the node's
+/// own hash.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProofNode {
+    Binary {
+        left: StarkHash,
+        right: StarkHash,
+    },
+    Edge {
+        child: StarkHash,
+        path: BitVec<Msb0, u8>,
+    },
+}
+
+impl ProofNode {
+    /// Calculates the hash of the node.
+    ///
+    /// Panics if the path of an edge node is longer than 251 bits.
+    pub fn hash(&self) -> StarkHash {
+        match self {
+            ProofNode::Binary { left, right } => stark_hash(*left, *right),
+            ProofNode::Edge { child, path } => edge_hash(*child, path),
+        }
+    }
+}
+
+/// Verifies that `proof`, as returned by [MerkleTree::get_proof], proves that the leaf at `key`
+/// has `value` in the tree with the given `root`.
+///
+/// The tree's height is the length of `key`, which allows verifying proofs of trees shorter than
+/// the 251 bits of Starknet's state trees.
+pub fn verify_proof(
+    root: StarkHash,
+    key: &BitSlice<Msb0, u8>,
+    value: StarkHash,
+    proof: &[ProofNode],
+) -> bool {
+    let mut expected = root;
+    let mut remaining = key;
+
+    for node in proof {
+        // Check the path before hashing, as an edge's hash is only defined for valid paths.
+        let next = match node {
+            ProofNode::Binary { left, right } => {
+                if remaining.is_empty() {
+                    return false;
+                }
+                let next = match Direction::from(remaining[0]) {
+                    Direction::Left => *left,
+                    Direction::Right => *right,
+                };
+                remaining = &remaining[1..];
+                next
+            }
+            ProofNode::Edge { child, path } => {
+                if path.len() > remaining.len() || *path != remaining[..path.len()] {
+                    return false;
+                }
+                remaining = &remaining[path.len()..];
+                *child
+            }
+        };
+
+        if node.hash() != expected {
+            return false;
+        }
+        expected = next;
+    }
+
+    remaining.is_empty() && expected == value
+}
 
 /// Backing storage for [`MerkleTree`].
 ///
@@ -110,7 +205,7 @@ impl<'a> MerkleTree<RcNodeStorage<'a>> {
         table: String,
         transaction: &'a Transaction,
         root: StarkHash,
-    ) -> anyhow::Result<Self> {
+    ) -> Result<Self, MerkleTreeError> {
         let storage = RcNodeStorage::open(table, transaction)?;
         Self::new(storage, root)
     }
@@ -135,12 +230,12 @@ impl<T: NodeStorage> MerkleTree<T> {
     /// This allows for multiple instances of the same tree state to be committed,
     /// without deleting all of them in a single call.
     #[cfg(test)]
-    pub fn delete(self) -> anyhow::Result<()> {
+    pub fn delete(self) -> Result<(), MerkleTreeError> {
         match self.root.borrow().hash() {
-            Some(hash) if hash != StarkHash::ZERO => self
+            Some(hash) if hash != StarkHash::ZERO => Ok(self
                 .storage
                 .decrement_ref_count(hash)
-                .context("Failed to delete tree root"),
+                .context("Failed to delete tree root")?),
             _ => Ok(()),
         }
     }
@@ -148,7 +243,7 @@ impl<T: NodeStorage> MerkleTree<T> {
     /// Less visible initialization for `MerkleTree<T>` as the main entry points should be
     /// [`MerkleTree::<RcNodeStorage>::load`] for persistent trees and [`MerkleTree::default`] for
     /// transient ones.
-    fn new(storage: T, root: StarkHash) -> anyhow::Result<Self> {
+    pub(crate) fn new(storage: T, root: StarkHash) -> Result<Self, MerkleTreeError> {
         let root_node = Rc::new(RefCell::new(Node::Unresolved(root)));
         let mut tree = Self {
             storage,
@@ -156,9 +251,7 @@ impl<T: NodeStorage> MerkleTree<T> {
         };
         if root != StarkHash::ZERO {
             // Resolve non-zero root node to check that it does exist.
-            let root_node = tree
-                .resolve(root, 0)
-                .context("Failed to resolve root node")?;
+            let root_node = tree.resolve(root, 0)?;
             tree.root = Rc::new(RefCell::new(root_node));
         }
         Ok(tree)
@@ -168,7 +261,7 @@ impl<T: NodeStorage> MerkleTree<T> {
     ///
     /// Note that the root is reference counted in storage. Committing the
     /// same tree again will therefore increment the count again.
-    pub fn commit(self) -> anyhow::Result<StarkHash> {
+    pub fn commit(self) -> Result<StarkHash, MerkleTreeError> {
         // Go through tree, collect dirty nodes, calculate their hashes and
         // persist them. Take care to increment ref counts of child nodes. So in order
         // to do this correctly, will have to start back-to-front.
@@ -189,7 +282,7 @@ impl<T: NodeStorage> MerkleTree<T> {
     /// as the parent node's hash relies on its childrens hashes.
     ///
     /// In effect, the entire subtree gets persisted.
-    fn commit_subtree(&self, node: &mut Node) -> anyhow::Result<()> {
+    fn commit_subtree(&self, node: &mut Node) -> Result<(), MerkleTreeError> {
         use Node::*;
         match node {
             // Unresolved nodes are already persisted.
@@ -237,7 +330,13 @@ impl<T: NodeStorage> MerkleTree<T> {
     }
 
     /// Sets the value of a key. To delete a key, set the value to [StarkHash::ZERO].
-    pub fn set(&mut self, key: StarkHash, value: StarkHash) -> anyhow::Result<()> {
+    ///
+    /// Fails with [MerkleTreeError::Overflow] if `key` has more than 251 bits.
+    pub fn set(&mut self, key: StarkHash, value: StarkHash) -> Result<(), MerkleTreeError> {
+        if key.has_more_than_251_bits() {
+            return Err(MerkleTreeError::Overflow);
+        }
+
         if value == StarkHash::ZERO {
             return self.delete_leaf(key);
         }
@@ -369,7 +468,7 @@ impl<T: NodeStorage> MerkleTree<T> {
     ///
     /// This is not an external facing API; the functionality is instead accessed by calling
     /// [`MerkleTree::set`] with value set to [`StarkHash::ZERO`].
-    fn delete_leaf(&mut self, key: StarkHash) -> anyhow::Result<()> {
+    fn delete_leaf(&mut self, key: StarkHash) -> Result<(), MerkleTreeError> {
         // Algorithm explanation:
         //
         // The leaf's parent node is either an edge, or a binary node.
@@ -450,7 +549,13 @@ impl<T: NodeStorage> MerkleTree<T> {
     }
 
     /// Returns the value stored at key, or [StarkHash::ZERO] if it does not exist.
-    pub fn get(&self, key: StarkHash) -> anyhow::Result<StarkHash> {
+    ///
+    /// Fails with [MerkleTreeError::Overflow] if `key` has more than 251 bits.
+    pub fn get(&self, key: StarkHash) -> Result<StarkHash, MerkleTreeError> {
+        if key.has_more_than_251_bits() {
+            return Err(MerkleTreeError::Overflow);
+        }
+
         let val = match self.traverse(key)?.last() {
             Some(node) => match &*node.borrow() {
                 Node::Leaf(value) => *value,
@@ -461,6 +566,379 @@ impl<T: NodeStorage> MerkleTree<T> {
         Ok(val)
     }
 
+    /// Returns the values stored at `keys`, in the same order as `keys`. Keys which do not exist
+    /// have the value [StarkHash::ZERO].
+    ///
+    /// Equivalent to calling [MerkleTree::get] for each key, except that the keys are looked up
+    /// in sorted order so that nodes on a path shared by several keys are only traversed once.
+    pub fn get_many(&self, keys: &[StarkHash]) -> Result<Vec<StarkHash>, MerkleTreeError> {
+        if keys.iter().any(StarkHash::has_more_than_251_bits) {
+            return Err(MerkleTreeError::Overflow);
+        }
+
+        let mut sorted = keys
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(index, key)| (key, index))
+            .collect::<Vec<_>>();
+        sorted.sort_unstable();
+
+        let mut values = vec![StarkHash::ZERO; keys.len()];
+        self.get_in_subtree(&self.root, 0, &sorted, &mut values)?;
+
+        Ok(values)
+    }
+
+    /// Looks up the sorted `keys` in the subtree rooted at `node`, which is at `height`, and
+    /// stores each found value in `values` at the key's original index.
+    ///
+    /// All `keys` must share the path leading to `node`.
+    fn get_in_subtree(
+        &self,
+        node: &Rc<RefCell<Node>>,
+        height: usize,
+        keys: &[(StarkHash, usize)],
+        values: &mut [StarkHash],
+    ) -> Result<(), MerkleTreeError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        // Resolve in place, like `traverse` does, so that later lookups can reuse the node.
+        let resolved = match &*node.borrow() {
+            Node::Unresolved(hash) if *hash == StarkHash::ZERO => return Ok(()),
+            Node::Unresolved(hash) => Some(self.resolve(*hash, height)?),
+            _ => None,
+        };
+        if let Some(resolved) = resolved {
+            node.swap(&RefCell::new(resolved));
+        }
+
+        let current = node.borrow().clone();
+        match current {
+            Node::Unresolved(_) => unreachable!("Node was resolved above"),
+            Node::Leaf(value) => {
+                for (_, index) in keys {
+                    values[*index] = value;
+                }
+            }
+            Node::Binary(binary) => {
+                // The keys are sorted, so the ones going left precede the ones going right.
+                let split =
+                    keys.partition_point(|(key, _)| binary.direction(*key) == Direction::Left);
+                self.get_in_subtree(&binary.left, height + 1, &keys[..split], values)?;
+                self.get_in_subtree(&binary.right, height + 1, &keys[split..], values)?;
+            }
+            Node::Edge(edge) => {
+                // Keys sharing the edge's path form a contiguous run of the sorted keys.
+                let start = keys
+                    .iter()
+                    .position(|(key, _)| edge.path_matches(*key))
+                    .unwrap_or(keys.len());
+                let end = start
+                    + keys[start..]
+                        .iter()
+                        .take_while(|(key, _)| edge.path_matches(*key))
+                        .count();
+                self.get_in_subtree(
+                    &edge.child,
+                    height + edge.path.len(),
+                    &keys[start..end],
+                    values,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the nodes on the path from the root to the leaf at `key`, starting at the root.
+    ///
+    /// Together with the leaf's value these prove its inclusion in the tree, see [verify_proof].
+    /// If there is no leaf at `key`, the path ends with the edge which diverges from it instead.
+    ///
+    /// Fails with [MerkleTreeError::Uncommitted] if the tree has changes which were not committed
+    /// yet, and with [MerkleTreeError::Overflow] if `key` has more than 251 bits.
+    pub fn get_proof(&self, key: StarkHash) -> Result<Vec<ProofNode>, MerkleTreeError> {
+        if key.has_more_than_251_bits() {
+            return Err(MerkleTreeError::Overflow);
+        }
+
+        let mut proof = Vec::new();
+        for node in self.traverse(key)? {
+            let node = match &*node.borrow() {
+                Node::Binary(binary) => ProofNode::Binary {
+                    left: binary
+                        .left
+                        .borrow()
+                        .hash()
+                        .ok_or(MerkleTreeError::Uncommitted)?,
+                    right: binary
+                        .right
+                        .borrow()
+                        .hash()
+                        .ok_or(MerkleTreeError::Uncommitted)?,
+                },
+                Node::Edge(edge) => ProofNode::Edge {
+                    child: edge
+                        .child
+                        .borrow()
+                        .hash()
+                        .ok_or(MerkleTreeError::Uncommitted)?,
+                    path: edge.path.clone(),
+                },
+                Node::Leaf(_) => break,
+                Node::Unresolved(_) => unreachable!("Traversal resolves all nodes on the path"),
+            };
+            proof.push(node);
+        }
+
+        Ok(proof)
+    }
+
+    /// Visits all leaves of the tree in ascending key order.
+    ///
+    /// See [MerkleTree::visit_leaves_in_range] for more information.
+    #[allow(dead_code)]
+    pub fn visit_leaves<F>(&self, visitor: F) -> Result<(), MerkleTreeError>
+    where
+        F: FnMut(StarkHash, StarkHash) -> ControlFlow<()>,
+    {
+        let max_key = StarkHash::from_bits(&BitVec::<Msb0, u8>::repeat(true, 251))
+            .expect("251 bits always fit");
+        self.visit_leaves_in_range(StarkHash::ZERO, max_key, visitor)
+    }
+
+    /// Visits the leaves with keys in the inclusive range `start..=end`, in ascending key order.
+    ///
+    /// The `visitor` is called with each leaf's key and value, and can stop the traversal early
+    /// by returning [ControlFlow::Break]. This allows for chunked extraction of large trees, where
+    /// the next chunk starts where the previous one stopped.
+    ///
+    /// Subtrees which lie entirely outside of the range are pruned using the path information
+    /// of the nodes leading up to them, and are therefore never loaded from storage.
+    pub fn visit_leaves_in_range<F>(
+        &self,
+        start: StarkHash,
+        end: StarkHash,
+        mut visitor: F,
+    ) -> Result<(), MerkleTreeError>
+    where
+        F: FnMut(StarkHash, StarkHash) -> ControlFlow<()>,
+    {
+        self.visit_range(start, end, &mut visitor)?;
+        Ok(())
+    }
+
+    /// Implements [MerkleTree::visit_leaves_in_range], returning the number of nodes visited.
+    fn visit_range<F>(
+        &self,
+        start: StarkHash,
+        end: StarkHash,
+        visitor: &mut F,
+    ) -> Result<usize, MerkleTreeError>
+    where
+        F: FnMut(StarkHash, StarkHash) -> ControlFlow<()>,
+    {
+        let mut visited = 0;
+        if start > end {
+            return Ok(visited);
+        }
+
+        let range = (start.view_bits(), end.view_bits());
+        let mut path = BitVec::<Msb0, u8>::with_capacity(251);
+        self.visit_subtree(&self.root, &mut path, range, visitor, &mut visited)?;
+
+        Ok(visited)
+    }
+
+    /// Visits the leaves of the subtree rooted at `node`, which is reached via `path`.
+    ///
+    /// Only children whose path prefix overlaps with the `range` are visited.
+    fn visit_subtree<F>(
+        &self,
+        node: &Rc<RefCell<Node>>,
+        path: &mut BitVec<Msb0, u8>,
+        range: (&BitSlice<Msb0, u8>, &BitSlice<Msb0, u8>),
+        visitor: &mut F,
+        visited: &mut usize,
+    ) -> Result<ControlFlow<()>, MerkleTreeError>
+    where
+        F: FnMut(StarkHash, StarkHash) -> ControlFlow<()>,
+    {
+        /// Returns true if any key starting with `prefix` can lie within the range.
+        fn overlaps(
+            prefix: &BitSlice<Msb0, u8>,
+            (start, end): (&BitSlice<Msb0, u8>, &BitSlice<Msb0, u8>),
+        ) -> bool {
+            let len = prefix.len();
+            prefix >= &start[..len] && prefix <= &end[..len]
+        }
+
+        let node = match &*node.borrow() {
+            Node::Unresolved(hash) if *hash == StarkHash::ZERO => {
+                return Ok(ControlFlow::Continue(()))
+            }
+            Node::Unresolved(hash) => self.resolve(*hash, path.len())?,
+            other => other.clone(),
+        };
+        *visited += 1;
+
+        match node {
+            Node::Unresolved(_) => unreachable!("Node was resolved above"),
+            Node::Leaf(value) => {
+                let key = StarkHash::from_bits(path).map_err(|_| MerkleTreeError::Overflow)?;
+                Ok(visitor(key, value))
+            }
+            Node::Binary(binary) => {
+                for (bit, child) in [(false, binary.left), (true, binary.right)] {
+                    path.push(bit);
+                    let flow = match overlaps(path, range) {
+                        true => self.visit_subtree(&child, path, range, visitor, visited)?,
+                        false => ControlFlow::Continue(()),
+                    };
+                    path.pop();
+
+                    if let ControlFlow::Break(()) = flow {
+                        return Ok(flow);
+                    }
+                }
+                Ok(ControlFlow::Continue(()))
+            }
+            Node::Edge(edge) => {
+                let height = path.len();
+                path.extend_from_bitslice(&edge.path);
+                let flow = match overlaps(path, range) {
+                    true => self.visit_subtree(&edge.child, path, range, visitor, visited)?,
+                    false => ControlFlow::Continue(()),
+                };
+                path.truncate(height);
+
+                Ok(flow)
+            }
+        }
+    }
+
+    /// Visits the leaves whose values differ between this tree and `newer`, in ascending key
+    /// order.
+    ///
+    /// The `visitor` is called with each such leaf's key, its value in this tree and its value
+    /// in `newer`, where a missing leaf has the value [StarkHash::ZERO]. The traversal can be
+    /// stopped early by returning [ControlFlow::Break].
+    ///
+    /// Both trees are descended in lockstep. Subtrees with identical hashes in both trees are
+    /// pruned without being loaded from storage, and where the tree structures diverge edges
+    /// are split one bit at a time until identical subtrees (or leaves) line up again.
+    pub fn diff<F>(&self, newer: &Self, mut visitor: F) -> Result<(), MerkleTreeError>
+    where
+        F: FnMut(StarkHash, StarkHash, StarkHash) -> ControlFlow<()>,
+    {
+        let old = Some(self.root.borrow().clone());
+        let new = Some(newer.root.borrow().clone());
+        let mut path = BitVec::<Msb0, u8>::with_capacity(251);
+
+        self.diff_subtree(newer, old, new, &mut path, &mut visitor)?;
+
+        Ok(())
+    }
+
+    /// Implements [MerkleTree::diff] for the subtrees reached via `path`.
+    ///
+    /// [None] represents an empty subtree. Nodes may be virtual edges created by splitting an
+    /// edge, these have no hash.
+    fn diff_subtree<F>(
+        &self,
+        newer: &Self,
+        old: Option<Node>,
+        new: Option<Node>,
+        path: &mut BitVec<Msb0, u8>,
+        visitor: &mut F,
+    ) -> Result<ControlFlow<()>, MerkleTreeError>
+    where
+        F: FnMut(StarkHash, StarkHash, StarkHash) -> ControlFlow<()>,
+    {
+        /// Splits a node into its left and right subtrees, shortening edges by a single bit.
+        fn split(node: Option<Node>) -> (Option<Node>, Option<Node>) {
+            match node {
+                None => (None, None),
+                Some(Node::Binary(binary)) => (
+                    Some(binary.left.borrow().clone()),
+                    Some(binary.right.borrow().clone()),
+                ),
+                Some(Node::Edge(edge)) => {
+                    let child = match edge.path.len() {
+                        1 => edge.child.borrow().clone(),
+                        _ => Node::Edge(EdgeNode {
+                            hash: None,
+                            height: edge.height + 1,
+                            path: edge.path[1..].to_bitvec(),
+                            child: edge.child,
+                        }),
+                    };
+
+                    match edge.path[0] {
+                        false => (Some(child), None),
+                        true => (None, Some(child)),
+                    }
+                }
+                Some(Node::Leaf(_)) | Some(Node::Unresolved(_)) => {
+                    unreachable!("Leaves have no children and nodes are resolved before splitting")
+                }
+            }
+        }
+
+        let is_empty =
+            |node: &Node| matches!(node, Node::Unresolved(hash) if *hash == StarkHash::ZERO);
+        let old = old.filter(|node| !is_empty(node));
+        let new = new.filter(|node| !is_empty(node));
+
+        let old_hash = old.as_ref().and_then(Node::hash);
+        let new_hash = new.as_ref().and_then(Node::hash);
+
+        match (&old, &new) {
+            (None, None) => return Ok(ControlFlow::Continue(())),
+            (Some(_), Some(_)) if old_hash.is_some() && old_hash == new_hash => {
+                return Ok(ControlFlow::Continue(()))
+            }
+            _ => {}
+        }
+
+        if path.len() == 251 {
+            // Leaves are either unresolved or resolved to their value, both of which are
+            // reported as the node's hash.
+            let key = StarkHash::from_bits(path).map_err(|_| MerkleTreeError::Overflow)?;
+            let old = old_hash.unwrap_or(StarkHash::ZERO);
+            let new = new_hash.unwrap_or(StarkHash::ZERO);
+
+            return Ok(visitor(key, old, new));
+        }
+
+        let old = match old {
+            Some(Node::Unresolved(hash)) => Some(self.resolve(hash, path.len())?),
+            other => other,
+        };
+        let new = match new {
+            Some(Node::Unresolved(hash)) => Some(newer.resolve(hash, path.len())?),
+            other => other,
+        };
+
+        let (old_left, old_right) = split(old);
+        let (new_left, new_right) = split(new);
+
+        for (bit, old, new) in [(false, old_left, new_left), (true, old_right, new_right)] {
+            path.push(bit);
+            let flow = self.diff_subtree(newer, old, new, path, visitor)?;
+            path.pop();
+
+            if let ControlFlow::Break(()) = flow {
+                return Ok(flow);
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
     /// Traverses from the current root towards the destination [Leaf](Node::Leaf) node.
     /// Returns the list of nodes along the path.
     ///
@@ -472,7 +950,7 @@ impl<T: NodeStorage> MerkleTree<T> {
     /// The final node can __not__ be a [Binary](Node::Binary) node since it would always be possible to continue
     /// on towards the destination. Nor can it be an [Unresolved](Node::Unresolved) node since this would be
     /// resolved to check if we can travel further.
-    fn traverse(&self, dst: StarkHash) -> anyhow::Result<Vec<Rc<RefCell<Node>>>> {
+    fn traverse(&self, dst: StarkHash) -> Result<Vec<Rc<RefCell<Node>>>, MerkleTreeError> {
         if self.root.borrow().is_empty() {
             return Ok(Vec::new());
         }
@@ -516,8 +994,17 @@ impl<T: NodeStorage> MerkleTree<T> {
     /// Retrieves the requested node from storage.
     ///
     /// Result will be either a [Binary](Node::Binary), [Edge](Node::Edge) or [Leaf](Node::Leaf) node.
-    fn resolve(&self, hash: StarkHash, height: usize) -> anyhow::Result<Node> {
-        let node = self.storage.get(hash)?.context("Node does not exists")?;
+    fn resolve(&self, hash: StarkHash, height: usize) -> Result<Node, MerkleTreeError> {
+        let node = match self.storage.get(hash) {
+            Ok(Some(node)) => node,
+            Ok(None) => return Err(MerkleTreeError::NotFound(hash)),
+            Err(e) => {
+                return Err(match e.downcast::<CorruptNodeError>() {
+                    Ok(corrupt) => MerkleTreeError::Corrupt(hash, corrupt),
+                    Err(e) => MerkleTreeError::Storage(e),
+                })
+            }
+        };
 
         let node = match node {
             PersistedNode::Binary(binary) => Node::Binary(BinaryNode {
@@ -544,7 +1031,7 @@ impl<T: NodeStorage> MerkleTree<T> {
     ///
     /// This can occur when mutating the tree (e.g. deleting a child of a binary node), and is an illegal state
     /// (since edge nodes __must be__ maximal subtrees).
-    fn merge_edges(&self, parent: &mut EdgeNode) -> anyhow::Result<()> {
+    fn merge_edges(&self, parent: &mut EdgeNode) -> Result<(), MerkleTreeError> {
         let resolved_child = match &*parent.child.borrow() {
             Node::Unresolved(hash) => self.resolve(*hash, parent.height + parent.path.len())?,
             other => other.clone(),
@@ -559,7 +1046,18 @@ impl<T: NodeStorage> MerkleTree<T> {
     }
 }
 
-#[cfg(any(test, fuzzing))]
+impl MerkleTree<()> {
+    /// Calculates the root hash of a transient tree.
+    ///
+    /// Unlike [MerkleTree::commit] this keeps the tree, so that proofs can still be generated
+    /// from it afterwards.
+    pub fn root_hash(&self) -> Result<StarkHash, MerkleTreeError> {
+        self.commit_subtree(&mut *self.root.borrow_mut())?;
+        // unwrap is safe as `commit_subtree` will set the hash.
+        Ok(self.root.borrow().hash().unwrap())
+    }
+}
+
 impl NodeStorage for () {
     fn get(&self, _key: StarkHash) -> anyhow::Result<Option<PersistedNode>> {
         // the rc<refcell> impl will do just fine by without any backing for transaction tree
@@ -584,6 +1082,7 @@ impl NodeStorage for () {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use assert_matches::assert_matches;
     use bitvec::prelude::*;
 
     fn starkhash_from_bits(bits: &BitSlice<Msb0, u8>) -> StarkHash {
@@ -612,7 +1111,48 @@ mod tests {
         let transaction = conn.transaction().unwrap();
 
         let non_root = StarkHash::from_hex_str("99cadc82").unwrap();
-        MerkleTree::load("test".to_string(), &transaction, non_root).unwrap_err();
+        let error = MerkleTree::load("test".to_string(), &transaction, non_root).unwrap_err();
+        assert_matches!(error, MerkleTreeError::NotFound(hash) if hash == non_root);
+    }
+
+    #[test]
+    fn load_corrupt_root() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        let mut uut = MerkleTree::load("test".to_string(), &transaction, StarkHash::ZERO).unwrap();
+        let key = StarkHash::from_hex_str("99cadc82").unwrap();
+        let value = StarkHash::from_hex_str("12345").unwrap();
+        uut.set(key, value).unwrap();
+        let root = uut.commit().unwrap();
+
+        transaction
+            .execute(
+                "UPDATE test SET data = X'0102' WHERE hash = ?",
+                [&root.as_be_bytes()[..]],
+            )
+            .unwrap();
+
+        let error = MerkleTree::load("test".to_string(), &transaction, root).unwrap_err();
+        assert_matches!(error, MerkleTreeError::Corrupt(hash, _) if hash == root);
+    }
+
+    #[test]
+    fn overflowing_key() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+        let mut uut = MerkleTree::load("test".to_string(), &transaction, StarkHash::ZERO).unwrap();
+
+        // 2^251 is a valid field element, but does not fit into a tree path.
+        let key = StarkHash::from_hex_str(
+            "0x800000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        assert_matches!(uut.get(key), Err(MerkleTreeError::Overflow));
+        assert_matches!(
+            uut.set(key, StarkHash::from_hex_str("1").unwrap()),
+            Err(MerkleTreeError::Overflow)
+        );
     }
 
     mod set {
@@ -931,6 +1471,66 @@ mod tests {
         }
     }
 
+    mod get_many {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Keys from a small range, which share long path prefixes, and arbitrary keys.
+        fn key() -> impl Strategy<Value = StarkHash> {
+            prop_oneof![
+                any::<u16>().prop_map(|k| StarkHash::from_be_slice(&k.to_be_bytes()).unwrap()),
+                any::<[u8; 31]>().prop_map(|k| StarkHash::from_be_slice(&k).unwrap()),
+            ]
+        }
+
+        fn value() -> impl Strategy<Value = StarkHash> {
+            any::<u64>().prop_map(|v| StarkHash::from_be_slice(&v.to_be_bytes()).unwrap())
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(64))]
+
+            #[test]
+            fn matches_sequential_get(
+                leaves in prop::collection::vec((key(), value()), 0..64),
+                absent in prop::collection::vec(key(), 0..16),
+                seed in any::<u64>(),
+            ) {
+                let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+                let transaction = conn.transaction().unwrap();
+                let mut uut =
+                    MerkleTree::load("test".to_string(), &transaction, StarkHash::ZERO).unwrap();
+                for (key, value) in &leaves {
+                    uut.set(*key, *value).unwrap();
+                }
+                let root = uut.commit().unwrap();
+
+                // Present keys (including duplicates) and most likely absent ones, shuffled.
+                let mut keys = leaves
+                    .iter()
+                    .map(|(key, _)| *key)
+                    .chain(leaves.iter().take(3).map(|(key, _)| *key))
+                    .chain(absent)
+                    .collect::<Vec<_>>();
+                let len = keys.len();
+                for i in 0..len {
+                    let j = (seed.wrapping_mul(i as u64 + 1) % len as u64) as usize;
+                    keys.swap(i, j);
+                }
+
+                let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+                let expected = keys
+                    .iter()
+                    .map(|key| uut.get(*key).unwrap())
+                    .collect::<Vec<_>>();
+
+                // A freshly loaded tree, so that get_many resolves the nodes itself.
+                let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+                prop_assert_eq!(uut.get_many(&keys).unwrap(), expected);
+            }
+        }
+    }
+
     mod persistence {
         use super::*;
 
@@ -1235,6 +1835,344 @@ mod tests {
         }
     }
 
+    mod visit_leaves {
+        use super::*;
+
+        /// Creates and commits a tree with leaves `0..count`, where each value is the key + 1.
+        fn create_tree(transaction: &Transaction, count: u64) -> StarkHash {
+            let mut tree =
+                MerkleTree::load("test".to_string(), transaction, StarkHash::ZERO).unwrap();
+            for i in 0..count {
+                tree.set(key(i), key(i + 1)).unwrap();
+            }
+            tree.commit().unwrap()
+        }
+
+        fn key(i: u64) -> StarkHash {
+            StarkHash::from_be_slice(&i.to_be_bytes()).unwrap()
+        }
+
+        #[test]
+        fn empty() {
+            let uut = MerkleTree::<()>::default();
+
+            let mut leaves = Vec::new();
+            uut.visit_leaves(|key, value| {
+                leaves.push((key, value));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+            assert!(leaves.is_empty());
+        }
+
+        #[test]
+        fn all() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 256);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let mut leaves = Vec::new();
+            uut.visit_leaves(|key, value| {
+                leaves.push((key, value));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+            let expected = (0..256).map(|i| (key(i), key(i + 1))).collect::<Vec<_>>();
+            assert_eq!(leaves, expected);
+        }
+
+        #[test]
+        fn in_range() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 256);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let mut leaves = Vec::new();
+            uut.visit_leaves_in_range(key(13), key(77), |key, value| {
+                leaves.push((key, value));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+            let expected = (13..=77).map(|i| (key(i), key(i + 1))).collect::<Vec<_>>();
+            assert_eq!(leaves, expected);
+        }
+
+        #[test]
+        fn range_past_last_leaf() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 256);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let mut leaves = Vec::new();
+            uut.visit_leaves_in_range(key(250), key(1000), |key, value| {
+                leaves.push((key, value));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+            let expected = (250..256).map(|i| (key(i), key(i + 1))).collect::<Vec<_>>();
+            assert_eq!(leaves, expected);
+        }
+
+        #[test]
+        fn stops_on_break() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 256);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let mut leaves = Vec::new();
+            uut.visit_leaves_in_range(key(100), key(200), |key, _| {
+                leaves.push(key);
+                match leaves.len() {
+                    5 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            })
+            .unwrap();
+
+            let expected = (100..105).map(key).collect::<Vec<_>>();
+            assert_eq!(leaves, expected);
+        }
+
+        #[test]
+        fn prunes_subtrees_outside_of_range() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 256);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let max_key = StarkHash::from_bits(&bitvec![Msb0, u8; 1; 251]).unwrap();
+            let full = uut
+                .visit_range(StarkHash::ZERO, max_key, &mut |_, _| {
+                    ControlFlow::Continue(())
+                })
+                .unwrap();
+            // The tree consists of a root edge leading to a complete binary tree of
+            // depth 8 i.e. 1 edge + 255 binary nodes + 256 leaves.
+            assert_eq!(full, 512);
+
+            let mut leaves = Vec::new();
+            let single = uut
+                .visit_range(key(42), key(42), &mut |key, _| {
+                    leaves.push(key);
+                    ControlFlow::Continue(())
+                })
+                .unwrap();
+            assert_eq!(leaves, vec![key(42)]);
+            // Only the path to the leaf is visited: 1 edge + 8 binary nodes + 1 leaf.
+            assert_eq!(single, 10);
+        }
+    }
+
+    mod diff {
+        use super::*;
+
+        fn key(i: u64) -> StarkHash {
+            StarkHash::from_be_slice(&i.to_be_bytes()).unwrap()
+        }
+
+        /// Sets the leaves and commits the tree, starting from `root`.
+        fn commit(transaction: &Transaction, root: StarkHash, leaves: &[(u64, u64)]) -> StarkHash {
+            let mut tree = MerkleTree::load("test".to_string(), transaction, root).unwrap();
+            for (k, v) in leaves {
+                tree.set(key(*k), key(*v)).unwrap();
+            }
+            tree.commit().unwrap()
+        }
+
+        fn diff(
+            transaction: &Transaction,
+            old: StarkHash,
+            new: StarkHash,
+        ) -> Vec<(StarkHash, StarkHash, StarkHash)> {
+            let old = MerkleTree::load("test".to_string(), transaction, old).unwrap();
+            let new = MerkleTree::load("test".to_string(), transaction, new).unwrap();
+
+            let mut changes = Vec::new();
+            old.diff(&new, |key, old, new| {
+                changes.push((key, old, new));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+            changes
+        }
+
+        #[test]
+        fn identical() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let leaves = (0..64).map(|i| (i, i + 1)).collect::<Vec<_>>();
+            let root = commit(&transaction, StarkHash::ZERO, &leaves);
+
+            assert!(diff(&transaction, root, root).is_empty());
+        }
+
+        #[test]
+        fn from_empty() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let leaves = (0..64).map(|i| (i, i + 1)).collect::<Vec<_>>();
+            let root = commit(&transaction, StarkHash::ZERO, &leaves);
+
+            let expected = leaves
+                .iter()
+                .map(|(k, v)| (key(*k), StarkHash::ZERO, key(*v)))
+                .collect::<Vec<_>>();
+            assert_eq!(diff(&transaction, StarkHash::ZERO, root), expected);
+
+            let expected = leaves
+                .iter()
+                .map(|(k, v)| (key(*k), key(*v), StarkHash::ZERO))
+                .collect::<Vec<_>>();
+            assert_eq!(diff(&transaction, root, StarkHash::ZERO), expected);
+        }
+
+        #[test]
+        fn changes() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let leaves = (0..256).map(|i| (i, i + 1)).collect::<Vec<_>>();
+            let old = commit(&transaction, StarkHash::ZERO, &leaves);
+            // Modify, delete and insert a leaf. The insert splits the root edge of the old tree.
+            let new = commit(&transaction, old, &[(5, 500), (7, 0), (300, 301)]);
+
+            let expected = vec![
+                (key(5), key(6), key(500)),
+                (key(7), key(8), StarkHash::ZERO),
+                (key(300), StarkHash::ZERO, key(301)),
+            ];
+            assert_eq!(diff(&transaction, old, new), expected);
+        }
+
+        #[test]
+        fn stops_on_break() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let leaves = (0..16).map(|i| (i, i + 1)).collect::<Vec<_>>();
+            let root = commit(&transaction, StarkHash::ZERO, &leaves);
+
+            let old = MerkleTree::load("test".to_string(), &transaction, StarkHash::ZERO).unwrap();
+            let new = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let mut keys = Vec::new();
+            old.diff(&new, |key, _, _| {
+                keys.push(key);
+                match keys.len() {
+                    3 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            })
+            .unwrap();
+
+            assert_eq!(keys, vec![key(0), key(1), key(2)]);
+        }
+    }
+
+    mod proof {
+        use super::*;
+
+        fn key(i: u64) -> StarkHash {
+            StarkHash::from_be_slice(&i.to_be_bytes()).unwrap()
+        }
+
+        /// Creates and commits a tree with leaves at every third key in `0..count`, where each
+        /// value is the key + 1.
+        fn create_tree(transaction: &Transaction, count: u64) -> StarkHash {
+            let mut tree =
+                MerkleTree::load("test".to_string(), transaction, StarkHash::ZERO).unwrap();
+            for i in (0..count).step_by(3) {
+                tree.set(key(i), key(i + 1)).unwrap();
+            }
+            tree.commit().unwrap()
+        }
+
+        #[test]
+        fn leaves_are_proven() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 100);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            for i in (0..100).step_by(3) {
+                let proof = uut.get_proof(key(i)).unwrap();
+                assert!(verify_proof(root, key(i).view_bits(), key(i + 1), &proof));
+                assert!(!verify_proof(root, key(i).view_bits(), key(i + 2), &proof));
+                assert!(!verify_proof(
+                    root,
+                    key(i + 1).view_bits(),
+                    key(i + 1),
+                    &proof
+                ));
+            }
+        }
+
+        #[test]
+        fn missing_leaf_ends_with_diverging_edge() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 100);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let proof = uut.get_proof(key(1)).unwrap();
+            assert_matches!(proof.last(), Some(ProofNode::Edge { .. }));
+            assert!(!verify_proof(
+                root,
+                key(1).view_bits(),
+                StarkHash::ZERO,
+                &proof
+            ));
+        }
+
+        #[test]
+        fn tampered_proof_is_rejected() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            let transaction = conn.transaction().unwrap();
+            let root = create_tree(&transaction, 100);
+            let uut = MerkleTree::load("test".to_string(), &transaction, root).unwrap();
+
+            let proof = uut.get_proof(key(42)).unwrap();
+            assert!(verify_proof(root, key(42).view_bits(), key(43), &proof));
+
+            for i in 0..proof.len() {
+                let mut tampered = proof.clone();
+                match &mut tampered[i] {
+                    ProofNode::Binary { left, right } => std::mem::swap(left, right),
+                    ProofNode::Edge { child, .. } => *child = *child + key(1),
+                }
+                assert!(!verify_proof(root, key(42).view_bits(), key(43), &tampered));
+            }
+
+            let mut truncated = proof;
+            truncated.pop();
+            assert!(!verify_proof(
+                root,
+                key(42).view_bits(),
+                key(43),
+                &truncated
+            ));
+        }
+
+        #[test]
+        fn transient_tree() {
+            let mut uut = MerkleTree::<()>::default();
+            uut.set(key(1), key(2)).unwrap();
+            uut.set(key(5), key(6)).unwrap();
+            assert_matches!(uut.get_proof(key(1)), Err(MerkleTreeError::Uncommitted));
+
+            let root = uut.root_hash().unwrap();
+            let proof = uut.get_proof(key(5)).unwrap();
+            assert!(verify_proof(root, key(5).view_bits(), key(6), &proof));
+            assert_eq!(root, uut.commit().unwrap());
+        }
+    }
+
     mod real_world {
         use super::*;
 
@@ -0,0 +1,387 @@
+//! Applies the storage updates of many contracts concurrently.
+//!
+//! The storage trees of different contracts are independent, but a [Transaction] can't be shared
+//! between threads. Only the hashing of the updated trees is therefore spread across workers,
+//! which dominates the cost of an update, while all database access stays on the calling thread.
+//! Contracts are processed in batches, each in three phases:
+//!
+//! 1. The nodes which the updates traverse are read from the database, by applying the updates to
+//!    a throwaway tree which records every node it resolves.
+//! 2. Workers apply the updates again on trees backed only by the recorded nodes, commit them, and
+//!    buffer the resulting node writes in memory.
+//! 3. The buffered writes are replayed on the calling thread, in the order in which
+//!    [update_contract_state](super::update_contract_state) would have performed them.
+//!
+//! Batching bounds the memory held by the recorded nodes and buffered writes to that of a few
+//! contracts per worker.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use anyhow::Context;
+use rayon::prelude::*;
+use rusqlite::Transaction;
+use stark_hash::StarkHash;
+
+use crate::{
+    core::{ClassHash, ContractRoot, ContractStateHash},
+    ethereum::state_update::ContractUpdate,
+    state::{
+        calculate_contract_state_hash,
+        merkle_tree::{MerkleTree, NodeStorage},
+        state_tree::GlobalStateTree,
+    },
+    storage::{
+        merkle_tree::{PersistedNode, RcNodeStorage},
+        ContractsStateTable, ContractsTable,
+    },
+};
+
+/// The table which stores the nodes of all contract storage trees.
+const CONTRACT_TREE_TABLE: &str = "tree_contracts";
+
+/// Number of contracts per batch and worker.
+const CONTRACTS_PER_WORKER: usize = 4;
+
+/// Updates the state of every contract in `updates` like
+/// [update_contract_state](super::update_contract_state), hashing their storage trees on the
+/// workers of `pool`. Returns the new [ContractStateHash] of each contract, in order.
+///
+/// The contracts of `updates` must be distinct, as their trees are updated independently of
+/// each other.
+pub(crate) fn update_contract_states(
+    updates: &[ContractUpdate],
+    global_tree: &GlobalStateTree<'_>,
+    db: &Transaction<'_>,
+    pool: &rayon::ThreadPool,
+) -> anyhow::Result<Vec<ContractStateHash>> {
+    let batch_size = pool.current_num_threads() * CONTRACTS_PER_WORKER;
+    let mut state_hashes = Vec::with_capacity(updates.len());
+
+    for batch in updates.chunks(batch_size) {
+        let jobs = batch
+            .iter()
+            .map(|update| prepare(update, global_tree, db))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let results = pool.install(|| {
+            jobs.par_iter()
+                .map(hash_contract_tree)
+                .collect::<anyhow::Result<Vec<_>>>()
+        })?;
+
+        let storage = RcNodeStorage::open(CONTRACT_TREE_TABLE.to_owned(), db)
+            .context("Open contract tree storage")?;
+        for (job, (root, writes)) in jobs.iter().zip(results) {
+            for write in writes {
+                match write {
+                    NodeWrite::Upsert(key, node) => storage.upsert(key, node),
+                    NodeWrite::IncrementRefCount(key) => storage.increment_ref_count(key),
+                }
+                .context("Write contract tree node")?;
+            }
+
+            let state_hash = calculate_contract_state_hash(job.class_hash, root);
+            ContractsStateTable::upsert(db, state_hash, job.class_hash, root)
+                .context("Insert contract state hash into contracts state table")?;
+            state_hashes.push(state_hash);
+        }
+    }
+
+    Ok(state_hashes)
+}
+
+/// A contract whose storage tree is to be updated by a worker.
+struct Job<'a> {
+    update: &'a ContractUpdate,
+    root: ContractRoot,
+    class_hash: ClassHash,
+    /// The nodes which applying `update` to the tree with `root` resolves.
+    nodes: HashMap<StarkHash, PersistedNode>,
+}
+
+/// Reads everything from `db` which is required to update the contract's state.
+fn prepare<'a>(
+    update: &'a ContractUpdate,
+    global_tree: &GlobalStateTree<'_>,
+    db: &Transaction<'_>,
+) -> anyhow::Result<Job<'a>> {
+    let contract_state_hash = global_tree
+        .get(update.address)
+        .context("Get contract state hash from global state tree")?;
+    let root = ContractsStateTable::get_root(db, contract_state_hash)
+        .context("Read contract root from contracts state table")?
+        .unwrap_or(ContractRoot(StarkHash::ZERO));
+    let class_hash = ContractsTable::get_hash(db, update.address)
+        .context("Read class hash from contracts table")?
+        .context("Class hash is missing from contracts table")?;
+
+    let nodes = RefCell::new(HashMap::new());
+    {
+        let storage = RecordingNodeStorage {
+            storage: RcNodeStorage::open(CONTRACT_TREE_TABLE.to_owned(), db)
+                .context("Open contract tree storage")?,
+            nodes: &nodes,
+        };
+        let mut tree = MerkleTree::new(storage, root.0).context("Load contract state tree")?;
+        for storage_update in &update.storage_updates {
+            tree.set(storage_update.address.0, storage_update.value.0)
+                .context("Update contract storage tree")?;
+        }
+    }
+
+    Ok(Job {
+        update,
+        root,
+        class_hash,
+        nodes: nodes.into_inner(),
+    })
+}
+
+/// Applies the job's update to its storage tree and commits it. Returns the new root and the
+/// node writes of the commit.
+fn hash_contract_tree(job: &Job<'_>) -> anyhow::Result<(ContractRoot, Vec<NodeWrite>)> {
+    let writes = RefCell::new(Vec::new());
+    let root = {
+        let storage = BufferedNodeStorage {
+            nodes: &job.nodes,
+            writes: &writes,
+        };
+        let mut tree = MerkleTree::new(storage, job.root.0).context("Load contract state tree")?;
+        for storage_update in &job.update.storage_updates {
+            tree.set(storage_update.address.0, storage_update.value.0)
+                .context("Update contract storage tree")?;
+        }
+        tree.commit()
+            .context("Apply contract storage tree changes")?
+    };
+
+    Ok((ContractRoot(root), writes.into_inner()))
+}
+
+/// A write performed on [NodeStorage] by [MerkleTree::commit].
+#[derive(Debug, Clone, PartialEq)]
+enum NodeWrite {
+    Upsert(StarkHash, PersistedNode),
+    IncrementRefCount(StarkHash),
+}
+
+/// Read-only [NodeStorage] which records every node it reads.
+struct RecordingNodeStorage<'a> {
+    storage: RcNodeStorage<'a>,
+    nodes: &'a RefCell<HashMap<StarkHash, PersistedNode>>,
+}
+
+impl NodeStorage for RecordingNodeStorage<'_> {
+    fn get(&self, key: StarkHash) -> anyhow::Result<Option<PersistedNode>> {
+        let node = self.storage.get(key)?;
+        if let Some(node) = &node {
+            self.nodes.borrow_mut().insert(key, node.clone());
+        }
+        Ok(node)
+    }
+
+    fn upsert(&self, _: StarkHash, _: PersistedNode) -> anyhow::Result<()> {
+        anyhow::bail!("Node storage is read-only")
+    }
+
+    #[cfg(test)]
+    fn decrement_ref_count(&self, _: StarkHash) -> anyhow::Result<()> {
+        anyhow::bail!("Node storage is read-only")
+    }
+
+    fn increment_ref_count(&self, _: StarkHash) -> anyhow::Result<()> {
+        anyhow::bail!("Node storage is read-only")
+    }
+}
+
+/// [NodeStorage] which reads from a fixed set of nodes, and buffers all writes.
+struct BufferedNodeStorage<'a> {
+    nodes: &'a HashMap<StarkHash, PersistedNode>,
+    writes: &'a RefCell<Vec<NodeWrite>>,
+}
+
+impl NodeStorage for BufferedNodeStorage<'_> {
+    fn get(&self, key: StarkHash) -> anyhow::Result<Option<PersistedNode>> {
+        Ok(self.nodes.get(&key).cloned())
+    }
+
+    fn upsert(&self, key: StarkHash, node: PersistedNode) -> anyhow::Result<()> {
+        self.writes.borrow_mut().push(NodeWrite::Upsert(key, node));
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn decrement_ref_count(&self, _: StarkHash) -> anyhow::Result<()> {
+        anyhow::bail!("Deleting nodes is not supported")
+    }
+
+    fn increment_ref_count(&self, key: StarkHash) -> anyhow::Result<()> {
+        self.writes
+            .borrow_mut()
+            .push(NodeWrite::IncrementRefCount(key));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{ClassHash, ContractAddress, GlobalRoot, StorageAddress, StorageValue};
+    use crate::ethereum::state_update::{
+        ContractUpdate, DeployedContract, StateUpdate, StorageUpdate,
+    };
+    use crate::state::sync::update_starknet_state;
+    use crate::storage::{test_utils, ContractCodeTable, StarknetBlocksTable, Storage};
+    use proptest::prelude::*;
+    use stark_hash::StarkHash;
+
+    fn felt(value: u64) -> StarkHash {
+        StarkHash::from_be_slice(&value.to_be_bytes()).unwrap()
+    }
+
+    fn class() -> ClassHash {
+        ClassHash(felt(0xc1a55))
+    }
+
+    /// Deploys `contracts` contracts with addresses `1..=contracts`.
+    fn deployments(contracts: u64) -> StateUpdate {
+        StateUpdate {
+            deployed_contracts: (1..=contracts)
+                .map(|address| DeployedContract {
+                    address: ContractAddress(felt(address)),
+                    hash: class(),
+                    call_data: vec![],
+                })
+                .collect(),
+            contract_updates: vec![],
+        }
+    }
+
+    /// A node of a storage tree, as stored in the database.
+    type StoredNode = (Vec<u8>, Option<Vec<u8>>, i64);
+
+    /// Applies `diffs` as consecutive blocks, each in its own transaction. Returns the global
+    /// root of each block and the resulting contract storage tree nodes.
+    fn apply(
+        diffs: &[StateUpdate],
+        pool: Option<&rayon::ThreadPool>,
+    ) -> (Vec<GlobalRoot>, Vec<StoredNode>) {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        ContractCodeTable::insert_without_definition(&connection, class()).unwrap();
+
+        let mut roots = Vec::new();
+        for (diff, mut block) in diffs.iter().zip(test_utils::create_blocks::<8>()) {
+            let transaction = connection.transaction().unwrap();
+            block.root = update_starknet_state(&transaction, diff, pool).unwrap();
+            StarknetBlocksTable::insert(&transaction, &block).unwrap();
+            transaction.commit().unwrap();
+
+            roots.push(block.root);
+        }
+
+        let mut statement = connection
+            .prepare("SELECT hash, data, ref_count FROM tree_contracts ORDER BY hash")
+            .unwrap();
+        let nodes = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        (roots, nodes)
+    }
+
+    fn pool(threads: usize) -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+    }
+
+    const CONTRACTS: u64 = 12;
+
+    /// Blocks which write to a few storage slots of the [CONTRACTS] deployed contracts. Slots
+    /// and values are drawn from small ranges, so that writes overwrite and delete each other.
+    fn contract_updates() -> impl Strategy<Value = Vec<StateUpdate>> {
+        let storage_updates = prop::collection::vec((0..32u64, 0..4u64), 1..8);
+        let contract_updates = prop::collection::vec((1..=CONTRACTS, storage_updates), 1..16);
+        let block = contract_updates.prop_map(|updates| StateUpdate {
+            deployed_contracts: vec![],
+            contract_updates: updates
+                .into_iter()
+                .map(|(address, writes)| ContractUpdate {
+                    address: ContractAddress(felt(address)),
+                    storage_updates: writes
+                        .into_iter()
+                        .map(|(key, value)| StorageUpdate {
+                            address: StorageAddress(felt(key)),
+                            value: StorageValue(felt(value)),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        });
+
+        prop::collection::vec(block, 1..6)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn matches_sequential_update(blocks in contract_updates()) {
+            let diffs = std::iter::once(deployments(CONTRACTS))
+                .chain(blocks)
+                .collect::<Vec<_>>();
+
+            let sequential = apply(&diffs, None);
+            // Two threads process batches of eight contracts, so blocks span multiple batches.
+            let parallel = apply(&diffs, Some(&pool(2)));
+
+            prop_assert_eq!(parallel.0, sequential.0);
+            prop_assert_eq!(parallel.1, sequential.1);
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --release"]
+    fn speedup() {
+        const CONTRACTS: u64 = 200;
+        const WRITES: u64 = 50;
+
+        let block = StateUpdate {
+            deployed_contracts: vec![],
+            contract_updates: (1..=CONTRACTS)
+                .map(|address| ContractUpdate {
+                    address: ContractAddress(felt(address)),
+                    storage_updates: (0..WRITES)
+                        .map(|key| StorageUpdate {
+                            address: StorageAddress(felt(key * 7919 + address)),
+                            value: StorageValue(felt(key + 1)),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        let diffs = [deployments(CONTRACTS), block];
+
+        let start = std::time::Instant::now();
+        let sequential = apply(&diffs, None);
+        let sequential_time = start.elapsed();
+
+        let pool = pool(8);
+        let start = std::time::Instant::now();
+        let parallel = apply(&diffs, Some(&pool));
+        let parallel_time = start.elapsed();
+
+        assert_eq!(parallel, sequential);
+        eprintln!(
+            "sequential: {:?}, 8 threads: {:?}, speedup: {:.2}",
+            sequential_time,
+            parallel_time,
+            sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+        );
+        assert!(parallel_time < sequential_time);
+    }
+}
@@ -0,0 +1,240 @@
+//! The pending block, shared between the sync process and the RPC API.
+
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+
+use crate::core::StarknetBlockHash;
+use crate::sequencer::reply::{transaction::Event, Block};
+
+/// The pending block along with the events emitted by its transactions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingBlock {
+    pub block: Block,
+    /// The events of all receipts in `block`, in transaction order.
+    pub events: Vec<Event>,
+}
+
+impl PendingBlock {
+    pub fn new(block: Block) -> Self {
+        let events = block
+            .transaction_receipts
+            .iter()
+            .flat_map(|receipt| receipt.events.iter().cloned())
+            .collect();
+
+        Self { block, events }
+    }
+}
+
+/// Holds the current [PendingBlock], if any.
+///
+/// The block and its events are replaced together, so readers always observe a consistent
+/// snapshot. Cloning is cheap and all clones share the same pending block.
+///
+/// A pending block is only held while it builds on the [head](Self::set_head) of the sync
+/// process. Both are kept under the same lock, so that a pending block fetched before the head
+/// moved on cannot be published after it.
+#[derive(Debug, Clone, Default)]
+pub struct PendingData(Arc<RwLock<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    head: Option<StarknetBlockHash>,
+    block: Option<Arc<PendingBlock>>,
+}
+
+impl PendingData {
+    /// Replaces the current pending block, if `pending` builds on the current head. Otherwise
+    /// the current pending block is removed, as it is outdated as well.
+    ///
+    /// Returns whether `pending` was kept.
+    pub async fn set(&self, pending: PendingBlock) -> bool {
+        let mut inner = self.0.write().await;
+        let builds_on_head = inner.head == Some(pending.block.parent_block_hash);
+        inner.block = builds_on_head.then(|| Arc::new(pending));
+        builds_on_head
+    }
+
+    /// Records the new head of the sync process, and removes the pending block which was built
+    /// on the previous one.
+    pub async fn set_head(&self, head: Option<StarknetBlockHash>) {
+        let mut inner = self.0.write().await;
+        inner.head = head;
+        inner.block = None;
+    }
+
+    /// Removes the current pending block, e.g. once it has been finalized.
+    pub async fn clear(&self) {
+        self.0.write().await.block = None;
+    }
+
+    /// Returns a snapshot of the current pending block.
+    pub async fn get(&self) -> Option<Arc<PendingBlock>> {
+        self.0.read().await.block.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PendingBlock, PendingData};
+    use crate::core::{
+        ContractAddress, EventKey, StarknetBlockHash, StarknetBlockTimestamp,
+        StarknetTransactionHash, StarknetTransactionIndex,
+    };
+    use crate::sequencer::reply::{
+        transaction::{
+            execution_resources::{BuiltinInstanceCounter, EmptyBuiltinInstanceCounter},
+            Event, ExecutionResources, Receipt,
+        },
+        Block, Status,
+    };
+    use stark_hash::StarkHash;
+
+    /// Creates a pending block with `n` receipts, each emitting a single event keyed by `n`.
+    fn pending_block(n: u64) -> PendingBlock {
+        let key = EventKey(StarkHash::from_be_slice(&n.to_be_bytes()).unwrap());
+        let receipts = (0..n)
+            .map(|i| Receipt {
+                actual_fee: None,
+                events: vec![Event {
+                    data: vec![],
+                    from_address: ContractAddress(StarkHash::from_be_slice(&[1]).unwrap()),
+                    keys: vec![key],
+                }],
+                execution_resources: Some(ExecutionResources {
+                    builtin_instance_counter: BuiltinInstanceCounter::Empty(
+                        EmptyBuiltinInstanceCounter {},
+                    ),
+                    n_memory_holes: 0,
+                    n_steps: 0,
+                }),
+                execution_status: None,
+                placeholder: false,
+                l1_to_l2_consumed_message: None,
+                l2_to_l1_messages: vec![],
+                transaction_hash: StarknetTransactionHash(
+                    StarkHash::from_be_slice(&i.to_be_bytes()).unwrap(),
+                ),
+                transaction_index: StarknetTransactionIndex(i),
+            })
+            .collect();
+
+        PendingBlock::new(Block {
+            block_hash: None,
+            block_number: None,
+            gas_price: None,
+            parent_block_hash: StarknetBlockHash(StarkHash::from_be_slice(&[0xa]).unwrap()),
+            sequencer_address: None,
+            state_root: None,
+            status: Status::Pending,
+            timestamp: StarknetBlockTimestamp(n),
+            transaction_receipts: receipts,
+            transactions: vec![],
+        })
+    }
+
+    #[test]
+    fn events_follow_receipts() {
+        let pending = pending_block(3);
+        assert_eq!(pending.events.len(), 3);
+        assert_eq!(
+            pending.events,
+            pending
+                .block
+                .transaction_receipts
+                .iter()
+                .flat_map(|r| r.events.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn set_get_clear() {
+        let data = PendingData::default();
+        assert_eq!(data.get().await, None);
+
+        data.set_head(Some(pending_block(2).block.parent_block_hash))
+            .await;
+        assert!(data.set(pending_block(2)).await);
+        assert_eq!(data.get().await.as_deref(), Some(&pending_block(2)));
+
+        // Clones share the same pending block.
+        assert!(data.clone().set(pending_block(5)).await);
+        assert_eq!(data.get().await.as_deref(), Some(&pending_block(5)));
+
+        data.clear().await;
+        assert_eq!(data.get().await, None);
+    }
+
+    #[tokio::test]
+    async fn only_kept_while_it_builds_on_the_head() {
+        let data = PendingData::default();
+        let parent = pending_block(1).block.parent_block_hash;
+
+        // There is no head yet.
+        assert!(!data.set(pending_block(1)).await);
+        assert_eq!(data.get().await, None);
+
+        data.set_head(Some(parent)).await;
+        assert!(data.set(pending_block(1)).await);
+
+        // A new head outdates the pending block, as well as any fetched before it was stored.
+        let head = StarknetBlockHash(StarkHash::from_be_slice(&[0xb]).unwrap());
+        data.set_head(Some(head)).await;
+        assert_eq!(data.get().await, None);
+        assert!(!data.set(pending_block(2)).await);
+        assert_eq!(data.get().await, None);
+
+        // A stale pending block also removes the current one.
+        let mut next = pending_block(3);
+        next.block.parent_block_hash = head;
+        assert!(data.set(next).await);
+        assert!(!data.set(pending_block(4)).await);
+        assert_eq!(data.get().await, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn readers_observe_consistent_snapshots() {
+        let data = PendingData::default();
+        data.set_head(Some(pending_block(1).block.parent_block_hash))
+            .await;
+
+        let writer = {
+            let data = data.clone();
+            tokio::spawn(async move {
+                for n in 1..=200 {
+                    data.set(pending_block(n)).await;
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let readers = (0..4)
+            .map(|_| {
+                let data = data.clone();
+                tokio::spawn(async move {
+                    for _ in 0..500 {
+                        if let Some(pending) = data.get().await {
+                            // The block and its events must come from the same update.
+                            let n = pending.block.timestamp.0;
+                            assert_eq!(pending.block.transaction_receipts.len() as u64, n);
+                            assert_eq!(pending.events.len() as u64, n);
+
+                            let key = EventKey(StarkHash::from_be_slice(&n.to_be_bytes()).unwrap());
+                            assert!(pending.events.iter().all(|e| e.keys == [key]));
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        writer.await.unwrap();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+
+        assert_eq!(data.get().await.as_deref(), Some(&pending_block(200)));
+    }
+}
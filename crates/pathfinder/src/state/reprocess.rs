@@ -0,0 +1,328 @@
+//! Re-parses the retained gateway JSON of blocks, see [RawGatewayBlocksTable].
+//!
+//! Blocks are stored as parsed by the code which synced them, so fields which it failed to
+//! capture are missing from the database. Once the parsing is fixed, reprocessing the blocks
+//! whose raw JSON is still retained repairs their stored transactions and receipts.
+
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::{
+    core::StarknetBlockNumber,
+    sequencer::reply::Block,
+    storage::{
+        RawGatewayBlocksTable, StarknetBlocksBlockId, StarknetBlocksTable,
+        StarknetTransactionsTable, StorageLimits,
+    },
+};
+
+/// The outcome of [reprocess_blocks].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReprocessSummary {
+    /// Blocks whose stored data differed from the re-parsed JSON and was replaced.
+    pub repaired: u64,
+    /// Blocks whose stored data already matched the re-parsed JSON.
+    pub unchanged: u64,
+    /// Blocks without retained JSON, which were left untouched.
+    pub missing: u64,
+}
+
+/// Re-parses the retained gateway JSON of the blocks `from..=to` with the current code, and
+/// replaces the stored transactions and receipts (and with them, the events) of the blocks where
+/// they differ. The block's previous data is deleted first, so nothing stored only by the old
+/// code survives.
+///
+/// The block headers are not rewritten, as blocks are identified by their hash. A block whose
+/// JSON no longer parses, or whose hash does not match the stored block, fails reprocessing.
+pub fn reprocess_blocks(
+    transaction: &Transaction<'_>,
+    from: StarknetBlockNumber,
+    to: StarknetBlockNumber,
+    limits: &StorageLimits,
+) -> anyhow::Result<ReprocessSummary> {
+    let mut summary = ReprocessSummary::default();
+
+    for number in from.0..=to.0 {
+        let number = StarknetBlockNumber(number);
+
+        let (hash, raw_block) = match RawGatewayBlocksTable::get(transaction, number, limits)
+            .with_context(|| format!("Read raw block {}", number.0))?
+        {
+            Some(raw) => raw,
+            None => {
+                summary.missing += 1;
+                continue;
+            }
+        };
+
+        let block = serde_json::from_slice::<Block>(&raw_block)
+            .with_context(|| format!("Parse raw block {}", number.0))?;
+        anyhow::ensure!(
+            block.block_hash == Some(hash),
+            "Raw block {} does not match its stored hash",
+            number.0
+        );
+        let stored_block =
+            StarknetBlocksTable::get(transaction, StarknetBlocksBlockId::Number(number))
+                .context("Query block")?
+                .with_context(|| format!("Block {} is missing", number.0))?;
+        anyhow::ensure!(
+            stored_block.hash == hash,
+            "Raw block {} does not match the stored block",
+            number.0
+        );
+
+        anyhow::ensure!(
+            block.transactions.len() == block.transaction_receipts.len(),
+            "Transactions and receipts mismatch in block {}. There were {} transactions and {} receipts.",
+            number.0,
+            block.transactions.len(),
+            block.transaction_receipts.len()
+        );
+        let transaction_data = block
+            .transactions
+            .into_iter()
+            .zip(block.transaction_receipts.into_iter())
+            .collect::<Vec<_>>();
+
+        let stored = StarknetTransactionsTable::get_transaction_data_for_block(
+            transaction,
+            StarknetBlocksBlockId::Number(number),
+            limits,
+        )
+        .context("Query stored transactions")?;
+
+        if stored == transaction_data {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        tracing::info!(block=%number.0, "Repairing block from its raw gateway JSON");
+        StarknetTransactionsTable::delete_block(transaction, hash, number)
+            .context("Delete previous transaction data")?;
+        StarknetTransactionsTable::upsert(transaction, hash, number, &transaction_data, limits)
+            .context("Replace transaction data")?;
+        summary.repaired += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Fee, StarknetBlockNumber};
+    use crate::sequencer::reply::{transaction, Status};
+    use crate::storage::{test_utils, StarknetBlock, StarknetEventsTable, Storage};
+    use web3::types::H128;
+
+    /// The gateway's view of `block`, with `transaction_data`.
+    fn raw_block(
+        block: &StarknetBlock,
+        transaction_data: &[(transaction::Transaction, transaction::Receipt)],
+    ) -> Vec<u8> {
+        let block = Block {
+            block_hash: Some(block.hash),
+            block_number: Some(block.number),
+            gas_price: Some(block.gas_price),
+            parent_block_hash: block.parent_hash,
+            sequencer_address: Some(block.sequencer_address),
+            state_root: Some(block.root),
+            status: Status::AcceptedOnL2,
+            timestamp: block.timestamp,
+            transactions: transaction_data.iter().map(|(tx, _)| tx.clone()).collect(),
+            transaction_receipts: transaction_data.iter().map(|(_, r)| r.clone()).collect(),
+        };
+        serde_json::to_vec(&block).unwrap()
+    }
+
+    fn stored_receipt(
+        transaction: &Transaction<'_>,
+        block: StarknetBlockNumber,
+    ) -> transaction::Receipt {
+        StarknetTransactionsTable::get_transaction_data_for_block(
+            transaction,
+            StarknetBlocksBlockId::Number(block),
+            &StorageLimits::default(),
+        )
+        .unwrap()
+        .pop()
+        .unwrap()
+        .1
+    }
+
+    #[test]
+    fn repairs_retained_blocks() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let blocks = test_utils::create_blocks::<3>();
+        let transaction_data = test_utils::create_transactions_and_receipts::<3>();
+
+        // The gateway sent a fee, which the code that synced the blocks dropped.
+        let fee = Some(Fee(H128::from_low_u64_be(0x5)));
+        for (i, (block, (tx, receipt))) in blocks.iter().zip(transaction_data).enumerate() {
+            StarknetBlocksTable::insert(&transaction, block).unwrap();
+            let parsed = transaction::Receipt {
+                actual_fee: None,
+                ..receipt.clone()
+            };
+            StarknetTransactionsTable::upsert(
+                &transaction,
+                block.hash,
+                block.number,
+                &[(tx.clone(), parsed)],
+                storage.limits(),
+            )
+            .unwrap();
+
+            // The first block has already left the retention window.
+            if i > 0 {
+                let sent = transaction::Receipt {
+                    actual_fee: fee,
+                    ..receipt
+                };
+                RawGatewayBlocksTable::insert(
+                    &transaction,
+                    block.number,
+                    block.hash,
+                    &raw_block(block, &[(tx, sent)]),
+                    storage.limits(),
+                )
+                .unwrap();
+            }
+        }
+
+        let summary = reprocess_blocks(
+            &transaction,
+            StarknetBlockNumber(0),
+            StarknetBlockNumber(2),
+            storage.limits(),
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            ReprocessSummary {
+                repaired: 2,
+                unchanged: 0,
+                missing: 1,
+            }
+        );
+
+        assert_eq!(
+            stored_receipt(&transaction, blocks[0].number).actual_fee,
+            None
+        );
+        assert_eq!(
+            stored_receipt(&transaction, blocks[1].number).actual_fee,
+            fee
+        );
+        assert_eq!(
+            stored_receipt(&transaction, blocks[2].number).actual_fee,
+            fee
+        );
+
+        // The repaired blocks now match their JSON.
+        let summary = reprocess_blocks(
+            &transaction,
+            StarknetBlockNumber(1),
+            StarknetBlockNumber(2),
+            storage.limits(),
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            ReprocessSummary {
+                repaired: 0,
+                unchanged: 2,
+                missing: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn replaces_events_of_the_block() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let block = test_utils::create_blocks::<1>()[0].clone();
+        let transaction_data = test_utils::create_transactions_and_receipts::<2>();
+        StarknetBlocksTable::insert(&transaction, &block).unwrap();
+
+        // The old code attributed a bogus second transaction to the block.
+        StarknetTransactionsTable::upsert(
+            &transaction,
+            block.hash,
+            block.number,
+            &transaction_data,
+            storage.limits(),
+        )
+        .unwrap();
+        RawGatewayBlocksTable::insert(
+            &transaction,
+            block.number,
+            block.hash,
+            &raw_block(&block, &transaction_data[..1]),
+            storage.limits(),
+        )
+        .unwrap();
+
+        let summary =
+            reprocess_blocks(&transaction, block.number, block.number, storage.limits()).unwrap();
+        assert_eq!(summary.repaired, 1);
+
+        let events = transaction
+            .query_row(
+                "SELECT count(1) FROM starknet_events WHERE block_number = ?",
+                [block.number.0],
+                |row| row.get::<_, u64>(0),
+            )
+            .unwrap();
+        assert_eq!(events, 1);
+        assert_eq!(
+            StarknetEventsTable::get_block_summary(&transaction, block.number)
+                .unwrap()
+                .event_count,
+            1
+        );
+        assert_eq!(
+            StarknetTransactionsTable::get_transaction_data_for_block(
+                &transaction,
+                StarknetBlocksBlockId::Number(block.number),
+                storage.limits(),
+            )
+            .unwrap(),
+            transaction_data[..1].to_vec()
+        );
+    }
+
+    #[test]
+    fn rejects_raw_block_of_another_block() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let blocks = test_utils::create_blocks::<2>();
+        for block in &blocks {
+            StarknetBlocksTable::insert(&transaction, block).unwrap();
+        }
+        RawGatewayBlocksTable::insert(
+            &transaction,
+            blocks[0].number,
+            blocks[0].hash,
+            &raw_block(&blocks[1], &[]),
+            storage.limits(),
+        )
+        .unwrap();
+
+        reprocess_blocks(
+            &transaction,
+            blocks[0].number,
+            blocks[0].number,
+            storage.limits(),
+        )
+        .unwrap_err();
+    }
+}
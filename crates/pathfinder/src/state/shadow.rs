@@ -0,0 +1,318 @@
+//! Syncs the chain a second time, from another gateway and into a separate
+//! [namespace](crate::storage::ChainNamespace), so that gateway inconsistencies show up as
+//! differences between the primary and the shadow chain.
+//!
+//! The shadow sync must never slow down the primary one. It runs on its own runtime with a
+//! single worker thread, updates contract storage trees one after the other and spaces out its
+//! gateway queries. It only follows L2, as the comparison is limited to block hashes and state roots.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use rusqlite::Connection;
+use tokio::sync::mpsc;
+
+use crate::{
+    core::{GlobalRoot, StarknetBlockHash, StarknetBlockNumber},
+    ethereum::{log::StateUpdateLog, transport::EthereumTransport, Chain},
+    sequencer::{self, Throttled},
+    state::{l1, l2, sync, SyncOptions, SyncState},
+    storage::{StarknetBlocksBlockId, StarknetBlocksTable, Storage},
+};
+
+/// Default time between the starts of two gateway queries of the shadow sync.
+pub const DEFAULT_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A block whose hash or state root differs between the primary and the shadow chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub block_number: StarknetBlockNumber,
+    pub primary_hash: StarknetBlockHash,
+    pub shadow_hash: StarknetBlockHash,
+    pub primary_root: GlobalRoot,
+    pub shadow_root: GlobalRoot,
+}
+
+/// The result of [compare].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Comparison {
+    /// The number of blocks which are stored in both chains.
+    pub compared: u64,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Starts syncing `storage` from `sequencer` on a dedicated thread, which starts at most one
+/// gateway query every `request_interval`.
+///
+/// `options` are those of the primary sync, the shadow sync only overrides those which would
+/// let it use more resources or halt it.
+pub fn spawn<Transport, SequencerClient>(
+    storage: Storage,
+    transport: Transport,
+    chain: Chain,
+    sequencer: SequencerClient,
+    options: SyncOptions,
+    request_interval: Duration,
+) -> anyhow::Result<std::thread::JoinHandle<()>>
+where
+    Transport: EthereumTransport + Clone + Send + Sync + 'static,
+    SequencerClient: sequencer::ClientApi + Send + Sync + 'static,
+{
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("shadow-sync")
+        .enable_all()
+        .build()
+        .context("Creating shadow sync runtime")?;
+
+    let options = SyncOptions {
+        max_reorg_depth: None,
+        stall_watchdog: None,
+        state_update_parallelism: 1,
+        pending_poll_interval: None,
+        ..options
+    };
+    let sequencer = Throttled::new(sequencer, request_interval);
+
+    std::thread::Builder::new()
+        .name("shadow-sync".to_owned())
+        .spawn(move || {
+            let result = runtime.block_on(async move {
+                // Spawned, so that the sync runs on the runtime's worker thread.
+                tokio::spawn(sync(
+                    storage,
+                    transport,
+                    chain,
+                    sequencer,
+                    Arc::new(SyncState::default()),
+                    options,
+                    l1_disabled,
+                    l2::sync,
+                ))
+                .await
+            });
+
+            match result {
+                Ok(Ok(())) => tracing::error!("Shadow sync ended unexpectedly"),
+                Ok(Err(e)) => tracing::error!(reason=?e, "Shadow sync failed"),
+                Err(e) => tracing::error!(reason=?e, "Shadow sync panicked"),
+            }
+        })
+        .context("Spawning shadow sync thread")
+}
+
+/// Stands in for the L1 sync process, which the shadow sync does not run.
+async fn l1_disabled<Transport>(
+    _tx_event: mpsc::Sender<l1::Event>,
+    _transport: Transport,
+    _chain: Chain,
+    _head: Option<StateUpdateLog>,
+    _confirmation_depth: u64,
+) -> anyhow::Result<()> {
+    // Returning would get this process restarted.
+    std::future::pending().await
+}
+
+/// Compares the hashes and state roots of the blocks `from..=to` which are stored in both the
+/// `primary` and the `shadow` database. Blocks missing from either are skipped, e.g. as the
+/// shadow chain is usually behind.
+pub fn compare(
+    primary: &Connection,
+    shadow: &Connection,
+    from: StarknetBlockNumber,
+    to: StarknetBlockNumber,
+) -> anyhow::Result<Comparison> {
+    let mut comparison = Comparison::default();
+
+    for number in from.0..=to.0 {
+        let id = StarknetBlocksBlockId::Number(StarknetBlockNumber(number));
+        let primary_block = StarknetBlocksTable::get(primary, id)
+            .with_context(|| format!("Read primary block {}", number))?;
+        let shadow_block = StarknetBlocksTable::get(shadow, id)
+            .with_context(|| format!("Read shadow block {}", number))?;
+
+        let (primary_block, shadow_block) = match (primary_block, shadow_block) {
+            (Some(primary_block), Some(shadow_block)) => (primary_block, shadow_block),
+            _ => continue,
+        };
+
+        comparison.compared += 1;
+        if primary_block.hash != shadow_block.hash || primary_block.root != shadow_block.root {
+            comparison.mismatches.push(Mismatch {
+                block_number: primary_block.number,
+                primary_hash: primary_block.hash,
+                shadow_hash: shadow_block.hash,
+                primary_root: primary_block.root,
+                shadow_root: shadow_block.root,
+            });
+        }
+    }
+
+    Ok(comparison)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::StarknetBlockTimestamp;
+    use crate::ethereum::transport::HttpTransport;
+    use crate::rpc::types::{BlockNumberOrTag, Tag};
+    use crate::sequencer::error::{SequencerError, StarknetError, StarknetErrorCode};
+    use crate::sequencer::{reply, MockClientApi};
+    use stark_hash::StarkHash;
+
+    /// The last block served by the test gateways.
+    const HEAD: u64 = 5;
+
+    /// The block at which the shadow gateway disagrees with the primary one.
+    const DISAGREEMENT: u64 = 3;
+
+    fn hash(shadow: bool, number: u64) -> StarknetBlockHash {
+        let gateway = match shadow && number == DISAGREEMENT {
+            true => 0xB,
+            false => 0xA,
+        };
+        let mut bytes = [gateway; 9];
+        bytes[1..].copy_from_slice(&number.to_be_bytes());
+        StarknetBlockHash(StarkHash::from_be_slice(&bytes).unwrap())
+    }
+
+    fn block(shadow: bool, number: u64) -> reply::Block {
+        let parent_block_hash = match number {
+            0 => StarknetBlockHash(StarkHash::ZERO),
+            n => hash(shadow, n - 1),
+        };
+        reply::Block {
+            block_hash: Some(hash(shadow, number)),
+            block_number: Some(StarknetBlockNumber(number)),
+            gas_price: None,
+            parent_block_hash,
+            sequencer_address: None,
+            state_root: Some(GlobalRoot(StarkHash::ZERO)),
+            status: reply::Status::AcceptedOnL2,
+            timestamp: StarknetBlockTimestamp(number),
+            transaction_receipts: vec![],
+            transactions: vec![],
+        }
+    }
+
+    /// A gateway serving the empty blocks up to [HEAD]. The shadow gateway's block
+    /// [DISAGREEMENT] has a different hash.
+    fn gateway(shadow: bool) -> MockClientApi {
+        let mut gateway = MockClientApi::new();
+        gateway
+            .expect_raw_block_by_number()
+            .returning(move |requested| match requested {
+                BlockNumberOrTag::Number(number) if number.0 <= HEAD => {
+                    Ok(serde_json::to_vec(&block(shadow, number.0)).unwrap().into())
+                }
+                _ => Err(SequencerError::StarknetError(StarknetError {
+                    code: StarknetErrorCode::BlockNotFound,
+                    message: String::new(),
+                })),
+            });
+        gateway
+            .expect_block_by_number()
+            .withf(|requested| *requested == BlockNumberOrTag::Tag(Tag::Latest))
+            .returning(move |_| Ok(block(shadow, HEAD)));
+        gateway.expect_state_update_by_hash().returning(|_| {
+            Ok(reply::StateUpdate {
+                new_root: GlobalRoot(StarkHash::ZERO),
+                old_root: GlobalRoot(StarkHash::ZERO),
+                state_diff: reply::state_update::StateDiff {
+                    storage_diffs: Default::default(),
+                    deployed_contracts: vec![],
+                },
+            })
+        });
+        gateway
+    }
+
+    fn transport() -> HttpTransport {
+        // Never queried, as neither sync follows L1.
+        let http = web3::transports::Http::new("http://127.0.0.1:1").unwrap();
+        HttpTransport::new(web3::Web3::new(http))
+    }
+
+    /// Waits until `storage` holds the block [HEAD].
+    async fn synced(storage: &Storage) {
+        let poll = async {
+            loop {
+                let connection = storage.connection().unwrap();
+                let head = StarknetBlocksTable::get(&connection, StarknetBlocksBlockId::Latest)
+                    .unwrap()
+                    .map(|block| block.number);
+                if head == Some(StarknetBlockNumber(HEAD)) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), poll)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn comparison_pinpoints_disagreement() {
+        let primary = Storage::in_memory().unwrap();
+        let shadow = Storage::in_memory().unwrap();
+
+        let _primary_sync = tokio::spawn(sync(
+            primary.clone(),
+            transport(),
+            Chain::Goerli,
+            Throttled::new(gateway(false), Duration::ZERO),
+            Arc::new(SyncState::default()),
+            SyncOptions::default(),
+            l1_disabled,
+            l2::sync,
+        ));
+        spawn(
+            shadow.clone(),
+            transport(),
+            Chain::Goerli,
+            gateway(true),
+            SyncOptions::default(),
+            Duration::from_millis(1),
+        )
+        .unwrap();
+
+        synced(&primary).await;
+        synced(&shadow).await;
+
+        let primary = primary.connection().unwrap();
+        let shadow = shadow.connection().unwrap();
+        let comparison = compare(
+            &primary,
+            &shadow,
+            StarknetBlockNumber::GENESIS,
+            StarknetBlockNumber(HEAD + 10),
+        )
+        .unwrap();
+
+        assert_eq!(comparison.compared, HEAD + 1);
+        assert_eq!(
+            comparison.mismatches,
+            vec![Mismatch {
+                block_number: StarknetBlockNumber(DISAGREEMENT),
+                primary_hash: hash(false, DISAGREEMENT),
+                shadow_hash: hash(true, DISAGREEMENT),
+                primary_root: GlobalRoot(StarkHash::ZERO),
+                shadow_root: GlobalRoot(StarkHash::ZERO),
+            }]
+        );
+
+        // The primary chain is exactly the primary gateway's.
+        for number in 0..=HEAD {
+            let stored = StarknetBlocksTable::get(
+                &primary,
+                StarknetBlocksBlockId::Number(StarknetBlockNumber(number)),
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(stored.hash, hash(false, number));
+        }
+    }
+}
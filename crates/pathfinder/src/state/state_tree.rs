@@ -1,15 +1,18 @@
 //! Contains the [GlobalStateTree] and [ContractsStateTree] trees, which combined
 //! store the total StarkNet state.
 //!
-//! These are abstractions built-on the [Binary Merkle-Patricia Tree](MerkleTree).
+//! These are abstractions built-on the [Binary Merkle-Patricia Tree](MerkleTree), and return
+//! its [MerkleTreeError] so that callers can tell, for example, an invalid key from a storage
+//! failure.
 
 use rusqlite::Transaction;
+use std::ops::ControlFlow;
 
 use crate::{
     core::{
         ContractAddress, ContractRoot, ContractStateHash, GlobalRoot, StorageAddress, StorageValue,
     },
-    state::merkle_tree::MerkleTree,
+    state::merkle_tree::{MerkleTree, MerkleTreeError},
     storage::merkle_tree::RcNodeStorage,
 };
 
@@ -20,25 +23,79 @@ pub struct ContractsStateTree<'a> {
 }
 
 impl<'a> ContractsStateTree<'a> {
-    pub fn load(transaction: &'a Transaction, root: ContractRoot) -> anyhow::Result<Self> {
+    pub fn load(transaction: &'a Transaction, root: ContractRoot) -> Result<Self, MerkleTreeError> {
         // TODO: move the string into storage.
         let tree = MerkleTree::load("tree_contracts".to_string(), transaction, root.0)?;
 
         Ok(Self { tree })
     }
 
-    #[allow(dead_code)]
-    pub fn get(&self, address: StorageAddress) -> anyhow::Result<StorageValue> {
+    pub fn get(&self, address: StorageAddress) -> Result<StorageValue, MerkleTreeError> {
         let value = self.tree.get(address.0)?;
         Ok(StorageValue(value))
     }
 
-    pub fn set(&mut self, address: StorageAddress, value: StorageValue) -> anyhow::Result<()> {
-        self.tree.set(address.0, value.0)
+    /// Returns the values at `addresses`, in the same order.
+    ///
+    /// See [MerkleTree::get_many] for more information.
+    pub fn get_many(
+        &self,
+        addresses: &[StorageAddress],
+    ) -> Result<Vec<StorageValue>, MerkleTreeError> {
+        let keys = addresses.iter().map(|a| a.0).collect::<Vec<_>>();
+        let values = self.tree.get_many(&keys)?;
+        Ok(values.into_iter().map(StorageValue).collect())
+    }
+
+    pub fn set(
+        &mut self,
+        address: StorageAddress,
+        value: StorageValue,
+    ) -> Result<(), MerkleTreeError> {
+        self.tree.set(address.0, value.0)?;
+        Ok(())
+    }
+
+    /// Visits the storage entries with addresses in the inclusive range `start..=end`,
+    /// in ascending address order.
+    ///
+    /// See [MerkleTree::visit_leaves_in_range] for more information.
+    pub fn visit_storage_in_range<F>(
+        &self,
+        start: StorageAddress,
+        end: StorageAddress,
+        mut visitor: F,
+    ) -> Result<(), MerkleTreeError>
+    where
+        F: FnMut(StorageAddress, StorageValue) -> ControlFlow<()>,
+    {
+        self.tree
+            .visit_leaves_in_range(start.0, end.0, |address, value| {
+                visitor(StorageAddress(address), StorageValue(value))
+            })?;
+        Ok(())
+    }
+
+    /// Visits the storage entries which differ between this tree and `newer`, in ascending
+    /// address order.
+    ///
+    /// See [MerkleTree::diff] for more information.
+    pub fn diff<F>(&self, newer: &Self, mut visitor: F) -> Result<(), MerkleTreeError>
+    where
+        F: FnMut(StorageAddress, StorageValue, StorageValue) -> ControlFlow<()>,
+    {
+        self.tree.diff(&newer.tree, |address, old, new| {
+            visitor(
+                StorageAddress(address),
+                StorageValue(old),
+                StorageValue(new),
+            )
+        })?;
+        Ok(())
     }
 
     /// Applies and persists any changes. Returns the new tree root.
-    pub fn apply(self) -> anyhow::Result<ContractRoot> {
+    pub fn apply(self) -> Result<ContractRoot, MerkleTreeError> {
         let root = self.tree.commit()?;
         Ok(ContractRoot(root))
     }
@@ -51,14 +108,14 @@ pub struct GlobalStateTree<'a> {
 }
 
 impl<'a> GlobalStateTree<'a> {
-    pub fn load(transaction: &'a Transaction, root: GlobalRoot) -> anyhow::Result<Self> {
+    pub fn load(transaction: &'a Transaction, root: GlobalRoot) -> Result<Self, MerkleTreeError> {
         // TODO: move the string into storage.
         let tree = MerkleTree::load("tree_global".to_string(), transaction, root.0)?;
 
         Ok(Self { tree })
     }
 
-    pub fn get(&self, address: ContractAddress) -> anyhow::Result<ContractStateHash> {
+    pub fn get(&self, address: ContractAddress) -> Result<ContractStateHash, MerkleTreeError> {
         let value = self.tree.get(address.0)?;
         Ok(ContractStateHash(value))
     }
@@ -67,12 +124,31 @@ impl<'a> GlobalStateTree<'a> {
         &mut self,
         address: ContractAddress,
         value: ContractStateHash,
-    ) -> anyhow::Result<()> {
-        self.tree.set(address.0, value.0)
+    ) -> Result<(), MerkleTreeError> {
+        self.tree.set(address.0, value.0)?;
+        Ok(())
+    }
+
+    /// Visits the contracts whose state differs between this tree and `newer`, in ascending
+    /// address order.
+    ///
+    /// See [MerkleTree::diff] for more information.
+    pub fn diff<F>(&self, newer: &Self, mut visitor: F) -> Result<(), MerkleTreeError>
+    where
+        F: FnMut(ContractAddress, ContractStateHash, ContractStateHash) -> ControlFlow<()>,
+    {
+        self.tree.diff(&newer.tree, |address, old, new| {
+            visitor(
+                ContractAddress(address),
+                ContractStateHash(old),
+                ContractStateHash(new),
+            )
+        })?;
+        Ok(())
     }
 
     /// Applies and persists any changes. Returns the new global root.
-    pub fn apply(self) -> anyhow::Result<GlobalRoot> {
+    pub fn apply(self) -> Result<GlobalRoot, MerkleTreeError> {
         let root = self.tree.commit()?;
         Ok(GlobalRoot(root))
     }
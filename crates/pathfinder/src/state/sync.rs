@@ -1,44 +1,373 @@
 pub mod l1;
 pub mod l2;
 
+use std::collections::VecDeque;
 use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::{
     core::{
         ContractRoot, GasPrice, GlobalRoot, SequencerAddress, StarknetBlockHash,
-        StarknetBlockNumber,
+        StarknetBlockNumber, StarknetBlockTimestamp,
     },
     ethereum::{
         log::StateUpdateLog,
-        state_update::{DeployedContract, StateUpdate},
+        state_update::{ContractUpdate, DeployedContract, StateUpdate},
         transport::EthereumTransport,
         Chain,
     },
     rpc::types::reply::{syncing, syncing::NumberedBlock, Syncing as SyncStatus},
     sequencer::{self, reply::Block},
-    state::{calculate_contract_state_hash, state_tree::GlobalStateTree, update_contract_state},
+    state::{
+        calculate_contract_state_hash, parallel_update, state_tree::GlobalStateTree, sync_retry,
+        update_contract_state, PendingBlock,
+    },
     storage::{
-        ContractCodeTable, ContractsStateTable, ContractsTable, L1StateTable, L1TableBlockId,
-        RefsTable, StarknetBlock, StarknetBlocksBlockId, StarknetBlocksTable,
-        StarknetTransactionsTable, Storage,
+        ChainStatsTable, ContractCodeTable, ContractDivergence, ContractsStateTable,
+        ContractsTable, L1StateTable, L1TableBlockId, RawGatewayBlocksTable, RefsTable, ReorgLimit,
+        RootDivergence, RootDivergenceTable, StarknetBlock, StarknetBlocksBlockId,
+        StarknetBlocksTable, StarknetStateUpdatesTable, StarknetTransactionsTable, Storage,
+        StorageLimits,
     },
 };
 
 use anyhow::Context;
-use rusqlite::{Connection, Transaction};
+use rusqlite::{Connection, Transaction, TransactionBehavior};
 use stark_hash::StarkHash;
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
 
 pub struct State {
     pub status: RwLock<SyncStatus>,
+    pub throughput: RwLock<Throughput>,
+    /// The pending block, kept up to date by the sync process and read by the RPC API.
+    pub pending: super::PendingData,
+    events: broadcast::Sender<SyncEvent>,
+    /// The L2 reorg which sync is halted on, and the means to let it proceed.
+    halted_reorg: std::sync::Mutex<Option<(DeepReorg, oneshot::Sender<()>)>>,
+    /// Cleared while the [StallWatchdog] has given up on a stalled sync process.
+    healthy: AtomicBool,
+    /// The global root divergence which sync is halted on.
+    root_divergence: std::sync::Mutex<Option<RootDivergence>>,
+    /// The number of L1 update logs awaiting confirmation, see
+    /// [SyncOptions::l1_confirmation_depth].
+    l1_pending_confirmations: AtomicU64,
 }
 
 impl Default for State {
     fn default() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
         Self {
             status: RwLock::new(SyncStatus::False(false)),
+            throughput: RwLock::new(Throughput::default()),
+            pending: Default::default(),
+            events,
+            halted_reorg: Default::default(),
+            healthy: AtomicBool::new(true),
+            root_divergence: Default::default(),
+            l1_pending_confirmations: AtomicU64::new(0),
+        }
+    }
+}
+
+impl State {
+    /// Registers a new consumer of [SyncEvent]s.
+    ///
+    /// The receiver only observes events published after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<SyncEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publishes the event to all current subscribers.
+    fn publish(&self, event: SyncEvent) {
+        // An error only means that there are currently no subscribers.
+        let _ = self.events.send(event);
+    }
+
+    /// Returns the L2 reorg which sync is currently halted on, if any.
+    pub fn halted_reorg(&self) -> Option<DeepReorg> {
+        self.halted_reorg
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(reorg, _)| *reorg)
+    }
+
+    /// Lets sync proceed with the L2 reorg it is halted on, returning that reorg.
+    ///
+    /// Returns `None` if sync is not halted.
+    pub fn approve_reorg(&self) -> Option<DeepReorg> {
+        let (reorg, approve) = self.halted_reorg.lock().unwrap().take()?;
+        // An error only means that sync has since been shut down.
+        let _ = approve.send(());
+        Some(reorg)
+    }
+
+    /// Returns false while a sync process is stalled and the [StallWatchdog] has given up
+    /// on restarting it, or if sync halted on a [RootDivergence].
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed) && self.root_divergence.lock().unwrap().is_none()
+    }
+
+    /// Returns the global root divergence which sync is halted on, if any.
+    ///
+    /// Sync does not resume from a divergence, it requires operator intervention.
+    pub fn root_divergence(&self) -> Option<RootDivergence> {
+        self.root_divergence.lock().unwrap().clone()
+    }
+
+    pub(crate) fn halt_on_divergence(&self, divergence: RootDivergence) {
+        *self.root_divergence.lock().unwrap() = Some(divergence);
+    }
+
+    /// Records the number of L1 update logs awaiting confirmation, and updates the sync status.
+    async fn set_l1_pending_confirmations(&self, count: u64) {
+        self.l1_pending_confirmations
+            .store(count, Ordering::Relaxed);
+        if let SyncStatus::Status(status) = &mut *self.status.write().await {
+            status.l1_pending_confirmations = count;
+        }
+    }
+
+    /// Flags sync as halted on `reorg`, the returned receiver completes once it is approved.
+    pub(crate) fn halt_on_reorg(&self, reorg: DeepReorg) -> oneshot::Receiver<()> {
+        let (approve, approved) = oneshot::channel();
+        *self.halted_reorg.lock().unwrap() = Some((reorg, approve));
+        approved
+    }
+}
+
+/// An L2 reorg which is deeper than [SyncOptions::max_reorg_depth] allows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeepReorg {
+    /// The L2 head at the time the reorg was detected.
+    pub head: StarknetBlockNumber,
+    /// The oldest block which the reorg invalidates.
+    pub reorg_tail: StarknetBlockNumber,
+}
+
+impl DeepReorg {
+    /// The number of blocks which the reorg invalidates.
+    pub fn depth(&self) -> u64 {
+        self.head.0 - self.reorg_tail.0 + 1
+    }
+}
+
+/// Tracks the times at which recent L2 blocks were stored, in order to estimate the
+/// sync rate.
+#[derive(Debug, Default)]
+pub struct Throughput {
+    samples: VecDeque<(Instant, StarknetBlockNumber)>,
+}
+
+impl Throughput {
+    /// How long samples are retained for. This is also the largest window over which a
+    /// rate can be computed.
+    pub const WINDOW: Duration = Duration::from_secs(300);
+
+    /// Records that `block` was stored at `at`.
+    ///
+    /// A block which does not extend the series (i.e. a reorg) discards all previous
+    /// samples, as they no longer describe progress towards the new head.
+    pub fn record(&mut self, at: Instant, block: StarknetBlockNumber) {
+        if matches!(self.samples.back(), Some((_, last)) if *last >= block) {
+            self.samples.clear();
+        }
+        self.samples.push_back((at, block));
+
+        while let Some((oldest, _)) = self.samples.front() {
+            if at.saturating_duration_since(*oldest) <= Self::WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the number of blocks stored per second over the window `since` before `now`.
+    ///
+    /// The window is capped at [Throughput::WINDOW]. Returns `None` if fewer than two blocks
+    /// were stored within the window.
+    pub fn blocks_per_second(&self, now: Instant, since: Duration) -> Option<f64> {
+        let since = since.min(Self::WINDOW);
+        let mut window = self
+            .samples
+            .iter()
+            .filter(|(at, _)| now.saturating_duration_since(*at) <= since);
+
+        let (first_at, first) = window.next()?;
+        let (_, last) = window.last()?;
+
+        let elapsed = now.saturating_duration_since(*first_at).as_secs_f64();
+        if elapsed == 0.0 {
+            return None;
+        }
+
+        Some((last.0 - first.0) as f64 / elapsed)
+    }
+}
+
+/// Number of [SyncEvent]s buffered per subscriber before it starts lagging.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Events published by the [sync] task.
+///
+/// Events are only published once the corresponding database transaction has been
+/// committed, so consumers can rely on the data being visible in storage.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncEvent {
+    /// L1 state updates were committed.
+    L1Update(Vec<StateUpdateLog>),
+    /// L1 state was reverted, removing all blocks `>= reorg_tail`.
+    L1Reorg(StarknetBlockNumber),
+    /// A new L2 block was committed.
+    L2Update {
+        block: StarknetBlock,
+        transaction_count: usize,
+        deployed_contracts: usize,
+        storage_updates: usize,
+    },
+    /// L2 state was reverted, removing all blocks `>= reorg_tail`.
+    L2Reorg(StarknetBlockNumber),
+    /// The latest block for which L1 and L2 agree has changed.
+    L1L2HeadUpdated(Option<StarknetBlockNumber>),
+}
+
+/// Options which alter the behaviour of the [sync] task.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SyncOptions {
+    /// Checks the constructor calldata of deploy transactions against the constructor ABI
+    /// of the deployed class, and logs any discrepancies.
+    pub validate_constructor_calldata: bool,
+    /// L2 reorgs invalidating more blocks than this halt sync until they are approved via
+    /// [State::approve_reorg], while L1 reorgs reverting more state updates than this fail
+    /// sync with a [ReorgTooDeep](crate::storage::ReorgTooDeep) error. `None` disables the limit.
+    ///
+    /// Such deep reorgs almost certainly indicate a gateway bug or an attack.
+    pub max_reorg_depth: Option<u64>,
+    /// Restarts the L1 and L2 sync processes if they stall. `None` disables the watchdog.
+    pub stall_watchdog: Option<StallWatchdog>,
+    /// Halts sync on blocks whose timestamp precedes their parent's, instead of only
+    /// logging them.
+    pub reject_non_monotonic_timestamps: bool,
+    /// Blocks whose timestamp precedes their parent's by at most this much are accepted
+    /// without a warning.
+    pub timestamp_tolerance: Duration,
+    /// Number of threads which update the storage trees of a block's contracts concurrently.
+    /// `0` and `1` update them one after the other on the sync task's thread.
+    pub state_update_parallelism: usize,
+    /// Number of most recent blocks whose verbatim gateway JSON is kept, see
+    /// [reprocess_blocks](crate::state::reprocess::reprocess_blocks). `0` disables retention.
+    pub raw_block_window: u64,
+    /// Number of L1 blocks which must follow the L1 block of a state update log before it
+    /// is stored. L1 reorgs of more recent blocks are handled without touching storage.
+    pub l1_confirmation_depth: u64,
+    /// Time between polls of the gateway's pending block, which is kept in [State::pending]
+    /// while it builds on the latest stored block. `None` disables polling.
+    pub pending_poll_interval: Option<Duration>,
+}
+
+/// Restarts sync processes which have stopped making progress, for example because a request
+/// hangs on a connection which never errors.
+///
+/// The L1 process is stalled if it has sent no events for [timeout](StallWatchdog::timeout),
+/// it sends a heartbeat on every poll of the L1 head. The L2 process is stalled if it has sent
+/// no events for as long while the gateway reports blocks which have not been stored yet.
+///
+/// A stalled process is cancelled and respawned after a delay which doubles with every
+/// consecutive restart. Once [max_restarts](StallWatchdog::max_restarts) is exceeded the
+/// watchdog gives up on the process, and marks sync as unhealthy until it recovers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StallWatchdog {
+    /// How long a process may go without activity. This must exceed the head poll interval
+    /// of the chain.
+    pub timeout: Duration,
+    /// The number of consecutive restarts after which the watchdog gives up.
+    pub max_restarts: u32,
+    /// The delay before the first restart of a stalled process.
+    pub backoff: Duration,
+}
+
+impl StallWatchdog {
+    pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+    pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+    /// A watchdog with the given timeout, and the default restart limit and backoff.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            max_restarts: Self::DEFAULT_MAX_RESTARTS,
+            backoff: Self::DEFAULT_BACKOFF,
+        }
+    }
+
+    /// How often processes are checked for stalls.
+    fn check_interval(&self) -> Duration {
+        (self.timeout / 4).max(Duration::from_millis(1))
+    }
+}
+
+/// Tracks the activity of a sync process on behalf of the [StallWatchdog].
+struct Liveness {
+    /// When the process last sent an event, or when it (re)starts running if that is later.
+    last_activity: Instant,
+    /// The number of consecutive restarts by the watchdog.
+    restarts: u32,
+    /// Whether the watchdog has given up on restarting the process.
+    given_up: bool,
+}
+
+impl Liveness {
+    fn new() -> Self {
+        Self {
+            last_activity: Instant::now(),
+            restarts: 0,
+            given_up: false,
+        }
+    }
+
+    /// Passes on an event received from the process, recording it as activity.
+    fn observe<T>(&mut self, event: Option<T>) -> Option<T> {
+        if event.is_some() {
+            self.last_activity = Instant::now();
+            self.restarts = 0;
+            self.given_up = false;
+        }
+        event
+    }
+
+    /// Records that the process was (re)spawned, and starts running after `delay`.
+    fn started(&mut self, delay: Duration) {
+        self.last_activity = Instant::now() + delay;
+    }
+
+    /// Returns for how long the process has been stalled, if that exceeds the watchdog's
+    /// timeout. Processes the watchdog has given up on are not reported.
+    fn stalled(&self, watchdog: &StallWatchdog) -> Option<Duration> {
+        if self.given_up {
+            return None;
+        }
+        let stalled_for = Instant::now().saturating_duration_since(self.last_activity);
+        if stalled_for > watchdog.timeout {
+            Some(stalled_for)
+        } else {
+            None
+        }
+    }
+
+    /// Records a restart of the stalled process, and returns the delay before the restarted
+    /// process should run. Returns `None` if the watchdog gives up on the process instead.
+    fn restart(&mut self, watchdog: &StallWatchdog) -> Option<Duration> {
+        if self.restarts >= watchdog.max_restarts {
+            self.given_up = true;
+            return None;
         }
+        let delay = watchdog
+            .backoff
+            .saturating_mul(2u32.saturating_pow(self.restarts));
+        self.restarts += 1;
+        self.started(delay);
+        Some(delay)
     }
 }
 
@@ -49,6 +378,7 @@ pub async fn sync<Transport, SequencerClient, F1, F2, L1Sync, L2Sync>(
     chain: Chain,
     sequencer: SequencerClient,
     state: Arc<State>,
+    options: SyncOptions,
     l1_sync: L1Sync,
     l2_sync: L2Sync,
 ) -> anyhow::Result<()>
@@ -57,7 +387,8 @@ where
     SequencerClient: sequencer::ClientApi + Clone + Send + Sync + 'static,
     F1: Future<Output = anyhow::Result<()>> + Send + 'static,
     F2: Future<Output = anyhow::Result<()>> + Send + 'static,
-    L1Sync: FnOnce(mpsc::Sender<l1::Event>, Transport, Chain, Option<StateUpdateLog>) -> F1 + Copy,
+    L1Sync:
+        FnOnce(mpsc::Sender<l1::Event>, Transport, Chain, Option<StateUpdateLog>, u64) -> F1 + Copy,
     L2Sync: FnOnce(
             mpsc::Sender<l2::Event>,
             SequencerClient,
@@ -68,20 +399,43 @@ where
 {
     // TODO: should this be owning a Storage, or just take in a Connection?
     let mut db_conn = storage
-        .connection()
+        .long_lived_connection()
         .context("Creating database connection")?;
 
+    // Deeper L2 reorgs halt sync until they are approved, whereas deeper L1 reorgs are refused
+    // and fail sync.
+    let reorg_limit = match options.max_reorg_depth {
+        Some(max_depth) => ReorgLimit {
+            max_depth,
+            force: false,
+        },
+        None => ReorgLimit::force(),
+    };
+
     let (tx_l1, mut rx_l1) = mpsc::channel(1);
     let (tx_l2, mut rx_l2) = mpsc::channel(1);
 
-    let (l1_head, l2_head) = tokio::task::block_in_place(|| -> anyhow::Result<_> {
-        let l1_head = L1StateTable::get(&db_conn, L1TableBlockId::Latest)
-            .context("Query L1 head from database")?;
-        let l2_head = StarknetBlocksTable::get(&db_conn, StarknetBlocksBlockId::Latest)
-            .context("Query L2 head from database")?
-            .map(|block| (block.number, block.hash));
-        Ok((l1_head, l2_head))
-    })?;
+    let state_update_pool = match options.state_update_parallelism {
+        0 | 1 => None,
+        threads => Some(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|i| format!("state-update-{}", i))
+                .build()
+                .context("Creating state update thread pool")?,
+        ),
+    };
+
+    let (l1_head, l2_head, mut l1_l2_head) =
+        tokio::task::block_in_place(|| -> anyhow::Result<_> {
+            let l1_head = L1StateTable::get(&db_conn, L1TableBlockId::Latest)
+                .context("Query L1 head from database")?;
+            let l2_head = StarknetBlocksTable::get_state_head(&db_conn)
+                .context("Query L2 head from database")?
+                .map(|block| (block.number, block.hash));
+            let l1_l2_head = RefsTable::get_l1_l2_head(&db_conn).context("Query L1-L2 head")?;
+            Ok((l1_head, l2_head, l1_l2_head))
+        })?;
 
     // Start update sync-status process.
     let (starting_block_num, starting_block_hash) = l2_head.unwrap_or((
@@ -96,25 +450,53 @@ where
         starting_block_num,
         chain,
     ));
+    let _status_current = tokio::spawn(update_sync_status_current(
+        Arc::clone(&state),
+        state.subscribe(),
+    ));
+    // A pending block left over from a previous run of this loop may be outdated.
+    state.pending.set_head(l2_head.map(|(_, hash)| hash)).await;
+    let _pending = options.pending_poll_interval.map(|interval| {
+        AbortOnDrop(tokio::spawn(poll_pending(
+            Arc::clone(&state),
+            sequencer.clone(),
+            interval,
+        )))
+    });
 
     // Start L1 and L2 sync processes.
-    let mut l1_handle = tokio::spawn(l1_sync(tx_l1, transport.clone(), chain, l1_head));
+    let mut l1_handle = tokio::spawn(l1_sync(
+        tx_l1,
+        transport.clone(),
+        chain,
+        l1_head,
+        options.l1_confirmation_depth,
+    ));
     let mut l2_handle = tokio::spawn(l2_sync(tx_l2, sequencer.clone(), l2_head, chain));
 
     let mut existed = (0, 0);
 
+    let mut l1_liveness = Liveness::new();
+    let mut l2_liveness = Liveness::new();
+    let mut watchdog_interval = tokio::time::interval(
+        options
+            .stall_watchdog
+            .map(|watchdog| watchdog.check_interval())
+            .unwrap_or(Duration::from_secs(60)),
+    );
+
     let mut last_block_start = std::time::Instant::now();
     let mut block_time_avg = std::time::Duration::ZERO;
     const BLOCK_TIME_WEIGHT: f32 = 0.05;
 
     loop {
         tokio::select! {
-            l1_event = rx_l1.recv() => match l1_event {
+            l1_event = rx_l1.recv() => match l1_liveness.observe(l1_event) {
                 Some(l1::Event::Update(updates)) => {
                     let first = updates.first().map(|u| u.block_number.0);
                     let last = updates.last().map(|u| u.block_number.0);
 
-                    l1_update(&mut db_conn, &updates).await.with_context(|| {
+                    let new_l1_l2_head = l1_update(&mut db_conn, &updates).await.with_context(|| {
                         format!("Update L1 state with blocks {:?}-{:?}", first, last)
                     })?;
 
@@ -131,9 +513,15 @@ where
                         }
                         _ => {}
                     }
+
+                    state.publish(SyncEvent::L1Update(updates));
+                    if new_l1_l2_head != l1_l2_head {
+                        l1_l2_head = new_l1_l2_head;
+                        state.publish(SyncEvent::L1L2HeadUpdated(l1_l2_head));
+                    }
                 }
                 Some(l1::Event::Reorg(reorg_tail)) => {
-                    l1_reorg(&mut db_conn, reorg_tail)
+                    let new_l1_l2_head = l1_reorg(&mut db_conn, reorg_tail, reorg_limit)
                         .await
                         .with_context(|| format!("Reorg L1 state to block {}", reorg_tail.0))?;
 
@@ -148,6 +536,12 @@ where
                         }
                         None => tracing::info!("L1 reorg occurred, new L1 head is genesis"),
                     }
+
+                    state.publish(SyncEvent::L1Reorg(reorg_tail));
+                    if new_l1_l2_head != l1_l2_head {
+                        l1_l2_head = new_l1_l2_head;
+                        state.publish(SyncEvent::L1L2HeadUpdated(l1_l2_head));
+                    }
                 }
                 Some(l1::Event::QueryUpdate(block, tx)) => {
                     let update =
@@ -158,6 +552,12 @@ where
 
                     tracing::trace!("Query for L1 update for block {}", block.0);
                 }
+                Some(l1::Event::Heartbeat) => {}
+                Some(l1::Event::PendingConfirmations(count)) => {
+                    state.set_l1_pending_confirmations(count).await;
+
+                    tracing::trace!("{} L1 update logs are awaiting confirmation", count);
+                }
                 None => {
                     // L1 sync process failed; restart it.
                     match l1_handle.await.context("Join L1 sync process handle")? {
@@ -176,24 +576,64 @@ where
                     let (new_tx, new_rx) = mpsc::channel(1);
                     rx_l1 = new_rx;
 
-                    l1_handle = tokio::spawn(l1_sync(new_tx, transport.clone(), chain, l1_head));
+                    l1_handle = tokio::spawn(l1_sync(
+                        new_tx,
+                        transport.clone(),
+                        chain,
+                        l1_head,
+                        options.l1_confirmation_depth,
+                    ));
+                    l1_liveness.started(Duration::ZERO);
                     tracing::info!("L1 sync process restarted.")
                 },
             },
-            l2_event = rx_l2.recv() => match l2_event {
-                Some(l2::Event::Update(block, diff, timings)) => {
+            l2_event = rx_l2.recv() => match l2_liveness.observe(l2_event) {
+                Some(l2::Event::Update(block, diff, timings, raw_block)) => {
                     // unwrap is safe as only pending query blocks are None.
                     let block_num = block.block_number.unwrap().0;
-                    let block_hash = block.block_hash.unwrap();
                     let storage_updates: usize = diff
                         .contract_updates
                         .iter()
                         .map(|u| u.storage_updates.len())
                         .sum();
+                    let deployed_contracts = diff.deployed_contracts.len();
+                    let transaction_count = block.transactions.len();
                     let update_t = std::time::Instant::now();
-                    l2_update(&mut db_conn, *block, diff)
-                        .await
-                        .with_context(|| format!("Update L2 state to {}", block_num))?;
+                    let update = l2_update(
+                        &mut db_conn,
+                        *block,
+                        diff,
+                        raw_block,
+                        options,
+                        *storage.limits(),
+                        state_update_pool.as_ref(),
+                    )
+                    .await
+                    .with_context(|| format!("Update L2 state to {}", block_num))?;
+                    let (stored_block, new_l1_l2_head) = match update {
+                        Ok(stored) => stored,
+                        Err(divergence) => {
+                            let inconsistent = divergence
+                                .contracts
+                                .iter()
+                                .filter(|contract| contract.is_inconsistent())
+                                .count();
+                            tracing::error!(
+                                block=%divergence.block_number.0,
+                                computed_root=%divergence.computed_root.0,
+                                expected_root=%divergence.expected_root.0,
+                                contracts=%divergence.contracts.len(),
+                                %inconsistent,
+                                "Computed global root diverges from the block's state root, sync is halted"
+                            );
+                            state.halt_on_divergence(divergence);
+                            l1_handle.abort();
+                            l2_handle.abort();
+                            // Storing anything past the divergence would build on a wrong state,
+                            // while the data preceding it can still be served.
+                            return std::future::pending().await;
+                        }
+                    };
                     let block_time = last_block_start.elapsed();
                     let update_t = update_t.elapsed();
                     last_block_start = std::time::Instant::now();
@@ -201,16 +641,17 @@ where
                     block_time_avg = block_time_avg.mul_f32(1.0 - BLOCK_TIME_WEIGHT)
                         + block_time.mul_f32(BLOCK_TIME_WEIGHT);
 
-                    // Update sync status
-                    match &mut *state.status.write().await {
-                        SyncStatus::False(_) => {}
-                        SyncStatus::Status(status) => {
-                            status.current = NumberedBlock::from((block_hash, StarknetBlockNumber(block_num)));
-
-                            if status.highest.number.0 <= block_num {
-                                status.highest = status.current;
-                            }
-                        }
+                    // The pending block was built on the previous head.
+                    state.pending.set_head(Some(stored_block.hash)).await;
+                    state.publish(SyncEvent::L2Update {
+                        block: stored_block,
+                        transaction_count,
+                        deployed_contracts,
+                        storage_updates,
+                    });
+                    if new_l1_l2_head != l1_l2_head {
+                        l1_l2_head = new_l1_l2_head;
+                        state.publish(SyncEvent::L1L2HeadUpdated(l1_l2_head));
                     }
 
                     // Give a simple log under INFO level, and a more verbose log
@@ -240,7 +681,30 @@ where
                     }
                 }
                 Some(l2::Event::Reorg(reorg_tail)) => {
-                    l2_reorg(&mut db_conn, reorg_tail)
+                    let mut limit = reorg_limit;
+                    if let Some(max_depth) = options.max_reorg_depth {
+                        let head = tokio::task::block_in_place(|| {
+                            StarknetBlocksTable::get_latest_number(&db_conn)
+                        })
+                        .context("Query L2 head from database")?
+                        .filter(|head| *head >= reorg_tail);
+
+                        if let Some(head) = head {
+                            let reorg = DeepReorg { head, reorg_tail };
+                            if reorg.depth() > max_depth {
+                                tracing::error!(
+                                    head=%head.0, reorg_tail=%reorg_tail.0, depth=%reorg.depth(), %max_depth,
+                                    "L2 reorg exceeds the maximum reorg depth, sync is halted until it is approved"
+                                );
+                                // The sender is only dropped along with the state, which we hold.
+                                let _ = state.halt_on_reorg(reorg).await;
+                                tracing::warn!(depth=%reorg.depth(), "Deep L2 reorg approved, resuming sync");
+                                limit = ReorgLimit::force();
+                            }
+                        }
+                    }
+
+                    let new_l1_l2_head = l2_reorg(&mut db_conn, reorg_tail, limit)
                         .await
                         .with_context(|| format!("Reorg L2 state to {:?}", reorg_tail))?;
 
@@ -254,6 +718,18 @@ where
                         }
                         None => tracing::info!("L2 reorg occurred, new L2 head is genesis"),
                     }
+
+                    let head = tokio::task::block_in_place(|| {
+                        StarknetBlocksTable::get_state_head(&db_conn)
+                    })
+                    .context("Query L2 head from database")?
+                    .map(|block| block.hash);
+                    state.pending.set_head(head).await;
+                    state.publish(SyncEvent::L2Reorg(reorg_tail));
+                    if new_l1_l2_head != l1_l2_head {
+                        l1_l2_head = new_l1_l2_head;
+                        state.publish(SyncEvent::L1L2HeadUpdated(l1_l2_head));
+                    }
                 }
                 Some(l2::Event::NewContract(contract)) => {
                     tokio::task::block_in_place(|| {
@@ -265,6 +741,14 @@ where
 
                     tracing::trace!("Inserted new contract {}", contract.hash.0.to_hex_str());
                 }
+                Some(l2::Event::DeferredContract(hash, error)) => {
+                    let entry = tokio::task::block_in_place(|| {
+                        sync_retry::defer_class(&mut db_conn, hash, &error, sync_retry::unix_now())
+                    })
+                    .with_context(|| format!("Defer contract definition with hash: {:?}", hash))?;
+
+                    tracing::debug!(class_hash=%hash.0, attempts=%entry.attempts, "Deferred contract definition");
+                }
                 Some(l2::Event::QueryHash(block, tx)) => {
                     let hash = tokio::task::block_in_place(|| {
                         StarknetBlocksTable::get(&db_conn, block.into())
@@ -301,7 +785,7 @@ where
                     }
 
                     let l2_head = tokio::task::block_in_place(|| {
-                        StarknetBlocksTable::get(&db_conn, StarknetBlocksBlockId::Latest)
+                        StarknetBlocksTable::get_state_head(&db_conn)
                     })
                     .context("Query L2 head from database")?
                     .map(|block| (block.number, block.hash));
@@ -310,13 +794,152 @@ where
                     rx_l2 = new_rx;
 
                     l2_handle = tokio::spawn(l2_sync(new_tx, sequencer.clone(), l2_head, chain));
+                    l2_liveness.started(Duration::ZERO);
                     tracing::info!("L2 sync process restarted.");
                 }
+            },
+            _ = watchdog_interval.tick(), if options.stall_watchdog.is_some() => {
+                // Unwrap is safe as this branch is disabled otherwise.
+                let watchdog = options.stall_watchdog.unwrap();
+
+                // The sync processes leave all writes to this loop, which makes it safe to
+                // cancel them at any await point.
+                if let Some(stalled_for) = l1_liveness.stalled(&watchdog) {
+                    match l1_liveness.restart(&watchdog) {
+                        Some(delay) => {
+                            l1_handle.abort();
+                            tracing::warn!(
+                                stalled_for=?stalled_for, attempt=%l1_liveness.restarts, delay=?delay,
+                                "L1 sync process stalled, restarting"
+                            );
+                            let l1_head = tokio::task::block_in_place(|| {
+                                L1StateTable::get(&db_conn, L1TableBlockId::Latest)
+                            })
+                            .context("Query L1 head from database")?;
+
+                            let (new_tx, new_rx) = mpsc::channel(1);
+                            rx_l1 = new_rx;
+
+                            let process = l1_sync(
+                                new_tx,
+                                transport.clone(),
+                                chain,
+                                l1_head,
+                                options.l1_confirmation_depth,
+                            );
+                            l1_handle = tokio::spawn(async move {
+                                tokio::time::sleep(delay).await;
+                                process.await
+                            });
+                        }
+                        None => tracing::error!(
+                            stalled_for=?stalled_for, restarts=%watchdog.max_restarts,
+                            "L1 sync process is still stalled, giving up on restarting it"
+                        ),
+                    }
+                }
+
+                let l2_stalled_for = match l2_liveness.stalled(&watchdog) {
+                    Some(stalled_for) => {
+                        let highest = match &*state.status.read().await {
+                            SyncStatus::Status(status) => Some(status.highest.number),
+                            SyncStatus::False(_) => None,
+                        };
+                        let head = tokio::task::block_in_place(|| {
+                            StarknetBlocksTable::get_state_head(&db_conn)
+                        })
+                        .context("Query L2 head from database")?
+                        .map(|block| block.number);
+
+                        // Being idle is only a stall if there are blocks left to download.
+                        match highest {
+                            Some(highest) if head < Some(highest) => Some(stalled_for),
+                            _ => None,
+                        }
+                    }
+                    None => None,
+                };
+                if let Some(stalled_for) = l2_stalled_for {
+                    match l2_liveness.restart(&watchdog) {
+                        Some(delay) => {
+                            l2_handle.abort();
+                            tracing::warn!(
+                                stalled_for=?stalled_for, attempt=%l2_liveness.restarts, delay=?delay,
+                                "L2 sync process stalled, restarting"
+                            );
+                            let l2_head = tokio::task::block_in_place(|| {
+                                StarknetBlocksTable::get_state_head(&db_conn)
+                            })
+                            .context("Query L2 head from database")?
+                            .map(|block| (block.number, block.hash));
+
+                            let (new_tx, new_rx) = mpsc::channel(1);
+                            rx_l2 = new_rx;
+
+                            let process = l2_sync(new_tx, sequencer.clone(), l2_head, chain);
+                            l2_handle = tokio::spawn(async move {
+                                tokio::time::sleep(delay).await;
+                                process.await
+                            });
+                        }
+                        None => tracing::error!(
+                            stalled_for=?stalled_for, restarts=%watchdog.max_restarts,
+                            "L2 sync process is still stalled, giving up on restarting it"
+                        ),
+                    }
+                }
+
+                state.healthy.store(
+                    !l1_liveness.given_up && !l2_liveness.given_up,
+                    Ordering::Relaxed,
+                );
             }
         }
     }
 }
 
+/// Keeps [State::pending] up to date with the gateway's pending block, polling it every
+/// `interval`.
+///
+/// The pending block is only kept while it builds on the head which the main sync loop
+/// [records](super::PendingData::set_head), so it is dropped while sync is catching up. The main
+/// sync loop clears it whenever the head changes, and it is set again on the next poll.
+async fn poll_pending(state: Arc<State>, sequencer: impl sequencer::ClientApi, interval: Duration) {
+    use crate::rpc::types::{BlockNumberOrTag, Tag};
+
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let pending = match sequencer
+            .block_by_number(BlockNumberOrTag::Tag(Tag::Pending))
+            .await
+        {
+            Ok(pending) => pending,
+            Err(e) => {
+                tracing::debug!(error=%e, "Failed to fetch pending block");
+                continue;
+            }
+        };
+
+        let parent = pending.parent_block_hash;
+        if !state.pending.set(PendingBlock::new(pending)).await {
+            tracing::trace!(parent=%parent.0, "Pending block does not build on the head, dropped");
+        }
+    }
+}
+
+/// Aborts the spawned task once dropped, which ties it to the sync loop that spawned it.
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 /// Periodically updates sync state with the latest block height.
 async fn update_sync_status_latest(
     state: Arc<State>,
@@ -348,6 +971,9 @@ async fn update_sync_status_latest(
                             starting,
                             current: starting,
                             highest: latest,
+                            l1_pending_confirmations: state
+                                .l1_pending_confirmations
+                                .load(Ordering::Relaxed),
                         });
 
                         tracing::debug!(
@@ -376,7 +1002,46 @@ async fn update_sync_status_latest(
     }
 }
 
-async fn l1_update(connection: &mut Connection, updates: &[StateUpdateLog]) -> anyhow::Result<()> {
+/// Keeps the current block of the sync status up-to-date by consuming [SyncEvent]s.
+async fn update_sync_status_current(
+    state: Arc<State>,
+    mut events: broadcast::Receiver<SyncEvent>,
+) -> anyhow::Result<()> {
+    loop {
+        let block = match events.recv().await {
+            Ok(SyncEvent::L2Update { block, .. }) => block,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(%skipped, "Sync status tracking lagged behind sync events");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+
+        state
+            .throughput
+            .write()
+            .await
+            .record(Instant::now(), block.number);
+
+        match &mut *state.status.write().await {
+            SyncStatus::False(_) => {}
+            SyncStatus::Status(status) => {
+                status.current = NumberedBlock::from((block.hash, block.number));
+
+                if status.highest.number <= block.number {
+                    status.highest = status.current;
+                }
+            }
+        }
+    }
+}
+
+/// Inserts the L1 state updates, and returns the resulting L1-L2 head.
+async fn l1_update(
+    connection: &mut Connection,
+    updates: &[StateUpdateLog],
+) -> anyhow::Result<Option<StarknetBlockNumber>> {
     tokio::task::block_in_place(move || {
         let transaction = connection
             .transaction()
@@ -387,7 +1052,7 @@ async fn l1_update(connection: &mut Connection, updates: &[StateUpdateLog]) -> a
         }
 
         // Track combined L1 and L2 state.
-        let l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
+        let mut l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
         let expected_next = l1_l2_head
             .map(|head| head + 1)
             .unwrap_or(StarknetBlockNumber::GENESIS);
@@ -412,58 +1077,92 @@ async fn l1_update(connection: &mut Connection, updates: &[StateUpdateLog]) -> a
                 if let Some(next_head) = next_head {
                     RefsTable::set_l1_l2_head(&transaction, Some(next_head))
                         .context("Update L1-L2 head")?;
+                    l1_l2_head = Some(next_head);
                 }
             }
             _ => {}
         }
 
-        transaction.commit().context("Commit database transaction")
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
+
+        Ok(l1_l2_head)
     })
 }
 
+/// Reverts the L1 state down to `reorg_tail`, and returns the resulting L1-L2 head.
 async fn l1_reorg(
     connection: &mut Connection,
     reorg_tail: StarknetBlockNumber,
-) -> anyhow::Result<()> {
+    limit: ReorgLimit,
+) -> anyhow::Result<Option<StarknetBlockNumber>> {
     tokio::task::block_in_place(move || {
         let transaction = connection
             .transaction()
             .context("Create database transaction")?;
 
-        L1StateTable::reorg(&transaction, reorg_tail).context("Delete L1 state from database")?;
+        // Also tracks the combined L1 and L2 state.
+        let l1_l2_head = Storage::reorg_l1(&transaction, reorg_tail, limit)?;
 
-        // Track combined L1 and L2 state.
-        let l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
-        match l1_l2_head {
-            Some(head) if head >= reorg_tail => {
-                let new_head = match reorg_tail {
-                    StarknetBlockNumber::GENESIS => None,
-                    other => Some(other - 1),
-                };
-                RefsTable::set_l1_l2_head(&transaction, new_head).context("Update L1-L2 head")?;
-            }
-            _ => {}
-        }
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
 
-        transaction.commit().context("Commit database transaction")
+        Ok(l1_l2_head)
     })
 }
 
+/// Inserts the L2 block and applies its state diff. Returns the stored block
+/// and the resulting L1-L2 head.
+///
+/// If the computed global root does not match the block's `state_root`, nothing is stored
+/// and the recorded [RootDivergence] is returned instead.
 async fn l2_update(
     connection: &mut Connection,
     block: Block,
     state_diff: StateUpdate,
-) -> anyhow::Result<()> {
+    raw_block: Option<bytes::Bytes>,
+    options: SyncOptions,
+    limits: StorageLimits,
+    state_update_pool: Option<&rayon::ThreadPool>,
+) -> anyhow::Result<Result<(StarknetBlock, Option<StarknetBlockNumber>), RootDivergence>> {
     tokio::task::block_in_place(move || {
+        let transaction_data = sequencer::reply::transaction::pair_with_receipts(
+            block.transactions,
+            block.transaction_receipts,
+        )?;
+
+        // The block backfill writes to the same tables, so the write lock is taken right away.
         let transaction = connection
-            .transaction()
+            .transaction_with_behavior(TransactionBehavior::Immediate)
             .context("Create database transaction")?;
 
-        let new_root =
-            update_starknet_state(&transaction, state_diff).context("Updating Starknet state")?;
+        let new_root = update_starknet_state(&transaction, &state_diff, state_update_pool)
+            .context("Updating Starknet state")?;
+
+        // A root which only differs from L1's is left to the L1-L2 head tracking below, as
+        // the sequencer's and our state agree and it signals a reorg instead.
+        if new_root != block.state_root.unwrap() {
+            let divergence = RootDivergence {
+                block_number: block.block_number.unwrap(),
+                block_hash: block.block_hash.unwrap(),
+                computed_root: new_root,
+                expected_root: block.state_root.unwrap(),
+                l1_root: L1StateTable::get_root(&transaction, block.block_number.unwrap().into())
+                    .context("Query L1 root")?,
+                contracts: contract_divergences(&transaction, new_root, &state_diff)
+                    .context("Compare contract states")?,
+                recorded_at: sync_retry::unix_now(),
+            };
+
+            // Discard the diverging state before recording the divergence.
+            drop(transaction);
+            RootDivergenceTable::insert(connection, &divergence)
+                .context("Record root divergence")?;
 
-        // Ensure that roots match.. what should we do if it doesn't? For now the whole sync process ends..
-        anyhow::ensure!(new_root == block.state_root.unwrap(), "State root mismatch");
+            return Ok(Err(divergence));
+        }
 
         // Update L2 database. These types shouldn't be options at this level,
         // but for now the unwraps are "safe" in that these should only ever be
@@ -478,32 +1177,96 @@ async fn l2_update(
             sequencer_address: block
                 .sequencer_address
                 .unwrap_or(SequencerAddress(StarkHash::ZERO)),
+            parent_hash: block.parent_block_hash,
         };
-        StarknetBlocksTable::insert(&transaction, &starknet_block)
-            .context("Insert block into database")?;
+
+        if let Some(parent_timestamp) =
+            timestamp_regression(&transaction, &starknet_block, options.timestamp_tolerance)
+                .context("Validate block timestamp")?
+        {
+            tracing::warn!(
+                block=%starknet_block.number.0, timestamp=%starknet_block.timestamp.0, parent_timestamp=%parent_timestamp.0,
+                "Block timestamp precedes its parent's timestamp"
+            );
+            anyhow::ensure!(
+                !options.reject_non_monotonic_timestamps,
+                "Timestamp {} of block {} precedes its parent's timestamp {}",
+                starknet_block.timestamp.0,
+                starknet_block.number.0,
+                parent_timestamp.0
+            );
+        }
+
+        let backfilled = take_backfilled_block(&transaction, &starknet_block)
+            .context("Check for backfilled block")?;
+
+        if !backfilled {
+            StarknetBlocksTable::insert(&transaction, &starknet_block)
+                .context("Insert block into database")?;
+        }
+        StarknetStateUpdatesTable::insert(
+            &transaction,
+            starknet_block.number,
+            &state_diff,
+            &limits,
+        )
+        .context("Insert state update into database")?;
 
         // Insert the transactions.
-        anyhow::ensure!(
-            block.transactions.len() == block.transaction_receipts.len(),
-            "Transactions and receipts mismatch. There were {} transactions and {} receipts.",
-            block.transactions.len(),
-            block.transaction_receipts.len()
-        );
-        let transaction_data = block
-            .transactions
-            .into_iter()
-            .zip(block.transaction_receipts.into_iter())
+        if !backfilled {
+            StarknetTransactionsTable::upsert(
+                &transaction,
+                starknet_block.hash,
+                starknet_block.number,
+                &transaction_data,
+                &limits,
+            )
+            .context("Insert transaction data into database")?;
+        }
+
+        if let Some(raw_block) = raw_block.filter(|_| options.raw_block_window > 0) {
+            RawGatewayBlocksTable::insert(
+                &transaction,
+                starknet_block.number,
+                starknet_block.hash,
+                &raw_block,
+                &limits,
+            )
+            .context("Insert raw block into database")?;
+
+            let oldest_retained = starknet_block
+                .number
+                .0
+                .saturating_sub(options.raw_block_window - 1);
+            RawGatewayBlocksTable::prune_before(&transaction, StarknetBlockNumber(oldest_retained))
+                .context("Prune raw blocks")?;
+        }
+
+        let deployed_classes = state_diff
+            .deployed_contracts
+            .iter()
+            .map(|contract| contract.hash)
             .collect::<Vec<_>>();
-        StarknetTransactionsTable::upsert(
+        let events = transaction_data
+            .iter()
+            .map(|(_, receipt)| receipt.events.len())
+            .sum();
+        ChainStatsTable::insert(
             &transaction,
-            starknet_block.hash,
             starknet_block.number,
-            &transaction_data,
+            &deployed_classes,
+            transaction_data.len(),
+            events,
         )
-        .context("Insert transaction data into database")?;
+        .context("Update chain statistics")?;
+
+        if options.validate_constructor_calldata {
+            validate_constructor_calldata(&transaction, starknet_block.number, &transaction_data)
+                .context("Validate constructor calldata")?;
+        }
 
         // Track combined L1 and L2 state.
-        let l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
+        let mut l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
         let expected_next = l1_l2_head
             .map(|head| head + 1)
             .unwrap_or(StarknetBlockNumber::GENESIS);
@@ -514,17 +1277,179 @@ async fn l2_update(
             if l1_root == Some(starknet_block.root) {
                 RefsTable::set_l1_l2_head(&transaction, Some(starknet_block.number))
                     .context("Update L1-L2 head")?;
+                l1_l2_head = Some(starknet_block.number);
             }
         }
 
-        transaction.commit().context("Commit database transaction")
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
+
+        Ok(Ok((starknet_block, l1_l2_head)))
+    })
+}
+
+/// Returns true if `block` was already stored by the [block backfill](crate::state::block_backfill),
+/// in which case its state update is all that is left to apply. The block is removed from the
+/// backfill range.
+///
+/// Backfilled blocks which turn out to belong to another fork are deleted, along with all
+/// backfilled blocks above them.
+fn take_backfilled_block(transaction: &Transaction, block: &StarknetBlock) -> anyhow::Result<bool> {
+    let range = match RefsTable::get_backfill_range(transaction).context("Query backfill range")? {
+        Some(range) if range.contains(&block.number) => range,
+        _ => return Ok(false),
+    };
+
+    let stored = StarknetBlocksTable::get(transaction, block.number.into())
+        .context("Query backfilled block")?
+        .map(|stored| stored.hash);
+    if stored == Some(block.hash) {
+        let remaining = (block.number < *range.end()).then(|| block.number + 1..=*range.end());
+        RefsTable::set_backfill_range(transaction, remaining).context("Update backfill range")?;
+        return Ok(true);
+    }
+
+    tracing::warn!(
+        block=%block.number.0, expected=%block.hash.0, backfilled=?stored.map(|hash| hash.0),
+        "Backfilled blocks belong to another fork, discarding them"
+    );
+    StarknetBlocksTable::reorg(transaction, block.number)
+        .context("Delete backfilled blocks from database")?;
+    RefsTable::set_backfill_range(transaction, None).context("Clear backfill range")?;
+
+    Ok(false)
+}
+
+/// Returns the timestamp of the stored parent of `block` if it is later than the timestamp of
+/// `block` by more than `tolerance`.
+///
+/// Block timestamps are not strictly increasing, blocks may share their parent's timestamp.
+fn timestamp_regression(
+    transaction: &Transaction,
+    block: &StarknetBlock,
+    tolerance: Duration,
+) -> anyhow::Result<Option<StarknetBlockTimestamp>> {
+    if block.number == StarknetBlockNumber::GENESIS {
+        return Ok(None);
+    }
+
+    let parent =
+        StarknetBlocksTable::get(transaction, StarknetBlocksBlockId::Number(block.number - 1))
+            .context("Query parent block")?;
+
+    Ok(match parent {
+        Some(parent)
+            if parent.timestamp.0.saturating_sub(block.timestamp.0) > tolerance.as_secs() =>
+        {
+            Some(parent.timestamp)
+        }
+        _ => None,
     })
 }
 
+/// Logs deploy transactions whose constructor calldata length does not match the constructor
+/// ABI of the deployed class.
+///
+/// Such discrepancies have historically indicated gateway bugs, they are therefore only logged.
+fn validate_constructor_calldata(
+    transaction: &Transaction,
+    block: StarknetBlockNumber,
+    transaction_data: &[(
+        sequencer::reply::transaction::Transaction,
+        sequencer::reply::transaction::Receipt,
+    )],
+) -> anyhow::Result<()> {
+    use sequencer::reply::transaction::Type;
+
+    for (tx, _) in transaction_data
+        .iter()
+        .filter(|(tx, _)| tx.r#type == Type::Deploy)
+    {
+        let class_hash = match tx.class_hash {
+            Some(class_hash) => Some(class_hash),
+            // Older deploy transactions do not contain the class hash.
+            None => ContractsTable::get_hash(transaction, tx.contract_address)
+                .context("Query class hash")?,
+        };
+        let class_hash = match class_hash {
+            Some(class_hash) => class_hash,
+            None => continue,
+        };
+
+        let abi = match ContractCodeTable::get_abi(transaction, class_hash).context("Query ABI")? {
+            Some(abi) => abi,
+            None => continue,
+        };
+
+        let expected = match expected_constructor_calldata_len(&abi) {
+            Ok(Some(expected)) => expected,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!(class=%class_hash.0, reason=?e, "Failed to parse class ABI");
+                continue;
+            }
+        };
+        let actual = tx
+            .constructor_calldata
+            .as_ref()
+            .map(Vec::len)
+            .unwrap_or_default();
+
+        if expected != actual {
+            tracing::warn!(
+                block=%block.0, transaction=%tx.transaction_hash.0, class=%class_hash.0, %expected, %actual,
+                "Constructor calldata length does not match the class ABI"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the number of calldata elements the constructor in the given ABI expects,
+/// or [None] if this depends on the calldata itself (i.e. the constructor takes arrays or
+/// structs as inputs).
+///
+/// A class without a constructor expects no calldata.
+fn expected_constructor_calldata_len(abi: &[u8]) -> anyhow::Result<Option<usize>> {
+    #[derive(serde::Deserialize)]
+    struct Entry {
+        r#type: String,
+        #[serde(default)]
+        inputs: Vec<Input>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Input {
+        r#type: String,
+    }
+
+    let abi = serde_json::from_slice::<Vec<Entry>>(abi).context("Parse ABI")?;
+
+    let constructor = match abi.into_iter().find(|entry| entry.r#type == "constructor") {
+        Some(constructor) => constructor,
+        None => return Ok(Some(0)),
+    };
+
+    let expected = match constructor
+        .inputs
+        .iter()
+        .all(|input| input.r#type == "felt")
+    {
+        true => Some(constructor.inputs.len()),
+        false => None,
+    };
+
+    Ok(expected)
+}
+
+/// Reverts the L2 state down to `reorg_tail`, and returns the resulting L1-L2 head.
 async fn l2_reorg(
     connection: &mut Connection,
     reorg_tail: StarknetBlockNumber,
-) -> anyhow::Result<()> {
+    limit: ReorgLimit,
+) -> anyhow::Result<Option<StarknetBlockNumber>> {
     tokio::task::block_in_place(move || {
         let transaction = connection
             .transaction()
@@ -532,46 +1457,67 @@ async fn l2_reorg(
 
         // TODO: clean up state tree's as well...
 
-        StarknetBlocksTable::reorg(&transaction, reorg_tail)
-            .context("Delete L2 state from database")?;
+        // Also tracks the combined L1 and L2 state.
+        let l1_l2_head = Storage::reorg_l2(&transaction, reorg_tail, limit)?;
 
-        // Track combined L1 and L2 state.
-        let l1_l2_head = RefsTable::get_l1_l2_head(&transaction).context("Query L1-L2 head")?;
-        match l1_l2_head {
-            Some(head) if head >= reorg_tail => {
-                let new_head = match reorg_tail {
-                    StarknetBlockNumber::GENESIS => None,
-                    other => Some(other - 1),
-                };
-                RefsTable::set_l1_l2_head(&transaction, new_head).context("Update L1-L2 head")?;
-            }
-            _ => {}
-        }
+        transaction
+            .commit()
+            .context("Commit database transaction")?;
 
-        transaction.commit().context("Commit database transaction")
+        Ok(l1_l2_head)
     })
 }
 
-fn update_starknet_state(
+/// Applies `diff` to the latest state, and returns the new global root.
+///
+/// The storage trees of the updated contracts are hashed on the threads of `pool` if given, and
+/// one after the other on the calling thread otherwise.
+pub(crate) fn update_starknet_state(
     transaction: &Transaction,
-    diff: StateUpdate,
+    diff: &StateUpdate,
+    pool: Option<&rayon::ThreadPool>,
 ) -> anyhow::Result<GlobalRoot> {
-    let global_root = StarknetBlocksTable::get(transaction, StarknetBlocksBlockId::Latest)
+    let global_root = StarknetBlocksTable::get_state_head(transaction)
         .context("Query latest state root")?
         .map(|block| block.root)
         .unwrap_or(GlobalRoot(StarkHash::ZERO));
+
+    apply_diff(transaction, global_root, diff, pool)
+}
+
+/// Applies `diff` to the state with `global_root`, and returns the new global root.
+///
+/// See [update_starknet_state] for the meaning of `pool`.
+pub(crate) fn apply_diff(
+    transaction: &Transaction,
+    global_root: GlobalRoot,
+    diff: &StateUpdate,
+    pool: Option<&rayon::ThreadPool>,
+) -> anyhow::Result<GlobalRoot> {
     let mut global_tree =
         GlobalStateTree::load(transaction, global_root).context("Loading global state tree")?;
 
-    for contract in diff.deployed_contracts {
+    for contract in &diff.deployed_contracts {
         deploy_contract(transaction, &mut global_tree, contract).context("Deploying contract")?;
     }
 
-    for update in diff.contract_updates {
-        let contract_state_hash = update_contract_state(&update, &global_tree, transaction)
-            .context("Update contract state")?;
+    let updates = group_contract_updates(&diff.contract_updates);
+    let contract_state_hashes = match pool {
+        Some(pool) => {
+            parallel_update::update_contract_states(&updates, &global_tree, transaction, pool)
+                .context("Update contract states")?
+        }
+        None => updates
+            .iter()
+            .map(|update| {
+                update_contract_state(update, &global_tree, transaction)
+                    .context("Update contract state")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
 
-        // Update the global state tree.
+    // Update the global state tree.
+    for (update, contract_state_hash) in updates.iter().zip(contract_state_hashes) {
         global_tree
             .set(update.address, contract_state_hash)
             .context("Updating global state tree")?;
@@ -583,10 +1529,75 @@ fn update_starknet_state(
         .context("Apply global state tree updates")
 }
 
+/// Lists the contracts touched by `diff` along with their leaf in the global tree with `root`,
+/// and the state hash recomputed from their stored class hash and storage root.
+fn contract_divergences(
+    transaction: &Transaction,
+    root: GlobalRoot,
+    diff: &StateUpdate,
+) -> anyhow::Result<Vec<ContractDivergence>> {
+    let global_tree =
+        GlobalStateTree::load(transaction, root).context("Loading global state tree")?;
+
+    let mut addresses = diff
+        .deployed_contracts
+        .iter()
+        .map(|contract| contract.address)
+        .chain(diff.contract_updates.iter().map(|update| update.address))
+        .collect::<Vec<_>>();
+    addresses.sort();
+    addresses.dedup();
+
+    addresses
+        .into_iter()
+        .map(|address| {
+            let computed_state_hash = global_tree
+                .get(address)
+                .context("Get contract state hash from global state tree")?;
+            let class_hash =
+                ContractsTable::get_hash(transaction, address).context("Read class hash")?;
+            let contract_root = ContractsStateTable::get_root(transaction, computed_state_hash)
+                .context("Read contract root")?;
+
+            Ok(ContractDivergence {
+                address,
+                computed_state_hash,
+                recomputed_state_hash: class_hash
+                    .zip(contract_root)
+                    .map(|(class_hash, root)| calculate_contract_state_hash(class_hash, root)),
+            })
+        })
+        .collect()
+}
+
+/// Merges the updates of contracts which appear more than once in `updates`, so that each
+/// contract's storage tree is loaded and committed only once.
+///
+/// Contracts keep the order of their first appearance and storage updates their relative
+/// order, so that later writes to the same storage address still take precedence.
+fn group_contract_updates(updates: &[ContractUpdate]) -> Vec<ContractUpdate> {
+    let mut grouped: Vec<ContractUpdate> = Vec::with_capacity(updates.len());
+    let mut index = std::collections::HashMap::new();
+
+    for update in updates {
+        match index.get(&update.address) {
+            Some(&i) => grouped[i]
+                .storage_updates
+                .extend_from_slice(&update.storage_updates),
+            None => {
+                index.insert(update.address, grouped.len());
+                grouped.push(update.clone());
+            }
+        }
+    }
+
+    grouped
+}
+
 fn deploy_contract(
     transaction: &Transaction,
     global_tree: &mut GlobalStateTree,
-    contract: DeployedContract,
+    contract: &DeployedContract,
 ) -> anyhow::Result<()> {
     // Add a new contract to global tree, the contract root is initialized to ZERO.
     let contract_root = ContractRoot(StarkHash::ZERO);
@@ -656,32 +1667,43 @@ mod tests {
             &self,
             _: web3::types::BlockId,
         ) -> web3::Result<Option<web3::types::Block<H256>>> {
-            unimplemented!()
+            Err(web3::Error::Unreachable)
         }
 
         async fn block_number(&self) -> web3::Result<u64> {
-            unimplemented!()
+            Err(web3::Error::Unreachable)
         }
 
         async fn chain(&self) -> anyhow::Result<ethereum::Chain> {
-            unimplemented!()
+            anyhow::bail!("FakeTransport does not serve chain")
         }
 
         async fn logs(
             &self,
             _: web3::types::Filter,
         ) -> std::result::Result<Vec<web3::types::Log>, ethereum::transport::LogsError> {
-            unimplemented!()
+            Err(web3::Error::Unreachable.into())
         }
 
         async fn transaction(
             &self,
             _: web3::types::TransactionId,
         ) -> web3::Result<Option<web3::types::Transaction>> {
-            unimplemented!()
+            Err(web3::Error::Unreachable)
         }
     }
 
+    /// The reply of the test gateways to requests they do not serve, which fails the caller
+    /// instead of panicking inside a spawned sync task.
+    fn unsupported<T>(request: &str) -> Result<T, SequencerError> {
+        Err(SequencerError::StarknetError(
+            sequencer::error::StarknetError {
+                code: sequencer::error::StarknetErrorCode::MalformedRequest,
+                message: format!("{} is not served by this test gateway", request),
+            },
+        ))
+    }
+
     // We need a simple clonable mock here. Satisfies the sync() internals,
     // and is not really called anywhere in the tests except for status updates
     // which we don't test against here.
@@ -697,8 +1719,16 @@ mod tests {
             Ok(BLOCK0.clone())
         }
 
+        async fn raw_block_by_number(
+            &self,
+            block: BlockNumberOrTag,
+        ) -> Result<bytes::Bytes, SequencerError> {
+            let block = self.block_by_number(block).await?;
+            Ok(serde_json::to_vec(&block).unwrap().into())
+        }
+
         async fn block_by_hash(&self, _: BlockHashOrTag) -> Result<reply::Block, SequencerError> {
-            unimplemented!()
+            unsupported("block_by_hash")
         }
 
         async fn call(
@@ -706,11 +1736,15 @@ mod tests {
             _: request::Call,
             _: BlockHashOrTag,
         ) -> Result<reply::Call, SequencerError> {
-            unimplemented!()
+            unsupported("call")
         }
 
         async fn full_contract(&self, _: ContractAddress) -> Result<bytes::Bytes, SequencerError> {
-            unimplemented!()
+            unsupported("full_contract")
+        }
+
+        async fn class_by_hash(&self, _: ClassHash) -> Result<bytes::Bytes, SequencerError> {
+            unsupported("class_by_hash")
         }
 
         async fn storage(
@@ -719,41 +1753,41 @@ mod tests {
             _: StorageAddress,
             _: BlockHashOrTag,
         ) -> Result<StorageValue, SequencerError> {
-            unimplemented!()
+            unsupported("storage")
         }
 
         async fn transaction(
             &self,
             _: StarknetTransactionHash,
         ) -> Result<reply::Transaction, SequencerError> {
-            unimplemented!()
+            unsupported("transaction")
         }
 
         async fn transaction_status(
             &self,
             _: StarknetTransactionHash,
         ) -> Result<reply::TransactionStatus, SequencerError> {
-            unimplemented!()
+            unsupported("transaction_status")
         }
 
         async fn state_update_by_hash(
             &self,
             _: BlockHashOrTag,
         ) -> Result<reply::StateUpdate, SequencerError> {
-            unimplemented!()
+            unsupported("state_update_by_hash")
         }
 
         async fn state_update_by_number(
             &self,
             _: BlockNumberOrTag,
         ) -> Result<reply::StateUpdate, SequencerError> {
-            unimplemented!()
+            unsupported("state_update_by_number")
         }
 
         async fn eth_contract_addresses(
             &self,
         ) -> Result<reply::EthContractAddresses, SequencerError> {
-            unimplemented!()
+            unsupported("eth_contract_addresses")
         }
 
         async fn add_invoke_transaction(
@@ -762,7 +1796,7 @@ mod tests {
             _: Fee,
             _: TransactionVersion,
         ) -> Result<reply::add_transaction::InvokeResponse, SequencerError> {
-            unimplemented!()
+            unsupported("add_invoke_transaction")
         }
 
         async fn add_declare_transaction(
@@ -775,7 +1809,7 @@ mod tests {
             _: TransactionVersion,
             _: Option<String>,
         ) -> Result<reply::add_transaction::DeclareResponse, SequencerError> {
-            unimplemented!()
+            unsupported("add_declare_transaction")
         }
 
         async fn add_deploy_transaction(
@@ -785,7 +1819,7 @@ mod tests {
             _: ContractDefinition,
             _: Option<String>,
         ) -> Result<reply::add_transaction::DeployResponse, SequencerError> {
-            unimplemented!()
+            unsupported("add_deploy_transaction")
         }
     }
 
@@ -794,6 +1828,7 @@ mod tests {
         _: FakeTransport,
         _: ethereum::Chain,
         _: Option<ethereum::log::StateUpdateLog>,
+        _: u64,
     ) -> anyhow::Result<()> {
         // Avoid being restarted all the time by the outer sync() loop
         let () = std::future::pending().await;
@@ -867,6 +1902,7 @@ mod tests {
             timestamp: StarknetBlockTimestamp(0),
             gas_price: GasPrice::ZERO,
             sequencer_address: SequencerAddress(StarkHash::ZERO),
+            parent_hash: StarknetBlockHash(StarkHash::ZERO),
         };
         pub static ref STORAGE_BLOCK1: storage::StarknetBlock = storage::StarknetBlock {
             number: StarknetBlockNumber(1),
@@ -875,6 +1911,7 @@ mod tests {
             timestamp: StarknetBlockTimestamp(1),
             gas_price: GasPrice::from(1),
             sequencer_address: SequencerAddress(StarkHash::from_be_bytes([1u8; 32]).unwrap()),
+            parent_hash: StarknetBlockHash(*A),
         };
         // Causes root to remain 0
         pub static ref STATE_UPDATE0: ethereum::state_update::StateUpdate = ethereum::state_update::StateUpdate {
@@ -898,7 +1935,7 @@ mod tests {
         }
 
         // A simple L1 sync task
-        let l1 = |tx: mpsc::Sender<l1::Event>, _, _, _| async move {
+        let l1 = |tx: mpsc::Sender<l1::Event>, _, _, _, _| async move {
             let mut update = UPDATES.write().await;
             if let Some(some_update) = update.pop() {
                 tx.send(l1::Event::Update(some_update)).await.unwrap();
@@ -933,6 +1970,7 @@ mod tests {
                 chain,
                 FakeSequencer,
                 sync_state.clone(),
+                state::SyncOptions::default(),
                 l1,
                 l2_noop,
             ));
@@ -977,7 +2015,7 @@ mod tests {
             let connection = storage.connection().unwrap();
 
             // A simple L1 sync task
-            let l1 = move |tx: mpsc::Sender<l1::Event>, _, _, _| async move {
+            let l1 = move |tx: mpsc::Sender<l1::Event>, _, _, _, _| async move {
                 tx.send(l1::Event::Reorg(StarknetBlockNumber(reorg_on_block)))
                     .await
                     .unwrap();
@@ -998,6 +2036,7 @@ mod tests {
                 ethereum::Chain::Goerli,
                 FakeSequencer,
                 Arc::new(state::SyncState::default()),
+                state::SyncOptions::default(),
                 l1,
                 l2_noop,
             ));
@@ -1036,7 +2075,7 @@ mod tests {
         L1StateTable::insert(&connection, &*STATE_UPDATE_LOG0).unwrap();
 
         // A simple L1 sync task which does the request and checks he result
-        let l1 = |tx: mpsc::Sender<l1::Event>, _, _, _| async move {
+        let l1 = |tx: mpsc::Sender<l1::Event>, _, _, _, _| async move {
             let (tx1, rx1) =
                 tokio::sync::oneshot::channel::<Option<ethereum::log::StateUpdateLog>>();
 
@@ -1058,6 +2097,7 @@ mod tests {
             ethereum::Chain::Goerli,
             FakeSequencer,
             Arc::new(state::SyncState::default()),
+            state::SyncOptions::default(),
             l1,
             l2_noop,
         ));
@@ -1074,7 +2114,7 @@ mod tests {
         static CNT: AtomicUsize = AtomicUsize::new(0);
 
         // A simple L1 sync task
-        let l1 = move |_, _, _, _| async move {
+        let l1 = move |_, _, _, _, _| async move {
             CNT.fetch_add(1, Ordering::Relaxed);
             Ok(())
         };
@@ -1086,6 +2126,7 @@ mod tests {
             ethereum::Chain::Goerli,
             FakeSequencer,
             Arc::new(state::SyncState::default()),
+            state::SyncOptions::default(),
             l1,
             l2_noop,
         ));
@@ -1115,6 +2156,7 @@ mod tests {
                 Box::new(block()),
                 state_update(),
                 timings,
+                None,
             ))
             .await
             .unwrap();
@@ -1144,6 +2186,7 @@ mod tests {
                 chain,
                 FakeSequencer,
                 sync_state.clone(),
+                state::SyncOptions::default(),
                 l1_noop,
                 l2,
             ));
@@ -1204,6 +2247,7 @@ mod tests {
                 ethereum::Chain::Goerli,
                 FakeSequencer,
                 Arc::new(state::SyncState::default()),
+                state::SyncOptions::default(),
                 l1_noop,
                 l2,
             ));
@@ -1233,6 +2277,78 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn events() {
+        use state::SyncEvent;
+
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        // L1 already agrees with block 0, so the L1-L2 head follows the L2 update.
+        L1StateTable::insert(&connection, &STATE_UPDATE_LOG0).unwrap();
+
+        // A simple L2 sync task which commits block 0 and then reorgs it away.
+        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _| async move {
+            let timings = l2::Timings {
+                block_download: Duration::default(),
+                state_diff_download: Duration::default(),
+                contract_deployment: Duration::default(),
+            };
+            tx.send(l2::Event::Update(
+                Box::new(BLOCK0.clone()),
+                STATE_UPDATE0.clone(),
+                timings,
+                None,
+            ))
+            .await
+            .unwrap();
+            tx.send(l2::Event::Reorg(StarknetBlockNumber::GENESIS))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        };
+
+        let sync_state = Arc::new(state::SyncState::default());
+        let mut events = sync_state.subscribe();
+
+        // UUT
+        let _jh = tokio::spawn(state::sync(
+            storage,
+            FakeTransport,
+            ethereum::Chain::Goerli,
+            FakeSequencer,
+            sync_state,
+            state::SyncOptions::default(),
+            l1_noop,
+            l2,
+        ));
+
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push(event);
+        }
+
+        assert_eq!(
+            received,
+            vec![
+                SyncEvent::L2Update {
+                    block: STORAGE_BLOCK0.clone(),
+                    transaction_count: 0,
+                    deployed_contracts: 0,
+                    storage_updates: 0,
+                },
+                SyncEvent::L1L2HeadUpdated(Some(StarknetBlockNumber::GENESIS)),
+                SyncEvent::L2Reorg(StarknetBlockNumber::GENESIS),
+                SyncEvent::L1L2HeadUpdated(None),
+            ]
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn l2_new_contract() {
         let storage = Storage::in_memory().unwrap();
@@ -1261,6 +2377,7 @@ mod tests {
             ethereum::Chain::Goerli,
             FakeSequencer,
             Arc::new(state::SyncState::default()),
+            state::SyncOptions::default(),
             l1_noop,
             l2,
         ));
@@ -1274,6 +2391,362 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_deferred_contract() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        // A simple L2 sync task
+        let l2 = |tx: mpsc::Sender<l2::Event>, _, _, _| async move {
+            tx.send(l2::Event::DeferredContract(
+                ClassHash(*A),
+                "gateway error".to_owned(),
+            ))
+            .await
+            .unwrap();
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        };
+
+        // UUT
+        let _jh = tokio::spawn(state::sync(
+            storage,
+            FakeTransport,
+            ethereum::Chain::Goerli,
+            FakeSequencer,
+            Arc::new(state::SyncState::default()),
+            state::SyncOptions::default(),
+            l1_noop,
+            l2,
+        ));
+
+        // TODO Find a better way to figure out that the DB update has already been performed
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // The class is stored so that its contracts can be, but its definition is queued.
+        assert_eq!(
+            storage::ContractCodeTable::exists(&connection, &[ClassHash(*A)]).unwrap(),
+            vec![true]
+        );
+        let queue = storage::SyncRetryQueueTable::all(&connection).unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].item, storage::RetryItem::Class(ClassHash(*A)));
+        assert_eq!(queue[0].attempts, 1);
+        assert_eq!(queue[0].last_error, "gateway error");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_update_with_backwards_timestamp() {
+        let parent = storage::StarknetBlock {
+            timestamp: StarknetBlockTimestamp(100),
+            ..STORAGE_BLOCK0.clone()
+        };
+        // Precedes its parent by 20 seconds.
+        let block = reply::Block {
+            block_hash: Some(StarknetBlockHash(*B)),
+            block_number: Some(StarknetBlockNumber(1)),
+            parent_block_hash: StarknetBlockHash(*A),
+            timestamp: StarknetBlockTimestamp(80),
+            ..BLOCK0.clone()
+        };
+
+        let options = |reject, tolerance| state::SyncOptions {
+            reject_non_monotonic_timestamps: reject,
+            timestamp_tolerance: Duration::from_secs(tolerance),
+            ..Default::default()
+        };
+        let cases = [
+            // Only logged by default.
+            (options(false, 0), true),
+            (options(true, 0), false),
+            (options(true, 19), false),
+            (options(true, 20), true),
+        ];
+
+        for (options, accepted) in cases {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            StarknetBlocksTable::insert(&connection, &parent).unwrap();
+
+            let result = super::l2_update(
+                &mut connection,
+                block.clone(),
+                STATE_UPDATE0.clone(),
+                None,
+                options,
+                Default::default(),
+                None,
+            )
+            .await;
+            assert_eq!(result.is_ok(), accepted, "{:?}", options);
+
+            let stored = StarknetBlocksTable::get(
+                &connection,
+                storage::StarknetBlocksBlockId::Number(StarknetBlockNumber(1)),
+            )
+            .unwrap();
+            assert_eq!(stored.is_some(), accepted, "{:?}", options);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_update_with_receipts_mismatch() {
+        let mut block = serde_json::from_str::<reply::Block>(include_str!(
+            "../../fixtures/sequencer/genesis_block.json"
+        ))
+        .unwrap();
+        assert!(!block.transaction_receipts.is_empty());
+        block.transaction_receipts.pop();
+        block.transaction_receipts[0].transaction_hash = StarknetTransactionHash(*A);
+
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let error = super::l2_update(
+            &mut connection,
+            block,
+            STATE_UPDATE0.clone(),
+            None,
+            Default::default(),
+            Default::default(),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Transactions and receipts mismatch"),
+            "{}",
+            error
+        );
+
+        let stored =
+            StarknetBlocksTable::get(&connection, storage::StarknetBlocksBlockId::Latest).unwrap();
+        assert!(stored.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_update_with_missing_receipts() {
+        let mut block = serde_json::from_str::<reply::Block>(include_str!(
+            "../../fixtures/sequencer/genesis_block.json"
+        ))
+        .unwrap();
+        // Matches the root of the empty state update.
+        block.state_root = Some(GlobalRoot(StarkHash::ZERO));
+        // The earliest blocks lack some receipts, and the gas price. No such block has been
+        // captured as a fixture, so the genesis block is stripped down instead.
+        block.gas_price = None;
+        let missing = block.transaction_receipts.remove(1);
+        let receipts = block.transaction_receipts.clone();
+        let transaction_count = block.transactions.len();
+
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        super::l2_update(
+            &mut connection,
+            block,
+            STATE_UPDATE0.clone(),
+            None,
+            Default::default(),
+            Default::default(),
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        let stored = storage::StarknetTransactionsTable::get_transaction_data_for_block(
+            &connection,
+            StarknetBlockNumber::GENESIS.into(),
+            storage.limits(),
+        )
+        .unwrap();
+        assert_eq!(stored.len(), transaction_count);
+        assert_eq!(
+            stored[1].1,
+            reply::transaction::Receipt::placeholder(
+                missing.transaction_hash,
+                missing.transaction_index
+            )
+        );
+        assert_eq!(stored[0].1, receipts[0]);
+        assert_eq!(
+            stored[2..]
+                .iter()
+                .map(|(_, r)| r.clone())
+                .collect::<Vec<_>>(),
+            receipts[1..]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_update_applies_backfilled_block() {
+        let mut block = serde_json::from_str::<reply::Block>(include_str!(
+            "../../fixtures/sequencer/genesis_block.json"
+        ))
+        .unwrap();
+        // Matches the root of the empty state update.
+        block.state_root = Some(GlobalRoot(StarkHash::ZERO));
+        let transaction_count = block.transactions.len();
+        assert!(transaction_count > 0);
+
+        let backfilled = storage::StarknetBlock {
+            number: StarknetBlockNumber::GENESIS,
+            hash: block.block_hash.unwrap(),
+            root: GlobalRoot(StarkHash::ZERO),
+            timestamp: block.timestamp,
+            gas_price: GasPrice::ZERO,
+            sequencer_address: SequencerAddress(StarkHash::ZERO),
+            parent_hash: block.parent_block_hash,
+        };
+        let backfilled_child = storage::StarknetBlock {
+            number: StarknetBlockNumber(1),
+            hash: StarknetBlockHash(*B),
+            parent_hash: backfilled.hash,
+            ..backfilled.clone()
+        };
+
+        // (hash of the backfilled genesis, expected backfill range afterwards)
+        let cases = [
+            (
+                backfilled.hash,
+                Some(StarknetBlockNumber(1)..=StarknetBlockNumber(1)),
+            ),
+            // A backfilled block of another fork is replaced along with its descendants.
+            (StarknetBlockHash(*A), None),
+        ];
+
+        for (hash, expected_range) in cases {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+
+            let transaction_data = block
+                .transactions
+                .iter()
+                .cloned()
+                .zip(block.transaction_receipts.iter().cloned())
+                .collect::<Vec<_>>();
+            let genesis = storage::StarknetBlock {
+                hash,
+                ..backfilled.clone()
+            };
+            StarknetBlocksTable::insert(&connection, &genesis).unwrap();
+            storage::StarknetTransactionsTable::upsert(
+                &connection,
+                hash,
+                genesis.number,
+                &transaction_data,
+                storage.limits(),
+            )
+            .unwrap();
+            StarknetBlocksTable::insert(&connection, &backfilled_child).unwrap();
+            RefsTable::set_backfill_range(
+                &connection,
+                Some(StarknetBlockNumber::GENESIS..=StarknetBlockNumber(1)),
+            )
+            .unwrap();
+
+            super::l2_update(
+                &mut connection,
+                block.clone(),
+                STATE_UPDATE0.clone(),
+                None,
+                Default::default(),
+                Default::default(),
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+            assert_eq!(
+                RefsTable::get_backfill_range(&connection).unwrap(),
+                expected_range
+            );
+            let state_head = StarknetBlocksTable::get_state_head(&connection)
+                .unwrap()
+                .unwrap();
+            assert_eq!(state_head.hash, block.block_hash.unwrap());
+
+            let count = storage::StarknetTransactionsTable::get_transaction_count(
+                &connection,
+                StarknetBlockNumber::GENESIS.into(),
+            )
+            .unwrap();
+            assert_eq!(count, Some(transaction_count));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn l2_update_retains_raw_blocks() {
+        let block = reply::Block {
+            block_hash: Some(StarknetBlockHash(*B)),
+            block_number: Some(StarknetBlockNumber(1)),
+            parent_block_hash: StarknetBlockHash(*A),
+            timestamp: STORAGE_BLOCK0.timestamp,
+            ..BLOCK0.clone()
+        };
+        let raw_block = bytes::Bytes::from(serde_json::to_vec(&block).unwrap());
+
+        // (window, block 0 retained, block 1 retained)
+        let cases = [(0, true, false), (1, false, true), (2, true, true)];
+
+        for (window, retains_parent, retains_block) in cases {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            StarknetBlocksTable::insert(&connection, &STORAGE_BLOCK0).unwrap();
+            storage::RawGatewayBlocksTable::insert(
+                &connection,
+                STORAGE_BLOCK0.number,
+                STORAGE_BLOCK0.hash,
+                b"{}",
+                storage.limits(),
+            )
+            .unwrap();
+
+            let options = state::SyncOptions {
+                raw_block_window: window,
+                ..Default::default()
+            };
+            super::l2_update(
+                &mut connection,
+                block.clone(),
+                STATE_UPDATE0.clone(),
+                Some(raw_block.clone()),
+                options,
+                Default::default(),
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+            let parent = storage::RawGatewayBlocksTable::get(
+                &connection,
+                StarknetBlockNumber(0),
+                storage.limits(),
+            )
+            .unwrap();
+            assert_eq!(parent.is_some(), retains_parent, "window {}", window);
+            let stored = storage::RawGatewayBlocksTable::get(
+                &connection,
+                StarknetBlockNumber(1),
+                storage.limits(),
+            )
+            .unwrap();
+            match retains_block {
+                true => assert_eq!(
+                    stored,
+                    Some((StarknetBlockHash(*B), raw_block.to_vec())),
+                    "window {}",
+                    window
+                ),
+                false => assert_eq!(stored, None, "window {}", window),
+            }
+        }
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn l2_query_hash() {
         let storage = Storage::in_memory().unwrap();
@@ -1304,6 +2777,7 @@ mod tests {
             ethereum::Chain::Goerli,
             FakeSequencer,
             Arc::new(state::SyncState::default()),
+            state::SyncOptions::default(),
             l1_noop,
             l2,
         ));
@@ -1349,6 +2823,7 @@ mod tests {
             ethereum::Chain::Goerli,
             FakeSequencer,
             Arc::new(state::SyncState::default()),
+            state::SyncOptions::default(),
             l1_noop,
             l2,
         ));
@@ -1375,6 +2850,7 @@ mod tests {
             ethereum::Chain::Goerli,
             FakeSequencer,
             Arc::new(state::SyncState::default()),
+            state::SyncOptions::default(),
             l1_noop,
             l2,
         ));
@@ -1383,4 +2859,1201 @@ mod tests {
 
         assert!(CNT.load(Ordering::Relaxed) > 1);
     }
+
+    mod expected_constructor_calldata_len {
+        use super::super::expected_constructor_calldata_len;
+
+        #[test]
+        fn without_constructor() {
+            let abi = br#"[{"type": "function", "name": "foo", "inputs": [{"name": "x", "type": "felt"}], "outputs": []}]"#;
+            assert_eq!(expected_constructor_calldata_len(abi).unwrap(), Some(0));
+        }
+
+        #[test]
+        fn felt_inputs() {
+            let abi = br#"[
+                {"type": "event", "name": "bar", "keys": [], "data": []},
+                {"type": "constructor", "name": "constructor", "inputs": [{"name": "a", "type": "felt"}, {"name": "b", "type": "felt"}], "outputs": []}
+            ]"#;
+            assert_eq!(expected_constructor_calldata_len(abi).unwrap(), Some(2));
+        }
+
+        #[test]
+        fn array_inputs() {
+            let abi = br#"[{"type": "constructor", "name": "constructor", "inputs": [{"name": "a_len", "type": "felt"}, {"name": "a", "type": "felt*"}], "outputs": []}]"#;
+            assert_eq!(expected_constructor_calldata_len(abi).unwrap(), None);
+        }
+
+        #[test]
+        fn invalid_abi() {
+            expected_constructor_calldata_len(b"{}").unwrap_err();
+        }
+    }
+
+    mod update_starknet_state {
+        use super::super::{deploy_contract, update_starknet_state};
+        use crate::core::{ClassHash, ContractAddress, GlobalRoot, StorageAddress, StorageValue};
+        use crate::ethereum::state_update::{
+            ContractUpdate, DeployedContract, StateUpdate, StorageUpdate,
+        };
+        use crate::state::{
+            state_tree::GlobalStateTree, update_contract_state, CONTRACT_TREE_LOADS,
+        };
+        use crate::storage::{ContractCodeTable, Storage};
+        use stark_hash::StarkHash;
+
+        fn felt(hex: &str) -> StarkHash {
+            StarkHash::from_hex_str(hex).unwrap()
+        }
+
+        fn update(address: &str, writes: &[(&str, &str)]) -> ContractUpdate {
+            ContractUpdate {
+                address: ContractAddress(felt(address)),
+                storage_updates: writes
+                    .iter()
+                    .map(|(key, value)| StorageUpdate {
+                        address: StorageAddress(felt(key)),
+                        value: StorageValue(felt(value)),
+                    })
+                    .collect(),
+            }
+        }
+
+        #[test]
+        fn interleaved_contract_updates_load_each_tree_once() {
+            let class = ClassHash(felt("c1a55"));
+            let diff = StateUpdate {
+                deployed_contracts: ["a", "b"]
+                    .iter()
+                    .map(|address| DeployedContract {
+                        address: ContractAddress(felt(address)),
+                        hash: class,
+                        call_data: vec![],
+                    })
+                    .collect(),
+                contract_updates: vec![
+                    update("a", &[("1", "11")]),
+                    update("b", &[("1", "21")]),
+                    update("a", &[("2", "12"), ("1", "13")]),
+                    update("b", &[("3", "23")]),
+                ],
+            };
+
+            // The root as computed by applying every update separately, in order.
+            let expected = {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                let transaction = connection.transaction().unwrap();
+                ContractCodeTable::insert_without_definition(&transaction, class).unwrap();
+
+                let mut global_tree =
+                    GlobalStateTree::load(&transaction, GlobalRoot(StarkHash::ZERO)).unwrap();
+                for contract in &diff.deployed_contracts {
+                    deploy_contract(&transaction, &mut global_tree, contract).unwrap();
+                }
+                for update in &diff.contract_updates {
+                    let state_hash =
+                        update_contract_state(update, &global_tree, &transaction).unwrap();
+                    global_tree.set(update.address, state_hash).unwrap();
+                }
+                global_tree.apply().unwrap()
+            };
+
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+            ContractCodeTable::insert_without_definition(&transaction, class).unwrap();
+
+            CONTRACT_TREE_LOADS.with(|loads| loads.set(0));
+            let root = update_starknet_state(&transaction, &diff, None).unwrap();
+
+            assert_eq!(root, expected);
+            assert_eq!(CONTRACT_TREE_LOADS.with(|loads| loads.get()), 2);
+        }
+
+        #[test]
+        fn later_writes_take_precedence() {
+            let grouped = super::super::group_contract_updates(&[
+                update("a", &[("1", "11")]),
+                update("b", &[("1", "21")]),
+                update("a", &[("1", "13")]),
+            ]);
+
+            assert_eq!(
+                grouped,
+                vec![
+                    update("a", &[("1", "11"), ("1", "13")]),
+                    update("b", &[("1", "21")]),
+                ]
+            );
+        }
+    }
+
+    mod root_divergence {
+        use super::*;
+        use crate::ethereum::state_update::{
+            ContractUpdate, DeployedContract, StateUpdate, StorageUpdate,
+        };
+        use crate::storage::{ContractCodeTable, RootDivergenceTable};
+
+        fn felt(hex: &str) -> StarkHash {
+            StarkHash::from_hex_str(hex).unwrap()
+        }
+
+        /// Deploys a contract and writes `value` to one of its storage slots.
+        fn diff(value: &str) -> StateUpdate {
+            StateUpdate {
+                deployed_contracts: vec![DeployedContract {
+                    address: ContractAddress(felt("a")),
+                    hash: ClassHash(felt("c1a55")),
+                    call_data: vec![],
+                }],
+                contract_updates: vec![ContractUpdate {
+                    address: ContractAddress(felt("a")),
+                    storage_updates: vec![StorageUpdate {
+                        address: StorageAddress(felt("1")),
+                        value: StorageValue(felt(value)),
+                    }],
+                }],
+            }
+        }
+
+        fn storage_with_class() -> Storage {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            ContractCodeTable::insert_without_definition(&connection, ClassHash(felt("c1a55")))
+                .unwrap();
+            storage
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn corrupted_storage_diff_halts_sync() {
+            let chain = ethereum::Chain::Goerli;
+            let sync_state = Arc::new(state::SyncState::default());
+
+            // The root which the sequencer committed to for the honest diff.
+            let expected_root = {
+                let storage = storage_with_class();
+                let mut connection = storage.connection().unwrap();
+                let transaction = connection.transaction().unwrap();
+                super::super::update_starknet_state(&transaction, &diff("11"), None).unwrap()
+            };
+
+            let timings = l2::Timings {
+                block_download: Duration::default(),
+                state_diff_download: Duration::default(),
+                contract_deployment: Duration::default(),
+            };
+
+            let l2 = move |tx: mpsc::Sender<l2::Event>, _, _, _| async move {
+                tx.send(l2::Event::Update(
+                    Box::new(BLOCK0.clone()),
+                    STATE_UPDATE0.clone(),
+                    timings,
+                    None,
+                ))
+                .await
+                .unwrap();
+
+                let block1 = reply::Block {
+                    state_root: Some(expected_root),
+                    ..BLOCK1.clone()
+                };
+                tx.send(l2::Event::Update(
+                    Box::new(block1),
+                    diff("12"),
+                    timings,
+                    None,
+                ))
+                .await
+                .unwrap();
+
+                let () = std::future::pending().await;
+                Ok(())
+            };
+
+            let storage = storage_with_class();
+            let connection = storage.connection().unwrap();
+
+            let jh = tokio::spawn(state::sync(
+                storage.clone(),
+                FakeTransport,
+                chain,
+                FakeSequencer,
+                sync_state.clone(),
+                state::SyncOptions::default(),
+                l1_noop,
+                l2,
+            ));
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let divergence = sync_state.root_divergence().expect("Sync should halt");
+            assert_eq!(divergence.block_number, StarknetBlockNumber(1));
+            assert_eq!(divergence.block_hash, StarknetBlockHash(*B));
+            assert_eq!(divergence.expected_root, expected_root);
+            assert_ne!(divergence.computed_root, expected_root);
+            assert_eq!(divergence.l1_root, None);
+            assert_eq!(divergence.contracts.len(), 1);
+            assert_eq!(divergence.contracts[0].address, ContractAddress(felt("a")));
+            // The corruption is in the diff itself, so the computed leaf is self-consistent.
+            assert!(!divergence.contracts[0].is_inconsistent());
+            assert!(!sync_state.is_healthy());
+
+            let recorded = RootDivergenceTable::get_latest(&connection)
+                .unwrap()
+                .unwrap();
+            assert_eq!(recorded, divergence);
+
+            // Nothing past the divergence is stored, while the blocks preceding it are served.
+            assert_eq!(
+                StarknetBlocksTable::get_latest_number(&connection).unwrap(),
+                Some(StarknetBlockNumber(0))
+            );
+            let api = crate::rpc::api::RpcApi::new(
+                storage,
+                sequencer::Client::new(chain).unwrap(),
+                chain,
+                sync_state,
+            );
+            assert_eq!(api.block_number().await.unwrap(), 0);
+
+            // Sync stays halted rather than exiting.
+            assert!(tokio::time::timeout(Duration::from_millis(100), jh)
+                .await
+                .is_err());
+        }
+    }
+
+    mod timestamp_regression {
+        use super::super::timestamp_regression;
+        use crate::core::StarknetBlockTimestamp;
+        use crate::storage::{StarknetBlocksTable, Storage};
+        use std::time::Duration;
+
+        #[test]
+        fn non_decreasing_timestamps_are_accepted() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let mut blocks = crate::storage::test_utils::create_blocks::<3>();
+            blocks[1].timestamp = blocks[0].timestamp;
+            for block in &blocks {
+                assert_eq!(
+                    timestamp_regression(&transaction, block, Duration::ZERO).unwrap(),
+                    None
+                );
+                StarknetBlocksTable::insert(&transaction, block).unwrap();
+            }
+        }
+
+        #[test]
+        fn regression_is_reported() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let [genesis, mut block] = crate::storage::test_utils::create_blocks::<2>();
+            StarknetBlocksTable::insert(&transaction, &genesis).unwrap();
+            block.timestamp = StarknetBlockTimestamp(genesis.timestamp.0 - 1);
+
+            assert_eq!(
+                timestamp_regression(&transaction, &block, Duration::ZERO).unwrap(),
+                Some(genesis.timestamp)
+            );
+        }
+
+        #[test]
+        fn regression_within_tolerance_is_accepted() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let [genesis, mut block] = crate::storage::test_utils::create_blocks::<2>();
+            StarknetBlocksTable::insert(&transaction, &genesis).unwrap();
+            let tolerance = Duration::from_secs(10);
+
+            block.timestamp = StarknetBlockTimestamp(genesis.timestamp.0 - 10);
+            assert_eq!(
+                timestamp_regression(&transaction, &block, tolerance).unwrap(),
+                None
+            );
+
+            block.timestamp = StarknetBlockTimestamp(genesis.timestamp.0 - 11);
+            assert_eq!(
+                timestamp_regression(&transaction, &block, tolerance).unwrap(),
+                Some(genesis.timestamp)
+            );
+        }
+
+        #[test]
+        fn missing_parent_is_ignored() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let [_, mut block] = crate::storage::test_utils::create_blocks::<2>();
+            block.timestamp = StarknetBlockTimestamp(0);
+
+            assert_eq!(
+                timestamp_regression(&transaction, &block, Duration::ZERO).unwrap(),
+                None
+            );
+        }
+    }
+
+    mod throughput {
+        use super::super::Throughput;
+        use crate::core::StarknetBlockNumber;
+        use std::time::{Duration, Instant};
+
+        /// Records blocks 0..=4 one second apart, followed by blocks 5..=14 every 500ms.
+        fn synthetic() -> (Throughput, Instant) {
+            let start = Instant::now();
+            let mut throughput = Throughput::default();
+
+            let slow = (0..5).map(|i| (Duration::from_secs(i), i));
+            let fast = (1..=10).map(|i| {
+                (
+                    Duration::from_secs(4) + Duration::from_millis(500 * i),
+                    4 + i,
+                )
+            });
+            for (offset, block) in slow.chain(fast) {
+                throughput.record(start + offset, StarknetBlockNumber(block));
+            }
+
+            (throughput, start + Duration::from_secs(9))
+        }
+
+        #[test]
+        fn full_window() {
+            let (throughput, now) = synthetic();
+            let rate = throughput
+                .blocks_per_second(now, Throughput::WINDOW)
+                .unwrap();
+            // 14 blocks over 9 seconds.
+            assert_eq!(rate, 14.0 / 9.0);
+        }
+
+        #[test]
+        fn since() {
+            let (throughput, now) = synthetic();
+            let rate = throughput
+                .blocks_per_second(now, Duration::from_secs(4))
+                .unwrap();
+            // Blocks 6..=14, stored within the last four seconds.
+            assert_eq!(rate, 2.0);
+        }
+
+        #[test]
+        fn stalled() {
+            let (throughput, now) = synthetic();
+            let now = now + Duration::from_secs(10);
+            assert_eq!(
+                throughput.blocks_per_second(now, Duration::from_secs(5)),
+                None
+            );
+        }
+
+        #[test]
+        fn reorg_resets_samples() {
+            let (mut throughput, now) = synthetic();
+            throughput.record(now, StarknetBlockNumber(3));
+            assert_eq!(throughput.blocks_per_second(now, Throughput::WINDOW), None);
+        }
+
+        #[test]
+        fn old_samples_are_pruned() {
+            let (mut throughput, now) = synthetic();
+            let later = now + Throughput::WINDOW;
+            throughput.record(later, StarknetBlockNumber(15));
+            // Only blocks stored within the window of the last sample remain.
+            assert_eq!(throughput.samples.len(), 2);
+            assert_eq!(
+                throughput.samples.front().unwrap().1,
+                StarknetBlockNumber(14)
+            );
+        }
+    }
+
+    mod deep_reorg {
+        use super::*;
+        use crate::sequencer::error::{StarknetError, StarknetErrorCode};
+        use crate::state::{DeepReorg, SyncEvent};
+        use crate::storage::StarknetBlocksBlockId;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use tokio::sync::broadcast;
+
+        /// A scripted gateway which serves chain A up to block `a_head`. Once it is asked for
+        /// the block after `a_head` it switches to chain B, which shares chain A's blocks below
+        /// `fork` and ends at block `b_head`.
+        ///
+        /// All blocks are empty, so their state roots are all zero.
+        #[derive(Debug, Clone)]
+        struct ReorgingSequencer {
+            a_head: u64,
+            fork: u64,
+            b_head: u64,
+            switched: Arc<AtomicBool>,
+        }
+
+        impl ReorgingSequencer {
+            /// Chain B invalidates the last `depth` blocks of chain A.
+            fn new(a_head: u64, depth: u64, b_head: u64) -> Self {
+                assert!(
+                    depth > 0 && depth <= a_head,
+                    "Chain B must share the genesis block"
+                );
+                assert!(b_head > a_head, "Chain B must be longer than chain A");
+                Self {
+                    a_head,
+                    fork: a_head + 1 - depth,
+                    b_head,
+                    switched: Default::default(),
+                }
+            }
+
+            fn hash(&self, chain_b: bool, number: u64) -> StarknetBlockHash {
+                let chain = if chain_b && number >= self.fork {
+                    0xB
+                } else {
+                    0xA
+                };
+                let mut bytes = [chain; 9];
+                bytes[1..].copy_from_slice(&number.to_be_bytes());
+                StarknetBlockHash(StarkHash::from_be_slice(&bytes).unwrap())
+            }
+
+            fn block(&self, chain_b: bool, number: u64) -> reply::Block {
+                let parent_block_hash = match number {
+                    0 => StarknetBlockHash(StarkHash::ZERO),
+                    n => self.hash(chain_b, n - 1),
+                };
+                reply::Block {
+                    block_hash: Some(self.hash(chain_b, number)),
+                    block_number: Some(StarknetBlockNumber(number)),
+                    gas_price: None,
+                    parent_block_hash,
+                    sequencer_address: None,
+                    state_root: Some(GlobalRoot(StarkHash::ZERO)),
+                    status: reply::Status::AcceptedOnL2,
+                    timestamp: StarknetBlockTimestamp(number),
+                    transaction_receipts: vec![],
+                    transactions: vec![],
+                }
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl sequencer::ClientApi for ReorgingSequencer {
+            async fn block_by_number(
+                &self,
+                block: BlockNumberOrTag,
+            ) -> Result<reply::Block, SequencerError> {
+                let mut chain_b = self.switched.load(Ordering::SeqCst);
+                let number = match block {
+                    BlockNumberOrTag::Number(number) => {
+                        if !chain_b && number.0 > self.a_head {
+                            self.switched.store(true, Ordering::SeqCst);
+                            chain_b = true;
+                        }
+                        number.0
+                    }
+                    BlockNumberOrTag::Tag(_) if chain_b => self.b_head,
+                    BlockNumberOrTag::Tag(_) => self.a_head,
+                    BlockNumberOrTag::Relative(_) => {
+                        return unsupported("relative block_by_number")
+                    }
+                };
+
+                let head = if chain_b { self.b_head } else { self.a_head };
+                if number > head {
+                    return Err(SequencerError::StarknetError(StarknetError {
+                        code: StarknetErrorCode::BlockNotFound,
+                        message: String::new(),
+                    }));
+                }
+
+                Ok(self.block(chain_b, number))
+            }
+
+            async fn raw_block_by_number(
+                &self,
+                block: BlockNumberOrTag,
+            ) -> Result<bytes::Bytes, SequencerError> {
+                let block = self.block_by_number(block).await?;
+                Ok(serde_json::to_vec(&block).unwrap().into())
+            }
+
+            async fn block_by_hash(
+                &self,
+                _: BlockHashOrTag,
+            ) -> Result<reply::Block, SequencerError> {
+                unsupported("block_by_hash")
+            }
+
+            async fn call(
+                &self,
+                _: request::Call,
+                _: BlockHashOrTag,
+            ) -> Result<reply::Call, SequencerError> {
+                unsupported("call")
+            }
+
+            async fn full_contract(
+                &self,
+                _: ContractAddress,
+            ) -> Result<bytes::Bytes, SequencerError> {
+                unsupported("full_contract")
+            }
+
+            async fn class_by_hash(&self, _: ClassHash) -> Result<bytes::Bytes, SequencerError> {
+                unsupported("class_by_hash")
+            }
+
+            async fn storage(
+                &self,
+                _: ContractAddress,
+                _: StorageAddress,
+                _: BlockHashOrTag,
+            ) -> Result<StorageValue, SequencerError> {
+                unsupported("storage")
+            }
+
+            async fn transaction(
+                &self,
+                _: StarknetTransactionHash,
+            ) -> Result<reply::Transaction, SequencerError> {
+                unsupported("transaction")
+            }
+
+            async fn transaction_status(
+                &self,
+                _: StarknetTransactionHash,
+            ) -> Result<reply::TransactionStatus, SequencerError> {
+                unsupported("transaction_status")
+            }
+
+            async fn state_update_by_hash(
+                &self,
+                _: BlockHashOrTag,
+            ) -> Result<reply::StateUpdate, SequencerError> {
+                Ok(reply::StateUpdate {
+                    new_root: GlobalRoot(StarkHash::ZERO),
+                    old_root: GlobalRoot(StarkHash::ZERO),
+                    state_diff: reply::state_update::StateDiff {
+                        storage_diffs: Default::default(),
+                        deployed_contracts: vec![],
+                    },
+                })
+            }
+
+            async fn state_update_by_number(
+                &self,
+                _: BlockNumberOrTag,
+            ) -> Result<reply::StateUpdate, SequencerError> {
+                unsupported("state_update_by_number")
+            }
+
+            async fn eth_contract_addresses(
+                &self,
+            ) -> Result<reply::EthContractAddresses, SequencerError> {
+                unsupported("eth_contract_addresses")
+            }
+
+            async fn add_invoke_transaction(
+                &self,
+                _: crate::sequencer::request::Call,
+                _: Fee,
+                _: TransactionVersion,
+            ) -> Result<reply::add_transaction::InvokeResponse, SequencerError> {
+                unsupported("add_invoke_transaction")
+            }
+
+            async fn add_declare_transaction(
+                &self,
+                _: ContractDefinition,
+                _: ContractAddress,
+                _: Fee,
+                _: Vec<CallSignatureElem>,
+                _: TransactionNonce,
+                _: TransactionVersion,
+                _: Option<String>,
+            ) -> Result<reply::add_transaction::DeclareResponse, SequencerError> {
+                unsupported("add_declare_transaction")
+            }
+
+            async fn add_deploy_transaction(
+                &self,
+                _: ContractAddressSalt,
+                _: Vec<ConstructorParam>,
+                _: ContractDefinition,
+                _: Option<String>,
+            ) -> Result<reply::add_transaction::DeployResponse, SequencerError> {
+                unsupported("add_deploy_transaction")
+            }
+        }
+
+        /// Runs sync against the `sequencer`, using the real L2 sync process.
+        fn spawn_sync(
+            sequencer: ReorgingSequencer,
+            max_reorg_depth: Option<u64>,
+        ) -> (
+            Storage,
+            Arc<state::SyncState>,
+            broadcast::Receiver<SyncEvent>,
+        ) {
+            let storage = Storage::in_memory().unwrap();
+            let sync_state = Arc::new(state::SyncState::default());
+            let events = sync_state.subscribe();
+
+            let _jh = tokio::spawn(state::sync(
+                storage.clone(),
+                FakeTransport,
+                ethereum::Chain::Goerli,
+                sequencer,
+                sync_state.clone(),
+                state::SyncOptions {
+                    max_reorg_depth,
+                    ..Default::default()
+                },
+                l1_noop,
+                l2::sync,
+            ));
+
+            (storage, sync_state, events)
+        }
+
+        /// Collects the published events until the block with `hash` has been stored.
+        async fn until_stored(
+            events: &mut broadcast::Receiver<SyncEvent>,
+            hash: StarknetBlockHash,
+        ) -> Vec<SyncEvent> {
+            let collect = async {
+                let mut seen = Vec::new();
+                loop {
+                    let event = events.recv().await.unwrap();
+                    let done =
+                        matches!(&event, SyncEvent::L2Update { block, .. } if block.hash == hash);
+                    seen.push(event);
+                    if done {
+                        return seen;
+                    }
+                }
+            };
+
+            tokio::time::timeout(Duration::from_secs(10), collect)
+                .await
+                .unwrap()
+        }
+
+        fn head_hash(storage: &Storage) -> Option<StarknetBlockHash> {
+            let connection = storage.connection().unwrap();
+            StarknetBlocksTable::get(&connection, StarknetBlocksBlockId::Latest)
+                .unwrap()
+                .map(|block| block.hash)
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn within_limit_is_applied() {
+            let sequencer = ReorgingSequencer::new(10, 5, 12);
+            let (storage, sync_state, mut events) = spawn_sync(sequencer.clone(), Some(5));
+
+            let seen = until_stored(&mut events, sequencer.hash(true, 12)).await;
+
+            assert!(seen.contains(&SyncEvent::L2Reorg(StarknetBlockNumber(6))));
+            assert_eq!(sync_state.halted_reorg(), None);
+            assert_eq!(head_hash(&storage), Some(sequencer.hash(true, 12)));
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn no_limit() {
+            let sequencer = ReorgingSequencer::new(10, 8, 12);
+            let (storage, sync_state, mut events) = spawn_sync(sequencer.clone(), None);
+
+            let seen = until_stored(&mut events, sequencer.hash(true, 12)).await;
+
+            assert!(seen.contains(&SyncEvent::L2Reorg(StarknetBlockNumber(3))));
+            assert_eq!(sync_state.halted_reorg(), None);
+            assert_eq!(head_hash(&storage), Some(sequencer.hash(true, 12)));
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn beyond_limit_halts_until_approved() {
+            let sequencer = ReorgingSequencer::new(10, 6, 12);
+            let (storage, sync_state, mut events) = spawn_sync(sequencer.clone(), Some(5));
+
+            let wait_for_halt = async {
+                loop {
+                    if let Some(reorg) = sync_state.halted_reorg() {
+                        return reorg;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            let reorg = tokio::time::timeout(Duration::from_secs(10), wait_for_halt)
+                .await
+                .unwrap();
+
+            let expected = DeepReorg {
+                head: StarknetBlockNumber(10),
+                reorg_tail: StarknetBlockNumber(5),
+            };
+            assert_eq!(reorg, expected);
+            assert_eq!(reorg.depth(), 6);
+
+            // Nothing may be reverted while halted.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(sync_state.halted_reorg(), Some(expected));
+            assert_eq!(head_hash(&storage), Some(sequencer.hash(false, 10)));
+
+            assert_eq!(sync_state.approve_reorg(), Some(expected));
+            assert_eq!(sync_state.approve_reorg(), None);
+
+            let seen = until_stored(&mut events, sequencer.hash(true, 12)).await;
+
+            assert!(seen.contains(&SyncEvent::L2Reorg(StarknetBlockNumber(5))));
+            assert_eq!(sync_state.halted_reorg(), None);
+            assert_eq!(head_hash(&storage), Some(sequencer.hash(true, 12)));
+        }
+    }
+
+    mod stall_watchdog {
+        use super::*;
+        use crate::sequencer::error::{StarknetError, StarknetErrorCode};
+        use crate::state::{StallWatchdog, SyncEvent};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const HEAD: u64 = 2;
+
+        /// A gateway serving a chain of empty blocks up to [HEAD], whose first `hangs` block
+        /// requests by number never complete.
+        #[derive(Debug, Clone)]
+        struct HangingSequencer {
+            hangs: usize,
+            requests: Arc<AtomicUsize>,
+        }
+
+        impl HangingSequencer {
+            fn new(hangs: usize) -> Self {
+                Self {
+                    hangs,
+                    requests: Default::default(),
+                }
+            }
+
+            fn hash(number: u64) -> StarknetBlockHash {
+                StarknetBlockHash(
+                    StarkHash::from_be_slice(&(0xB10C00 + number).to_be_bytes()).unwrap(),
+                )
+            }
+
+            fn block(number: u64) -> reply::Block {
+                let parent_block_hash = match number {
+                    0 => StarknetBlockHash(StarkHash::ZERO),
+                    n => Self::hash(n - 1),
+                };
+                reply::Block {
+                    block_hash: Some(Self::hash(number)),
+                    block_number: Some(StarknetBlockNumber(number)),
+                    gas_price: None,
+                    parent_block_hash,
+                    sequencer_address: None,
+                    state_root: Some(GlobalRoot(StarkHash::ZERO)),
+                    status: reply::Status::AcceptedOnL2,
+                    timestamp: StarknetBlockTimestamp(number),
+                    transaction_receipts: vec![],
+                    transactions: vec![],
+                }
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl sequencer::ClientApi for HangingSequencer {
+            async fn block_by_number(
+                &self,
+                block: BlockNumberOrTag,
+            ) -> Result<reply::Block, SequencerError> {
+                let number = match block {
+                    BlockNumberOrTag::Number(number) => number.0,
+                    BlockNumberOrTag::Tag(_) => return Ok(Self::block(HEAD)),
+                    BlockNumberOrTag::Relative(_) => {
+                        return unsupported("relative block_by_number")
+                    }
+                };
+
+                if self.requests.fetch_add(1, Ordering::SeqCst) < self.hangs {
+                    std::future::pending::<()>().await;
+                }
+
+                if number > HEAD {
+                    return Err(SequencerError::StarknetError(StarknetError {
+                        code: StarknetErrorCode::BlockNotFound,
+                        message: String::new(),
+                    }));
+                }
+
+                Ok(Self::block(number))
+            }
+
+            async fn raw_block_by_number(
+                &self,
+                block: BlockNumberOrTag,
+            ) -> Result<bytes::Bytes, SequencerError> {
+                let block = self.block_by_number(block).await?;
+                Ok(serde_json::to_vec(&block).unwrap().into())
+            }
+
+            async fn block_by_hash(
+                &self,
+                _: BlockHashOrTag,
+            ) -> Result<reply::Block, SequencerError> {
+                unsupported("block_by_hash")
+            }
+
+            async fn call(
+                &self,
+                _: request::Call,
+                _: BlockHashOrTag,
+            ) -> Result<reply::Call, SequencerError> {
+                unsupported("call")
+            }
+
+            async fn full_contract(
+                &self,
+                _: ContractAddress,
+            ) -> Result<bytes::Bytes, SequencerError> {
+                unsupported("full_contract")
+            }
+
+            async fn class_by_hash(&self, _: ClassHash) -> Result<bytes::Bytes, SequencerError> {
+                unsupported("class_by_hash")
+            }
+
+            async fn storage(
+                &self,
+                _: ContractAddress,
+                _: StorageAddress,
+                _: BlockHashOrTag,
+            ) -> Result<StorageValue, SequencerError> {
+                unsupported("storage")
+            }
+
+            async fn transaction(
+                &self,
+                _: StarknetTransactionHash,
+            ) -> Result<reply::Transaction, SequencerError> {
+                unsupported("transaction")
+            }
+
+            async fn transaction_status(
+                &self,
+                _: StarknetTransactionHash,
+            ) -> Result<reply::TransactionStatus, SequencerError> {
+                unsupported("transaction_status")
+            }
+
+            async fn state_update_by_hash(
+                &self,
+                _: BlockHashOrTag,
+            ) -> Result<reply::StateUpdate, SequencerError> {
+                Ok(reply::StateUpdate {
+                    new_root: GlobalRoot(StarkHash::ZERO),
+                    old_root: GlobalRoot(StarkHash::ZERO),
+                    state_diff: reply::state_update::StateDiff {
+                        storage_diffs: Default::default(),
+                        deployed_contracts: vec![],
+                    },
+                })
+            }
+
+            async fn state_update_by_number(
+                &self,
+                _: BlockNumberOrTag,
+            ) -> Result<reply::StateUpdate, SequencerError> {
+                unsupported("state_update_by_number")
+            }
+
+            async fn eth_contract_addresses(
+                &self,
+            ) -> Result<reply::EthContractAddresses, SequencerError> {
+                unsupported("eth_contract_addresses")
+            }
+
+            async fn add_invoke_transaction(
+                &self,
+                _: crate::sequencer::request::Call,
+                _: Fee,
+                _: TransactionVersion,
+            ) -> Result<reply::add_transaction::InvokeResponse, SequencerError> {
+                unsupported("add_invoke_transaction")
+            }
+
+            async fn add_declare_transaction(
+                &self,
+                _: ContractDefinition,
+                _: ContractAddress,
+                _: Fee,
+                _: Vec<CallSignatureElem>,
+                _: TransactionNonce,
+                _: TransactionVersion,
+                _: Option<String>,
+            ) -> Result<reply::add_transaction::DeclareResponse, SequencerError> {
+                unsupported("add_declare_transaction")
+            }
+
+            async fn add_deploy_transaction(
+                &self,
+                _: ContractAddressSalt,
+                _: Vec<ConstructorParam>,
+                _: ContractDefinition,
+                _: Option<String>,
+            ) -> Result<reply::add_transaction::DeployResponse, SequencerError> {
+                unsupported("add_deploy_transaction")
+            }
+        }
+
+        /// An L1 sync process which is permanently at the head of L1.
+        async fn l1_at_head(
+            tx_event: mpsc::Sender<l1::Event>,
+            _: FakeTransport,
+            _: ethereum::Chain,
+            _: Option<ethereum::log::StateUpdateLog>,
+            _: u64,
+        ) -> anyhow::Result<()> {
+            while tx_event.send(l1::Event::Heartbeat).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        }
+
+        fn spawn_sync(sequencer: HangingSequencer, max_restarts: u32) -> Arc<state::SyncState> {
+            let sync_state = Arc::new(state::SyncState::default());
+
+            let _jh = tokio::spawn(state::sync(
+                Storage::in_memory().unwrap(),
+                FakeTransport,
+                ethereum::Chain::Goerli,
+                sequencer,
+                sync_state.clone(),
+                state::SyncOptions {
+                    stall_watchdog: Some(StallWatchdog {
+                        timeout: Duration::from_millis(100),
+                        max_restarts,
+                        backoff: Duration::from_millis(10),
+                    }),
+                    ..Default::default()
+                },
+                l1_at_head,
+                l2::sync,
+            ));
+
+            sync_state
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn restarts_stalled_l2_sync() {
+            let sequencer = HangingSequencer::new(2);
+            let sync_state = spawn_sync(sequencer.clone(), 3);
+            let mut events = sync_state.subscribe();
+
+            let head_stored = async {
+                loop {
+                    if let SyncEvent::L2Update { block, .. } = events.recv().await.unwrap() {
+                        if block.number == StarknetBlockNumber(HEAD) {
+                            return;
+                        }
+                    }
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(10), head_stored)
+                .await
+                .unwrap();
+
+            // Both hanging requests were abandoned by restarts, followed by at least one per block.
+            assert!(sequencer.requests.load(Ordering::SeqCst) >= 2 + HEAD as usize + 1);
+            assert!(sync_state.is_healthy());
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn gives_up_and_marks_unhealthy() {
+            let sequencer = HangingSequencer::new(usize::MAX);
+            let sync_state = spawn_sync(sequencer.clone(), 2);
+
+            let unhealthy = async {
+                while sync_state.is_healthy() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(10), unhealthy)
+                .await
+                .unwrap();
+
+            // The initial process and its two restarts.
+            assert_eq!(sequencer.requests.load(Ordering::SeqCst), 3);
+        }
+    }
+
+    mod poll_pending {
+        use super::*;
+        use crate::sequencer::MockClientApi;
+
+        /// Polls a gateway whose pending block builds on `parent` until the first poll has
+        /// been handled, with [STORAGE_BLOCK0] as the head.
+        async fn pending_after_poll(parent: StarknetBlockHash) -> Option<reply::Block> {
+            let (polled_tx, mut polled_rx) = mpsc::channel(1);
+            let mut sequencer = MockClientApi::new();
+            sequencer.expect_block_by_number().returning(move |_| {
+                let _ = polled_tx.try_send(());
+                Ok(reply::Block {
+                    block_hash: None,
+                    block_number: None,
+                    parent_block_hash: parent,
+                    status: reply::Status::Pending,
+                    ..BLOCK1.clone()
+                })
+            });
+
+            let state = Arc::new(state::SyncState::default());
+            state.pending.set_head(Some(STORAGE_BLOCK0.hash)).await;
+            let poller = tokio::spawn(super::super::poll_pending(
+                Arc::clone(&state),
+                sequencer,
+                Duration::from_millis(1),
+            ));
+
+            // The second poll only starts once the first one has updated the state.
+            polled_rx.recv().await.unwrap();
+            polled_rx.recv().await.unwrap();
+            poller.abort();
+
+            state
+                .pending
+                .get()
+                .await
+                .map(|pending| pending.block.clone())
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn kept_while_it_builds_on_the_head() {
+            let pending = pending_after_poll(STORAGE_BLOCK0.hash).await.unwrap();
+            assert_eq!(pending.parent_block_hash, STORAGE_BLOCK0.hash);
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+        async fn dropped_while_sync_is_behind() {
+            let pending = pending_after_poll(StarknetBlockHash(*B)).await;
+            assert_eq!(pending, None);
+        }
+    }
+
+    mod backfill_convergence {
+        use super::*;
+        use crate::rpc::types::Tag;
+        use crate::sequencer::MockClientApi;
+        use crate::state::block_backfill;
+        use crate::storage::test_utils;
+
+        const BLOCKS: usize = 5;
+
+        /// A chain of blocks with a single transaction each, which leave the state empty.
+        fn chain() -> Vec<reply::Block> {
+            let mut parent = StarknetBlockHash(StarkHash::ZERO);
+            test_utils::create_transactions_and_receipts::<BLOCKS>()
+                .into_iter()
+                .enumerate()
+                .map(|(number, (transaction, receipt))| {
+                    let hash =
+                        StarknetBlockHash(StarkHash::from_be_slice(&[number as u8 + 1]).unwrap());
+                    let block = reply::Block {
+                        block_hash: Some(hash),
+                        block_number: Some(StarknetBlockNumber(number as u64)),
+                        parent_block_hash: parent,
+                        timestamp: StarknetBlockTimestamp(number as u64),
+                        transactions: vec![transaction],
+                        transaction_receipts: vec![receipt],
+                        ..BLOCK0.clone()
+                    };
+                    parent = hash;
+                    block
+                })
+                .collect()
+        }
+
+        fn count(storage: &Storage, table: &str) -> usize {
+            storage
+                .connection()
+                .unwrap()
+                .query_row(&format!("SELECT count(1) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn forward_sync_consumes_backfilled_blocks() {
+            let chain = chain();
+            let head = StarknetBlockNumber(BLOCKS as u64 - 1);
+            let dir = tempfile::tempdir().unwrap();
+            let storage = Storage::migrate(dir.path().join("convergence.sqlite")).unwrap();
+
+            let mut sequencer = MockClientApi::new();
+            let blocks = chain.clone();
+            sequencer
+                .expect_block_by_number()
+                .returning(move |block| match block {
+                    BlockNumberOrTag::Tag(Tag::Latest) => Ok(blocks.last().unwrap().clone()),
+                    BlockNumberOrTag::Number(number) => Ok(blocks[number.0 as usize].clone()),
+                    other => unreachable!("Unexpected block request {:?}", other),
+                });
+            let backfill = tokio::spawn(block_backfill::backfill(
+                storage.clone(),
+                sequencer,
+                Duration::from_millis(10),
+            ));
+
+            // The backfill stores the whole chain before the forward sync starts.
+            let backfilled = Some(StarknetBlockNumber::GENESIS..=head);
+            while RefsTable::get_backfill_range(&storage.connection().unwrap()).unwrap()
+                != backfilled
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(count(&storage, "starknet_transactions"), BLOCKS);
+
+            let mut connection = storage.connection().unwrap();
+            for block in chain {
+                super::super::l2_update(
+                    &mut connection,
+                    block,
+                    STATE_UPDATE0.clone(),
+                    None,
+                    Default::default(),
+                    Default::default(),
+                    None,
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            }
+
+            // The backfill completes once the forward sync has caught up with the head.
+            tokio::time::timeout(Duration::from_secs(10), backfill)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(RefsTable::get_backfill_range(&connection).unwrap(), None);
+            let state_head = StarknetBlocksTable::get_state_head(&connection)
+                .unwrap()
+                .unwrap();
+            assert_eq!(state_head.number, head);
+            assert_eq!(count(&storage, "starknet_blocks"), BLOCKS);
+            assert_eq!(count(&storage, "starknet_transactions"), BLOCKS);
+            assert_eq!(count(&storage, "starknet_events"), BLOCKS);
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use std::{num::NonZeroU64, sync::Arc, time::Duration};
+use std::{collections::VecDeque, num::NonZeroU64, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use futures::Future;
@@ -29,15 +29,27 @@ pub enum Event {
     /// The receiver should return the [update log](StateUpdateLog) using the
     /// [oneshot::channel].
     QueryUpdate(StarknetBlockNumber, oneshot::Sender<Option<StateUpdateLog>>),
+    /// The head of L1 was polled without finding new [update logs](StateUpdateLog).
+    ///
+    /// Lets the receiver know that the process is still making progress.
+    Heartbeat,
+    /// The number of [update logs](StateUpdateLog) which were found but are not yet
+    /// confirmed by enough L1 blocks to be emitted as an [Event::Update].
+    PendingConfirmations(u64),
 }
 
 /// Syncs L1 state update logs. Emits [sync events](Event) which should be handled
 /// to update storage and respond to queries.
+///
+/// Logs are only emitted once their L1 block is at least `confirmation_depth` blocks below
+/// the L1 head. Until then they are kept in memory, so that L1 reorgs which only affect
+/// these logs are handled without emitting an [Event::Reorg].
 pub async fn sync<T>(
     tx_event: mpsc::Sender<Event>,
     transport: T,
     chain: Chain,
     head: Option<StateUpdateLog>,
+    confirmation_depth: u64,
 ) -> anyhow::Result<()>
 where
     T: EthereumTransport + Send + Sync + Clone,
@@ -48,7 +60,7 @@ where
     };
 
     // The core sync logic implementation.
-    sync_impl(eth_api, tx_event, chain).await
+    sync_impl(eth_api, tx_event, chain, confirmation_depth).await
 }
 
 #[cfg_attr(test, mockall::automock)]
@@ -64,6 +76,8 @@ trait EthereumApi {
         &self,
         block: EthereumBlockNumber,
     ) -> anyhow::Result<Option<EthereumBlockHash>>;
+
+    async fn chain_head(&self) -> anyhow::Result<EthereumBlockNumber>;
 }
 
 /// A helper function to keep the backoff strategy construction separated.
@@ -128,6 +142,11 @@ impl<T: EthereumTransport + Send + Sync + Clone> EthereumApi for EthereumImpl<T>
             .await?
             .map(|b| EthereumBlockHash(b.hash.unwrap())))
     }
+
+    async fn chain_head(&self) -> anyhow::Result<EthereumBlockNumber> {
+        let number = self.transport.block_number().await?;
+        Ok(EthereumBlockNumber(number))
+    }
 }
 
 /// Sends [sync events](Event) on its channel.
@@ -169,30 +188,92 @@ impl EventSender {
             .await
             .map_err(|_send_err| ChannelClosedError)
     }
+
+    /// Sends [Event::Heartbeat] on its channel.
+    async fn heartbeat(&self) -> Result<(), ChannelClosedError> {
+        self.0
+            .send(Event::Heartbeat)
+            .await
+            .map_err(|_send_err| ChannelClosedError)
+    }
+
+    /// Sends [Event::PendingConfirmations] on its channel.
+    async fn pending_confirmations(&self, count: u64) -> Result<(), ChannelClosedError> {
+        self.0
+            .send(Event::PendingConfirmations(count))
+            .await
+            .map_err(|_send_err| ChannelClosedError)
+    }
+}
+
+/// Checks whether the L1 block of `log` is still part of the L1 chain.
+async fn is_canonical(eth_api: &impl EthereumApi, log: &StateUpdateLog) -> anyhow::Result<bool> {
+    // We need to query L1 by block number. If we query by hash, this may still exist
+    // but won't be connected to the "main" L1 chain. So instead we query by number and
+    // check if the hash matches ours. It is also possible the block number no longer exists,
+    // in which case this block is also invalid.
+    let block_hash = eth_api
+        .block_hash(log.origin.block.number)
+        .await
+        .context("Fetch block from L1")?;
+
+    Ok(block_hash == Some(log.origin.block.hash))
 }
 
 async fn sync_impl(
     mut eth_api: impl EthereumApi,
     tx_event: mpsc::Sender<Event>,
     chain: Chain,
+    confirmation_depth: u64,
 ) -> anyhow::Result<()> {
     use crate::state::sync::head_poll_interval;
 
     let head_poll_interval = head_poll_interval(chain);
 
     let event_sender = EventSender(tx_event);
+
+    // Logs which were fetched but are not yet confirmed by enough L1 blocks, oldest first.
+    // The fetcher's log head is the newest of these, if there are any.
+    let mut pending = VecDeque::<StateUpdateLog>::new();
+    let mut reported_pending = 0;
+
     loop {
         match eth_api.fetch_logs().await {
             Ok(logs) => {
-                // If empty, then we are at head of chain, sleep a bit and try again.
-                if logs.is_empty() {
-                    tokio::time::sleep(head_poll_interval).await;
-                    continue;
+                pending.extend(logs);
+
+                let confirmed = match confirmation_depth {
+                    0 => pending.len(),
+                    depth => {
+                        let head = eth_api.chain_head().await.context("Fetch L1 head")?;
+                        pending
+                            .iter()
+                            .take_while(|log| log.origin.block.number.0 + depth <= head.0)
+                            .count()
+                    }
+                };
+                let updates = pending.drain(..confirmed).collect::<Vec<_>>();
+
+                if !updates.is_empty() {
+                    // There were confirmed log updates, send the event!
+                    if let Err(_exit) = event_sender.updates(updates).await {
+                        return Ok(());
+                    }
+                }
+
+                if pending.len() as u64 != reported_pending {
+                    reported_pending = pending.len() as u64;
+                    if let Err(_exit) = event_sender.pending_confirmations(reported_pending).await {
+                        return Ok(());
+                    }
                 }
 
-                // There were log updates, send the event!
-                if let Err(_exit) = event_sender.updates(logs).await {
-                    return Ok(());
+                // If nothing was confirmed, then we are at head of chain, sleep a bit and try again.
+                if confirmed == 0 {
+                    if let Err(_exit) = event_sender.heartbeat().await {
+                        return Ok(());
+                    }
+                    tokio::time::sleep(head_poll_interval).await;
                 }
             }
             Err(FetchError::Reorg) => {
@@ -200,61 +281,80 @@ async fn sync_impl(
                 // was no latest log to reorg against. We know that this block already needs to
                 // be reorg'd since it triggered the reorg in the first place.
                 let mut reorg_tail = eth_api.log_head().await.clone().unwrap();
-
-                // Check each Starknet block in reverse history order, until we find a still
-                // valid block. This becomes the new head of our L1 state.
-                let new_head = loop {
-                    // We have reached Starknet genesis, no older blocks to check.
-                    if reorg_tail.block_number == StarknetBlockNumber::GENESIS {
-                        break None;
+                // The oldest Starknet block which has not been sent as an update.
+                let first_pending = pending.front().map(|log| log.block_number);
+
+                // Pending logs were never sent, so if one of them is still valid the reorg
+                // is resolved without emitting a Reorg event. The newest one is the log head.
+                pending.pop_back();
+                let mut new_pending_head = None;
+                while let Some(log) = pending.pop_back() {
+                    if is_canonical(&eth_api, &log).await? {
+                        new_pending_head = Some(log);
+                        break;
                     }
+                    reorg_tail = log;
+                }
 
-                    // Reqeuest the previous Starknet block update.
-                    let update = match event_sender.get_update(reorg_tail.block_number - 1).await {
-                        Ok(update) => update,
-                        Err(_exit) => return Ok(()),
-                    };
-
-                    // It is possible for the database to not contain this update if we only keep a limited history.
-                    // In which case we have to essentially reset to starting from genesis again.
-                    let update = match update {
-                        Some(update) => update,
-                        None => {
+                if let Some(head) = new_pending_head {
+                    pending.push_back(head.clone());
+                    eth_api.set_log_head(Some(head)).await;
+                } else {
+                    // Check each Starknet block in reverse history order, until we find a still
+                    // valid block. This becomes the new head of our L1 state.
+                    let new_head = loop {
+                        // We have reached Starknet genesis, no older blocks to check.
+                        if reorg_tail.block_number == StarknetBlockNumber::GENESIS {
                             break None;
                         }
-                    };
 
-                    // Fetch the L1 block for this Starknet update.
-                    //
-                    // We need to query L1 by block number. If we query by hash, this may still exist
-                    // but won't be connected to the "main" L1 chain. So instead we query by number and
-                    // check if the hash matches ours. It is also possible the block number no longer exists,
-                    // in which case this block is also invalid.
-                    if let Some(block_hash) = eth_api
-                        .block_hash(update.origin.block.number)
-                        .await
-                        .context("Fetch block from L1")?
-                    {
-                        if update.origin.block.hash == block_hash {
+                        // Reqeuest the previous Starknet block update.
+                        let update =
+                            match event_sender.get_update(reorg_tail.block_number - 1).await {
+                                Ok(update) => update,
+                                Err(_exit) => return Ok(()),
+                            };
+
+                        // It is possible for the database to not contain this update if we only keep a limited history.
+                        // In which case we have to essentially reset to starting from genesis again.
+                        let update = match update {
+                            Some(update) => update,
+                            None => {
+                                break None;
+                            }
+                        };
+
+                        // Fetch the L1 block for this Starknet update.
+                        if is_canonical(&eth_api, &update).await? {
                             break Some(update);
                         }
-                    }
-                    // This block no longer exists, update tail and check next block.
-                    reorg_tail = update;
-                };
+                        // This block no longer exists, update tail and check next block.
+                        reorg_tail = update;
+                    };
 
-                let reorg_tail_number = new_head
-                    .as_ref()
-                    .map(|log| log.block_number + 1)
-                    .unwrap_or(StarknetBlockNumber::GENESIS);
+                    let reorg_tail_number = new_head
+                        .as_ref()
+                        .map(|log| log.block_number + 1)
+                        .unwrap_or(StarknetBlockNumber::GENESIS);
+
+                    // Send Reorg event, with the oldest Starknet block which was invalidated by this L1 reorg.
+                    // There is nothing to revert if only pending logs were invalidated.
+                    if Some(reorg_tail_number) != first_pending {
+                        if let Err(_exit) = event_sender.reorg(reorg_tail_number).await {
+                            return Ok(());
+                        }
+                    }
 
-                // Send Reorg event, with the oldest Starknet block which was invalidated by this L1 reorg.
-                if let Err(_exit) = event_sender.reorg(reorg_tail_number).await {
-                    return Ok(());
+                    // Update the Ethereum log fetcher.
+                    eth_api.set_log_head(new_head).await;
                 }
 
-                // Update the Ethereum log fetcher.
-                eth_api.set_log_head(new_head).await;
+                if pending.len() as u64 != reported_pending {
+                    reported_pending = pending.len() as u64;
+                    if let Err(_exit) = event_sender.pending_confirmations(reported_pending).await {
+                        return Ok(());
+                    }
+                }
             }
             // Unreachable provided that `eth_api` implements a retry policy.
             Err(FetchError::Other(other)) => anyhow::bail!(other),
@@ -350,7 +450,7 @@ mod tests {
                 .in_sequence(&mut seq)
                 .return_once(|| mock_output);
 
-            tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli));
+            tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli, 0));
 
             match rx_event.recv().await.unwrap() {
                 Event::Update(recv) => assert_eq!(recv, logs1),
@@ -389,7 +489,7 @@ mod tests {
             mock_fetcher
                 .expect_fetch_logs()
                 .return_once(move || Ok(logs));
-            let handle = tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli));
+            let handle = tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli, 0));
 
             // Wrap this in a timeout so we don't wait forever in case of test failure.
             tokio::time::timeout(Duration::from_secs(2), handle)
@@ -472,7 +572,7 @@ mod tests {
                     .in_sequence(&mut seq)
                     .return_once(move || mock_output);
 
-                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli));
+                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli, 0));
 
                 // Receive first log update event.
                 match rx_event.recv().await.unwrap() {
@@ -560,7 +660,7 @@ mod tests {
                     .in_sequence(&mut seq)
                     .return_once(move || mock_output);
 
-                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli));
+                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli, 0));
 
                 // Receive the first log update event.
                 match rx_event.recv().await.unwrap() {
@@ -644,7 +744,7 @@ mod tests {
                     .in_sequence(&mut seq)
                     .return_once(move || mock_output);
 
-                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli));
+                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli, 0));
 
                 // First log batch event.
                 match rx_event.recv().await.unwrap() {
@@ -678,5 +778,179 @@ mod tests {
                 }
             }
         }
+
+        mod confirmation_depth {
+            use super::*;
+
+            /// A log of Starknet block `number`, emitted in L1 block `l1_block` with hash `l1_hash`.
+            fn log(number: u64, l1_block: u64, l1_hash: u64) -> StateUpdateLog {
+                StateUpdateLog {
+                    origin: EthOrigin {
+                        block: BlockOrigin {
+                            hash: EthereumBlockHash(H256::from_low_u64_be(l1_hash)),
+                            number: EthereumBlockNumber(l1_block),
+                        },
+                        transaction: TransactionOrigin {
+                            hash: EthereumTransactionHash(H256::from_low_u64_be(l1_hash + 1000)),
+                            index: EthereumTransactionIndex(0),
+                        },
+                        log_index: EthereumLogIndex(0),
+                    },
+                    global_root: GlobalRoot(StarkHash::from_hex_str(&l1_hash.to_string()).unwrap()),
+                    block_number: StarknetBlockNumber(number),
+                }
+            }
+
+            /// Mocks an L1 chain which returns the given `fetches` in order, followed by no new logs,
+            /// and whose head is at the given `heads` in order, after which it stays at the last one.
+            fn mock_chain(
+                fetches: Vec<Result<Vec<StateUpdateLog>, FetchError>>,
+                heads: Vec<u64>,
+            ) -> MockEthereumApi {
+                let mut fetches = VecDeque::from(fetches);
+                let last_head = *heads.last().unwrap();
+                let mut heads = VecDeque::from(heads);
+
+                let mut mock_fetcher = MockEthereumApi::new();
+                mock_fetcher
+                    .expect_fetch_logs()
+                    .returning(move || fetches.pop_front().unwrap_or_else(|| Ok(Vec::new())));
+                mock_fetcher.expect_chain_head().returning(move || {
+                    Ok(EthereumBlockNumber(heads.pop_front().unwrap_or(last_head)))
+                });
+                mock_fetcher
+            }
+
+            #[tokio::test(start_paused = true)]
+            async fn reorg_within_depth() {
+                // A reorg which only replaces unconfirmed logs must not be visible to the receiver,
+                // which only gets the logs of the final chain once they are confirmed.
+                let (tx_event, mut rx_event) = mpsc::channel(1);
+
+                let logs = vec![log(0, 100, 1), log(1, 101, 2), log(2, 102, 3)];
+                let reorged = vec![log(1, 101, 12), log(2, 102, 13)];
+
+                let mut mock_fetcher = mock_chain(
+                    vec![
+                        Ok(logs.clone()),
+                        Err(FetchError::Reorg),
+                        Ok(reorged.clone()),
+                    ],
+                    vec![104, 108],
+                );
+                mock_fetcher
+                    .expect_log_head()
+                    .times(1)
+                    .return_const(logs.last().cloned());
+                let canonical = logs[0].origin.block.clone();
+                mock_fetcher.expect_block_hash().returning(move |block| {
+                    if block == canonical.number {
+                        Ok(Some(canonical.hash))
+                    } else {
+                        Ok(Some(EthereumBlockHash(H256::from_low_u64_be(66666))))
+                    }
+                });
+                let new_head = Some(logs[0].clone());
+                mock_fetcher
+                    .expect_set_log_head()
+                    .times(1)
+                    .withf(move |x| x == &new_head)
+                    .return_const(());
+
+                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli, 6));
+
+                // None of the logs are 6 blocks deep yet.
+                match rx_event.recv().await.unwrap() {
+                    Event::PendingConfirmations(count) => assert_eq!(count, 3),
+                    other => panic!("Expected PendingConfirmations event, got {:?}", other),
+                }
+                match rx_event.recv().await.unwrap() {
+                    Event::Heartbeat => {}
+                    other => panic!("Expected Heartbeat event, got {:?}", other),
+                }
+
+                // The reorg is resolved without querying for or reverting stored updates.
+                match rx_event.recv().await.unwrap() {
+                    Event::PendingConfirmations(count) => assert_eq!(count, 1),
+                    other => panic!("Expected PendingConfirmations event, got {:?}", other),
+                }
+
+                // Once the head has advanced, the logs of the new chain are confirmed.
+                let mut expected = vec![logs[0].clone()];
+                expected.extend(reorged);
+                match rx_event.recv().await.unwrap() {
+                    Event::Update(recv) => assert_eq!(recv, expected),
+                    other => panic!("Expected Update event, got {:?}", other),
+                }
+                match rx_event.recv().await.unwrap() {
+                    Event::PendingConfirmations(count) => assert_eq!(count, 0),
+                    other => panic!("Expected PendingConfirmations event, got {:?}", other),
+                }
+                match rx_event.recv().await.unwrap() {
+                    Event::Heartbeat => {}
+                    other => panic!("Expected Heartbeat event, got {:?}", other),
+                }
+            }
+
+            #[tokio::test(start_paused = true)]
+            async fn reorg_beyond_depth() {
+                // A reorg which replaces confirmed logs must still be reverted by the receiver.
+                let (tx_event, mut rx_event) = mpsc::channel(1);
+
+                let logs = vec![log(0, 100, 1), log(1, 101, 2), log(2, 102, 3)];
+
+                let mut mock_fetcher =
+                    mock_chain(vec![Ok(logs.clone()), Err(FetchError::Reorg)], vec![103]);
+                mock_fetcher
+                    .expect_log_head()
+                    .times(1)
+                    .return_const(logs.last().cloned());
+                let canonical = logs[0].origin.block.clone();
+                mock_fetcher.expect_block_hash().returning(move |block| {
+                    if block == canonical.number {
+                        Ok(Some(canonical.hash))
+                    } else {
+                        Ok(Some(EthereumBlockHash(H256::from_low_u64_be(66666))))
+                    }
+                });
+                let new_head = Some(logs[0].clone());
+                mock_fetcher
+                    .expect_set_log_head()
+                    .times(1)
+                    .withf(move |x| x == &new_head)
+                    .return_const(());
+
+                tokio::spawn(sync_impl(mock_fetcher, tx_event, Chain::Goerli, 2));
+
+                // Only the logs which are 2 blocks deep are confirmed.
+                match rx_event.recv().await.unwrap() {
+                    Event::Update(recv) => assert_eq!(recv, logs[..2]),
+                    other => panic!("Expected Update event, got {:?}", other),
+                }
+                match rx_event.recv().await.unwrap() {
+                    Event::PendingConfirmations(count) => assert_eq!(count, 1),
+                    other => panic!("Expected PendingConfirmations event, got {:?}", other),
+                }
+
+                // The confirmed update of block 1 is checked, and found to be invalid.
+                for log in logs[..2].iter().rev() {
+                    match rx_event.recv().await.unwrap() {
+                        Event::QueryUpdate(block, tx) => {
+                            assert_eq!(block, log.block_number);
+                            tx.send(Some(log.clone())).unwrap();
+                        }
+                        other => panic!("Expected QueryUpdate event, got {:?}", other),
+                    }
+                }
+                match rx_event.recv().await.unwrap() {
+                    Event::Reorg(recv_tail) => assert_eq!(recv_tail, StarknetBlockNumber(1)),
+                    other => panic!("Expected Reorg event, got {:?}", other),
+                }
+                match rx_event.recv().await.unwrap() {
+                    Event::PendingConfirmations(count) => assert_eq!(count, 0),
+                    other => panic!("Expected PendingConfirmations event, got {:?}", other),
+                }
+            }
+        }
     }
 }
@@ -5,7 +5,7 @@ use anyhow::Context;
 use tokio::sync::{mpsc, oneshot};
 
 use crate::core::{ClassHash, StarknetBlockHash, StarknetBlockNumber};
-use crate::ethereum::state_update::{ContractUpdate, DeployedContract, StateUpdate, StorageUpdate};
+use crate::ethereum::state_update::StateUpdate;
 use crate::rpc::types::{BlockNumberOrTag, Tag};
 use crate::sequencer::error::SequencerError;
 use crate::sequencer::reply::state_update::{Contract, StateDiff};
@@ -24,14 +24,20 @@ pub struct Timings {
 /// Events and queries emitted by L2 sync process.
 #[derive(Debug)]
 pub enum Event {
-    /// New L2 [block update](StateUpdate) found.
-    Update(Box<Block>, StateUpdate, Timings),
+    /// New L2 [block update](StateUpdate) found, with the block's verbatim gateway JSON if it is
+    /// available.
+    Update(Box<Block>, StateUpdate, Timings, Option<bytes::Bytes>),
     /// An L2 reorg was detected, contains the reorg-tail which
     /// indicates the oldest block which is now invalid
     /// i.e. reorg-tail + 1 should be the new head.
     Reorg(StarknetBlockNumber),
     /// A new unique L2 [contract](CompressedContract) was found.
     NewContract(CompressedContract),
+    /// The definition of a new unique L2 class could not be downloaded, contains the error.
+    ///
+    /// The definition is not required for the state, so it should be retried later instead
+    /// of stalling sync.
+    DeferredContract(ClassHash, String),
     /// Query for the [block hash](StarknetBlockHash) of the given block.
     ///
     /// The receiver should return the [block hash](StarknetBlockHash) using the
@@ -63,9 +69,17 @@ pub async fn sync(
         };
 
         let t_block = std::time::Instant::now();
-        let block = loop {
+        let (block, raw_block) = loop {
             match download_block(next, head_hash, &sequencer).await? {
-                DownloadBlock::Block(block) => break block,
+                DownloadBlock::Block(block, _) if !block.is_final() => {
+                    let poll_interval = head_poll_interval(chain);
+                    tracing::info!(
+                        block=%next.0, status=?block.status, poll_interval=?poll_interval,
+                        "Block is not final yet, retrying later"
+                    );
+                    tokio::time::sleep(poll_interval).await;
+                }
+                DownloadBlock::Block(block, raw_block) => break (block, raw_block),
                 DownloadBlock::AtHead => {
                     let poll_interval = head_poll_interval(chain);
                     tracing::info!(poll_interval=?poll_interval, "At head of chain");
@@ -107,43 +121,7 @@ pub async fn sync(
             .with_context(|| format!("Deploying new contracts for block {:?}", next))?;
         let t_deploy = t_deploy.elapsed();
 
-        // Map from sequencer type to the actual type... we should declutter these types.
-        let deployed_contracts = state_update
-            .state_diff
-            .deployed_contracts
-            .into_iter()
-            .map(|contract| DeployedContract {
-                address: contract.address,
-                hash: contract.contract_hash,
-                call_data: vec![], // todo!("This is missing from sequencer API..."),
-            })
-            .collect::<Vec<_>>();
-
-        let contract_updates = state_update
-            .state_diff
-            .storage_diffs
-            .into_iter()
-            .map(|contract_update| {
-                let storage_updates = contract_update
-                    .1
-                    .into_iter()
-                    .map(|diff| StorageUpdate {
-                        address: diff.key,
-                        value: diff.value,
-                    })
-                    .collect();
-
-                ContractUpdate {
-                    address: contract_update.0,
-                    storage_updates,
-                }
-            })
-            .collect::<Vec<_>>();
-
-        let update = StateUpdate {
-            deployed_contracts,
-            contract_updates,
-        };
+        let update = StateUpdate::from(state_update.state_diff);
 
         head = Some((next, block.block_hash.unwrap()));
 
@@ -154,14 +132,15 @@ pub async fn sync(
         };
 
         tx_event
-            .send(Event::Update(block, update, timings))
+            .send(Event::Update(block, update, timings, Some(raw_block)))
             .await
             .context("Event channel closed")?;
     }
 }
 
 enum DownloadBlock {
-    Block(Box<Block>),
+    /// The parsed block and the gateway's JSON it was parsed from.
+    Block(Box<Block>, bytes::Bytes),
     AtHead,
     Reorg,
 }
@@ -173,10 +152,16 @@ async fn download_block(
 ) -> anyhow::Result<DownloadBlock> {
     use sequencer::error::StarknetErrorCode::BlockNotFound;
 
-    let result = sequencer.block_by_number(block_number.into()).await;
+    // The raw JSON is kept so that the block can be reprocessed if our parsing turns out to
+    // have been lossy.
+    let result = sequencer.raw_block_by_number(block_number.into()).await;
 
     match result {
-        Ok(block) => Ok(DownloadBlock::Block(Box::new(block))),
+        Ok(raw_block) => {
+            let block = serde_json::from_slice::<Block>(&raw_block)
+                .with_context(|| format!("Parse block {} from sequencer", block_number.0))?;
+            Ok(DownloadBlock::Block(Box::new(block), raw_block))
+        }
         Err(SequencerError::StarknetError(err)) if err.code == BlockNotFound => {
             // This would occur if we queried past the head of the chain. We now need to check that
             // a reorg hasn't put us too far in the future. This does run into race conditions with
@@ -190,7 +175,9 @@ async fn download_block(
                 match prev_block_hash {
                     // We are definitely still at the head and it's just that a new block
                     // has not been published yet
-                    Some(parent_block_hash) if parent_block_hash == latest.block_hash.unwrap() => {
+                    Some(parent_block_hash)
+                        if parent_block_hash.0.ct_eq(&latest.block_hash.unwrap().0) =>
+                    {
                         Ok(DownloadBlock::AtHead)
                     }
                     // Our head is not valid anymore so there must have been a reorg only at this height
@@ -239,7 +226,7 @@ async fn reorg(
             .await
             .with_context(|| format!("Download block {} from sequencer", previous_block_number.0))?
         {
-            DownloadBlock::Block(block) if block.block_hash.unwrap() == previous_hash => {
+            DownloadBlock::Block(block, _) if block.block_hash.unwrap() == previous_hash => {
                 break Some((previous_block_number, previous_hash));
             }
             _ => {}
@@ -309,19 +296,37 @@ async fn deploy_contracts(
             .find(|contract| contract.contract_hash == contract_hash)
             .unwrap();
 
-        let contract = download_and_compress_contract(contract, sequencer)
-            .await
-            .with_context(|| format!("Download and compress contract {:?}", contract.address))?;
+        let event = match download_and_compress_contract(contract, sequencer).await {
+            Ok(contract) => Event::NewContract(contract),
+            Err(e) if is_transient(&e) => {
+                tracing::warn!(
+                    contract=%contract.address.0, class_hash=%contract_hash.0, reason=?e,
+                    "Downloading contract failed, deferring it"
+                );
+                Event::DeferredContract(contract_hash, format!("{:#}", e))
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Download and compress contract {:?}", contract.address)
+                })
+            }
+        };
 
-        tx_event
-            .send(Event::NewContract(contract))
-            .await
-            .context("Event channel closed")?;
+        tx_event.send(event).await.context("Event channel closed")?;
     }
 
     Ok(())
 }
 
+/// Returns `true` if the download failed because the gateway could not be reached, which a
+/// later retry may fix. An invalid definition or a class hash mismatch is not transient.
+fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<SequencerError>(),
+        Some(SequencerError::Timeout(_) | SequencerError::ReqwestError(_))
+    )
+}
+
 async fn download_and_compress_contract(
     contract: &Contract,
     sequencer: &impl sequencer::ClientApi,
@@ -343,7 +348,7 @@ async fn download_and_compress_contract(
 
     // Sanity check.
     anyhow::ensure!(
-        contract.contract_hash == hash,
+        contract.contract_hash.0.ct_eq(&hash.0),
         "Class hash mismatch for contract {:?}",
         contract.address
     );
@@ -656,11 +661,13 @@ mod tests {
             block_number: StarknetBlockNumber,
             returned_result: Result<reply::Block, SequencerError>,
         ) {
-            mock.expect_block_by_number()
+            mock.expect_raw_block_by_number()
                 .withf(move |x| x == &BlockNumberOrTag::Number(block_number))
                 .times(1)
                 .in_sequence(seq)
-                .return_once(move |_| returned_result);
+                .return_once(move |_| {
+                    returned_result.map(|block| serde_json::to_vec(&block).unwrap().into())
+                });
         }
 
         /// Convenience wrapper
@@ -762,7 +769,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block,state_update,_) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0);
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE0);
                 });
@@ -778,7 +785,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT1_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block,mut state_update,_) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _, _) => {
                     assert_eq!(*block, *BLOCK1);
                     state_update.contract_updates.sort();
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE1);
@@ -827,7 +834,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT1_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block,mut state_update,_) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _, _) => {
                     assert_eq!(*block, *BLOCK1);
                     assert_eq!(state_update.deployed_contracts, vec![
                         state::sync::DeployedContract {
@@ -841,6 +848,137 @@ mod tests {
             }
         }
 
+        mod deferred_contract {
+            use super::*;
+            use crate::ethereum::Chain;
+            use pretty_assertions::assert_eq;
+
+            #[tokio::test]
+            async fn failed_download_does_not_block_sync() {
+                let (tx_event, mut rx_event) = tokio::sync::mpsc::channel(1);
+                let mut mock = MockClientApi::new();
+                let mut seq = mockall::Sequence::new();
+
+                // The genesis block's contract fails to download
+                expect_block(&mut mock, &mut seq, BLOCK0_NUMBER, Ok(BLOCK0.clone()));
+                expect_state_update(&mut mock, &mut seq, *BLOCK0_HASH, Ok(STATE_UPDATE0.clone()));
+                expect_full_contract(
+                    &mut mock,
+                    &mut seq,
+                    *CONTRACT0_ADDR,
+                    Err(SequencerError::ReqwestError(
+                        reqwest::Client::new().get("not a url").build().unwrap_err(),
+                    )),
+                );
+                // Stay at head, no more blocks available
+                expect_block(&mut mock, &mut seq, BLOCK1_NUMBER, Err(block_not_found()));
+                expect_latest_block(&mut mock, &mut seq, Ok(BLOCK0.clone()));
+
+                // Let's run the UUT
+                let _jh = tokio::spawn(sync(tx_event, mock, None, Chain::Goerli));
+
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryContractExistance(contract_hashes, sender) => {
+                    assert_eq!(contract_hashes, vec![*CONTRACT0_HASH]);
+                    sender.send(vec![false]).unwrap();
+                });
+                assert_matches!(rx_event.recv().await.unwrap(), Event::DeferredContract(hash, _) => {
+                    assert_eq!(hash, *CONTRACT0_HASH);
+                });
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
+                    assert_eq!(*block, *BLOCK0);
+                    assert_eq!(state_update, *EXPECTED_STATE_UPDATE0);
+                });
+            }
+
+            #[tokio::test]
+            async fn class_hash_mismatch_is_not_deferred() {
+                let (tx_event, mut rx_event) = tokio::sync::mpsc::channel(1);
+                let mut mock = MockClientApi::new();
+                let mut seq = mockall::Sequence::new();
+
+                // The genesis block's contract has the definition of another class
+                expect_block(&mut mock, &mut seq, BLOCK0_NUMBER, Ok(BLOCK0.clone()));
+                expect_state_update(&mut mock, &mut seq, *BLOCK0_HASH, Ok(STATE_UPDATE0.clone()));
+                expect_full_contract(
+                    &mut mock,
+                    &mut seq,
+                    *CONTRACT0_ADDR,
+                    Ok(CONTRACT1_DEF.clone()),
+                );
+
+                // Let's run the UUT
+                let jh = tokio::spawn(sync(tx_event, mock, None, Chain::Goerli));
+
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryContractExistance(contract_hashes, sender) => {
+                    assert_eq!(contract_hashes, vec![*CONTRACT0_HASH]);
+                    sender.send(vec![false]).unwrap();
+                });
+
+                let error = jh.await.unwrap().unwrap_err();
+                assert!(format!("{:#}", error).contains("Class hash mismatch"));
+                assert!(rx_event.recv().await.is_none());
+            }
+        }
+
+        mod non_final_block {
+            use super::*;
+            use crate::ethereum::Chain;
+            use pretty_assertions::assert_eq;
+
+            #[tokio::test(start_paused = true)]
+            async fn is_retried_until_accepted() {
+                let (tx_event, mut rx_event) = tokio::sync::mpsc::channel(1);
+                let mut mock = MockClientApi::new();
+                let mut seq = mockall::Sequence::new();
+
+                // Block #1 is first served as pending, and later on as accepted.
+                let pending_block1 = reply::Block {
+                    status: reply::Status::Pending,
+                    ..BLOCK1.clone()
+                };
+                let received_block1 = reply::Block {
+                    status: reply::Status::Received,
+                    ..BLOCK1.clone()
+                };
+                expect_block(&mut mock, &mut seq, BLOCK1_NUMBER, Ok(pending_block1));
+                expect_block(&mut mock, &mut seq, BLOCK1_NUMBER, Ok(received_block1));
+                // The state update must only be requested once the block is final.
+                expect_block(&mut mock, &mut seq, BLOCK1_NUMBER, Ok(BLOCK1.clone()));
+                expect_state_update(&mut mock, &mut seq, *BLOCK1_HASH, Ok(STATE_UPDATE1.clone()));
+                expect_full_contract(
+                    &mut mock,
+                    &mut seq,
+                    *CONTRACT1_ADDR,
+                    Ok(CONTRACT1_DEF.clone()),
+                );
+
+                // Stay at head, no more blocks available
+                expect_block(&mut mock, &mut seq, BLOCK2_NUMBER, Err(block_not_found()));
+                expect_latest_block(&mut mock, &mut seq, Ok(BLOCK1.clone()));
+
+                // Let's run the UUT
+                let _jh = tokio::spawn(sync(
+                    tx_event,
+                    mock,
+                    Some((BLOCK0_NUMBER, *BLOCK0_HASH)),
+                    Chain::Goerli,
+                ));
+
+                // No update may be emitted for the non-final versions of block #1.
+                assert_matches!(rx_event.recv().await.unwrap(), Event::QueryContractExistance(contract_hashes, sender) => {
+                    assert_eq!(contract_hashes, vec![*CONTRACT1_HASH]);
+                    sender.send(vec![false]).unwrap();
+                });
+                assert_matches!(rx_event.recv().await.unwrap(), Event::NewContract(compressed_contract) => {
+                    assert_eq!(compressed_contract.hash, *CONTRACT1_HASH);
+                });
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, _, _, _) => {
+                    assert_eq!(*block, *BLOCK1);
+                    assert!(block.is_final());
+                });
+            }
+        }
+
         mod reorg {
             use super::*;
             use crate::ethereum::Chain;
@@ -916,7 +1054,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0);
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE0);
                 });
@@ -936,7 +1074,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH_V2);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0_V2);
                     assert_eq!(state_update.deployed_contracts, vec![
                         state::sync::DeployedContract {
@@ -1056,7 +1194,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0);
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE0);
                 });
@@ -1072,7 +1210,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT1_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _, _) => {
                     assert_eq!(*block, *BLOCK1);
                     assert_eq!(state_update.deployed_contracts, vec![
                         state::sync::DeployedContract {
@@ -1083,7 +1221,7 @@ mod tests {
                     state_update.contract_updates.sort();
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE1);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
@@ -1112,7 +1250,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH_V2);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0_V2);
                     assert_eq!(state_update.deployed_contracts, vec![
                         state::sync::DeployedContract {
@@ -1122,7 +1260,7 @@ mod tests {
                     }]);
                     assert!(state_update.contract_updates.is_empty());
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, block1_v2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
@@ -1262,7 +1400,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0);
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE0);
                 });
@@ -1278,7 +1416,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT1_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _, _) => {
                     assert_eq!(*block, *BLOCK1);
                     assert_eq!(state_update.deployed_contracts, vec![
                         state::sync::DeployedContract {
@@ -1289,12 +1427,12 @@ mod tests {
                     state_update.contract_updates.sort();
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE1);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, block3);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
@@ -1315,12 +1453,12 @@ mod tests {
                 assert_matches!(rx_event.recv().await.unwrap(), Event::Reorg(tail) => {
                     assert_eq!(tail, BLOCK1_NUMBER);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, block1_v2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, block2_v2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
@@ -1419,7 +1557,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0);
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE0);
                 });
@@ -1435,7 +1573,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT1_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _, _) => {
                     assert_eq!(*block, *BLOCK1);
                     assert_eq!(state_update.deployed_contracts, vec![
                         state::sync::DeployedContract {
@@ -1446,7 +1584,7 @@ mod tests {
                     state_update.contract_updates.sort();
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE1);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
@@ -1459,7 +1597,7 @@ mod tests {
                 assert_matches!(rx_event.recv().await.unwrap(), Event::Reorg(tail) => {
                     assert_eq!(tail, BLOCK2_NUMBER);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, block2_v2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
@@ -1567,7 +1705,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT0_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, *BLOCK0);
                     assert_eq!(state_update, *EXPECTED_STATE_UPDATE0);
                 });
@@ -1583,7 +1721,7 @@ mod tests {
                         assert_eq!(compressed_contract.definition[..4], zstd_magic);
                         assert_eq!(compressed_contract.hash, *CONTRACT1_HASH);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, mut state_update, _, _) => {
                     assert_eq!(*block, *BLOCK1);
                     assert_eq!(state_update.deployed_contracts, vec![
                         state::sync::DeployedContract {
@@ -1602,12 +1740,12 @@ mod tests {
                 assert_matches!(rx_event.recv().await.unwrap(), Event::Reorg(tail) => {
                     assert_eq!(tail, BLOCK1_NUMBER);
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, block1_v2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
                 });
-                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _) => {
+                assert_matches!(rx_event.recv().await.unwrap(), Event::Update(block, state_update, _, _) => {
                     assert_eq!(*block, block2);
                     assert!(state_update.deployed_contracts.is_empty());
                     assert!(state_update.contract_updates.is_empty());
@@ -0,0 +1,238 @@
+//! Retries the downloads which failed transiently during sync, but which are not required for
+//! the state and were therefore deferred to the [SyncRetryQueueTable].
+//!
+//! Downloads which are required for the state, i.e. blocks and state updates, still block sync
+//! until they succeed. Classes are retried by the [class backfill](backfill_class), which also
+//! gives up on them after [MAX_ATTEMPTS](crate::state::class_backfill::MAX_ATTEMPTS).
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use rusqlite::Connection;
+
+use crate::{
+    core::ClassHash,
+    sequencer,
+    state::class_backfill::{backfill_class, Outcome},
+    storage::{ContractCodeTable, RetryEntry, RetryItem, Storage, SyncRetryQueueTable},
+};
+
+/// The number of due items read from storage at a time.
+const BATCH_SIZE: usize = 100;
+
+/// Returns the current unix time in seconds.
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Stores the class without its definition and queues the definition for a retry.
+pub(crate) fn defer_class(
+    connection: &mut Connection,
+    hash: ClassHash,
+    error: &str,
+    now: u64,
+) -> anyhow::Result<RetryEntry> {
+    let transaction = connection
+        .transaction()
+        .context("Create database transaction")?;
+
+    ContractCodeTable::insert_without_definition(&transaction, hash)
+        .context("Insert class hash")?;
+    let entry =
+        SyncRetryQueueTable::record_failure(&transaction, RetryItem::Class(hash), error, now)
+            .context("Queue class for retry")?;
+
+    transaction
+        .commit()
+        .context("Commit database transaction")?;
+
+    Ok(entry)
+}
+
+/// Retries the queued downloads as they become due, polling the queue every `poll_interval`.
+///
+/// Never returns unless the database fails.
+pub async fn drain(
+    storage: Storage,
+    sequencer: impl sequencer::ClientApi,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    loop {
+        let completed = drain_due(&storage, &sequencer, unix_now()).await?;
+        if completed > 0 {
+            tracing::info!(%completed, "Completed deferred downloads");
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Retries the downloads which are due at unix time `now`, and returns the number which
+/// succeeded.
+async fn drain_due(
+    storage: &Storage,
+    sequencer: &impl sequencer::ClientApi,
+    now: u64,
+) -> anyhow::Result<usize> {
+    let s = storage.clone();
+    let due = tokio::task::spawn_blocking(move || {
+        let connection = s.connection()?;
+        SyncRetryQueueTable::due(&connection, now, BATCH_SIZE)
+    })
+    .await
+    .context("Query due retries")??;
+
+    let mut completed = 0;
+    for entry in due {
+        match entry.item {
+            RetryItem::Class(hash) => match backfill_class(storage, sequencer, hash, now).await? {
+                Outcome::Stored => {
+                    tracing::debug!(class_hash=%hash.0, attempts=%entry.attempts, "Downloaded deferred class definition");
+                    completed += 1;
+                }
+                Outcome::Rejected(reason) => {
+                    tracing::warn!(class_hash=%hash.0, %reason, "Gave up on deferred class definition");
+                }
+                Outcome::Deferred(entry) => {
+                    tracing::warn!(class_hash=%hash.0, attempts=%entry.attempts, reason=%entry.last_error, "Downloading deferred class definition failed");
+                }
+            },
+        }
+    }
+
+    Ok(completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use stark_hash::StarkHash;
+
+    use crate::{
+        core::{ClassHash, ContractAddress},
+        sequencer::{error::SequencerError, MockClientApi},
+        state::class_backfill::MAX_ATTEMPTS,
+        storage::{ClassBackfillTable, ContractsTable, RetryItem, Storage, SyncRetryQueueTable},
+    };
+
+    /// Defers the class as sync does, and deploys a contract of the class regardless.
+    fn deferred(hash: ClassHash) -> (Storage, Connection) {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let entry = super::defer_class(&mut connection, hash, "gateway error", 0).unwrap();
+        assert_eq!(entry.attempts, 1);
+
+        let transaction = connection.transaction().unwrap();
+        ContractsTable::upsert(
+            &transaction,
+            ContractAddress(StarkHash::from_hex_str("abc").unwrap()),
+            hash,
+        )
+        .unwrap();
+        transaction.commit().unwrap();
+
+        (storage, connection)
+    }
+
+    fn unreachable_gateway() -> SequencerError {
+        SequencerError::ReqwestError(reqwest::Client::new().get("not a url").build().unwrap_err())
+    }
+
+    #[tokio::test]
+    async fn failing_class_is_retried_until_it_succeeds() {
+        let definition = bytes::Bytes::from(
+            zstd::decode_all(&include_bytes!("../../fixtures/contract_definition.json.zst")[..])
+                .unwrap(),
+        );
+        let hash = ClassHash(
+            StarkHash::from_hex_str(
+                "050b2148c0d782914e0b12a1a32abe5e398930b7e914f82c65cb7afce0a0ab9b",
+            )
+            .unwrap(),
+        );
+
+        let (storage, connection) = deferred(hash);
+
+        // The gateway fails another four times, before succeeding.
+        let mut sequencer = MockClientApi::new();
+        let mut seq = mockall::Sequence::new();
+        sequencer
+            .expect_class_by_hash()
+            .withf(move |x| x == &hash)
+            .times(4)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(unreachable_gateway()));
+        let returned = definition.clone();
+        sequencer
+            .expect_class_by_hash()
+            .withf(move |x| x == &hash)
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_once(move |_| Ok(returned));
+
+        // Nothing is due before the retry delay has passed.
+        let completed = super::drain_due(&storage, &sequencer, 0).await.unwrap();
+        assert_eq!(completed, 0);
+
+        let mut now = 0;
+        for attempts in 2..=5 {
+            now = SyncRetryQueueTable::all(&connection).unwrap()[0].next_attempt_at;
+            let completed = super::drain_due(&storage, &sequencer, now).await.unwrap();
+            assert_eq!(completed, 0);
+
+            let queue = SyncRetryQueueTable::all(&connection).unwrap();
+            assert_eq!(queue.len(), 1);
+            assert_eq!(queue[0].item, RetryItem::Class(hash));
+            assert_eq!(queue[0].attempts, attempts);
+            assert_eq!(
+                queue[0].next_attempt_at,
+                now + SyncRetryQueueTable::retry_delay(attempts).as_secs()
+            );
+        }
+
+        now = SyncRetryQueueTable::all(&connection).unwrap()[0].next_attempt_at;
+        let completed = super::drain_due(&storage, &sequencer, now).await.unwrap();
+        assert_eq!(completed, 1);
+        assert!(SyncRetryQueueTable::all(&connection).unwrap().is_empty());
+
+        let stored: Vec<u8> = connection
+            .query_row(
+                "SELECT definition FROM contract_code WHERE hash = ?",
+                [&hash.0.to_be_bytes()[..]],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(zstd::decode_all(&stored[..]).unwrap(), definition);
+    }
+
+    #[tokio::test]
+    async fn class_is_given_up_after_max_attempts() {
+        let hash = ClassHash(StarkHash::from_hex_str("123").unwrap());
+        let (storage, connection) = deferred(hash);
+
+        let mut sequencer = MockClientApi::new();
+        sequencer
+            .expect_class_by_hash()
+            .withf(move |x| x == &hash)
+            .times(MAX_ATTEMPTS as usize - 1)
+            .returning(|_| Err(unreachable_gateway()));
+
+        for _ in 1..MAX_ATTEMPTS {
+            let now = SyncRetryQueueTable::all(&connection).unwrap()[0].next_attempt_at;
+            let completed = super::drain_due(&storage, &sequencer, now).await.unwrap();
+            assert_eq!(completed, 0);
+        }
+
+        // The class is no longer retried, neither by the queue nor by the class backfill.
+        assert!(SyncRetryQueueTable::all(&connection).unwrap().is_empty());
+        let failures = ClassBackfillTable::failures(&connection).unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, hash);
+        assert!(ClassBackfillTable::missing(&connection, None, u64::MAX, 10)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+//! Computes the transaction commitment of a block, as done by [cairo-lang][cairo-commitment],
+//! along with proofs that a transaction is included in it.
+//!
+//! The commitment is the root of a Merkle-Patricia tree of height 64, which maps the index of
+//! each transaction in the block to `h(transaction_hash, h(signature))`, where `h(signature)` is
+//! the [HashChain] over the transaction's signature.
+//!
+//! Such a tree has the same nodes as a [MerkleTree] of height 251 holding the same leaves, apart
+//! from the leading 187 bits of the latter's root edge which all keys share. It is therefore built
+//! using [MerkleTree], and those bits are dropped from its proofs.
+//!
+//! [cairo-commitment]: https://github.com/starkware-libs/cairo-lang/blob/v0.9.1/src/starkware/starknet/business_logic/state/state_api_objects.py
+
+use anyhow::Context;
+use bitvec::prelude::{BitView, Msb0};
+use stark_hash::{stark_hash, StarkHash};
+
+use crate::{
+    sequencer::reply::transaction::Transaction,
+    state::{
+        class_hash::HashChain,
+        merkle_tree::{verify_proof, MerkleTree, ProofNode},
+    },
+};
+
+/// Height of the transaction commitment tree.
+const HEIGHT: usize = 64;
+
+/// The number of leading bits shared by all keys of a height 64 tree stored in a [MerkleTree].
+const PREFIX: usize = 251 - HEIGHT;
+
+/// The transaction commitment tree of a block.
+pub struct TransactionCommitmentTree {
+    tree: MerkleTree<()>,
+    len: u64,
+}
+
+impl TransactionCommitmentTree {
+    /// Builds the tree of the block's `transactions`, which must be in the order of the block.
+    pub fn new(transactions: &[Transaction]) -> anyhow::Result<Self> {
+        let mut tree = MerkleTree::<()>::default();
+        for (index, transaction) in transactions.iter().enumerate() {
+            tree.set(key(index as u64), leaf(transaction))
+                .context("Set transaction commitment leaf")?;
+        }
+        tree.root_hash()
+            .context("Calculate transaction commitment tree hashes")?;
+
+        Ok(Self {
+            tree,
+            len: transactions.len() as u64,
+        })
+    }
+
+    /// The transaction commitment of the block, which is zero for a block without transactions.
+    pub fn commitment(&self) -> anyhow::Result<StarkHash> {
+        if self.len == 0 {
+            return Ok(StarkHash::ZERO);
+        }
+
+        // A non-empty tree always has a root node once the shared prefix is removed.
+        let proof = self.get_proof(0)?;
+        Ok(proof[0].hash())
+    }
+
+    /// Returns the proof of the transaction at `index`, see [verify_inclusion].
+    pub fn get_proof(&self, index: u64) -> anyhow::Result<Vec<ProofNode>> {
+        anyhow::ensure!(
+            index < self.len,
+            "Transaction index {} is out of range",
+            index
+        );
+
+        let mut proof = self
+            .tree
+            .get_proof(key(index))
+            .context("Get transaction commitment proof")?;
+
+        // The root edge starts with the prefix, as it is shared by all keys and edges are
+        // maximal. It is dropped entirely if it consists only of the prefix.
+        match proof.first_mut() {
+            Some(ProofNode::Edge { path, .. }) if path.len() > PREFIX => {
+                let trimmed = path[PREFIX..].to_bitvec();
+                *path = trimmed;
+            }
+            Some(ProofNode::Edge { path, .. }) if path.len() == PREFIX => {
+                proof.remove(0);
+            }
+            _ => anyhow::bail!("Transaction commitment tree has no root edge"),
+        }
+
+        Ok(proof)
+    }
+}
+
+/// The leaf of `transaction` in the transaction commitment tree.
+pub fn leaf(transaction: &Transaction) -> StarkHash {
+    let mut signature = HashChain::default();
+    transaction
+        .signature
+        .iter()
+        .flatten()
+        .for_each(|element| signature.update(element.0));
+
+    stark_hash(transaction.transaction_hash.0, signature.finalize())
+}
+
+/// Verifies that the transaction at `index` with the given `leaf` is included in the block with
+/// the given transaction `commitment`, using a proof from [TransactionCommitmentTree::get_proof].
+pub fn verify_inclusion(
+    commitment: StarkHash,
+    index: u64,
+    leaf: StarkHash,
+    proof: &[ProofNode],
+) -> bool {
+    let index = index.to_be_bytes();
+    verify_proof(commitment, index.view_bits::<Msb0>(), leaf, proof)
+}
+
+fn key(index: u64) -> StarkHash {
+    StarkHash::from_be_slice(&index.to_be_bytes()).expect("64 bits fit into a field element")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequencer::reply::Block;
+
+    fn block_200k() -> Block {
+        serde_json::from_str(include_str!("../../fixtures/sequencer/block_200k.json")).unwrap()
+    }
+
+    #[test]
+    fn empty() {
+        let tree = TransactionCommitmentTree::new(&[]).unwrap();
+        assert_eq!(tree.commitment().unwrap(), StarkHash::ZERO);
+        tree.get_proof(0).unwrap_err();
+    }
+
+    #[test]
+    fn single_transaction() {
+        let transaction = block_200k().transactions[0].clone();
+        let tree = TransactionCommitmentTree::new(&[transaction.clone()]).unwrap();
+
+        // The root is the edge along the 64 bits of index zero.
+        let commitment = tree.commitment().unwrap();
+        let path = 0u64.to_be_bytes().view_bits::<Msb0>().to_bitvec();
+        let expected = ProofNode::Edge {
+            child: leaf(&transaction),
+            path,
+        };
+        assert_eq!(tree.get_proof(0).unwrap(), vec![expected.clone()]);
+        assert_eq!(commitment, expected.hash());
+    }
+
+    #[test]
+    fn every_transaction_is_proven() {
+        let transactions = block_200k().transactions;
+        assert!(transactions.len() > 1);
+        let tree = TransactionCommitmentTree::new(&transactions).unwrap();
+        let commitment = tree.commitment().unwrap();
+
+        for (index, transaction) in transactions.iter().enumerate() {
+            let index = index as u64;
+            let proof = tree.get_proof(index).unwrap();
+            assert!(verify_inclusion(
+                commitment,
+                index,
+                leaf(transaction),
+                &proof
+            ));
+        }
+        tree.get_proof(transactions.len() as u64).unwrap_err();
+    }
+
+    #[test]
+    fn tampered_proof_is_rejected() {
+        let transactions = block_200k().transactions;
+        let tree = TransactionCommitmentTree::new(&transactions).unwrap();
+        let commitment = tree.commitment().unwrap();
+        let leaf = leaf(&transactions[1]);
+        let proof = tree.get_proof(1).unwrap();
+
+        assert!(!verify_inclusion(commitment, 0, leaf, &proof));
+        assert!(!verify_inclusion(commitment, 1, StarkHash::ZERO, &proof));
+        assert!(!verify_inclusion(StarkHash::ZERO, 1, leaf, &proof));
+
+        let mut tampered = proof;
+        match &mut tampered[0] {
+            ProofNode::Binary { left, .. } => *left = StarkHash::ZERO,
+            ProofNode::Edge { child, .. } => *child = StarkHash::ZERO,
+        }
+        assert!(!verify_inclusion(commitment, 1, leaf, &tampered));
+    }
+
+    #[test]
+    fn commitment_of_block_200k() {
+        let block = block_200k();
+        let tree = TransactionCommitmentTree::new(&block.transactions).unwrap();
+        let commitment = tree.commitment().unwrap();
+
+        let expected = StarkHash::from_hex_str(
+            "0x3b68c2b8ea1cdbbbc13c9d68e94a5c4ce812a9d953dfd095c6f534aaf63c80d",
+        )
+        .unwrap();
+        assert_eq!(commitment, expected);
+
+        // The block hash commits to the transaction commitment, which confirms the value above.
+        // The event commitment is taken as given here, as this module does not compute it.
+        let event_commitment = StarkHash::from_hex_str(
+            "0x65a0673b893fb685e9d79ce6e064d2efa461f9fd2d4c0f080095ec441ae534f",
+        )
+        .unwrap();
+        let number = |n: usize| StarkHash::from_be_slice(&n.to_be_bytes()).unwrap();
+        let event_count = block
+            .transaction_receipts
+            .iter()
+            .map(|receipt| receipt.events.len())
+            .sum();
+
+        let mut block_hash = HashChain::default();
+        block_hash.update(number(block.block_number.unwrap().0 as usize));
+        block_hash.update(block.state_root.unwrap().0);
+        block_hash.update(block.sequencer_address.unwrap().0);
+        block_hash.update(number(block.timestamp.0 as usize));
+        block_hash.update(number(block.transactions.len()));
+        block_hash.update(commitment);
+        block_hash.update(number(event_count));
+        block_hash.update(event_commitment);
+        block_hash.update(StarkHash::ZERO);
+        block_hash.update(StarkHash::ZERO);
+        block_hash.update(block.parent_block_hash.0);
+        assert_eq!(block_hash.finalize(), block.block_hash.unwrap().0);
+
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let index = index as u64;
+            let proof = tree.get_proof(index).unwrap();
+            assert!(verify_inclusion(expected, index, leaf(transaction), &proof));
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Computes the hashes of L2 transactions, as done by [cairo-lang][cairo-tx-hash].
+//!
+//! Every transaction hash is a hash chain over a type specific prefix and the transaction's
+//! fields, which binds it to the chain it was sent to:
+//!
+//! `h(prefix, version, contract_address, entry_point_selector, h(calldata), max_fee, chain_id, ...)`
+//!
+//! where `h` is the [HashChain] construction also used for class hashes. Transactions predating
+//! the introduction of `version` and `max_fee` were hashed without them, see
+//! [compute_legacy_transaction_hash].
+//!
+//! [cairo-tx-hash]: https://github.com/starkware-libs/cairo-lang/blob/v0.9.1/src/starkware/starknet/core/os/transaction_hash/transaction_hash.py
+
+use anyhow::Context;
+use stark_hash::StarkHash;
+
+use crate::{
+    core::{ContractAddress, EntryPoint, Fee, StarknetTransactionHash},
+    ethereum::Chain,
+    sequencer::reply::transaction::{EntryPointType, Transaction, Type},
+    state::class_hash::HashChain,
+};
+
+/// `starknet_keccak(b"constructor")`, the selector of a deployed contract's constructor.
+const CONSTRUCTOR_SELECTOR: &str =
+    "028ffe4ff0f226a9107253e17a904099aa4f63a02a5621de0576e5aa71bc5194";
+
+/// The only transaction version supported by [compute_transaction_hash].
+const VERSION: StarkHash = StarkHash::ZERO;
+
+/// Computes the hash of `transaction` when sent on `chain`.
+///
+/// Covers invoke, deploy, declare and L1 handler transactions. L1 handlers are hashed over the
+/// nonce of the L1 to L2 message which they handle, and fail without one.
+pub fn compute_transaction_hash(
+    transaction: &Transaction,
+    chain: Chain,
+) -> anyhow::Result<StarknetTransactionHash> {
+    let chain_id = chain_id(chain);
+
+    let hash = match (transaction.r#type, transaction.entry_point_type) {
+        (Type::InvokeFunction, Some(EntryPointType::L1Handler)) => {
+            let nonce = transaction.nonce.context("L1 handler nonce is missing")?;
+            hash_elements(&[
+                prefix(b"l1_handler"),
+                VERSION,
+                transaction.contract_address.0,
+                selector(transaction)?.0,
+                hash_chain(transaction.calldata.iter().flatten().map(|param| param.0)),
+                StarkHash::ZERO,
+                chain_id,
+                nonce.0,
+            ])
+        }
+        (Type::InvokeFunction, _) => hash_elements(&[
+            prefix(b"invoke"),
+            VERSION,
+            transaction.contract_address.0,
+            selector(transaction)?.0,
+            hash_chain(transaction.calldata.iter().flatten().map(|param| param.0)),
+            max_fee(transaction.max_fee),
+            chain_id,
+        ]),
+        (Type::Deploy, _) => hash_elements(&[
+            prefix(b"deploy"),
+            VERSION,
+            transaction.contract_address.0,
+            constructor_selector(),
+            hash_chain(
+                transaction
+                    .constructor_calldata
+                    .iter()
+                    .flatten()
+                    .map(|param| param.0),
+            ),
+            StarkHash::ZERO,
+            chain_id,
+        ]),
+        // Version 0 declarations carry the class hash as additional data, rather than calldata.
+        (Type::Declare, _) => hash_elements(&[
+            prefix(b"declare"),
+            VERSION,
+            sender_address(transaction)?.0,
+            StarkHash::ZERO,
+            hash_chain(std::iter::empty()),
+            max_fee(transaction.max_fee),
+            chain_id,
+            transaction
+                .class_hash
+                .context("Declared class hash is missing")?
+                .0,
+        ]),
+    };
+
+    Ok(StarknetTransactionHash(hash))
+}
+
+/// Computes the hash of `transaction` as done before transactions had a version and fee, which
+/// is what the oldest blocks of each chain commit to.
+///
+/// Only invoke and deploy transactions were available at the time.
+pub fn compute_legacy_transaction_hash(
+    transaction: &Transaction,
+    chain: Chain,
+) -> anyhow::Result<StarknetTransactionHash> {
+    let hash = match transaction.r#type {
+        Type::InvokeFunction => hash_elements(&[
+            prefix(b"invoke"),
+            transaction.contract_address.0,
+            selector(transaction)?.0,
+            hash_chain(transaction.calldata.iter().flatten().map(|param| param.0)),
+            chain_id(chain),
+        ]),
+        Type::Deploy => hash_elements(&[
+            prefix(b"deploy"),
+            transaction.contract_address.0,
+            constructor_selector(),
+            hash_chain(
+                transaction
+                    .constructor_calldata
+                    .iter()
+                    .flatten()
+                    .map(|param| param.0),
+            ),
+            chain_id(chain),
+        ]),
+        Type::Declare => anyhow::bail!("Declare transactions have no legacy hash"),
+    };
+
+    Ok(StarknetTransactionHash(hash))
+}
+
+fn chain_id(chain: Chain) -> StarkHash {
+    match chain {
+        Chain::Mainnet => prefix(b"SN_MAIN"),
+        Chain::Goerli => prefix(b"SN_GOERLI"),
+    }
+}
+
+/// Short ASCII strings are encoded as big-endian field elements.
+fn prefix(ascii: &[u8]) -> StarkHash {
+    StarkHash::from_be_slice(ascii).expect("Short string fits into a field element")
+}
+
+fn constructor_selector() -> StarkHash {
+    StarkHash::from_hex_str(CONSTRUCTOR_SELECTOR).expect("Constructor selector is valid")
+}
+
+fn selector(transaction: &Transaction) -> anyhow::Result<EntryPoint> {
+    transaction
+        .entry_point_selector
+        .context("Entry point selector is missing")
+}
+
+fn sender_address(transaction: &Transaction) -> anyhow::Result<ContractAddress> {
+    transaction
+        .sender_address
+        .context("Sender address is missing")
+}
+
+fn max_fee(fee: Option<Fee>) -> StarkHash {
+    fee.map(|fee| {
+        let mut bytes = [0u8; 32];
+        bytes[16..].copy_from_slice(fee.0.as_bytes());
+        StarkHash::from_be_bytes(bytes).expect("128 bits fit into a field element")
+    })
+    .unwrap_or(StarkHash::ZERO)
+}
+
+fn hash_chain(elements: impl Iterator<Item = StarkHash>) -> StarkHash {
+    let mut chain = HashChain::default();
+    elements.for_each(|element| chain.update(element));
+    chain.finalize()
+}
+
+fn hash_elements(elements: &[StarkHash]) -> StarkHash {
+    hash_chain(elements.iter().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ClassHash, TransactionNonce};
+    use crate::sequencer::reply::Block;
+
+    fn block(json: &str) -> Block {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn block_200k() -> Block {
+        block(include_str!("../../fixtures/sequencer/block_200k.json"))
+    }
+
+    fn pending_block() -> Block {
+        block(include_str!("../../fixtures/sequencer/pending_block.json"))
+    }
+
+    fn assert_hash(transaction: &Transaction) {
+        assert_eq!(
+            compute_transaction_hash(transaction, Chain::Goerli).unwrap(),
+            transaction.transaction_hash
+        );
+    }
+
+    #[test]
+    fn invoke() {
+        for transaction in block_200k()
+            .transactions
+            .iter()
+            .filter(|tx| tx.entry_point_type == Some(EntryPointType::External))
+        {
+            assert_hash(transaction);
+        }
+    }
+
+    #[test]
+    fn deploy() {
+        let block = pending_block();
+        let deploys = block
+            .transactions
+            .iter()
+            .filter(|tx| tx.r#type == Type::Deploy)
+            .collect::<Vec<_>>();
+        assert!(!deploys.is_empty());
+
+        deploys.into_iter().for_each(assert_hash);
+    }
+
+    #[test]
+    fn l1_handler() {
+        let mut transaction = block_200k().transactions[1].clone();
+        assert_eq!(
+            transaction.entry_point_type,
+            Some(EntryPointType::L1Handler)
+        );
+
+        // The gateway did not report the nonce of the handled L1 to L2 message at the time.
+        compute_transaction_hash(&transaction, Chain::Goerli).unwrap_err();
+
+        transaction.nonce = Some(TransactionNonce(
+            StarkHash::from_be_slice(&78085u64.to_be_bytes()).unwrap(),
+        ));
+        assert_hash(&transaction);
+    }
+
+    #[test]
+    fn declare() {
+        let transaction = Transaction {
+            calldata: None,
+            class_hash: Some(ClassHash(
+                StarkHash::from_hex_str(
+                    "0x711941b11a8236b8cca42b664e19342ac7300abb1dc44957763cb65877c2708",
+                )
+                .unwrap(),
+            )),
+            constructor_calldata: None,
+            contract_address: ContractAddress(StarkHash::from_hex_str("0x1").unwrap()),
+            contract_address_salt: None,
+            entry_point_type: None,
+            entry_point_selector: None,
+            max_fee: Some(Fee(web3::types::H128::zero())),
+            signature: Some(vec![]),
+            // Computed independently from cairo-lang's `calculate_declare_transaction_hash`
+            // for version 0.
+            transaction_hash: StarknetTransactionHash(
+                StarkHash::from_hex_str(
+                    "0x77ccba4df42cf0f74a8eb59a96d7880fae371edca5d000ca5f9985652c8a8ed",
+                )
+                .unwrap(),
+            ),
+            sender_address: Some(ContractAddress(StarkHash::from_hex_str("0x1").unwrap())),
+            nonce: Some(TransactionNonce(StarkHash::ZERO)),
+            r#type: Type::Declare,
+        };
+
+        assert_hash(&transaction);
+    }
+
+    #[test]
+    fn legacy() {
+        let genesis = block(include_str!("../../fixtures/sequencer/genesis_block.json"));
+        let block_1716 = block(include_str!("../../fixtures/sequencer/block_1716.json"));
+
+        for transaction in genesis.transactions.iter().chain(&block_1716.transactions) {
+            assert_eq!(
+                compute_legacy_transaction_hash(transaction, Chain::Goerli).unwrap(),
+                transaction.transaction_hash
+            );
+            assert_ne!(
+                compute_transaction_hash(transaction, Chain::Goerli).unwrap(),
+                transaction.transaction_hash
+            );
+        }
+    }
+
+    #[test]
+    fn chain_id_is_committed_to() {
+        let transaction = &block_200k().transactions[0];
+        assert_ne!(
+            compute_transaction_hash(transaction, Chain::Mainnet).unwrap(),
+            transaction.transaction_hash
+        );
+    }
+}
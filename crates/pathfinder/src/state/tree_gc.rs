@@ -0,0 +1,386 @@
+//! Garbage collection of the tree nodes of old states.
+//!
+//! Tree nodes are content addressed and reference counted by [RcNodeStorage], so states share
+//! all of their identical subtrees, such as the storage of cloned contracts. Sync however only
+//! ever adds references, which retains the trees of every state it ever committed.
+//! [gc_trees] releases the references held by the states of blocks which have fallen behind a
+//! horizon, which deletes every node that no retained state references any longer.
+//!
+//! The references are released as follows:
+//!
+//! - a block's global root was committed once for the block, and is released with it;
+//! - a contract root was committed when it was set, and is released once the contract
+//!   is changed to a different root and the last block which still had it is released.
+
+use std::ops::ControlFlow;
+
+use anyhow::Context;
+use rusqlite::Transaction;
+use stark_hash::StarkHash;
+
+use crate::{
+    core::{ContractRoot, GlobalRoot, StarknetBlockNumber},
+    state::{diff::contract_root, state_tree::GlobalStateTree},
+    storage::{merkle_tree::RcNodeStorage, RefsTable, StarknetBlocksBlockId, StarknetBlocksTable},
+};
+
+/// The table which stores the nodes of all global state trees.
+const GLOBAL_TREE_TABLE: &str = "tree_global";
+/// The table which stores the nodes of all contract storage trees.
+const CONTRACT_TREE_TABLE: &str = "tree_contracts";
+
+/// The outcome of [gc_trees].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GcSummary {
+    /// Blocks whose state was released.
+    pub pruned_blocks: u64,
+    /// Contract roots which were released along with them.
+    pub released_contract_roots: u64,
+}
+
+/// Releases the tree nodes of the states of all blocks but the newest `keep_blocks`, deleting
+/// the nodes which are no longer referenced by any retained state.
+///
+/// The states of released blocks can no longer be read, and the chain can no longer be reorged
+/// to below them. `keep_blocks` must therefore be at least the `max_reorg_depth` which sync
+/// accepts without approval, if any. Blocks which were released by an earlier run are skipped,
+/// see [RefsTable::get_trees_pruned_before].
+pub fn gc_trees(
+    transaction: &Transaction<'_>,
+    keep_blocks: u64,
+    max_reorg_depth: Option<u64>,
+) -> anyhow::Result<GcSummary> {
+    anyhow::ensure!(
+        keep_blocks > 0,
+        "The state of the latest block must be kept"
+    );
+    if let Some(max_reorg_depth) = max_reorg_depth {
+        anyhow::ensure!(
+            keep_blocks >= max_reorg_depth,
+            "Keeping {} blocks would prune states which a reorg within the maximum reorg depth of {} blocks reverts to",
+            keep_blocks,
+            max_reorg_depth
+        );
+    }
+
+    let mut summary = GcSummary::default();
+
+    let head =
+        match StarknetBlocksTable::get_latest_number(transaction).context("Query latest block")? {
+            Some(head) => head,
+            None => return Ok(summary),
+        };
+    let horizon = StarknetBlockNumber((head.0 + 1).saturating_sub(keep_blocks));
+    let start = RefsTable::get_trees_pruned_before(transaction)
+        .context("Query pruned blocks")?
+        .unwrap_or(StarknetBlockNumber::GENESIS);
+    if start >= horizon {
+        return Ok(summary);
+    }
+
+    let global_nodes = RcNodeStorage::open(GLOBAL_TREE_TABLE.to_owned(), transaction)
+        .context("Open global tree storage")?;
+    let contract_nodes = RcNodeStorage::open(CONTRACT_TREE_TABLE.to_owned(), transaction)
+        .context("Open contract tree storage")?;
+
+    // Every released block has a successor, as the horizon never exceeds the head.
+    let mut next_root = block_root(transaction, start)?;
+    for number in start.0..horizon.0 {
+        let root = next_root;
+        next_root = block_root(transaction, StarknetBlockNumber(number + 1))?;
+
+        for replaced in replaced_contract_roots(transaction, root, next_root)
+            .with_context(|| format!("Compare states of blocks {} and {}", number, number + 1))?
+        {
+            contract_nodes
+                .decrement_ref_count(replaced.0)
+                .context("Release contract root")?;
+            summary.released_contract_roots += 1;
+        }
+
+        if root.0 != StarkHash::ZERO {
+            global_nodes
+                .decrement_ref_count(root.0)
+                .context("Release global root")?;
+        }
+        summary.pruned_blocks += 1;
+    }
+
+    RefsTable::set_trees_pruned_before(transaction, horizon).context("Update pruned blocks")?;
+
+    Ok(summary)
+}
+
+fn block_root(
+    transaction: &Transaction<'_>,
+    number: StarknetBlockNumber,
+) -> anyhow::Result<GlobalRoot> {
+    let block = StarknetBlocksTable::get(transaction, StarknetBlocksBlockId::Number(number))
+        .context("Query block")?
+        .with_context(|| format!("Block {} is missing", number.0))?;
+
+    Ok(block.root)
+}
+
+/// Returns the roots of the contracts whose storage root differs between the states at `old`
+/// and `new`, as they were in `old`.
+///
+/// A contract which is only assigned a different class keeps its root, which is therefore not
+/// replaced.
+fn replaced_contract_roots(
+    transaction: &Transaction<'_>,
+    old: GlobalRoot,
+    new: GlobalRoot,
+) -> anyhow::Result<Vec<ContractRoot>> {
+    if old == new {
+        return Ok(Vec::new());
+    }
+
+    let old_tree = GlobalStateTree::load(transaction, old).context("Loading global state tree")?;
+    let new_tree = GlobalStateTree::load(transaction, new).context("Loading global state tree")?;
+
+    let mut changed = Vec::new();
+    old_tree
+        .diff(&new_tree, |_, old, new| {
+            changed.push((old, new));
+            ControlFlow::Continue(())
+        })
+        .context("Diffing global state trees")?;
+
+    let mut replaced = Vec::new();
+    for (old, new) in changed {
+        let old = contract_root(transaction, old)?;
+        let new = contract_root(transaction, new)?;
+
+        if old != new && old.0 != StarkHash::ZERO {
+            replaced.push(old);
+        }
+    }
+
+    Ok(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::core::{
+        ClassHash, ContractAddress, ContractStateHash, StorageAddress, StorageValue,
+    };
+    use crate::ethereum::state_update::{
+        ContractUpdate, DeployedContract, StateUpdate, StorageUpdate,
+    };
+    use crate::state::merkle_tree::MerkleTree;
+    use crate::state::sync::update_starknet_state;
+    use crate::storage::{
+        test_utils, ContractCodeTable, ContractsStateTable, StarknetBlock, Storage,
+    };
+
+    const CLASS: u8 = 0xc1;
+    const MAX_BLOCKS: usize = 16;
+
+    /// The storage of every contract in a state.
+    type State = BTreeMap<StarkHash, BTreeMap<StarkHash, StarkHash>>;
+
+    fn felt(value: u8) -> StarkHash {
+        StarkHash::from_be_slice(&[value]).unwrap()
+    }
+
+    fn storage_with_class() -> Storage {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+        ContractCodeTable::insert_without_definition(&connection, ClassHash(felt(CLASS))).unwrap();
+        storage
+    }
+
+    /// Applies `writes` as the next block, deploying the contracts they write to first.
+    fn apply_block(transaction: &Transaction<'_>, writes: &[(u8, u8, u8)]) -> GlobalRoot {
+        let latest = StarknetBlocksTable::get(transaction, StarknetBlocksBlockId::Latest).unwrap();
+        let next = latest
+            .as_ref()
+            .map(|block| block.number.0 as usize + 1)
+            .unwrap_or_default();
+        let latest_root = latest
+            .map(|block| block.root)
+            .unwrap_or(GlobalRoot(StarkHash::ZERO));
+        let latest_tree = GlobalStateTree::load(transaction, latest_root).unwrap();
+
+        let deployed_contracts = writes
+            .iter()
+            .map(|(contract, _, _)| ContractAddress(felt(*contract)))
+            .filter(|address| latest_tree.get(*address).unwrap().0 == StarkHash::ZERO)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|address| DeployedContract {
+                address,
+                hash: ClassHash(felt(CLASS)),
+                call_data: vec![],
+            })
+            .collect();
+        let contract_updates = writes
+            .iter()
+            .map(|(contract, key, value)| ContractUpdate {
+                address: ContractAddress(felt(*contract)),
+                storage_updates: vec![StorageUpdate {
+                    address: StorageAddress(felt(*key)),
+                    value: StorageValue(felt(*value)),
+                }],
+            })
+            .collect();
+        let update = StateUpdate {
+            deployed_contracts,
+            contract_updates,
+        };
+
+        let root = update_starknet_state(transaction, &update, None).unwrap();
+        let block = StarknetBlock {
+            root,
+            ..test_utils::create_blocks::<MAX_BLOCKS>()[next].clone()
+        };
+        StarknetBlocksTable::insert(transaction, &block).unwrap();
+
+        root
+    }
+
+    /// Reads every node of the state at `root`.
+    fn read_state(transaction: &Transaction<'_>, root: GlobalRoot) -> anyhow::Result<State> {
+        let global = MerkleTree::load(GLOBAL_TREE_TABLE.to_owned(), transaction, root.0)?;
+        let mut contracts = Vec::new();
+        global.visit_leaves(|address, state_hash| {
+            contracts.push((address, ContractStateHash(state_hash)));
+            ControlFlow::Continue(())
+        })?;
+
+        let mut state = State::new();
+        for (address, state_hash) in contracts {
+            let root = ContractsStateTable::get_root(transaction, state_hash)?
+                .context("Contract state missing")?;
+            let tree = MerkleTree::load(CONTRACT_TREE_TABLE.to_owned(), transaction, root.0)?;
+            let storage = state.entry(address).or_default();
+            tree.visit_leaves(|key, value| {
+                storage.insert(key, value);
+                ControlFlow::Continue(())
+            })?;
+        }
+
+        Ok(state)
+    }
+
+    fn node_exists(transaction: &Transaction<'_>, table: &str, hash: StarkHash) -> bool {
+        RcNodeStorage::open(table.to_owned(), transaction)
+            .unwrap()
+            .get(hash)
+            .unwrap()
+            .is_some()
+    }
+
+    #[test]
+    fn removes_unreferenced_nodes() {
+        let storage = storage_with_class();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let roots = [1, 2, 3].map(|value| apply_block(&transaction, &[(0xa, 1, value)]));
+        let contract_roots = roots.map(|root| {
+            let state_hash = GlobalStateTree::load(&transaction, root)
+                .unwrap()
+                .get(ContractAddress(felt(0xa)))
+                .unwrap();
+            contract_root(&transaction, state_hash).unwrap()
+        });
+        let head_state = read_state(&transaction, roots[2]).unwrap();
+
+        let summary = gc_trees(&transaction, 1, None).unwrap();
+        assert_eq!(
+            summary,
+            GcSummary {
+                pruned_blocks: 2,
+                released_contract_roots: 2,
+            }
+        );
+        assert_eq!(
+            RefsTable::get_trees_pruned_before(&transaction).unwrap(),
+            Some(StarknetBlockNumber(2))
+        );
+
+        for released in &roots[..2] {
+            assert!(!node_exists(&transaction, GLOBAL_TREE_TABLE, released.0));
+        }
+        for released in &contract_roots[..2] {
+            assert!(!node_exists(&transaction, CONTRACT_TREE_TABLE, released.0));
+        }
+        assert_eq!(read_state(&transaction, roots[2]).unwrap(), head_state);
+
+        // Released blocks are not released again.
+        assert_eq!(
+            gc_trees(&transaction, 1, None).unwrap(),
+            GcSummary::default()
+        );
+        gc_trees(&transaction, 0, None).unwrap_err();
+        // Fewer blocks than the maximum reorg depth must not be kept.
+        gc_trees(&transaction, 1, Some(2)).unwrap_err();
+        gc_trees(&transaction, 2, Some(2)).unwrap();
+    }
+
+    #[test]
+    fn contract_root_shared_by_clones_is_kept() {
+        let storage = storage_with_class();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        // Both contracts end up with identical storage, until the first one changes.
+        apply_block(&transaction, &[(0xa, 1, 1), (0xb, 1, 1)]);
+        let head = apply_block(&transaction, &[(0xa, 1, 2)]);
+        let head_state = read_state(&transaction, head).unwrap();
+
+        gc_trees(&transaction, 1, None).unwrap();
+        assert_eq!(read_state(&transaction, head).unwrap(), head_state);
+    }
+
+    /// Writes to storage `(contract, key, value)`, where a zero value deletes the key.
+    fn writes() -> impl Strategy<Value = Vec<(u8, u8, u8)>> {
+        prop::collection::vec((1u8..4, 1u8..8, 0u8..4), 0..6)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Collects garbage in between two stretches of sync, and checks that the state of
+        /// every retained block can still be read in full.
+        #[test]
+        fn retained_states_stay_readable(
+            first in prop::collection::vec(writes(), 1..8),
+            second in prop::collection::vec(writes(), 0..8),
+            keep_first in 1u64..8,
+            keep_second in 1u64..8,
+        ) {
+            let storage = storage_with_class();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let mut states = Vec::new();
+            for writes in &first {
+                let root = apply_block(&transaction, writes);
+                states.push((root, read_state(&transaction, root).unwrap()));
+            }
+            gc_trees(&transaction, keep_first, None).unwrap();
+
+            for writes in &second {
+                let root = apply_block(&transaction, writes);
+                states.push((root, read_state(&transaction, root).unwrap()));
+            }
+            gc_trees(&transaction, keep_second, None).unwrap();
+
+            let pruned_before = RefsTable::get_trees_pruned_before(&transaction)
+                .unwrap()
+                .unwrap_or(StarknetBlockNumber::GENESIS);
+            prop_assert!(pruned_before.0 < states.len() as u64);
+            for (root, state) in &states[pruned_before.0 as usize..] {
+                prop_assert_eq!(&read_state(&transaction, *root).unwrap(), state);
+            }
+        }
+    }
+}
@@ -2,35 +2,98 @@
 //!
 //! Currently this consists of a Sqlite backend implementation.
 
+mod blob;
+mod bloom;
 mod contract;
+mod divergence;
 mod ethereum;
 pub(crate) mod merkle_tree;
+mod packed;
+mod raw_block;
+mod retry;
 mod schema;
+mod sql;
 mod state;
+mod statement_cache;
+mod stats;
+mod token;
 
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 #[cfg(test)]
 use std::sync::Mutex;
 
-pub use contract::{ContractCodeTable, ContractsTable};
+pub use blob::{BlobError, DEFAULT_MAX_BLOB_SIZE};
+pub use contract::{ClassBackfillTable, ContractCodeTable, ContractsTable, DefinitionNotAvailable};
+pub use divergence::{ContractDivergence, RootDivergence, RootDivergenceTable};
 pub use ethereum::{EthereumBlocksTable, EthereumTransactionsTable};
+pub use raw_block::RawGatewayBlocksTable;
+pub use retry::{RetryEntry, RetryItem, SyncRetryQueueTable};
+pub use sql::{read_only_query, QueryError, QueryResult};
 pub use state::{
-    ContractsStateTable, EventFilterError, L1StateTable, L1TableBlockId, RefsTable, StarknetBlock,
-    StarknetBlocksBlockId, StarknetBlocksTable, StarknetEmittedEvent, StarknetEventFilter,
-    StarknetEventsTable, StarknetTransactionsTable,
+    BlockEventSummary, ContractsStateTable, DeclaredClass, DeclaredClassesTable,
+    EventDataCondition, EventFilterConstraint, EventFilterError, L1StateTable, L1TableBlockId,
+    ParseBlockIdError, RefsTable, StarknetBlock, StarknetBlocksBlockId, StarknetBlocksTable,
+    StarknetEmittedEvent, StarknetEventFilter, StarknetEventsTable, StarknetStateUpdatesTable,
+    StarknetTransactionsTable, TimestampDirection, TransactionsError,
+    DEFAULT_EVENT_QUERY_COST_BUDGET, DEFAULT_MAX_BLOCK_TRANSACTIONS, STATE_UPDATE_RETENTION,
 };
+pub use statement_cache::{
+    render_statement_cache_metrics, thread_statement_cache_stats, total_statement_cache_stats,
+    PrepareCounted, StatementCacheStats,
+};
+pub use stats::{ChainStats, ChainStatsTable};
+pub use token::{TokenMetadata, TokenMetadataTable};
 
 use anyhow::Context;
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 use tracing::info;
 
+use crate::core::{StarknetBlockHash, StarknetBlockNumber};
+use crate::ethereum::Chain;
+
 /// Indicates database is non-existant.
 const DB_VERSION_EMPTY: u32 = 0;
 /// Current database version.
-const DB_VERSION_CURRENT: u32 = 10;
+pub const DB_VERSION_CURRENT: u32 = 33;
+/// Default number of prepared statements cached per [Connection].
+///
+/// Large enough to hold all the distinct statically prepared queries, unlike rusqlite's default of 16.
+pub const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 64;
 /// Sqlite key used for the PRAGMA user version.
 const VERSION_KEY: &str = "user_version";
 
+/// The chains of a network which a node can store side by side, each in its own database file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainNamespace {
+    /// The chain synced from the configured gateway, which the node serves.
+    Primary,
+    /// The chain synced from a second gateway for comparison with the primary one, see
+    /// [shadow](crate::state::shadow).
+    Shadow,
+}
+
+impl ChainNamespace {
+    /// The database file of this namespace, given the database file of the primary chain.
+    ///
+    /// The shadow database lives next to the primary one, e.g. `goerli.shadow.sqlite` next to
+    /// `goerli.sqlite`.
+    pub fn database_path(self, primary: &Path) -> PathBuf {
+        match self {
+            ChainNamespace::Primary => primary.to_owned(),
+            ChainNamespace::Shadow => {
+                let mut name = primary.file_stem().unwrap_or_default().to_owned();
+                name.push(".shadow");
+                if let Some(extension) = primary.extension() {
+                    name.push(".");
+                    name.push(extension);
+                }
+                primary.with_file_name(name)
+            }
+        }
+    }
+}
+
 /// Used to create [Connection's](Connection) to the pathfinder database.
 ///
 /// Intended usage:
@@ -43,6 +106,11 @@ pub struct Storage(std::sync::Arc<Inner>);
 
 struct Inner {
     database_path: PathBuf,
+    /// The prepared statement cache capacity of each [Connection].
+    statement_cache_capacity: AtomicUsize,
+    limits: StorageLimits,
+    /// Whether [connections](Storage::connection) are opened read-only.
+    read_only: bool,
     /// Required to keep the in-memory variant alive. Sqlite drops in-memory databases
     /// as soon as all living connections are dropped, so we prevent this by storing
     /// a keep-alive connection.
@@ -60,31 +128,176 @@ impl Storage {
     ///
     /// May be cloned safely.
     pub fn migrate(database_path: PathBuf) -> anyhow::Result<Self> {
-        let mut conn = Self::open_connection(&database_path)?;
+        Self::migrate_with_statement_cache_capacity(database_path, DEFAULT_STATEMENT_CACHE_CAPACITY)
+    }
+
+    /// Same as [Storage::migrate], but each [connection](Storage::connection) caches up to
+    /// `capacity` prepared statements instead of [DEFAULT_STATEMENT_CACHE_CAPACITY].
+    pub fn migrate_with_statement_cache_capacity(
+        database_path: PathBuf,
+        capacity: usize,
+    ) -> anyhow::Result<Self> {
+        Self::migrate_with_limits(database_path, capacity, StorageLimits::default())
+    }
+
+    /// Same as [Storage::migrate_with_statement_cache_capacity], but the data read and written
+    /// through the tables is bounded by `limits` instead of the [default](StorageLimits::default)
+    /// limits.
+    pub fn migrate_with_limits(
+        database_path: PathBuf,
+        capacity: usize,
+        limits: StorageLimits,
+    ) -> anyhow::Result<Self> {
+        let mut conn = Self::open_connection(&database_path, capacity, false)?;
         migrate_database(&mut conn).context("Migrate database")?;
 
+        Ok(Self::new(database_path, capacity, limits, false, conn))
+    }
+
+    /// Same as [Storage::migrate_with_limits], but the database is first checked to belong to
+    /// `chain`, see [Storage::verify_chain].
+    ///
+    /// The check runs on the database as it is, before any migration, so that a database of
+    /// another chain or of a newer version of pathfinder is refused untouched.
+    pub fn migrate_verified(
+        database_path: PathBuf,
+        capacity: usize,
+        limits: StorageLimits,
+        chain: Chain,
+        force: bool,
+    ) -> anyhow::Result<Self> {
+        let mut conn = Self::open_connection(&database_path, capacity, false)?;
+        let version = schema_version(&conn)?;
+        ensure_supported_version(version)?;
+        let record = check_chain(&conn, version, chain, force)?;
+
+        migrate_database(&mut conn).context("Migrate database")?;
+        if record {
+            RefsTable::set_chain(&conn, chain).context("Record chain")?;
+        }
+
+        Ok(Self::new(database_path, capacity, limits, false, conn))
+    }
+
+    /// Opens an existing database without write access, e.g. to serve a database which another
+    /// process syncs.
+    ///
+    /// Committed writes of the other process are visible to each new
+    /// [connection](Storage::connection). Errors if the database schema is not exactly
+    /// [DB_VERSION_CURRENT], as it cannot be migrated.
+    pub fn open_read_only(
+        database_path: PathBuf,
+        capacity: usize,
+        limits: StorageLimits,
+    ) -> anyhow::Result<Self> {
+        let conn = Self::open_connection(&database_path, capacity, true)?;
+        let version = schema_version(&conn)?;
+
+        ensure_supported_version(version)?;
+        anyhow::ensure!(
+            version == DB_VERSION_CURRENT,
+            "Database version is older than this application ({} < {}), it must be migrated by a node which syncs it",
+            version,
+            DB_VERSION_CURRENT
+        );
+
+        Ok(Self::new(database_path, capacity, limits, true, conn))
+    }
+
+    #[cfg_attr(not(test), allow(unused_variables))]
+    fn new(
+        database_path: PathBuf,
+        capacity: usize,
+        limits: StorageLimits,
+        read_only: bool,
+        conn: Connection,
+    ) -> Self {
         #[cfg(not(test))]
-        let inner = Inner { database_path };
+        let inner = Inner {
+            database_path,
+            statement_cache_capacity: AtomicUsize::new(capacity),
+            limits,
+            read_only,
+        };
         #[cfg(test)]
         let inner = Inner {
             database_path,
+            statement_cache_capacity: AtomicUsize::new(capacity),
+            limits,
+            read_only,
             _keep_alive: Mutex::new(conn),
         };
 
-        let storage = Storage(std::sync::Arc::new(inner));
-
-        Ok(storage)
+        Storage(std::sync::Arc::new(inner))
     }
 
     /// Returns a new Sqlite [Connection] to the database.
     pub fn connection(&self) -> anyhow::Result<Connection> {
-        Self::open_connection(&self.0.database_path)
+        Self::open_connection(
+            &self.0.database_path,
+            self.statement_cache_capacity(),
+            self.0.read_only,
+        )
+    }
+
+    /// Same as [Storage::connection], but the most frequently used queries are already in the
+    /// connection's prepared statement cache.
+    ///
+    /// Preparing the queries ahead of time only pays off for connections which are kept open,
+    /// such as the sync process' connection.
+    pub fn long_lived_connection(&self) -> anyhow::Result<Connection> {
+        let conn = self.connection()?;
+        statement_cache::warm(&conn, state::HOT_QUERIES);
+        Ok(conn)
+    }
+
+    /// The prepared statement cache capacity of new [connections](Storage::connection).
+    pub fn statement_cache_capacity(&self) -> usize {
+        self.0.statement_cache_capacity.load(Ordering::Relaxed)
+    }
+
+    /// Changes the prepared statement cache capacity of [connections](Storage::connection)
+    /// opened from now on, returning the previous capacity. Open connections are unaffected.
+    pub fn set_statement_cache_capacity(&self, capacity: usize) -> usize {
+        self.0
+            .statement_cache_capacity
+            .swap(capacity, Ordering::Relaxed)
+    }
+
+    /// The limits on the data read and written through the tables, which they are passed to.
+    pub fn limits(&self) -> &StorageLimits {
+        &self.0.limits
+    }
+
+    /// Whether the database was opened with [Storage::open_read_only].
+    pub fn is_read_only(&self) -> bool {
+        self.0.read_only
     }
 
     /// Opens a connection the given database path.
-    fn open_connection(database_path: &Path) -> anyhow::Result<Connection> {
-        // TODO: think about flags?
-        let conn = Connection::open(database_path)?;
+    fn open_connection(
+        database_path: &Path,
+        statement_cache_capacity: usize,
+        read_only: bool,
+    ) -> anyhow::Result<Connection> {
+        use rusqlite::OpenFlags;
+
+        let conn = match read_only {
+            true => {
+                let conn = Connection::open_with_flags(
+                    database_path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY
+                        | OpenFlags::SQLITE_OPEN_URI
+                        | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?;
+                conn.pragma_update(None, "query_only", true)
+                    .context("Enable query_only")?;
+                conn
+            }
+            // TODO: think about flags?
+            false => Connection::open(database_path)?,
+        };
+        conn.set_prepared_statement_cache_capacity(statement_cache_capacity);
         Ok(conn)
     }
 
@@ -92,6 +305,13 @@ impl Storage {
     /// Convenience function for tests to create an in-memory database.
     /// Equivalent to [Storage::migrate] with an in-memory backed database.
     pub fn in_memory() -> anyhow::Result<Self> {
+        Self::in_memory_with_statement_cache_capacity(DEFAULT_STATEMENT_CACHE_CAPACITY)
+    }
+
+    #[cfg(test)]
+    /// Equivalent to [Storage::migrate_with_statement_cache_capacity] with an in-memory
+    /// backed database.
+    pub fn in_memory_with_statement_cache_capacity(capacity: usize) -> anyhow::Result<Self> {
         // Create a unique database name so that they are not shared between
         // concurrent tests. i.e. Make every in-mem Storage unique.
         lazy_static::lazy_static!(
@@ -106,12 +326,300 @@ impl Storage {
 
         let database_path = PathBuf::from(unique_mem_db);
 
-        Self::migrate(database_path)
+        Self::migrate_with_statement_cache_capacity(database_path, capacity)
     }
 
     pub fn path(&self) -> &Path {
         &self.0.database_path
     }
+
+    /// Reverts the L1 and L2 state down to `reorg_tail`, i.e. deletes all blocks and L1 state
+    /// updates with `block number >= reorg_tail`, and lowers the L1-L2 head accordingly.
+    ///
+    /// A reorg which would revert more blocks than `limit` allows is refused with a
+    /// [ReorgTooDeep] error, and nothing is deleted. Returns the resulting L1-L2 head.
+    pub fn reorg(
+        connection: &Connection,
+        reorg_tail: StarknetBlockNumber,
+        limit: ReorgLimit,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        let head = StarknetBlocksTable::get_latest_number(connection)
+            .context("Query L2 head from database")?;
+        limit.check(head, reorg_tail)?;
+
+        L1StateTable::reorg(connection, reorg_tail).context("Delete L1 state from database")?;
+        Self::revert_l2(connection, reorg_tail)
+    }
+
+    /// Same as [Storage::reorg], but only reverts the L1 state. The depth is measured from the
+    /// latest L1 state update.
+    pub fn reorg_l1(
+        connection: &Connection,
+        reorg_tail: StarknetBlockNumber,
+        limit: ReorgLimit,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        let head = L1StateTable::get(connection, L1TableBlockId::Latest)
+            .context("Query L1 head from database")?
+            .map(|update| update.block_number);
+        limit.check(head, reorg_tail)?;
+
+        L1StateTable::reorg(connection, reorg_tail).context("Delete L1 state from database")?;
+        RefsTable::reorg(connection, reorg_tail)
+    }
+
+    /// Same as [Storage::reorg], but only reverts the L2 state.
+    pub fn reorg_l2(
+        connection: &Connection,
+        reorg_tail: StarknetBlockNumber,
+        limit: ReorgLimit,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        let head = StarknetBlocksTable::get_latest_number(connection)
+            .context("Query L2 head from database")?;
+        limit.check(head, reorg_tail)?;
+
+        Self::revert_l2(connection, reorg_tail)
+    }
+
+    fn revert_l2(
+        connection: &Connection,
+        reorg_tail: StarknetBlockNumber,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        ChainStatsTable::reorg(connection, reorg_tail).context("Revert chain statistics")?;
+        StarknetBlocksTable::reorg(connection, reorg_tail)
+            .context("Delete L2 state from database")?;
+        RefsTable::reorg(connection, reorg_tail)
+    }
+
+    /// Returns the schema revisions which [Storage::migrate] would apply to the database,
+    /// without performing any of the migrations.
+    ///
+    /// An empty list indicates that the database is already up-to-date.
+    pub fn pending_migrations(connection: &Connection) -> anyhow::Result<Vec<u32>> {
+        let version = schema_version(connection)?;
+
+        ensure_supported_version(version)?;
+
+        Ok(((version + 1)..=DB_VERSION_CURRENT).collect())
+    }
+
+    /// Checks that the database belongs to `chain`, and fails with a [ChainMismatch] otherwise.
+    ///
+    /// The chain is recorded the first time a writable database is verified. Databases which
+    /// predate this record have their chain inferred from the hash of their genesis block.
+    /// With `force`, a mismatch is only logged and nothing is recorded.
+    ///
+    /// A database which still has to be migrated should be opened with
+    /// [Storage::migrate_verified] instead, which checks it before migrating it.
+    pub fn verify_chain(&self, chain: Chain, force: bool) -> anyhow::Result<()> {
+        let connection = self.connection().context("Opening database connection")?;
+
+        let record = check_chain(&connection, DB_VERSION_CURRENT, chain, force)?;
+        if record && !self.is_read_only() {
+            RefsTable::set_chain(&connection, chain).context("Record chain")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The schema revision which added the chain record to the `refs` table.
+const CHAIN_RECORD_REVISION: u32 = 26;
+
+/// The schema revision which added the `starknet_blocks` table.
+const STARKNET_BLOCKS_REVISION: u32 = 3;
+
+/// Checks that the database, whose schema is at revision `version`, belongs to `chain`, see
+/// [Storage::verify_chain]. Returns whether `chain` should be recorded.
+///
+/// Only the parts of the schema which exist at `version` are read, so that this works on a
+/// database which has not been migrated yet.
+fn check_chain(
+    connection: &Connection,
+    version: u32,
+    chain: Chain,
+    force: bool,
+) -> anyhow::Result<bool> {
+    let recorded = match version >= CHAIN_RECORD_REVISION {
+        true => RefsTable::get_chain(connection).context("Read recorded chain")?,
+        false => None,
+    };
+    let stored = match recorded {
+        Some(recorded) => Some(recorded),
+        None => {
+            let genesis = match version >= STARKNET_BLOCKS_REVISION {
+                true => connection
+                    .query_row(
+                        "SELECT hash FROM starknet_blocks WHERE number = ?",
+                        [StarknetBlockNumber::GENESIS.0],
+                        |row| row.get::<_, Vec<u8>>(0),
+                    )
+                    .optional()
+                    .context("Read genesis block")?
+                    .map(|hash| {
+                        StarknetBlockHash(stark_hash::StarkHash::from_be_slice(&hash).unwrap())
+                    }),
+                false => None,
+            };
+            match genesis.map(|hash| (genesis_chain(hash), hash)) {
+                Some((Some(inferred), _)) => Some(inferred),
+                Some((None, hash)) if force => {
+                    tracing::warn!(genesis=%hash.0, configured=?chain, "Database genesis block belongs to no known chain, continuing as forced");
+                    return Ok(false);
+                }
+                Some((None, hash)) => anyhow::bail!(
+                    "Database genesis block {} belongs to no known chain, but the node is configured for {:?}",
+                    hash.0,
+                    chain
+                ),
+                // A new database belongs to the chain it is synced from.
+                None => None,
+            }
+        }
+    };
+
+    match stored {
+        Some(stored) if stored != chain && force => {
+            tracing::warn!(?stored, configured=?chain, "Database belongs to a different chain, continuing as forced");
+            return Ok(false);
+        }
+        Some(stored) if stored != chain => {
+            return Err(ChainMismatch {
+                stored,
+                configured: chain,
+            }
+            .into())
+        }
+        _ => {}
+    }
+
+    Ok(recorded.is_none())
+}
+
+/// Returns the chain whose genesis block has the given hash.
+fn genesis_chain(hash: StarknetBlockHash) -> Option<Chain> {
+    const MAINNET: &str = "0x047c3637b57c2b079b93c61539950c17e868a28f46cdef28f88521067f21e943";
+    const GOERLI: &str = "0x07d328a71faf48c5c3857e99f20a77b18522480956d1cd5bff1ff2df3c8b427b";
+
+    [(MAINNET, Chain::Mainnet), (GOERLI, Chain::Goerli)]
+        .into_iter()
+        .find(|(genesis, _)| stark_hash::StarkHash::from_hex_str(genesis).unwrap() == hash.0)
+        .map(|(_, chain)| chain)
+}
+
+/// Error returned by [Storage::verify_chain] for a database which belongs to another chain.
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq)]
+#[error(
+    "the database belongs to {:?}, but the node is configured for {:?}. Point the node at a database of the configured chain, or pass --force to use this one regardless",
+    .stored,
+    .configured
+)]
+pub struct ChainMismatch {
+    pub stored: Chain,
+    pub configured: Chain,
+}
+
+/// Bounds the resources spent on the data read from and written to a [Storage].
+///
+/// The tables take these limits as an argument, see [Storage::limits].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StorageLimits {
+    /// Upper bound on the decompressed size of a single blob read from the database.
+    pub max_blob_size: usize,
+    /// Upper bound on the number of transactions of a block stored by
+    /// [StarknetTransactionsTable::upsert].
+    pub max_block_transactions: usize,
+    /// Upper bound on the estimated cost of the queries run by
+    /// [StarknetEventsTable::get_events], `None` to only log queries over the
+    /// [default budget](DEFAULT_EVENT_QUERY_COST_BUDGET).
+    pub event_query_cost_budget: Option<u64>,
+}
+
+impl Default for StorageLimits {
+    fn default() -> Self {
+        Self {
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+            max_block_transactions: DEFAULT_MAX_BLOCK_TRANSACTIONS,
+            event_query_cost_budget: None,
+        }
+    }
+}
+
+/// Bounds the number of blocks which [Storage::reorg] and its variants may revert.
+///
+/// A buggy or malicious upstream could otherwise have the whole chain reverted down to genesis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReorgLimit {
+    /// Reorgs which revert more blocks than this are refused.
+    pub max_depth: u64,
+    /// Reverts regardless of [max_depth](ReorgLimit::max_depth).
+    pub force: bool,
+}
+
+impl ReorgLimit {
+    pub const DEFAULT_MAX_DEPTH: u64 = 1000;
+
+    /// A limit which allows reorgs of any depth.
+    pub fn force() -> Self {
+        Self {
+            force: true,
+            ..Default::default()
+        }
+    }
+
+    /// Fails with [ReorgTooDeep] if reverting from `head` down to `reorg_tail` exceeds the limit.
+    fn check(
+        &self,
+        head: Option<StarknetBlockNumber>,
+        reorg_tail: StarknetBlockNumber,
+    ) -> Result<(), ReorgTooDeep> {
+        match head.filter(|head| !self.force && *head >= reorg_tail) {
+            Some(head) if head.0 - reorg_tail.0 + 1 > self.max_depth => Err(ReorgTooDeep {
+                head,
+                reorg_tail,
+                max_depth: self.max_depth,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for ReorgLimit {
+    fn default() -> Self {
+        Self {
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            force: false,
+        }
+    }
+}
+
+/// Error returned by [Storage::reorg] for a reorg which is deeper than its [ReorgLimit] allows.
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq)]
+#[error(
+    "reorg to block {} would revert {} blocks below head {}, the maximum depth is {}",
+    .reorg_tail.0,
+    .head.0 - .reorg_tail.0 + 1,
+    .head.0,
+    .max_depth
+)]
+pub struct ReorgTooDeep {
+    /// The L2 head, or L1 head for [Storage::reorg_l1], at the time of the reorg.
+    pub head: StarknetBlockNumber,
+    /// The oldest block which the reorg would revert.
+    pub reorg_tail: StarknetBlockNumber,
+    pub max_depth: u64,
+}
+
+/// Fails if the database schema revision is newer than [DB_VERSION_CURRENT], i.e. the database
+/// was last used by a newer version of pathfinder.
+fn ensure_supported_version(version: u32) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        version <= DB_VERSION_CURRENT,
+        "Database schema revision {} is newer than the latest revision {} supported by this version of pathfinder",
+        version,
+        DB_VERSION_CURRENT
+    );
+
+    Ok(())
 }
 
 /// Migrates the database to the latest version. This __MUST__ be called
@@ -123,12 +631,7 @@ fn migrate_database(connection: &mut Connection) -> anyhow::Result<()> {
     let version = schema_version(connection)?;
 
     // Check that the database is not newer than this application knows of.
-    anyhow::ensure!(
-        version <= DB_VERSION_CURRENT,
-        "Database version is newer than this application ({} > {})",
-        version,
-        DB_VERSION_CURRENT
-    );
+    ensure_supported_version(version)?;
 
     let mut post_action = PostMigrationAction::None;
 
@@ -137,19 +640,7 @@ fn migrate_database(connection: &mut Connection) -> anyhow::Result<()> {
         let transaction = connection
             .transaction()
             .context("Create database transaction")?;
-        let action = match from_version {
-            DB_VERSION_EMPTY => schema::revision_0001::migrate(&transaction)?,
-            1 => schema::revision_0002::migrate(&transaction)?,
-            2 => schema::revision_0003::migrate(&transaction)?,
-            3 => schema::revision_0004::migrate(&transaction)?,
-            4 => schema::revision_0005::migrate(&transaction)?,
-            5 => schema::revision_0006::migrate(&transaction)?,
-            6 => schema::revision_0007::migrate(&transaction)?,
-            7 => schema::revision_0008::migrate(&transaction)?,
-            8 => schema::revision_0009::migrate(&transaction)?,
-            9 => schema::revision_0010::migrate(&transaction)?,
-            _ => unreachable!("Database version constraint was already checked!"),
-        };
+        let action = schema::migrate(&transaction, from_version + 1)?;
         // If any migration action requires vacuuming, we should vacuum.
         if action == PostMigrationAction::Vacuum {
             post_action = PostMigrationAction::Vacuum;
@@ -177,7 +668,7 @@ fn migrate_database(connection: &mut Connection) -> anyhow::Result<()> {
 
 /// Returns the current schema version of the existing database,
 /// or [DB_VERSION_EMPTY] if database does not yet exist.
-fn schema_version(connection: &Connection) -> anyhow::Result<u32> {
+pub fn schema_version(connection: &Connection) -> anyhow::Result<u32> {
     // We store the schema version in the Sqlite provided PRAGMA "user_version",
     // which stores an INTEGER and defaults to 0.
     let version = connection.query_row(
@@ -198,6 +689,7 @@ fn enable_foreign_keys(connection: &Connection) -> anyhow::Result<()> {
 #[cfg(test)]
 pub(crate) mod test_utils {
     use super::StarknetBlock;
+    use crate::ethereum::log::StateUpdateLog;
 
     use crate::{
         core::{
@@ -210,17 +702,54 @@ pub(crate) mod test_utils {
 
     use stark_hash::StarkHash;
 
+    /// Creates a set of consecutive [StateUpdateLog]s starting from L2 genesis,
+    /// with arbitrary other values.
+    pub(crate) fn create_state_update_logs<const N: usize>() -> [StateUpdateLog; N] {
+        use crate::core::{
+            EthereumBlockHash, EthereumBlockNumber, EthereumLogIndex, EthereumTransactionHash,
+            EthereumTransactionIndex,
+        };
+        use crate::ethereum::{BlockOrigin, EthOrigin, TransactionOrigin};
+        use web3::types::H256;
+
+        (0..N)
+            .map(|i| StateUpdateLog {
+                origin: EthOrigin {
+                    block: BlockOrigin {
+                        hash: EthereumBlockHash(H256::from_low_u64_le(i as u64 + 33)),
+                        number: EthereumBlockNumber(i as u64 + 12_000),
+                    },
+                    transaction: TransactionOrigin {
+                        hash: EthereumTransactionHash(H256::from_low_u64_le(i as u64 + 999)),
+                        index: EthereumTransactionIndex(i as u64),
+                    },
+                    log_index: EthereumLogIndex(i as u64),
+                },
+                global_root: GlobalRoot(StarkHash::from_hex_str(&"3".repeat(i + 1)).unwrap()),
+                block_number: StarknetBlockNumber::GENESIS + i as u64,
+            })
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap()
+    }
+
     /// Creates a set of consecutive [StarknetBlock]s starting from L2 genesis,
     /// with arbitrary other values.
     pub(crate) fn create_blocks<const N: usize>() -> [StarknetBlock; N] {
+        let hash =
+            |i: usize| StarknetBlockHash(StarkHash::from_hex_str(&"a".repeat(i + 3)).unwrap());
         (0..N)
             .map(|i| StarknetBlock {
                 number: StarknetBlockNumber::GENESIS + i as u64,
-                hash: StarknetBlockHash(StarkHash::from_hex_str(&"a".repeat(i + 3)).unwrap()),
+                hash: hash(i),
                 root: GlobalRoot(StarkHash::from_hex_str(&"f".repeat(i + 3)).unwrap()),
                 timestamp: StarknetBlockTimestamp(i as u64 + 500),
                 gas_price: GasPrice::from(i as u64),
                 sequencer_address: SequencerAddress(StarkHash::from_be_slice(&[i as u8]).unwrap()),
+                parent_hash: match i {
+                    0 => StarknetBlockHash(StarkHash::ZERO),
+                    i => hash(i - 1),
+                },
             })
             .collect::<Vec<_>>()
             .try_into()
@@ -259,14 +788,16 @@ pub(crate) mod test_utils {
                     EventKey(StarkHash::from_hex_str("deadbeef").unwrap()),
                 ],
             }],
-            execution_resources: transaction::ExecutionResources {
+            execution_resources: Some(transaction::ExecutionResources {
                 builtin_instance_counter:
                     transaction::execution_resources::BuiltinInstanceCounter::Empty(
                         transaction::execution_resources::EmptyBuiltinInstanceCounter {},
                     ),
                 n_steps: i as u64 + 987,
                 n_memory_holes: i as u64 + 1177,
-            },
+            }),
+            execution_status: None,
+            placeholder: false,
             l1_to_l2_consumed_message: None,
             l2_to_l1_messages: Vec::new(),
             transaction_hash: StarknetTransactionHash(
@@ -288,6 +819,273 @@ pub(crate) mod test_utils {
 mod tests {
     use super::*;
 
+    #[test]
+    fn chain_namespace_database_path() {
+        let primary = Path::new("/data/goerli.sqlite");
+        assert_eq!(ChainNamespace::Primary.database_path(primary), primary);
+        assert_eq!(
+            ChainNamespace::Shadow.database_path(primary),
+            Path::new("/data/goerli.shadow.sqlite")
+        );
+        assert_eq!(
+            ChainNamespace::Shadow.database_path(Path::new("mainnet")),
+            Path::new("mainnet.shadow")
+        );
+    }
+
+    mod statement_cache {
+        use super::Storage;
+
+        /// A mix of distinct queries served by the storage tables, more than fit into a small
+        /// cache.
+        const QUERIES: &[&str] = &[
+            "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash FROM starknet_blocks WHERE number = ?",
+            "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash FROM starknet_blocks WHERE hash = ? ORDER BY number ASC LIMIT 1",
+            "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash FROM starknet_blocks WHERE root = ? ORDER BY number ASC",
+            "SELECT root FROM starknet_blocks WHERE number = ?",
+            "SELECT root FROM starknet_blocks WHERE hash = ? ORDER BY number ASC LIMIT 1",
+            "SELECT number FROM starknet_blocks ORDER BY number DESC LIMIT ?",
+            "SELECT starknet_global_root FROM l1_state WHERE starknet_block_number = ?",
+            "SELECT tx FROM starknet_transactions WHERE hash = ?",
+            "SELECT receipt, block_hash FROM starknet_transactions WHERE hash = ?",
+            "SELECT tx, receipt FROM starknet_transactions WHERE block_hash = ? ORDER BY idx ASC",
+            "SELECT tx FROM starknet_transactions WHERE block_hash = ? AND idx = 0",
+            "SELECT count(1) FROM starknet_transactions WHERE block_hash = ?",
+            "SELECT data FROM starknet_state_updates WHERE block_number = ?",
+            "SELECT root FROM contract_states WHERE state_hash = ?",
+            "SELECT state_hash FROM contract_states WHERE root = ? ORDER BY state_hash",
+            "SELECT hash FROM contracts WHERE address = ?",
+            "SELECT abi FROM contract_code WHERE hash = ?",
+            "SELECT definition FROM contract_code WHERE hash = ?",
+        ];
+
+        /// Runs every query in [QUERIES] twice, round-robin, on a new connection and returns the
+        /// number of second runs which re-used the statement prepared by the first run.
+        fn cache_hits(storage: &Storage) -> usize {
+            let connection = storage.connection().unwrap();
+
+            let mut hits = 0;
+            for round in 0..2 {
+                for query in QUERIES {
+                    let mut statement = connection.prepare_cached(query).unwrap();
+                    // A freshly prepared statement has never been run.
+                    let runs = statement.get_status(rusqlite::StatementStatus::Run);
+                    if round == 1 && runs > 0 {
+                        hits += 1;
+                    }
+                    statement.query([0]).unwrap().next().unwrap();
+                }
+            }
+            hits
+        }
+
+        #[test]
+        fn only_long_lived_connections_are_warmed() {
+            use crate::storage::{
+                state::HOT_QUERIES, thread_statement_cache_stats, PrepareCounted,
+            };
+
+            let storage = Storage::in_memory().unwrap();
+            let connections = [
+                (storage.connection().unwrap(), 0),
+                (
+                    storage.long_lived_connection().unwrap(),
+                    HOT_QUERIES.len() as u64,
+                ),
+            ];
+
+            for (connection, expected_hits) in connections {
+                let before = thread_statement_cache_stats();
+                for query in HOT_QUERIES {
+                    connection.prepare_counted(query).unwrap();
+                }
+                let after = thread_statement_cache_stats();
+                assert_eq!(after.hits - before.hits, expected_hits);
+            }
+        }
+
+        #[test]
+        fn default_capacity_holds_all_queries() {
+            // Event queries are built per filter: with or without a block range, a contract
+            // address and keys.
+            const EVENT_QUERY_SHAPES: usize = 8;
+
+            // Each call site prepares at least one distinct statement, the tests excepted.
+            let sites = include_str!("storage/state.rs")
+                .split("\n#[cfg(test)]\nmod tests {")
+                .next()
+                .unwrap()
+                .matches("prepare_counted(")
+                .count();
+            assert!(sites > 0);
+
+            assert!(
+                sites + EVENT_QUERY_SHAPES <= super::DEFAULT_STATEMENT_CACHE_CAPACITY,
+                "{} call sites and {} event query shapes do not fit into {} cached statements",
+                sites,
+                EVENT_QUERY_SHAPES,
+                super::DEFAULT_STATEMENT_CACHE_CAPACITY
+            );
+        }
+
+        #[test]
+        fn small_capacity_churns() {
+            // Cycling through more queries than fit in the cache evicts each statement
+            // before it is used again.
+            let storage = Storage::in_memory_with_statement_cache_capacity(16).unwrap();
+            assert_eq!(cache_hits(&storage), 0);
+        }
+
+        #[test]
+        fn changed_capacity_applies_to_new_connections() {
+            let storage = Storage::in_memory_with_statement_cache_capacity(16).unwrap();
+
+            let previous =
+                storage.set_statement_cache_capacity(super::DEFAULT_STATEMENT_CACHE_CAPACITY);
+            assert_eq!(previous, 16);
+            assert_eq!(cache_hits(&storage.clone()), QUERIES.len());
+        }
+    }
+
+    #[test]
+    fn reorg_lowers_l1_l2_head() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let blocks = test_utils::create_blocks::<3>();
+        for block in &blocks {
+            StarknetBlocksTable::insert(&transaction, block).unwrap();
+        }
+        RefsTable::set_l1_l2_head(&transaction, Some(blocks[2].number)).unwrap();
+
+        let head = Storage::reorg(&transaction, blocks[1].number, ReorgLimit::default()).unwrap();
+        assert_eq!(head, Some(blocks[0].number));
+        assert_eq!(RefsTable::get_l1_l2_head(&transaction).unwrap(), head);
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&transaction).unwrap(),
+            Some(blocks[0].number)
+        );
+
+        let head = Storage::reorg(
+            &transaction,
+            StarknetBlockNumber::GENESIS,
+            ReorgLimit::default(),
+        )
+        .unwrap();
+        assert_eq!(head, None);
+        assert_eq!(RefsTable::get_l1_l2_head(&transaction).unwrap(), None);
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&transaction).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn reorg_l1_and_l2_separately() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let blocks = test_utils::create_blocks::<3>();
+        let updates = test_utils::create_state_update_logs::<3>();
+        for (block, update) in blocks.iter().zip(&updates) {
+            StarknetBlocksTable::insert(&transaction, block).unwrap();
+            L1StateTable::insert(&transaction, update).unwrap();
+        }
+        RefsTable::set_l1_l2_head(&transaction, Some(blocks[2].number)).unwrap();
+        let limit = ReorgLimit {
+            max_depth: 1,
+            force: false,
+        };
+
+        // The depth of an L1 reorg is measured from the latest L1 state update.
+        let error = Storage::reorg_l1(&transaction, updates[1].block_number, limit).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ReorgTooDeep>(),
+            Some(&ReorgTooDeep {
+                head: updates[2].block_number,
+                reorg_tail: updates[1].block_number,
+                max_depth: 1,
+            })
+        );
+
+        // An L1 reorg keeps the L2 blocks.
+        let head = Storage::reorg_l1(&transaction, updates[2].block_number, limit).unwrap();
+        assert_eq!(head, Some(blocks[1].number));
+        assert_eq!(
+            L1StateTable::get(&transaction, L1TableBlockId::Latest).unwrap(),
+            Some(updates[1].clone())
+        );
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&transaction).unwrap(),
+            Some(blocks[2].number)
+        );
+
+        // An L2 reorg keeps the L1 state.
+        let head = Storage::reorg_l2(&transaction, blocks[1].number, ReorgLimit::force()).unwrap();
+        assert_eq!(head, Some(blocks[0].number));
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&transaction).unwrap(),
+            Some(blocks[0].number)
+        );
+        assert_eq!(
+            L1StateTable::get(&transaction, L1TableBlockId::Latest).unwrap(),
+            Some(updates[1].clone())
+        );
+    }
+
+    #[test]
+    fn reorg_depth_limit() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let blocks = test_utils::create_blocks::<4>();
+        for block in &blocks {
+            StarknetBlocksTable::insert(&transaction, block).unwrap();
+        }
+        let limit = ReorgLimit {
+            max_depth: 2,
+            force: false,
+        };
+
+        // Reverting the 3 newest blocks is too deep, and leaves the chain untouched.
+        let error = Storage::reorg(&transaction, blocks[1].number, limit).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ReorgTooDeep>(),
+            Some(&ReorgTooDeep {
+                head: blocks[3].number,
+                reorg_tail: blocks[1].number,
+                max_depth: 2,
+            })
+        );
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&transaction).unwrap(),
+            Some(blocks[3].number)
+        );
+
+        // Reorgs within the limit, or past the head, are allowed.
+        Storage::reorg(&transaction, blocks[3].number + 1, limit).unwrap();
+        Storage::reorg(&transaction, blocks[2].number, limit).unwrap();
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&transaction).unwrap(),
+            Some(blocks[1].number)
+        );
+
+        // Forcing overrides the limit.
+        Storage::reorg(
+            &transaction,
+            StarknetBlockNumber::GENESIS,
+            ReorgLimit::force(),
+        )
+        .unwrap();
+        assert_eq!(
+            StarknetBlocksTable::get_latest_number(&transaction).unwrap(),
+            None
+        );
+    }
+
     #[test]
     fn schema_version_defaults_to_zero() {
         let mut conn = rusqlite::Connection::open_in_memory().unwrap();
@@ -313,8 +1111,306 @@ mod tests {
         conn.pragma_update(None, VERSION_KEY, DB_VERSION_CURRENT + 1)
             .unwrap();
 
-        // Migration should fail.
-        migrate_database(&mut conn).unwrap_err();
+        // Migration should fail, naming both revisions.
+        let error = migrate_database(&mut conn).unwrap_err().to_string();
+        assert!(
+            error.contains(&format!(
+                "revision {} is newer than the latest revision {}",
+                DB_VERSION_CURRENT + 1,
+                DB_VERSION_CURRENT
+            )),
+            "{}",
+            error
+        );
+    }
+
+    mod verify_chain {
+        use super::*;
+
+        /// A database whose genesis block is that of `chain`, which predates the chain record.
+        fn legacy_storage(chain: Chain) -> Storage {
+            let genesis = match chain {
+                Chain::Mainnet => {
+                    "0x047c3637b57c2b079b93c61539950c17e868a28f46cdef28f88521067f21e943"
+                }
+                Chain::Goerli => {
+                    "0x07d328a71faf48c5c3857e99f20a77b18522480956d1cd5bff1ff2df3c8b427b"
+                }
+            };
+            let genesis = StarknetBlock {
+                hash: StarknetBlockHash(stark_hash::StarkHash::from_hex_str(genesis).unwrap()),
+                ..test_utils::create_blocks::<1>()[0].clone()
+            };
+
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            StarknetBlocksTable::insert(&connection, &genesis).unwrap();
+            storage
+        }
+
+        #[test]
+        fn new_database_records_chain() {
+            let storage = Storage::in_memory().unwrap();
+            storage.verify_chain(Chain::Goerli, false).unwrap();
+
+            let connection = storage.connection().unwrap();
+            assert_eq!(
+                RefsTable::get_chain(&connection).unwrap(),
+                Some(Chain::Goerli)
+            );
+
+            storage.verify_chain(Chain::Goerli, false).unwrap();
+        }
+
+        #[test]
+        fn mismatch() {
+            let storage = Storage::in_memory().unwrap();
+            storage.verify_chain(Chain::Goerli, false).unwrap();
+
+            let error = storage.verify_chain(Chain::Mainnet, false).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<ChainMismatch>(),
+                Some(&ChainMismatch {
+                    stored: Chain::Goerli,
+                    configured: Chain::Mainnet,
+                })
+            );
+        }
+
+        #[test]
+        fn force_allows_mismatch() {
+            let storage = Storage::in_memory().unwrap();
+            storage.verify_chain(Chain::Goerli, false).unwrap();
+
+            storage.verify_chain(Chain::Mainnet, true).unwrap();
+
+            // The recorded chain is left untouched.
+            let connection = storage.connection().unwrap();
+            assert_eq!(
+                RefsTable::get_chain(&connection).unwrap(),
+                Some(Chain::Goerli)
+            );
+        }
+
+        #[test]
+        fn legacy_database_infers_chain_from_genesis() {
+            let storage = legacy_storage(Chain::Goerli);
+            let error = storage.verify_chain(Chain::Mainnet, false).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<ChainMismatch>(),
+                Some(&ChainMismatch {
+                    stored: Chain::Goerli,
+                    configured: Chain::Mainnet,
+                })
+            );
+
+            let storage = legacy_storage(Chain::Mainnet);
+            storage.verify_chain(Chain::Mainnet, false).unwrap();
+            let connection = storage.connection().unwrap();
+            assert_eq!(
+                RefsTable::get_chain(&connection).unwrap(),
+                Some(Chain::Mainnet)
+            );
+        }
+
+        #[test]
+        fn unknown_genesis() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            let genesis = test_utils::create_blocks::<1>()[0].clone();
+            StarknetBlocksTable::insert(&connection, &genesis).unwrap();
+
+            storage.verify_chain(Chain::Goerli, false).unwrap_err();
+            storage.verify_chain(Chain::Goerli, true).unwrap();
+            assert_eq!(RefsTable::get_chain(&connection).unwrap(), None);
+        }
+
+        #[test]
+        fn mismatch_is_refused_before_migrating() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("goerli.sqlite");
+            let storage = Storage::migrate(path.clone()).unwrap();
+            storage.verify_chain(Chain::Goerli, false).unwrap();
+
+            // The latest migration is still pending.
+            let connection = storage.connection().unwrap();
+            connection
+                .pragma_update(None, VERSION_KEY, DB_VERSION_CURRENT - 1)
+                .unwrap();
+
+            let error = Storage::migrate_verified(
+                path,
+                DEFAULT_STATEMENT_CACHE_CAPACITY,
+                StorageLimits::default(),
+                Chain::Mainnet,
+                false,
+            )
+            .err()
+            .unwrap();
+            assert_eq!(
+                error.downcast_ref::<ChainMismatch>(),
+                Some(&ChainMismatch {
+                    stored: Chain::Goerli,
+                    configured: Chain::Mainnet,
+                })
+            );
+            assert_eq!(schema_version(&connection).unwrap(), DB_VERSION_CURRENT - 1);
+        }
+
+        #[test]
+        fn new_database_records_chain_once_migrated() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("goerli.sqlite");
+            let storage = Storage::migrate_verified(
+                path,
+                DEFAULT_STATEMENT_CACHE_CAPACITY,
+                StorageLimits::default(),
+                Chain::Goerli,
+                false,
+            )
+            .unwrap();
+
+            let connection = storage.connection().unwrap();
+            assert_eq!(schema_version(&connection).unwrap(), DB_VERSION_CURRENT);
+            assert_eq!(
+                RefsTable::get_chain(&connection).unwrap(),
+                Some(Chain::Goerli)
+            );
+        }
+    }
+
+    mod read_only {
+        use super::*;
+
+        fn setup() -> (tempfile::TempDir, PathBuf, Storage) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("read_only.sqlite");
+            let writer = Storage::migrate(path.clone()).unwrap();
+            (dir, path, writer)
+        }
+
+        #[test]
+        fn sees_committed_writes() {
+            let (_dir, path, writer) = setup();
+            let reader = Storage::open_read_only(
+                path,
+                DEFAULT_STATEMENT_CACHE_CAPACITY,
+                StorageLimits::default(),
+            )
+            .unwrap();
+            assert!(reader.is_read_only());
+            assert!(!writer.is_read_only());
+
+            let blocks = test_utils::create_blocks::<2>();
+            let write = writer.connection().unwrap();
+            let read = reader.connection().unwrap();
+
+            StarknetBlocksTable::insert(&write, &blocks[0]).unwrap();
+            assert_eq!(
+                StarknetBlocksTable::get_latest_number(&read).unwrap(),
+                Some(blocks[0].number)
+            );
+
+            StarknetBlocksTable::insert(&write, &blocks[1]).unwrap();
+            assert_eq!(
+                StarknetBlocksTable::get_latest_number(&read).unwrap(),
+                Some(blocks[1].number)
+            );
+        }
+
+        #[test]
+        fn writes_are_rejected() {
+            let (_dir, path, _writer) = setup();
+            let reader = Storage::open_read_only(
+                path,
+                DEFAULT_STATEMENT_CACHE_CAPACITY,
+                StorageLimits::default(),
+            )
+            .unwrap();
+
+            let block = test_utils::create_blocks::<1>();
+            let connection = reader.connection().unwrap();
+            StarknetBlocksTable::insert(&connection, &block[0]).unwrap_err();
+        }
+
+        #[test]
+        fn newer_schema_is_refused() {
+            let (_dir, path, writer) = setup();
+            writer
+                .connection()
+                .unwrap()
+                .pragma_update(None, VERSION_KEY, DB_VERSION_CURRENT + 1)
+                .unwrap();
+
+            let error = Storage::open_read_only(
+                path,
+                DEFAULT_STATEMENT_CACHE_CAPACITY,
+                StorageLimits::default(),
+            )
+            .err()
+            .unwrap();
+            assert!(error.to_string().contains("newer"), "{}", error);
+        }
+
+        #[test]
+        fn missing_database_is_refused() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("missing.sqlite");
+
+            Storage::open_read_only(
+                path.clone(),
+                DEFAULT_STATEMENT_CACHE_CAPACITY,
+                StorageLimits::default(),
+            )
+            .err()
+            .unwrap();
+            assert!(!path.exists());
+        }
+    }
+
+    mod pending_migrations {
+        use super::*;
+
+        #[test]
+        fn empty_database() {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+
+            let pending = Storage::pending_migrations(&conn).unwrap();
+            assert_eq!(pending, (1..=DB_VERSION_CURRENT).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn partially_migrated() {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+            let version = DB_VERSION_CURRENT - 3;
+            conn.pragma_update(None, VERSION_KEY, version).unwrap();
+
+            let pending = Storage::pending_migrations(&conn).unwrap();
+            assert_eq!(
+                pending,
+                ((version + 1)..=DB_VERSION_CURRENT).collect::<Vec<_>>()
+            );
+            // Nothing should have been migrated.
+            assert_eq!(schema_version(&conn).unwrap(), version);
+        }
+
+        #[test]
+        fn up_to_date() {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            migrate_database(&mut conn).unwrap();
+
+            let pending = Storage::pending_migrations(&conn).unwrap();
+            assert!(pending.is_empty());
+        }
+
+        #[test]
+        fn fails_if_db_is_newer() {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+            conn.pragma_update(None, VERSION_KEY, DB_VERSION_CURRENT + 1)
+                .unwrap();
+
+            Storage::pending_migrations(&conn).unwrap_err();
+        }
     }
 
     #[test]
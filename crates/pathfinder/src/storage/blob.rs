@@ -0,0 +1,87 @@
+//! Bounded (de)compression of the zstd blobs stored in the database.
+//!
+//! A corrupted (or maliciously crafted) row could otherwise decompress into gigabytes of data
+//! and exhaust the node's memory while serving a single request.
+
+use std::io::Read;
+
+/// Default upper bound on the decompressed size of a single blob.
+pub const DEFAULT_MAX_BLOB_SIZE: usize = 8 * 1024 * 1024;
+
+/// Serialized payloads larger than this are logged when written, as they are far larger than
+/// any transaction, receipt or state update seen so far.
+const LARGE_BLOB_WARNING_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BlobError {
+    #[error("Blob decompresses to more than {limit} bytes")]
+    TooLarge { limit: usize },
+    #[error("Corrupt blob: {0}")]
+    Corrupt(#[from] std::io::Error),
+}
+
+/// Decompresses a zstd blob, failing with [BlobError::TooLarge] if it expands beyond `limit`
+/// bytes.
+pub(crate) fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, BlobError> {
+    let decoder = zstd::stream::read::Decoder::with_buffer(data)?;
+
+    // Read one byte past the limit to tell a blob of exactly `limit` bytes from a larger one.
+    let mut buffer = Vec::new();
+    decoder.take(limit as u64 + 1).read_to_end(&mut buffer)?;
+
+    if buffer.len() > limit {
+        return Err(BlobError::TooLarge { limit });
+    }
+
+    Ok(buffer)
+}
+
+/// Logs serialized payloads which are unusually large, or which are too large to be read back
+/// with the given `limit`.
+pub(crate) fn check_size(kind: &str, serialized: &[u8], limit: usize) {
+    if serialized.len() > limit {
+        tracing::warn!(
+            size=%serialized.len(),
+            %limit,
+            "Storing {} which exceeds the maximum blob size, reading it back will fail",
+            kind
+        );
+    } else if serialized.len() > LARGE_BLOB_WARNING_SIZE {
+        tracing::warn!(size=%serialized.len(), "Storing unusually large {}", kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, BlobError};
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn within_limit() {
+        let data = vec![7u8; 1000];
+        let compressed = zstd::bulk::compress(&data, 10).unwrap();
+
+        assert_eq!(decompress(&compressed, 1000).unwrap(), data);
+    }
+
+    #[test]
+    fn beyond_limit() {
+        // Highly compressible, so the blob itself is tiny.
+        let data = vec![0u8; 1024 * 1024];
+        let compressed = zstd::bulk::compress(&data, 10).unwrap();
+        assert!(compressed.len() < 1024);
+
+        assert_matches!(
+            decompress(&compressed, 1024 * 1024 - 1),
+            Err(BlobError::TooLarge { limit }) => assert_eq!(limit, 1024 * 1024 - 1)
+        );
+    }
+
+    #[test]
+    fn corrupt() {
+        assert_matches!(
+            decompress(b"not zstd at all", 1024),
+            Err(BlobError::Corrupt(_))
+        );
+    }
+}
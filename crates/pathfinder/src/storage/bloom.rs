@@ -0,0 +1,93 @@
+//! Per-block bloom filters over the keys of the emitted events.
+//!
+//! These let event queries filtering on keys skip blocks which cannot contain a matching event,
+//! without having to consult the keys' full-text index for each of them.
+
+use sha3::{Digest, Keccak256};
+use stark_hash::StarkHash;
+
+/// Size of the filter in bytes.
+const BLOOM_BYTES: usize = 256;
+/// Number of bits set for each inserted key.
+const BLOOM_HASHES: usize = 3;
+
+/// A 2048 bit bloom filter of event keys, set in the same manner as Ethereum's log blooms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct KeyBloom([u8; BLOOM_BYTES]);
+
+impl Default for KeyBloom {
+    fn default() -> Self {
+        Self([0u8; BLOOM_BYTES])
+    }
+}
+
+impl KeyBloom {
+    /// Reads a filter as stored in the database.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let bytes: [u8; BLOOM_BYTES] = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid bloom filter length {}", bytes.len()))?;
+        Ok(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn insert(&mut self, key: &StarkHash) {
+        for (byte, mask) in Self::bits(key) {
+            self.0[byte] |= mask;
+        }
+    }
+
+    /// False if `key` was definitely never inserted.
+    pub fn may_contain(&self, key: &StarkHash) -> bool {
+        Self::bits(key).all(|(byte, mask)| self.0[byte] & mask != 0)
+    }
+
+    /// Byte index and bit mask of each bit set for `key`.
+    ///
+    /// Keys may be chosen freely by contracts, so they are hashed first instead of using their
+    /// bits directly. Each pair of digest bytes then selects one of the 2048 bits.
+    fn bits(key: &StarkHash) -> impl Iterator<Item = (usize, u8)> {
+        let digest = Keccak256::digest(key.as_be_bytes());
+        (0..BLOOM_HASHES).map(move |i| {
+            let bit =
+                ((digest[2 * i] as usize) << 8 | digest[2 * i + 1] as usize) % (8 * BLOOM_BYTES);
+            (BLOOM_BYTES - 1 - bit / 8, 1u8 << (bit % 8))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserted_keys_are_contained() {
+        let keys = (0..50u8)
+            .map(|i| StarkHash::from_be_slice(&[i]).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut bloom = KeyBloom::default();
+        keys.iter().for_each(|key| bloom.insert(key));
+
+        assert!(keys.iter().all(|key| bloom.may_contain(key)));
+    }
+
+    #[test]
+    fn empty_contains_nothing() {
+        let bloom = KeyBloom::default();
+        assert!(!bloom.may_contain(&StarkHash::ZERO));
+        assert!(!bloom.may_contain(&StarkHash::from_be_slice(&[1]).unwrap()));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut bloom = KeyBloom::default();
+        bloom.insert(&StarkHash::from_be_slice(b"key").unwrap());
+
+        assert_eq!(KeyBloom::from_bytes(bloom.as_bytes()).unwrap(), bloom);
+        KeyBloom::from_bytes(&[0u8; 32]).unwrap_err();
+    }
+}
@@ -69,14 +69,33 @@ impl ContractCodeTable {
         Ok(())
     }
 
+    /// Inserts only the hash of a class whose definition could not be downloaded, so that
+    /// contracts of this class can be stored. The definition is added later by
+    /// [backfill](Self::backfill).
+    ///
+    /// Does nothing if the class is already present.
+    pub fn insert_without_definition(
+        connection: &Connection,
+        hash: ClassHash,
+    ) -> anyhow::Result<()> {
+        connection.execute(
+            "INSERT OR IGNORE INTO contract_code (hash) VALUES (?)",
+            [&hash.0.to_be_bytes()[..]],
+        )?;
+        Ok(())
+    }
+
     /// Gets the specified contract's [code](ContractCode).
+    ///
+    /// Fails with [DefinitionNotAvailable] if the contract's class is stored without its
+    /// definition, i.e. its download was deferred.
     pub fn get_code(
         transaction: &Transaction,
         address: ContractAddress,
     ) -> anyhow::Result<Option<ContractCode>> {
         let row = transaction
             .query_row(
-                "SELECT contract_code.bytecode, contract_code.abi
+                "SELECT contract_code.hash, contract_code.bytecode, contract_code.abi
                 FROM contracts
                 JOIN contract_code ON contracts.hash = contract_code.hash
                 WHERE contracts.address = :address
@@ -85,17 +104,22 @@ impl ContractCodeTable {
                     ":address": &address.0.to_be_bytes()[..]
                 },
                 |row| {
-                    let bytecode: Vec<u8> = row.get("bytecode")?;
-                    let abi: Vec<u8> = row.get("abi")?;
+                    let hash: Vec<u8> = row.get("hash")?;
+                    let bytecode: Option<Vec<u8>> = row.get("bytecode")?;
+                    let abi: Option<Vec<u8>> = row.get("abi")?;
 
-                    Ok((bytecode, abi))
+                    Ok((hash, bytecode, abi))
                 },
             )
             .optional()?;
 
         let (bytecode, abi) = match row {
             None => return Ok(None),
-            Some((bytecode, abi)) => (bytecode, abi),
+            Some((_, Some(bytecode), Some(abi))) => (bytecode, abi),
+            Some((hash, _, _)) => {
+                let hash = StarkHash::from_be_slice(&hash).context("Parsing class hash")?;
+                return Err(DefinitionNotAvailable(ClassHash(hash)).into());
+            }
         };
 
         // It might be dangerious to not have some upper bound on the compressed size.
@@ -117,6 +141,51 @@ impl ContractCodeTable {
         Ok(Some(ContractCode { bytecode, abi }))
     }
 
+    /// Gets the ABI of the specified class, as a json blob.
+    ///
+    /// Returns [None] if the class or its definition is not stored.
+    pub fn get_abi(connection: &Connection, hash: ClassHash) -> anyhow::Result<Option<Vec<u8>>> {
+        let abi: Option<Option<Vec<u8>>> = connection
+            .query_row(
+                "SELECT abi FROM contract_code WHERE hash = :hash",
+                named_params! {
+                    ":hash": &hash.0.to_be_bytes()[..]
+                },
+                |row| row.get("abi"),
+            )
+            .optional()?;
+
+        let abi = match abi.flatten() {
+            Some(abi) => abi,
+            None => return Ok(None),
+        };
+
+        let abi = zstd::decode_all(&*abi).context("Corruption: invalid compressed column (abi)")?;
+
+        Ok(Some(abi))
+    }
+
+    /// Stores the given class, replacing the class' row if it exists without a definition.
+    ///
+    /// Used to backfill classes which were only partially stored by older versions.
+    pub fn backfill(connection: &Connection, contract: &CompressedContract) -> anyhow::Result<()> {
+        connection.execute(
+            r"INSERT INTO contract_code ( hash,  bytecode,  abi,  definition)
+                             VALUES (:hash, :bytecode, :abi, :definition)
+                ON CONFLICT(hash) DO UPDATE SET
+                    bytecode = excluded.bytecode,
+                    abi = excluded.abi,
+                    definition = excluded.definition",
+            named_params! {
+                ":hash": &contract.hash.0.to_be_bytes()[..],
+                ":bytecode": &contract.bytecode[..],
+                ":abi": &contract.abi[..],
+                ":definition": &contract.definition[..],
+            },
+        )?;
+        Ok(())
+    }
+
     /// Returns true for each [ClassHash] if the class definition already exists in the table.
     pub fn exists(connection: &Connection, contracts: &[ClassHash]) -> anyhow::Result<Vec<bool>> {
         let mut stmt = connection.prepare("select 1 from contract_code where hash = ?")?;
@@ -128,6 +197,114 @@ impl ContractCodeTable {
     }
 }
 
+/// Error returned by [ContractCodeTable::get_code] for a class which is stored without its
+/// definition, because downloading it was deferred and has not succeeded yet.
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq)]
+#[error("the definition of class {0:?} has not been downloaded yet")]
+pub struct DefinitionNotAvailable(pub ClassHash);
+
+/// Tracks the backfill of class definitions which are referenced by contracts or contract
+/// states, but missing from [ContractCodeTable].
+pub struct ClassBackfillTable {}
+
+impl ClassBackfillTable {
+    /// Returns up to `limit` referenced classes without a stored definition, in order of their
+    /// hashes, starting after the class hash `after`. Classes whose backfill
+    /// [failed](Self::record_failure) are excluded, as are the classes queued in
+    /// [SyncRetryQueueTable](crate::storage::SyncRetryQueueTable) which are not due for a retry
+    /// at unix time `now`.
+    ///
+    /// Passing the last hash of a batch as `after` reads the next batch without revisiting the
+    /// classes before it.
+    pub fn missing(
+        connection: &Connection,
+        after: Option<ClassHash>,
+        now: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ClassHash>> {
+        // Each side of the UNION reads its class hash index in order from the cursor, so that the
+        // LIMIT stops the query early.
+        let mut statement = connection
+            .prepare(
+                r"SELECT hash FROM contracts WHERE hash > :after
+                    AND hash NOT IN (SELECT hash FROM contract_code WHERE definition IS NOT NULL)
+                    AND hash NOT IN (SELECT hash FROM class_backfill_failures)
+                    AND hash NOT IN (
+                        SELECT identifier FROM sync_retry_queue
+                        WHERE item_type = 'class' AND next_attempt_at > :now
+                    )
+                UNION
+                SELECT hash FROM contract_states WHERE hash > :after
+                    AND hash NOT IN (SELECT hash FROM contract_code WHERE definition IS NOT NULL)
+                    AND hash NOT IN (SELECT hash FROM class_backfill_failures)
+                    AND hash NOT IN (
+                        SELECT identifier FROM sync_retry_queue
+                        WHERE item_type = 'class' AND next_attempt_at > :now
+                    )
+                ORDER BY hash
+                LIMIT :limit",
+            )
+            .context("Preparing statement")?;
+
+        // Every hash compares greater than the empty blob.
+        let after = after
+            .map(|hash| hash.0.to_be_bytes().to_vec())
+            .unwrap_or_default();
+        let mut rows = statement
+            .query(named_params! {
+                ":after": after,
+                ":now": now,
+                ":limit": limit,
+            })
+            .context("Executing query")?;
+
+        let mut hashes = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let hash = row.get_ref_unwrap("hash").as_blob()?;
+            let hash = StarkHash::from_be_slice(hash).context("Parsing class hash")?;
+            hashes.push(ClassHash(hash));
+        }
+
+        Ok(hashes)
+    }
+
+    /// Records that the class could not be backfilled, which excludes it from
+    /// [missing](Self::missing).
+    pub fn record_failure(
+        connection: &Connection,
+        hash: ClassHash,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        connection.execute(
+            "INSERT OR REPLACE INTO class_backfill_failures (hash, reason) VALUES (:hash, :reason)",
+            named_params! {
+                ":hash": &hash.0.to_be_bytes()[..],
+                ":reason": reason,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Returns all recorded failures along with their reasons, in order of the class hashes.
+    pub fn failures(connection: &Connection) -> anyhow::Result<Vec<(ClassHash, String)>> {
+        let mut statement = connection
+            .prepare("SELECT hash, reason FROM class_backfill_failures ORDER BY hash")
+            .context("Preparing statement")?;
+
+        let mut rows = statement.query([]).context("Executing query")?;
+
+        let mut failures = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let hash = row.get_ref_unwrap("hash").as_blob()?;
+            let hash = StarkHash::from_be_slice(hash).context("Parsing class hash")?;
+            let reason = row.get("reason")?;
+            failures.push((ClassHash(hash), reason));
+        }
+
+        Ok(failures)
+    }
+}
+
 /// Stores the mapping from StarkNet contract [address](ContractAddress) to [hash](ClassHash).
 pub struct ContractsTable {}
 
@@ -201,6 +378,34 @@ mod tests {
         ContractsTable::upsert(&transaction, address, hash).unwrap_err();
     }
 
+    #[test]
+    fn class_without_definition() {
+        let storage = Storage::in_memory().unwrap();
+        let mut conn = storage.connection().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        let address = ContractAddress(StarkHash::from_hex_str("abc").unwrap());
+        let hash = ClassHash(StarkHash::from_hex_str("123").unwrap());
+
+        ContractCodeTable::insert_without_definition(&transaction, hash).unwrap();
+        // Inserting twice is fine.
+        ContractCodeTable::insert_without_definition(&transaction, hash).unwrap();
+        ContractsTable::upsert(&transaction, address, hash).unwrap();
+
+        assert_eq!(
+            ContractCodeTable::exists(&transaction, &[hash]).unwrap(),
+            vec![true]
+        );
+        assert_eq!(
+            ClassBackfillTable::missing(&transaction, None, 0, 10).unwrap(),
+            vec![hash]
+        );
+        assert_eq!(
+            ContractCodeTable::get_abi(&transaction, hash).unwrap(),
+            None
+        );
+    }
+
     #[test]
     fn get_hash() {
         let storage = Storage::in_memory().unwrap();
@@ -219,6 +424,25 @@ mod tests {
         assert_eq!(result, Some(hash));
     }
 
+    #[test]
+    fn get_abi() {
+        let storage = Storage::in_memory().unwrap();
+        let mut conn = storage.connection().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        let hash = ClassHash(StarkHash::from_hex_str("123").unwrap());
+        let abi = br#"[{"type":"constructor"}]"#;
+
+        ContractCodeTable::insert(&transaction, hash, &abi[..], &[][..], &[][..]).unwrap();
+
+        let result = ContractCodeTable::get_abi(&transaction, hash).unwrap();
+        assert_eq!(result, Some(abi.to_vec()));
+
+        let missing = ClassHash(StarkHash::from_hex_str("456").unwrap());
+        let result = ContractCodeTable::get_abi(&transaction, missing).unwrap();
+        assert_eq!(result, None);
+    }
+
     #[test]
     fn get_code() {
         let storage = Storage::in_memory().unwrap();
@@ -251,6 +475,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_code_without_definition() {
+        let storage = Storage::in_memory().unwrap();
+        let mut conn = storage.connection().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        let address = ContractAddress(StarkHash::from_hex_str("abc").unwrap());
+        let hash = ClassHash(StarkHash::from_hex_str("123").unwrap());
+
+        ContractCodeTable::insert_without_definition(&transaction, hash).unwrap();
+        ContractsTable::upsert(&transaction, address, hash).unwrap();
+
+        let error = ContractCodeTable::get_code(&transaction, address).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DefinitionNotAvailable>(),
+            Some(&DefinitionNotAvailable(hash))
+        );
+    }
+
     #[test]
     fn contracts_exist() {
         let storage = Storage::in_memory().unwrap();
@@ -274,4 +517,119 @@ mod tests {
 
         assert_eq!(result, expected);
     }
+
+    mod class_backfill {
+        use super::*;
+        use crate::core::{ContractRoot, ContractStateHash};
+        use crate::storage::{ContractsStateTable, RetryItem, SyncRetryQueueTable};
+
+        #[test]
+        fn missing_classes() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let stored = ClassHash(StarkHash::from_hex_str("1").unwrap());
+            let without_definition = ClassHash(StarkHash::from_hex_str("2").unwrap());
+            let state_only = ClassHash(StarkHash::from_hex_str("3").unwrap());
+            let failed = ClassHash(StarkHash::from_hex_str("4").unwrap());
+
+            ContractCodeTable::insert(&transaction, stored, &[][..], &[][..], &[1][..]).unwrap();
+            ContractsTable::upsert(
+                &transaction,
+                ContractAddress(StarkHash::from_hex_str("a").unwrap()),
+                stored,
+            )
+            .unwrap();
+
+            transaction
+                .execute(
+                    "INSERT INTO contract_code (hash) VALUES (?)",
+                    [&without_definition.0.to_be_bytes()[..]],
+                )
+                .unwrap();
+            ContractsTable::upsert(
+                &transaction,
+                ContractAddress(StarkHash::from_hex_str("b").unwrap()),
+                without_definition,
+            )
+            .unwrap();
+
+            for (i, hash) in [state_only, failed].into_iter().enumerate() {
+                ContractsStateTable::upsert(
+                    &transaction,
+                    ContractStateHash(StarkHash::from_be_slice(&[i as u8 + 100]).unwrap()),
+                    hash,
+                    ContractRoot(StarkHash::ZERO),
+                )
+                .unwrap();
+            }
+            ClassBackfillTable::record_failure(&transaction, failed, "reason").unwrap();
+
+            let missing = ClassBackfillTable::missing(&transaction, None, 0, 10).unwrap();
+            assert_eq!(missing, vec![without_definition, state_only]);
+
+            let missing = ClassBackfillTable::missing(&transaction, None, 0, 1).unwrap();
+            assert_eq!(missing, vec![without_definition]);
+
+            // The next batch continues after the cursor.
+            let missing =
+                ClassBackfillTable::missing(&transaction, Some(without_definition), 0, 10).unwrap();
+            assert_eq!(missing, vec![state_only]);
+            let missing =
+                ClassBackfillTable::missing(&transaction, Some(state_only), 0, 10).unwrap();
+            assert_eq!(missing, vec![]);
+
+            let failures = ClassBackfillTable::failures(&transaction).unwrap();
+            assert_eq!(failures, vec![(failed, "reason".to_owned())]);
+
+            // Queued classes are skipped until their retry is due.
+            let entry = SyncRetryQueueTable::record_failure(
+                &transaction,
+                RetryItem::Class(without_definition),
+                "gateway error",
+                100,
+            )
+            .unwrap();
+            let missing =
+                ClassBackfillTable::missing(&transaction, None, entry.next_attempt_at - 1, 10)
+                    .unwrap();
+            assert_eq!(missing, vec![state_only]);
+            let missing =
+                ClassBackfillTable::missing(&transaction, None, entry.next_attempt_at, 10).unwrap();
+            assert_eq!(missing, vec![without_definition, state_only]);
+        }
+
+        #[test]
+        fn backfill_replaces_row_without_definition() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
+
+            let hash = ClassHash(StarkHash::from_hex_str("123").unwrap());
+            transaction
+                .execute(
+                    "INSERT INTO contract_code (hash) VALUES (?)",
+                    [&hash.0.to_be_bytes()[..]],
+                )
+                .unwrap();
+
+            let contract = CompressedContract {
+                abi: vec![1],
+                bytecode: vec![2],
+                definition: vec![3],
+                hash,
+            };
+            ContractCodeTable::backfill(&transaction, &contract).unwrap();
+
+            let definition: Vec<u8> = transaction
+                .query_row(
+                    "SELECT definition FROM contract_code WHERE hash = ?",
+                    [&hash.0.to_be_bytes()[..]],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(definition, vec![3]);
+        }
+    }
 }
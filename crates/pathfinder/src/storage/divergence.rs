@@ -0,0 +1,174 @@
+//! Blocks whose recomputed global root did not match the expected root, and on which sync
+//! halted instead of storing them.
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use stark_hash::StarkHash;
+
+use crate::core::{
+    ContractAddress, ContractStateHash, GlobalRoot, StarknetBlockHash, StarknetBlockNumber,
+};
+
+/// The global root computed for a block did not match the block's `state_root`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootDivergence {
+    pub block_number: StarknetBlockNumber,
+    pub block_hash: StarknetBlockHash,
+    /// The root after applying the block's state update to the parent state.
+    pub computed_root: GlobalRoot,
+    /// The `state_root` of the block header.
+    pub expected_root: GlobalRoot,
+    /// The root which L1 committed to for the block, if known.
+    pub l1_root: Option<GlobalRoot>,
+    /// The contracts which the state update touched.
+    pub contracts: Vec<ContractDivergence>,
+    /// Unix timestamp in seconds at which the divergence was detected.
+    pub recorded_at: u64,
+}
+
+/// The state of a contract touched by a [RootDivergence].
+///
+/// The sequencer does not publish contract state hashes, so the computed leaf of the global tree
+/// can only be checked for consistency with the contract's stored class hash and storage root.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractDivergence {
+    pub address: ContractAddress,
+    /// The contract's leaf in the computed global tree.
+    pub computed_state_hash: ContractStateHash,
+    /// The state hash recomputed from the contract's class hash and storage root, `None` if
+    /// either of them is missing.
+    pub recomputed_state_hash: Option<ContractStateHash>,
+}
+
+impl ContractDivergence {
+    /// Whether the computed leaf is inconsistent with the contract's stored state.
+    pub fn is_inconsistent(&self) -> bool {
+        self.recomputed_state_hash != Some(self.computed_state_hash)
+    }
+}
+
+/// Records the [RootDivergence]s on which sync halted.
+pub struct RootDivergenceTable {}
+
+impl RootDivergenceTable {
+    /// Records `divergence`, replacing any earlier record for the same block.
+    pub fn insert(connection: &Connection, divergence: &RootDivergence) -> anyhow::Result<()> {
+        let contracts =
+            serde_json::to_string(&divergence.contracts).context("Serialize contracts")?;
+
+        connection
+            .execute(
+                r"INSERT OR REPLACE INTO root_divergences
+                        ( block_number,  block_hash,  computed_root,  expected_root,  l1_root,  contracts,  recorded_at)
+                VALUES  (:block_number, :block_hash, :computed_root, :expected_root, :l1_root, :contracts, :recorded_at)",
+                named_params! {
+                    ":block_number": divergence.block_number.0,
+                    ":block_hash": &divergence.block_hash.0.to_be_bytes()[..],
+                    ":computed_root": &divergence.computed_root.0.to_be_bytes()[..],
+                    ":expected_root": &divergence.expected_root.0.to_be_bytes()[..],
+                    ":l1_root": divergence.l1_root.map(|root| root.0.to_be_bytes().to_vec()),
+                    ":contracts": contracts,
+                    ":recorded_at": divergence.recorded_at,
+                },
+            )
+            .context("Insert into root_divergences table")?;
+
+        Ok(())
+    }
+
+    /// Returns the most recently detected divergence, if any.
+    pub fn get_latest(connection: &Connection) -> anyhow::Result<Option<RootDivergence>> {
+        connection
+            .query_row(
+                r"SELECT block_number, block_hash, computed_root, expected_root, l1_root, contracts, recorded_at
+                    FROM root_divergences ORDER BY recorded_at DESC, block_number DESC LIMIT 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>("block_number")?,
+                        row.get::<_, Vec<u8>>("block_hash")?,
+                        row.get::<_, Vec<u8>>("computed_root")?,
+                        row.get::<_, Vec<u8>>("expected_root")?,
+                        row.get::<_, Option<Vec<u8>>>("l1_root")?,
+                        row.get::<_, String>("contracts")?,
+                        row.get::<_, i64>("recorded_at")?,
+                    ))
+                },
+            )
+            .optional()
+            .context("Query root_divergences table")?
+            .map(
+                |(number, hash, computed, expected, l1_root, contracts, recorded_at)| {
+                    let parse = |bytes: &[u8]| {
+                        StarkHash::from_be_slice(bytes).context("Parsing stored hash")
+                    };
+
+                    Ok(RootDivergence {
+                        block_number: StarknetBlockNumber(number as u64),
+                        block_hash: StarknetBlockHash(parse(&hash)?),
+                        computed_root: GlobalRoot(parse(&computed)?),
+                        expected_root: GlobalRoot(parse(&expected)?),
+                        l1_root: l1_root
+                            .map(|root| parse(&root).map(GlobalRoot))
+                            .transpose()?,
+                        contracts: serde_json::from_str(&contracts)
+                            .context("Parsing contracts")?,
+                        recorded_at: recorded_at as u64,
+                    })
+                },
+            )
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+
+    fn hash(hex: &str) -> StarkHash {
+        StarkHash::from_hex_str(hex).unwrap()
+    }
+
+    #[test]
+    fn insert_and_get_latest() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        assert_eq!(RootDivergenceTable::get_latest(&connection).unwrap(), None);
+
+        let divergence = RootDivergence {
+            block_number: StarknetBlockNumber(7),
+            block_hash: StarknetBlockHash(hash("b7")),
+            computed_root: GlobalRoot(hash("c0")),
+            expected_root: GlobalRoot(hash("e0")),
+            l1_root: None,
+            contracts: vec![ContractDivergence {
+                address: ContractAddress(hash("abc")),
+                computed_state_hash: ContractStateHash(hash("1")),
+                recomputed_state_hash: Some(ContractStateHash(hash("2"))),
+            }],
+            recorded_at: 100,
+        };
+        RootDivergenceTable::insert(&connection, &divergence).unwrap();
+        assert_eq!(
+            RootDivergenceTable::get_latest(&connection).unwrap(),
+            Some(divergence.clone())
+        );
+        assert!(divergence.contracts[0].is_inconsistent());
+
+        let later = RootDivergence {
+            block_number: StarknetBlockNumber(5),
+            l1_root: Some(GlobalRoot(hash("e1"))),
+            contracts: vec![],
+            recorded_at: 200,
+            ..divergence
+        };
+        RootDivergenceTable::insert(&connection, &later).unwrap();
+        assert_eq!(
+            RootDivergenceTable::get_latest(&connection).unwrap(),
+            Some(later)
+        );
+    }
+}
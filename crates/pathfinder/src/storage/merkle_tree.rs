@@ -102,6 +102,11 @@ pub enum PersistedNode {
     Leaf,
 }
 
+/// The data of a [PersistedNode] read from an [RcNodeStorage] could not be deserialized.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct CorruptNodeError(String);
+
 impl PersistedNode {
     fn serialize(self) -> Vec<u8> {
         match self {
@@ -133,7 +138,7 @@ impl PersistedNode {
         }
     }
 
-    fn deserialize(bytes: &[u8]) -> anyhow::Result<PersistedNode> {
+    fn deserialize(bytes: &[u8]) -> Result<PersistedNode, CorruptNodeError> {
         match bytes.len() {
             0 => Ok(PersistedNode::Leaf),
             64 => {
@@ -141,10 +146,12 @@ impl PersistedNode {
                 let left: [u8; 32] = bytes[..32].try_into().unwrap();
                 let right: [u8; 32] = bytes[32..].try_into().unwrap();
 
-                let left = StarkHash::from_be_bytes(left)
-                    .context("Binary node's left hash is corrupt.")?;
-                let right = StarkHash::from_be_bytes(right)
-                    .context("Binary node's right hash is corrupt.")?;
+                let left = StarkHash::from_be_bytes(left).map_err(|_| {
+                    CorruptNodeError("Binary node's left hash is corrupt.".to_owned())
+                })?;
+                let right = StarkHash::from_be_bytes(right).map_err(|_| {
+                    CorruptNodeError("Binary node's right hash is corrupt.".to_owned())
+                })?;
 
                 Ok(PersistedNode::Binary(PersistedBinaryNode { left, right }))
             }
@@ -154,21 +161,27 @@ impl PersistedNode {
                 let path = bytes[32..64].to_vec();
                 let length = bytes[64] as usize;
 
-                anyhow::ensure!(length <= 251, "Edge node's length is too big: {}.", length);
+                if length > 251 {
+                    return Err(CorruptNodeError(format!(
+                        "Edge node's length is too big: {}.",
+                        length
+                    )));
+                }
                 // Grab the __last__ `length` bits. Path is stored in MSB format, which means LSB
                 // is always stored in the last bit. Since the path may vary in length we must take
                 // the last bits.
                 let path = path.view_bits::<Msb0>()[256 - length..].to_bitvec();
 
-                let child = StarkHash::from_be_bytes(child)
-                    .context("Edge node's child hash is corrupt.")?;
+                let child = StarkHash::from_be_bytes(child).map_err(|_| {
+                    CorruptNodeError("Edge node's child hash is corrupt.".to_owned())
+                })?;
 
                 Ok(PersistedNode::Edge(PersistedEdgeNode { path, child }))
             }
-            other => anyhow::bail!(
+            other => Err(CorruptNodeError(format!(
                 "Failed to deserialize node, data size is incorrect: {} bytes.",
                 other
-            ),
+            ))),
         }
     }
 }
@@ -269,7 +282,7 @@ impl<'a> RcNodeStorage<'a> {
             )
             .optional()?;
 
-        node.transpose()
+        Ok(node.transpose()?)
     }
 
     /// Deletes the given node from storage, and decrements the reference count of the node's
@@ -283,7 +296,6 @@ impl<'a> RcNodeStorage<'a> {
     ///
     /// Does not perform rollback on failure. This implies that you should rollback the [RcNodeStorage's](RcNodeStorage) transaction
     /// if this call returns an error to prevent database corruption.
-    #[cfg(test)]
     fn delete_node(&self, key: StarkHash) -> anyhow::Result<()> {
         let hash = key.to_be_bytes();
 
@@ -313,7 +325,15 @@ impl<'a> RcNodeStorage<'a> {
 
     /// Decrements the reference count of the node and automatically deletes it
     /// if the count becomes zero.
-    #[cfg(test)]
+    ///
+    /// This is how the nodes of an old tree are garbage collected: decrementing its root
+    /// deletes exactly those nodes which are no longer referenced by any other stored node
+    /// or root.
+    ///
+    /// ### Warning
+    ///
+    /// Does not perform rollback on failure. This implies that you should rollback the [RcNodeStorage's](RcNodeStorage) transaction
+    /// if this call returns an error to prevent database corruption.
     pub fn decrement_ref_count(&self, key: StarkHash) -> anyhow::Result<()> {
         let hash = key.to_be_bytes();
 
@@ -325,7 +345,9 @@ impl<'a> RcNodeStorage<'a> {
                     ":hash": &hash[..],
                 },
                 |row| {
-                    let ref_count: u16 = row.get("ref_count")?;
+                    // Nodes shared by many trees, such as common leaf values, easily exceed
+                    // smaller integer types.
+                    let ref_count: i64 = row.get("ref_count")?;
 
                     Ok(ref_count)
                 },
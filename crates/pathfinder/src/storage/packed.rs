@@ -0,0 +1,396 @@
+//! The encoding of the transaction and receipt blobs of `starknet_transactions`.
+//!
+//! These were originally stored as JSON, in which every felt is a decimal string. Transactions
+//! with thousands of calldata elements are slow to (de)serialize that way, so they are now
+//! stored in a packed format instead: a [PACKED] tag byte, followed by the length prefixed JSON
+//! of the value without its felt arrays, followed by the felt arrays as raw 32 byte big-endian
+//! values. Each felt array is prefixed by its length as a little-endian `u32`, with [ABSENT]
+//! marking a missing optional array.
+//!
+//! JSON always starts with `{`, so rows written before the packed format are still read
+//! transparently, and can be rewritten using [repack](super::StarknetTransactionsTable::repack).
+use anyhow::Context;
+use stark_hash::StarkHash;
+
+use crate::core::{
+    CallParam, ConstructorParam, EventData, EventKey, L1ToL2MessagePayloadElem,
+    L2ToL1MessagePayloadElem, TransactionSignatureElem,
+};
+use crate::sequencer::reply::transaction::{
+    Event, L1ToL2Message, L2ToL1Message, Receipt, Transaction,
+};
+
+/// Tag byte of the packed format.
+const PACKED: u8 = 1;
+
+/// Length prefix of an optional felt array which is absent.
+const ABSENT: u32 = u32::MAX;
+
+/// Whether `data` is in the packed format rather than legacy JSON.
+pub(crate) fn is_packed(data: &[u8]) -> bool {
+    data.first() == Some(&PACKED)
+}
+
+pub(crate) fn encode_transaction(transaction: &Transaction) -> anyhow::Result<Vec<u8>> {
+    let header = Transaction {
+        calldata: None,
+        constructor_calldata: None,
+        signature: None,
+        ..*transaction
+    };
+    let mut out = encode_header(&header)?;
+
+    write_felts(
+        &mut out,
+        transaction.calldata.as_ref().map(|x| x.iter().map(|x| x.0)),
+    )?;
+    write_felts(
+        &mut out,
+        transaction
+            .constructor_calldata
+            .as_ref()
+            .map(|x| x.iter().map(|x| x.0)),
+    )?;
+    write_felts(
+        &mut out,
+        transaction
+            .signature
+            .as_ref()
+            .map(|x| x.iter().map(|x| x.0)),
+    )?;
+
+    Ok(out)
+}
+
+pub(crate) fn decode_transaction(data: &[u8]) -> anyhow::Result<Transaction> {
+    if !is_packed(data) {
+        return serde_json::from_slice(data).context("Deserializing legacy JSON");
+    }
+
+    let (mut transaction, mut felts) = decode_header::<Transaction>(data)?;
+
+    transaction.calldata = felts.next_optional(CallParam)?;
+    transaction.constructor_calldata = felts.next_optional(ConstructorParam)?;
+    transaction.signature = felts.next_optional(TransactionSignatureElem)?;
+    felts.finish()?;
+
+    Ok(transaction)
+}
+
+pub(crate) fn encode_receipt(receipt: &Receipt) -> anyhow::Result<Vec<u8>> {
+    let header = Receipt {
+        events: receipt
+            .events
+            .iter()
+            .map(|event| Event {
+                data: Vec::new(),
+                from_address: event.from_address,
+                keys: Vec::new(),
+            })
+            .collect(),
+        l1_to_l2_consumed_message: receipt.l1_to_l2_consumed_message.as_ref().map(|message| {
+            L1ToL2Message {
+                payload: Vec::new(),
+                ..*message
+            }
+        }),
+        l2_to_l1_messages: receipt
+            .l2_to_l1_messages
+            .iter()
+            .map(|message| L2ToL1Message {
+                payload: Vec::new(),
+                ..*message
+            })
+            .collect(),
+        ..*receipt
+    };
+    let mut out = encode_header(&header)?;
+
+    for event in &receipt.events {
+        write_felts(&mut out, Some(event.data.iter().map(|x| x.0)))?;
+        write_felts(&mut out, Some(event.keys.iter().map(|x| x.0)))?;
+    }
+    if let Some(message) = &receipt.l1_to_l2_consumed_message {
+        write_felts(&mut out, Some(message.payload.iter().map(|x| x.0)))?;
+    }
+    for message in &receipt.l2_to_l1_messages {
+        write_felts(&mut out, Some(message.payload.iter().map(|x| x.0)))?;
+    }
+
+    Ok(out)
+}
+
+pub(crate) fn decode_receipt(data: &[u8]) -> anyhow::Result<Receipt> {
+    if !is_packed(data) {
+        return serde_json::from_slice(data).context("Deserializing legacy JSON");
+    }
+
+    let (mut receipt, mut felts) = decode_header::<Receipt>(data)?;
+
+    for event in &mut receipt.events {
+        event.data = felts.next(EventData)?;
+        event.keys = felts.next(EventKey)?;
+    }
+    if let Some(message) = &mut receipt.l1_to_l2_consumed_message {
+        message.payload = felts.next(L1ToL2MessagePayloadElem)?;
+    }
+    for message in &mut receipt.l2_to_l1_messages {
+        message.payload = felts.next(L2ToL1MessagePayloadElem)?;
+    }
+    felts.finish()?;
+
+    Ok(receipt)
+}
+
+/// Starts the packed encoding of a value whose felt arrays were removed.
+fn encode_header<T: serde::Serialize>(header: &T) -> anyhow::Result<Vec<u8>> {
+    let json = serde_json::to_vec(header).context("Serializing header")?;
+    let len = u32::try_from(json.len()).context("Header is too long")?;
+
+    let mut out = Vec::with_capacity(1 + 4 + json.len());
+    out.push(PACKED);
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&json);
+    Ok(out)
+}
+
+/// Returns the value encoded by [encode_header], and the felt arrays which follow it.
+fn decode_header<T: serde::de::DeserializeOwned>(data: &[u8]) -> anyhow::Result<(T, Felts<'_>)> {
+    let mut felts = Felts(&data[1..]);
+    let len = felts.read_u32().context("Reading header length")? as usize;
+    anyhow::ensure!(felts.0.len() >= len, "Header is truncated");
+
+    let (header, rest) = felts.0.split_at(len);
+    let header = serde_json::from_slice(header).context("Deserializing header")?;
+    Ok((header, Felts(rest)))
+}
+
+fn write_felts(
+    out: &mut Vec<u8>,
+    felts: Option<impl ExactSizeIterator<Item = StarkHash>>,
+) -> anyhow::Result<()> {
+    let felts = match felts {
+        Some(felts) => felts,
+        None => {
+            out.extend_from_slice(&ABSENT.to_le_bytes());
+            return Ok(());
+        }
+    };
+
+    let len = u32::try_from(felts.len())
+        .ok()
+        .filter(|len| *len != ABSENT)
+        .context("Too many felts")?;
+    out.reserve(4 + felts.len() * 32);
+    out.extend_from_slice(&len.to_le_bytes());
+    felts.for_each(|felt| out.extend_from_slice(felt.as_be_bytes()));
+    Ok(())
+}
+
+/// The felt arrays of a packed value which have not been read yet.
+struct Felts<'a>(&'a [u8]);
+
+impl Felts<'_> {
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        anyhow::ensure!(self.0.len() >= 4, "Unexpected end of data");
+        let (bytes, rest) = self.0.split_at(4);
+        self.0 = rest;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("Length was checked"),
+        ))
+    }
+
+    fn next_optional<T>(
+        &mut self,
+        wrap: impl Fn(StarkHash) -> T,
+    ) -> anyhow::Result<Option<Vec<T>>> {
+        let len = match self.read_u32()? {
+            ABSENT => return Ok(None),
+            len => len as usize,
+        };
+        // Checked before allocating, so that a corrupt length cannot exhaust the memory.
+        anyhow::ensure!(self.0.len() / 32 >= len, "Felt array is truncated");
+
+        let (bytes, rest) = self.0.split_at(len * 32);
+        self.0 = rest;
+        bytes
+            .chunks_exact(32)
+            .map(|felt| {
+                let felt = StarkHash::from_be_slice(felt).context("Felt is out of range")?;
+                Ok(wrap(felt))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(Some)
+    }
+
+    fn next<T>(&mut self, wrap: impl Fn(StarkHash) -> T) -> anyhow::Result<Vec<T>> {
+        self.next_optional(wrap)?
+            .context("Required felt array is absent")
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.0.is_empty(), "Trailing data after felt arrays");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ContractAddress, StarknetTransactionHash};
+    use crate::sequencer::reply::{transaction::Type, Block};
+    use std::time::{Duration, Instant};
+
+    fn fixture_blocks() -> Vec<Block> {
+        [
+            include_str!("../../fixtures/sequencer/genesis_block.json"),
+            include_str!("../../fixtures/sequencer/block_1716.json"),
+            include_str!("../../fixtures/sequencer/block_200k.json"),
+        ]
+        .iter()
+        .map(|json| serde_json::from_str(json).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        for block in fixture_blocks() {
+            assert!(!block.transactions.is_empty());
+            for transaction in &block.transactions {
+                let packed = encode_transaction(transaction).unwrap();
+                assert!(is_packed(&packed));
+                assert_eq!(&decode_transaction(&packed).unwrap(), transaction);
+
+                let json = serde_json::to_vec(transaction).unwrap();
+                assert!(!is_packed(&json));
+                assert_eq!(&decode_transaction(&json).unwrap(), transaction);
+            }
+            for receipt in &block.transaction_receipts {
+                let packed = encode_receipt(receipt).unwrap();
+                assert!(is_packed(&packed));
+                assert_eq!(&decode_receipt(&packed).unwrap(), receipt);
+
+                let json = serde_json::to_vec(receipt).unwrap();
+                assert!(!is_packed(&json));
+                assert_eq!(&decode_receipt(&json).unwrap(), receipt);
+            }
+        }
+    }
+
+    #[test]
+    fn absent_and_empty_arrays_are_distinct() {
+        let transaction = fixture_blocks()[2].transactions[0].clone();
+        for calldata in [None, Some(vec![])] {
+            let transaction = Transaction {
+                calldata: calldata.clone(),
+                ..transaction.clone()
+            };
+            let decoded = decode_transaction(&encode_transaction(&transaction).unwrap()).unwrap();
+            assert_eq!(decoded.calldata, calldata);
+        }
+    }
+
+    #[test]
+    fn corrupt_data_is_rejected() {
+        let transaction = fixture_blocks()[2].transactions[0].clone();
+        let packed = encode_transaction(&transaction).unwrap();
+
+        decode_transaction(&packed[..packed.len() - 1]).unwrap_err();
+
+        let mut trailing = packed.clone();
+        trailing.push(0);
+        decode_transaction(&trailing).unwrap_err();
+
+        // A huge array length must not be trusted.
+        let mut truncated = encode_header(&Transaction {
+            calldata: None,
+            ..transaction
+        })
+        .unwrap();
+        truncated.extend_from_slice(&(ABSENT - 1).to_le_bytes());
+        decode_transaction(&truncated).unwrap_err();
+    }
+
+    /// A transaction with 5000 calldata felts, which are pseudo-random like real ones.
+    fn large_transaction() -> Transaction {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let calldata = (0..5000)
+            .map(|_| {
+                let mut bytes = [0u8; 32];
+                for chunk in bytes.chunks_exact_mut(8) {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    chunk.copy_from_slice(&state.to_be_bytes());
+                }
+                // Keeps the value below the field modulus.
+                bytes[0] &= 0x03;
+                CallParam(StarkHash::from_be_bytes(bytes).unwrap())
+            })
+            .collect();
+
+        Transaction {
+            calldata: Some(calldata),
+            class_hash: None,
+            constructor_calldata: None,
+            contract_address: ContractAddress(StarkHash::from_be_slice(&[1]).unwrap()),
+            contract_address_salt: None,
+            entry_point_type: None,
+            entry_point_selector: None,
+            max_fee: None,
+            signature: None,
+            transaction_hash: StarknetTransactionHash(StarkHash::from_be_slice(&[2]).unwrap()),
+            sender_address: None,
+            nonce: None,
+            r#type: Type::InvokeFunction,
+        }
+    }
+
+    /// The fastest of a few runs of `f`, which is less noisy than a single run.
+    fn fastest(f: impl Fn()) -> Duration {
+        (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                f();
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn large_calldata_is_smaller_and_faster_to_decode() {
+        let transaction = large_transaction();
+        let json = serde_json::to_vec(&transaction).unwrap();
+        let packed = encode_transaction(&transaction).unwrap();
+
+        assert!(
+            packed.len() * 2 < json.len(),
+            "{} vs {}",
+            packed.len(),
+            json.len()
+        );
+
+        let compressed_json = zstd::bulk::compress(&json, 10).unwrap();
+        let compressed_packed = zstd::bulk::compress(&packed, 10).unwrap();
+        assert!(
+            compressed_packed.len() < compressed_json.len(),
+            "{} vs {}",
+            compressed_packed.len(),
+            compressed_json.len()
+        );
+
+        let json_decoding = fastest(|| {
+            decode_transaction(&json).unwrap();
+        });
+        let packed_decoding = fastest(|| {
+            decode_transaction(&packed).unwrap();
+        });
+        assert!(
+            packed_decoding < json_decoding,
+            "{:?} vs {:?}",
+            packed_decoding,
+            json_decoding
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! The verbatim gateway JSON of recent blocks.
+//!
+//! Parsed data only contains what our types capture, so fields the gateway sent but which we
+//! failed to parse are lost. Keeping the raw JSON of the most recent blocks allows them to be
+//! reprocessed once the parsing is fixed.
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use stark_hash::StarkHash;
+
+use crate::core::{StarknetBlockHash, StarknetBlockNumber};
+use crate::storage::{blob, StorageLimits};
+
+/// Stores the zstd compressed gateway JSON of blocks, by block number.
+///
+/// Rows are removed together with their block on reorgs.
+pub struct RawGatewayBlocksTable {}
+
+impl RawGatewayBlocksTable {
+    /// Stores the gateway JSON of block `number`, replacing any earlier entry.
+    pub fn insert(
+        connection: &Connection,
+        number: StarknetBlockNumber,
+        hash: StarknetBlockHash,
+        json: &[u8],
+        limits: &StorageLimits,
+    ) -> anyhow::Result<()> {
+        blob::check_size("raw block", json, limits.max_blob_size);
+        let data = zstd::bulk::compress(json, 10).context("Compress raw block")?;
+
+        connection
+            .execute(
+                r"INSERT OR REPLACE INTO raw_gateway_blocks (number, hash, data)
+                VALUES (:number, :hash, :data)",
+                named_params! {
+                    ":number": number.0,
+                    ":hash": &hash.0.to_be_bytes()[..],
+                    ":data": &data,
+                },
+            )
+            .context("Insert into raw_gateway_blocks table")?;
+
+        Ok(())
+    }
+
+    /// Returns the hash and gateway JSON of block `number`, if it is retained.
+    pub fn get(
+        connection: &Connection,
+        number: StarknetBlockNumber,
+        limits: &StorageLimits,
+    ) -> anyhow::Result<Option<(StarknetBlockHash, Vec<u8>)>> {
+        let row = connection
+            .query_row(
+                "SELECT hash, data FROM raw_gateway_blocks WHERE number = ?",
+                [number.0],
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )
+            .optional()
+            .context("Query raw_gateway_blocks table")?;
+
+        let (hash, data) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let hash = StarkHash::from_be_slice(&hash).context("Parsing stored block hash")?;
+        let json =
+            blob::decompress(&data, limits.max_blob_size).context("Decompressing raw block")?;
+
+        Ok(Some((StarknetBlockHash(hash), json)))
+    }
+
+    /// Removes the entries of all blocks before `number`, returning how many were removed.
+    pub fn prune_before(
+        connection: &Connection,
+        number: StarknetBlockNumber,
+    ) -> anyhow::Result<usize> {
+        connection
+            .execute(
+                "DELETE FROM raw_gateway_blocks WHERE number < ?",
+                [number.0],
+            )
+            .context("Prune raw_gateway_blocks table")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_utils, StarknetBlocksTable, Storage};
+
+    #[test]
+    fn insert_get_and_prune() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        let blocks = test_utils::create_blocks::<3>();
+        for block in &blocks {
+            StarknetBlocksTable::insert(&connection, block).unwrap();
+            let json = format!(r#"{{"block_number":{}}}"#, block.number.0);
+            RawGatewayBlocksTable::insert(
+                &connection,
+                block.number,
+                block.hash,
+                json.as_bytes(),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            RawGatewayBlocksTable::get(&connection, blocks[1].number, &StorageLimits::default())
+                .unwrap(),
+            Some((blocks[1].hash, br#"{"block_number":1}"#.to_vec()))
+        );
+
+        let pruned = RawGatewayBlocksTable::prune_before(&connection, blocks[2].number).unwrap();
+        assert_eq!(pruned, 2);
+        assert_eq!(
+            RawGatewayBlocksTable::get(&connection, blocks[1].number, &StorageLimits::default())
+                .unwrap(),
+            None
+        );
+        assert!(RawGatewayBlocksTable::get(
+            &connection,
+            blocks[2].number,
+            &StorageLimits::default()
+        )
+        .unwrap()
+        .is_some());
+
+        // Reorged blocks take their raw JSON with them.
+        StarknetBlocksTable::reorg(&connection, blocks[2].number).unwrap();
+        assert_eq!(
+            RawGatewayBlocksTable::get(&connection, blocks[2].number, &StorageLimits::default())
+                .unwrap(),
+            None
+        );
+    }
+}
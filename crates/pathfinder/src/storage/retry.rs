@@ -0,0 +1,249 @@
+//! Items whose download failed during sync, and which are retried in the background instead of
+//! blocking the sync head.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use stark_hash::StarkHash;
+
+use crate::core::ClassHash;
+
+/// An item whose download can be deferred without affecting the correctness of the state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryItem {
+    /// A class definition. Only the class hash is part of the state.
+    Class(ClassHash),
+}
+
+impl RetryItem {
+    fn item_type(&self) -> &'static str {
+        match self {
+            RetryItem::Class(_) => "class",
+        }
+    }
+
+    fn identifier(&self) -> [u8; 32] {
+        match self {
+            RetryItem::Class(hash) => hash.0.to_be_bytes(),
+        }
+    }
+
+    fn from_row(item_type: &str, identifier: &[u8]) -> anyhow::Result<Self> {
+        match item_type {
+            "class" => {
+                let hash = StarkHash::from_be_slice(identifier).context("Parsing class hash")?;
+                Ok(RetryItem::Class(ClassHash(hash)))
+            }
+            other => anyhow::bail!("Unknown retry item type {:?}", other),
+        }
+    }
+}
+
+/// The retry state of a [RetryItem].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryEntry {
+    pub item: RetryItem,
+    /// The number of failed downloads, including the one during sync.
+    pub attempts: u32,
+    /// Unix timestamp in seconds before which the item should not be retried.
+    pub next_attempt_at: u64,
+    pub last_error: String,
+}
+
+/// Tracks the [RetryItem]s which failed to download.
+///
+/// Each failure pushes the next attempt back by [retry_delay](Self::retry_delay), which grows
+/// exponentially with the number of attempts.
+pub struct SyncRetryQueueTable {}
+
+impl SyncRetryQueueTable {
+    /// The delay before the first retry.
+    pub const BASE_DELAY: Duration = Duration::from_secs(30);
+    /// Upper bound on the delay between retries.
+    pub const MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+
+    /// The delay before the next attempt, after `attempts` failed ones.
+    pub fn retry_delay(attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        Self::BASE_DELAY
+            .checked_mul(factor)
+            .unwrap_or(Self::MAX_DELAY)
+            .min(Self::MAX_DELAY)
+    }
+
+    /// Records a failed download of `item` at unix time `now`, and returns its updated
+    /// [RetryEntry].
+    pub fn record_failure(
+        connection: &Connection,
+        item: RetryItem,
+        error: &str,
+        now: u64,
+    ) -> anyhow::Result<RetryEntry> {
+        let attempts: Option<u32> = connection
+            .query_row(
+                "SELECT attempts FROM sync_retry_queue WHERE item_type = ? AND identifier = ?",
+                rusqlite::params![item.item_type(), &item.identifier()[..]],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Query previous attempts")?;
+        let attempts = attempts.unwrap_or_default() + 1;
+        let next_attempt_at = now.saturating_add(Self::retry_delay(attempts).as_secs());
+
+        connection
+            .execute(
+                r"INSERT OR REPLACE INTO sync_retry_queue
+                        ( item_type,  identifier,  attempts,  next_attempt_at,  last_error)
+                VALUES  (:item_type, :identifier, :attempts, :next_attempt_at, :last_error)",
+                named_params! {
+                    ":item_type": item.item_type(),
+                    ":identifier": &item.identifier()[..],
+                    ":attempts": attempts,
+                    ":next_attempt_at": next_attempt_at,
+                    ":last_error": error,
+                },
+            )
+            .context("Insert into sync_retry_queue table")?;
+
+        Ok(RetryEntry {
+            item,
+            attempts,
+            next_attempt_at,
+            last_error: error.to_owned(),
+        })
+    }
+
+    /// Removes `item` from the queue, once it has been downloaded.
+    pub fn remove(connection: &Connection, item: RetryItem) -> anyhow::Result<()> {
+        connection
+            .execute(
+                "DELETE FROM sync_retry_queue WHERE item_type = ? AND identifier = ?",
+                rusqlite::params![item.item_type(), &item.identifier()[..]],
+            )
+            .context("Delete from sync_retry_queue table")?;
+        Ok(())
+    }
+
+    /// Returns up to `limit` entries which are due for a retry at unix time `now`, the most
+    /// overdue first.
+    pub fn due(connection: &Connection, now: u64, limit: usize) -> anyhow::Result<Vec<RetryEntry>> {
+        Self::query(
+            connection,
+            r"SELECT item_type, identifier, attempts, next_attempt_at, last_error
+                FROM sync_retry_queue WHERE next_attempt_at <= ?
+                ORDER BY next_attempt_at ASC LIMIT ?",
+            rusqlite::params![now, limit],
+        )
+    }
+
+    /// Returns all entries, in order of their next attempt.
+    pub fn all(connection: &Connection) -> anyhow::Result<Vec<RetryEntry>> {
+        Self::query(
+            connection,
+            r"SELECT item_type, identifier, attempts, next_attempt_at, last_error
+                FROM sync_retry_queue ORDER BY next_attempt_at ASC",
+            [],
+        )
+    }
+
+    fn query<P: rusqlite::Params>(
+        connection: &Connection,
+        sql: &str,
+        params: P,
+    ) -> anyhow::Result<Vec<RetryEntry>> {
+        let mut statement = connection.prepare(sql).context("Preparing statement")?;
+        let mut rows = statement.query(params).context("Executing query")?;
+
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let item_type = row.get_ref_unwrap("item_type").as_str()?;
+            let identifier = row.get_ref_unwrap("identifier").as_blob()?;
+            let item = RetryItem::from_row(item_type, identifier)?;
+
+            entries.push(RetryEntry {
+                item,
+                attempts: row.get("attempts")?,
+                next_attempt_at: row.get::<_, i64>("next_attempt_at")? as u64,
+                last_error: row.get("last_error")?,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+
+    fn class(hex: &str) -> RetryItem {
+        RetryItem::Class(ClassHash(StarkHash::from_hex_str(hex).unwrap()))
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially_up_to_the_maximum() {
+        assert_eq!(SyncRetryQueueTable::retry_delay(1), Duration::from_secs(30));
+        assert_eq!(SyncRetryQueueTable::retry_delay(2), Duration::from_secs(60));
+        assert_eq!(
+            SyncRetryQueueTable::retry_delay(3),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            SyncRetryQueueTable::retry_delay(10),
+            SyncRetryQueueTable::MAX_DELAY
+        );
+        assert_eq!(
+            SyncRetryQueueTable::retry_delay(u32::MAX),
+            SyncRetryQueueTable::MAX_DELAY
+        );
+    }
+
+    #[test]
+    fn record_failure_counts_attempts() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        let entry =
+            SyncRetryQueueTable::record_failure(&connection, class("1"), "first", 100).unwrap();
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(entry.next_attempt_at, 130);
+
+        let entry =
+            SyncRetryQueueTable::record_failure(&connection, class("1"), "second", 200).unwrap();
+        assert_eq!(entry.attempts, 2);
+        assert_eq!(entry.next_attempt_at, 260);
+
+        assert_eq!(SyncRetryQueueTable::all(&connection).unwrap(), vec![entry]);
+    }
+
+    #[test]
+    fn due_and_remove() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        let later = SyncRetryQueueTable::record_failure(&connection, class("1"), "a", 200).unwrap();
+        let sooner =
+            SyncRetryQueueTable::record_failure(&connection, class("2"), "b", 100).unwrap();
+
+        assert!(SyncRetryQueueTable::due(&connection, 100, 10)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            SyncRetryQueueTable::due(&connection, 130, 10).unwrap(),
+            vec![sooner.clone()]
+        );
+        assert_eq!(
+            SyncRetryQueueTable::due(&connection, 1000, 10).unwrap(),
+            vec![sooner.clone(), later.clone()]
+        );
+        assert_eq!(
+            SyncRetryQueueTable::due(&connection, 1000, 1).unwrap(),
+            vec![sooner]
+        );
+
+        SyncRetryQueueTable::remove(&connection, class("2")).unwrap();
+        assert_eq!(SyncRetryQueueTable::all(&connection).unwrap(), vec![later]);
+    }
+}
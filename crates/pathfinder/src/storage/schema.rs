@@ -8,6 +8,31 @@ pub(crate) mod revision_0007;
 pub(crate) mod revision_0008;
 pub(crate) mod revision_0009;
 pub(crate) mod revision_0010;
+pub(crate) mod revision_0011;
+pub(crate) mod revision_0012;
+pub(crate) mod revision_0013;
+pub(crate) mod revision_0014;
+pub(crate) mod revision_0015;
+pub(crate) mod revision_0016;
+pub(crate) mod revision_0017;
+pub(crate) mod revision_0018;
+pub(crate) mod revision_0019;
+pub(crate) mod revision_0020;
+pub(crate) mod revision_0021;
+pub(crate) mod revision_0022;
+pub(crate) mod revision_0023;
+pub(crate) mod revision_0024;
+pub(crate) mod revision_0025;
+pub(crate) mod revision_0026;
+pub(crate) mod revision_0027;
+pub(crate) mod revision_0028;
+pub(crate) mod revision_0029;
+pub(crate) mod revision_0030;
+pub(crate) mod revision_0031;
+pub(crate) mod revision_0032;
+pub(crate) mod revision_0033;
+
+use rusqlite::Transaction;
 
 /// Used to indicate which action the caller should perform after a schema migration.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -17,3 +42,56 @@ pub enum PostMigrationAction {
     /// No further action requried.
     None,
 }
+
+/// Applies the migration to the given schema `revision` from the one preceding it.
+pub(crate) fn migrate(
+    transaction: &Transaction,
+    revision: u32,
+) -> anyhow::Result<PostMigrationAction> {
+    match revision {
+        1 => revision_0001::migrate(transaction),
+        2 => revision_0002::migrate(transaction),
+        3 => revision_0003::migrate(transaction),
+        4 => revision_0004::migrate(transaction),
+        5 => revision_0005::migrate(transaction),
+        6 => revision_0006::migrate(transaction),
+        7 => revision_0007::migrate(transaction),
+        8 => revision_0008::migrate(transaction),
+        9 => revision_0009::migrate(transaction),
+        10 => revision_0010::migrate(transaction),
+        11 => revision_0011::migrate(transaction),
+        12 => revision_0012::migrate(transaction),
+        13 => revision_0013::migrate(transaction),
+        14 => revision_0014::migrate(transaction),
+        15 => revision_0015::migrate(transaction),
+        16 => revision_0016::migrate(transaction),
+        17 => revision_0017::migrate(transaction),
+        18 => revision_0018::migrate(transaction),
+        19 => revision_0019::migrate(transaction),
+        20 => revision_0020::migrate(transaction),
+        21 => revision_0021::migrate(transaction),
+        22 => revision_0022::migrate(transaction),
+        23 => revision_0023::migrate(transaction),
+        24 => revision_0024::migrate(transaction),
+        25 => revision_0025::migrate(transaction),
+        26 => revision_0026::migrate(transaction),
+        27 => revision_0027::migrate(transaction),
+        28 => revision_0028::migrate(transaction),
+        29 => revision_0029::migrate(transaction),
+        30 => revision_0030::migrate(transaction),
+        31 => revision_0031::migrate(transaction),
+        32 => revision_0032::migrate(transaction),
+        33 => revision_0033::migrate(transaction),
+        _ => anyhow::bail!("Unknown schema revision {}", revision),
+    }
+}
+
+/// Migrates an empty database to the given schema `revision`, panicking on failure.
+///
+/// Lets the tests of a migration set up the schema it migrates from.
+#[cfg(test)]
+pub(crate) fn migrate_to(transaction: &Transaction, revision: u32) {
+    for revision in 1..=revision {
+        migrate(transaction, revision).unwrap();
+    }
+}
@@ -14,7 +14,7 @@ mod transaction {
             L2ToL1MessagePayloadElem, StarknetTransactionHash, StarknetTransactionIndex,
             TransactionSignatureElem,
         },
-        rpc::serde::{
+        serde::{
             CallParamAsDecimalStr, ConstructorParamAsDecimalStr, EthereumAddressAsHexStr,
             EventDataAsDecimalStr, EventKeyAsDecimalStr, L1ToL2MessagePayloadElemAsDecimalStr,
             L2ToL1MessagePayloadElemAsDecimalStr, TransactionSignatureElemAsDecimalStr,
@@ -13,7 +13,7 @@ mod transaction {
             L1ToL2MessagePayloadElem, L2ToL1MessagePayloadElem, StarknetTransactionHash,
             StarknetTransactionIndex, TransactionSignatureElem,
         },
-        rpc::serde::{
+        serde::{
             CallParamAsDecimalStr, ConstructorParamAsDecimalStr, EthereumAddressAsHexStr,
             EventDataAsDecimalStr, EventKeyAsDecimalStr, FeeAsHexStr,
             L1ToL2MessagePayloadElemAsDecimalStr, L2ToL1MessagePayloadElemAsDecimalStr,
@@ -307,7 +307,7 @@ pub(crate) fn migrate_with(
                 ).context("Query block number based on block hash")?;
 
                 let serialized_data = StarknetEventsTable::event_data_to_bytes(&event.data);
-                let serialized_keys = StarknetEventsTable::event_keys_to_base64_strings(&event.keys);
+                let serialized_keys = event_keys_to_base64_strings(&event.keys);
 
                 transaction.execute(r"INSERT INTO starknet_events ( block_number,  idx,  transaction_hash,  from_address,  keys,  data)
                                                            VALUES (:block_number, :idx, :transaction_hash, :from_address, :keys, :data)",
@@ -329,6 +329,17 @@ pub(crate) fn migrate_with(
     Ok(PostMigrationAction::None)
 }
 
+/// Encodes the keys as space separated base64 strings, as stored until revision 29.
+///
+/// A copy of the encoding as of this revision, which later revisions must not change: revision 29
+/// decodes it when re-encoding the keys as hex.
+fn event_keys_to_base64_strings(keys: &[crate::core::EventKey]) -> String {
+    keys.iter()
+        .map(|key| base64::encode(key.0.as_be_bytes()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     use rusqlite::Connection;
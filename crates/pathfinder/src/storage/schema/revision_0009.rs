@@ -22,10 +22,9 @@ mod tests {
 
     use crate::{
         core::{
-            GasPrice, GlobalRoot, SequencerAddress, StarknetBlockHash, StarknetBlockNumber,
-            StarknetBlockTimestamp,
+            GasPrice, GlobalRoot, StarknetBlockHash, StarknetBlockNumber, StarknetBlockTimestamp,
         },
-        storage::{schema, StarknetBlock, StarknetBlocksBlockId, StarknetBlocksTable},
+        storage::schema,
     };
 
     use rusqlite::{named_params, Connection};
@@ -84,20 +83,21 @@ mod tests {
         let action = super::migrate(&transaction).unwrap();
         assert_eq!(action, PostMigrationAction::None);
 
-        let block = StarknetBlocksTable::get(&transaction, StarknetBlocksBlockId::Hash(block_hash))
-            .unwrap()
+        let (gas_price, sequencer_address) = transaction
+            .query_row(
+                "SELECT gas_price, sequencer_address FROM starknet_blocks WHERE hash = ?",
+                [&block_hash.0.as_be_bytes()],
+                |row| {
+                    let gas_price = row.get_ref_unwrap(0).as_blob().unwrap();
+                    let gas_price = GasPrice::from_be_slice(gas_price).unwrap();
+                    let sequencer_address = row.get_ref_unwrap(1).as_blob().unwrap();
+                    let sequencer_address = StarkHash::from_be_slice(sequencer_address).unwrap();
+                    Ok((gas_price, sequencer_address))
+                },
+            )
             .unwrap();
 
-        assert_eq!(
-            block,
-            StarknetBlock {
-                number: block_number,
-                hash: block_hash,
-                root,
-                timestamp,
-                gas_price: GasPrice::ZERO,
-                sequencer_address: SequencerAddress(StarkHash::ZERO)
-            }
-        )
+        assert_eq!(gas_price, GasPrice::ZERO);
+        assert_eq!(sequencer_address, StarkHash::ZERO);
     }
 }
@@ -263,7 +263,7 @@ mod tests {
                 schema::{self, PostMigrationAction},
                 state::PageOfEvents,
                 StarknetBlocksTable, StarknetEmittedEvent, StarknetEventFilter,
-                StarknetEventsTable,
+                StarknetEventsTable, StorageLimits,
             },
         };
 
@@ -303,6 +303,40 @@ mod tests {
                     Ok(())
                 }
             }
+
+            fn event_keys_to_base64_strings(keys: &[EventKey]) -> String {
+                keys.iter()
+                    .map(|key| base64::encode(key.0.as_be_bytes()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+
+            pub struct StarknetEventsTable;
+
+            impl StarknetEventsTable {
+                pub fn insert_events(
+                    connection: &Connection,
+                    block_number: StarknetBlockNumber,
+                    transaction: &Transaction,
+                    events: &[Event],
+                ) -> anyhow::Result<()> {
+                    for (idx, event) in events.iter().enumerate() {
+                        connection.execute(
+                            r"INSERT INTO starknet_events ( block_number,  idx,  transaction_hash,  from_address,  keys,  data)
+                                                   VALUES (:block_number, :idx, :transaction_hash, :from_address, :keys, :data)",
+                            named_params![
+                                ":block_number": block_number.0,
+                                ":idx": idx,
+                                ":transaction_hash": &transaction.transaction_hash.0.as_be_bytes()[..],
+                                ":from_address": &transaction.contract_address.0.as_be_bytes()[..],
+                                ":keys": event_keys_to_base64_strings(&event.keys),
+                                ":data": crate::storage::StarknetEventsTable::event_data_to_bytes(&event.data),
+                            ],
+                        )?;
+                    }
+                    Ok(())
+                }
+            }
         }
 
         /// This is a test helper function which runs a stateful scenario of the migration
@@ -377,7 +411,7 @@ mod tests {
             };
 
             storage_rev7::StarknetBlocksTable::insert(&transaction, &block0).unwrap();
-            StarknetEventsTable::insert_events(
+            storage_rev7::StarknetEventsTable::insert_events(
                 &transaction,
                 block0_number,
                 &transaction0,
@@ -385,7 +419,7 @@ mod tests {
             )
             .unwrap();
             storage_rev7::StarknetBlocksTable::insert(&transaction, &block1).unwrap();
-            StarknetEventsTable::insert_events(
+            storage_rev7::StarknetEventsTable::insert_events(
                 &transaction,
                 block1_number,
                 &transaction1,
@@ -414,7 +448,10 @@ mod tests {
                 contract_address: None,
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 keys: vec![event0_key],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 10,
                 page_number: 0,
             };
@@ -422,12 +459,16 @@ mod tests {
                 contract_address: None,
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 keys: vec![event1_key],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 10,
                 page_number: 0,
             };
             assert_eq!(
-                StarknetEventsTable::get_events(&transaction, &filter0).unwrap(),
+                StarknetEventsTable::get_events(&transaction, &filter0, &StorageLimits::default())
+                    .unwrap(),
                 PageOfEvents {
                     events: vec![StarknetEmittedEvent {
                         block_hash: block0_hash,
@@ -440,10 +481,14 @@ mod tests {
                     is_last_page: true
                 }
             );
-            assert!(StarknetEventsTable::get_events(&transaction, &filter1)
-                .unwrap()
-                .events
-                .is_empty());
+            assert!(StarknetEventsTable::get_events(
+                &transaction,
+                &filter1,
+                &StorageLimits::default()
+            )
+            .unwrap()
+            .events
+            .is_empty());
         }
 
         #[test]
@@ -484,13 +529,17 @@ mod tests {
                     )
                     .unwrap();
 
-                crate::storage::StarknetTransactionsTable::upsert(
-                    connection,
-                    block.hash,
-                    block.number,
-                    &transactions_and_receipts[i * TXNS_PER_BLOCK..(i + 1) * TXNS_PER_BLOCK],
-                )
-                .unwrap();
+                for (txn, receipt) in
+                    &transactions_and_receipts[i * TXNS_PER_BLOCK..(i + 1) * TXNS_PER_BLOCK]
+                {
+                    storage_rev7::StarknetEventsTable::insert_events(
+                        connection,
+                        block.number,
+                        txn,
+                        &receipt.events,
+                    )
+                    .unwrap();
+                }
             }
 
             transactions_and_receipts
@@ -548,15 +597,20 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: Some(expected_event.block_number),
                 to_block: Some(expected_event.block_number),
+                block_hash: None,
                 contract_address: Some(expected_event.from_address),
                 // we're using a key which is present in _all_ events
                 keys: vec![EventKey(StarkHash::from_hex_str("deadbeef").unwrap())],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_TXNS,
                 page_number: 0,
             };
 
             // 3. Getting events works just fine, the result relies on the data in `starknet_events_keys` virtual table
-            let events = StarknetEventsTable::get_events(&transaction, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&transaction, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -574,7 +628,9 @@ mod tests {
             // in the new `starknet_events` table
             schema::revision_0010::migrate(&transaction).unwrap();
 
-            let events = StarknetEventsTable::get_events(&transaction, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&transaction, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -0,0 +1,50 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds a table which stores the state diff applied by each block.
+///
+/// Blocks synced before this migration have no stored state diff.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            r"CREATE TABLE starknet_state_updates (
+                block_number INTEGER PRIMARY KEY NOT NULL,
+                -- zstd compressed json
+                data BLOB NOT NULL,
+                FOREIGN KEY(block_number) REFERENCES starknet_blocks(number)
+                ON DELETE CASCADE
+            )",
+            [],
+        )
+        .context("Create starknet_state_updates table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 10);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM starknet_state_updates", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds a table which stores each element of an event's data as a
+/// separate row, indexed by value. This allows filtering events on their data without
+/// decoding the `data` blob of every candidate event.
+///
+/// The table is backfilled from the existing `starknet_events.data` blobs.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE starknet_events_data (
+                -- rowid of the event in starknet_events
+                event_id INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                value BLOB NOT NULL,
+                PRIMARY KEY(event_id, position)
+            ) WITHOUT ROWID;
+
+            CREATE INDEX starknet_events_data_value ON starknet_events_data(value, position);
+
+            CREATE TRIGGER starknet_events_data_ad
+            AFTER DELETE ON starknet_events
+            BEGIN
+                DELETE FROM starknet_events_data WHERE event_id = old.rowid;
+            END;",
+        )
+        .context("Create starknet_events_data table")?;
+
+    // Split the concatenated data of existing events into its 32 byte elements.
+    transaction
+        .execute(
+            r"WITH RECURSIVE elements(event_id, position, data) AS (
+                SELECT rowid, 0, data FROM starknet_events WHERE length(data) > 0
+                UNION ALL
+                SELECT event_id, position + 1, substr(data, 33) FROM elements WHERE length(data) > 32
+            )
+            INSERT INTO starknet_events_data (event_id, position, value)
+            SELECT event_id, position, substr(data, 1, 32) FROM elements",
+            [],
+        )
+        .context("Backfill starknet_events_data table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::{named_params, Connection};
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 11);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM starknet_events_data", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn backfill() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 11);
+
+        transaction
+            .execute(
+                r"INSERT INTO starknet_blocks (number, hash, root, timestamp, gas_price, sequencer_address)
+                VALUES (0, X'00', X'00', 0, X'00', X'00')",
+                [],
+            )
+            .unwrap();
+        // Events with three, one and no data elements respectively.
+        let data = [
+            [[1u8; 32], [2u8; 32], [3u8; 32]].concat(),
+            [4u8; 32].to_vec(),
+            vec![],
+        ];
+        for (idx, data) in data.iter().enumerate() {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data)
+                    VALUES (0, :idx, X'00', X'00', '', :data)",
+                    named_params! {":idx": idx, ":data": data},
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut statement = transaction
+            .prepare(
+                r"SELECT starknet_events.idx, position, value FROM starknet_events_data
+                INNER JOIN starknet_events ON starknet_events.rowid = event_id
+                ORDER BY starknet_events.idx, position",
+            )
+            .unwrap();
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, usize>(0)?,
+                    row.get::<_, usize>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (0, 0, vec![1u8; 32]),
+                (0, 1, vec![2u8; 32]),
+                (0, 2, vec![3u8; 32]),
+                (1, 0, vec![4u8; 32]),
+            ]
+        );
+
+        // Deleting an event also removes its data rows.
+        transaction
+            .execute("DELETE FROM starknet_events WHERE idx = 0", [])
+            .unwrap();
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM starknet_events_data", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
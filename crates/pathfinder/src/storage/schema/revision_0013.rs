@@ -0,0 +1,220 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+use crate::storage::schema::PostMigrationAction;
+
+/// Number of blocks whose transactions and events are counted per query.
+const BATCH_SIZE: u64 = 10_000;
+
+/// This schema migration adds the tables which maintain the chain statistics, and initializes
+/// the counters from the existing data.
+///
+/// The block which first deployed a class is unknown for classes already deployed, those are
+/// therefore never removed by a reorg.
+///
+/// Likewise, the `contracts` table does not record which block deployed a contract, and the
+/// transactions which could tell are only stored as compressed blobs. The total contract count
+/// is exact, but the contribution of each existing block in `chain_stats_blocks` is recorded as
+/// zero. A reorg of a block stored before this migration therefore leaves its contracts counted
+/// in the total; only blocks stored afterwards record their real contribution.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    migrate_with_batch_size(transaction, BATCH_SIZE)
+}
+
+fn migrate_with_batch_size(
+    transaction: &Transaction,
+    batch_size: u64,
+) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE chain_stats (
+                -- this table holds a single row
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                contracts INTEGER NOT NULL,
+                classes INTEGER NOT NULL,
+                transactions INTEGER NOT NULL,
+                events INTEGER NOT NULL
+            );
+
+            -- The contribution of each block to the counters, which is reverted on a reorg.
+            CREATE TABLE chain_stats_blocks (
+                block_number INTEGER PRIMARY KEY NOT NULL,
+                contracts INTEGER NOT NULL,
+                classes INTEGER NOT NULL,
+                transactions INTEGER NOT NULL,
+                events INTEGER NOT NULL
+            );
+
+            -- The distinct classes of all deployed contracts, along with the block which first deployed them.
+            CREATE TABLE chain_stats_classes (
+                hash BLOB PRIMARY KEY NOT NULL,
+                block_number INTEGER
+            );
+            CREATE INDEX chain_stats_classes_block_number ON chain_stats_classes(block_number);",
+        )
+        .context("Create chain stats tables")?;
+
+    let head: Option<u64> = transaction
+        .query_row("SELECT max(number) FROM starknet_blocks", [], |row| {
+            row.get(0)
+        })
+        .context("Query latest block number")?;
+
+    if let Some(head) = head {
+        tracing::info!(
+            "Counting transactions and events of {} blocks, this may take a while.",
+            head + 1
+        );
+
+        let mut statement = transaction
+            .prepare(
+                r"INSERT INTO chain_stats_blocks (block_number, contracts, classes, transactions, events)
+                SELECT
+                    number,
+                    0,
+                    0,
+                    (SELECT count(1) FROM starknet_transactions WHERE block_hash = starknet_blocks.hash),
+                    (SELECT count(1) FROM starknet_events WHERE block_number = starknet_blocks.number)
+                FROM starknet_blocks
+                WHERE number BETWEEN :first AND :last",
+            )
+            .context("Prepare statement")?;
+
+        let mut first = 0;
+        while first <= head {
+            let last = first + batch_size - 1;
+            statement
+                .execute(named_params! {":first": first, ":last": last})
+                .context("Count transactions and events")?;
+            first = last + 1;
+        }
+    }
+
+    transaction
+        .execute(
+            "INSERT INTO chain_stats_classes (hash, block_number) SELECT DISTINCT hash, NULL FROM contracts",
+            [],
+        )
+        .context("Initialize chain_stats_classes table")?;
+
+    transaction
+        .execute(
+            r"INSERT INTO chain_stats (id, contracts, classes, transactions, events) VALUES (
+                0,
+                (SELECT count(1) FROM contracts),
+                (SELECT count(1) FROM chain_stats_classes),
+                (SELECT ifnull(sum(transactions), 0) FROM chain_stats_blocks),
+                (SELECT ifnull(sum(events), 0) FROM chain_stats_blocks)
+            )",
+            [],
+        )
+        .context("Initialize chain_stats table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::{schema, test_utils, StarknetTransactionsTable, StorageLimits};
+
+    use rusqlite::{named_params, Connection, Transaction};
+
+    fn count(transaction: &Transaction, sql: &str) -> u64 {
+        transaction.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 12);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let counters: (u64, u64, u64, u64) = transaction
+            .query_row(
+                "SELECT contracts, classes, transactions, events FROM chain_stats",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(counters, (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn counters_match_data() {
+        const NUM_BLOCKS: usize = 5;
+        const TXNS_PER_BLOCK: usize = 3;
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 12);
+
+        let blocks = test_utils::create_blocks::<NUM_BLOCKS>();
+        let transactions_and_receipts =
+            test_utils::create_transactions_and_receipts::<{ NUM_BLOCKS * TXNS_PER_BLOCK }>();
+        for (i, block) in blocks.iter().enumerate() {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks ( number,  hash,  root,  timestamp)
+                                           VALUES (:number, :hash, :root, :timestamp)",
+                    named_params![
+                        ":number": block.number.0,
+                        ":hash": block.hash.0.as_be_bytes(),
+                        ":root": block.root.0.as_be_bytes(),
+                        ":timestamp": block.timestamp.0,
+                    ],
+                )
+                .unwrap();
+            StarknetTransactionsTable::upsert(
+                &transaction,
+                block.hash,
+                block.number,
+                &transactions_and_receipts[i * TXNS_PER_BLOCK..(i + 1) * TXNS_PER_BLOCK],
+                &StorageLimits::default(),
+            )
+            .unwrap();
+        }
+        // Four contracts of three distinct classes.
+        transaction
+            .execute_batch(
+                r"INSERT INTO contracts (address, hash) VALUES
+                    (X'01', X'0a'), (X'02', X'0b'), (X'03', X'0b'), (X'04', X'0c')",
+            )
+            .unwrap();
+
+        // A batch size which does not divide the number of blocks.
+        super::migrate_with_batch_size(&transaction, 2).unwrap();
+
+        let counters: (u64, u64, u64, u64) = transaction
+            .query_row(
+                "SELECT contracts, classes, transactions, events FROM chain_stats",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            counters,
+            (
+                count(&transaction, "SELECT count(1) FROM contracts"),
+                count(&transaction, "SELECT count(DISTINCT hash) FROM contracts"),
+                count(&transaction, "SELECT count(1) FROM starknet_transactions"),
+                count(&transaction, "SELECT count(1) FROM starknet_events"),
+            )
+        );
+        assert_eq!(counters, (4, 3, 15, 15));
+
+        // Each block's contribution is known, so that it can be reverted.
+        assert_eq!(
+            count(
+                &transaction,
+                "SELECT count(1) FROM chain_stats_blocks WHERE transactions = 3 AND events = 3"
+            ),
+            NUM_BLOCKS as u64
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration makes events unique per (block, transaction, index), so that
+/// re-processing a block can never store an event twice.
+///
+/// Existing duplicates are removed, keeping the first of each, and the event counts of the
+/// affected blocks in the chain statistics are recomputed.
+///
+/// Re-processing a block replaces its events by deleting those of each transaction before
+/// inserting them again, see `StarknetEventsTable::insert_events`, so the unique index only
+/// guards against this.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    let blocks = transaction
+        .prepare(
+            r"SELECT DISTINCT block_number FROM starknet_events
+                GROUP BY block_number, transaction_hash, idx HAVING count(1) > 1",
+        )
+        .context("Prepare duplicate events query")?
+        .query_map([], |row| row.get::<_, u64>(0))
+        .context("Query blocks with duplicate events")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Read blocks with duplicate events")?;
+
+    if !blocks.is_empty() {
+        let removed = transaction
+            .execute(
+                r"DELETE FROM starknet_events WHERE rowid NOT IN (
+                    SELECT min(rowid) FROM starknet_events GROUP BY block_number, transaction_hash, idx
+                )",
+                [],
+            )
+            .context("Delete duplicate events")?;
+        tracing::info!(%removed, blocks=%blocks.len(), "Deleted duplicate events");
+
+        let mut update = transaction
+            .prepare(
+                r"UPDATE chain_stats_blocks
+                    SET events = (SELECT count(1) FROM starknet_events WHERE block_number = ?1)
+                    WHERE block_number = ?1",
+            )
+            .context("Prepare block statistics update")?;
+        for block in blocks {
+            update.execute([block]).context("Recount events of block")?;
+        }
+        transaction
+            .execute(
+                "UPDATE chain_stats SET events = (SELECT ifnull(sum(events), 0) FROM chain_stats_blocks)",
+                [],
+            )
+            .context("Recount events")?;
+    }
+
+    transaction
+        .execute(
+            r"CREATE UNIQUE INDEX starknet_events_block_transaction_idx
+                ON starknet_events(block_number, transaction_hash, idx)",
+            [],
+        )
+        .context("Create unique index on starknet_events")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::{named_params, Connection, Transaction};
+
+    fn insert_event(transaction: &Transaction, idx: usize, keys: &str) {
+        transaction
+            .execute(
+                r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data)
+                VALUES (0, :idx, X'01', X'02', :keys, X'')",
+                named_params! {":idx": idx, ":keys": keys},
+            )
+            .unwrap();
+    }
+
+    /// The (idx, keys) of all events, along with the number of events the keys index matches
+    /// for `key`.
+    fn events(transaction: &Transaction, key: &str) -> (Vec<(usize, String)>, usize) {
+        let mut statement = transaction
+            .prepare("SELECT idx, keys FROM starknet_events ORDER BY rowid")
+            .unwrap();
+        let events = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let matches = transaction
+            .query_row(
+                "SELECT count(1) FROM starknet_events_keys WHERE keys MATCH ?",
+                [format!("\"{}\"", key)],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        (events, matches)
+    }
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 13);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+    }
+
+    #[test]
+    fn duplicates_are_removed() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 13);
+        transaction
+            .execute(
+                r"INSERT INTO starknet_blocks (number, hash, root, timestamp, gas_price, sequencer_address)
+                VALUES (0, X'00', X'00', 0, X'00', X'00')",
+                [],
+            )
+            .unwrap();
+        insert_event(&transaction, 0, "first");
+        insert_event(&transaction, 1, "first");
+        insert_event(&transaction, 0, "duplicate");
+        insert_event(&transaction, 0, "duplicate");
+        transaction
+            .execute_batch(
+                r"INSERT INTO chain_stats_blocks (block_number, contracts, classes, transactions, events)
+                VALUES (0, 0, 0, 1, 4);
+                UPDATE chain_stats SET events = 4;",
+            )
+            .unwrap();
+
+        super::migrate(&transaction).unwrap();
+
+        assert_eq!(
+            events(&transaction, "duplicate"),
+            (vec![(0, "first".to_owned()), (1, "first".to_owned())], 0)
+        );
+
+        let (block_events, total_events): (u64, u64) = transaction
+            .query_row(
+                r"SELECT chain_stats_blocks.events, chain_stats.events
+                    FROM chain_stats_blocks, chain_stats WHERE block_number = 0",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((block_events, total_events), (2, 2));
+
+        // The index rejects further duplicates.
+        transaction
+            .execute(
+                r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data)
+                VALUES (0, 1, X'01', X'02', 'duplicate', X'')",
+                [],
+            )
+            .unwrap_err();
+    }
+}
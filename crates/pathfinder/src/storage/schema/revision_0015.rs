@@ -0,0 +1,56 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration indexes the roots of blocks and contract states, so that the blocks
+/// (or contract states) with a given root can be found without a full table scan.
+///
+/// Both tables receive at most a few rows per block, so the additional write cost is small.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute_batch(
+            r"CREATE INDEX starknet_blocks_root ON starknet_blocks(root);
+            CREATE INDEX contract_states_root ON contract_states(root);",
+        )
+        .context("Create root indexes")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn root_lookups_use_the_indexes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 14);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        for (query, index) in [
+            (
+                "SELECT number FROM starknet_blocks WHERE root = X'01'",
+                "starknet_blocks_root",
+            ),
+            (
+                "SELECT state_hash FROM contract_states WHERE root = X'01'",
+                "contract_states_root",
+            ),
+        ] {
+            let plan: String = transaction
+                .query_row(&format!("EXPLAIN QUERY PLAN {}", query), [], |row| {
+                    row.get("detail")
+                })
+                .unwrap();
+            assert!(plan.contains(index), "{plan}");
+        }
+    }
+}
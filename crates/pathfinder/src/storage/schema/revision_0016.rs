@@ -0,0 +1,47 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `class_backfill_failures` table, which records the classes
+/// whose definitions could not be backfilled from the gateway, so that they are not retried
+/// after a restart.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            r"CREATE TABLE class_backfill_failures (
+                hash   BLOB PRIMARY KEY NOT NULL,
+                reason TEXT NOT NULL
+            ) WITHOUT ROWID",
+            [],
+        )
+        .context("Create class_backfill_failures table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 15);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM class_backfill_failures", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::Context;
+use rusqlite::{named_params, Transaction};
+
+use crate::storage::schema::PostMigrationAction;
+
+// This is a copy of the transaction fields required by this migration, as of revision 16.
+// We have to keep these intact so that future changes to the transaction type do not break
+// database upgrades.
+mod transaction {
+    use stark_hash::StarkHash;
+
+    #[derive(serde::Deserialize)]
+    pub struct Transaction {
+        #[serde(default)]
+        pub class_hash: Option<StarkHash>,
+        pub r#type: String,
+    }
+}
+
+/// This schema migration adds the `declared_classes` table, which maps the classes declared by
+/// DECLARE transactions to their declaring block and transaction.
+///
+/// The table is populated from the already stored transactions.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE declared_classes (
+                transaction_hash BLOB PRIMARY KEY NOT NULL,
+                class_hash       BLOB NOT NULL,
+                block_number     INTEGER NOT NULL,
+                FOREIGN KEY(block_number) REFERENCES starknet_blocks(number)
+                ON DELETE CASCADE
+            );
+
+            CREATE INDEX declared_classes_class_hash ON declared_classes(class_hash);
+
+            CREATE INDEX declared_classes_block_number ON declared_classes(block_number);",
+        )
+        .context("Create declared_classes table")?;
+
+    let todo: usize = transaction
+        .query_row("SELECT count(1) FROM starknet_transactions", [], |r| {
+            r.get(0)
+        })
+        .context("Count rows in starknet transactions table")?;
+
+    if todo == 0 {
+        return Ok(PostMigrationAction::None);
+    }
+
+    tracing::info!(
+        num_transactions=%todo,
+        "Scanning transactions for declared classes, this may take a while.",
+    );
+
+    let mut stmt = transaction
+        .prepare(
+            r"SELECT starknet_transactions.hash AS hash, starknet_blocks.number AS number, tx
+                FROM starknet_transactions
+                JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash",
+        )
+        .context("Prepare transaction query")?;
+    let mut rows = stmt.query([])?;
+
+    while let Some(r) = rows.next()? {
+        let transaction_hash = r.get_ref_unwrap("hash").as_blob()?;
+        let block_number = r.get_ref_unwrap("number").as_i64()?;
+        let tx = r.get_ref_unwrap("tx").as_blob()?;
+
+        let tx = zstd::decode_all(tx).context("Decompress transaction")?;
+        let tx: transaction::Transaction =
+            serde_json::de::from_slice(&tx).context("Deserializing transaction")?;
+
+        let class_hash = match (tx.r#type.as_str(), tx.class_hash) {
+            ("DECLARE", Some(class_hash)) => class_hash,
+            _ => continue,
+        };
+
+        transaction
+            .execute(
+                r"INSERT OR REPLACE INTO declared_classes ( transaction_hash,  class_hash,  block_number)
+                                                   VALUES (:transaction_hash, :class_hash, :block_number)",
+                named_params![
+                    ":transaction_hash": transaction_hash,
+                    ":class_hash": &class_hash.to_be_bytes()[..],
+                    ":block_number": block_number,
+                ],
+            )
+            .context("Insert declared class")?;
+    }
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::{named_params, Connection};
+    use serde_json::json;
+    use stark_hash::StarkHash;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 16);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM declared_classes", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn backfills_declare_transactions() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 16);
+
+        let block_hash = StarkHash::from_hex_str("b10c").unwrap();
+        const BLOCK_NUMBER: i64 = 7;
+        transaction
+            .execute(
+                r"INSERT INTO starknet_blocks ( number,  hash,  root,  timestamp,  gas_price)
+                                       VALUES (:number, :hash, :root, :timestamp, :gas_price)",
+                named_params![
+                    ":number": BLOCK_NUMBER,
+                    ":hash": &block_hash.to_be_bytes()[..],
+                    ":root": &[0u8; 32][..],
+                    ":timestamp": 0,
+                    ":gas_price": &[0u8; 16][..],
+                ],
+            )
+            .unwrap();
+
+        let declared = StarkHash::from_hex_str("c1a55").unwrap();
+        let transactions = [
+            (
+                StarkHash::from_hex_str("1").unwrap(),
+                json!({
+                    "class_hash": "0xc1a55",
+                    "contract_address": "0x1",
+                    "transaction_hash": "0x1",
+                    "type": "DECLARE",
+                }),
+            ),
+            (
+                StarkHash::from_hex_str("2").unwrap(),
+                json!({
+                    "contract_address": "0x2",
+                    "transaction_hash": "0x2",
+                    "type": "INVOKE_FUNCTION",
+                }),
+            ),
+            (
+                // Deploy transactions carry the class hash, but do not declare it.
+                StarkHash::from_hex_str("3").unwrap(),
+                json!({
+                    "class_hash": "0xc1a55",
+                    "contract_address": "0x3",
+                    "transaction_hash": "0x3",
+                    "type": "DEPLOY",
+                }),
+            ),
+        ];
+        for (idx, (hash, tx)) in transactions.iter().enumerate() {
+            let tx = zstd::bulk::compress(&serde_json::to_vec(tx).unwrap(), 1).unwrap();
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_transactions ( hash,  idx,  block_hash,  tx,  receipt)
+                                                 VALUES (:hash, :idx, :block_hash, :tx, :receipt)",
+                    named_params![
+                        ":hash": &hash.to_be_bytes()[..],
+                        ":idx": idx,
+                        ":block_hash": &block_hash.to_be_bytes()[..],
+                        ":tx": &tx,
+                        ":receipt": &[][..] as &[u8],
+                    ],
+                )
+                .unwrap();
+        }
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let rows = transaction
+            .prepare("SELECT transaction_hash, class_hash, block_number FROM declared_classes")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, Vec<u8>>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![(
+                transactions[0].0.to_be_bytes().to_vec(),
+                declared.to_be_bytes().to_vec(),
+                BLOCK_NUMBER
+            )]
+        );
+    }
+}
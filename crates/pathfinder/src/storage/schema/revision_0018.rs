@@ -0,0 +1,85 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `parent_hash` column to the `starknet_blocks` table.
+///
+/// Stored blocks always form a single chain starting at genesis, so the column is populated
+/// from the hash of the preceding block. The genesis block's parent is the zero hash, as it
+/// is reported by the sequencer.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute_batch(
+            r"ALTER TABLE starknet_blocks ADD COLUMN parent_hash BLOB NOT NULL
+            DEFAULT X'0000000000000000000000000000000000000000000000000000000000000000';
+
+            UPDATE starknet_blocks SET parent_hash = parent.hash
+                FROM (SELECT number, hash FROM starknet_blocks) AS parent
+                WHERE parent.number = starknet_blocks.number - 1;",
+        )
+        .context("Add parent_hash column to starknet_blocks table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::{named_params, Connection};
+    use stark_hash::StarkHash;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 17);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+    }
+
+    #[test]
+    fn backfills_parent_hashes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 17);
+
+        let hashes = ["a0", "a1", "a2"].map(|h| StarkHash::from_hex_str(h).unwrap());
+        for (number, hash) in hashes.iter().enumerate() {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks ( number,  hash,  root,  timestamp)
+                                           VALUES (:number, :hash, :root, :timestamp)",
+                    named_params![
+                        ":number": number,
+                        ":hash": &hash.to_be_bytes()[..],
+                        ":root": &[0u8; 32][..],
+                        ":timestamp": 0,
+                    ],
+                )
+                .unwrap();
+        }
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let mut stmt = transaction
+            .prepare("SELECT parent_hash FROM starknet_blocks ORDER BY number ASC")
+            .unwrap();
+        let parents = stmt
+            .query_map([], |row| {
+                let parent = row.get_ref_unwrap(0).as_blob().unwrap();
+                Ok(StarkHash::from_be_slice(parent).unwrap())
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(parents, vec![StarkHash::ZERO, hashes[0], hashes[1]]);
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration indexes block timestamps, so that the block closest to a given
+/// point in time can be found without a full table scan.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            "CREATE INDEX starknet_blocks_timestamp ON starknet_blocks(timestamp)",
+            [],
+        )
+        .context("Create timestamp index")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn timestamp_lookups_use_the_index() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 18);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let plan: String = transaction
+            .query_row(
+                "EXPLAIN QUERY PLAN SELECT number FROM starknet_blocks
+                    WHERE timestamp <= 10 ORDER BY timestamp DESC LIMIT 1",
+                [],
+                |row| row.get("detail"),
+            )
+            .unwrap();
+        assert!(plan.contains("starknet_blocks_timestamp"), "{plan}");
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `sync_retry_queue` table, which holds the items whose
+/// download failed during sync and which are retried in the background instead.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute_batch(
+            r"CREATE TABLE sync_retry_queue (
+                item_type       TEXT    NOT NULL,
+                identifier      BLOB    NOT NULL,
+                attempts        INTEGER NOT NULL,
+                next_attempt_at INTEGER NOT NULL,
+                last_error      TEXT    NOT NULL,
+                PRIMARY KEY (item_type, identifier)
+            ) WITHOUT ROWID;
+
+            CREATE INDEX sync_retry_queue_next_attempt_at ON sync_retry_queue(next_attempt_at);",
+        )
+        .context("Create sync_retry_queue table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 19);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM sync_retry_queue", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
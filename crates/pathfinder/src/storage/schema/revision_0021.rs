@@ -0,0 +1,75 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration replaces the empty data blobs of events without data by NULL, which
+/// is how such events are stored from now on.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    let updated = transaction
+        .execute(
+            "UPDATE starknet_events SET data = NULL WHERE length(data) = 0",
+            [],
+        )
+        .context("Set empty event data to NULL")?;
+    if updated > 0 {
+        tracing::info!(%updated, "Replaced empty event data by NULL");
+    }
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::{named_params, Connection};
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 20);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+    }
+
+    #[test]
+    fn empty_data_becomes_null() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 20);
+        transaction
+            .execute(
+                r"INSERT INTO starknet_blocks (number, hash, root, timestamp, gas_price, sequencer_address)
+                VALUES (0, X'00', X'00', 0, X'00', X'00')",
+                [],
+            )
+            .unwrap();
+        for (idx, data) in [vec![], vec![0u8; 32], vec![]].into_iter().enumerate() {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys, data)
+                    VALUES (0, :idx, X'01', X'02', 'key', :data)",
+                    named_params! {":idx": idx, ":data": data},
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let mut statement = transaction
+            .prepare("SELECT data FROM starknet_events ORDER BY idx")
+            .unwrap();
+        let data = statement
+            .query_map([], |row| row.get::<_, Option<Vec<u8>>>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(data, vec![None, Some(vec![0u8; 32]), None]);
+    }
+}
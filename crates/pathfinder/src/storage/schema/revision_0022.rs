@@ -0,0 +1,51 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `root_divergences` table, which records the blocks whose
+/// recomputed global root did not match the expected one, and on which sync halted.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            r"CREATE TABLE root_divergences (
+                block_number  INTEGER PRIMARY KEY,
+                block_hash    BLOB    NOT NULL,
+                computed_root BLOB    NOT NULL,
+                expected_root BLOB    NOT NULL,
+                l1_root       BLOB,
+                contracts     TEXT    NOT NULL,
+                recorded_at   INTEGER NOT NULL
+            )",
+            [],
+        )
+        .context("Create root_divergences table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 21);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM root_divergences", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `token_metadata` table, which caches the decimals, symbol and
+/// name of the ERC-20 tokens queried via `pathfinder_getTokenBalance`.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            r"CREATE TABLE token_metadata (
+                address    BLOB    PRIMARY KEY,
+                class_hash BLOB    NOT NULL,
+                decimals   INTEGER NOT NULL,
+                symbol     TEXT    NOT NULL,
+                name       TEXT    NOT NULL
+            )",
+            [],
+        )
+        .context("Create token_metadata table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 22);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM token_metadata", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
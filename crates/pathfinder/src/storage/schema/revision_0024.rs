@@ -0,0 +1,49 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `raw_gateway_blocks` table, which keeps the verbatim gateway JSON
+/// of the most recent blocks so that they can be re-parsed after a parsing bug is fixed.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            r"CREATE TABLE raw_gateway_blocks (
+                number INTEGER PRIMARY KEY,
+                hash   BLOB    NOT NULL,
+                data   BLOB    NOT NULL,
+                FOREIGN KEY(number) REFERENCES starknet_blocks(number)
+                ON DELETE CASCADE
+            )",
+            [],
+        )
+        .context("Create raw_gateway_blocks table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 23);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: usize = transaction
+            .query_row("SELECT count(1) FROM raw_gateway_blocks", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
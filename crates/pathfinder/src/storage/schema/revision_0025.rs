@@ -0,0 +1,47 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `trees_pruned_before` column to the `refs` table, which records
+/// up to which block the tree nodes of old states have been garbage collected.
+///
+/// `NULL` indicates that no state has been garbage collected yet.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            "ALTER TABLE refs ADD COLUMN trees_pruned_before INTEGER",
+            [],
+        )
+        .context("Add trees_pruned_before column to refs table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 24);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let pruned_before: Option<i64> = transaction
+            .query_row(
+                "SELECT trees_pruned_before FROM refs WHERE idx = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(pruned_before, None);
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `chain` column to the `refs` table, which records the chain
+/// the database belongs to, so that a node configured for another chain refuses to use it.
+///
+/// `NULL` indicates that the chain has not been recorded yet, either because the database is
+/// new or because it predates this revision.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute("ALTER TABLE refs ADD COLUMN chain TEXT", [])
+        .context("Add chain column to refs table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 25);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let chain: Option<String> = transaction
+            .query_row("SELECT chain FROM refs WHERE idx = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(chain, None);
+    }
+}
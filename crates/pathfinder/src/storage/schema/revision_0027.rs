@@ -0,0 +1,48 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `backfill_tail` and `backfill_head` columns to the `refs`
+/// table, which record the range of blocks that were backfilled backwards from the chain head
+/// without applying their state updates.
+///
+/// `NULL` indicates that there are no such blocks.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute("ALTER TABLE refs ADD COLUMN backfill_tail INTEGER", [])
+        .context("Add backfill_tail column to refs table")?;
+    transaction
+        .execute("ALTER TABLE refs ADD COLUMN backfill_head INTEGER", [])
+        .context("Add backfill_head column to refs table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 26);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let range: (Option<i64>, Option<i64>) = transaction
+            .query_row(
+                "SELECT backfill_tail, backfill_head FROM refs WHERE idx = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(range, (None, None));
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+use stark_hash::StarkHash;
+
+use crate::storage::bloom::KeyBloom;
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `starknet_events_key_blooms` table, which stores a bloom
+/// filter of the keys of all events emitted in a block. Event queries filtering on keys use
+/// it to skip blocks which cannot contain a matching event.
+///
+/// A block without a row emitted no events with keys. The table is backfilled from the
+/// existing events.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            r"CREATE TABLE starknet_events_key_blooms (
+                block_number INTEGER PRIMARY KEY NOT NULL,
+                bloom BLOB NOT NULL,
+                FOREIGN KEY(block_number) REFERENCES starknet_blocks(number)
+                ON DELETE CASCADE
+            )",
+            [],
+        )
+        .context("Create starknet_events_key_blooms table")?;
+
+    let mut query = transaction
+        .prepare("SELECT block_number, keys FROM starknet_events ORDER BY block_number")
+        .context("Prepare events query")?;
+    let mut insert = transaction
+        .prepare("INSERT INTO starknet_events_key_blooms (block_number, bloom) VALUES (?, ?)")
+        .context("Prepare bloom insert statement")?;
+
+    let mut rows = query.query([]).context("Query events")?;
+    let mut current: Option<(i64, KeyBloom)> = None;
+    while let Some(row) = rows.next().context("Fetch next event")? {
+        let block_number = row.get_ref_unwrap(0).as_i64()?;
+        let keys = row.get_ref_unwrap(1).as_str_or_null()?.unwrap_or_default();
+
+        match &current {
+            Some((number, bloom)) if *number != block_number => {
+                insert
+                    .execute(rusqlite::params![number, bloom.as_bytes()])
+                    .context("Insert bloom")?;
+                current = Some((block_number, KeyBloom::default()));
+            }
+            Some(_) => {}
+            None => current = Some((block_number, KeyBloom::default())),
+        }
+        let bloom = &mut current.as_mut().expect("Set above").1;
+
+        for key in keys.split_whitespace() {
+            let key = base64::decode(key).context("Decode event key")?;
+            let key = StarkHash::from_be_slice(&key).context("Parse event key")?;
+            bloom.insert(&key);
+        }
+    }
+
+    if let Some((number, bloom)) = current {
+        insert
+            .execute(rusqlite::params![number, bloom.as_bytes()])
+            .context("Insert bloom")?;
+    }
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use crate::storage::bloom::KeyBloom;
+
+    use rusqlite::Connection;
+    use stark_hash::StarkHash;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 27);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: i64 = transaction
+            .query_row(
+                "SELECT COUNT(*) FROM starknet_events_key_blooms",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn backfills_existing_events() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 27);
+
+        for number in 0..3u8 {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, root, timestamp)
+                    VALUES (?, ?, x'00', 0)",
+                    rusqlite::params![number, vec![number]],
+                )
+                .unwrap();
+        }
+
+        let key = |i: u8| StarkHash::from_be_slice(&[i]).unwrap();
+        let keys = |k: &[u8]| {
+            k.iter()
+                .map(|i| base64::encode(key(*i).as_be_bytes()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        // Block 0 emits two events, block 1 none and block 2 one.
+        for (block, idx, event_keys) in [
+            (0, 0, keys(&[1, 2])),
+            (0, 1, keys(&[3])),
+            (2, 0, keys(&[4])),
+        ] {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys)
+                    VALUES (?, ?, x'01', x'02', ?)",
+                    rusqlite::params![block, idx, event_keys],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let blooms = transaction
+            .prepare(
+                "SELECT block_number, bloom FROM starknet_events_key_blooms ORDER BY block_number",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .unwrap()
+            .map(|row| {
+                let (number, bloom) = row.unwrap();
+                (number, KeyBloom::from_bytes(&bloom).unwrap())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(blooms.len(), 2);
+        assert_eq!(blooms[0].0, 0);
+        assert!([1, 2, 3].iter().all(|i| blooms[0].1.may_contain(&key(*i))));
+        assert_eq!(blooms[1].0, 2);
+        assert!(blooms[1].1.may_contain(&key(4)));
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration re-encodes the keys of `starknet_events` as fixed width lowercase hex
+/// instead of base64.
+///
+/// Base64 output contains `+`, `/` and `=`, which the FTS5 `ascii` tokenizer treats as
+/// separators, so every key had to be quoted as a phrase when querying. Hex keys are always
+/// a single token.
+///
+/// The update trigger of the full-text index is dropped while re-encoding, and the index is
+/// rebuilt from the new contents afterwards instead.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute("DROP TRIGGER starknet_events_au", [])
+        .context("Drop starknet_events_au trigger")?;
+
+    let mut query = transaction
+        .prepare("SELECT rowid, keys FROM starknet_events")
+        .context("Prepare events query")?;
+    let mut update = transaction
+        .prepare("UPDATE starknet_events SET keys = ? WHERE rowid = ?")
+        .context("Prepare keys update statement")?;
+
+    let mut rows = query.query([]).context("Query events")?;
+    while let Some(row) = rows.next().context("Fetch next event")? {
+        let rowid = row.get_ref_unwrap(0).as_i64()?;
+        let keys = row.get_ref_unwrap(1).as_str_or_null()?.unwrap_or_default();
+        if keys.is_empty() {
+            continue;
+        }
+
+        let keys = keys
+            .split_whitespace()
+            .map(|key| {
+                let key = base64::decode(key).context("Decode event key")?;
+                anyhow::ensure!(key.len() <= 32, "Event key is too long");
+                let mut padded = [0u8; 32];
+                padded[32 - key.len()..].copy_from_slice(&key);
+                Ok(hex::encode(padded))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .join(" ");
+
+        update
+            .execute(rusqlite::params![keys, rowid])
+            .context("Update event keys")?;
+    }
+
+    transaction
+        .execute_batch(
+            r"CREATE TRIGGER starknet_events_au
+            AFTER UPDATE ON starknet_events
+            BEGIN
+                INSERT INTO starknet_events_keys(starknet_events_keys, rowid, keys)
+                VALUES (
+                    'delete',
+                    old.rowid,
+                    old.keys
+                );
+                INSERT INTO starknet_events_keys(rowid, keys)
+                VALUES (
+                    new.rowid,
+                    new.keys
+                );
+            END;
+
+            INSERT INTO starknet_events_keys(starknet_events_keys) VALUES ('rebuild');",
+        )
+        .context("Recreate starknet_events_au trigger and rebuild the keys index")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::core::{
+        ContractAddress, EventKey, StarknetBlockHash, StarknetBlockNumber, StarknetTransactionHash,
+    };
+    use crate::storage::bloom::KeyBloom;
+    use crate::storage::schema;
+    use crate::storage::{
+        StarknetEmittedEvent, StarknetEventFilter, StarknetEventsTable, StorageLimits,
+    };
+
+    use rusqlite::Connection;
+    use stark_hash::StarkHash;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 28);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+    }
+
+    #[test]
+    fn stateful() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        // 1. Insert events with base64 encoded keys, as the previous revision did
+        schema::migrate_to(&transaction, 28);
+
+        let key = |i: u8| EventKey(StarkHash::from_be_slice(&[i]).unwrap());
+        let base64_keys = |k: &[u8]| {
+            k.iter()
+                .map(|i| base64::encode(key(*i).0.as_be_bytes()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        for number in 0..2u8 {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, root, timestamp)
+                    VALUES (?, ?, x'00', 0)",
+                    rusqlite::params![number, vec![number]],
+                )
+                .unwrap();
+        }
+        for (block, idx, keys) in [
+            (0u8, 0, base64_keys(&[1, 2])),
+            (0, 1, base64_keys(&[])),
+            (1, 0, base64_keys(&[2, 3])),
+        ] {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys)
+                    VALUES (?, ?, x'01', x'02', ?)",
+                    rusqlite::params![block, idx, keys],
+                )
+                .unwrap();
+        }
+        for (block, keys) in [(0u8, [1, 2]), (1, [2, 3])] {
+            let mut bloom = KeyBloom::default();
+            keys.iter().for_each(|i| bloom.insert(&key(*i).0));
+            transaction
+                .execute(
+                    "INSERT INTO starknet_events_key_blooms (block_number, bloom) VALUES (?, ?)",
+                    rusqlite::params![block, bloom.as_bytes()],
+                )
+                .unwrap();
+        }
+
+        // 2. Migrate
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let stored_keys = transaction
+            .prepare("SELECT keys FROM starknet_events ORDER BY block_number, idx")
+            .unwrap()
+            .query_map([], |row| row.get::<_, Option<String>>(0))
+            .unwrap()
+            .map(|keys| keys.unwrap().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stored_keys,
+            vec![
+                format!("{:064x} {:064x}", 1, 2),
+                String::new(),
+                format!("{:064x} {:064x}", 2, 3),
+            ]
+        );
+
+        // 3. Querying by keys uses the rebuilt index
+        let filter = |keys: Vec<EventKey>| StarknetEventFilter {
+            from_block: None,
+            to_block: None,
+            block_hash: None,
+            contract_address: None,
+            keys,
+            data_contains: vec![],
+            include_reverted: true,
+            page_size: 10,
+            page_number: 0,
+        };
+        let event = |block: u8, keys: &[u8]| StarknetEmittedEvent {
+            from_address: ContractAddress(StarkHash::from_be_slice(&[2]).unwrap()),
+            data: vec![],
+            keys: keys.iter().map(|i| key(*i)).collect(),
+            block_hash: StarknetBlockHash(StarkHash::from_be_slice(&[block]).unwrap()),
+            block_number: StarknetBlockNumber(block as u64),
+            transaction_hash: StarknetTransactionHash(StarkHash::from_be_slice(&[1]).unwrap()),
+        };
+
+        let events = StarknetEventsTable::get_events(
+            &transaction,
+            &filter(vec![key(1)]),
+            &StorageLimits::default(),
+        )
+        .unwrap()
+        .events;
+        assert_eq!(events, vec![event(0, &[1, 2])]);
+
+        let events = StarknetEventsTable::get_events(
+            &transaction,
+            &filter(vec![key(2)]),
+            &StorageLimits::default(),
+        )
+        .unwrap()
+        .events;
+        assert_eq!(events, vec![event(0, &[1, 2]), event(1, &[2, 3])]);
+
+        let events = StarknetEventsTable::get_events(
+            &transaction,
+            &filter(vec![key(4)]),
+            &StorageLimits::default(),
+        )
+        .unwrap()
+        .events;
+        assert!(events.is_empty());
+
+        assert_eq!(
+            StarknetEventsTable::count_by_key(&transaction, key(3)).unwrap(),
+            1
+        );
+
+        // 4. Updates still keep the index in sync
+        transaction
+            .execute(
+                "UPDATE starknet_events SET keys = ? WHERE block_number = 1",
+                [format!("{:064x}", 4)],
+            )
+            .unwrap();
+        assert_eq!(
+            StarknetEventsTable::count_by_key(&transaction, key(3)).unwrap(),
+            0
+        );
+        assert_eq!(
+            StarknetEventsTable::count_by_key(&transaction, key(4)).unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `block_event_summary` table, which holds the number of events
+/// emitted in each block and the number of distinct contracts which emitted them, so that these
+/// can be read without aggregating the events table.
+///
+/// A block without a row emitted no events. The table is backfilled from the existing events.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            r"CREATE TABLE block_event_summary (
+                block_number INTEGER PRIMARY KEY NOT NULL,
+                event_count INTEGER NOT NULL,
+                emitter_count INTEGER NOT NULL,
+                FOREIGN KEY(block_number) REFERENCES starknet_blocks(number)
+                ON DELETE CASCADE
+            )",
+            [],
+        )
+        .context("Create block_event_summary table")?;
+
+    transaction
+        .execute(
+            r"INSERT INTO block_event_summary (block_number, event_count, emitter_count)
+                SELECT block_number, COUNT(1), COUNT(DISTINCT from_address)
+                FROM starknet_events GROUP BY block_number",
+            [],
+        )
+        .context("Backfill block_event_summary table")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 29);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let count: i64 = transaction
+            .query_row("SELECT COUNT(*) FROM block_event_summary", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn backfills_existing_events() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 29);
+
+        for number in 0..3u8 {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_blocks (number, hash, root, timestamp)
+                    VALUES (?, ?, x'00', 0)",
+                    rusqlite::params![number, vec![number]],
+                )
+                .unwrap();
+        }
+        // Block 0 emits three events from two contracts, block 1 none and block 2 one.
+        for (block, idx, from_address) in [(0, 0, 1u8), (0, 1, 2), (0, 2, 1), (2, 0, 3)] {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys)
+                    VALUES (?, ?, x'01', ?, '')",
+                    rusqlite::params![block, idx, vec![from_address]],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let summaries = transaction
+            .prepare(
+                "SELECT block_number, event_count, emitter_count FROM block_event_summary ORDER BY block_number",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(summaries, vec![(0, 3, 2), (2, 1, 1)]);
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::Context;
+use rusqlite::{OptionalExtension, Transaction};
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration pads the `from_address` of `starknet_events` to 32 bytes, and replaces
+/// the `from_address` index by a composite one on the contract address filter and the ordering
+/// of event queries.
+///
+/// Addresses have always been written as 32 big-endian bytes, so padding is only a safeguard:
+/// queries compare blobs, and a shorter encoding of the same address would never match. Short
+/// addresses are found by stepping through the distinct addresses of the old index, which costs
+/// a seek per contract instead of a scan over all events.
+///
+/// The new index is on `(from_address, block_number, transaction_hash, idx)`. The equality
+/// column comes first, so that a block range is a contiguous slice of a contract's entries,
+/// whereas a `(block_number, from_address)` index would have to visit every block of the range.
+/// Listing the remaining ordering columns lets queries by contract address read events in the
+/// requested order, stopping at the page size instead of sorting all of the contract's events.
+///
+/// The index is not covering: the `keys` and `data` of the matching events are still read from
+/// the table. Covering them would duplicate nearly all of `starknet_events` in the index.
+///
+/// Its `(from_address, block_number)` prefix serves queries which only use those columns, so no
+/// separate index is kept for them. Queries by block range only keep using
+/// `starknet_events_block_number`.
+///
+/// The wider index slows down inserting events by roughly an eighth, see the
+/// `address_index_insert_cost` test. It is not made optional: it replaces the only index on
+/// `from_address`, so leaving it out would turn every query by contract address into a scan.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    let short_addresses = {
+        let mut next_address = transaction
+            .prepare(
+                r"SELECT from_address FROM starknet_events INDEXED BY starknet_events_from_address
+                WHERE from_address > ? ORDER BY from_address LIMIT 1",
+            )
+            .context("Prepare next address query")?;
+
+        let mut short_addresses = Vec::new();
+        let mut previous = Vec::new();
+        loop {
+            let address = next_address
+                .query_row([&previous], |row| row.get::<_, Vec<u8>>(0))
+                .optional()
+                .context("Query next address")?;
+            match address {
+                Some(address) => {
+                    if address.len() < 32 {
+                        short_addresses.push(address.clone());
+                    }
+                    previous = address;
+                }
+                None => break,
+            }
+        }
+        short_addresses
+    };
+
+    // Collect the rows before updating, instead of updating the table while querying it.
+    let mut rows = Vec::new();
+    {
+        let mut query = transaction
+            .prepare("SELECT rowid FROM starknet_events WHERE from_address = ?")
+            .context("Prepare short address query")?;
+        for address in &short_addresses {
+            let rowids = query
+                .query_map([address], |row| row.get::<_, i64>(0))
+                .context("Query events of short address")?
+                .collect::<Result<Vec<_>, _>>()
+                .context("Fetch events of short address")?;
+
+            let mut padded = [0u8; 32];
+            padded[32 - address.len()..].copy_from_slice(address);
+            rows.extend(rowids.into_iter().map(|rowid| (rowid, padded)));
+        }
+    }
+
+    let mut update = transaction
+        .prepare("UPDATE starknet_events SET from_address = ? WHERE rowid = ?")
+        .context("Prepare address update statement")?;
+    for (rowid, padded) in rows {
+        update
+            .execute(rusqlite::params![&padded[..], rowid])
+            .context("Update event address")?;
+    }
+
+    transaction
+        .execute_batch(
+            r"DROP INDEX starknet_events_from_address;
+            CREATE INDEX starknet_events_from_address_block_number
+                ON starknet_events(from_address, block_number, transaction_hash, idx);",
+        )
+        .context("Replace from_address index")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::{Connection, Transaction};
+
+    fn event_indexes(transaction: &Transaction) -> Vec<String> {
+        transaction
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'starknet_events' ORDER BY name",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn empty() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 30);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let indexes = event_indexes(&transaction);
+        assert!(
+            indexes.contains(&"starknet_events_from_address_block_number".to_owned()),
+            "{:?}",
+            indexes
+        );
+        assert!(
+            !indexes.contains(&"starknet_events_from_address".to_owned()),
+            "{:?}",
+            indexes
+        );
+    }
+
+    #[test]
+    fn pads_short_addresses() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 30);
+
+        transaction
+            .execute(
+                r"INSERT INTO starknet_blocks (number, hash, root, timestamp)
+                VALUES (0, x'00', x'00', 0)",
+                [],
+            )
+            .unwrap();
+        let full = [0xabu8; 32];
+        for (idx, from_address) in [vec![0x12u8, 0x34], full.to_vec()].into_iter().enumerate() {
+            transaction
+                .execute(
+                    r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys)
+                    VALUES (0, ?, x'01', ?, '')",
+                    rusqlite::params![idx, from_address],
+                )
+                .unwrap();
+        }
+
+        super::migrate(&transaction).unwrap();
+
+        let addresses = transaction
+            .prepare("SELECT from_address FROM starknet_events ORDER BY idx")
+            .unwrap()
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut padded = [0u8; 32];
+        padded[30..].copy_from_slice(&[0x12, 0x34]);
+        assert_eq!(addresses, vec![padded.to_vec(), full.to_vec()]);
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration indexes the class hashes of contracts and contract states, so that
+/// [ClassBackfillTable::missing](crate::storage::ClassBackfillTable::missing) reads the classes
+/// after its cursor in order, instead of scanning both tables for every batch.
+///
+/// Both tables receive at most a few rows per block, so the additional write cost is small.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute_batch(
+            r"CREATE INDEX contracts_hash ON contracts(hash);
+            CREATE INDEX contract_states_hash ON contract_states(hash);",
+        )
+        .context("Create class hash indexes")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn class_hash_lookups_use_the_indexes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 31);
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        for (query, index) in [
+            (
+                "SELECT hash FROM contracts WHERE hash > X'01' ORDER BY hash",
+                "contracts_hash",
+            ),
+            (
+                "SELECT hash FROM contract_states WHERE hash > X'01' ORDER BY hash",
+                "contract_states_hash",
+            ),
+        ] {
+            let plan: String = transaction
+                .query_row(&format!("EXPLAIN QUERY PLAN {}", query), [], |row| {
+                    row.get("detail")
+                })
+                .unwrap();
+            assert!(plan.contains(index), "{plan}");
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::Context;
+use rusqlite::Transaction;
+
+use crate::storage::schema::PostMigrationAction;
+
+/// This schema migration adds the `reverted` column to `starknet_events`, which marks the events
+/// emitted by transactions whose receipt has an execution status of `REVERTED`. It is kept next
+/// to the events, so that event queries can exclude them without joining and decoding the
+/// receipts.
+///
+/// The events stored so far are marked as not reverted instead of decoding every receipt, as
+/// the gateway has not reported reverted transactions in its blocks.
+/// [StarknetEventsTable::rebuild_from_receipts](crate::storage::StarknetEventsTable::rebuild_from_receipts)
+/// re-derives the column from the receipts.
+pub(crate) fn migrate(transaction: &Transaction) -> anyhow::Result<PostMigrationAction> {
+    transaction
+        .execute(
+            "ALTER TABLE starknet_events ADD COLUMN reverted INTEGER NOT NULL DEFAULT 0",
+            [],
+        )
+        .context("Add reverted column")?;
+
+    Ok(PostMigrationAction::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostMigrationAction;
+    use crate::storage::schema;
+
+    use rusqlite::Connection;
+
+    #[test]
+    fn existing_events_are_not_reverted() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let transaction = conn.transaction().unwrap();
+
+        schema::migrate_to(&transaction, 32);
+
+        transaction
+            .execute(
+                r"INSERT INTO starknet_blocks (number, hash, root, timestamp)
+                VALUES (0, x'00', x'00', 0)",
+                [],
+            )
+            .unwrap();
+        transaction
+            .execute(
+                r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys)
+                VALUES (0, 0, x'01', x'02', '')",
+                [],
+            )
+            .unwrap();
+
+        let action = super::migrate(&transaction).unwrap();
+        assert_eq!(action, PostMigrationAction::None);
+
+        let reverted: i64 = transaction
+            .query_row("SELECT reverted FROM starknet_events", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(reverted, 0);
+    }
+}
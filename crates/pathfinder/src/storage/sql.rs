@@ -0,0 +1,212 @@
+//! Ad hoc, read-only SQL queries against the node's database.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::Context;
+use rusqlite::types::Value;
+
+use super::Storage;
+
+/// The rows returned by [read_only_query].
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    /// Set if the query returned more rows than were collected.
+    pub truncated: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("Only a single SELECT statement is allowed")]
+    NotSingleSelect,
+    #[error("Query exceeded its time budget of {0:?}")]
+    TimedOut(Duration),
+    #[error("Invalid query: {0}")]
+    Invalid(rusqlite::Error),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+impl From<rusqlite::Error> for QueryError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::MultipleStatement => QueryError::NotSingleSelect,
+            other => QueryError::Invalid(other),
+        }
+    }
+}
+
+/// Runs a single SELECT statement on a dedicated `query_only` connection.
+///
+/// At most `max_rows` rows are collected, and the query is interrupted once it has run for
+/// longer than `timeout`.
+pub fn read_only_query(
+    storage: &Storage,
+    sql: &str,
+    max_rows: usize,
+    timeout: Duration,
+) -> Result<QueryResult, QueryError> {
+    let connection = storage
+        .connection()
+        .context("Opening database connection")?;
+    connection
+        .execute_batch("PRAGMA query_only = ON")
+        .context("Enabling query_only")?;
+
+    let mut statement = connection.prepare(sql)?;
+    // Statements which neither write nor return data, e.g. BEGIN, are of no use here either.
+    if !statement.readonly() || statement.column_count() == 0 {
+        return Err(QueryError::NotSingleSelect);
+    }
+    let columns = statement
+        .column_names()
+        .into_iter()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    let interrupt = connection.get_interrupt_handle();
+    let (finished, finished_rx) = mpsc::channel::<()>();
+    let timer = std::thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = finished_rx.recv_timeout(timeout) {
+            interrupt.interrupt();
+        }
+    });
+
+    let result = collect_rows(&mut statement, columns.len(), max_rows);
+
+    drop(finished);
+    let _ = timer.join();
+
+    let (rows, truncated) = result.map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(e, _)
+            if e.code == rusqlite::ErrorCode::OperationInterrupted =>
+        {
+            QueryError::TimedOut(timeout)
+        }
+        other => QueryError::from(other),
+    })?;
+
+    Ok(QueryResult {
+        columns,
+        rows,
+        truncated,
+    })
+}
+
+fn collect_rows(
+    statement: &mut rusqlite::Statement<'_>,
+    column_count: usize,
+    max_rows: usize,
+) -> rusqlite::Result<(Vec<Vec<Value>>, bool)> {
+    let mut rows = statement.query([])?;
+    let mut collected = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        if collected.len() == max_rows {
+            return Ok((collected, true));
+        }
+
+        let values = (0..column_count)
+            .map(|i| row.get::<_, Value>(i))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        collected.push(values);
+    }
+
+    Ok((collected, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_only_query, QueryError, QueryResult};
+    use crate::storage::Storage;
+    use assert_matches::assert_matches;
+    use rusqlite::types::Value;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Numbers 1 to 100.
+    const NUMBERS: &str =
+        "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 100)";
+
+    #[test]
+    fn aggregate() {
+        let storage = Storage::in_memory().unwrap();
+        let sql = format!("{} SELECT count(x), sum(x) FROM n", NUMBERS);
+
+        let result = read_only_query(&storage, &sql, 10, TIMEOUT).unwrap();
+        assert_eq!(
+            result,
+            QueryResult {
+                columns: vec!["count(x)".to_owned(), "sum(x)".to_owned()],
+                rows: vec![vec![Value::Integer(100), Value::Integer(5050)]],
+                truncated: false,
+            }
+        );
+    }
+
+    #[test]
+    fn row_cap() {
+        let storage = Storage::in_memory().unwrap();
+        let sql = format!("{} SELECT x FROM n", NUMBERS);
+
+        let result = read_only_query(&storage, &sql, 10, TIMEOUT).unwrap();
+        assert!(result.truncated);
+        assert_eq!(
+            result.rows,
+            (1..=10)
+                .map(|x| vec![Value::Integer(x)])
+                .collect::<Vec<_>>()
+        );
+
+        // Exactly at the cap is not truncated.
+        let result = read_only_query(&storage, &sql, 100, TIMEOUT).unwrap();
+        assert!(!result.truncated);
+        assert_eq!(result.rows.len(), 100);
+    }
+
+    #[test]
+    fn rejects_writes() {
+        let storage = Storage::in_memory().unwrap();
+
+        for sql in [
+            "INSERT INTO refs (idx, l1_l2_head) VALUES (2, 0)",
+            "DELETE FROM starknet_blocks",
+            "CREATE TABLE foo (bar INTEGER)",
+            "SELECT 1; DELETE FROM starknet_blocks",
+            "BEGIN",
+            "",
+        ] {
+            assert_matches!(
+                read_only_query(&storage, sql, 10, TIMEOUT),
+                Err(QueryError::NotSingleSelect),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_sql() {
+        let storage = Storage::in_memory().unwrap();
+        assert_matches!(
+            read_only_query(&storage, "SELECT * FROM no_such_table", 10, TIMEOUT),
+            Err(QueryError::Invalid(_))
+        );
+    }
+
+    #[test]
+    fn time_budget() {
+        let storage = Storage::in_memory().unwrap();
+        // Never terminates by itself.
+        let sql = "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) \
+            SELECT count(x) FROM n";
+
+        let timeout = Duration::from_millis(100);
+        assert_matches!(
+            read_only_query(&storage, sql, 10, timeout),
+            Err(QueryError::TimedOut(t)) => assert_eq!(t, timeout)
+        );
+    }
+}
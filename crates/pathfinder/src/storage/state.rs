@@ -1,4 +1,9 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
 use anyhow::Context;
+use rusqlite::types::Value;
 use rusqlite::{named_params, params, Connection, OptionalExtension, Transaction};
 use stark_hash::StarkHash;
 use web3::types::H256;
@@ -10,10 +15,40 @@ use crate::{
         EventData, EventKey, GasPrice, GlobalRoot, SequencerAddress, StarknetBlockHash,
         StarknetBlockNumber, StarknetBlockTimestamp, StarknetTransactionHash,
     },
-    ethereum::{log::StateUpdateLog, BlockOrigin, EthOrigin, TransactionOrigin},
+    ethereum::{
+        log::StateUpdateLog, state_update::StateUpdate, BlockOrigin, Chain, EthOrigin,
+        TransactionOrigin,
+    },
     sequencer::reply::transaction,
+    storage::{blob, bloom::KeyBloom, packed, statement_cache::PrepareCounted, StorageLimits},
 };
 
+const BLOCK_BY_NUMBER_QUERY: &str =
+    "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+        FROM starknet_blocks WHERE number = ?";
+const BLOCK_BY_HASH_QUERY: &str =
+    "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+        FROM starknet_blocks WHERE hash = ? ORDER BY number ASC LIMIT 1";
+const LATEST_BLOCK_QUERY: &str =
+    "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+        FROM starknet_blocks ORDER BY number DESC LIMIT 1";
+const LATEST_BLOCK_NUMBER_QUERY: &str =
+    "SELECT number FROM starknet_blocks ORDER BY number DESC LIMIT 1";
+const TRANSACTION_BY_HASH_QUERY: &str = "SELECT tx FROM starknet_transactions WHERE hash = ?1";
+const RECEIPT_BY_HASH_QUERY: &str =
+    "SELECT receipt, block_hash FROM starknet_transactions WHERE hash = ?1";
+
+/// Fixed queries which are run by most RPC calls, and are prepared as soon as a
+/// [long-lived connection](crate::storage::Storage::long_lived_connection) is opened.
+pub(super) const HOT_QUERIES: &[&str] = &[
+    BLOCK_BY_NUMBER_QUERY,
+    BLOCK_BY_HASH_QUERY,
+    LATEST_BLOCK_QUERY,
+    LATEST_BLOCK_NUMBER_QUERY,
+    TRANSACTION_BY_HASH_QUERY,
+    RECEIPT_BY_HASH_QUERY,
+];
+
 /// Contains the [L1 Starknet update logs](StateUpdateLog).
 pub struct L1StateTable {}
 
@@ -83,10 +118,10 @@ impl L1StateTable {
     ) -> anyhow::Result<Option<GlobalRoot>> {
         let mut statement = match block {
             L1TableBlockId::Number(_) => {
-                connection.prepare("SELECT starknet_global_root FROM l1_state WHERE starknet_block_number = ?")
+                connection.prepare_counted("SELECT starknet_global_root FROM l1_state WHERE starknet_block_number = ?")
             }
             L1TableBlockId::Latest => connection
-                .prepare("SELECT starknet_global_root FROM l1_state ORDER BY starknet_block_number DESC LIMIT 1"),
+                .prepare_counted("SELECT starknet_global_root FROM l1_state ORDER BY starknet_block_number DESC LIMIT 1"),
         }?;
 
         let mut rows = match block {
@@ -116,7 +151,7 @@ impl L1StateTable {
         block: L1TableBlockId,
     ) -> anyhow::Result<Option<StateUpdateLog>> {
         let mut statement = match block {
-            L1TableBlockId::Number(_) => connection.prepare(
+            L1TableBlockId::Number(_) => connection.prepare_counted(
                 r"SELECT starknet_block_number,
                     starknet_global_root,
                     ethereum_block_hash,
@@ -126,7 +161,7 @@ impl L1StateTable {
                     ethereum_log_index
                 FROM l1_state WHERE starknet_block_number = ?",
             ),
-            L1TableBlockId::Latest => connection.prepare(
+            L1TableBlockId::Latest => connection.prepare_counted(
                 r"SELECT starknet_block_number,
                     starknet_global_root,
                     ethereum_block_hash,
@@ -238,6 +273,133 @@ impl RefsTable {
 
         Ok(())
     }
+
+    /// Lowers the L1-L2 head to below `reorg_tail` if it currently points at or above it,
+    /// and returns the resulting L1-L2 head.
+    ///
+    /// The [backfill range](Self::get_backfill_range) is truncated to below `reorg_tail` as well.
+    pub fn reorg(
+        connection: &Connection,
+        reorg_tail: StarknetBlockNumber,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        if let Some(range) = Self::get_backfill_range(connection).context("Query backfill range")? {
+            if *range.end() >= reorg_tail {
+                let range = (*range.start() < reorg_tail).then(|| *range.start()..=reorg_tail - 1);
+                Self::set_backfill_range(connection, range).context("Update backfill range")?;
+            }
+        }
+
+        let head = Self::get_l1_l2_head(connection).context("Query L1-L2 head")?;
+        match head {
+            Some(head) if head >= reorg_tail => {
+                let new_head = match reorg_tail {
+                    StarknetBlockNumber::GENESIS => None,
+                    other => Some(other - 1),
+                };
+                Self::set_l1_l2_head(connection, new_head).context("Update L1-L2 head")?;
+                Ok(new_head)
+            }
+            other => Ok(other),
+        }
+    }
+
+    /// Returns the block below which the tree nodes of old states have been garbage collected,
+    /// see [gc_trees](crate::state::tree_gc::gc_trees).
+    ///
+    /// The states of these blocks can no longer be read.
+    pub fn get_trees_pruned_before(
+        connection: &Connection,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        let block_number = connection.query_row(
+            "SELECT trees_pruned_before FROM refs WHERE idx = 1",
+            [],
+            |row| row.get::<_, Option<i64>>(0),
+        )?;
+
+        Ok(block_number.map(|x| StarknetBlockNumber(x as u64)))
+    }
+
+    /// Records that the tree nodes of the states of all blocks before `block` have been
+    /// garbage collected.
+    pub fn set_trees_pruned_before(
+        connection: &Connection,
+        block: StarknetBlockNumber,
+    ) -> anyhow::Result<()> {
+        connection.execute(
+            "UPDATE refs SET trees_pruned_before = ? WHERE idx = 1",
+            [block.0],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the range of blocks which were [backfilled](crate::state::block_backfill) without
+    /// applying their state updates, or [None] if there are no such blocks.
+    ///
+    /// The state of these blocks is not available until the forward sync reaches them.
+    pub fn get_backfill_range(
+        connection: &Connection,
+    ) -> anyhow::Result<Option<RangeInclusive<StarknetBlockNumber>>> {
+        let range = connection.query_row(
+            "SELECT backfill_tail, backfill_head FROM refs WHERE idx = 1",
+            [],
+            |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?)),
+        )?;
+
+        match range {
+            (Some(tail), Some(head)) => Ok(Some(
+                StarknetBlockNumber(tail as u64)..=StarknetBlockNumber(head as u64),
+            )),
+            (None, None) => Ok(None),
+            other => anyhow::bail!("Incomplete backfill range {:?}", other),
+        }
+    }
+
+    /// Sets the range of blocks which were backfilled without applying their state updates.
+    pub fn set_backfill_range(
+        connection: &Connection,
+        range: Option<RangeInclusive<StarknetBlockNumber>>,
+    ) -> anyhow::Result<()> {
+        match range {
+            Some(range) => connection.execute(
+                "UPDATE refs SET backfill_tail = ?, backfill_head = ? WHERE idx = 1",
+                [range.start().0, range.end().0],
+            ),
+            None => connection.execute(
+                "UPDATE refs SET backfill_tail = NULL, backfill_head = NULL WHERE idx = 1",
+                [],
+            ),
+        }?;
+
+        Ok(())
+    }
+
+    /// Returns the chain which the database belongs to, or [None] if it has not been recorded
+    /// yet.
+    pub fn get_chain(connection: &Connection) -> anyhow::Result<Option<Chain>> {
+        let chain = connection.query_row("SELECT chain FROM refs WHERE idx = 1", [], |row| {
+            row.get::<_, Option<String>>(0)
+        })?;
+
+        chain
+            .map(|chain| match chain.as_str() {
+                "mainnet" => Ok(Chain::Mainnet),
+                "goerli" => Ok(Chain::Goerli),
+                other => Err(anyhow::anyhow!("Unknown chain {}", other)),
+            })
+            .transpose()
+    }
+
+    /// Records the chain which the database belongs to.
+    pub fn set_chain(connection: &Connection, chain: Chain) -> anyhow::Result<()> {
+        let chain = match chain {
+            Chain::Mainnet => "mainnet",
+            Chain::Goerli => "goerli",
+        };
+        connection.execute("UPDATE refs SET chain = ? WHERE idx = 1", [chain])?;
+
+        Ok(())
+    }
 }
 /// Stores all known [StarknetBlocks][StarknetBlock].
 pub struct StarknetBlocksTable {}
@@ -245,8 +407,8 @@ impl StarknetBlocksTable {
     /// Insert a new [StarknetBlock]. Fails if the block number is not unique.
     pub fn insert(connection: &Connection, block: &StarknetBlock) -> anyhow::Result<()> {
         connection.execute(
-            r"INSERT INTO starknet_blocks ( number,  hash,  root,  timestamp,  gas_price,  sequencer_address)
-                                   VALUES (:number, :hash, :root, :timestamp, :gas_price, :sequencer_address)",
+            r"INSERT INTO starknet_blocks ( number,  hash,  root,  timestamp,  gas_price,  sequencer_address,  parent_hash)
+                                   VALUES (:number, :hash, :root, :timestamp, :gas_price, :sequencer_address, :parent_hash)",
             named_params! {
                 ":number": block.number.0,
                 ":hash": block.hash.0.as_be_bytes(),
@@ -254,6 +416,7 @@ impl StarknetBlocksTable {
                 ":timestamp": block.timestamp.0,
                 ":gas_price": &block.gas_price.to_be_bytes(),
                 ":sequencer_address": block.sequencer_address.0.as_be_bytes(),
+                ":parent_hash": block.parent_hash.0.as_be_bytes(),
             },
         )?;
 
@@ -261,22 +424,21 @@ impl StarknetBlocksTable {
     }
 
     /// Returns the requested [StarknetBlock].
+    ///
+    /// Block hashes are expected to be unique, but this is not enforced by the database. Should
+    /// multiple blocks share the requested hash, the lowest-numbered one is returned. Use
+    /// [find_duplicate_hashes](Self::find_duplicate_hashes) to check for such blocks.
     pub fn get(
         connection: &Connection,
         block: StarknetBlocksBlockId,
     ) -> anyhow::Result<Option<StarknetBlock>> {
         let mut statement = match block {
-            StarknetBlocksBlockId::Number(_) => connection.prepare(
-                "SELECT hash, number, root, timestamp, gas_price, sequencer_address
-                    FROM starknet_blocks WHERE number = ?",
-            ),
-            StarknetBlocksBlockId::Hash(_) => connection.prepare(
-                "SELECT hash, number, root, timestamp, gas_price, sequencer_address
-                    FROM starknet_blocks WHERE hash = ?",
-            ),
-            StarknetBlocksBlockId::Latest => connection.prepare(
-                "SELECT hash, number, root, timestamp, gas_price, sequencer_address
-                    FROM starknet_blocks ORDER BY number DESC LIMIT 1",
+            StarknetBlocksBlockId::Number(_) => connection.prepare_counted(BLOCK_BY_NUMBER_QUERY),
+            StarknetBlocksBlockId::Hash(_) => connection.prepare_counted(BLOCK_BY_HASH_QUERY),
+            StarknetBlocksBlockId::Latest => connection.prepare_counted(LATEST_BLOCK_QUERY),
+            StarknetBlocksBlockId::LatestMinus(_) => connection.prepare_counted(
+                "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+                    FROM starknet_blocks WHERE number = (SELECT MAX(number) FROM starknet_blocks) - ?",
             ),
         }?;
 
@@ -284,69 +446,210 @@ impl StarknetBlocksTable {
             StarknetBlocksBlockId::Number(number) => statement.query(params![number.0]),
             StarknetBlocksBlockId::Hash(hash) => statement.query(params![hash.0.as_be_bytes()]),
             StarknetBlocksBlockId::Latest => statement.query([]),
+            StarknetBlocksBlockId::LatestMinus(offset) => match i64::try_from(offset) {
+                Ok(offset) => statement.query(params![offset]),
+                // Past genesis for any chain.
+                Err(_) => return Ok(None),
+            },
         }?;
 
         let row = rows.next().context("Iterate rows")?;
 
-        match row {
-            Some(row) => {
-                let number = row.get_ref_unwrap("number").as_i64().unwrap() as u64;
-                let number = StarknetBlockNumber(number);
+        Ok(row.map(Self::block_from_row))
+    }
+
+    /// Returns the latest [StarknetBlock] whose state update has been applied, i.e. the latest
+    /// block below the [backfill range](RefsTable::get_backfill_range).
+    ///
+    /// This is the head of the forward sync, and equals the latest block if no blocks were
+    /// backfilled.
+    pub fn get_state_head(connection: &Connection) -> anyhow::Result<Option<StarknetBlock>> {
+        let mut statement = connection.prepare_counted(
+            "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+                FROM starknet_blocks
+                WHERE number < COALESCE((SELECT backfill_tail FROM refs WHERE idx = 1), 9223372036854775807)
+                ORDER BY number DESC LIMIT 1",
+        )?;
 
-                let hash = row.get_ref_unwrap("hash").as_blob().unwrap();
-                let hash = StarkHash::from_be_slice(hash).unwrap();
-                let hash = StarknetBlockHash(hash);
+        let mut rows = statement.query([])?;
 
-                let root = row.get_ref_unwrap("root").as_blob().unwrap();
-                let root = StarkHash::from_be_slice(root).unwrap();
-                let root = GlobalRoot(root);
+        let row = rows.next().context("Iterate rows")?;
+
+        Ok(row.map(Self::block_from_row))
+    }
 
-                let timestamp = row.get_ref_unwrap("timestamp").as_i64().unwrap() as u64;
-                let timestamp = StarknetBlockTimestamp(timestamp);
+    /// Returns the [StarknetBlock] which resulted in the given [root](GlobalRoot).
+    ///
+    /// A root can be shared by multiple blocks, for example if a block is reorged away and an
+    /// identical state is rebuilt on top of a different parent. In this case the lowest-numbered
+    /// block with this root is returned.
+    pub fn get_by_root(
+        connection: &Connection,
+        root: GlobalRoot,
+    ) -> anyhow::Result<Option<StarknetBlock>> {
+        let mut statement = connection.prepare_counted(
+            "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+                FROM starknet_blocks WHERE root = ? ORDER BY number ASC LIMIT 1",
+        )?;
 
-                let gas_price = row.get_ref_unwrap("gas_price").as_blob().unwrap();
-                let gas_price = GasPrice::from_be_slice(gas_price).unwrap();
+        let mut rows = statement.query(params![root.0.as_be_bytes()])?;
 
-                let sequencer_address = row.get_ref_unwrap("sequencer_address").as_blob().unwrap();
-                let sequencer_address = StarkHash::from_be_slice(sequencer_address).unwrap();
-                let sequencer_address = SequencerAddress(sequencer_address);
+        let row = rows.next().context("Iterate rows")?;
 
-                let block = StarknetBlock {
-                    number,
-                    hash,
-                    root,
-                    timestamp,
-                    gas_price,
-                    sequencer_address,
-                };
+        Ok(row.map(Self::block_from_row))
+    }
+
+    /// Returns all [StarknetBlocks](StarknetBlock) which resulted in the given
+    /// [root](GlobalRoot), ordered by block number.
+    ///
+    /// See [get_by_root](Self::get_by_root) for why a root can be shared by multiple blocks.
+    pub fn get_all_by_root(
+        connection: &Connection,
+        root: GlobalRoot,
+    ) -> anyhow::Result<Vec<StarknetBlock>> {
+        let mut statement = connection.prepare_counted(
+            "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+                FROM starknet_blocks WHERE root = ? ORDER BY number ASC",
+        )?;
+
+        let mut rows = statement.query(params![root.0.as_be_bytes()])?;
+
+        let mut blocks = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            blocks.push(Self::block_from_row(row));
+        }
+
+        Ok(blocks)
+    }
+
+    /// Returns the block closest to `timestamp` in the given [TimestampDirection], or [None] if
+    /// there is no such block.
+    ///
+    /// A block whose timestamp equals `timestamp` is always an exact match. If several blocks
+    /// share the matching timestamp, the latest one is returned when searching
+    /// [TimestampDirection::Before] and the earliest one when searching [TimestampDirection::After].
+    pub fn get_by_timestamp(
+        connection: &Connection,
+        timestamp: StarknetBlockTimestamp,
+        direction: TimestampDirection,
+    ) -> anyhow::Result<Option<StarknetBlock>> {
+        let mut statement = match direction {
+            TimestampDirection::Before => connection.prepare_counted(
+                "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+                    FROM starknet_blocks WHERE timestamp <= ?
+                    ORDER BY timestamp DESC, number DESC LIMIT 1",
+            ),
+            TimestampDirection::After => connection.prepare_counted(
+                "SELECT hash, number, root, timestamp, gas_price, sequencer_address, parent_hash
+                    FROM starknet_blocks WHERE timestamp >= ?
+                    ORDER BY timestamp ASC, number ASC LIMIT 1",
+            ),
+        }?;
 
-                Ok(Some(block))
+        let mut rows = statement.query(params![timestamp.0])?;
+
+        let row = rows.next().context("Iterate rows")?;
+        Ok(row.map(Self::block_from_row))
+    }
+
+    /// Parses a [StarknetBlock] from a row containing all of the `starknet_blocks` columns.
+    fn block_from_row(row: &rusqlite::Row<'_>) -> StarknetBlock {
+        let number = row.get_ref_unwrap("number").as_i64().unwrap() as u64;
+        let number = StarknetBlockNumber(number);
+
+        let hash = row.get_ref_unwrap("hash").as_blob().unwrap();
+        let hash = StarkHash::from_be_slice(hash).unwrap();
+        let hash = StarknetBlockHash(hash);
+
+        let root = row.get_ref_unwrap("root").as_blob().unwrap();
+        let root = StarkHash::from_be_slice(root).unwrap();
+        let root = GlobalRoot(root);
+
+        let timestamp = row.get_ref_unwrap("timestamp").as_i64().unwrap() as u64;
+        let timestamp = StarknetBlockTimestamp(timestamp);
+
+        let gas_price = row.get_ref_unwrap("gas_price").as_blob().unwrap();
+        let gas_price = GasPrice::from_be_slice(gas_price).unwrap();
+
+        let sequencer_address = row.get_ref_unwrap("sequencer_address").as_blob().unwrap();
+        let sequencer_address = StarkHash::from_be_slice(sequencer_address).unwrap();
+        let sequencer_address = SequencerAddress(sequencer_address);
+
+        let parent_hash = row.get_ref_unwrap("parent_hash").as_blob().unwrap();
+        let parent_hash = StarkHash::from_be_slice(parent_hash).unwrap();
+        let parent_hash = StarknetBlockHash(parent_hash);
+
+        StarknetBlock {
+            number,
+            hash,
+            root,
+            timestamp,
+            gas_price,
+            sequencer_address,
+            parent_hash,
+        }
+    }
+
+    /// Returns up to `depth` ancestors of the block with the given hash, found by following the
+    /// parent hashes, ordered from the direct parent backwards.
+    ///
+    /// The walk ends early at genesis, or if a parent is not stored. Returns an empty list if the
+    /// block itself is not stored.
+    pub fn get_ancestors(
+        connection: &Connection,
+        hash: StarknetBlockHash,
+        depth: usize,
+    ) -> anyhow::Result<Vec<StarknetBlock>> {
+        let mut ancestors = Vec::new();
+
+        let mut block = Self::get(connection, StarknetBlocksBlockId::Hash(hash))
+            .context("Query starting block")?;
+        while ancestors.len() < depth {
+            let parent_hash = match &block {
+                Some(block) if block.number != StarknetBlockNumber::GENESIS => block.parent_hash,
+                _ => break,
+            };
+
+            block = Self::get(connection, StarknetBlocksBlockId::Hash(parent_hash))
+                .context("Query parent block")?;
+            if let Some(parent) = &block {
+                ancestors.push(parent.clone());
             }
-            None => Ok(None),
         }
+
+        Ok(ancestors)
     }
 
     /// Returns the [root](GlobalRoot) of the given block.
+    ///
+    /// Lookups by hash resolve duplicate hashes the same way as [get](Self::get).
     pub fn get_root(
         connection: &Connection,
         block: StarknetBlocksBlockId,
     ) -> anyhow::Result<Option<GlobalRoot>> {
         let mut statement = match block {
             StarknetBlocksBlockId::Number(_) => {
-                connection.prepare("SELECT root FROM starknet_blocks WHERE number = ?")
-            }
-            StarknetBlocksBlockId::Hash(_) => {
-                connection.prepare("SELECT root FROM starknet_blocks WHERE hash = ?")
-            }
-            StarknetBlocksBlockId::Latest => {
-                connection.prepare("SELECT root FROM starknet_blocks ORDER BY number DESC LIMIT 1")
+                connection.prepare_counted("SELECT root FROM starknet_blocks WHERE number = ?")
             }
+            StarknetBlocksBlockId::Hash(_) => connection.prepare_counted(
+                "SELECT root FROM starknet_blocks WHERE hash = ? ORDER BY number ASC LIMIT 1",
+            ),
+            StarknetBlocksBlockId::Latest => connection
+                .prepare_counted("SELECT root FROM starknet_blocks ORDER BY number DESC LIMIT 1"),
+            StarknetBlocksBlockId::LatestMinus(_) => connection.prepare_counted(
+                "SELECT root FROM starknet_blocks
+                    WHERE number = (SELECT MAX(number) FROM starknet_blocks) - ?",
+            ),
         }?;
 
         let mut rows = match block {
             StarknetBlocksBlockId::Number(number) => statement.query(params![number.0]),
             StarknetBlocksBlockId::Hash(hash) => statement.query(params![hash.0.as_be_bytes()]),
             StarknetBlocksBlockId::Latest => statement.query([]),
+            StarknetBlocksBlockId::LatestMinus(offset) => match i64::try_from(offset) {
+                Ok(offset) => statement.query(params![offset]),
+                Err(_) => return Ok(None),
+            },
         }?;
 
         let row = rows.next().context("Iterate rows")?;
@@ -371,12 +674,36 @@ impl StarknetBlocksTable {
         Ok(())
     }
 
+    /// Returns all block hashes which are shared by more than one block, in ascending order.
+    ///
+    /// This should always be empty, a non-empty result indicates database corruption or a
+    /// misbehaving sequencer.
+    pub fn find_duplicate_hashes(
+        connection: &Connection,
+    ) -> anyhow::Result<Vec<StarknetBlockHash>> {
+        let mut statement = connection
+            .prepare_counted(
+                "SELECT hash FROM starknet_blocks GROUP BY hash HAVING COUNT(1) > 1 ORDER BY hash",
+            )
+            .context("Preparing statement")?;
+
+        let mut rows = statement.query([]).context("Executing query")?;
+
+        let mut hashes = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let hash = row.get_ref_unwrap("hash").as_blob()?;
+            let hash = StarkHash::from_be_slice(hash).context("Parsing block hash")?;
+            hashes.push(StarknetBlockHash(hash));
+        }
+
+        Ok(hashes)
+    }
+
     /// Returns the [number](StarknetBlockNumber) of the latest block.
     pub fn get_latest_number(
         connection: &Connection,
     ) -> anyhow::Result<Option<StarknetBlockNumber>> {
-        let mut statement = connection
-            .prepare("SELECT number FROM starknet_blocks ORDER BY number DESC LIMIT 1")?;
+        let mut statement = connection.prepare_counted(LATEST_BLOCK_NUMBER_QUERY)?;
         let mut rows = statement.query([])?;
         let row = rows.next().context("Iterate rows")?;
         match row {
@@ -388,6 +715,91 @@ impl StarknetBlocksTable {
             None => Ok(None),
         }
     }
+
+    /// Returns the highest block number such that all blocks from genesis up to it are present,
+    /// or [None] if the genesis block is missing.
+    ///
+    /// Equals [get_latest_number](Self::get_latest_number) unless there are gaps.
+    pub fn highest_contiguous_from_genesis(
+        connection: &Connection,
+    ) -> anyhow::Result<Option<StarknetBlockNumber>> {
+        let genesis_exists = connection
+            .prepare_counted("SELECT 1 FROM starknet_blocks WHERE number = 0")?
+            .exists([])
+            .context("Query genesis block")?;
+        if !genesis_exists {
+            return Ok(None);
+        }
+
+        // The first block without a successor is the one before the first gap, or the latest.
+        let number: u64 = connection
+            .prepare_counted(
+                r"SELECT number FROM starknet_blocks b
+                    WHERE NOT EXISTS (SELECT 1 FROM starknet_blocks WHERE number = b.number + 1)
+                    ORDER BY number ASC LIMIT 1",
+            )?
+            .query_row([], |row| row.get(0))
+            .context("Query first gap")?;
+
+        Ok(Some(StarknetBlockNumber(number)))
+    }
+
+    /// Returns the blocks in the inclusive range `from..=to` along with the number of
+    /// transactions each of them contains, ordered by block number.
+    ///
+    /// Blocks without any transactions are included with a count of zero.
+    pub fn get_range_with_tx_counts(
+        connection: &Connection,
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+    ) -> anyhow::Result<Vec<(StarknetBlock, usize)>> {
+        let mut statement = connection
+            .prepare_counted(
+                "SELECT starknet_blocks.hash AS hash,
+                        starknet_blocks.number AS number,
+                        starknet_blocks.root AS root,
+                        starknet_blocks.timestamp AS timestamp,
+                        starknet_blocks.gas_price AS gas_price,
+                        starknet_blocks.sequencer_address AS sequencer_address,
+                        starknet_blocks.parent_hash AS parent_hash,
+                        COUNT(starknet_transactions.hash) AS transaction_count
+                    FROM starknet_blocks
+                    LEFT JOIN starknet_transactions
+                        ON starknet_transactions.block_hash = starknet_blocks.hash
+                    WHERE starknet_blocks.number BETWEEN ? AND ?
+                    GROUP BY starknet_blocks.number
+                    ORDER BY starknet_blocks.number ASC",
+            )
+            .context("Preparing statement")?;
+
+        let mut rows = statement
+            .query(params![from.0, to.0])
+            .context("Executing query")?;
+
+        let mut blocks = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let block = Self::block_from_row(row);
+            let count = row
+                .get_ref_unwrap("transaction_count")
+                .as_i64()
+                .context("Transaction count")? as usize;
+            blocks.push((block, count));
+        }
+
+        Ok(blocks)
+    }
+}
+
+/// Search direction of [StarknetBlocksTable::get_by_timestamp], also passed to
+/// `pathfinder_getBlockByTimestamp`.
+#[derive(Debug, Clone, Copy, PartialEq, ::serde::Deserialize, ::serde::Serialize)]
+pub enum TimestampDirection {
+    /// The latest block with a timestamp at or before the target.
+    #[serde(rename = "BEFORE")]
+    Before,
+    /// The earliest block with a timestamp at or after the target.
+    #[serde(rename = "AFTER")]
+    After,
 }
 
 /// Identifies block in some [StarknetBlocksTable] queries.
@@ -396,6 +808,9 @@ pub enum StarknetBlocksBlockId {
     Number(StarknetBlockNumber),
     Hash(StarknetBlockHash),
     Latest,
+    /// The block this many blocks below the latest one, resolved by the same statement which
+    /// reads the block so that it cannot race with blocks being inserted.
+    LatestMinus(u64),
 }
 
 impl From<StarknetBlockNumber> for StarknetBlocksBlockId {
@@ -410,18 +825,176 @@ impl From<StarknetBlockHash> for StarknetBlocksBlockId {
     }
 }
 
+/// Error returned when parsing a [StarknetBlocksBlockId] from a string.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum ParseBlockIdError {
+    /// The pending block is never stored, and can therefore not be identified.
+    #[error("the pending block is not available from storage")]
+    Pending,
+    #[error("invalid block hash {0:?}: {1}")]
+    InvalidHash(String, stark_hash::HexParseError),
+    #[error(
+        "invalid block id {0:?}, expected a number, a 0x-prefixed hash, `latest` or `latest-<n>`"
+    )]
+    Invalid(String),
+}
+
+impl std::str::FromStr for StarknetBlocksBlockId {
+    type Err = ParseBlockIdError;
+
+    /// Parses a decimal block number, a `0x` prefixed block hash, the `latest` tag or an offset
+    /// below it such as `latest-10`.
+    ///
+    /// The `pending` tag is recognized, but results in [ParseBlockIdError::Pending]
+    /// since pending blocks are never stored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latest" => Ok(StarknetBlocksBlockId::Latest),
+            "pending" => Err(ParseBlockIdError::Pending),
+            _ if s.starts_with("latest-") => s["latest-".len()..]
+                .parse::<u64>()
+                .map(StarknetBlocksBlockId::LatestMinus)
+                .map_err(|_| ParseBlockIdError::Invalid(s.to_owned())),
+            _ => match s.strip_prefix("0x") {
+                Some(hex) if !hex.is_empty() => StarkHash::from_hex_str(hex)
+                    .map(|hash| StarknetBlocksBlockId::Hash(StarknetBlockHash(hash)))
+                    .map_err(|e| ParseBlockIdError::InvalidHash(s.to_owned(), e)),
+                Some(_) => Err(ParseBlockIdError::Invalid(s.to_owned())),
+                None => s
+                    .parse::<u64>()
+                    .map(|number| StarknetBlocksBlockId::Number(StarknetBlockNumber(number)))
+                    .map_err(|_| ParseBlockIdError::Invalid(s.to_owned())),
+            },
+        }
+    }
+}
+
+/// The number of most recent blocks whose state update is kept by [StarknetStateUpdatesTable].
+pub const STATE_UPDATE_RETENTION: u64 = 1_000;
+
+/// Stores the [StateUpdate] applied by each of the latest [STATE_UPDATE_RETENTION] [StarknetBlock]s.
+///
+/// Blocks synced before this table was introduced have no stored state update.
+pub struct StarknetStateUpdatesTable {}
+
+impl StarknetStateUpdatesTable {
+    /// Inserts the [StateUpdate] applied by the given block, which must already exist in the
+    /// [StarknetBlocksTable].
+    ///
+    /// Only the state updates of the latest [STATE_UPDATE_RETENTION] blocks up to and including
+    /// `block` are kept, older ones are deleted.
+    pub fn insert(
+        connection: &Connection,
+        block: StarknetBlockNumber,
+        update: &StateUpdate,
+        limits: &StorageLimits,
+    ) -> anyhow::Result<()> {
+        Self::insert_with_retention(connection, block, update, STATE_UPDATE_RETENTION, limits)
+    }
+
+    fn insert_with_retention(
+        connection: &Connection,
+        block: StarknetBlockNumber,
+        update: &StateUpdate,
+        retention: u64,
+        limits: &StorageLimits,
+    ) -> anyhow::Result<()> {
+        let data = serde_json::ser::to_vec(update).context("Serialize state update")?;
+        blob::check_size("state update", &data, limits.max_blob_size);
+        let data = zstd::bulk::compress(&data, 10).context("Compress state update")?;
+
+        connection
+            .execute(
+                "INSERT INTO starknet_state_updates (block_number, data) VALUES (?, ?)",
+                params![block.0, data],
+            )
+            .context("Insert state update")?;
+
+        if let Some(oldest) = block.0.checked_sub(retention) {
+            connection
+                .execute(
+                    "DELETE FROM starknet_state_updates WHERE block_number <= ?",
+                    [oldest],
+                )
+                .context("Delete old state updates")?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the [StateUpdate]s applied by the blocks in the inclusive range `from..=to`,
+    /// in ascending block order.
+    ///
+    /// Returns [None] if the state update of any block in the range is not stored.
+    pub fn get_range(
+        connection: &Connection,
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+        limits: &StorageLimits,
+    ) -> anyhow::Result<Option<Vec<StateUpdate>>> {
+        if from > to {
+            return Ok(Some(Vec::new()));
+        }
+
+        let mut statement = connection.prepare_counted(
+            "SELECT data FROM starknet_state_updates
+                WHERE block_number >= ? AND block_number <= ? ORDER BY block_number ASC",
+        )?;
+        let mut rows = statement.query(params![from.0, to.0])?;
+
+        let mut updates = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let data = row.get_ref_unwrap("data").as_blob()?;
+            let data =
+                blob::decompress(data, limits.max_blob_size).context("Decompress state update")?;
+            let update = serde_json::de::from_slice(&data).context("Deserialize state update")?;
+            updates.push(update);
+        }
+
+        match updates.len() as u64 == to.0 - from.0 + 1 {
+            true => Ok(Some(updates)),
+            false => Ok(None),
+        }
+    }
+}
+
+/// Default upper bound on the number of transactions in a single block.
+///
+/// Far above any block seen so far, it only guards against absurd blocks exhausting memory when
+/// they are stored or read back.
+pub const DEFAULT_MAX_BLOCK_TRANSACTIONS: usize = 100_000;
+
+#[derive(Copy, Clone, Debug, thiserror::Error, PartialEq)]
+pub enum TransactionsError {
+    #[error("block has {count} transactions, supported maximum is {limit}")]
+    TooManyTransactions { count: usize, limit: usize },
+}
+
 /// Stores all known starknet transactions
 pub struct StarknetTransactionsTable {}
 impl StarknetTransactionsTable {
     /// Inserts a Starknet block's transactions and transaction receipts into the [StarknetTransactionsTable].
     ///
     /// overwrites existing data if the transaction hash already exists.
+    ///
+    /// Fails with [TransactionsError::TooManyTransactions] if the block has more than
+    /// [StorageLimits::max_block_transactions] transactions, in which case nothing is stored.
     pub fn upsert(
         connection: &Connection,
         block_hash: StarknetBlockHash,
         block_number: StarknetBlockNumber,
         transaction_data: &[(transaction::Transaction, transaction::Receipt)],
+        limits: &StorageLimits,
     ) -> anyhow::Result<()> {
+        let limit = limits.max_block_transactions;
+        if transaction_data.len() > limit {
+            return Err(TransactionsError::TooManyTransactions {
+                count: transaction_data.len(),
+                limit,
+            }
+            .into());
+        }
+
         if transaction_data.is_empty() {
             return Ok(());
         }
@@ -429,14 +1002,20 @@ impl StarknetTransactionsTable {
         let mut compressor = zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
         for (i, (transaction, receipt)) in transaction_data.iter().enumerate() {
             // Serialize and compress transaction data.
-            let tx_data =
-                serde_json::ser::to_vec(&transaction).context("Serialize Starknet transaction")?;
+            let tx_data = packed::encode_transaction(transaction)
+                .context("Serialize Starknet transaction")?;
+            blob::check_size("transaction", &tx_data, limits.max_blob_size);
             let tx_data = compressor
                 .compress(&tx_data)
                 .context("Compress Starknet transaction")?;
 
-            let serialized_receipt = serde_json::ser::to_vec(&receipt)
+            let serialized_receipt = packed::encode_receipt(receipt)
                 .context("Serialize Starknet transaction receipt")?;
+            blob::check_size(
+                "transaction receipt",
+                &serialized_receipt,
+                limits.max_blob_size,
+            );
             let serialized_receipt = compressor
                 .compress(&serialized_receipt)
                 .context("Compress Starknet transaction receipt")?;
@@ -451,40 +1030,160 @@ impl StarknetTransactionsTable {
                 ]).context("Insert transaction data into transactions table")?;
 
             // insert events from receipt
-            StarknetEventsTable::insert_events(
+            StarknetEventsTable::insert_transaction_events(
                 connection,
                 block_number,
                 transaction,
+                receipt.execution_status,
                 &receipt.events,
             )?;
+
+            if let (transaction::Type::Declare, Some(class_hash)) =
+                (transaction.r#type, transaction.class_hash)
+            {
+                DeclaredClassesTable::insert(
+                    connection,
+                    class_hash,
+                    block_number,
+                    transaction.transaction_hash,
+                )?;
+            }
         }
 
-        Ok(())
+        // The block's event indexes are updated once all of its events are stored.
+        StarknetEventsTable::update_block_indexes(
+            connection,
+            block_number,
+            transaction_data
+                .iter()
+                .flat_map(|(_, receipt)| receipt.events.iter())
+                .flat_map(|event| event.keys.iter()),
+        )
+    }
+
+    /// Deletes the transactions and receipts of the given block, along with its events and
+    /// declared classes.
+    ///
+    /// [Self::upsert] only overwrites the rows of the transactions it is given, so this must
+    /// precede it when a block's data is replaced, e.g. when it is reprocessed.
+    pub fn delete_block(
+        connection: &Connection,
+        block_hash: StarknetBlockHash,
+        block_number: StarknetBlockNumber,
+    ) -> anyhow::Result<()> {
+        connection
+            .execute(
+                "DELETE FROM starknet_transactions WHERE block_hash = ?",
+                [&block_hash.0.as_be_bytes()[..]],
+            )
+            .context("Delete transactions")?;
+        connection
+            .execute(
+                "DELETE FROM declared_classes WHERE block_number = ?",
+                [block_number.0],
+            )
+            .context("Delete declared classes")?;
+
+        StarknetEventsTable::delete_block_events(connection, block_number)
+    }
+
+    /// Rewrites the transactions and receipts which are still stored as JSON in the
+    /// packed format, and returns the number of rewritten rows.
+    ///
+    /// Both formats are read transparently, so this is optional. It should be run within a
+    /// database transaction.
+    pub fn repack(connection: &Connection, limits: &StorageLimits) -> anyhow::Result<usize> {
+        const BATCH_SIZE: usize = 1000;
+
+        let mut select = connection
+            .prepare(
+                "SELECT rowid, tx, receipt FROM starknet_transactions WHERE rowid > ? ORDER BY rowid LIMIT ?",
+            )
+            .context("Preparing query")?;
+        let mut update = connection
+            .prepare("UPDATE starknet_transactions SET tx = ?, receipt = ? WHERE rowid = ?")
+            .context("Preparing update")?;
+        let mut compressor = zstd::bulk::Compressor::new(10).context("Create zstd compressor")?;
+
+        let mut repacked = 0;
+        let mut last_rowid = 0i64;
+        loop {
+            // Rows are read in batches, as they must not be updated while the query is running.
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            let mut rows = select
+                .query(params![last_rowid, BATCH_SIZE])
+                .context("Executing query")?;
+            while let Some(row) = rows.next().context("Iterate rows")? {
+                let rowid = row.get_ref_unwrap("rowid").as_i64()?;
+                let transaction = row
+                    .get_ref_unwrap("tx")
+                    .as_blob_or_null()?
+                    .map(<[u8]>::to_vec);
+                let receipt = row
+                    .get_ref_unwrap("receipt")
+                    .as_blob_or_null()?
+                    .map(<[u8]>::to_vec);
+                batch.push((rowid, transaction, receipt));
+            }
+            drop(rows);
+
+            let done = batch.len() < BATCH_SIZE;
+            for (rowid, transaction, receipt) in batch {
+                last_rowid = rowid;
+                let (transaction, receipt) = match (transaction, receipt) {
+                    (Some(transaction), Some(receipt)) => (transaction, receipt),
+                    _ => continue,
+                };
+
+                let transaction = blob::decompress(&transaction, limits.max_blob_size)
+                    .context("Decompressing transaction")?;
+                let receipt = blob::decompress(&receipt, limits.max_blob_size)
+                    .context("Decompressing transaction receipt")?;
+                if packed::is_packed(&transaction) && packed::is_packed(&receipt) {
+                    continue;
+                }
+
+                let transaction = packed::decode_transaction(&transaction)
+                    .and_then(|transaction| packed::encode_transaction(&transaction))
+                    .context("Repacking transaction")?;
+                let transaction = compressor
+                    .compress(&transaction)
+                    .context("Compress Starknet transaction")?;
+                let receipt = packed::decode_receipt(&receipt)
+                    .and_then(|receipt| packed::encode_receipt(&receipt))
+                    .context("Repacking transaction receipt")?;
+                let receipt = compressor
+                    .compress(&receipt)
+                    .context("Compress Starknet transaction receipt")?;
+
+                update
+                    .execute(params![transaction, receipt, rowid])
+                    .context("Updating transaction")?;
+                repacked += 1;
+            }
+
+            if done {
+                return Ok(repacked);
+            }
+        }
     }
 
     pub fn get_transaction_data_for_block(
         connection: &Connection,
         block: StarknetBlocksBlockId,
+        limits: &StorageLimits,
     ) -> anyhow::Result<Vec<(transaction::Transaction, transaction::Receipt)>> {
         // Identify block hash
         let block_hash = match block {
-            StarknetBlocksBlockId::Number(number) => {
-                match StarknetBlocksTable::get(connection, number.into())? {
-                    Some(block) => block.hash,
-                    None => return Ok(Vec::new()),
-                }
-            }
             StarknetBlocksBlockId::Hash(hash) => hash,
-            StarknetBlocksBlockId::Latest => {
-                match StarknetBlocksTable::get(connection, StarknetBlocksBlockId::Latest)? {
-                    Some(block) => block.hash,
-                    None => return Ok(Vec::new()),
-                }
-            }
+            other => match StarknetBlocksTable::get(connection, other)? {
+                Some(block) => block.hash,
+                None => return Ok(Vec::new()),
+            },
         };
 
         let mut stmt = connection
-            .prepare(
+            .prepare_counted(
                 "SELECT tx, receipt FROM starknet_transactions WHERE block_hash = ? ORDER BY idx ASC",
             )
             .context("Preparing statement")?;
@@ -499,17 +1198,19 @@ impl StarknetTransactionsTable {
                 .get_ref_unwrap("receipt")
                 .as_blob_or_null()?
                 .context("Receipt data missing")?;
-            let receipt = zstd::decode_all(receipt).context("Decompressing transaction receipt")?;
-            let receipt = serde_json::de::from_slice(&receipt)
-                .context("Deserializing transaction receipt")?;
+            let receipt = blob::decompress(receipt, limits.max_blob_size)
+                .context("Decompressing transaction receipt")?;
+            let receipt =
+                packed::decode_receipt(&receipt).context("Deserializing transaction receipt")?;
 
             let transaction = row
                 .get_ref_unwrap("tx")
                 .as_blob_or_null()?
                 .context("Transaction data missing")?;
-            let transaction = zstd::decode_all(transaction).context("Decompressing transaction")?;
+            let transaction = blob::decompress(transaction, limits.max_blob_size)
+                .context("Decompressing transaction")?;
             let transaction =
-                serde_json::de::from_slice(&transaction).context("Deserializing transaction")?;
+                packed::decode_transaction(&transaction).context("Deserializing transaction")?;
 
             data.push((transaction, receipt));
         }
@@ -520,8 +1221,15 @@ impl StarknetTransactionsTable {
     pub fn get_transaction_at_block(
         connection: &Connection,
         block: StarknetBlocksBlockId,
-        index: usize,
+        index: u64,
+        limits: &StorageLimits,
     ) -> anyhow::Result<Option<transaction::Transaction>> {
+        // Indexes beyond the range of SQLite integers cannot exist.
+        let index = match i64::try_from(index) {
+            Ok(index) => index,
+            Err(_) => return Ok(None),
+        };
+
         // Identify block hash
         let block_hash = match block {
             StarknetBlocksBlockId::Number(number) => {
@@ -531,8 +1239,8 @@ impl StarknetTransactionsTable {
                 }
             }
             StarknetBlocksBlockId::Hash(hash) => hash,
-            StarknetBlocksBlockId::Latest => {
-                match StarknetBlocksTable::get(connection, StarknetBlocksBlockId::Latest)? {
+            latest @ (StarknetBlocksBlockId::Latest | StarknetBlocksBlockId::LatestMinus(_)) => {
+                match StarknetBlocksTable::get(connection, latest)? {
                     Some(block) => block.hash,
                     None => return Ok(None),
                 }
@@ -540,7 +1248,9 @@ impl StarknetTransactionsTable {
         };
 
         let mut stmt = connection
-            .prepare("SELECT tx FROM starknet_transactions WHERE block_hash = ? AND idx = ?")
+            .prepare_counted(
+                "SELECT tx FROM starknet_transactions WHERE block_hash = ? AND idx = ?",
+            )
             .context("Preparing statement")?;
 
         let mut rows = stmt
@@ -557,9 +1267,10 @@ impl StarknetTransactionsTable {
             None => return Ok(None),
         };
 
-        let transaction = zstd::decode_all(transaction).context("Decompressing transaction")?;
+        let transaction = blob::decompress(transaction, limits.max_blob_size)
+            .context("Decompressing transaction")?;
         let transaction =
-            serde_json::de::from_slice(&transaction).context("Deserializing transaction")?;
+            packed::decode_transaction(&transaction).context("Deserializing transaction")?;
 
         Ok(Some(transaction))
     }
@@ -567,9 +1278,10 @@ impl StarknetTransactionsTable {
     pub fn get_receipt(
         connection: &Connection,
         transaction: StarknetTransactionHash,
+        limits: &StorageLimits,
     ) -> anyhow::Result<Option<(transaction::Receipt, StarknetBlockHash)>> {
         let mut stmt = connection
-            .prepare("SELECT receipt, block_hash FROM starknet_transactions WHERE hash = ?1")
+            .prepare_counted(RECEIPT_BY_HASH_QUERY)
             .context("Preparing statement")?;
 
         let mut rows = stmt
@@ -585,8 +1297,9 @@ impl StarknetTransactionsTable {
             Some(data) => data,
             None => return Ok(None),
         };
-        let receipt = zstd::decode_all(receipt).context("Decompressing transaction")?;
-        let receipt = serde_json::de::from_slice(&receipt).context("Deserializing transaction")?;
+        let receipt =
+            blob::decompress(receipt, limits.max_blob_size).context("Decompressing transaction")?;
+        let receipt = packed::decode_receipt(&receipt).context("Deserializing transaction")?;
 
         let block_hash = row.get_ref_unwrap("block_hash").as_blob()?;
         let block_hash =
@@ -599,9 +1312,10 @@ impl StarknetTransactionsTable {
     pub fn get_transaction(
         connection: &Connection,
         transaction: StarknetTransactionHash,
+        limits: &StorageLimits,
     ) -> anyhow::Result<Option<transaction::Transaction>> {
         let mut stmt = connection
-            .prepare("SELECT tx FROM starknet_transactions WHERE hash = ?1")
+            .prepare_counted(TRANSACTION_BY_HASH_QUERY)
             .context("Preparing statement")?;
 
         let mut rows = stmt
@@ -618,41 +1332,86 @@ impl StarknetTransactionsTable {
             None => return Ok(None),
         };
 
-        let transaction = zstd::decode_all(transaction).context("Decompressing transaction")?;
+        let transaction = blob::decompress(transaction, limits.max_blob_size)
+            .context("Decompressing transaction")?;
         let transaction =
-            serde_json::de::from_slice(&transaction).context("Deserializing transaction")?;
+            packed::decode_transaction(&transaction).context("Deserializing transaction")?;
 
         Ok(Some(transaction))
     }
 
-    pub fn get_transaction_count(
+    /// Returns the `n` most recent transactions along with the number of the block they are
+    /// in, newest first.
+    pub fn get_latest_transactions(
         connection: &Connection,
-        block: StarknetBlocksBlockId,
-    ) -> anyhow::Result<usize> {
+        n: usize,
+        limits: &StorageLimits,
+    ) -> anyhow::Result<Vec<(transaction::Transaction, StarknetBlockNumber)>> {
+        let mut stmt = connection
+            .prepare_counted(
+                "SELECT tx, number FROM starknet_transactions
+                JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
+                ORDER BY number DESC, idx DESC
+                LIMIT ?",
+            )
+            .context("Preparing statement")?;
+
+        let mut rows = stmt.query(params![n]).context("Executing query")?;
+
+        let mut data = Vec::new();
+        while let Some(row) = rows.next()? {
+            let transaction = row
+                .get_ref_unwrap("tx")
+                .as_blob_or_null()?
+                .context("Transaction data missing")?;
+            let transaction = blob::decompress(transaction, limits.max_blob_size)
+                .context("Decompressing transaction")?;
+            let transaction =
+                packed::decode_transaction(&transaction).context("Deserializing transaction")?;
+
+            let number = StarknetBlockNumber(row.get_unwrap("number"));
+
+            data.push((transaction, number));
+        }
+
+        Ok(data)
+    }
+
+    /// Returns the number of transactions in the given block, or [None] if the block is not
+    /// stored.
+    ///
+    /// A stored block without any transactions, e.g. an empty genesis block, has a count of zero.
+    pub fn get_transaction_count(
+        connection: &Connection,
+        block: StarknetBlocksBlockId,
+    ) -> anyhow::Result<Option<usize>> {
         match block {
             StarknetBlocksBlockId::Number(number) => connection
                 .query_row(
-                    "SELECT COUNT(*) FROM starknet_transactions
-                    JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
-                    WHERE number = ?1",
+                    "SELECT (SELECT COUNT(*) FROM starknet_transactions
+                        WHERE starknet_transactions.block_hash = starknet_blocks.hash)
+                    FROM starknet_blocks WHERE number = ?1",
                     params![number.0],
                     |row| row.get(0),
                 )
+                .optional()
                 .context("Counting transactions"),
             StarknetBlocksBlockId::Hash(hash) => connection
                 .query_row(
-                    "SELECT COUNT(*) FROM starknet_transactions WHERE block_hash = ?1",
+                    "SELECT (SELECT COUNT(*) FROM starknet_transactions
+                        WHERE starknet_transactions.block_hash = starknet_blocks.hash)
+                    FROM starknet_blocks WHERE hash = ?1 LIMIT 1",
                     params![hash.0.as_be_bytes()],
                     |row| row.get(0),
                 )
+                .optional()
                 .context("Counting transactions"),
-            StarknetBlocksBlockId::Latest => {
-                // First get the latest block
-                let block =
-                    match StarknetBlocksTable::get(connection, StarknetBlocksBlockId::Latest)? {
-                        Some(block) => block.number,
-                        None => return Ok(0),
-                    };
+            latest @ (StarknetBlocksBlockId::Latest | StarknetBlocksBlockId::LatestMinus(_)) => {
+                // First resolve the block relative to the latest one
+                let block = match StarknetBlocksTable::get(connection, latest)? {
+                    Some(block) => block.number,
+                    None => return Ok(None),
+                };
 
                 Self::get_transaction_count(connection, block.into())
             }
@@ -660,22 +1419,142 @@ impl StarknetTransactionsTable {
     }
 }
 
+/// Maps the classes declared by DECLARE transactions to their declaring block and transaction.
+///
+/// Rows are deleted along with their block on reorg.
+pub struct DeclaredClassesTable {}
+
+/// A class declared by a DECLARE transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeclaredClass {
+    pub class_hash: ClassHash,
+    pub block_number: StarknetBlockNumber,
+    pub transaction_hash: StarknetTransactionHash,
+}
+
+impl DeclaredClassesTable {
+    fn insert(
+        connection: &Connection,
+        class_hash: ClassHash,
+        block_number: StarknetBlockNumber,
+        transaction_hash: StarknetTransactionHash,
+    ) -> anyhow::Result<()> {
+        connection
+            .execute(
+                r"INSERT OR REPLACE INTO declared_classes ( transaction_hash,  class_hash,  block_number)
+                                                   VALUES (:transaction_hash, :class_hash, :block_number)",
+                named_params![
+                    ":transaction_hash": transaction_hash.0.as_be_bytes(),
+                    ":class_hash": class_hash.0.as_be_bytes(),
+                    ":block_number": block_number.0,
+                ],
+            )
+            .context("Insert declared class")?;
+        Ok(())
+    }
+
+    /// Returns the classes declared in the given block, in transaction order.
+    pub fn get_for_block(
+        connection: &Connection,
+        block_number: StarknetBlockNumber,
+    ) -> anyhow::Result<Vec<DeclaredClass>> {
+        let mut statement = connection
+            .prepare_counted(
+                r"SELECT declared_classes.class_hash AS class_hash,
+                        declared_classes.transaction_hash AS transaction_hash
+                    FROM declared_classes
+                    JOIN starknet_transactions ON declared_classes.transaction_hash = starknet_transactions.hash
+                    WHERE declared_classes.block_number = ?
+                    ORDER BY starknet_transactions.idx",
+            )
+            .context("Preparing statement")?;
+
+        let mut rows = statement
+            .query([block_number.0])
+            .context("Executing query")?;
+
+        let mut classes = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let class_hash = row.get_ref_unwrap("class_hash").as_blob()?;
+            let class_hash = StarkHash::from_be_slice(class_hash).context("Parsing class hash")?;
+
+            let transaction_hash = row.get_ref_unwrap("transaction_hash").as_blob()?;
+            let transaction_hash =
+                StarkHash::from_be_slice(transaction_hash).context("Parsing transaction hash")?;
+
+            classes.push(DeclaredClass {
+                class_hash: ClassHash(class_hash),
+                block_number,
+                transaction_hash: StarknetTransactionHash(transaction_hash),
+            });
+        }
+
+        Ok(classes)
+    }
+
+    /// Returns the earliest declaration of the given class, or [None] if it was never declared.
+    pub fn get_declaration(
+        connection: &Connection,
+        class_hash: ClassHash,
+    ) -> anyhow::Result<Option<DeclaredClass>> {
+        connection
+            .query_row(
+                r"SELECT block_number, transaction_hash FROM declared_classes
+                    WHERE class_hash = ? ORDER BY block_number ASC LIMIT 1",
+                [class_hash.0.as_be_bytes()],
+                |row| {
+                    let block_number = row.get_ref_unwrap(0).as_i64()? as u64;
+                    let transaction_hash = row.get_ref_unwrap(1).as_blob()?.to_vec();
+                    Ok((block_number, transaction_hash))
+                },
+            )
+            .optional()
+            .context("Querying class declaration")?
+            .map(|(block_number, transaction_hash)| {
+                let transaction_hash = StarkHash::from_be_slice(&transaction_hash)
+                    .context("Parsing transaction hash")?;
+                Ok(DeclaredClass {
+                    class_hash,
+                    block_number: StarknetBlockNumber(block_number),
+                    transaction_hash: StarknetTransactionHash(transaction_hash),
+                })
+            })
+            .transpose()
+    }
+}
+
 pub struct StarknetEventFilter {
     pub from_block: Option<StarknetBlockNumber>,
     pub to_block: Option<StarknetBlockNumber>,
+    /// Only events from the block with this hash are returned.
+    pub block_hash: Option<StarknetBlockHash>,
     pub contract_address: Option<ContractAddress>,
     pub keys: Vec<EventKey>,
+    /// Only events whose data matches all of these conditions are returned.
+    pub data_contains: Vec<EventDataCondition>,
+    /// Whether events of transactions which were reverted are returned.
+    pub include_reverted: bool,
     pub page_size: usize,
     pub page_number: usize,
 }
 
+/// Matches events which have `value` as the element at index `position` of their data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventDataCondition {
+    pub position: usize,
+    pub value: EventData,
+}
+
 impl From<crate::rpc::types::request::EventFilter> for StarknetEventFilter {
     fn from(filter: crate::rpc::types::request::EventFilter) -> Self {
         Self {
             from_block: filter.from_block,
             to_block: filter.to_block,
+            block_hash: None,
             contract_address: filter.address,
             keys: filter.keys,
+            data_contains: vec![],
+            include_reverted: filter.include_reverted.unwrap_or(true),
             page_size: filter.page_size,
             page_number: filter.page_number,
         }
@@ -696,6 +1575,63 @@ pub struct StarknetEmittedEvent {
 pub enum EventFilterError {
     #[error("requested page size is too big, supported maximum is {0}")]
     PageSizeTooBig(usize),
+    #[error("query is too expensive, it is estimated to scan {cost} events but at most {budget} are allowed: {constraint}")]
+    TooExpensive {
+        cost: u64,
+        budget: u64,
+        constraint: EventFilterConstraint,
+    },
+}
+
+/// The constraint which would make a [too expensive](EventFilterError::TooExpensive) event
+/// query cheaper.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventFilterConstraint {
+    BlockRange,
+    ContractAddress,
+    PageNumber,
+}
+
+impl std::fmt::Display for EventFilterConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventFilterConstraint::BlockRange => f.write_str("narrow the block range"),
+            EventFilterConstraint::ContractAddress => f.write_str("filter on a contract address"),
+            EventFilterConstraint::PageNumber => {
+                f.write_str("request a smaller page number, or narrow the filter instead of paging")
+            }
+        }
+    }
+}
+
+/// Estimated number of scanned events above which an event query is logged as expensive, when
+/// no [budget](StorageLimits::event_query_cost_budget) is set, see [StarknetEventsTable::get_events].
+///
+/// The selectivities [StarknetEventsTable::estimate_cost] assumes are not calibrated against a
+/// real database yet, so by default expensive queries are only logged instead of rejected.
+pub const DEFAULT_EVENT_QUERY_COST_BUDGET: u64 = 1_000_000;
+
+/// The query run by [StarknetEventsTable::get_events], along with its named parameters.
+struct EventQuery {
+    sql: String,
+    params: Vec<(Cow<'static, str>, Value)>,
+}
+
+impl EventQuery {
+    fn params(&self) -> Vec<(&str, &dyn rusqlite::ToSql)> {
+        self.params
+            .iter()
+            .map(|(name, value)| (name.as_ref(), value as &dyn rusqlite::ToSql))
+            .collect()
+    }
+}
+
+/// The result of [StarknetEventsTable::estimate_cost].
+struct EventQueryCost {
+    /// The estimated number of events scanned.
+    events: u64,
+    /// The constraint which would lower the cost the most.
+    constraint: EventFilterConstraint,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -704,6 +1640,15 @@ pub struct PageOfEvents {
     pub is_last_page: bool,
 }
 
+/// The events emitted in a single block, as kept up to date by
+/// [StarknetEventsTable::insert_events].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockEventSummary {
+    pub event_count: u64,
+    /// Number of distinct contracts which emitted the events.
+    pub emitter_count: u64,
+}
+
 pub struct StarknetEventsTable {}
 impl StarknetEventsTable {
     pub fn event_data_to_bytes(data: &[EventData]) -> Vec<u8> {
@@ -712,193 +1657,1001 @@ impl StarknetEventsTable {
             .collect()
     }
 
-    fn event_key_to_base64_string(key: &EventKey) -> String {
-        base64::encode(key.0.as_be_bytes())
+    /// Encodes the key as 64 lowercase hex digits, which the FTS5 `ascii` tokenizer keeps
+    /// as a single token.
+    fn event_key_to_hex_string(key: &EventKey) -> String {
+        hex::encode(key.0.as_be_bytes())
     }
 
-    pub fn event_keys_to_base64_strings(keys: &[EventKey]) -> String {
+    pub fn event_keys_to_hex_strings(keys: &[EventKey]) -> String {
         // TODO: we really should be using Iterator::intersperse() here once it's stabilized.
-        let keys: Vec<String> = keys.iter().map(Self::event_key_to_base64_string).collect();
+        let keys: Vec<String> = keys.iter().map(Self::event_key_to_hex_string).collect();
         keys.join(" ")
     }
 
+    /// Inserts the events emitted by the given transaction.
+    ///
+    /// Any events previously stored for this transaction in this block are replaced,
+    /// which makes re-processing a block idempotent.
+    ///
+    /// Each element of an event's data is additionally stored as a separate row in
+    /// `starknet_events_data`, which is what [StarknetEventFilter::data_contains] is
+    /// resolved against. Events without data store NULL data.
+    ///
+    /// The events are marked as reverted if the transaction's `execution_status` is
+    /// [Reverted](transaction::ExecutionStatus::Reverted), see
+    /// [StarknetEventFilter::include_reverted].
+    ///
+    /// The block's indexes are updated as well, see [Self::update_block_indexes]. Inserting all
+    /// of a block's transactions should update them only once instead, as
+    /// [StarknetTransactionsTable::upsert] does.
     pub fn insert_events(
         connection: &Connection,
         block_number: StarknetBlockNumber,
         transaction: &transaction::Transaction,
+        execution_status: Option<transaction::ExecutionStatus>,
+        events: &[transaction::Event],
+    ) -> anyhow::Result<()> {
+        Self::insert_transaction_events(
+            connection,
+            block_number,
+            transaction,
+            execution_status,
+            events,
+        )?;
+        Self::update_block_indexes(
+            connection,
+            block_number,
+            events.iter().flat_map(|event| event.keys.iter()),
+        )
+    }
+
+    /// Inserts the events emitted by the given transaction, without updating the block's indexes.
+    fn insert_transaction_events(
+        connection: &Connection,
+        block_number: StarknetBlockNumber,
+        transaction: &transaction::Transaction,
+        execution_status: Option<transaction::ExecutionStatus>,
         events: &[transaction::Event],
     ) -> anyhow::Result<()> {
+        let reverted = execution_status == Some(transaction::ExecutionStatus::Reverted);
+
+        connection
+            .execute(
+                "DELETE FROM starknet_events WHERE block_number = ? AND transaction_hash = ?",
+                params![
+                    block_number.0,
+                    &transaction.transaction_hash.0.as_be_bytes()[..]
+                ],
+            )
+            .context("Delete existing events for transaction")?;
+
         for (idx, event) in events.iter().enumerate() {
+            let data = match event.data.is_empty() {
+                true => None,
+                false => Some(Self::event_data_to_bytes(&event.data)),
+            };
+
             connection
                 .execute(
-                    r"INSERT INTO starknet_events ( block_number,  idx,  transaction_hash,  from_address,  keys,  data)
-                                           VALUES (:block_number, :idx, :transaction_hash, :from_address, :keys, :data)",
+                    r"INSERT INTO starknet_events ( block_number,  idx,  transaction_hash,  from_address,  keys,  data,  reverted)
+                                           VALUES (:block_number, :idx, :transaction_hash, :from_address, :keys, :data, :reverted)",
                     named_params![
                         ":block_number": block_number.0,
                         ":idx": idx,
                         ":transaction_hash": &transaction.transaction_hash.0.as_be_bytes()[..],
                         ":from_address": &transaction.contract_address.0.as_be_bytes()[..],
-                        ":keys": Self::event_keys_to_base64_strings(&event.keys),
-                        ":data": Self::event_data_to_bytes(&event.data),
+                        ":keys": Self::event_keys_to_hex_strings(&event.keys),
+                        ":data": data,
+                        ":reverted": reverted,
                     ],
                 )
                 .context("Insert events into events table")?;
+
+            let event_id = connection.last_insert_rowid();
+            for (position, value) in event.data.iter().enumerate() {
+                connection
+                    .execute(
+                        r"INSERT INTO starknet_events_data ( event_id,  position,  value)
+                                                   VALUES (:event_id, :position, :value)",
+                        named_params![
+                            ":event_id": event_id,
+                            ":position": position,
+                            ":value": &value.0.as_be_bytes()[..],
+                        ],
+                    )
+                    .context("Insert event data into events data table")?;
+            }
         }
+
         Ok(())
     }
 
-    pub(crate) const PAGE_SIZE_LIMIT: usize = 1024;
-
-    pub fn get_events(
+    /// Adds `keys` to the block's bloom filter in `starknet_events_key_blooms`, see
+    /// [Self::key_bloom_block_ranges], and recomputes the block's [BlockEventSummary] from its
+    /// stored events.
+    fn update_block_indexes<'a>(
         connection: &Connection,
-        filter: &StarknetEventFilter,
-    ) -> anyhow::Result<PageOfEvents> {
-        let mut base_query =
-            r#"SELECT
-                  block_number,
-                  starknet_blocks.hash as block_hash,
-                  transaction_hash,
-                  from_address,
-                  data,
-                  starknet_events.keys as keys
-               FROM starknet_events
-               INNER JOIN starknet_blocks ON starknet_blocks.number = starknet_events.block_number "#
-                .to_string();
-        let mut where_statement_parts: Vec<&'static str> = Vec::new();
-        let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = Vec::new();
-
-        // filter on block range
-        match (&filter.from_block, &filter.to_block) {
-            (Some(from_block), Some(to_block)) => {
-                where_statement_parts.push("block_number BETWEEN :from_block AND :to_block");
-                params.push((":from_block", &from_block.0));
-                params.push((":to_block", &to_block.0));
-            }
-            (Some(from_block), None) => {
-                where_statement_parts.push("block_number >= :from_block");
-                params.push((":from_block", &from_block.0));
-            }
-            (None, Some(to_block)) => {
-                where_statement_parts.push("block_number <= :to_block");
-                params.push((":to_block", &to_block.0));
-            }
-            (None, None) => {}
+        block_number: StarknetBlockNumber,
+        keys: impl IntoIterator<Item = &'a EventKey>,
+    ) -> anyhow::Result<()> {
+        let mut keys = keys.into_iter().peekable();
+        if keys.peek().is_some() {
+            Self::update_key_bloom(connection, block_number, keys)
+                .context("Update event key bloom filter")?;
         }
 
-        // filter on contract address
-        if let Some(contract_address) = &filter.contract_address {
-            where_statement_parts.push("from_address = :contract_address");
-            params.push((":contract_address", contract_address.0.as_be_bytes()))
-        }
+        connection
+            .execute(
+                r"INSERT OR REPLACE INTO block_event_summary (block_number, event_count, emitter_count)
+                    SELECT :block_number, COUNT(1), COUNT(DISTINCT from_address)
+                    FROM starknet_events WHERE block_number = :block_number",
+                named_params![":block_number": block_number.0],
+            )
+            .context("Update block event summary")?;
 
-        // Filter on keys: this is using an FTS5 full-text index (virtual table) on the keys.
-        // The idea is that we convert keys to a space-separated list of Bas64 encoded string
-        // representation and then use the full-text index to find events matching the events.
-        // HACK: make sure key_fts_expression lives long enough
-        let key_fts_expression;
-        if !filter.keys.is_empty() {
-            let base64_keys: Vec<String> = filter
-                .keys
-                .iter()
-                .map(|key| format!("\"{}\"", Self::event_key_to_base64_string(key)))
-                .collect();
-            key_fts_expression = base64_keys.join(" OR ");
+        Ok(())
+    }
 
-            base_query.push_str("INNER JOIN starknet_events_keys ON starknet_events.rowid = starknet_events_keys.rowid");
-            where_statement_parts.push("starknet_events_keys.keys MATCH :events_match");
-            params.push((":events_match", &key_fts_expression));
+    /// Deletes the events of the given block, along with its key bloom filter and
+    /// [BlockEventSummary].
+    pub fn delete_block_events(
+        connection: &Connection,
+        block_number: StarknetBlockNumber,
+    ) -> anyhow::Result<()> {
+        for table in [
+            "starknet_events",
+            "starknet_events_key_blooms",
+            "block_event_summary",
+        ] {
+            connection
+                .execute(
+                    &format!("DELETE FROM {} WHERE block_number = ?", table),
+                    [block_number.0],
+                )
+                .with_context(|| format!("Delete block from {}", table))?;
         }
 
-        // Paging
-        if filter.page_size > Self::PAGE_SIZE_LIMIT {
-            return Err(EventFilterError::PageSizeTooBig(Self::PAGE_SIZE_LIMIT).into());
-        }
-        if filter.page_size < 1 {
-            anyhow::bail!("Invalid page size");
-        }
-        let offset = filter.page_number * filter.page_size;
-        // We have to be able to decide if there are more events. We request one extra event
-        // above the requested page size, so that we can decide.
-        let limit = filter.page_size + 1;
-        params.push((":limit", &limit));
-        params.push((":offset", &offset));
+        Ok(())
+    }
 
-        let query = if where_statement_parts.is_empty() {
-            format!(
-                "{} ORDER BY block_number, transaction_hash, idx LIMIT :limit OFFSET :offset",
-                base_query
+    /// Returns the [BlockEventSummary] of `block`, which is empty for blocks without events.
+    ///
+    /// This is read from `block_event_summary` instead of being aggregated from the events.
+    pub fn get_block_summary(
+        connection: &Connection,
+        block: StarknetBlockNumber,
+    ) -> anyhow::Result<BlockEventSummary> {
+        let summary = connection
+            .query_row(
+                "SELECT event_count, emitter_count FROM block_event_summary WHERE block_number = ?",
+                [block.0],
+                |row| {
+                    Ok(BlockEventSummary {
+                        event_count: row.get(0)?,
+                        emitter_count: row.get(1)?,
+                    })
+                },
             )
-        } else {
-            format!(
-                "{} WHERE {} ORDER BY block_number, transaction_hash, idx LIMIT :limit OFFSET :offset",
-                base_query,
-                where_statement_parts.join(" AND "),
+            .optional()
+            .context("Read block event summary")?;
+
+        Ok(summary.unwrap_or_default())
+    }
+
+    /// Returns the [BlockEventSummary] of each stored block in `from..=to`, in ascending order.
+    pub fn get_block_summaries(
+        connection: &Connection,
+        from: StarknetBlockNumber,
+        to: StarknetBlockNumber,
+    ) -> anyhow::Result<Vec<(StarknetBlockNumber, BlockEventSummary)>> {
+        let mut statement = connection
+            .prepare_counted(
+                r"SELECT number, IFNULL(event_count, 0), IFNULL(emitter_count, 0)
+                    FROM starknet_blocks
+                    LEFT JOIN block_event_summary ON block_event_summary.block_number = starknet_blocks.number
+                    WHERE number BETWEEN ? AND ?
+                    ORDER BY number ASC",
+            )
+            .context("Preparing statement")?;
+
+        let summaries = statement
+            .query_map([from.0, to.0], |row| {
+                Ok((
+                    StarknetBlockNumber(row.get(0)?),
+                    BlockEventSummary {
+                        event_count: row.get(1)?,
+                        emitter_count: row.get(2)?,
+                    },
+                ))
+            })
+            .context("Executing query")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Reading block event summaries")?;
+
+        Ok(summaries)
+    }
+
+    /// Adds the keys of `events` to the bloom filter of `block_number`.
+    ///
+    /// Keys of replaced events are never removed, a stale bit only costs a false positive.
+    fn update_key_bloom<'a>(
+        connection: &Connection,
+        block_number: StarknetBlockNumber,
+        keys: impl Iterator<Item = &'a EventKey>,
+    ) -> anyhow::Result<()> {
+        let bloom = connection
+            .query_row(
+                "SELECT bloom FROM starknet_events_key_blooms WHERE block_number = ?",
+                [block_number.0],
+                |row| row.get::<_, Vec<u8>>(0),
             )
+            .optional()
+            .context("Read bloom filter")?;
+        let mut bloom = match bloom {
+            Some(bloom) => KeyBloom::from_bytes(&bloom)?,
+            None => KeyBloom::default(),
         };
 
-        let mut statement = connection.prepare(&query).context("Preparing SQL query")?;
-        let mut rows = statement
-            .query(params.as_slice())
-            .context("Executing SQL query")?;
+        keys.for_each(|key| bloom.insert(&key.0));
 
-        let mut is_last_page = true;
-        let mut emitted_events = Vec::new();
-        while let Some(row) = rows.next().context("Fetching next event")? {
-            let block_number = row.get_ref_unwrap("block_number").as_i64().unwrap() as u64;
-            let block_number = StarknetBlockNumber(block_number);
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO starknet_events_key_blooms (block_number, bloom) VALUES (?, ?)",
+                params![block_number.0, bloom.as_bytes()],
+            )
+            .context("Write bloom filter")?;
 
-            let block_hash = row.get_ref_unwrap("block_hash").as_blob().unwrap();
-            let block_hash = StarkHash::from_be_slice(block_hash).unwrap();
-            let block_hash = StarknetBlockHash(block_hash);
+        Ok(())
+    }
 
-            let transaction_hash = row.get_ref_unwrap("transaction_hash").as_blob().unwrap();
-            let transaction_hash = StarkHash::from_be_slice(transaction_hash).unwrap();
-            let transaction_hash = StarknetTransactionHash(transaction_hash);
+    /// Replaces all stored events with the events re-derived from the stored transaction
+    /// receipts, and returns the number of events inserted.
+    ///
+    /// The receipts in `starknet_transactions` are authoritative, this recovers from a lost or
+    /// corrupted events table. The keys' FTS index is rebuilt from scratch afterwards, so that
+    /// it is consistent even if it was corrupted as well.
+    ///
+    /// This rewrites the whole table and should be run within a database transaction.
+    pub fn rebuild_from_receipts(
+        connection: &Connection,
+        limits: &StorageLimits,
+    ) -> anyhow::Result<usize> {
+        connection
+            .execute_batch(
+                r"DELETE FROM starknet_events;
+                DELETE FROM starknet_events_data;
+                DELETE FROM starknet_events_key_blooms;
+                DELETE FROM block_event_summary;",
+            )
+            .context("Delete existing events")?;
 
-            let from_address = row.get_ref_unwrap("from_address").as_blob().unwrap();
-            let from_address = StarkHash::from_be_slice(from_address).unwrap();
-            let from_address = ContractAddress(from_address);
+        let mut statement = connection
+            .prepare(
+                r"SELECT starknet_blocks.number, tx, receipt FROM starknet_transactions
+                    JOIN starknet_blocks ON starknet_transactions.block_hash = starknet_blocks.hash
+                    ORDER BY starknet_blocks.number ASC, starknet_transactions.idx ASC",
+            )
+            .context("Preparing statement")?;
+        let mut rows = statement.query([]).context("Executing query")?;
 
-            let data = row.get_ref_unwrap("data").as_blob().unwrap();
-            let data: Vec<_> = data
-                .chunks_exact(32)
-                .map(|data| {
-                    let data = StarkHash::from_be_slice(data).unwrap();
-                    EventData(data)
-                })
-                .collect();
+        let mut count = 0;
+        // The keys of the block being rebuilt, whose indexes are updated once it is complete.
+        let mut block_keys: Option<(StarknetBlockNumber, Vec<EventKey>)> = None;
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let block_number = row.get_ref_unwrap("number").as_i64()? as u64;
+            let block_number = StarknetBlockNumber(block_number);
 
-            let keys = row.get_ref_unwrap("keys").as_str().unwrap();
-            let keys: Vec<_> = keys
-                .split(' ')
-                .map(|key| {
-                    let key = StarkHash::from_be_slice(&base64::decode(key).unwrap()).unwrap();
-                    EventKey(key)
-                })
-                .collect();
+            if block_keys.as_ref().map(|(number, _)| *number) != Some(block_number) {
+                if let Some((number, keys)) = block_keys.replace((block_number, Vec::new())) {
+                    Self::update_block_indexes(connection, number, &keys)?;
+                }
+            }
 
-            if emitted_events.len() == filter.page_size {
-                // We already have a full page, and are just fetching the extra event
-                // This means that there are more pages.
-                is_last_page = false;
-            } else {
-                let event = StarknetEmittedEvent {
-                    data,
-                    from_address,
-                    keys,
-                    block_hash,
-                    block_number,
-                    transaction_hash,
-                };
-                emitted_events.push(event);
+            let transaction = row
+                .get_ref_unwrap("tx")
+                .as_blob_or_null()?
+                .context("Transaction data missing")?;
+            let transaction = blob::decompress(transaction, limits.max_blob_size)
+                .context("Decompressing transaction")?;
+            let transaction: transaction::Transaction =
+                packed::decode_transaction(&transaction).context("Deserializing transaction")?;
+
+            let receipt = row
+                .get_ref_unwrap("receipt")
+                .as_blob_or_null()?
+                .context("Receipt data missing")?;
+            let receipt = blob::decompress(receipt, limits.max_blob_size)
+                .context("Decompressing transaction receipt")?;
+            let receipt: transaction::Receipt =
+                packed::decode_receipt(&receipt).context("Deserializing transaction receipt")?;
+
+            Self::insert_transaction_events(
+                connection,
+                block_number,
+                &transaction,
+                receipt.execution_status,
+                &receipt.events,
+            )
+            .with_context(|| {
+                format!(
+                    "Inserting events of transaction {}",
+                    transaction.transaction_hash.0
+                )
+            })?;
+            count += receipt.events.len();
+
+            if let Some((_, keys)) = block_keys.as_mut() {
+                keys.extend(receipt.events.into_iter().flat_map(|event| event.keys));
             }
         }
 
-        Ok(PageOfEvents {
-            events: emitted_events,
-            is_last_page,
-        })
-    }
+        if let Some((number, keys)) = block_keys {
+            Self::update_block_indexes(connection, number, &keys)?;
+        }
+
+        connection
+            .execute(
+                "INSERT INTO starknet_events_keys(starknet_events_keys) VALUES ('rebuild')",
+                [],
+            )
+            .context("Rebuild event keys index")?;
+
+        Ok(count)
+    }
+
+    pub(crate) const PAGE_SIZE_LIMIT: usize = 1024;
+
+    /// Upper bound on the number of block ranges [Self::get_events] restricts a query to based
+    /// on the key bloom filters. Filters matching more scattered blocks are not worth it.
+    const KEY_BLOOM_RANGES_LIMIT: usize = 64;
+
+    /// Upper bound on the number of key bloom filters read for a single query. The blocks after
+    /// the last filter read are kept as candidates.
+    const KEY_BLOOM_ROWS_LIMIT: usize = 1024;
+
+    /// The number of key bloom filters read before deciding whether the filter's keys are too
+    /// common for the bloom filters to be worth reading, see [Self::key_bloom_block_ranges].
+    const KEY_BLOOM_SAMPLE_ROWS: usize = 64;
+
+    /// The factor by which a contract address filter is assumed to reduce the number of
+    /// matching events, see [Self::estimate_cost].
+    const ADDRESS_SELECTIVITY: u64 = 100;
+
+    /// The factor by which a key or data filter is assumed to reduce the number of matching
+    /// events. This is lower than [Self::ADDRESS_SELECTIVITY], as some keys are very common.
+    const KEY_SELECTIVITY: u64 = 10;
+
+    /// Returns the events matching `filter`.
+    ///
+    /// Queries which are [estimated](Self::estimate_cost) to scan more events than the
+    /// [StorageLimits::event_query_cost_budget] are rejected with [EventFilterError::TooExpensive]
+    /// instead of being run. Without a budget, queries over [DEFAULT_EVENT_QUERY_COST_BUDGET] are
+    /// logged and run.
+    pub fn get_events(
+        connection: &Connection,
+        filter: &StarknetEventFilter,
+        limits: &StorageLimits,
+    ) -> anyhow::Result<PageOfEvents> {
+        let cost = Self::estimate_cost(connection, filter)?;
+        match limits.event_query_cost_budget {
+            Some(budget) if cost.events > budget => {
+                return Err(EventFilterError::TooExpensive {
+                    cost: cost.events,
+                    budget,
+                    constraint: cost.constraint,
+                }
+                .into());
+            }
+            Some(_) => {}
+            None if cost.events > DEFAULT_EVENT_QUERY_COST_BUDGET => {
+                tracing::warn!(cost=%cost.events, constraint=%cost.constraint, "Running event query which is estimated to be expensive");
+            }
+            None => {}
+        }
+
+        Self::find_events(connection, filter)
+    }
+
+    /// Runs the query for `filter`, skipping it if the key bloom filters rule out every block.
+    fn find_events(
+        connection: &Connection,
+        filter: &StarknetEventFilter,
+    ) -> anyhow::Result<PageOfEvents> {
+        let key_bloom_ranges = Self::key_bloom_block_ranges(connection, filter)?;
+        let query = Self::event_query(filter, key_bloom_ranges.as_deref())?;
+
+        // The bloom filters rule out every block, so there is no need to run the query.
+        if matches!(&key_bloom_ranges, Some(ranges) if ranges.is_empty()) {
+            return Ok(PageOfEvents {
+                events: Vec::new(),
+                is_last_page: true,
+            });
+        }
+
+        Self::page_of_events(connection, filter, &query)
+    }
+
+    /// Estimates the number of events the query for `filter` scans, without running it.
+    ///
+    /// The events within the block range are counted using `block_event_summary`. Of those, a
+    /// fixed share is assumed to match each address, key and data filter. The query has to
+    /// scan the matches up to the requested page, and at most all the events in the block range.
+    fn estimate_cost(
+        connection: &Connection,
+        filter: &StarknetEventFilter,
+    ) -> anyhow::Result<EventQueryCost> {
+        let in_range: i64 = match filter.block_hash {
+            Some(hash) => connection
+                .query_row(
+                    r"SELECT IFNULL(SUM(event_count), 0) FROM block_event_summary
+                        INNER JOIN starknet_blocks ON starknet_blocks.number = block_event_summary.block_number
+                        WHERE starknet_blocks.hash = ?",
+                    [&hash.0.as_be_bytes()[..]],
+                    |row| row.get(0),
+                )
+                .context("Count events in block")?,
+            None => {
+                let from_block = filter.from_block.map(|block| block.0 as i64).unwrap_or(0);
+                let to_block = filter
+                    .to_block
+                    .map(|block| block.0 as i64)
+                    .unwrap_or(i64::MAX);
+                connection
+                    .prepare_counted(
+                        r"SELECT IFNULL(SUM(event_count), 0) FROM block_event_summary
+                            WHERE block_number BETWEEN ? AND ?",
+                    )
+                    .context("Preparing event count query")?
+                    .query_row([from_block, to_block], |row| row.get(0))
+                    .context("Count events in block range")?
+            }
+        };
+        let in_range = in_range as u64;
+
+        let mut selectivity = 1;
+        if filter.contract_address.is_some() {
+            selectivity *= Self::ADDRESS_SELECTIVITY;
+        }
+        if !filter.keys.is_empty() {
+            selectivity *= Self::KEY_SELECTIVITY;
+        }
+        for _ in &filter.data_contains {
+            selectivity = selectivity.saturating_mul(Self::KEY_SELECTIVITY);
+        }
+        let matches = in_range / selectivity;
+        let offset = (filter.page_number as u64).saturating_mul(filter.page_size as u64);
+
+        let bounded = filter.block_hash.is_some() || filter.to_block.is_some();
+        let constraint = if offset > matches {
+            EventFilterConstraint::PageNumber
+        } else if bounded && filter.contract_address.is_none() {
+            EventFilterConstraint::ContractAddress
+        } else {
+            EventFilterConstraint::BlockRange
+        };
+
+        Ok(EventQueryCost {
+            events: in_range.min(matches.saturating_add(offset)),
+            constraint,
+        })
+    }
+
+    /// Runs the `query` built for `filter` by [Self::event_query].
+    fn page_of_events(
+        connection: &Connection,
+        filter: &StarknetEventFilter,
+        query: &EventQuery,
+    ) -> anyhow::Result<PageOfEvents> {
+        let mut statement = connection
+            .prepare_counted(&query.sql)
+            .context("Preparing SQL query")?;
+        let mut rows = statement
+            .query(query.params().as_slice())
+            .context("Executing SQL query")?;
+
+        let mut is_last_page = true;
+        let mut emitted_events = Vec::new();
+        while let Some(row) = rows.next().context("Fetching next event")? {
+            if emitted_events.len() == filter.page_size {
+                // We already have a full page, and are just fetching the extra event
+                // This means that there are more pages.
+                is_last_page = false;
+            } else {
+                emitted_events.push(Self::event_from_row(row));
+            }
+        }
+
+        Ok(PageOfEvents {
+            events: emitted_events,
+            is_last_page,
+        })
+    }
+
+    /// Returns SQLite's query plan for the query [Self::get_events] runs for `filter`.
+    ///
+    /// Each step of the plan is on its own line, indented by its depth in the plan. This is
+    /// meant for debugging slow event queries.
+    pub fn explain(
+        connection: &Connection,
+        filter: &StarknetEventFilter,
+    ) -> anyhow::Result<String> {
+        let key_bloom_ranges = Self::key_bloom_block_ranges(connection, filter)?;
+        let query = Self::event_query(filter, key_bloom_ranges.as_deref())?;
+
+        let mut statement = connection
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", query.sql))
+            .context("Preparing SQL query")?;
+        let mut rows = statement
+            .query(query.params().as_slice())
+            .context("Executing SQL query")?;
+
+        // Steps are listed depth first, after their parent.
+        let mut depths = HashMap::new();
+        let mut plan = String::new();
+        while let Some(row) = rows.next().context("Fetching next plan step")? {
+            let id = row.get_ref_unwrap("id").as_i64()?;
+            let parent = row.get_ref_unwrap("parent").as_i64()?;
+            let detail = row.get_ref_unwrap("detail").as_str()?;
+
+            let depth = depths.get(&parent).map(|depth| depth + 1).unwrap_or(0);
+            depths.insert(id, depth);
+
+            plan.push_str(&"  ".repeat(depth));
+            plan.push_str(detail);
+            plan.push('\n');
+        }
+
+        Ok(plan)
+    }
+
+    /// Builds the SQL query and its named parameters for [Self::get_events].
+    ///
+    /// The query is restricted to the `key_bloom_ranges` found by
+    /// [Self::key_bloom_block_ranges], if any.
+    fn event_query(
+        filter: &StarknetEventFilter,
+        key_bloom_ranges: Option<&[RangeInclusive<StarknetBlockNumber>]>,
+    ) -> anyhow::Result<EventQuery> {
+        let mut base_query =
+            r#"SELECT
+                  block_number,
+                  starknet_blocks.hash as block_hash,
+                  transaction_hash,
+                  from_address,
+                  data,
+                  starknet_events.keys as keys
+               FROM starknet_events
+               INNER JOIN starknet_blocks ON starknet_blocks.number = starknet_events.block_number "#
+                .to_string();
+        let mut where_statement_parts: Vec<Cow<'static, str>> = Vec::new();
+        let mut params: Vec<(Cow<'static, str>, Value)> = Vec::new();
+
+        // filter on block range: a missing bound is replaced by the lowest or highest block
+        // number, so that all ranges share the same SQL.
+        if filter.from_block.is_some() || filter.to_block.is_some() {
+            let from_block = filter.from_block.map(|block| block.0 as i64).unwrap_or(0);
+            let to_block = filter
+                .to_block
+                .map(|block| block.0 as i64)
+                .unwrap_or(i64::MAX);
+            where_statement_parts.push("block_number BETWEEN :from_block AND :to_block".into());
+            params.push((":from_block".into(), Value::Integer(from_block)));
+            params.push((":to_block".into(), Value::Integer(to_block)));
+        }
+
+        // filter on block hash
+        if let Some(block_hash) = &filter.block_hash {
+            where_statement_parts.push("starknet_blocks.hash = :block_hash".into());
+            params.push((
+                ":block_hash".into(),
+                Value::Blob(block_hash.0.as_be_bytes().to_vec()),
+            ));
+        }
+
+        // filter on contract address
+        if let Some(contract_address) = &filter.contract_address {
+            where_statement_parts.push("from_address = :contract_address".into());
+            params.push((
+                ":contract_address".into(),
+                Value::Blob(contract_address.0.as_be_bytes().to_vec()),
+            ));
+        }
+
+        // Filter on keys: this is using an FTS5 full-text index (virtual table) on the keys.
+        // The idea is that we convert keys to a space-separated list of hex encoded string
+        // representation and then use the full-text index to find events matching the events.
+        if !filter.keys.is_empty() {
+            let hex_keys: Vec<String> = filter
+                .keys
+                .iter()
+                .map(Self::event_key_to_hex_string)
+                .collect();
+            let key_fts_expression = hex_keys.join(" OR ");
+
+            base_query.push_str("INNER JOIN starknet_events_keys ON starknet_events.rowid = starknet_events_keys.rowid");
+            where_statement_parts.push("starknet_events_keys.keys MATCH :events_match".into());
+            params.push((":events_match".into(), Value::Text(key_fts_expression)));
+
+            // The bloom filters let us skip the blocks which cannot contain any of the keys,
+            // before consulting the full-text index.
+            if let Some(ranges) = key_bloom_ranges {
+                let ranges = match ranges.is_empty() {
+                    true => "FALSE".to_owned(),
+                    false => {
+                        // The number of ranges is rounded up to a power of two using empty
+                        // ranges, which bounds the number of distinct statements to cache.
+                        let slots = ranges.len().next_power_of_two();
+                        let bounds = ranges
+                            .iter()
+                            .map(|range| (range.start().0 as i64, range.end().0 as i64))
+                            .chain(std::iter::repeat((1, 0)))
+                            .take(slots);
+
+                        let mut conditions = Vec::with_capacity(slots);
+                        for (i, (start, end)) in bounds.enumerate() {
+                            let start_name = format!(":bloom_from_{}", i);
+                            let end_name = format!(":bloom_to_{}", i);
+                            conditions.push(format!(
+                                "block_number BETWEEN {} AND {}",
+                                start_name, end_name
+                            ));
+                            params.push((start_name.into(), Value::Integer(start)));
+                            params.push((end_name.into(), Value::Integer(end)));
+                        }
+                        conditions.join(" OR ")
+                    }
+                };
+                where_statement_parts.push(format!("({})", ranges).into());
+            }
+        }
+
+        // Filter on data: each condition is looked up using the value index of the
+        // `starknet_events_data` table.
+        for (i, condition) in filter.data_contains.iter().enumerate() {
+            let position_name = format!(":data_position_{}", i);
+            let value_name = format!(":data_value_{}", i);
+            where_statement_parts.push(
+                format!(
+                    "starknet_events.rowid IN (SELECT event_id FROM starknet_events_data WHERE value = {} AND position = {})",
+                    value_name, position_name
+                )
+                .into(),
+            );
+            params.push((
+                position_name.into(),
+                Value::Integer(condition.position as i64),
+            ));
+            params.push((
+                value_name.into(),
+                Value::Blob(condition.value.0.as_be_bytes().to_vec()),
+            ));
+        }
+
+        // filter out the events of reverted transactions
+        if !filter.include_reverted {
+            where_statement_parts.push("starknet_events.reverted = 0".into());
+        }
+
+        // Paging
+        if filter.page_size > Self::PAGE_SIZE_LIMIT {
+            return Err(EventFilterError::PageSizeTooBig(Self::PAGE_SIZE_LIMIT).into());
+        }
+        if filter.page_size < 1 {
+            anyhow::bail!("Invalid page size");
+        }
+        let offset = filter.page_number * filter.page_size;
+        // We have to be able to decide if there are more events. We request one extra event
+        // above the requested page size, so that we can decide.
+        let limit = filter.page_size + 1;
+        params.push((":limit".into(), Value::Integer(limit as i64)));
+        params.push((":offset".into(), Value::Integer(offset as i64)));
+
+        let sql = if where_statement_parts.is_empty() {
+            format!(
+                "{} ORDER BY block_number, transaction_hash, idx LIMIT :limit OFFSET :offset",
+                base_query
+            )
+        } else {
+            format!(
+                "{} WHERE {} ORDER BY block_number, transaction_hash, idx LIMIT :limit OFFSET :offset",
+                base_query,
+                where_statement_parts.join(" AND "),
+            )
+        };
+
+        Ok(EventQuery { sql, params })
+    }
+
+    /// Returns the ranges of blocks within the filter's block range whose key bloom filter may
+    /// contain any of the filter's keys, in ascending order.
+    ///
+    /// Blocks without a bloom filter emitted no events with keys and are never returned,
+    /// adjacent candidate blocks are merged into a single range. At most
+    /// [Self::KEY_BLOOM_ROWS_LIMIT] bloom filters are read, the blocks after them are all
+    /// candidates.
+    ///
+    /// Returns `None` if the filter has no keys, or the candidates are too scattered to be worth
+    /// restricting a query to. Reading stops early if more than half of the blocks read are
+    /// candidates once [Self::KEY_BLOOM_SAMPLE_ROWS] have been read, as keys which are that
+    /// common hardly let any blocks be skipped.
+    fn key_bloom_block_ranges(
+        connection: &Connection,
+        filter: &StarknetEventFilter,
+    ) -> anyhow::Result<Option<Vec<RangeInclusive<StarknetBlockNumber>>>> {
+        if filter.keys.is_empty() {
+            return Ok(None);
+        }
+
+        let from_block = filter.from_block.map(|block| block.0).unwrap_or_default();
+        let to_block = filter
+            .to_block
+            .map(|block| block.0 as i64)
+            .unwrap_or(i64::MAX);
+
+        let mut statement = connection
+            .prepare_counted(
+                r"SELECT block_number, bloom FROM starknet_events_key_blooms
+                    WHERE block_number BETWEEN :from_block AND :to_block
+                    ORDER BY block_number
+                    LIMIT :limit",
+            )
+            .context("Preparing bloom filter query")?;
+        let mut rows = statement
+            .query(named_params! {
+                ":from_block": from_block,
+                ":to_block": to_block,
+                ":limit": Self::KEY_BLOOM_ROWS_LIMIT,
+            })
+            .context("Executing bloom filter query")?;
+
+        let mut ranges = Vec::new();
+        // The candidate range which has not been interrupted by a ruled out block yet.
+        let mut current: Option<RangeInclusive<StarknetBlockNumber>> = None;
+        let mut read = 0;
+        let mut candidates = 0;
+        let mut last_read = None;
+        while let Some(row) = rows.next().context("Fetching next bloom filter")? {
+            let block_number = StarknetBlockNumber(row.get_ref_unwrap(0).as_i64()? as u64);
+            let bloom = KeyBloom::from_bytes(row.get_ref_unwrap(1).as_blob()?)?;
+            read += 1;
+            last_read = Some(block_number);
+
+            if filter.keys.iter().any(|key| bloom.may_contain(&key.0)) {
+                candidates += 1;
+                current = Some(match current {
+                    Some(range) => *range.start()..=block_number,
+                    None => block_number..=block_number,
+                });
+            } else if let Some(range) = current.take() {
+                ranges.push(range);
+                if ranges.len() > Self::KEY_BLOOM_RANGES_LIMIT {
+                    return Ok(None);
+                }
+            }
+
+            if read >= Self::KEY_BLOOM_SAMPLE_ROWS && candidates * 2 > read {
+                return Ok(None);
+            }
+        }
+
+        // The bloom filters of the blocks after the last one read were not checked.
+        if read == Self::KEY_BLOOM_ROWS_LIMIT {
+            if let Some(last_read) = last_read {
+                let unchecked =
+                    StarknetBlockNumber(last_read.0 + 1)..=StarknetBlockNumber(to_block as u64);
+                if !unchecked.is_empty() {
+                    current = Some(match current {
+                        Some(range) => *range.start()..=*unchecked.end(),
+                        None => unchecked,
+                    });
+                }
+            }
+        }
+        ranges.extend(current);
+
+        if ranges.len() > Self::KEY_BLOOM_RANGES_LIMIT {
+            return Ok(None);
+        }
+
+        Ok(Some(ranges))
+    }
+
+    /// Invokes `callback` for every stored event, in the same order as [Self::get_events].
+    ///
+    /// Events are read in pages of `batch_size` so that the full set is never held in memory,
+    /// which makes this suitable for exporting all events for offline indexing. Iteration stops
+    /// at the first error returned by `callback`.
+    ///
+    /// Returns the number of events passed to `callback`.
+    pub fn stream_all<F>(
+        connection: &Connection,
+        batch_size: usize,
+        mut callback: F,
+    ) -> anyhow::Result<usize>
+    where
+        F: FnMut(StarknetEmittedEvent) -> anyhow::Result<()>,
+    {
+        anyhow::ensure!(batch_size > 0, "Invalid batch size");
+
+        // Keyset pagination: each page continues after the last event of the previous one,
+        // so the cost of a page does not grow with the number of events already streamed.
+        let mut statement = connection
+            .prepare(
+                r"SELECT
+                      block_number,
+                      starknet_blocks.hash as block_hash,
+                      transaction_hash,
+                      idx,
+                      from_address,
+                      data,
+                      starknet_events.keys as keys
+                   FROM starknet_events
+                   INNER JOIN starknet_blocks ON starknet_blocks.number = starknet_events.block_number
+                   WHERE (block_number, transaction_hash, idx) > (:block_number, :transaction_hash, :idx)
+                   ORDER BY block_number, transaction_hash, idx
+                   LIMIT :limit",
+            )
+            .context("Preparing SQL query")?;
+
+        // Starts before the first event: -1 is below any block number and the empty blob sorts
+        // before any transaction hash.
+        let mut cursor: (i64, Vec<u8>, i64) = (-1, Vec::new(), -1);
+        let mut count = 0;
+
+        loop {
+            let mut rows = statement
+                .query(named_params! {
+                    ":block_number": cursor.0,
+                    ":transaction_hash": &cursor.1,
+                    ":idx": cursor.2,
+                    ":limit": batch_size,
+                })
+                .context("Executing SQL query")?;
+
+            let mut batch_len = 0;
+            while let Some(row) = rows.next().context("Fetching next event")? {
+                cursor = (
+                    row.get_ref_unwrap("block_number").as_i64().unwrap(),
+                    row.get_ref_unwrap("transaction_hash")
+                        .as_blob()
+                        .unwrap()
+                        .to_vec(),
+                    row.get_ref_unwrap("idx").as_i64().unwrap(),
+                );
+
+                callback(Self::event_from_row(row))?;
+                batch_len += 1;
+            }
+
+            count += batch_len;
+            if batch_len < batch_size {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Returns the events emitted by the transaction at `tx_index` in `block`, in emission
+    /// order.
+    ///
+    /// Empty if the block or the transaction does not exist, or the transaction emitted no
+    /// events.
+    pub fn get_events_for_block_transaction(
+        connection: &Connection,
+        block: StarknetBlocksBlockId,
+        tx_index: usize,
+    ) -> anyhow::Result<Vec<StarknetEmittedEvent>> {
+        // Identify block hash
+        let block_hash = match block {
+            StarknetBlocksBlockId::Hash(hash) => hash,
+            other => match StarknetBlocksTable::get(connection, other)? {
+                Some(block) => block.hash,
+                None => return Ok(Vec::new()),
+            },
+        };
+
+        let mut statement = connection
+            .prepare_counted(
+                r"SELECT
+                      block_number,
+                      starknet_blocks.hash as block_hash,
+                      transaction_hash,
+                      from_address,
+                      data,
+                      starknet_events.keys as keys
+                   FROM starknet_transactions
+                   INNER JOIN starknet_blocks ON starknet_blocks.hash = starknet_transactions.block_hash
+                   INNER JOIN starknet_events ON starknet_events.block_number = starknet_blocks.number
+                      AND starknet_events.transaction_hash = starknet_transactions.hash
+                   WHERE starknet_transactions.block_hash = :block_hash AND starknet_transactions.idx = :idx
+                   ORDER BY starknet_events.idx",
+            )
+            .context("Preparing SQL query")?;
+        let mut rows = statement
+            .query(named_params! {
+                ":block_hash": &block_hash.0.as_be_bytes()[..],
+                ":idx": tx_index,
+            })
+            .context("Executing SQL query")?;
+
+        let mut events = Vec::new();
+        while let Some(row) = rows.next().context("Fetching next event")? {
+            events.push(Self::event_from_row(row));
+        }
+
+        Ok(events)
+    }
+
+    fn event_from_row(row: &rusqlite::Row<'_>) -> StarknetEmittedEvent {
+        let block_number = row.get_ref_unwrap("block_number").as_i64().unwrap() as u64;
+        let block_number = StarknetBlockNumber(block_number);
+
+        let block_hash = row.get_ref_unwrap("block_hash").as_blob().unwrap();
+        let block_hash = StarkHash::from_be_slice(block_hash).unwrap();
+        let block_hash = StarknetBlockHash(block_hash);
+
+        let transaction_hash = row.get_ref_unwrap("transaction_hash").as_blob().unwrap();
+        let transaction_hash = StarkHash::from_be_slice(transaction_hash).unwrap();
+        let transaction_hash = StarknetTransactionHash(transaction_hash);
+
+        let from_address = row.get_ref_unwrap("from_address").as_blob().unwrap();
+        let from_address = StarkHash::from_be_slice(from_address).unwrap();
+        let from_address = ContractAddress(from_address);
+
+        // Events without data are stored with NULL (or empty) data.
+        let data = match row.get_ref_unwrap("data") {
+            rusqlite::types::ValueRef::Null => &[][..],
+            data => data.as_blob().unwrap(),
+        };
+        let data: Vec<_> = data
+            .chunks_exact(32)
+            .map(|data| {
+                let data = StarkHash::from_be_slice(data).unwrap();
+                EventData(data)
+            })
+            .collect();
+
+        // Events without keys are stored with empty (or NULL) keys.
+        let keys = match row.get_ref_unwrap("keys") {
+            rusqlite::types::ValueRef::Null => "",
+            keys => keys.as_str().unwrap(),
+        };
+        let keys: Vec<_> = keys
+            .split(' ')
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                let key = StarkHash::from_be_slice(&hex::decode(key).unwrap()).unwrap();
+                EventKey(key)
+            })
+            .collect();
+
+        StarknetEmittedEvent {
+            data,
+            from_address,
+            keys,
+            block_hash,
+            block_number,
+            transaction_hash,
+        }
+    }
+
+    /// Returns the number of events which contain the given key.
+    ///
+    /// Only the FTS index on the keys is consulted, which makes this cheap enough to be used
+    /// for estimating the cost of a key filter.
+    pub fn count_by_key(connection: &Connection, key: EventKey) -> anyhow::Result<usize> {
+        let key_fts_expression = Self::event_key_to_hex_string(&key);
+
+        let count: i64 = connection
+            .query_row(
+                "SELECT count(1) FROM starknet_events_keys WHERE keys MATCH ?",
+                [key_fts_expression],
+                |row| row.get(0),
+            )
+            .context("Count events by key")?;
+
+        Ok(count as usize)
+    }
 }
 
 /// Describes a Starknet block.
@@ -910,6 +2663,7 @@ pub struct StarknetBlock {
     pub timestamp: StarknetBlockTimestamp,
     pub gas_price: GasPrice,
     pub sequencer_address: SequencerAddress,
+    pub parent_hash: StarknetBlockHash,
 }
 
 /// Stores the contract state hash along with its preimage. This is useful to
@@ -972,6 +2726,33 @@ impl ContractsStateTable {
 
         Ok(Some(root))
     }
+
+    /// Returns the hashes of all contract states with the given root.
+    ///
+    /// Many contract states can share a root, e.g. all states of contracts with empty storage.
+    pub fn find_state_hashes_by_root(
+        transaction: &Transaction,
+        root: ContractRoot,
+    ) -> anyhow::Result<Vec<ContractStateHash>> {
+        let mut statement = transaction
+            .prepare_counted(
+                "SELECT state_hash FROM contract_states WHERE root = ? ORDER BY state_hash",
+            )
+            .context("Preparing statement")?;
+
+        let mut rows = statement
+            .query(params![root.0.as_be_bytes()])
+            .context("Executing query")?;
+
+        let mut state_hashes = Vec::new();
+        while let Some(row) = rows.next().context("Iterate rows")? {
+            let state_hash = row.get_ref_unwrap("state_hash").as_blob()?;
+            let state_hash = StarkHash::from_be_slice(state_hash).context("Parsing state hash")?;
+            state_hashes.push(ContractStateHash(state_hash));
+        }
+
+        Ok(state_hashes)
+    }
 }
 
 #[cfg(test)]
@@ -998,15 +2779,60 @@ mod tests {
 
             assert_eq!(result, Some(root));
         }
-    }
 
-    mod refs {
-        use super::*;
+        #[test]
+        fn find_state_hashes_by_root() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let transaction = connection.transaction().unwrap();
 
-        mod l1_l2_head {
-            use super::*;
+            let hash = ClassHash(StarkHash::from_hex_str("123").unwrap());
+            let shared = ContractRoot(StarkHash::from_hex_str("def").unwrap());
+            let other = ContractRoot(StarkHash::from_hex_str("fed").unwrap());
 
-            #[test]
+            let state_hashes = ["abc", "abd", "abe"]
+                .map(|s| ContractStateHash(StarkHash::from_hex_str(s).unwrap()));
+            ContractsStateTable::upsert(&transaction, state_hashes[2], hash, shared).unwrap();
+            ContractsStateTable::upsert(&transaction, state_hashes[1], hash, other).unwrap();
+            ContractsStateTable::upsert(&transaction, state_hashes[0], hash, shared).unwrap();
+
+            assert_eq!(
+                ContractsStateTable::find_state_hashes_by_root(&transaction, shared).unwrap(),
+                vec![state_hashes[0], state_hashes[2]]
+            );
+            assert_eq!(
+                ContractsStateTable::find_state_hashes_by_root(&transaction, other).unwrap(),
+                vec![state_hashes[1]]
+            );
+
+            let unknown = ContractRoot(StarkHash::from_hex_str("deadbeef").unwrap());
+            assert_eq!(
+                ContractsStateTable::find_state_hashes_by_root(&transaction, unknown).unwrap(),
+                vec![]
+            );
+        }
+    }
+
+    mod refs {
+        use super::*;
+
+        #[test]
+        fn chain() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            assert_eq!(RefsTable::get_chain(&connection).unwrap(), None);
+
+            for chain in [Chain::Mainnet, Chain::Goerli] {
+                RefsTable::set_chain(&connection, chain).unwrap();
+                assert_eq!(RefsTable::get_chain(&connection).unwrap(), Some(chain));
+            }
+        }
+
+        mod l1_l2_head {
+            use super::*;
+
+            #[test]
             fn fresh_is_none() {
                 let storage = Storage::in_memory().unwrap();
                 let connection = storage.connection().unwrap();
@@ -1031,6 +2857,75 @@ mod tests {
                 RefsTable::set_l1_l2_head(&connection, None).unwrap();
                 assert_eq!(None, RefsTable::get_l1_l2_head(&connection).unwrap());
             }
+
+            #[test]
+            fn reorg() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                // A head below the tail is left untouched.
+                RefsTable::set_l1_l2_head(&connection, Some(StarknetBlockNumber(4))).unwrap();
+                let head = RefsTable::reorg(&connection, StarknetBlockNumber(5)).unwrap();
+                assert_eq!(head, Some(StarknetBlockNumber(4)));
+
+                let head = RefsTable::reorg(&connection, StarknetBlockNumber(4)).unwrap();
+                assert_eq!(head, Some(StarknetBlockNumber(3)));
+                assert_eq!(head, RefsTable::get_l1_l2_head(&connection).unwrap());
+
+                let head = RefsTable::reorg(&connection, StarknetBlockNumber::GENESIS).unwrap();
+                assert_eq!(head, None);
+                assert_eq!(head, RefsTable::get_l1_l2_head(&connection).unwrap());
+
+                let head = RefsTable::reorg(&connection, StarknetBlockNumber::GENESIS).unwrap();
+                assert_eq!(head, None);
+            }
+        }
+
+        mod backfill_range {
+            use super::*;
+
+            #[test]
+            fn set_get() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                assert_eq!(RefsTable::get_backfill_range(&connection).unwrap(), None);
+
+                let expected = Some(StarknetBlockNumber(10)..=StarknetBlockNumber(12));
+                RefsTable::set_backfill_range(&connection, expected.clone()).unwrap();
+                assert_eq!(
+                    RefsTable::get_backfill_range(&connection).unwrap(),
+                    expected
+                );
+
+                RefsTable::set_backfill_range(&connection, None).unwrap();
+                assert_eq!(RefsTable::get_backfill_range(&connection).unwrap(), None);
+            }
+
+            #[test]
+            fn reorg() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let range = StarknetBlockNumber(10)..=StarknetBlockNumber(12);
+                RefsTable::set_backfill_range(&connection, Some(range.clone())).unwrap();
+
+                // A range below the tail is left untouched.
+                RefsTable::reorg(&connection, StarknetBlockNumber(13)).unwrap();
+                assert_eq!(
+                    RefsTable::get_backfill_range(&connection).unwrap(),
+                    Some(range)
+                );
+
+                RefsTable::reorg(&connection, StarknetBlockNumber(12)).unwrap();
+                assert_eq!(
+                    RefsTable::get_backfill_range(&connection).unwrap(),
+                    Some(StarknetBlockNumber(10)..=StarknetBlockNumber(11))
+                );
+
+                RefsTable::reorg(&connection, StarknetBlockNumber(10)).unwrap();
+                assert_eq!(RefsTable::get_backfill_range(&connection).unwrap(), None);
+            }
         }
     }
 
@@ -1379,6 +3274,55 @@ mod tests {
                     assert_eq!(latest, None);
                 }
             }
+
+            mod latest_minus {
+                use super::*;
+
+                fn get(offset: u64) -> Option<StarknetBlock> {
+                    let storage = Storage::in_memory().unwrap();
+                    let connection = storage.connection().unwrap();
+
+                    for block in create_blocks() {
+                        StarknetBlocksTable::insert(&connection, &block).unwrap();
+                    }
+
+                    StarknetBlocksTable::get(
+                        &connection,
+                        StarknetBlocksBlockId::LatestMinus(offset),
+                    )
+                    .unwrap()
+                }
+
+                #[test]
+                fn zero_is_latest() {
+                    assert_eq!(get(0), Some(create_blocks()[2].clone()));
+                }
+
+                #[test]
+                fn mid_chain() {
+                    assert_eq!(get(1), Some(create_blocks()[1].clone()));
+                    assert_eq!(get(2), Some(create_blocks()[0].clone()));
+                }
+
+                #[test]
+                fn beyond_genesis() {
+                    assert_eq!(get(3), None);
+                    assert_eq!(get(u64::MAX), None);
+                }
+
+                #[test]
+                fn empty() {
+                    let storage = Storage::in_memory().unwrap();
+                    let connection = storage.connection().unwrap();
+
+                    let block = StarknetBlocksTable::get(
+                        &connection,
+                        StarknetBlocksBlockId::LatestMinus(0),
+                    )
+                    .unwrap();
+                    assert_eq!(block, None);
+                }
+            }
         }
 
         mod get_root {
@@ -1500,11 +3444,11 @@ mod tests {
             }
         }
 
-        mod reorg {
+        mod get_by_root {
             use super::*;
 
             #[test]
-            fn full() {
+            fn distinct_roots() {
                 let storage = Storage::in_memory().unwrap();
                 let connection = storage.connection().unwrap();
 
@@ -1513,162 +3457,2051 @@ mod tests {
                     StarknetBlocksTable::insert(&connection, block).unwrap();
                 }
 
-                StarknetBlocksTable::reorg(&connection, StarknetBlockNumber::GENESIS).unwrap();
+                for block in blocks {
+                    let result = StarknetBlocksTable::get_by_root(&connection, block.root)
+                        .unwrap()
+                        .unwrap();
+
+                    assert_eq!(result, block);
+                }
+            }
+
+            #[test]
+            fn none() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let blocks = create_blocks();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
 
+                let non_existent = GlobalRoot(StarkHash::from_hex_str("0xdeadbeef").unwrap());
                 assert_eq!(
-                    StarknetBlocksTable::get(&connection, StarknetBlocksBlockId::Latest).unwrap(),
+                    StarknetBlocksTable::get_by_root(&connection, non_existent).unwrap(),
                     None
                 );
             }
 
             #[test]
-            fn partial() {
+            fn duplicate_root_returns_lowest_number() {
                 let storage = Storage::in_memory().unwrap();
                 let connection = storage.connection().unwrap();
 
-                let blocks = create_blocks();
+                let mut blocks = create_blocks();
+                blocks[2].root = blocks[1].root;
                 for block in &blocks {
                     StarknetBlocksTable::insert(&connection, block).unwrap();
                 }
 
-                let reorg_tail = blocks[1].number;
-                StarknetBlocksTable::reorg(&connection, reorg_tail).unwrap();
+                let result = StarknetBlocksTable::get_by_root(&connection, blocks[1].root)
+                    .unwrap()
+                    .unwrap();
 
-                let expected = StarknetBlock {
-                    number: blocks[0].number,
-                    hash: blocks[0].hash,
-                    root: blocks[0].root,
-                    timestamp: blocks[0].timestamp,
-                    gas_price: blocks[0].gas_price,
-                    sequencer_address: blocks[0].sequencer_address,
-                };
+                assert_eq!(result, blocks[1]);
+            }
 
-                assert_eq!(
-                    StarknetBlocksTable::get(&connection, StarknetBlocksBlockId::Latest).unwrap(),
-                    Some(expected)
-                );
+            #[test]
+            fn all_with_shared_root_ordered_by_number() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let mut blocks = create_blocks();
+                blocks[2].root = blocks[0].root;
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                let result =
+                    StarknetBlocksTable::get_all_by_root(&connection, blocks[0].root).unwrap();
+                assert_eq!(result, vec![blocks[0].clone(), blocks[2].clone()]);
+
+                let result =
+                    StarknetBlocksTable::get_all_by_root(&connection, blocks[1].root).unwrap();
+                assert_eq!(result, vec![blocks[1].clone()]);
+
+                let non_existent = GlobalRoot(StarkHash::from_hex_str("0xdeadbeef").unwrap());
+                let result =
+                    StarknetBlocksTable::get_all_by_root(&connection, non_existent).unwrap();
+                assert!(result.is_empty());
             }
         }
-    }
 
-    mod starknet_events {
-        use super::*;
+        mod duplicate_hashes {
+            use super::*;
 
-        use crate::core::EventData;
-        use crate::sequencer::reply::transaction;
+            #[test]
+            fn none() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
 
-        #[test]
-        fn event_data_serialization() {
-            let data = vec![
-                EventData(StarkHash::from_hex_str("0x1").unwrap()),
-                EventData(StarkHash::from_hex_str("0x2").unwrap()),
-                EventData(StarkHash::from_hex_str("0x3").unwrap()),
-            ];
-            assert_eq!(
-                &StarknetEventsTable::event_data_to_bytes(&data),
-                &[
-                    0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                    0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3
-                ]
-            );
+                for block in create_blocks() {
+                    StarknetBlocksTable::insert(&connection, &block).unwrap();
+                }
+
+                let duplicates = StarknetBlocksTable::find_duplicate_hashes(&connection).unwrap();
+                assert!(duplicates.is_empty());
+            }
+
+            #[test]
+            fn detected_and_resolved_to_lowest_number() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let mut blocks = create_blocks();
+                blocks[2].hash = blocks[0].hash;
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                let duplicates = StarknetBlocksTable::find_duplicate_hashes(&connection).unwrap();
+                assert_eq!(duplicates, vec![blocks[0].hash]);
+
+                let block = StarknetBlocksTable::get(&connection, blocks[0].hash.into()).unwrap();
+                assert_eq!(block, Some(blocks[0].clone()));
+
+                let root =
+                    StarknetBlocksTable::get_root(&connection, blocks[0].hash.into()).unwrap();
+                assert_eq!(root, Some(blocks[0].root));
+            }
         }
 
-        #[test]
-        fn event_keys_to_base64_strings() {
-            let event = transaction::Event {
-                from_address: ContractAddress::from_hex_str(
-                    "0x06fbd460228d843b7fbef670ff15607bf72e19fa94de21e29811ada167b4ca39",
+        mod get_state_head {
+            use super::*;
+
+            #[test]
+            fn without_backfill_is_latest() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                assert_eq!(
+                    StarknetBlocksTable::get_state_head(&connection).unwrap(),
+                    None
+                );
+
+                let blocks = create_blocks();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                let head = StarknetBlocksTable::get_state_head(&connection).unwrap();
+                assert_eq!(head.as_ref(), blocks.last());
+            }
+
+            #[test]
+            fn below_backfill_range() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let blocks = create_blocks();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                RefsTable::set_backfill_range(
+                    &connection,
+                    Some(blocks[2].number..=blocks[2].number),
                 )
-                .unwrap(),
-                data: vec![],
-                keys: vec![
-                    EventKey(StarkHash::from_hex_str("0x901823").unwrap()),
-                    EventKey(StarkHash::from_hex_str("0x901824").unwrap()),
-                    EventKey(StarkHash::from_hex_str("0x901825").unwrap()),
-                ],
-            };
-            assert_eq!(
-                StarknetEventsTable::event_keys_to_base64_strings(&event.keys),
-                "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCM= AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCQ= AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACQGCU="
-            );
+                .unwrap();
+                let head = StarknetBlocksTable::get_state_head(&connection).unwrap();
+                assert_eq!(head.as_ref(), Some(&blocks[1]));
+
+                RefsTable::set_backfill_range(
+                    &connection,
+                    Some(blocks[0].number..=blocks[2].number),
+                )
+                .unwrap();
+                let head = StarknetBlocksTable::get_state_head(&connection).unwrap();
+                assert_eq!(head, None);
+            }
         }
 
-        const NUM_BLOCKS: usize = 4;
+        mod get_ancestors {
+            use super::*;
 
-        fn create_blocks() -> [StarknetBlock; NUM_BLOCKS] {
-            crate::storage::test_utils::create_blocks::<NUM_BLOCKS>()
-        }
+            fn setup() -> (Storage, [StarknetBlock; 5]) {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
 
-        const TRANSACTIONS_PER_BLOCK: usize = 10;
-        const EVENTS_PER_BLOCK: usize = TRANSACTIONS_PER_BLOCK;
-        const NUM_TRANSACTIONS: usize = NUM_BLOCKS * TRANSACTIONS_PER_BLOCK;
-        const NUM_EVENTS: usize = NUM_BLOCKS * EVENTS_PER_BLOCK;
+                let blocks = crate::storage::test_utils::create_blocks::<5>();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
 
-        fn create_transactions_and_receipts(
-        ) -> [(transaction::Transaction, transaction::Receipt); NUM_TRANSACTIONS] {
-            crate::storage::test_utils::create_transactions_and_receipts::<NUM_TRANSACTIONS>()
+                (storage, blocks)
+            }
+
+            #[test]
+            fn walks_parent_links() {
+                let (storage, blocks) = setup();
+                let connection = storage.connection().unwrap();
+
+                let ancestors =
+                    StarknetBlocksTable::get_ancestors(&connection, blocks[4].hash, 3).unwrap();
+                assert_eq!(
+                    ancestors,
+                    vec![blocks[3].clone(), blocks[2].clone(), blocks[1].clone()]
+                );
+            }
+
+            #[test]
+            fn stops_at_genesis() {
+                let (storage, blocks) = setup();
+                let connection = storage.connection().unwrap();
+
+                let ancestors =
+                    StarknetBlocksTable::get_ancestors(&connection, blocks[2].hash, 10).unwrap();
+                assert_eq!(ancestors, vec![blocks[1].clone(), blocks[0].clone()]);
+
+                let ancestors =
+                    StarknetBlocksTable::get_ancestors(&connection, blocks[0].hash, 10).unwrap();
+                assert!(ancestors.is_empty());
+            }
+
+            #[test]
+            fn unknown_block() {
+                let (storage, _) = setup();
+                let connection = storage.connection().unwrap();
+
+                let unknown = StarknetBlockHash(StarkHash::from_hex_str("0xdead").unwrap());
+                let ancestors =
+                    StarknetBlocksTable::get_ancestors(&connection, unknown, 10).unwrap();
+                assert!(ancestors.is_empty());
+            }
+        }
+
+        mod highest_contiguous_from_genesis {
+            use super::*;
+
+            #[test]
+            fn empty() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let highest =
+                    StarknetBlocksTable::highest_contiguous_from_genesis(&connection).unwrap();
+                assert_eq!(highest, None);
+            }
+
+            #[test]
+            fn contiguous() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let blocks = create_blocks();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                let highest =
+                    StarknetBlocksTable::highest_contiguous_from_genesis(&connection).unwrap();
+                assert_eq!(highest, Some(blocks.last().unwrap().number));
+            }
+
+            #[test]
+            fn gapped() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let blocks = crate::storage::test_utils::create_blocks::<6>();
+                for i in [0, 1, 3, 5] {
+                    StarknetBlocksTable::insert(&connection, &blocks[i]).unwrap();
+                }
+
+                let highest =
+                    StarknetBlocksTable::highest_contiguous_from_genesis(&connection).unwrap();
+                assert_eq!(highest, Some(StarknetBlockNumber(1)));
+                assert_eq!(
+                    StarknetBlocksTable::get_latest_number(&connection).unwrap(),
+                    Some(StarknetBlockNumber(5))
+                );
+            }
+
+            #[test]
+            fn genesis_missing() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let blocks = create_blocks();
+                for block in &blocks[1..] {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                let highest =
+                    StarknetBlocksTable::highest_contiguous_from_genesis(&connection).unwrap();
+                assert_eq!(highest, None);
+            }
+        }
+
+        mod get_by_timestamp {
+            use super::*;
+
+            /// Blocks with timestamps 500, 510, 510 and 530.
+            fn setup() -> (Storage, [StarknetBlock; 4]) {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let mut blocks = crate::storage::test_utils::create_blocks::<4>();
+                for (block, timestamp) in blocks.iter_mut().zip([500, 510, 510, 530]) {
+                    block.timestamp = StarknetBlockTimestamp(timestamp);
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                (storage, blocks)
+            }
+
+            #[test]
+            fn exact_match() {
+                let (storage, blocks) = setup();
+                let connection = storage.connection().unwrap();
+
+                for direction in [TimestampDirection::Before, TimestampDirection::After] {
+                    let block = StarknetBlocksTable::get_by_timestamp(
+                        &connection,
+                        StarknetBlockTimestamp(530),
+                        direction,
+                    )
+                    .unwrap();
+                    assert_eq!(block, Some(blocks[3].clone()));
+                }
+
+                // Ties are resolved towards the searched direction's far end.
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(510),
+                    TimestampDirection::Before,
+                )
+                .unwrap();
+                assert_eq!(block, Some(blocks[2].clone()));
+
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(510),
+                    TimestampDirection::After,
+                )
+                .unwrap();
+                assert_eq!(block, Some(blocks[1].clone()));
+            }
+
+            #[test]
+            fn between_blocks() {
+                let (storage, blocks) = setup();
+                let connection = storage.connection().unwrap();
+
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(520),
+                    TimestampDirection::Before,
+                )
+                .unwrap();
+                assert_eq!(block, Some(blocks[2].clone()));
+
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(520),
+                    TimestampDirection::After,
+                )
+                .unwrap();
+                assert_eq!(block, Some(blocks[3].clone()));
+            }
+
+            #[test]
+            fn before_genesis() {
+                let (storage, blocks) = setup();
+                let connection = storage.connection().unwrap();
+
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(100),
+                    TimestampDirection::Before,
+                )
+                .unwrap();
+                assert_eq!(block, None);
+
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(100),
+                    TimestampDirection::After,
+                )
+                .unwrap();
+                assert_eq!(block, Some(blocks[0].clone()));
+            }
+
+            #[test]
+            fn after_head() {
+                let (storage, blocks) = setup();
+                let connection = storage.connection().unwrap();
+
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(1000),
+                    TimestampDirection::Before,
+                )
+                .unwrap();
+                assert_eq!(block, Some(blocks[3].clone()));
+
+                let block = StarknetBlocksTable::get_by_timestamp(
+                    &connection,
+                    StarknetBlockTimestamp(1000),
+                    TimestampDirection::After,
+                )
+                .unwrap();
+                assert_eq!(block, None);
+            }
+        }
+
+        mod block_id_from_str {
+            use super::*;
+
+            #[test]
+            fn number() {
+                assert_eq!(
+                    "123".parse::<StarknetBlocksBlockId>().unwrap(),
+                    StarknetBlocksBlockId::Number(StarknetBlockNumber(123))
+                );
+            }
+
+            #[test]
+            fn hash() {
+                assert_eq!(
+                    "0xabc".parse::<StarknetBlocksBlockId>().unwrap(),
+                    StarknetBlocksBlockId::Hash(StarknetBlockHash(
+                        StarkHash::from_hex_str("abc").unwrap()
+                    ))
+                );
+            }
+
+            #[test]
+            fn latest() {
+                assert_eq!(
+                    "latest".parse::<StarknetBlocksBlockId>().unwrap(),
+                    StarknetBlocksBlockId::Latest
+                );
+            }
+
+            #[test]
+            fn latest_minus() {
+                assert_eq!(
+                    "latest-10".parse::<StarknetBlocksBlockId>().unwrap(),
+                    StarknetBlocksBlockId::LatestMinus(10)
+                );
+            }
+
+            #[test]
+            fn pending() {
+                assert_eq!(
+                    "pending".parse::<StarknetBlocksBlockId>().unwrap_err(),
+                    ParseBlockIdError::Pending
+                );
+            }
+
+            #[test]
+            fn invalid() {
+                for input in [
+                    "",
+                    "0x",
+                    "-1",
+                    "abc",
+                    "Latest",
+                    "0xzz",
+                    "1.5",
+                    "latest-",
+                    "latest--1",
+                    "latest+1",
+                ] {
+                    input.parse::<StarknetBlocksBlockId>().unwrap_err();
+                }
+            }
+        }
+
+        mod reorg {
+            use super::*;
+
+            #[test]
+            fn full() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let blocks = create_blocks();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                StarknetBlocksTable::reorg(&connection, StarknetBlockNumber::GENESIS).unwrap();
+
+                assert_eq!(
+                    StarknetBlocksTable::get(&connection, StarknetBlocksBlockId::Latest).unwrap(),
+                    None
+                );
+            }
+
+            #[test]
+            fn partial() {
+                let storage = Storage::in_memory().unwrap();
+                let connection = storage.connection().unwrap();
+
+                let blocks = create_blocks();
+                for block in &blocks {
+                    StarknetBlocksTable::insert(&connection, block).unwrap();
+                }
+
+                let reorg_tail = blocks[1].number;
+                StarknetBlocksTable::reorg(&connection, reorg_tail).unwrap();
+
+                let expected = StarknetBlock {
+                    number: blocks[0].number,
+                    hash: blocks[0].hash,
+                    root: blocks[0].root,
+                    timestamp: blocks[0].timestamp,
+                    gas_price: blocks[0].gas_price,
+                    sequencer_address: blocks[0].sequencer_address,
+                    parent_hash: blocks[0].parent_hash,
+                };
+
+                assert_eq!(
+                    StarknetBlocksTable::get(&connection, StarknetBlocksBlockId::Latest).unwrap(),
+                    Some(expected)
+                );
+            }
+        }
+
+        #[test]
+        fn get_range_with_tx_counts() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            // The middle block has no transactions.
+            let blocks = create_blocks();
+            let transactions = crate::storage::test_utils::create_transactions_and_receipts::<5>();
+            let chunks = [&transactions[..3], &[][..], &transactions[3..]];
+            for (block, chunk) in blocks.iter().zip(chunks) {
+                StarknetBlocksTable::insert(&connection, block).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &connection,
+                    block.hash,
+                    block.number,
+                    chunk,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            let range = StarknetBlocksTable::get_range_with_tx_counts(
+                &connection,
+                StarknetBlockNumber::GENESIS,
+                blocks[2].number,
+            )
+            .unwrap();
+
+            let expected = blocks
+                .iter()
+                .map(|block| {
+                    let count = StarknetTransactionsTable::get_transaction_count(
+                        &connection,
+                        block.hash.into(),
+                    )
+                    .unwrap()
+                    .unwrap();
+                    (block.clone(), count)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(range, expected);
+            assert_eq!(
+                range.iter().map(|(_, count)| *count).collect::<Vec<_>>(),
+                vec![3, 0, 2]
+            );
+
+            // The range is inclusive and may extend past the latest block.
+            let tail = StarknetBlocksTable::get_range_with_tx_counts(
+                &connection,
+                blocks[1].number,
+                StarknetBlockNumber(100),
+            )
+            .unwrap();
+            assert_eq!(tail, expected[1..]);
+        }
+
+        #[test]
+        fn get_range_with_tx_counts_empty_genesis() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            // Only the middle block has transactions.
+            let blocks = create_blocks();
+            let transactions = crate::storage::test_utils::create_transactions_and_receipts::<2>();
+            let chunks = [&[][..], &transactions[..], &[][..]];
+            for (block, chunk) in blocks.iter().zip(chunks) {
+                StarknetBlocksTable::insert(&connection, block).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &connection,
+                    block.hash,
+                    block.number,
+                    chunk,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            let genesis = StarknetBlocksTable::get_range_with_tx_counts(
+                &connection,
+                StarknetBlockNumber::GENESIS,
+                StarknetBlockNumber::GENESIS,
+            )
+            .unwrap();
+            assert_eq!(genesis, vec![(blocks[0].clone(), 0)]);
+
+            let range = StarknetBlocksTable::get_range_with_tx_counts(
+                &connection,
+                StarknetBlockNumber::GENESIS,
+                blocks[2].number,
+            )
+            .unwrap();
+            assert_eq!(
+                range.iter().map(|(_, count)| *count).collect::<Vec<_>>(),
+                vec![0, 2, 0]
+            );
+        }
+    }
+
+    mod starknet_state_updates {
+        use super::*;
+        use crate::core::{ClassHash, StorageAddress, StorageValue};
+        use crate::ethereum::state_update::{
+            ContractUpdate, DeployedContract, StateUpdate, StorageUpdate,
+        };
+
+        fn create_update(i: u8) -> StateUpdate {
+            let address = ContractAddress(StarkHash::from_be_slice(&[i]).unwrap());
+            StateUpdate {
+                deployed_contracts: vec![DeployedContract {
+                    address,
+                    hash: ClassHash(StarkHash::from_be_slice(&[i, i]).unwrap()),
+                    call_data: vec![],
+                }],
+                contract_updates: vec![ContractUpdate {
+                    address,
+                    storage_updates: vec![StorageUpdate {
+                        address: StorageAddress(StarkHash::from_be_slice(&[1]).unwrap()),
+                        value: StorageValue(StarkHash::from_be_slice(&[i + 1]).unwrap()),
+                    }],
+                }],
+            }
+        }
+
+        fn setup(connection: &Connection) -> Vec<StateUpdate> {
+            crate::storage::test_utils::create_blocks::<3>()
+                .iter()
+                .zip(0u8..)
+                .map(|(block, i)| {
+                    StarknetBlocksTable::insert(connection, block).unwrap();
+                    let update = create_update(i);
+                    StarknetStateUpdatesTable::insert(
+                        connection,
+                        block.number,
+                        &update,
+                        &StorageLimits::default(),
+                    )
+                    .unwrap();
+                    update
+                })
+                .collect()
+        }
+
+        #[test]
+        fn get_range() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            let updates = setup(&connection);
+
+            let result = StarknetStateUpdatesTable::get_range(
+                &connection,
+                StarknetBlockNumber(1),
+                StarknetBlockNumber(2),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(result, Some(updates[1..].to_vec()));
+        }
+
+        #[test]
+        fn get_range_with_missing_update() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            setup(&connection);
+
+            connection
+                .execute(
+                    "DELETE FROM starknet_state_updates WHERE block_number = 1",
+                    [],
+                )
+                .unwrap();
+
+            let result = StarknetStateUpdatesTable::get_range(
+                &connection,
+                StarknetBlockNumber(0),
+                StarknetBlockNumber(2),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(result, None);
+        }
+
+        #[test]
+        fn deleted_on_reorg() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            setup(&connection);
+
+            StarknetBlocksTable::reorg(&connection, StarknetBlockNumber(1)).unwrap();
+
+            let count: usize = connection
+                .query_row("SELECT count(1) FROM starknet_state_updates", [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(count, 1);
+        }
+
+        #[test]
+        fn only_latest_are_kept() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            let updates = setup(&connection);
+
+            // Re-insert the latest state update, keeping only the two latest ones.
+            connection
+                .execute(
+                    "DELETE FROM starknet_state_updates WHERE block_number = 2",
+                    [],
+                )
+                .unwrap();
+            StarknetStateUpdatesTable::insert_with_retention(
+                &connection,
+                StarknetBlockNumber(2),
+                &updates[2],
+                2,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+
+            let result = StarknetStateUpdatesTable::get_range(
+                &connection,
+                StarknetBlockNumber(1),
+                StarknetBlockNumber(2),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(result, Some(updates[1..].to_vec()));
+
+            let result = StarknetStateUpdatesTable::get_range(
+                &connection,
+                StarknetBlockNumber(0),
+                StarknetBlockNumber(2),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(result, None);
+        }
+    }
+
+    mod declared_classes {
+        use super::*;
+        use crate::storage::test_utils;
+
+        /// Inserts three blocks of three transactions each, where the first and last transactions
+        /// of the first two blocks are declarations.
+        fn setup(connection: &Connection) -> Vec<DeclaredClass> {
+            let blocks = test_utils::create_blocks::<3>();
+            let mut transactions = test_utils::create_transactions_and_receipts::<9>();
+
+            let mut declared = Vec::new();
+            for i in [0, 2, 3, 5] {
+                let class_hash = ClassHash(StarkHash::from_hex_str(&"c".repeat(i + 3)).unwrap());
+                let tx = &mut transactions[i].0;
+                tx.r#type = transaction::Type::Declare;
+                tx.class_hash = Some(class_hash);
+                declared.push(DeclaredClass {
+                    class_hash,
+                    block_number: blocks[i / 3].number,
+                    transaction_hash: tx.transaction_hash,
+                });
+            }
+            // Deploy transactions carry the class hash, but do not declare it.
+            transactions[1].0.r#type = transaction::Type::Deploy;
+            transactions[1].0.class_hash = Some(declared[0].class_hash);
+
+            for (block, chunk) in blocks.iter().zip(transactions.chunks(3)) {
+                StarknetBlocksTable::insert(connection, block).unwrap();
+                StarknetTransactionsTable::upsert(
+                    connection,
+                    block.hash,
+                    block.number,
+                    chunk,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            declared
+        }
+
+        #[test]
+        fn get_for_block() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let declared = setup(&connection);
+
+            for (number, expected) in [(0, &declared[..2]), (1, &declared[2..]), (2, &[][..])] {
+                let classes =
+                    DeclaredClassesTable::get_for_block(&connection, StarknetBlockNumber(number))
+                        .unwrap();
+                assert_eq!(classes, expected, "block {number}");
+            }
+        }
+
+        #[test]
+        fn get_declaration() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let declared = setup(&connection);
+
+            let declaration =
+                DeclaredClassesTable::get_declaration(&connection, declared[2].class_hash).unwrap();
+            assert_eq!(declaration, Some(declared[2].clone()));
+
+            let undeclared = ClassHash(StarkHash::from_hex_str("123").unwrap());
+            let declaration =
+                DeclaredClassesTable::get_declaration(&connection, undeclared).unwrap();
+            assert_eq!(declaration, None);
+        }
+
+        #[test]
+        fn reorg() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let declared = setup(&connection);
+
+            StarknetBlocksTable::reorg(&connection, StarknetBlockNumber(1)).unwrap();
+
+            let classes =
+                DeclaredClassesTable::get_for_block(&connection, StarknetBlockNumber(1)).unwrap();
+            assert_eq!(classes, vec![]);
+            let declaration =
+                DeclaredClassesTable::get_declaration(&connection, declared[2].class_hash).unwrap();
+            assert_eq!(declaration, None);
+
+            let classes =
+                DeclaredClassesTable::get_for_block(&connection, StarknetBlockNumber(0)).unwrap();
+            assert_eq!(classes, &declared[..2]);
+        }
+    }
+
+    mod starknet_transactions {
+        use super::*;
+        use crate::storage::{test_utils, BlobError, DEFAULT_MAX_BLOB_SIZE};
+
+        #[test]
+        fn get_latest_transactions() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            const TRANSACTIONS_PER_BLOCK: usize = 3;
+            let blocks = test_utils::create_blocks::<3>();
+            let transactions = test_utils::create_transactions_and_receipts::<9>();
+            for (block, chunk) in blocks
+                .iter()
+                .zip(transactions.chunks(TRANSACTIONS_PER_BLOCK))
+            {
+                StarknetBlocksTable::insert(&connection, block).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &connection,
+                    block.hash,
+                    block.number,
+                    chunk,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            let expected = transactions
+                .iter()
+                .enumerate()
+                .rev()
+                .take(4)
+                .map(|(i, (tx, _))| (tx.clone(), blocks[i / TRANSACTIONS_PER_BLOCK].number))
+                .collect::<Vec<_>>();
+
+            let latest = StarknetTransactionsTable::get_latest_transactions(
+                &connection,
+                4,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(latest, expected);
+
+            // Asking for more than there are returns all of them.
+            let all = StarknetTransactionsTable::get_latest_transactions(
+                &connection,
+                100,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(all.len(), transactions.len());
+
+            let none = StarknetTransactionsTable::get_latest_transactions(
+                &connection,
+                0,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert!(none.is_empty());
+        }
+
+        #[test]
+        fn get_transaction_count() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            // Nothing is known about an empty chain.
+            let count = StarknetTransactionsTable::get_transaction_count(
+                &connection,
+                StarknetBlocksBlockId::Latest,
+            )
+            .unwrap();
+            assert_eq!(count, None);
+
+            // Genesis and the latest block are empty.
+            let blocks = test_utils::create_blocks::<3>();
+            let transactions = test_utils::create_transactions_and_receipts::<2>();
+            let chunks = [&[][..], &transactions[..], &[][..]];
+            for (block, chunk) in blocks.iter().zip(chunks) {
+                StarknetBlocksTable::insert(&connection, block).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &connection,
+                    block.hash,
+                    block.number,
+                    chunk,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            for (block, expected) in blocks.iter().zip([0, 2, 0]) {
+                for id in [block.number.into(), block.hash.into()] {
+                    let count =
+                        StarknetTransactionsTable::get_transaction_count(&connection, id).unwrap();
+                    assert_eq!(count, Some(expected), "{:?}", id);
+                }
+            }
+            let latest = StarknetTransactionsTable::get_transaction_count(
+                &connection,
+                StarknetBlocksBlockId::Latest,
+            )
+            .unwrap();
+            assert_eq!(latest, Some(0));
+            let below_latest = StarknetTransactionsTable::get_transaction_count(
+                &connection,
+                StarknetBlocksBlockId::LatestMinus(1),
+            )
+            .unwrap();
+            assert_eq!(below_latest, Some(2));
+
+            let unknown = StarknetTransactionsTable::get_transaction_count(
+                &connection,
+                StarknetBlockNumber(3).into(),
+            )
+            .unwrap();
+            assert_eq!(unknown, None);
+        }
+
+        #[test]
+        fn execution_status_round_trip() {
+            use transaction::ExecutionStatus;
+
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let block = test_utils::create_blocks::<1>()[0].clone();
+            let mut transactions = test_utils::create_transactions_and_receipts::<3>();
+            transactions[1].1.execution_status = Some(ExecutionStatus::Succeeded);
+            transactions[2].1.execution_status = Some(ExecutionStatus::Reverted);
+            StarknetBlocksTable::insert(&connection, &block).unwrap();
+            StarknetTransactionsTable::upsert(
+                &connection,
+                block.hash,
+                block.number,
+                &transactions,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+
+            for (transaction, receipt) in &transactions {
+                let (stored, _) = StarknetTransactionsTable::get_receipt(
+                    &connection,
+                    transaction.transaction_hash,
+                    &StorageLimits::default(),
+                )
+                .unwrap()
+                .unwrap();
+                assert_eq!(&stored, receipt);
+            }
+        }
+
+        #[test]
+        fn transaction_count_is_bounded() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let blocks = test_utils::create_blocks::<2>();
+            let transactions = test_utils::create_transactions_and_receipts::<7>();
+            let (at_limit, over_limit) = transactions.split_at(3);
+            let limits = StorageLimits {
+                max_block_transactions: 3,
+                ..Default::default()
+            };
+            for block in &blocks {
+                StarknetBlocksTable::insert(&connection, block).unwrap();
+            }
+
+            StarknetTransactionsTable::upsert(
+                &connection,
+                blocks[0].hash,
+                blocks[0].number,
+                at_limit,
+                &limits,
+            )
+            .unwrap();
+            let stored = StarknetTransactionsTable::get_transaction_data_for_block(
+                &connection,
+                blocks[0].number.into(),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(stored, at_limit);
+
+            let error = StarknetTransactionsTable::upsert(
+                &connection,
+                blocks[1].hash,
+                blocks[1].number,
+                over_limit,
+                &limits,
+            )
+            .unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<TransactionsError>(),
+                Some(&TransactionsError::TooManyTransactions { count: 4, limit: 3 })
+            );
+            let stored = StarknetTransactionsTable::get_transaction_data_for_block(
+                &connection,
+                blocks[1].number.into(),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert!(stored.is_empty());
+        }
+
+        #[test]
+        fn oversized_blob_fails_cleanly() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let block = test_utils::create_blocks::<1>()[0].clone();
+            let transactions = test_utils::create_transactions_and_receipts::<2>();
+            StarknetBlocksTable::insert(&connection, &block).unwrap();
+            StarknetTransactionsTable::upsert(
+                &connection,
+                block.hash,
+                block.number,
+                &transactions,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+
+            // A tiny blob which decompresses to just beyond the limit.
+            let bomb = zstd::bulk::compress(&vec![0u8; DEFAULT_MAX_BLOB_SIZE + 1], 10).unwrap();
+            let bomb_hash = transactions[0].0.transaction_hash;
+            connection
+                .execute(
+                    "UPDATE starknet_transactions SET tx = ?, receipt = ? WHERE hash = ?",
+                    params![&bomb, &bomb, bomb_hash.0.as_be_bytes()],
+                )
+                .unwrap();
+
+            let error = StarknetTransactionsTable::get_transaction(
+                &connection,
+                bomb_hash,
+                &StorageLimits::default(),
+            )
+            .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<BlobError>(),
+                Some(BlobError::TooLarge { .. })
+            ));
+            StarknetTransactionsTable::get_receipt(
+                &connection,
+                bomb_hash,
+                &StorageLimits::default(),
+            )
+            .unwrap_err();
+
+            // The neighbouring row is unaffected.
+            let neighbour = &transactions[1];
+            assert_eq!(
+                StarknetTransactionsTable::get_transaction(
+                    &connection,
+                    neighbour.0.transaction_hash,
+                    &StorageLimits::default()
+                )
+                .unwrap(),
+                Some(neighbour.0.clone())
+            );
+            assert_eq!(
+                StarknetTransactionsTable::get_receipt(
+                    &connection,
+                    neighbour.0.transaction_hash,
+                    &StorageLimits::default()
+                )
+                .unwrap(),
+                Some((neighbour.1.clone(), block.hash))
+            );
+        }
+
+        #[test]
+        fn legacy_json_is_read_and_repacked() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let block = test_utils::create_blocks::<1>()[0].clone();
+            let transactions = test_utils::create_transactions_and_receipts::<3>();
+            StarknetBlocksTable::insert(&connection, &block).unwrap();
+            StarknetTransactionsTable::upsert(
+                &connection,
+                block.hash,
+                block.number,
+                &transactions,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+
+            let stored_blobs = || {
+                connection
+                    .prepare("SELECT tx, receipt FROM starknet_transactions ORDER BY idx")
+                    .unwrap()
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .unwrap()
+                    .map(|blobs| {
+                        let (tx, receipt): (Vec<u8>, Vec<u8>) = blobs.unwrap();
+                        (
+                            blob::decompress(&tx, DEFAULT_MAX_BLOB_SIZE).unwrap(),
+                            blob::decompress(&receipt, DEFAULT_MAX_BLOB_SIZE).unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert!(stored_blobs()
+                .iter()
+                .all(|(tx, receipt)| packed::is_packed(tx) && packed::is_packed(receipt)));
+
+            // Store the first two rows as JSON, like older versions did.
+            for (transaction, receipt) in &transactions[..2] {
+                let tx = serde_json::to_vec(transaction).unwrap();
+                let receipt_json = serde_json::to_vec(receipt).unwrap();
+                connection
+                    .execute(
+                        "UPDATE starknet_transactions SET tx = ?, receipt = ? WHERE hash = ?",
+                        params![
+                            zstd::bulk::compress(&tx, 10).unwrap(),
+                            zstd::bulk::compress(&receipt_json, 10).unwrap(),
+                            transaction.transaction_hash.0.as_be_bytes()
+                        ],
+                    )
+                    .unwrap();
+            }
+
+            let read = || {
+                StarknetTransactionsTable::get_transaction_data_for_block(
+                    &connection,
+                    block.number.into(),
+                    &StorageLimits::default(),
+                )
+                .unwrap()
+            };
+            assert_eq!(read(), transactions.to_vec());
+
+            assert_eq!(
+                StarknetTransactionsTable::repack(&connection, &StorageLimits::default()).unwrap(),
+                2
+            );
+            assert!(stored_blobs()
+                .iter()
+                .all(|(tx, receipt)| packed::is_packed(tx) && packed::is_packed(receipt)));
+            assert_eq!(read(), transactions.to_vec());
+
+            // Nothing is left to repack.
+            assert_eq!(
+                StarknetTransactionsTable::repack(&connection, &StorageLimits::default()).unwrap(),
+                0
+            );
+        }
+    }
+
+    mod starknet_events {
+        use super::*;
+
+        use crate::core::EventData;
+        use crate::sequencer::reply::transaction;
+
+        #[test]
+        fn event_data_serialization() {
+            let data = vec![
+                EventData(StarkHash::from_hex_str("0x1").unwrap()),
+                EventData(StarkHash::from_hex_str("0x2").unwrap()),
+                EventData(StarkHash::from_hex_str("0x3").unwrap()),
+            ];
+            assert_eq!(
+                &StarknetEventsTable::event_data_to_bytes(&data),
+                &[
+                    0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                    0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3
+                ]
+            );
+        }
+
+        #[test]
+        fn event_keys_to_hex_strings() {
+            let event = transaction::Event {
+                from_address: ContractAddress::from_hex_str(
+                    "0x06fbd460228d843b7fbef670ff15607bf72e19fa94de21e29811ada167b4ca39",
+                )
+                .unwrap(),
+                data: vec![],
+                keys: vec![
+                    EventKey(StarkHash::from_hex_str("0x901823").unwrap()),
+                    EventKey(StarkHash::from_hex_str("0x901824").unwrap()),
+                    EventKey(StarkHash::from_hex_str("0x901825").unwrap()),
+                ],
+            };
+            assert_eq!(
+                StarknetEventsTable::event_keys_to_hex_strings(&event.keys),
+                concat!(
+                    "0000000000000000000000000000000000000000000000000000000000901823 ",
+                    "0000000000000000000000000000000000000000000000000000000000901824 ",
+                    "0000000000000000000000000000000000000000000000000000000000901825"
+                )
+            );
+        }
+
+        const NUM_BLOCKS: usize = 4;
+
+        fn create_blocks() -> [StarknetBlock; NUM_BLOCKS] {
+            crate::storage::test_utils::create_blocks::<NUM_BLOCKS>()
+        }
+
+        const TRANSACTIONS_PER_BLOCK: usize = 10;
+        const EVENTS_PER_BLOCK: usize = TRANSACTIONS_PER_BLOCK;
+        const NUM_TRANSACTIONS: usize = NUM_BLOCKS * TRANSACTIONS_PER_BLOCK;
+        const NUM_EVENTS: usize = NUM_BLOCKS * EVENTS_PER_BLOCK;
+
+        fn create_transactions_and_receipts(
+        ) -> [(transaction::Transaction, transaction::Receipt); NUM_TRANSACTIONS] {
+            crate::storage::test_utils::create_transactions_and_receipts::<NUM_TRANSACTIONS>()
         }
 
         fn setup(connection: &Connection) -> Vec<StarknetEmittedEvent> {
             let blocks = create_blocks();
             let transactions_and_receipts = create_transactions_and_receipts();
 
-            for (i, block) in blocks.iter().enumerate() {
-                StarknetBlocksTable::insert(connection, block).unwrap();
-                StarknetTransactionsTable::upsert(
-                    connection,
-                    block.hash,
-                    block.number,
-                    &transactions_and_receipts
-                        [i * TRANSACTIONS_PER_BLOCK..(i + 1) * TRANSACTIONS_PER_BLOCK],
+            for (i, block) in blocks.iter().enumerate() {
+                StarknetBlocksTable::insert(connection, block).unwrap();
+                StarknetTransactionsTable::upsert(
+                    connection,
+                    block.hash,
+                    block.number,
+                    &transactions_and_receipts
+                        [i * TRANSACTIONS_PER_BLOCK..(i + 1) * TRANSACTIONS_PER_BLOCK],
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            transactions_and_receipts
+                .iter()
+                .enumerate()
+                .map(|(i, (txn, receipt))| {
+                    let event = &receipt.events[0];
+                    let block = &blocks[i / TRANSACTIONS_PER_BLOCK];
+
+                    StarknetEmittedEvent {
+                        data: event.data.clone(),
+                        from_address: event.from_address,
+                        keys: event.keys.clone(),
+                        block_hash: block.hash,
+                        block_number: block.number,
+                        transaction_hash: txn.transaction_hash,
+                    }
+                })
+                .collect()
+        }
+
+        #[test]
+        fn get_events_with_fully_specified_filter() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            let expected_event = &emitted_events[1];
+            let filter = StarknetEventFilter {
+                from_block: Some(expected_event.block_number),
+                to_block: Some(expected_event.block_number),
+                block_hash: None,
+                contract_address: Some(expected_event.from_address),
+                // we're using a key which is present in _all_ events
+                keys: vec![EventKey(StarkHash::from_hex_str("deadbeef").unwrap())],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: vec![expected_event.clone()],
+                    is_last_page: true
+                }
+            );
+        }
+
+        #[test]
+        fn get_events_spanning_empty_blocks() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            // Genesis and block 2 are empty.
+            let blocks = create_blocks();
+            let transactions_and_receipts = create_transactions_and_receipts();
+            let chunks = [
+                &[][..],
+                &transactions_and_receipts[..TRANSACTIONS_PER_BLOCK],
+                &[][..],
+                &transactions_and_receipts[TRANSACTIONS_PER_BLOCK..2 * TRANSACTIONS_PER_BLOCK],
+            ];
+            for (block, chunk) in blocks.iter().zip(chunks) {
+                StarknetBlocksTable::insert(&connection, block).unwrap();
+                StarknetTransactionsTable::upsert(
+                    &connection,
+                    block.hash,
+                    block.number,
+                    chunk,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            let filter =
+                |from: u64, to: u64, page_size: usize, page_number: usize| StarknetEventFilter {
+                    from_block: Some(StarknetBlockNumber(from)),
+                    to_block: Some(StarknetBlockNumber(to)),
+                    block_hash: None,
+                    contract_address: None,
+                    keys: vec![],
+                    data_contains: vec![],
+                    include_reverted: true,
+                    page_size,
+                    page_number,
+                };
+            let events_of = |block: &StarknetBlock| {
+                StarknetEventsTable::get_events(
+                    &connection,
+                    &filter(block.number.0, block.number.0, NUM_EVENTS, 0),
+                    &StorageLimits::default(),
+                )
+                .unwrap()
+                .events
+            };
+            let empty = PageOfEvents {
+                events: vec![],
+                is_last_page: true,
+            };
+
+            // An empty block behaves like a block which does not exist.
+            for (from, to) in [(0, 0), (2, 2), (10, 10)] {
+                let page = StarknetEventsTable::get_events(
+                    &connection,
+                    &filter(from, to, NUM_EVENTS, 0),
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+                assert_eq!(page, empty, "{}..={}", from, to);
+            }
+
+            // Ranges starting or ending in an empty block only contain the other blocks' events.
+            let page = StarknetEventsTable::get_events(
+                &connection,
+                &filter(0, 2, NUM_EVENTS, 0),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                page,
+                PageOfEvents {
+                    events: events_of(&blocks[1]),
+                    is_last_page: true,
+                }
+            );
+
+            // Page boundaries fall on the events, regardless of the empty blocks in between.
+            let first = StarknetEventsTable::get_events(
+                &connection,
+                &filter(0, 3, EVENTS_PER_BLOCK, 0),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                first,
+                PageOfEvents {
+                    events: events_of(&blocks[1]),
+                    is_last_page: false,
+                }
+            );
+            let second = StarknetEventsTable::get_events(
+                &connection,
+                &filter(0, 3, EVENTS_PER_BLOCK, 1),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                second,
+                PageOfEvents {
+                    events: events_of(&blocks[3]),
+                    is_last_page: true,
+                }
+            );
+            let past_the_end = StarknetEventsTable::get_events(
+                &connection,
+                &filter(0, 3, EVENTS_PER_BLOCK, 2),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(past_the_end, empty);
+        }
+
+        #[test]
+        fn get_events_for_block_transaction() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+            let blocks = create_blocks();
+
+            // Each transaction emits a single event.
+            let tx_index = 3;
+            let expected = &emitted_events[TRANSACTIONS_PER_BLOCK + tx_index];
+            for block in [
+                StarknetBlocksBlockId::Number(blocks[1].number),
+                StarknetBlocksBlockId::Hash(blocks[1].hash),
+            ] {
+                let events = StarknetEventsTable::get_events_for_block_transaction(
+                    &connection,
+                    block,
+                    tx_index,
+                )
+                .unwrap();
+                assert_eq!(events, vec![expected.clone()]);
+            }
+
+            let latest = StarknetEventsTable::get_events_for_block_transaction(
+                &connection,
+                StarknetBlocksBlockId::Latest,
+                0,
+            )
+            .unwrap();
+            assert_eq!(
+                latest,
+                vec![emitted_events[(NUM_BLOCKS - 1) * TRANSACTIONS_PER_BLOCK].clone()]
+            );
+
+            // Missing transaction or block.
+            let events = StarknetEventsTable::get_events_for_block_transaction(
+                &connection,
+                StarknetBlocksBlockId::Number(blocks[1].number),
+                TRANSACTIONS_PER_BLOCK,
+            )
+            .unwrap();
+            assert!(events.is_empty());
+            let events = StarknetEventsTable::get_events_for_block_transaction(
+                &connection,
+                StarknetBlocksBlockId::Number(StarknetBlockNumber(NUM_BLOCKS as u64)),
+                0,
+            )
+            .unwrap();
+            assert!(events.is_empty());
+        }
+
+        #[test]
+        fn stream_all() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            setup(&connection);
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            let expected =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap()
+                    .events;
+            assert_eq!(expected.len(), NUM_EVENTS);
+
+            // Batch sizes which do and do not divide the number of events, and one that
+            // exceeds it.
+            for batch_size in [1, 3, EVENTS_PER_BLOCK, NUM_EVENTS + 1] {
+                let mut streamed = Vec::new();
+                let count = StarknetEventsTable::stream_all(&connection, batch_size, |event| {
+                    streamed.push(event);
+                    Ok(())
+                })
+                .unwrap();
+
+                assert_eq!(count, NUM_EVENTS, "batch size {}", batch_size);
+                assert_eq!(streamed, expected, "batch size {}", batch_size);
+            }
+        }
+
+        #[test]
+        fn stream_all_stops_on_callback_error() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            setup(&connection);
+
+            let mut calls = 0;
+            StarknetEventsTable::stream_all(&connection, 3, |_| {
+                calls += 1;
+                if calls == 5 {
+                    anyhow::bail!("Stop");
+                }
+                Ok(())
+            })
+            .unwrap_err();
+            assert_eq!(calls, 5);
+        }
+
+        #[test]
+        fn count_by_key() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            setup(&connection);
+
+            // this key is present in _all_ events
+            let shared_key = EventKey(StarkHash::from_hex_str("deadbeef").unwrap());
+            assert_eq!(
+                StarknetEventsTable::count_by_key(&connection, shared_key).unwrap(),
+                NUM_EVENTS
+            );
+
+            let absent_key = EventKey(StarkHash::from_hex_str("abcdef").unwrap());
+            assert_eq!(
+                StarknetEventsTable::count_by_key(&connection, absent_key).unwrap(),
+                0
+            );
+        }
+
+        #[test]
+        fn empty_data_is_stored_as_null() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let block = &create_blocks()[0];
+            StarknetBlocksTable::insert(&connection, block).unwrap();
+            let [(transaction, mut receipt)] =
+                crate::storage::test_utils::create_transactions_and_receipts::<1>();
+            let event = receipt.events[0].clone();
+            receipt.events = vec![
+                transaction::Event {
+                    data: vec![],
+                    ..event.clone()
+                },
+                transaction::Event {
+                    data: vec![EventData(StarkHash::ZERO)],
+                    ..event
+                },
+            ];
+            StarknetTransactionsTable::upsert(
+                &connection,
+                block.hash,
+                block.number,
+                &[(transaction, receipt)],
+                &StorageLimits::default(),
+            )
+            .unwrap();
+
+            let mut statement = connection
+                .prepare("SELECT data FROM starknet_events ORDER BY idx")
+                .unwrap();
+            let stored = statement
+                .query_map([], |row| row.get::<_, Option<Vec<u8>>>(0))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            // Empty data is distinct from a single zero element.
+            assert_eq!(stored, vec![None, Some(vec![0u8; 32])]);
+
+            // Only the zero element gets a data row.
+            let data_rows: usize = connection
+                .query_row("SELECT count(1) FROM starknet_events_data", [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(data_rows, 1);
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: 10,
+                page_number: 0,
+            };
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap()
+                    .events;
+            let data = events.into_iter().map(|e| e.data).collect::<Vec<_>>();
+            assert_eq!(data, vec![vec![], vec![EventData(StarkHash::ZERO)]]);
+        }
+
+        #[test]
+        fn insert_events_is_idempotent() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            // Re-process block 2 without reorging it first.
+            const BLOCK_NUMBER: usize = 2;
+            let block = &create_blocks()[BLOCK_NUMBER];
+            let transactions_and_receipts = create_transactions_and_receipts();
+            StarknetTransactionsTable::upsert(
+                &connection,
+                block.hash,
+                block.number,
+                &transactions_and_receipts[BLOCK_NUMBER * TRANSACTIONS_PER_BLOCK
+                    ..(BLOCK_NUMBER + 1) * TRANSACTIONS_PER_BLOCK],
+                &StorageLimits::default(),
+            )
+            .unwrap();
+
+            let filter = StarknetEventFilter {
+                from_block: Some(block.number),
+                to_block: Some(block.number),
+                block_hash: None,
+                contract_address: None,
+                // we're using a key which is present in _all_ events, to hit the FTS index
+                keys: vec![EventKey(StarkHash::from_hex_str("deadbeef").unwrap())],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+
+            let expected_events = &emitted_events
+                [EVENTS_PER_BLOCK * BLOCK_NUMBER..EVENTS_PER_BLOCK * (BLOCK_NUMBER + 1)];
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: expected_events.to_vec(),
+                    is_last_page: true
+                }
+            );
+        }
+
+        #[test]
+        fn upserting_blocks_twice_matches_single_insertion() {
+            fn summary(connection: &Connection) -> (usize, usize, usize, PageOfEvents) {
+                let count = |table: &str| -> usize {
+                    connection
+                        .query_row(&format!("SELECT count(1) FROM {}", table), [], |row| {
+                            row.get(0)
+                        })
+                        .unwrap()
+                };
+                let shared_key = EventKey(StarkHash::from_hex_str("deadbeef").unwrap());
+                let filter = StarknetEventFilter {
+                    from_block: None,
+                    to_block: None,
+                    block_hash: None,
+                    contract_address: None,
+                    keys: vec![shared_key],
+                    data_contains: vec![],
+                    include_reverted: true,
+                    page_size: NUM_EVENTS,
+                    page_number: 0,
+                };
+
+                (
+                    count("starknet_events"),
+                    count("starknet_events_data"),
+                    StarknetEventsTable::count_by_key(connection, shared_key).unwrap(),
+                    StarknetEventsTable::get_events(connection, &filter, &StorageLimits::default())
+                        .unwrap(),
+                )
+            }
+
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            setup(&connection);
+            let expected = summary(&connection);
+            assert_eq!(expected.0, NUM_EVENTS);
+            assert_eq!(expected.2, NUM_EVENTS);
+
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            setup(&connection);
+            let transactions_and_receipts = create_transactions_and_receipts();
+            for (i, block) in create_blocks().iter().enumerate() {
+                StarknetTransactionsTable::upsert(
+                    &connection,
+                    block.hash,
+                    block.number,
+                    &transactions_and_receipts
+                        [i * TRANSACTIONS_PER_BLOCK..(i + 1) * TRANSACTIONS_PER_BLOCK],
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+            }
+
+            assert_eq!(summary(&connection), expected);
+        }
+
+        #[test]
+        fn rebuild_from_receipts() {
+            let storage = Storage::in_memory().unwrap();
+            let mut connection = storage.connection().unwrap();
+            let emitted_events = setup(&connection);
+
+            let all = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            // A key which is present in _all_ events, to hit the FTS index.
+            let shared_key = EventKey(StarkHash::from_hex_str("deadbeef").unwrap());
+            let by_key = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![shared_key],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            let by_data = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![],
+                data_contains: vec![EventDataCondition {
+                    position: 0,
+                    value: emitted_events[5].data[0],
+                }],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            let expected_by_data = PageOfEvents {
+                events: vec![emitted_events[5].clone()],
+                is_last_page: true,
+            };
+            let expected = PageOfEvents {
+                events: emitted_events,
+                is_last_page: true,
+            };
+
+            connection
+                .execute("DELETE FROM starknet_events", [])
+                .unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &all, &StorageLimits::default())
+                    .unwrap();
+            assert!(events.events.is_empty());
+
+            let transaction = connection.transaction().unwrap();
+            let count =
+                StarknetEventsTable::rebuild_from_receipts(&transaction, &StorageLimits::default())
+                    .unwrap();
+            transaction.commit().unwrap();
+            assert_eq!(count, NUM_EVENTS);
+
+            for filter in [&all, &by_key] {
+                let events =
+                    StarknetEventsTable::get_events(&connection, filter, &StorageLimits::default())
+                        .unwrap();
+                assert_eq!(events, expected);
+            }
+            let events =
+                StarknetEventsTable::get_events(&connection, &by_data, &StorageLimits::default())
+                    .unwrap();
+            assert_eq!(events, expected_by_data);
+            assert_eq!(
+                StarknetEventsTable::count_by_key(&connection, shared_key).unwrap(),
+                NUM_EVENTS
+            );
+
+            // Rebuilding is idempotent.
+            let count =
+                StarknetEventsTable::rebuild_from_receipts(&connection, &StorageLimits::default())
+                    .unwrap();
+            assert_eq!(count, NUM_EVENTS);
+            let events =
+                StarknetEventsTable::get_events(&connection, &by_key, &StorageLimits::default())
+                    .unwrap();
+            assert_eq!(events, expected);
+        }
+
+        #[test]
+        fn get_events_by_data_value_at_position() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            let expected_event = &emitted_events[5];
+            let value = expected_event.data[0];
+            let filter = |conditions: Vec<(usize, EventData)>| StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![],
+                data_contains: conditions
+                    .into_iter()
+                    .map(|(position, value)| EventDataCondition { position, value })
+                    .collect(),
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+
+            let events = StarknetEventsTable::get_events(
+                &connection,
+                &filter(vec![(0, value)]),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: vec![expected_event.clone()],
+                    is_last_page: true
+                }
+            );
+
+            // The value has to be at the given position.
+            let events = StarknetEventsTable::get_events(
+                &connection,
+                &filter(vec![(1, value)]),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert!(events.events.is_empty());
+
+            // All conditions have to match.
+            let other_value = emitted_events[6].data[0];
+            let events = StarknetEventsTable::get_events(
+                &connection,
+                &filter(vec![(0, value), (0, other_value)]),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert!(events.events.is_empty());
+        }
+
+        #[test]
+        fn get_events_of_reverted_transactions() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let block = create_blocks()[0].clone();
+            let mut transactions_and_receipts = create_transactions_and_receipts();
+            let transactions_and_receipts =
+                &mut transactions_and_receipts[..TRANSACTIONS_PER_BLOCK];
+            transactions_and_receipts[1].1.execution_status =
+                Some(transaction::ExecutionStatus::Succeeded);
+            transactions_and_receipts[2].1.execution_status =
+                Some(transaction::ExecutionStatus::Reverted);
+            StarknetBlocksTable::insert(&connection, &block).unwrap();
+            StarknetTransactionsTable::upsert(
+                &connection,
+                block.hash,
+                block.number,
+                transactions_and_receipts,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+
+            let filter = |include_reverted| StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![],
+                data_contains: vec![],
+                include_reverted,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            let transaction_hashes = |include_reverted| {
+                StarknetEventsTable::get_events(
+                    &connection,
+                    &filter(include_reverted),
+                    &StorageLimits::default(),
+                )
+                .unwrap()
+                .events
+                .into_iter()
+                .map(|event| event.transaction_hash)
+                .collect::<Vec<_>>()
+            };
+
+            let all = transaction_hashes(true);
+            assert_eq!(all.len(), EVENTS_PER_BLOCK);
+            let reverted = transactions_and_receipts[2].0.transaction_hash;
+            assert!(all.contains(&reverted));
+
+            let expected = all
+                .into_iter()
+                .filter(|hash| *hash != reverted)
+                .collect::<Vec<_>>();
+            assert_eq!(transaction_hashes(false), expected);
+        }
+
+        #[test]
+        fn get_events_without_keys() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            setup(&connection);
+
+            let block = &create_blocks()[0];
+            let (mut txn, _) = create_transactions_and_receipts()[0].clone();
+            txn.transaction_hash =
+                StarknetTransactionHash(StarkHash::from_hex_str("0xabcdef").unwrap());
+            txn.contract_address = ContractAddress(StarkHash::from_hex_str("0x123456").unwrap());
+            let event = transaction::Event {
+                data: vec![EventData(StarkHash::from_hex_str("0x1").unwrap())],
+                from_address: txn.contract_address,
+                keys: vec![],
+            };
+            StarknetEventsTable::insert_events(
+                &connection,
+                block.number,
+                &txn,
+                None,
+                &[event.clone()],
+            )
+            .unwrap();
+
+            let expected_event = StarknetEmittedEvent {
+                from_address: event.from_address,
+                data: event.data,
+                keys: vec![],
+                block_hash: block.hash,
+                block_number: block.number,
+                transaction_hash: txn.transaction_hash,
+            };
+            let filter = StarknetEventFilter {
+                from_block: Some(block.number),
+                to_block: Some(block.number),
+                block_hash: None,
+                contract_address: Some(txn.contract_address),
+                keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
+            assert_eq!(events.events, vec![expected_event.clone()]);
+
+            // Keys may also be NULL.
+            connection
+                .execute(
+                    "UPDATE starknet_events SET keys = NULL WHERE transaction_hash = ?",
+                    [&txn.transaction_hash.0.as_be_bytes()[..]],
                 )
                 .unwrap();
-            }
-
-            transactions_and_receipts
-                .iter()
-                .enumerate()
-                .map(|(i, (txn, receipt))| {
-                    let event = &receipt.events[0];
-                    let block = &blocks[i / TRANSACTIONS_PER_BLOCK];
-
-                    StarknetEmittedEvent {
-                        data: event.data.clone(),
-                        from_address: event.from_address,
-                        keys: event.keys.clone(),
-                        block_hash: block.hash,
-                        block_number: block.number,
-                        transaction_hash: txn.transaction_hash,
-                    }
-                })
-                .collect()
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
+            assert_eq!(events.events, vec![expected_event]);
         }
 
         #[test]
-        fn get_events_with_fully_specified_filter() {
+        fn get_events_by_block_hash() {
             let storage = Storage::in_memory().unwrap();
             let connection = storage.connection().unwrap();
 
             let emitted_events = setup(&connection);
 
-            let expected_event = &emitted_events[1];
+            const BLOCK_NUMBER: usize = 1;
+            let expected_events = &emitted_events
+                [EVENTS_PER_BLOCK * BLOCK_NUMBER..EVENTS_PER_BLOCK * (BLOCK_NUMBER + 1)];
             let filter = StarknetEventFilter {
-                from_block: Some(expected_event.block_number),
-                to_block: Some(expected_event.block_number),
-                contract_address: Some(expected_event.from_address),
-                // we're using a key which is present in _all_ events
-                keys: vec![EventKey(StarkHash::from_hex_str("deadbeef").unwrap())],
+                from_block: None,
+                to_block: None,
+                block_hash: Some(expected_events[0].block_hash),
+                contract_address: None,
+                keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_EVENTS,
                 page_number: 0,
             };
 
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
-                    events: vec![expected_event.clone()],
+                    events: expected_events.to_vec(),
                     is_last_page: true
                 }
             );
@@ -1685,15 +5518,20 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: Some(StarknetBlockNumber(BLOCK_NUMBER as u64)),
                 to_block: Some(StarknetBlockNumber(BLOCK_NUMBER as u64)),
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_EVENTS,
                 page_number: 0,
             };
 
             let expected_events = &emitted_events
                 [EVENTS_PER_BLOCK * BLOCK_NUMBER..EVENTS_PER_BLOCK * (BLOCK_NUMBER + 1)];
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1714,15 +5552,20 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: Some(StarknetBlockNumber(UNTIL_BLOCK_NUMBER as u64)),
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_EVENTS,
                 page_number: 0,
             };
 
             let expected_events =
                 &emitted_events[..TRANSACTIONS_PER_BLOCK * (UNTIL_BLOCK_NUMBER + 1)];
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1743,14 +5586,19 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: Some(StarknetBlockNumber(FROM_BLOCK_NUMBER as u64)),
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_EVENTS,
                 page_number: 0,
             };
 
             let expected_events = &emitted_events[TRANSACTIONS_PER_BLOCK * FROM_BLOCK_NUMBER..];
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1772,13 +5620,18 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: Some(expected_event.from_address),
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_EVENTS,
                 page_number: 0,
             };
 
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1799,13 +5652,18 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![expected_event.keys[0]],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_EVENTS,
                 page_number: 0,
             };
 
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1815,6 +5673,515 @@ mod tests {
             );
         }
 
+        #[test]
+        fn explain() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![emitted_events[27].keys[0]],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            let plan = StarknetEventsTable::explain(&connection, &filter).unwrap();
+            assert!(plan.contains("starknet_events_keys"), "{}", plan);
+            assert!(plan.contains("VIRTUAL TABLE"), "{}", plan);
+
+            let filter = StarknetEventFilter {
+                from_block: Some(StarknetBlockNumber(1)),
+                to_block: Some(StarknetBlockNumber(2)),
+                keys: vec![],
+                ..filter
+            };
+            let plan = StarknetEventsTable::explain(&connection, &filter).unwrap();
+            assert!(plan.contains("starknet_events_block_number"), "{}", plan);
+            assert!(!plan.contains("starknet_events_keys"), "{}", plan);
+
+            // Events of a contract are read in order, instead of sorting all of them.
+            let filter = StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                contract_address: Some(emitted_events[0].from_address),
+                ..filter
+            };
+            let plan = StarknetEventsTable::explain(&connection, &filter).unwrap();
+            assert!(
+                plan.contains("starknet_events_from_address_block_number"),
+                "{}",
+                plan
+            );
+            assert!(!plan.contains("TEMP B-TREE"), "{}", plan);
+
+            let filter = StarknetEventFilter {
+                from_block: Some(StarknetBlockNumber(1)),
+                to_block: Some(StarknetBlockNumber(2)),
+                ..filter
+            };
+            let plan = StarknetEventsTable::explain(&connection, &filter).unwrap();
+            assert!(
+                plan.contains("starknet_events_from_address_block_number"),
+                "{}",
+                plan
+            );
+            assert!(!plan.contains("TEMP B-TREE"), "{}", plan);
+        }
+
+        #[test]
+        #[ignore = "benchmark, run with --release"]
+        fn address_index_insert_cost() {
+            use std::time::{Duration, Instant};
+
+            const EVENTS: usize = 5_000;
+
+            // Inserts events of many contracts into a fresh database, optionally with the
+            // composite address index swapped for the single column one it replaced.
+            let insert = |single_column: bool| -> Duration {
+                let storage = Storage::in_memory().unwrap();
+                let mut connection = storage.connection().unwrap();
+                setup(&connection);
+                if single_column {
+                    connection
+                        .execute_batch(
+                            r"DROP INDEX starknet_events_from_address_block_number;
+                            CREATE INDEX starknet_events_from_address ON starknet_events(from_address);",
+                        )
+                        .unwrap();
+                }
+
+                let start = Instant::now();
+                let transaction = connection.transaction().unwrap();
+                let mut statement = transaction
+                    .prepare(
+                        r"INSERT INTO starknet_events (block_number, idx, transaction_hash, from_address, keys)
+                        VALUES (?, ?, ?, ?, '')",
+                    )
+                    .unwrap();
+                for i in 0..EVENTS {
+                    let mut transaction_hash = [0u8; 32];
+                    transaction_hash[..8].copy_from_slice(&(i as u64 / 4).to_be_bytes());
+                    let mut from_address = [0u8; 32];
+                    from_address[24..].copy_from_slice(&(i as u64 % 300).to_be_bytes());
+                    statement
+                        .execute(params![
+                            i / (EVENTS / NUM_BLOCKS),
+                            i % 4,
+                            &transaction_hash[..],
+                            &from_address[..]
+                        ])
+                        .unwrap();
+                }
+                drop(statement);
+                transaction.commit().unwrap();
+                start.elapsed()
+            };
+
+            let fastest =
+                |single_column: bool| (0..5).map(|_| insert(single_column)).min().unwrap();
+            let single_column = fastest(true);
+            let composite = fastest(false);
+
+            // Inserting into the wider index is measurably slower, but must stay the same order
+            // of magnitude.
+            assert!(
+                composite < 2 * single_column,
+                "{:?} vs {:?}",
+                composite,
+                single_column
+            );
+        }
+
+        #[test]
+        fn get_events_with_all_filter_combinations() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            // Events 15 and 35 are emitted in blocks 1 and 3.
+            let event = &emitted_events[15];
+            let keys = vec![event.keys[0], emitted_events[35].keys[0]];
+            let condition = EventDataCondition {
+                position: 0,
+                value: event.data[0],
+            };
+
+            for combination in 0..64u8 {
+                let is_set = |bit: u8| combination & (1 << bit) != 0;
+                let filter = StarknetEventFilter {
+                    from_block: is_set(0).then(|| event.block_number),
+                    to_block: is_set(1).then(|| StarknetBlockNumber(2)),
+                    block_hash: is_set(2).then(|| event.block_hash),
+                    contract_address: is_set(3).then(|| event.from_address),
+                    keys: if is_set(4) { keys.clone() } else { vec![] },
+                    data_contains: if is_set(5) {
+                        vec![condition.clone()]
+                    } else {
+                        vec![]
+                    },
+                    include_reverted: true,
+                    page_size: NUM_EVENTS,
+                    page_number: 0,
+                };
+
+                let expected = emitted_events
+                    .iter()
+                    .filter(|e| {
+                        filter
+                            .from_block
+                            .map_or(true, |from| e.block_number >= from)
+                    })
+                    .filter(|e| filter.to_block.map_or(true, |to| e.block_number <= to))
+                    .filter(|e| filter.block_hash.map_or(true, |hash| e.block_hash == hash))
+                    .filter(|e| {
+                        filter
+                            .contract_address
+                            .map_or(true, |address| e.from_address == address)
+                    })
+                    .filter(|e| {
+                        filter.keys.is_empty() || filter.keys.iter().any(|k| e.keys.contains(k))
+                    })
+                    .filter(|e| {
+                        filter
+                            .data_contains
+                            .iter()
+                            .all(|c| e.data.get(c.position) == Some(&c.value))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                let events = StarknetEventsTable::get_events(
+                    &connection,
+                    &filter,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+                assert_eq!(
+                    events,
+                    PageOfEvents {
+                        events: expected,
+                        is_last_page: true
+                    },
+                    "filter combination {:06b}",
+                    combination
+                );
+            }
+        }
+
+        #[test]
+        fn repeated_event_queries_reuse_statements() {
+            use crate::storage::thread_statement_cache_stats;
+
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            let filter = |event: &StarknetEmittedEvent| StarknetEventFilter {
+                from_block: Some(event.block_number),
+                to_block: None,
+                block_hash: None,
+                contract_address: Some(event.from_address),
+                keys: vec![event.keys[0]],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+
+            StarknetEventsTable::get_events(
+                &connection,
+                &filter(&emitted_events[5]),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            let before = thread_statement_cache_stats();
+
+            // The same filter, and a filter differing only in its values.
+            for event in [&emitted_events[5], &emitted_events[27]] {
+                let events = StarknetEventsTable::get_events(
+                    &connection,
+                    &filter(event),
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+                assert_eq!(events.events, vec![event.clone()]);
+            }
+
+            let after = thread_statement_cache_stats();
+            assert_eq!(after.misses, before.misses);
+            // The event count, bloom filter and events queries of both filters.
+            assert_eq!(after.hits - before.hits, 6);
+        }
+
+        #[test]
+        fn get_events_by_key_skips_blocks_using_bloom_filters() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            let filter = |keys: Vec<EventKey>, page_size| StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys,
+                data_contains: vec![],
+                include_reverted: true,
+                page_size,
+                page_number: 0,
+            };
+            let unknown_key = EventKey(StarkHash::from_hex_str("0x1234567").unwrap());
+            let common_key = EventKey(StarkHash::from_hex_str("deadbeef").unwrap());
+
+            let cases = [
+                // Events 5 and 27 are emitted in blocks 0 and 2.
+                (
+                    filter(vec![emitted_events[27].keys[0]], NUM_EVENTS),
+                    vec![StarknetBlockNumber(2)..=StarknetBlockNumber(2)],
+                ),
+                (
+                    filter(
+                        vec![emitted_events[5].keys[0], emitted_events[27].keys[0]],
+                        NUM_EVENTS,
+                    ),
+                    vec![
+                        StarknetBlockNumber(0)..=StarknetBlockNumber(0),
+                        StarknetBlockNumber(2)..=StarknetBlockNumber(2),
+                    ],
+                ),
+                (
+                    filter(vec![common_key], 3),
+                    vec![StarknetBlockNumber(0)..=StarknetBlockNumber(3)],
+                ),
+                (filter(vec![unknown_key], NUM_EVENTS), vec![]),
+            ];
+
+            for (filter, expected_ranges) in cases {
+                let ranges = StarknetEventsTable::key_bloom_block_ranges(&connection, &filter)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(ranges, expected_ranges);
+
+                let with_bloom = StarknetEventsTable::get_events(
+                    &connection,
+                    &filter,
+                    &StorageLimits::default(),
+                )
+                .unwrap();
+                let query = StarknetEventsTable::event_query(&filter, None).unwrap();
+                let without_bloom =
+                    StarknetEventsTable::page_of_events(&connection, &filter, &query).unwrap();
+                assert_eq!(with_bloom, without_bloom);
+            }
+
+            // Reorged blocks no longer have a bloom filter.
+            StarknetBlocksTable::reorg(&connection, StarknetBlockNumber(2)).unwrap();
+            let ranges = StarknetEventsTable::key_bloom_block_ranges(
+                &connection,
+                &filter(vec![emitted_events[27].keys[0]], NUM_EVENTS),
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(ranges, vec![]);
+        }
+
+        #[test]
+        fn get_events_by_key_is_not_run_if_no_block_matches() {
+            use crate::storage::thread_statement_cache_stats;
+
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            let emitted_events = setup(&connection);
+
+            let filter = |key| StarknetEventFilter {
+                from_block: None,
+                to_block: None,
+                block_hash: None,
+                contract_address: None,
+                keys: vec![key],
+                data_contains: vec![],
+                include_reverted: true,
+                page_size: NUM_EVENTS,
+                page_number: 0,
+            };
+            let prepared = || {
+                let stats = thread_statement_cache_stats();
+                stats.hits + stats.misses
+            };
+
+            // The cost estimate, bloom filter and events queries.
+            let before = prepared();
+            let events = StarknetEventsTable::get_events(
+                &connection,
+                &filter(emitted_events[27].keys[0]),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(events.events, vec![emitted_events[27].clone()]);
+            assert_eq!(prepared() - before, 3);
+
+            // The bloom filters rule out every block, so the events query is skipped.
+            let unknown_key = EventKey(StarkHash::from_hex_str("0x1234567").unwrap());
+            let before = prepared();
+            let events = StarknetEventsTable::get_events(
+                &connection,
+                &filter(unknown_key),
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                events,
+                PageOfEvents {
+                    events: vec![],
+                    is_last_page: true
+                }
+            );
+            assert_eq!(prepared() - before, 2);
+        }
+
+        #[test]
+        fn key_bloom_reads_are_bounded() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+            // Only the bloom filters are read, so the blocks themselves are left out.
+            connection.execute("PRAGMA foreign_keys = OFF", []).unwrap();
+
+            let key = EventKey(StarkHash::from_hex_str("deadbeef").unwrap());
+            let other_key = StarkHash::from_hex_str("0x1234567").unwrap();
+            let mut matching = KeyBloom::default();
+            matching.insert(&key.0);
+            let mut other = KeyBloom::default();
+            other.insert(&other_key);
+            assert!(!other.may_contain(&key.0));
+
+            let set_blooms = |blooms: &[&KeyBloom]| {
+                connection
+                    .execute("DELETE FROM starknet_events_key_blooms", [])
+                    .unwrap();
+                for (number, bloom) in blooms.iter().enumerate() {
+                    connection
+                        .execute(
+                            "INSERT INTO starknet_events_key_blooms (block_number, bloom) VALUES (?, ?)",
+                            rusqlite::params![number as i64, bloom.as_bytes()],
+                        )
+                        .unwrap();
+                }
+            };
+            let ranges = |to_block| {
+                let filter = StarknetEventFilter {
+                    from_block: None,
+                    to_block: Some(StarknetBlockNumber(to_block)),
+                    block_hash: None,
+                    contract_address: None,
+                    keys: vec![key],
+                    data_contains: vec![],
+                    include_reverted: true,
+                    page_size: NUM_EVENTS,
+                    page_number: 0,
+                };
+                StarknetEventsTable::key_bloom_block_ranges(&connection, &filter).unwrap()
+            };
+
+            // Reading stops once the key turns out to be too common.
+            let mut blooms = vec![&other; StarknetEventsTable::KEY_BLOOM_SAMPLE_ROWS];
+            blooms[..StarknetEventsTable::KEY_BLOOM_SAMPLE_ROWS / 2 + 1].fill(&matching);
+            set_blooms(&blooms);
+            assert_eq!(ranges(blooms.len() as u64), None);
+
+            // Blocks past the bloom filters which were read are kept as candidates.
+            let limit = StarknetEventsTable::KEY_BLOOM_ROWS_LIMIT;
+            let mut blooms = vec![&other; limit + 10];
+            blooms[0] = &matching;
+            set_blooms(&blooms);
+            assert_eq!(
+                ranges(blooms.len() as u64 - 1),
+                Some(vec![
+                    StarknetBlockNumber(0)..=StarknetBlockNumber(0),
+                    StarknetBlockNumber(limit as u64)..=StarknetBlockNumber(limit as u64 + 9),
+                ])
+            );
+        }
+
+        #[test]
+        fn block_summary_is_kept_consistent() {
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            setup(&connection);
+            let summary = |event_count, emitter_count| BlockEventSummary {
+                event_count,
+                emitter_count,
+            };
+            let every_block = |connection: &Connection| {
+                StarknetEventsTable::get_block_summaries(
+                    connection,
+                    StarknetBlockNumber::GENESIS,
+                    StarknetBlockNumber(NUM_BLOCKS as u64),
+                )
+                .unwrap()
+            };
+
+            // Every transaction emits a single event, and is sent to a distinct contract.
+            let expected = (0..NUM_BLOCKS as u64)
+                .map(|number| (StarknetBlockNumber(number), summary(10, 10)))
+                .collect::<Vec<_>>();
+            assert_eq!(every_block(&connection), expected);
+
+            // Replace the events of the first transaction of block 1 by two events, emitted by
+            // the contract which the second transaction of the block is sent to.
+            let transactions_and_receipts = create_transactions_and_receipts();
+            let (mut transaction, receipt) =
+                transactions_and_receipts[TRANSACTIONS_PER_BLOCK].clone();
+            transaction.contract_address = transactions_and_receipts[TRANSACTIONS_PER_BLOCK + 1]
+                .0
+                .contract_address;
+            let events = vec![receipt.events[0].clone(), receipt.events[0].clone()];
+            StarknetEventsTable::insert_events(
+                &connection,
+                StarknetBlockNumber(1),
+                &transaction,
+                None,
+                &events,
+            )
+            .unwrap();
+
+            assert_eq!(
+                StarknetEventsTable::get_block_summary(&connection, StarknetBlockNumber(1))
+                    .unwrap(),
+                summary(11, 9)
+            );
+            assert_eq!(
+                StarknetEventsTable::get_block_summary(&connection, StarknetBlockNumber(0))
+                    .unwrap(),
+                summary(10, 10)
+            );
+
+            // Reorged blocks no longer have a summary.
+            StarknetBlocksTable::reorg(&connection, StarknetBlockNumber(2)).unwrap();
+            assert_eq!(
+                every_block(&connection),
+                vec![
+                    (StarknetBlockNumber(0), summary(10, 10)),
+                    (StarknetBlockNumber(1), summary(11, 9)),
+                ]
+            );
+            assert_eq!(
+                StarknetEventsTable::get_block_summary(&connection, StarknetBlockNumber(2))
+                    .unwrap(),
+                BlockEventSummary::default()
+            );
+        }
+
         #[test]
         fn get_events_with_no_filter() {
             let storage = Storage::in_memory().unwrap();
@@ -1825,13 +6192,18 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: NUM_EVENTS,
                 page_number: 0,
             };
 
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1851,12 +6223,17 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 10,
                 page_number: 0,
             };
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1868,12 +6245,17 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 10,
                 page_number: 1,
             };
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1885,12 +6267,17 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 10,
                 page_number: 3,
             };
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1911,13 +6298,18 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: PAGE_SIZE,
                 // one page _after_ the last one
                 page_number: NUM_BLOCKS * EVENTS_PER_BLOCK / PAGE_SIZE,
             };
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1937,24 +6329,32 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 0,
                 page_number: 0,
             };
-            let result = StarknetEventsTable::get_events(&connection, &filter);
+            let result =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default());
             assert!(result.is_err());
             assert_eq!(result.unwrap_err().to_string(), "Invalid page size");
 
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: vec![],
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: StarknetEventsTable::PAGE_SIZE_LIMIT + 1,
                 page_number: 0,
             };
-            let result = StarknetEventsTable::get_events(&connection, &filter);
+            let result =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default());
             assert!(result.is_err());
             assert_eq!(
                 result.unwrap_err().downcast::<EventFilterError>().unwrap(),
@@ -1962,6 +6362,111 @@ mod tests {
             );
         }
 
+        #[test]
+        fn expensive_queries_are_rejected() {
+            const BLOCKS: u64 = 200;
+            const EVENTS_PER_BLOCK: usize = 20;
+
+            let storage = Storage::in_memory().unwrap();
+            let connection = storage.connection().unwrap();
+
+            // Every event has the same key and is emitted by the same contract.
+            let template = create_blocks::<1>()[0].clone();
+            let (transaction, receipt) = create_transactions_and_receipts()[0].clone();
+            let events = vec![receipt.events[0].clone(); EVENTS_PER_BLOCK];
+            for number in 0..BLOCKS {
+                let block = StarknetBlock {
+                    number: StarknetBlockNumber(number),
+                    hash: StarknetBlockHash(
+                        StarkHash::from_be_slice(&(number + 1).to_be_bytes()).unwrap(),
+                    ),
+                    ..template.clone()
+                };
+                StarknetBlocksTable::insert(&connection, &block).unwrap();
+                StarknetEventsTable::insert_events(
+                    &connection,
+                    block.number,
+                    &transaction,
+                    None,
+                    &events,
+                )
+                .unwrap();
+            }
+
+            let common_key = EventKey(StarkHash::from_hex_str("deadbeef").unwrap());
+            let filter =
+                |from_block: Option<u64>, to_block: Option<u64>, page_number| StarknetEventFilter {
+                    from_block: from_block.map(StarknetBlockNumber),
+                    to_block: to_block.map(StarknetBlockNumber),
+                    block_hash: None,
+                    contract_address: None,
+                    keys: vec![common_key],
+                    data_contains: vec![],
+                    include_reverted: true,
+                    page_size: 10,
+                    page_number,
+                };
+            let budget = 100;
+            let limits = StorageLimits {
+                event_query_cost_budget: Some(budget),
+                ..Default::default()
+            };
+            let rejection = |filter: &StarknetEventFilter| {
+                StarknetEventsTable::get_events(
+                    &connection,
+                    filter,
+                    &limits,
+                    &StorageLimits::default(),
+                )
+                .unwrap_err()
+                .downcast::<EventFilterError>()
+                .unwrap()
+            };
+
+            // All 4000 events have the key, of which a tenth is assumed to match.
+            assert_eq!(
+                rejection(&filter(None, None, 0)),
+                EventFilterError::TooExpensive {
+                    cost: 400,
+                    budget,
+                    constraint: EventFilterConstraint::BlockRange,
+                }
+            );
+            let message = rejection(&filter(None, None, 0)).to_string();
+            assert!(message.contains("narrow the block range"), "{message}");
+
+            // The same query over ten blocks is cheap enough.
+            let page = StarknetEventsTable::get_events(
+                &connection,
+                &filter(Some(100), Some(109), 0),
+                &limits,
+                &StorageLimits::default(),
+            )
+            .unwrap();
+            assert_eq!(page.events.len(), 10);
+            assert!(!page.is_last_page);
+
+            // Over a bounded range, an address filter is the missing constraint.
+            assert_eq!(
+                rejection(&filter(Some(0), Some(99), 0)),
+                EventFilterError::TooExpensive {
+                    cost: 200,
+                    budget,
+                    constraint: EventFilterConstraint::ContractAddress,
+                }
+            );
+
+            // Paging deep into the matches is expensive even when they are few.
+            assert_eq!(
+                rejection(&filter(Some(100), Some(109), 15)),
+                EventFilterError::TooExpensive {
+                    cost: 170,
+                    budget,
+                    constraint: EventFilterConstraint::PageNumber,
+                }
+            );
+        }
+
         #[test]
         fn get_events_by_key_with_paging() {
             let storage = Storage::in_memory().unwrap();
@@ -1976,12 +6481,17 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: keys_for_expected_events.clone(),
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 2,
                 page_number: 0,
             };
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -1993,12 +6503,17 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: keys_for_expected_events.clone(),
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 2,
                 page_number: 1,
             };
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -2010,12 +6525,17 @@ mod tests {
             let filter = StarknetEventFilter {
                 from_block: None,
                 to_block: None,
+                block_hash: None,
                 contract_address: None,
                 keys: keys_for_expected_events,
+                data_contains: vec![],
+                include_reverted: true,
                 page_size: 2,
                 page_number: 2,
             };
-            let events = StarknetEventsTable::get_events(&connection, &filter).unwrap();
+            let events =
+                StarknetEventsTable::get_events(&connection, &filter, &StorageLimits::default())
+                    .unwrap();
             assert_eq!(
                 events,
                 PageOfEvents {
@@ -0,0 +1,165 @@
+//! Hit and miss counters of the prepared statement cache.
+//!
+//! Statements prepared with [PrepareCounted::prepare_counted] count as a hit if the cached
+//! statement was re-used, and as a miss if it had to be compiled. Statements put into the cache
+//! by [warm] count as a hit on their first use.
+//!
+//! Counters are summed over all connections of the process, and over the connections used by
+//! the current thread. Neither requires any state per connection.
+use std::cell::Cell;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rusqlite::{CachedStatement, Connection, StatementStatus};
+
+static TOTAL_HITS: AtomicU64 = AtomicU64::new(0);
+static TOTAL_MISSES: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static THREAD_STATS: Cell<StatementCacheStats> = Cell::new(StatementCacheStats::default());
+}
+
+/// The number of prepared statement cache hits and misses.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatementCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl StatementCacheStats {
+    /// The share of statements which were served from the cache, `None` if nothing was
+    /// prepared yet.
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            total => Some(self.hits as f64 / total as f64),
+        }
+    }
+}
+
+/// Prepares statements like [Connection::prepare_cached], while counting cache hits and misses.
+pub trait PrepareCounted {
+    fn prepare_counted(&self, sql: &str) -> rusqlite::Result<CachedStatement<'_>>;
+}
+
+impl PrepareCounted for Connection {
+    fn prepare_counted(&self, sql: &str) -> rusqlite::Result<CachedStatement<'_>> {
+        let statement = self.prepare_cached(sql)?;
+
+        // A statement which was compiled just now has never been run, whereas `warm` runs the
+        // statements it compiles ahead of time.
+        let hit = statement.get_status(StatementStatus::Run) > 0;
+
+        THREAD_STATS.with(|stats| {
+            let mut current = stats.get();
+            match hit {
+                true => current.hits += 1,
+                false => current.misses += 1,
+            }
+            stats.set(current);
+        });
+        match hit {
+            true => TOTAL_HITS.fetch_add(1, Ordering::Relaxed),
+            false => TOTAL_MISSES.fetch_add(1, Ordering::Relaxed),
+        };
+
+        Ok(statement)
+    }
+}
+
+/// Compiles `queries` into the cache of `connection`.
+///
+/// Each query is run once with all its parameters `NULL`, so that it counts as a hit on its
+/// first use. Only read queries should therefore be warmed. Queries which fail are skipped.
+pub(super) fn warm(connection: &Connection, queries: &[&str]) {
+    for &sql in queries {
+        let result = connection.prepare_cached(sql).and_then(|mut statement| {
+            let mut rows = statement.raw_query();
+            rows.next()?;
+            Ok(())
+        });
+        if let Err(error) = result {
+            tracing::debug!(%error, %sql, "Failed to prepare statement ahead of use");
+        }
+    }
+}
+
+/// The cache hits and misses of the statements prepared by the current thread so far.
+pub fn thread_statement_cache_stats() -> StatementCacheStats {
+    THREAD_STATS.with(Cell::get)
+}
+
+/// The cache hits and misses of all connections so far.
+pub fn total_statement_cache_stats() -> StatementCacheStats {
+    StatementCacheStats {
+        hits: TOTAL_HITS.load(Ordering::Relaxed),
+        misses: TOTAL_MISSES.load(Ordering::Relaxed),
+    }
+}
+
+/// Renders [total_statement_cache_stats] in the Prometheus text exposition format.
+pub fn render_statement_cache_metrics() -> String {
+    let stats = total_statement_cache_stats();
+
+    let mut out = String::new();
+    // Writing to a String cannot fail.
+    let _ = writeln!(
+        out,
+        "# HELP storage_statement_cache_hits_total Number of prepared statements served from the cache."
+    );
+    let _ = writeln!(out, "# TYPE storage_statement_cache_hits_total counter");
+    let _ = writeln!(out, "storage_statement_cache_hits_total {}", stats.hits);
+    let _ = writeln!(
+        out,
+        "# HELP storage_statement_cache_misses_total Number of prepared statements which had to be compiled."
+    );
+    let _ = writeln!(out, "# TYPE storage_statement_cache_misses_total counter");
+    let _ = writeln!(out, "storage_statement_cache_misses_total {}", stats.misses);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+
+    #[test]
+    fn repeated_queries_hit() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+        let before = thread_statement_cache_stats();
+
+        let sql = "SELECT number FROM starknet_blocks WHERE number = ?";
+        for _ in 0..3 {
+            let mut statement = connection.prepare_counted(sql).unwrap();
+            statement.query([0]).unwrap().next().unwrap();
+        }
+
+        let after = thread_statement_cache_stats();
+        assert_eq!(after.misses - before.misses, 1);
+        assert_eq!(after.hits - before.hits, 2);
+    }
+
+    #[test]
+    fn warmed_queries_hit() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        let before = thread_statement_cache_stats();
+
+        let sql = "SELECT hash FROM starknet_blocks WHERE number = ?";
+        warm(&connection, &[sql]);
+        connection.prepare_counted(sql).unwrap();
+
+        let after = thread_statement_cache_stats();
+        assert_eq!(after.hits - before.hits, 1);
+        assert_eq!(after.misses, before.misses);
+    }
+
+    #[test]
+    fn hit_rate() {
+        assert_eq!(StatementCacheStats::default().hit_rate(), None);
+        let stats = StatementCacheStats { hits: 3, misses: 1 };
+        assert_eq!(stats.hit_rate(), Some(0.75));
+    }
+}
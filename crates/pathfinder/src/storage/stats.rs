@@ -0,0 +1,219 @@
+//! Aggregate chain statistics, maintained incrementally as blocks are stored and reorged.
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, Transaction};
+
+use crate::core::{ClassHash, StarknetBlockNumber};
+
+/// Aggregate counters over all blocks up to and including `block_number`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainStats {
+    /// The latest block, [None] if there are no blocks.
+    pub block_number: Option<StarknetBlockNumber>,
+    /// Number of contracts deployed.
+    pub contracts: u64,
+    /// Number of distinct classes of the deployed contracts.
+    pub classes: u64,
+    pub transactions: u64,
+    pub events: u64,
+}
+
+/// Maintains the [ChainStats] counters.
+///
+/// The counters are kept in a single row, alongside each block's contribution to them which
+/// is what allows them to be reverted on a reorg. This avoids counting rows of the (very large)
+/// transactions and events tables.
+pub struct ChainStatsTable {}
+
+impl ChainStatsTable {
+    /// Adds a newly stored block to the counters.
+    ///
+    /// `deployed_classes` holds the class of each contract deployed in this block.
+    pub fn insert(
+        transaction: &Transaction,
+        block_number: StarknetBlockNumber,
+        deployed_classes: &[ClassHash],
+        transactions: usize,
+        events: usize,
+    ) -> anyhow::Result<()> {
+        let mut classes = 0;
+        for class in deployed_classes {
+            classes += transaction
+                .execute(
+                    "INSERT OR IGNORE INTO chain_stats_classes (hash, block_number) VALUES (?, ?)",
+                    rusqlite::params![&class.0.to_be_bytes()[..], block_number.0],
+                )
+                .context("Insert into chain_stats_classes table")?;
+        }
+
+        transaction
+            .execute(
+                r"INSERT INTO chain_stats_blocks ( block_number,  contracts,  classes,  transactions,  events)
+                                          VALUES (:block_number, :contracts, :classes, :transactions, :events)",
+                named_params! {
+                    ":block_number": block_number.0,
+                    ":contracts": deployed_classes.len(),
+                    ":classes": classes,
+                    ":transactions": transactions,
+                    ":events": events,
+                },
+            )
+            .context("Insert into chain_stats_blocks table")?;
+
+        transaction
+            .execute(
+                r"UPDATE chain_stats SET
+                    contracts = contracts + :contracts,
+                    classes = classes + :classes,
+                    transactions = transactions + :transactions,
+                    events = events + :events",
+                named_params! {
+                    ":contracts": deployed_classes.len(),
+                    ":classes": classes,
+                    ":transactions": transactions,
+                    ":events": events,
+                },
+            )
+            .context("Update chain_stats table")?;
+
+        Ok(())
+    }
+
+    /// Removes the blocks from `reorg_tail` onwards from the counters.
+    ///
+    /// This must be called along with [StarknetBlocksTable::reorg](super::StarknetBlocksTable::reorg).
+    pub fn reorg(transaction: &Transaction, reorg_tail: StarknetBlockNumber) -> anyhow::Result<()> {
+        transaction
+            .execute(
+                r"UPDATE chain_stats SET
+                    contracts = contracts - (SELECT ifnull(sum(contracts), 0) FROM chain_stats_blocks WHERE block_number >= :reorg_tail),
+                    classes = classes - (SELECT ifnull(sum(classes), 0) FROM chain_stats_blocks WHERE block_number >= :reorg_tail),
+                    transactions = transactions - (SELECT ifnull(sum(transactions), 0) FROM chain_stats_blocks WHERE block_number >= :reorg_tail),
+                    events = events - (SELECT ifnull(sum(events), 0) FROM chain_stats_blocks WHERE block_number >= :reorg_tail)",
+                named_params! {":reorg_tail": reorg_tail.0},
+            )
+            .context("Update chain_stats table")?;
+
+        transaction
+            .execute(
+                "DELETE FROM chain_stats_blocks WHERE block_number >= ?",
+                [reorg_tail.0],
+            )
+            .context("Delete from chain_stats_blocks table")?;
+        transaction
+            .execute(
+                "DELETE FROM chain_stats_classes WHERE block_number >= ?",
+                [reorg_tail.0],
+            )
+            .context("Delete from chain_stats_classes table")?;
+
+        Ok(())
+    }
+
+    /// Returns the counters as of the latest block.
+    pub fn get(connection: &Connection) -> anyhow::Result<ChainStats> {
+        connection
+            .query_row(
+                r"SELECT contracts, classes, transactions, events,
+                    (SELECT max(number) FROM starknet_blocks)
+                FROM chain_stats",
+                [],
+                |row| {
+                    Ok(ChainStats {
+                        contracts: row.get(0)?,
+                        classes: row.get(1)?,
+                        transactions: row.get(2)?,
+                        events: row.get(3)?,
+                        block_number: row.get::<_, Option<u64>>(4)?.map(StarknetBlockNumber),
+                    })
+                },
+            )
+            .context("Query chain_stats table")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_utils, StarknetBlocksTable, Storage};
+    use stark_hash::StarkHash;
+
+    fn class(byte: u8) -> ClassHash {
+        ClassHash(StarkHash::from_be_slice(&[byte]).unwrap())
+    }
+
+    #[test]
+    fn empty() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        assert_eq!(
+            ChainStatsTable::get(&connection).unwrap(),
+            ChainStats::default()
+        );
+    }
+
+    #[test]
+    fn insert_and_reorg() {
+        let storage = Storage::in_memory().unwrap();
+        let mut connection = storage.connection().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        let blocks = test_utils::create_blocks::<3>();
+        for block in &blocks {
+            StarknetBlocksTable::insert(&transaction, block).unwrap();
+        }
+
+        ChainStatsTable::insert(&transaction, blocks[0].number, &[class(1), class(2)], 2, 3)
+            .unwrap();
+        // A repeated class is only counted once, also within a single block.
+        ChainStatsTable::insert(
+            &transaction,
+            blocks[1].number,
+            &[class(2), class(3), class(3)],
+            5,
+            0,
+        )
+        .unwrap();
+        ChainStatsTable::insert(&transaction, blocks[2].number, &[], 1, 10).unwrap();
+
+        assert_eq!(
+            ChainStatsTable::get(&transaction).unwrap(),
+            ChainStats {
+                block_number: Some(blocks[2].number),
+                contracts: 5,
+                classes: 3,
+                transactions: 8,
+                events: 13,
+            }
+        );
+
+        ChainStatsTable::reorg(&transaction, blocks[1].number).unwrap();
+        StarknetBlocksTable::reorg(&transaction, blocks[1].number).unwrap();
+        assert_eq!(
+            ChainStatsTable::get(&transaction).unwrap(),
+            ChainStats {
+                block_number: Some(blocks[0].number),
+                contracts: 2,
+                classes: 2,
+                transactions: 2,
+                events: 3,
+            }
+        );
+
+        // Classes which were reorged away are counted again once they reappear.
+        let block = &test_utils::create_blocks::<2>()[1];
+        StarknetBlocksTable::insert(&transaction, block).unwrap();
+        ChainStatsTable::insert(&transaction, block.number, &[class(1), class(3)], 1, 1).unwrap();
+        assert_eq!(
+            ChainStatsTable::get(&transaction).unwrap(),
+            ChainStats {
+                block_number: Some(block.number),
+                contracts: 4,
+                classes: 3,
+                transactions: 3,
+                events: 4,
+            }
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Cached metadata of ERC-20 token contracts.
+
+use anyhow::Context;
+use rusqlite::{named_params, Connection, OptionalExtension};
+use stark_hash::StarkHash;
+
+use crate::core::{ClassHash, ContractAddress};
+
+/// The decimals, symbol and name of an ERC-20 token, as returned by the token contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenMetadata {
+    /// The class hash of the token contract at the time the metadata was queried.
+    pub class_hash: ClassHash,
+    pub decimals: u8,
+    pub symbol: String,
+    pub name: String,
+}
+
+/// Caches [TokenMetadata] by token contract address.
+pub struct TokenMetadataTable {}
+
+impl TokenMetadataTable {
+    /// Stores the metadata of `token`, replacing any earlier entry.
+    pub fn upsert(
+        connection: &Connection,
+        token: ContractAddress,
+        metadata: &TokenMetadata,
+    ) -> anyhow::Result<()> {
+        connection
+            .execute(
+                r"INSERT OR REPLACE INTO token_metadata
+                        ( address,  class_hash,  decimals,  symbol,  name)
+                VALUES  (:address, :class_hash, :decimals, :symbol, :name)",
+                named_params! {
+                    ":address": &token.0.to_be_bytes()[..],
+                    ":class_hash": &metadata.class_hash.0.to_be_bytes()[..],
+                    ":decimals": metadata.decimals,
+                    ":symbol": &metadata.symbol,
+                    ":name": &metadata.name,
+                },
+            )
+            .context("Insert into token_metadata table")?;
+
+        Ok(())
+    }
+
+    /// Returns the cached metadata of `token`, if any.
+    ///
+    /// The caller is responsible for checking [TokenMetadata::class_hash] against the token's
+    /// current class hash, as the entry is stale if the contract has been upgraded.
+    pub fn get(
+        connection: &Connection,
+        token: ContractAddress,
+    ) -> anyhow::Result<Option<TokenMetadata>> {
+        connection
+            .query_row(
+                r"SELECT class_hash, decimals, symbol, name FROM token_metadata WHERE address = :address",
+                named_params! {
+                    ":address": &token.0.to_be_bytes()[..],
+                },
+                |row| {
+                    Ok((
+                        row.get::<_, Vec<u8>>("class_hash")?,
+                        row.get::<_, u8>("decimals")?,
+                        row.get::<_, String>("symbol")?,
+                        row.get::<_, String>("name")?,
+                    ))
+                },
+            )
+            .optional()
+            .context("Query token_metadata table")?
+            .map(|(class_hash, decimals, symbol, name)| {
+                let class_hash =
+                    StarkHash::from_be_slice(&class_hash).context("Parsing stored class hash")?;
+
+                Ok(TokenMetadata {
+                    class_hash: ClassHash(class_hash),
+                    decimals,
+                    symbol,
+                    name,
+                })
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+
+    fn hash(hex: &str) -> StarkHash {
+        StarkHash::from_hex_str(hex).unwrap()
+    }
+
+    #[test]
+    fn upsert_and_get() {
+        let storage = Storage::in_memory().unwrap();
+        let connection = storage.connection().unwrap();
+
+        let token = ContractAddress(hash("70ce"));
+        assert_eq!(TokenMetadataTable::get(&connection, token).unwrap(), None);
+
+        let metadata = TokenMetadata {
+            class_hash: ClassHash(hash("c1a55")),
+            decimals: 18,
+            symbol: "ETH".to_owned(),
+            name: "Ether".to_owned(),
+        };
+        TokenMetadataTable::upsert(&connection, token, &metadata).unwrap();
+        assert_eq!(
+            TokenMetadataTable::get(&connection, token).unwrap(),
+            Some(metadata.clone())
+        );
+
+        let upgraded = TokenMetadata {
+            class_hash: ClassHash(hash("c1a56")),
+            decimals: 6,
+            ..metadata
+        };
+        TokenMetadataTable::upsert(&connection, token, &upgraded).unwrap();
+        assert_eq!(
+            TokenMetadataTable::get(&connection, token).unwrap(),
+            Some(upgraded)
+        );
+    }
+}
@@ -75,6 +75,9 @@ impl StarkHash {
     }
 
     /// Convenience function which extends [StarkHash::from_be_bytes] to work with slices.
+    ///
+    /// Slices shorter than 32 bytes are left-padded with zeros. Returns [OverflowError] if the
+    /// slice is longer than 32 bytes or the value is not less than the field modulus.
     pub fn from_be_slice(bytes: &[u8]) -> Result<Self, OverflowError> {
         if bytes.len() > 32 {
             return Err(OverflowError);
@@ -114,6 +117,15 @@ impl StarkHash {
         Ok(Self(bytes))
     }
 
+    /// Compares two [StarkHash]es in constant time.
+    ///
+    /// Use this instead of `==` when comparing a caller supplied hash against an expected one,
+    /// so that the comparison does not leak how many leading bytes matched.
+    pub fn ct_eq(&self, other: &StarkHash) -> bool {
+        use subtle::ConstantTimeEq;
+        self.0.ct_eq(&other.0).into()
+    }
+
     /// Returns `true` if the value of [`StarkHash`] is larger than `2^251 - 1`.
     ///
     /// Every [`StarkHash`] that is used to traverse a Merkle-Patricia Tree
@@ -346,6 +358,29 @@ mod tests {
     use bitvec::bitvec;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn ct_eq_agrees_with_eq() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+        for _ in 0..10_000 {
+            let a = StarkHash(rng.gen());
+            let b = match rng.gen_range(0..3) {
+                0 => a,
+                1 => {
+                    // Differs in a single byte only.
+                    let mut b = a;
+                    b.0[rng.gen_range(0..32)] ^= rng.gen_range(1..=u8::MAX);
+                    b
+                }
+                _ => StarkHash(rng.gen()),
+            };
+
+            assert_eq!(a.ct_eq(&b), a == b, "{} vs {}", a, b);
+            assert_eq!(b.ct_eq(&a), a == b, "{} vs {}", b, a);
+        }
+    }
+
     #[test]
     fn view_bits() {
         let one = StarkHash::from_hex_str("1").unwrap();
@@ -471,6 +506,11 @@ mod tests {
             assert_eq!(result, Ok(original));
         }
 
+        #[test]
+        fn empty() {
+            assert_eq!(StarkHash::from_be_slice(&[]), Ok(StarkHash::ZERO));
+        }
+
         #[test]
         fn max() {
             let mut max_val = MODULUS;
@@ -7,14 +7,20 @@ from starkware.starkware_utils.error_handling import WebFriendlyException
 from starkware.storage.storage import Storage
 
 # used from tests, and the query which asserts that the schema is of expected version.
-EXPECTED_SCHEMA_REVISION = 10
+EXPECTED_SCHEMA_REVISION = 33
 EXPECTED_CAIRO_VERSION = "0.8.2.1"
 
+# highest version of the protocol spoken with pathfinder, see
+# `pathfinder_lib::cairo::ext_py::protocol` for the description.
+PROTOCOL_VERSION = 2
+# optional capabilities announced to pathfinder during the handshake
+SUPPORTED_FEATURES = ["estimate_fee"]
+
 
 def main():
     """
-    Loops on stdin, reads json commands from lines, outputs single json as a response.
-    Starts by outputting "ready"
+    Loops on stdin, reads commands, outputs a single response per command.
+    Starts by negotiating the protocol version with pathfinder.
     """
     if len(sys.argv) != 2:
         print("usage: call.py [sqlite.db]")
@@ -45,8 +51,11 @@ def main():
         # whenever communicating with the other process, it's important to flush manually
         # even though "the general wisdom" is to flush on '\n', python seems to only do it
         # if it didn't add the newline to the written out string.
-        print("ready", flush=True)
-        do_loop(connection, sys.stdin, sys.stdout)
+        protocol = handshake(sys.stdin.buffer, sys.stdout)
+        if protocol >= 2:
+            do_framed_loop(connection, sys.stdin.buffer, sys.stdout.buffer)
+        else:
+            do_loop(connection, sys.stdin, sys.stdout)
 
 
 def check_cairolang_version():
@@ -56,8 +65,69 @@ def check_cairolang_version():
     return version == EXPECTED_CAIRO_VERSION
 
 
+def handshake(input_file, output_file):
+    """
+    Announces the supported protocol version and features, returns the version chosen by
+    pathfinder. Without msgpack only the json lines protocol (version 1) can be spoken.
+    """
+    try:
+        import msgpack  # noqa: F401
+
+        version = PROTOCOL_VERSION
+    except ImportError:
+        version = 1
+
+    print(
+        json.dumps({"protocol": version, "features": SUPPORTED_FEATURES}),
+        file=output_file,
+        flush=True,
+    )
+
+    reply = json.loads(input_file.readline())
+    return reply["protocol"]
+
+
 def do_loop(connection, input_gen, output_file):
+    """
+    Json lines protocol: reads json commands from lines, outputs single json line as a response.
+    """
+    for line in input_gen:
+        if line == "" or line.startswith("#"):
+            continue
+
+        out = handle_command(connection, json.loads, line)
+
+        print(json.dumps(out), file=output_file, flush=True)
+
+
+def do_framed_loop(connection, input_file, output_file):
+    """
+    Framed protocol: reads 4-byte big endian length prefixed msgpack commands, outputs a single
+    frame as a response.
+    """
+    import msgpack
+
+    while True:
+        header = input_file.read(4)
+        if len(header) < 4:
+            # pathfinder has closed our stdin
+            return
+
+        length = int.from_bytes(header, "big")
+        payload = input_file.read(length)
+
+        out = handle_command(connection, msgpack.unpackb, payload)
 
+        packed = msgpack.packb(out)
+        output_file.write(len(packed).to_bytes(4, "big"))
+        output_file.write(packed)
+        output_file.flush()
+
+
+def handle_command(connection, decode, raw):
+    """
+    Decodes and executes a single command, returning the response.
+    """
     required = {
         # FIXME: this should be hash_or_latest
         "at_block": int_hash_or_latest,
@@ -66,61 +136,72 @@ def do_loop(connection, input_gen, output_file):
         "calldata": list_of_hash_or_int,
     }
 
-    optional = {"caller_address": hash_or_int, "signature": hash_or_int}
-
-    for line in input_gen:
-        if line == "" or line.startswith("#"):
-            continue
-
-        out = {"status": "ok"}
+    optional = {
+        "caller_address": hash_or_int,
+        "signature": hash_or_int,
+        "entry_point_type": entry_point_type,
+        "estimate_fee": bool,
+    }
 
-        started_at = time.time()
-        parsed_at = None
+    out = {"status": "ok"}
 
-        try:
-            command = parse_command(json.loads(line), required, optional)
+    started_at = time.time()
+    parsed_at = None
+    estimate_fee = False
 
-            parsed_at = time.time()
+    try:
+        command = parse_command(decode(raw), required, optional)
+        estimate_fee = command.get("estimate_fee", False)
 
-            connection.execute("BEGIN")
+        parsed_at = time.time()
 
-            output = loop_inner(connection, command)
+        connection.execute("BEGIN")
 
-            # we need to render the retdata as hex strings, so we can just deserialize it easily
-            out["output"] = list(
-                map(lambda x: "0x" + x.to_bytes(32, "big").hex(), output)
-            )
-        except NoSuchBlock:
-            out = {"status": "error", "kind": "NO_SUCH_BLOCK"}
-        except NoSuchContract:
-            out = {"status": "error", "kind": "NO_SUCH_CONTRACT"}
-        except UnexpectedSchemaVersion:
-            out = {"status": "error", "kind": "INVALID_SCHEMA_VERSION"}
-        except InvalidInput:
-            out = {"status": "error", "kind": "INVALID_INPUT"}
-        except WebFriendlyException as e:
+        (output, fee) = loop_inner(connection, command)
+
+        # we need to render the retdata as hex strings, so we can just deserialize it easily
+        out["output"] = list(map(as_hex, output))
+
+        if estimate_fee:
+            out["fee"] = {k: as_hex(v) for k, v in fee.items()}
+    except NoSuchBlock:
+        out = {"status": "error", "kind": "NO_SUCH_BLOCK"}
+    except NoSuchContract:
+        out = {"status": "error", "kind": "NO_SUCH_CONTRACT"}
+    except UnexpectedSchemaVersion:
+        out = {"status": "error", "kind": "INVALID_SCHEMA_VERSION"}
+    except InvalidInput:
+        out = {"status": "error", "kind": "INVALID_INPUT"}
+    except WebFriendlyException as e:
+        if (
+            estimate_fee
+            and str(e.code) == "StarknetErrorCode.ENTRY_POINT_NOT_FOUND_IN_CONTRACT"
+        ):
+            # the selector of a message is an input, while calls report this as a failure
+            out = {"status": "error", "kind": "NO_SUCH_ENTRY_POINT"}
+        else:
             # this is hopefully something we can give to the user
             out = {"status": "failed", "exception": str(e.code)}
-        except Exception as e:
-            stringified = str(e)
-            if len(stringified) > 200:
-                stringified = stringified[:197] + "..."
-            out = {"status": "failed", "exception": stringified}
-        finally:
-            connection.rollback()
+    except Exception as e:
+        stringified = str(e)
+        if len(stringified) > 200:
+            stringified = stringified[:197] + "..."
+        out = {"status": "failed", "exception": stringified}
+    finally:
+        connection.rollback()
 
-            completed_at = time.time()
-            timings = {}
+        completed_at = time.time()
+        timings = {}
 
-            if parsed_at is not None and started_at < parsed_at:
-                timings["parsing"] = parsed_at - started_at
+        if parsed_at is not None and started_at < parsed_at:
+            timings["parsing"] = parsed_at - started_at
 
-            if parsed_at is not None and parsed_at < completed_at:
-                timings["execution"] = completed_at - parsed_at
+        if parsed_at is not None and parsed_at < completed_at:
+            timings["execution"] = completed_at - parsed_at
 
-            out["timings"] = timings
+        out["timings"] = timings
 
-            print(json.dumps(out), file=output_file, flush=True)
+    return out
 
 
 def loop_inner(connection, command):
@@ -138,6 +219,7 @@ def loop_inner(connection, command):
             command["calldata"],
             command.get("caller_address", 0),
             command.get("signature", None),
+            command.get("entry_point_type", None),
             block_info,
         )
     )
@@ -171,6 +253,16 @@ def parse_command(command, required, optional):
     return converted
 
 
+def entry_point_type(s):
+    from starkware.starknet.services.api.contract_definition import EntryPointType
+
+    return EntryPointType[s]
+
+
+def as_hex(x):
+    return "0x" + x.to_bytes(32, "big").hex()
+
+
 def int_hash_or_latest(s):
     if type(s) == int:
         return s
@@ -420,6 +512,7 @@ async def do_call(
     calldata,
     caller_address,
     signature,
+    entry_point_type,
     block_info,
 ):
     """
@@ -427,7 +520,8 @@ async def do_call(
     cairo-lang state which does not matter, because the state will be thrown
     out.
 
-    Returns the retdata from the call, which is the only property needed by the RPC api.
+    Returns the retdata from the call, and the fee of the invocation as
+    `gas_consumed`, `gas_price` and `overall_fee`.
     """
     from starkware.starknet.business_logic.state.state import (
         SharedState,
@@ -440,6 +534,7 @@ async def do_call(
     )
     from starkware.cairo.lang.vm.crypto import pedersen_hash_func
     from starkware.starknet.testing.state import StarknetState
+    from starkware.starknet.services.api.contract_definition import EntryPointType
 
     general_config = StarknetGeneralConfig()
 
@@ -456,12 +551,29 @@ async def do_call(
     state = StarknetState(state=carried_state, general_config=general_config)
     max_fee = 0
 
+    if entry_point_type is None:
+        entry_point_type = EntryPointType.EXTERNAL
+
     output = await state.invoke_raw(
-        contract_address, selector, calldata, caller_address, max_fee, signature
+        contract_address,
+        selector,
+        calldata,
+        caller_address,
+        max_fee,
+        signature,
+        entry_point_type=entry_point_type,
     )
 
-    # this is everything we need, at least so far for the "call".
-    return output.call_info.retdata
+    # with max_fee = 0 nothing is charged, but the fee is still calculated
+    gas_price = block_info.gas_price
+    overall_fee = output.actual_fee
+    fee = {
+        "gas_consumed": overall_fee // gas_price if gas_price > 0 else 0,
+        "gas_price": gas_price,
+        "overall_fee": overall_fee,
+    }
+
+    return (output.call_info.retdata, fee)
 
 
 if __name__ == "__main__":
@@ -1,4 +1,13 @@
-from call import do_loop, loop_inner, EXPECTED_SCHEMA_REVISION, check_cairolang_version
+from call import (
+    do_loop,
+    do_framed_loop,
+    handshake,
+    loop_inner,
+    EXPECTED_SCHEMA_REVISION,
+    PROTOCOL_VERSION,
+    SUPPORTED_FEATURES,
+    check_cairolang_version,
+)
 import sqlite3
 import io
 import json
@@ -36,6 +45,20 @@ func get_value{syscall_ptr : felt*}(address : felt) -> (res : felt):
 end
 """
 
+# the test contract with an l1 handler, for estimating the fee of messages. it is deployed with
+# the same class hash because the hash is not checked when calling.
+L1_HANDLER_TEST_CONTRACT = (
+    SIMPLIFIED_TEST_CONTRACT
+    + """
+@l1_handler
+func increase_value_from_l1{syscall_ptr : felt*}(
+        from_address : felt, address : felt, value : felt):
+    let (res) = storage_read(address=address)
+    return storage_write(address=address, value=res + value)
+end
+"""
+)
+
 
 # This only contains the tables required for call.
 def inmemory_with_tables():
@@ -114,7 +137,7 @@ def inmemory_with_tables():
     return con
 
 
-def populate_test_contract_with_132_on_3(con):
+def populate_test_contract_with_132_on_3(con, source=SIMPLIFIED_TEST_CONTRACT):
     """
     Populates a situation created with cairo-lang contract_test.py where
     the test contract has been deployed and it's memory address 132 has been
@@ -137,7 +160,7 @@ def populate_test_contract_with_132_on_3(con):
             bytes.fromhex(
                 "050b2148c0d782914e0b12a1a32abe5e398930b7e914f82c65cb7afce0a0ab9b"
             ),
-            compile_test_contract(),
+            compile_test_contract(source),
         ],
     )
     cur.execute(
@@ -206,14 +229,14 @@ def populate_test_contract_with_132_on_3(con):
     return contract_address
 
 
-def compile_test_contract():
+def compile_test_contract(source=SIMPLIFIED_TEST_CONTRACT):
     from starkware.starknet.compiler.compile import compile_starknet_codes
     import zstandard
 
     # FIXME: use crates/pathfinder/fixtures/contract_definition.json.zst here, it's the same.
 
     raw = compile_starknet_codes(
-        [(SIMPLIFIED_TEST_CONTRACT, "-")], debug_info=False
+        [(source, "-")], debug_info=False
     ).serialize()
     # we use 10 over at pathfinder, but for tests 1 is probably better
     compressor = zstandard.ZstdCompressor(level=1)
@@ -277,7 +300,7 @@ def test_positive_directly():
 
     con.execute("BEGIN")
 
-    output = loop_inner(con, command)
+    (output, _fee) = loop_inner(con, command)
 
     assert output == [3]
 
@@ -365,7 +388,104 @@ def test_no_such_block():
     assert latest == expected
 
 
+def test_estimate_fee_of_l1_handler():
+    con = inmemory_with_tables()
+    contract_address = populate_test_contract_with_132_on_3(
+        con, source=L1_HANDLER_TEST_CONTRACT
+    )
+
+    output = default_132_on_3_scenario(
+        con,
+        [
+            f'{{ "at_block": 1, "contract_address": {contract_address}, "entry_point_selector": "increase_value_from_l1", "calldata": [1, 132, 4], "entry_point_type": "L1_HANDLER", "estimate_fee": true }}',
+        ],
+    )
+
+    assert output["status"] == "ok"
+    assert output["output"] == []
+
+    fee = output["fee"]
+    assert set(fee.keys()) == {"gas_consumed", "gas_price", "overall_fee"}
+
+    gas_consumed = int(fee["gas_consumed"], 16)
+    gas_price = int(fee["gas_price"], 16)
+    overall_fee = int(fee["overall_fee"], 16)
+
+    # the block gas price is left_pad(b"0", 16)
+    assert gas_price == ord("0")
+    # with max_fee = 0 nothing is charged, but the actual fee should still be calculated
+    assert overall_fee > 0
+    assert overall_fee == gas_consumed * gas_price
+
+
+def test_no_such_entry_point_only_when_estimating_fee():
+    con = inmemory_with_tables()
+    contract_address = populate_test_contract_with_132_on_3(
+        con, source=L1_HANDLER_TEST_CONTRACT
+    )
+
+    output = default_132_on_3_scenario(
+        con,
+        [
+            f'{{ "at_block": 1, "contract_address": {contract_address}, "entry_point_selector": "no_such_handler", "calldata": [1, 132, 4], "entry_point_type": "L1_HANDLER", "estimate_fee": true }}',
+            f'{{ "at_block": 1, "contract_address": {contract_address}, "entry_point_selector": "no_such_function", "calldata": [132] }}',
+        ],
+    )
+
+    [estimate, call] = output
+
+    assert estimate == {"status": "error", "kind": "NO_SUCH_ENTRY_POINT"}
+    assert call == {
+        "status": "failed",
+        "exception": "StarknetErrorCode.ENTRY_POINT_NOT_FOUND_IN_CONTRACT",
+    }
+
+
 def test_check_cairolang_version():
     # run this here as well so that we get earlier than CI feedback
     # of another constant that needs to be upgraded
     assert check_cairolang_version()
+
+
+def test_handshake():
+    output_catcher = io.StringIO()
+
+    protocol = handshake(io.BytesIO(b'{"protocol": 1}\n'), output_catcher)
+
+    assert json.loads(output_catcher.getvalue()) == {
+        "protocol": PROTOCOL_VERSION,
+        "features": SUPPORTED_FEATURES,
+    }
+    assert protocol == 1
+
+
+def test_framed_success():
+    import msgpack
+
+    con = inmemory_with_tables()
+    contract_address = populate_test_contract_with_132_on_3(con)
+
+    command = msgpack.packb(
+        {
+            "at_block": 1,
+            "contract_address": contract_address,
+            "entry_point_selector": "get_value",
+            "calldata": [132],
+        }
+    )
+
+    output_catcher = io.BytesIO()
+    do_framed_loop(
+        con,
+        io.BytesIO(len(command).to_bytes(4, "big") + command),
+        output_catcher,
+    )
+
+    output = output_catcher.getvalue()
+    length = int.from_bytes(output[:4], "big")
+    assert len(output) == 4 + length
+
+    output = msgpack.unpackb(output[4:])
+    del output["timings"]
+
+    assert output == {"status": "ok", "output": ["0x" + (3).to_bytes(32, "big").hex()]}